@@ -0,0 +1,54 @@
+//! Records the git commit and time of the build, reported by `GET /version`.
+//!
+//! The script reruns whenever the commit checked out changes: when HEAD
+//! moves to another branch or commit, or the branch it points to moves,
+//! whether its ref is loose or packed. `BUILT_AT` is thus the time of the
+//! first build of that commit, not of later rebuilds from edited sources:
+//! it's a best-effort hint, not a build timestamp.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The output of `git args`, if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
+
+/// The files whose changes move the commit checked out.
+fn watched() -> Vec<String> {
+    let Some(head) = git(&["rev-parse", "--git-path", "HEAD"]) else {
+        return Vec::new();
+    };
+    let mut paths = vec![head];
+    paths.extend(git(&["rev-parse", "--git-path", "packed-refs"]).filter(|path| Path::new(path).exists()));
+    // A detached HEAD holds the commit itself.
+    if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]).and_then(|name| git(&["rev-parse", "--git-path", &name])) {
+        let branch = Path::new(&branch);
+        // A packed ref gets a loose file on the next commit, in a directory
+        // which is watched instead until then.
+        match (branch.exists(), branch.parent()) {
+            (true, _) => paths.push(branch.display().to_string()),
+            (false, Some(refs)) if refs.exists() => paths.push(refs.display().to_string()),
+            _ => {}
+        }
+    }
+    paths
+}
+
+fn main() {
+    let sha = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default();
+
+    println!("cargo:rustc-env=GIT_SHA={}", sha);
+    println!("cargo:rustc-env=BUILT_AT={}", built_at);
+    for path in watched() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
@@ -0,0 +1,3 @@
+DROP INDEX elus_commune_code;
+ALTER TABLE elus DROP COLUMN commune_code;
+DROP TABLE communes;
@@ -0,0 +1,8 @@
+CREATE TABLE communes (
+  code TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  department TEXT NOT NULL
+);
+
+ALTER TABLE elus ADD COLUMN commune_code TEXT;
+CREATE INDEX elus_commune_code ON elus (commune_code);
@@ -0,0 +1,3 @@
+ALTER TABLE elus DROP COLUMN longitude;
+ALTER TABLE elus DROP COLUMN latitude;
+ALTER TABLE elus DROP COLUMN office_address;
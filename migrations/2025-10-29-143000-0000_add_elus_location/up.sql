@@ -0,0 +1,3 @@
+ALTER TABLE elus ADD COLUMN office_address TEXT;
+ALTER TABLE elus ADD COLUMN latitude DOUBLE;
+ALTER TABLE elus ADD COLUMN longitude DOUBLE;
@@ -0,0 +1 @@
+ALTER TABLE elus DROP COLUMN email_status;
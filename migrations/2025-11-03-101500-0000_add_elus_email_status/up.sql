@@ -0,0 +1 @@
+ALTER TABLE elus ADD COLUMN email_status TEXT NOT NULL DEFAULT 'unchecked';
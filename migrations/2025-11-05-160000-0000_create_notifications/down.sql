@@ -0,0 +1,2 @@
+DROP TABLE notification_recipients;
+DROP TABLE notifications;
@@ -0,0 +1,17 @@
+CREATE TABLE notifications (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  subject TEXT NOT NULL,
+  template TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE notification_recipients (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  notification_id INTEGER NOT NULL REFERENCES notifications (id),
+  email TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'queued',
+  error TEXT
+);
+CREATE INDEX notification_recipients_notification_id ON notification_recipients (notification_id);
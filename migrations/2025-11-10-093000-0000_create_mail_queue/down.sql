@@ -0,0 +1,18 @@
+CREATE TABLE notification_recipients (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  notification_id INTEGER NOT NULL REFERENCES notifications (id),
+  email TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'queued',
+  error TEXT
+);
+CREATE INDEX notification_recipients_notification_id ON notification_recipients (notification_id);
+
+INSERT INTO notification_recipients (notification_id, email, subject, body, status, error)
+  SELECT notification_id, recipient, subject, body, status, last_error
+  FROM mail_queue
+  WHERE notification_id IS NOT NULL
+  ORDER BY id;
+
+DROP TABLE mail_queue;
@@ -0,0 +1,22 @@
+CREATE TABLE mail_queue (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  notification_id INTEGER REFERENCES notifications (id),
+  recipient TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  body TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'queued',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  sent_at TIMESTAMP
+);
+CREATE INDEX mail_queue_status_next_attempt_at ON mail_queue (status, next_attempt_at);
+CREATE INDEX mail_queue_notification_id ON mail_queue (notification_id);
+
+INSERT INTO mail_queue (notification_id, recipient, subject, body, status, attempts, last_error)
+  SELECT notification_id, email, subject, body, status, CASE status WHEN 'queued' THEN 0 ELSE 1 END, error
+  FROM notification_recipients
+  ORDER BY id;
+
+DROP TABLE notification_recipients;
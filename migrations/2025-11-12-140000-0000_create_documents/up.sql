@@ -0,0 +1,10 @@
+CREATE TABLE documents (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  title TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  size BIGINT NOT NULL,
+  visibility TEXT NOT NULL DEFAULT 'private',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX documents_elu_id ON documents (elu_id);
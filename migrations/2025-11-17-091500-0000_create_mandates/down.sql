@@ -0,0 +1,5 @@
+ALTER TABLE elus ADD COLUMN mandates TEXT NOT NULL DEFAULT '[]';
+UPDATE elus SET mandates = (
+  SELECT json_group_array(title) FROM (SELECT title FROM mandates WHERE elu_id = elus.id ORDER BY position)
+);
+DROP TABLE mandates;
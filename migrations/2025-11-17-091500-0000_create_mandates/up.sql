@@ -0,0 +1,11 @@
+CREATE TABLE mandates (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  position INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  UNIQUE (elu_id, position)
+);
+CREATE INDEX mandates_title ON mandates (title);
+INSERT INTO mandates (elu_id, position, title)
+  SELECT elus.id, mandate.key, mandate.value FROM elus, json_each(elus.mandates) AS mandate;
+ALTER TABLE elus DROP COLUMN mandates;
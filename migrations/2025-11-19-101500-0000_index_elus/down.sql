@@ -0,0 +1,5 @@
+DROP INDEX elus_search_name;
+DROP INDEX elus_updated_at;
+DROP INDEX elus_name;
+ALTER TABLE elus DROP COLUMN search_name;
+ALTER TABLE elus DROP COLUMN updated_at;
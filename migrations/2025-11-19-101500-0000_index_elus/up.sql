@@ -0,0 +1,46 @@
+-- email needs no index of its own: its UNIQUE constraint already has one.
+ALTER TABLE elus ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE elus SET updated_at = CURRENT_TIMESTAMP;
+-- The name in lowercase without diacritics, which name searches match
+-- against; must give the same result as repository::normalize_name.
+ALTER TABLE elus ADD COLUMN search_name TEXT NOT NULL DEFAULT '';
+UPDATE elus SET search_name = lower(name);
+UPDATE elus SET search_name = replace(search_name, 'à', 'a');
+UPDATE elus SET search_name = replace(search_name, 'â', 'a');
+UPDATE elus SET search_name = replace(search_name, 'ä', 'a');
+UPDATE elus SET search_name = replace(search_name, 'À', 'a');
+UPDATE elus SET search_name = replace(search_name, 'Â', 'a');
+UPDATE elus SET search_name = replace(search_name, 'Ä', 'a');
+UPDATE elus SET search_name = replace(search_name, 'ç', 'c');
+UPDATE elus SET search_name = replace(search_name, 'Ç', 'c');
+UPDATE elus SET search_name = replace(search_name, 'é', 'e');
+UPDATE elus SET search_name = replace(search_name, 'è', 'e');
+UPDATE elus SET search_name = replace(search_name, 'ê', 'e');
+UPDATE elus SET search_name = replace(search_name, 'ë', 'e');
+UPDATE elus SET search_name = replace(search_name, 'É', 'e');
+UPDATE elus SET search_name = replace(search_name, 'È', 'e');
+UPDATE elus SET search_name = replace(search_name, 'Ê', 'e');
+UPDATE elus SET search_name = replace(search_name, 'Ë', 'e');
+UPDATE elus SET search_name = replace(search_name, 'î', 'i');
+UPDATE elus SET search_name = replace(search_name, 'ï', 'i');
+UPDATE elus SET search_name = replace(search_name, 'Î', 'i');
+UPDATE elus SET search_name = replace(search_name, 'Ï', 'i');
+UPDATE elus SET search_name = replace(search_name, 'ô', 'o');
+UPDATE elus SET search_name = replace(search_name, 'ö', 'o');
+UPDATE elus SET search_name = replace(search_name, 'Ô', 'o');
+UPDATE elus SET search_name = replace(search_name, 'Ö', 'o');
+UPDATE elus SET search_name = replace(search_name, 'ù', 'u');
+UPDATE elus SET search_name = replace(search_name, 'û', 'u');
+UPDATE elus SET search_name = replace(search_name, 'ü', 'u');
+UPDATE elus SET search_name = replace(search_name, 'Ù', 'u');
+UPDATE elus SET search_name = replace(search_name, 'Û', 'u');
+UPDATE elus SET search_name = replace(search_name, 'Ü', 'u');
+UPDATE elus SET search_name = replace(search_name, 'ÿ', 'y');
+UPDATE elus SET search_name = replace(search_name, 'Ÿ', 'y');
+UPDATE elus SET search_name = replace(search_name, 'œ', 'oe');
+UPDATE elus SET search_name = replace(search_name, 'Œ', 'oe');
+UPDATE elus SET search_name = replace(search_name, 'æ', 'ae');
+UPDATE elus SET search_name = replace(search_name, 'Æ', 'ae');
+CREATE INDEX elus_name ON elus (name);
+CREATE INDEX elus_updated_at ON elus (updated_at);
+CREATE INDEX elus_search_name ON elus (search_name);
@@ -0,0 +1,16 @@
+CREATE TABLE audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  event TEXT NOT NULL,
+  username TEXT,
+  ip TEXT,
+  at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX audit_log_at ON audit_log (at);
+-- Failed sign-ins, counted per attempted username (`user:<name>`) and per
+-- client address (`ip:<address>`).
+CREATE TABLE login_failures (
+  key TEXT PRIMARY KEY NOT NULL,
+  failures INTEGER NOT NULL,
+  last_failure_at TIMESTAMP NOT NULL,
+  locked_until TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- Where password reset links are sent.
+ALTER TABLE users ADD COLUMN email TEXT;
+CREATE UNIQUE INDEX users_email ON users (email);
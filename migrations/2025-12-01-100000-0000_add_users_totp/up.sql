@@ -0,0 +1,13 @@
+-- TOTP second factor: the base32 secret, set at enrollment, only required
+-- to sign in once confirmed with a first code. The last step a code was
+-- accepted for keeps codes from being used twice.
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+-- Single-use codes for when the authenticator is lost, stored as SHA-256.
+CREATE TABLE backup_codes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  user_id INTEGER NOT NULL REFERENCES users (id),
+  code_hash TEXT NOT NULL
+);
+CREATE INDEX backup_codes_user_id ON backup_codes (user_id);
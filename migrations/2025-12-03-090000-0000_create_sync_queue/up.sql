@@ -0,0 +1,17 @@
+-- Changes to elus waiting to be pushed to each sync target, in order. The
+-- payload is the person as of the change; persons deleted since keep their
+-- pending changes, hence no foreign key.
+CREATE TABLE sync_queue (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  target TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  elu_id INTEGER NOT NULL,
+  payload TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'queued',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  synced_at TIMESTAMP
+);
+CREATE INDEX sync_queue_status_next_attempt_at ON sync_queue (status, next_attempt_at);
@@ -0,0 +1,10 @@
+-- Append-only log of the changes to elus, for external consumers to
+-- replicate incrementally by sequence number. The payload is the person as
+-- of the change (as of their deletion, for deletions).
+CREATE TABLE events (
+  seq INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  kind TEXT NOT NULL,
+  elu_id INTEGER NOT NULL,
+  payload TEXT NOT NULL,
+  at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- Last event each in-process consumer of the events table (such as the
+-- NATS publisher) has handled, so it resumes there after a restart.
+CREATE TABLE event_cursors (
+  consumer TEXT PRIMARY KEY NOT NULL,
+  seq BIGINT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Endpoints change events are POSTed to. Each webhook's progress in the
+-- events table is its `webhook:<id>` row in event_cursors.
+CREATE TABLE webhooks (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  url TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,17 @@
+-- Deliveries of events to webhooks, created as the dispatcher moves each
+-- webhook's cursor forward, and kept with the outcome of their last attempt
+-- so integrators can see and replay failures.
+CREATE TABLE webhook_deliveries (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  webhook_id INTEGER NOT NULL REFERENCES webhooks (id),
+  event_seq BIGINT NOT NULL REFERENCES events (seq),
+  status TEXT NOT NULL DEFAULT 'pending',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  response_code INTEGER,
+  last_error TEXT,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  delivered_at TIMESTAMP
+);
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, id);
+CREATE INDEX webhook_deliveries_status ON webhook_deliveries (status, id);
@@ -0,0 +1,7 @@
+-- Secrets webhook deliveries are signed with. While a rotation is under
+-- way, deliveries are signed with the previous secret too, until it
+-- expires.
+ALTER TABLE webhooks ADD COLUMN secret TEXT NOT NULL DEFAULT '';
+ALTER TABLE webhooks ADD COLUMN previous_secret TEXT;
+ALTER TABLE webhooks ADD COLUMN previous_secret_expires_at TIMESTAMP;
+UPDATE webhooks SET secret = lower(hex(randomblob(32)));
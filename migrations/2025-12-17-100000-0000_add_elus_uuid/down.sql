@@ -0,0 +1,2 @@
+DROP INDEX elus_uuid;
+ALTER TABLE elus DROP COLUMN uuid;
@@ -0,0 +1,8 @@
+-- Public identifiers of elus (random UUIDs), which unlike the integer ids
+-- don't depend on the database instance that issued them.
+ALTER TABLE elus ADD COLUMN uuid TEXT NOT NULL DEFAULT '';
+UPDATE elus SET uuid = lower(
+  hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-'
+  || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+);
+CREATE UNIQUE INDEX elus_uuid ON elus (uuid);
@@ -0,0 +1,7 @@
+-- Former email addresses of elus, so links using them keep resolving after
+-- an address change. An address taken again by an elu stops being an alias.
+CREATE TABLE email_aliases (
+  email TEXT PRIMARY KEY NOT NULL,
+  elu_id INTEGER NOT NULL REFERENCES elus (id)
+);
+CREATE INDEX email_aliases_elu_id ON email_aliases (elu_id);
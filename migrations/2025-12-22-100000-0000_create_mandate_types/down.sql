@@ -0,0 +1,2 @@
+DROP TABLE mandate_labels;
+DROP TABLE mandate_types;
@@ -0,0 +1,41 @@
+-- Common mandates, which can be given by code on write. Titles are stored in
+-- French; labels in other languages are what responses show to callers
+-- preferring those.
+CREATE TABLE mandate_types (
+  code TEXT PRIMARY KEY NOT NULL
+);
+CREATE TABLE mandate_labels (
+  code TEXT NOT NULL REFERENCES mandate_types (code),
+  language TEXT NOT NULL,
+  label TEXT NOT NULL,
+  PRIMARY KEY (code, language)
+);
+INSERT INTO mandate_types (code) VALUES
+  ('maire'),
+  ('adjoint_au_maire'),
+  ('conseiller_municipal'),
+  ('conseiller_communautaire'),
+  ('conseiller_departemental'),
+  ('conseiller_regional'),
+  ('depute'),
+  ('senateur'),
+  ('depute_europeen');
+INSERT INTO mandate_labels (code, language, label) VALUES
+  ('maire', 'fr', 'Maire'),
+  ('maire', 'en', 'Mayor'),
+  ('adjoint_au_maire', 'fr', 'Adjoint au maire'),
+  ('adjoint_au_maire', 'en', 'Deputy mayor'),
+  ('conseiller_municipal', 'fr', 'Conseiller municipal'),
+  ('conseiller_municipal', 'en', 'Municipal councillor'),
+  ('conseiller_communautaire', 'fr', 'Conseiller communautaire'),
+  ('conseiller_communautaire', 'en', 'Intercommunal councillor'),
+  ('conseiller_departemental', 'fr', 'Conseiller départemental'),
+  ('conseiller_departemental', 'en', 'Departmental councillor'),
+  ('conseiller_regional', 'fr', 'Conseiller régional'),
+  ('conseiller_regional', 'en', 'Regional councillor'),
+  ('depute', 'fr', 'Député'),
+  ('depute', 'en', 'Member of the National Assembly'),
+  ('senateur', 'fr', 'Sénateur'),
+  ('senateur', 'en', 'Senator'),
+  ('depute_europeen', 'fr', 'Député européen'),
+  ('depute_europeen', 'en', 'Member of the European Parliament');
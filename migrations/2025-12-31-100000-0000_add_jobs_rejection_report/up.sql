@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN rejection_report TEXT;
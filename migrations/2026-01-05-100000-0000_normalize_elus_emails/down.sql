@@ -0,0 +1 @@
+-- The original case of the domains isn't kept: nothing to undo.
@@ -0,0 +1,14 @@
+-- Lowercases the domain of stored addresses, as email::Email does on the
+-- way in, unless that would collide with another address.
+UPDATE elus SET email = trim(substr(email, 1, instr(email, '@')) || lower(substr(email, instr(email, '@') + 1)))
+  WHERE instr(email, '@') > 0
+    AND NOT EXISTS (
+      SELECT 1 FROM elus other
+      WHERE other.email = trim(substr(elus.email, 1, instr(elus.email, '@')) || lower(substr(elus.email, instr(elus.email, '@') + 1)))
+    );
+UPDATE email_aliases SET email = trim(substr(email, 1, instr(email, '@')) || lower(substr(email, instr(email, '@') + 1)))
+  WHERE instr(email, '@') > 0
+    AND NOT EXISTS (
+      SELECT 1 FROM email_aliases other
+      WHERE other.email = trim(substr(email_aliases.email, 1, instr(email_aliases.email, '@')) || lower(substr(email_aliases.email, instr(email_aliases.email, '@') + 1)))
+    );
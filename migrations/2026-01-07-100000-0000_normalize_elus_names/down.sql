@@ -0,0 +1 @@
+-- The original whitespace of the names isn't kept: nothing to undo.
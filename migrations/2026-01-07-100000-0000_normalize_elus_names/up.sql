@@ -0,0 +1,16 @@
+-- Trims the names of elus and collapses their runs of whitespace, as
+-- person_name::PersonName does on the way in. Composing accents needs
+-- Unicode tables SQLite doesn't have: names are recomposed when next saved.
+CREATE TEMPORARY TABLE normalized_names AS
+  SELECT id, trim(replace(replace(replace(name, char(9), ' '), char(10), ' '), char(13), ' ')) AS name FROM elus;
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE normalized_names SET name = replace(name, '  ', ' ') WHERE name LIKE '%  %';
+UPDATE elus SET
+    name = (SELECT normalized.name FROM normalized_names normalized WHERE normalized.id = elus.id),
+    search_name = trim(replace(replace(replace(replace(replace(replace(search_name, char(9), ' '), char(10), ' '), char(13), ' '), '    ', ' '), '  ', ' '), '  ', ' '))
+  WHERE name != (SELECT normalized.name FROM normalized_names normalized WHERE normalized.id = elus.id);
+DROP TABLE normalized_names;
@@ -0,0 +1,9 @@
+-- Links between elus, such as the substitute of a deputy or the deputies
+-- of a mayor: elu_id is the <kind> of related_id.
+CREATE TABLE relations (
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  kind TEXT NOT NULL,
+  related_id INTEGER NOT NULL REFERENCES elus (id),
+  PRIMARY KEY (elu_id, kind, related_id)
+);
+CREATE INDEX relations_related_id ON relations (related_id);
@@ -0,0 +1,2 @@
+DROP TABLE body_members;
+DROP TABLE bodies;
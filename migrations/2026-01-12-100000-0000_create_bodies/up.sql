@@ -0,0 +1,16 @@
+-- Councils, commissions and the like, and the elus sitting on them.
+CREATE TABLE bodies (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  commune_code TEXT
+);
+CREATE INDEX bodies_commune_code ON bodies (commune_code);
+CREATE TABLE body_members (
+  body_id INTEGER NOT NULL REFERENCES bodies (id),
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  -- Role within the body, such as "Président"; null for plain members.
+  role TEXT,
+  PRIMARY KEY (body_id, elu_id)
+);
+CREATE INDEX body_members_elu_id ON body_members (elu_id);
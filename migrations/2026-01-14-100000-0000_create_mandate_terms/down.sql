@@ -0,0 +1 @@
+DROP TABLE mandate_terms;
@@ -0,0 +1,11 @@
+-- Start and end dates of mandates, by elu and title. alerted_on is when
+-- the coming end of the mandate was last announced.
+CREATE TABLE mandate_terms (
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  title TEXT NOT NULL,
+  started_on DATE,
+  ends_on DATE,
+  alerted_on DATE,
+  PRIMARY KEY (elu_id, title)
+);
+CREATE INDEX mandate_terms_ends_on ON mandate_terms (ends_on);
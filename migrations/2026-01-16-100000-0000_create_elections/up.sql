@@ -0,0 +1,12 @@
+-- Elections, and whether their results have been applied to the mandates
+-- of the elus they renew.
+CREATE TABLE elections (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  date DATE NOT NULL,
+  -- "national", "department:<code>" or "commune:<INSEE code>".
+  scope TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'scheduled',
+  transitioned_at TIMESTAMP
+);
+CREATE INDEX elections_date ON elections (date);
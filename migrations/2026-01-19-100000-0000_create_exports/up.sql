@@ -0,0 +1,13 @@
+-- Runs of the nightly export, and the files each wrote, which are removed
+-- once newer exports are kept in their place.
+CREATE TABLE exports (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'running',
+  -- JSON array of the keys of the files written.
+  files TEXT NOT NULL DEFAULT '[]',
+  persons INTEGER NOT NULL DEFAULT 0,
+  error TEXT,
+  removed BOOLEAN NOT NULL DEFAULT 0,
+  started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  finished_at TIMESTAMP
+);
@@ -0,0 +1,2 @@
+DROP INDEX elus_search_phonetic;
+ALTER TABLE elus DROP COLUMN search_phonetic;
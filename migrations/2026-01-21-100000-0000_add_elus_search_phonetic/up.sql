@@ -0,0 +1,4 @@
+-- The phonetic key of the name, which fuzzy searches match against; its
+-- rules are those of phonetic::key, so existing rows get it on startup.
+ALTER TABLE elus ADD COLUMN search_phonetic TEXT NOT NULL DEFAULT '';
+CREATE INDEX elus_search_phonetic ON elus (search_phonetic);
@@ -0,0 +1 @@
+DROP TABLE elu_emails;
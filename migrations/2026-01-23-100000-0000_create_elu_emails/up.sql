@@ -0,0 +1,9 @@
+-- Every address of each elu, the one of elus.email flagged as primary; an
+-- address belongs to one elu at most.
+CREATE TABLE elu_emails (
+  email TEXT PRIMARY KEY NOT NULL,
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  is_primary BOOLEAN NOT NULL DEFAULT 0
+);
+CREATE INDEX elu_emails_elu_id ON elu_emails (elu_id);
+INSERT INTO elu_emails (email, elu_id, is_primary) SELECT email, id, 1 FROM elus;
@@ -0,0 +1,4 @@
+ALTER TABLE notifications DROP COLUMN skipped;
+ALTER TABLE elus DROP COLUMN consent_source;
+ALTER TABLE elus DROP COLUMN consent_date;
+ALTER TABLE elus DROP COLUMN may_contact_by_email;
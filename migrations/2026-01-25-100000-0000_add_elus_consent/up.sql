@@ -0,0 +1,7 @@
+-- Consent to mailings, which notifications only go out with: when and how
+-- it was given is kept as proof, as GDPR requires.
+ALTER TABLE elus ADD COLUMN may_contact_by_email BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE elus ADD COLUMN consent_date TIMESTAMP;
+ALTER TABLE elus ADD COLUMN consent_source TEXT;
+-- Elus a notification wasn't sent to for lack of consent.
+ALTER TABLE notifications ADD COLUMN skipped INTEGER NOT NULL DEFAULT 0;
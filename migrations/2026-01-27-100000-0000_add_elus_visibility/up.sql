@@ -0,0 +1,2 @@
+-- Who may see each elu: public, internal or hidden.
+ALTER TABLE elus ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
@@ -0,0 +1,2 @@
+DROP TABLE elu_tags;
+DROP TABLE tags;
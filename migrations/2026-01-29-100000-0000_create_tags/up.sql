@@ -0,0 +1,12 @@
+-- Free-form labels staff put on elus, beyond their mandates.
+CREATE TABLE tags (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  description TEXT
+);
+CREATE TABLE elu_tags (
+  elu_id INTEGER NOT NULL REFERENCES elus (id),
+  tag_id INTEGER NOT NULL REFERENCES tags (id),
+  PRIMARY KEY (elu_id, tag_id)
+);
+CREATE INDEX elu_tags_tag_id ON elu_tags (tag_id);
@@ -0,0 +1,2 @@
+ALTER TABLE elus DROP COLUMN custom;
+DROP TABLE custom_fields;
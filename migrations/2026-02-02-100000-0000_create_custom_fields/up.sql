@@ -0,0 +1,14 @@
+-- Attributes collectivities add to elus, such as a commission seat number;
+-- the values live in `elus.custom`, a JSON object keyed by field name.
+CREATE TABLE custom_fields (
+  name TEXT PRIMARY KEY NOT NULL,
+  kind TEXT NOT NULL,
+  description TEXT,
+  required BOOLEAN NOT NULL DEFAULT 0,
+  -- JSON array of the allowed values, for text fields.
+  options TEXT,
+  minimum DOUBLE,
+  maximum DOUBLE,
+  max_length INTEGER
+);
+ALTER TABLE elus ADD COLUMN custom TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+DROP TABLE collectivite_settings;
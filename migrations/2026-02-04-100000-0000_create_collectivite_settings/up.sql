@@ -0,0 +1,9 @@
+-- How each collectivity, a commune, presents itself on the pages and
+-- exports of its elus; communes without a row get the defaults.
+CREATE TABLE collectivite_settings (
+  code TEXT PRIMARY KEY NOT NULL REFERENCES communes (code),
+  display_name TEXT,
+  contact_email TEXT,
+  default_visibility TEXT NOT NULL DEFAULT 'public',
+  locale TEXT NOT NULL DEFAULT 'fr'
+);
@@ -0,0 +1,253 @@
+//! Alerts on mandates coming to an end: `GET /alerts/expiring` lists the
+//! terms ending within the next days, and a background check flags those
+//! it hasn't announced yet, mailing a digest of them to the configured
+//! distribution list through the mail queue. A term is announced once,
+//! unless its end date is changed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::Date;
+
+use crate::auth::Admin;
+use crate::config::AppConfig;
+use crate::encryption::Sealer;
+use crate::mail::Message;
+use crate::mail_queue;
+use crate::person_name::PersonName;
+use crate::repository::PersonRepository;
+use crate::schema::mandate_terms;
+use crate::{db, shutdown, timeouts, timestamp, DbConn};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AlertsConfig {
+    /// Seconds between two checks for expiring mandates; 0 disables them.
+    pub poll_interval: u64,
+    /// How many days ahead a mandate's end is announced.
+    pub within_days: u32,
+    /// Addresses the digest of newly expiring mandates is mailed to; they
+    /// are only flagged when empty.
+    pub recipients: Vec<String>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            poll_interval: 3600,
+            within_days: 30,
+            recipients: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Expiring {
+    pub uuid: String,
+    pub name: PersonName,
+    pub mandate: String,
+    #[serde(with = "timestamp::date")]
+    pub ends_on: Date,
+    pub days_left: i64,
+    /// When the end of the mandate was announced, if it was.
+    #[serde(with = "timestamp::date::option")]
+    pub alerted_on: Option<Date>,
+}
+
+/// The mandates ending between `today` and `within_days` later, soonest
+/// first, with the ids of their elus. Terms of mandates the elu no longer
+/// holds are left out.
+fn find(today: Date, within_days: u32, db: &DbConn, repository: &dyn PersonRepository) -> Result<Vec<(i32, Expiring)>, Status> {
+    let last = today + time::Duration::days(within_days.into());
+    let terms: Vec<(i32, String, Option<Date>, Option<Date>)> = mandate_terms::table
+        .filter(mandate_terms::ends_on.between(today, last))
+        .order((mandate_terms::ends_on, mandate_terms::elu_id, mandate_terms::title))
+        .select((mandate_terms::elu_id, mandate_terms::title, mandate_terms::ends_on, mandate_terms::alerted_on))
+        .load(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut expiring = Vec::with_capacity(terms.len());
+    for (elu_id, mandate, ends_on, alerted_on) in terms {
+        let Some(ends_on) = ends_on else { continue };
+        let person = match repository.get(elu_id) {
+            Ok(person) => person,
+            Err(status) if status == Status::NotFound => continue,
+            Err(status) => return Err(status),
+        };
+        if !person.mandates.contains(&mandate) {
+            continue;
+        }
+        let days_left = (ends_on - today).whole_days();
+        expiring.push((elu_id, Expiring { uuid: person.uuid, name: person.name, mandate, ends_on, days_left, alerted_on }));
+    }
+    Ok(expiring)
+}
+
+/// The mandates ending within `within_days` of `today`.
+pub fn expiring(today: Date, within_days: u32, db: &DbConn, repository: &dyn PersonRepository) -> Result<Vec<Expiring>, Status> {
+    Ok(find(today, within_days, db, repository)?.into_iter().map(|(_, expiring)| expiring).collect())
+}
+
+fn digest(to: &str, expiring: &[Expiring]) -> Message {
+    let lines: Vec<String> = expiring
+        .iter()
+        .map(|expiring| format!("- {} ({}): ends on {}, in {} days", expiring.name, expiring.mandate, expiring.ends_on, expiring.days_left))
+        .collect();
+    Message {
+        to: to.to_string(),
+        subject: format!("{} mandates ending soon", expiring.len()),
+        body: format!("These mandates end within the coming days:\n\n{}\n", lines.join("\n")),
+    }
+}
+
+/// Flags the mandates expiring as of `today` which weren't announced yet,
+/// queueing their digest for each recipient in the same transaction.
+/// Returns the number of mandates flagged.
+pub fn announce(db: &DbConn, repository: &dyn PersonRepository, config: &AlertsConfig, sealer: &Sealer, today: Date) -> Result<usize, Status> {
+    let (ids, expiring): (Vec<i32>, Vec<Expiring>) =
+        find(today, config.within_days, db, repository)?.into_iter().filter(|(_, expiring)| expiring.alerted_on.is_none()).unzip();
+    if expiring.is_empty() {
+        return Ok(0);
+    }
+
+    let mut connection = db::lock(db)?;
+    connection
+        .transaction(|connection| {
+            for (elu_id, expiring) in ids.iter().zip(&expiring) {
+                diesel::update(mandate_terms::table.find((elu_id, &expiring.mandate)))
+                    .set(mandate_terms::alerted_on.eq(today))
+                    .execute(connection)?;
+            }
+            for recipient in &config.recipients {
+                mail_queue::enqueue(&digest(recipient, &expiring), None, sealer, connection)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(expiring.len())
+}
+
+/// Background check announcing expiring mandates.
+pub fn fairing(config: AlertsConfig) -> AdHoc {
+    AdHoc::on_liftoff("Expiring mandates check", move |rocket| Box::pin(async move {
+        if config.poll_interval == 0 {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let repository = rocket.state::<Arc<dyn PersonRepository>>().expect("repository is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval));
+            while shutdown::tick(&mut interval, &shutdown).await {
+                let (db, repository, config, sealer) = (db.clone(), repository.clone(), config.clone(), sealer.clone());
+                let run = rocket::tokio::task::spawn_blocking(move || announce(&db, repository.as_ref(), &config, &sealer, timestamp::now().date()));
+                match rocket::tokio::time::timeout(job, run).await {
+                    Ok(Ok(Ok(0))) => {}
+                    Ok(Ok(Ok(flagged))) => log::info!("Announced the end of {} mandates", flagged),
+                    Ok(Ok(Err(status))) => log::error!("Expiring mandates check failed: {}", status),
+                    Ok(Err(e)) => log::error!("Expiring mandates check panicked: {}", e),
+                    Err(_) => log::warn!("Expiring mandates check still running after {} seconds, no longer waiting for it", job.as_secs()),
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+/// Lists the mandates ending within `within_days`, by default those the
+/// background check announces.
+#[get("/alerts/expiring?<within_days>")]
+fn list_expiring(
+    within_days: Option<u32>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    config: &State<AppConfig>,
+) -> Result<Json<Vec<Expiring>>, Status> {
+    let within_days = within_days.unwrap_or(config.alerts.within_days);
+    expiring(timestamp::now().date(), within_days, db, repository.as_ref()).map(Json)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_expiring]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::SqliteRepository;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+    use std::sync::Mutex;
+    use time::macros::date;
+
+    fn set_term(elu: &str, mandate: &str, ends_on: Date, connection: &mut diesel::SqliteConnection) {
+        let elu_id: i32 = crate::schema::elus::table
+            .filter(crate::schema::elus::email.eq(elu))
+            .select(crate::schema::elus::id)
+            .first(connection)
+            .unwrap();
+        diesel::insert_into(mandate_terms::table)
+            .values((mandate_terms::elu_id.eq(elu_id), mandate_terms::title.eq(mandate), mandate_terms::ends_on.eq(ends_on)))
+            .execute(connection)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_announce() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        set_term("jean.dupont@example.com", "Maire", date!(2026 - 03 - 22), &mut connection);
+        set_term("marie.martin@example.com", "Députée", date!(2026 - 03 - 01), &mut connection);
+        set_term("jean.dupont@example.com", "Conseiller régional", date!(2028 - 03 - 31), &mut connection);
+        // No longer held, so never announced.
+        set_term("marie.martin@example.com", "Maire", date!(2026 - 03 - 10), &mut connection);
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        let repository = SqliteRepository::new(db.clone());
+        let config = AlertsConfig { recipients: vec!["cabinet@example.com".to_string()], ..Default::default() };
+
+        let today = date!(2026 - 02 - 25);
+        let found = expiring(today, config.within_days, &db, &repository).unwrap();
+        let found: Vec<(&str, &str, i64)> = found.iter().map(|expiring| (expiring.name.as_str(), expiring.mandate.as_str(), expiring.days_left)).collect();
+        assert_eq!(found, [("Marie Martin", "Députée", 4), ("Jean Dupont", "Maire", 25)]);
+
+        assert_eq!(announce(&db, &repository, &config, &Sealer::default(), today), Ok(2));
+        assert_eq!(announce(&db, &repository, &config, &Sealer::default(), today), Ok(0));
+        let mail: Vec<(String, String)> = crate::schema::mail_queue::table
+            .select((crate::schema::mail_queue::recipient, crate::schema::mail_queue::body))
+            .load(&mut *db.lock().unwrap())
+            .unwrap();
+        assert_eq!(mail.len(), 1);
+        assert_eq!(mail[0].0, "cabinet@example.com");
+        assert!(mail[0].1.contains("- Marie Martin (Députée): ends on 2026-03-01, in 4 days\n- Jean Dupont (Maire)"));
+        let alerted = expiring(today, config.within_days, &db, &repository).unwrap();
+        assert!(alerted.iter().all(|expiring| expiring.alerted_on == Some(today)));
+    }
+
+    #[test]
+    fn test_list_expiring() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let ends_on = timestamp::now().date() + time::Duration::days(45);
+        let term = json!({ "ends_on": ends_on.to_string() });
+        client.put("/elus/jean.dupont@example.com/terms/Maire").header(admin()).json(&term).dispatch();
+
+        assert_eq!(client.get("/alerts/expiring").dispatch().status(), Status::Unauthorized);
+        let found: Vec<Expiring> = client.get("/alerts/expiring").header(admin()).dispatch().into_json().unwrap();
+        assert!(found.is_empty());
+        let found: Vec<Expiring> = client.get("/alerts/expiring?within_days=60").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!((found[0].mandate.as_str(), found[0].days_left, found[0].alerted_on), ("Maire", 45, None));
+    }
+}
@@ -0,0 +1,310 @@
+//! Keys identifying third-party consumers of the API. Requests carrying one
+//! in `X-Api-Key` are counted per calendar month (UTC), and refused with 429
+//! once the key's monthly quota is used up; requests without a key are
+//! served as before. The open-data mirror relaxes this: requests with
+//! unknown keys or beyond their quota are still served.
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::route::{Handler, Outcome, Route};
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::{Data, Request, State};
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::schema::{api_keys, api_usage};
+use crate::sha256::{hex, sha256};
+use crate::{db, timestamp, DbConn};
+
+pub const HEADER: &str = "X-Api-Key";
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = api_keys)]
+#[serde(crate = "rocket::serde")]
+pub struct ApiKey {
+    pub id: i32,
+    /// Who the key was handed out to.
+    pub name: String,
+    /// Requests allowed per month; unlimited when unset.
+    pub monthly_quota: Option<i64>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewApiKey {
+    pub name: String,
+    pub monthly_quota: Option<i64>,
+}
+
+/// A newly created key: the only time the key itself is shown, as only its
+/// hash is stored.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Usage {
+    pub name: String,
+    pub monthly_quota: Option<i64>,
+    pub requests: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UsageReport {
+    pub month: String,
+    pub keys: Vec<Usage>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Metering {
+    Counted,
+    UnknownKey,
+    QuotaExceeded,
+}
+
+fn hash(key: &str) -> String {
+    hex(&sha256(key.as_bytes()))
+}
+
+/// The month usage is accounted to, as `YYYY-MM`.
+pub fn month(at: PrimitiveDateTime) -> String {
+    at.format(format_description!("[year]-[month]")).expect("valid date format")
+}
+
+/// Counts a request made with `key` against its quota for `month`;
+/// requests beyond the quota aren't counted.
+pub fn meter(key: &str, month: &str, connection: &mut SqliteConnection) -> QueryResult<Metering> {
+    connection.transaction(|connection| {
+        let found: Option<(i32, Option<i64>)> = api_keys::table
+            .filter(api_keys::key_hash.eq(hash(key)))
+            .select((api_keys::id, api_keys::monthly_quota))
+            .first(connection)
+            .optional()?;
+        let Some((key_id, quota)) = found else {
+            return Ok(Metering::UnknownKey);
+        };
+
+        let used: i64 = api_usage::table
+            .find((key_id, month))
+            .select(api_usage::requests)
+            .first(connection)
+            .optional()?
+            .unwrap_or(0);
+        if quota.is_some_and(|quota| used >= quota) {
+            return Ok(Metering::QuotaExceeded);
+        }
+
+        diesel::insert_into(api_usage::table)
+            .values((api_usage::api_key_id.eq(key_id), api_usage::month.eq(month), api_usage::requests.eq(1)))
+            .on_conflict((api_usage::api_key_id, api_usage::month))
+            .do_update()
+            .set(api_usage::requests.eq(api_usage::requests + 1))
+            .execute(connection)?;
+        Ok(Metering::Counted)
+    })
+}
+
+/// Wraps a route's handler to meter the requests carrying an API key
+/// before they're handled, refusing them unless quotas are relaxed.
+#[derive(Clone)]
+struct Metered {
+    handler: Box<dyn Handler>,
+    relaxed: bool,
+}
+
+#[rocket::async_trait]
+impl Handler for Metered {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        if let Some(key) = request.headers().get_one(HEADER) {
+            let Some(db) = request.rocket().state::<DbConn>() else {
+                return Outcome::Error(Status::InternalServerError);
+            };
+            let Ok(mut connection) = db::lock(db) else {
+                return Outcome::Error(Status::ServiceUnavailable);
+            };
+            let metering = meter(key, &month(timestamp::now()), &mut connection);
+            match metering {
+                Ok(Metering::Counted) => {}
+                Ok(_) if self.relaxed => {}
+                Ok(Metering::UnknownKey) => return Outcome::Error(Status::Unauthorized),
+                Ok(Metering::QuotaExceeded) => return Outcome::Error(Status::TooManyRequests),
+                Err(e) => {
+                    log::error!("Could not meter API key usage: {}", e);
+                    return Outcome::Error(Status::InternalServerError);
+                }
+            }
+        }
+
+        self.handler.handle(request, data).await
+    }
+}
+
+/// Applies API key metering to every route; with `relaxed` quotas, keys
+/// are only counted.
+pub fn metered(routes: Vec<Route>, relaxed: bool) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(Metered { handler: route.handler, relaxed });
+            route
+        })
+        .collect()
+}
+
+#[post("/admin/api-keys", data = "<new_key>")]
+fn create_api_key(new_key: Json<NewApiKey>, _admin: Admin, db: &State<DbConn>) -> Result<Created<Json<CreatedApiKey>>, Status> {
+    if new_key.monthly_quota.is_some_and(|quota| quota < 0) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let key = BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let api_key = diesel::insert_into(api_keys::table)
+        .values((
+            api_keys::name.eq(&new_key.name),
+            api_keys::key_hash.eq(hash(&key)),
+            api_keys::monthly_quota.eq(new_key.monthly_quota),
+        ))
+        .returning(ApiKey::as_returning())
+        .get_result(&mut *db::lock(db)?)
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+            _ => Status::InternalServerError,
+        })?;
+
+    let location = format!("/admin/api-keys/{}", api_key.id);
+    Ok(Created::new(location).body(Json(CreatedApiKey { api_key, key })))
+}
+
+/// Revokes a key; its usage history is dropped along with it.
+#[delete("/admin/api-keys/<id>")]
+fn delete_api_key(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let deleted = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            diesel::delete(api_usage::table.filter(api_usage::api_key_id.eq(id))).execute(connection)?;
+            diesel::delete(api_keys::table.find(id)).execute(connection)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// Requests made with each key during the month (`YYYY-MM`, the current
+/// one by default).
+#[get("/admin/usage?<month>")]
+fn usage(month: Option<String>, _admin: Admin, db: &State<DbConn>) -> Result<Json<UsageReport>, Status> {
+    let month = match month {
+        Some(month) if is_month(&month) => month,
+        Some(_) => return Err(Status::BadRequest),
+        None => self::month(timestamp::now()),
+    };
+
+    let keys = api_keys::table
+        .left_join(api_usage::table.on(api_usage::api_key_id.eq(api_keys::id).and(api_usage::month.eq(&month))))
+        .order(api_keys::name)
+        .select((api_keys::name, api_keys::monthly_quota, api_usage::requests.nullable()))
+        .load::<(String, Option<i64>, Option<i64>)>(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|(name, monthly_quota, requests)| Usage { name, monthly_quota, requests: requests.unwrap_or(0) })
+        .collect();
+
+    Ok(Json(UsageReport { month, keys }))
+}
+
+fn is_month(value: &str) -> bool {
+    let Some((year, month)) = value.split_once('-') else {
+        return false;
+    };
+    year.len() == 4
+        && year.bytes().all(|byte| byte.is_ascii_digit())
+        && month.len() == 2
+        && month.parse::<u8>().is_ok_and(|month| (1..=12).contains(&month))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![create_api_key, delete_api_key, usage]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::http::Header;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_meter() {
+        let mut connection = setup_test_db();
+        diesel::insert_into(api_keys::table)
+            .values((api_keys::name.eq("Open data partner"), api_keys::key_hash.eq(hash("secret")), api_keys::monthly_quota.eq(Some(2))))
+            .execute(&mut connection)
+            .unwrap();
+
+        assert_eq!(meter("secret", "2025-11", &mut connection), Ok(Metering::Counted));
+        assert_eq!(meter("secret", "2025-11", &mut connection), Ok(Metering::Counted));
+        assert_eq!(meter("secret", "2025-11", &mut connection), Ok(Metering::QuotaExceeded));
+        // Quotas are reset every month.
+        assert_eq!(meter("secret", "2025-12", &mut connection), Ok(Metering::Counted));
+        assert_eq!(meter("guessed", "2025-12", &mut connection), Ok(Metering::UnknownKey));
+
+        let used: i64 = api_usage::table.find((1, "2025-11")).select(api_usage::requests).first(&mut connection).unwrap();
+        assert_eq!(used, 2);
+    }
+
+    #[test]
+    fn test_is_month() {
+        assert!(is_month("2025-11"));
+        assert!(!is_month("2025-13"));
+        assert!(!is_month("2025-1"));
+        assert!(!is_month("25-11"));
+    }
+
+    #[test]
+    fn test_quota_enforcement() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.post("/admin/api-keys").json(&json!({ "name": "Partner", "monthly_quota": 2 })).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.post("/admin/api-keys").header(admin()).json(&json!({ "name": "Partner", "monthly_quota": 2 })).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let created: CreatedApiKey = response.into_json().expect("valid JSON");
+        let response = client.post("/admin/api-keys").header(admin()).json(&json!({ "name": "Partner" })).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let with_key = |uri: &str, key: &str| client.get(uri.to_string()).header(Header::new(HEADER, key.to_string())).dispatch().status();
+        assert_eq!(with_key("/elus", &created.key), Status::Ok);
+        assert_eq!(with_key("/elus/jean.dupont@example.com", &created.key), Status::Ok);
+        assert_eq!(with_key("/elus", &created.key), Status::TooManyRequests);
+        assert_eq!(with_key("/elus", "guessed"), Status::Unauthorized);
+        // Anonymous access is unaffected.
+        assert_eq!(client.get("/elus").dispatch().status(), Status::Ok);
+
+        let response = client.get("/admin/usage").header(admin()).dispatch();
+        let report: UsageReport = response.into_json().expect("valid JSON");
+        assert_eq!(report.month, month(timestamp::now()));
+        assert_eq!((report.keys[0].name.as_str(), report.keys[0].requests), ("Partner", 2));
+        let response = client.get("/admin/usage?month=1999-01").header(admin()).dispatch();
+        assert_eq!(response.into_json::<UsageReport>().map(|report| report.keys[0].requests), Some(0));
+
+        let uri = format!("/admin/api-keys/{}", created.api_key.id);
+        assert_eq!(client.delete(uri.clone()).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NotFound);
+        assert_eq!(with_key("/elus", &created.key), Status::Unauthorized);
+    }
+}
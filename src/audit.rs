@@ -0,0 +1,78 @@
+//! Log of security events: sign-ins, failed attempts, lockouts, password
+//! resets and changes to second factors.
+
+use std::net::IpAddr;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::schema::audit_log;
+use crate::{db, timestamp, DbConn};
+
+pub const LOGIN_SUCCEEDED: &str = "login_succeeded";
+pub const LOGIN_FAILED: &str = "login_failed";
+/// Failures reached the threshold, locking the account or address out.
+pub const LOCKOUT_STARTED: &str = "lockout_started";
+/// An attempt refused because of a lockout, without checking the password.
+pub const LOGIN_LOCKED_OUT: &str = "login_locked_out";
+pub const LOGOUT: &str = "logout";
+pub const PASSWORD_RESET_REQUESTED: &str = "password_reset_requested";
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const TWO_FACTOR_ENABLED: &str = "two_factor_enabled";
+pub const TWO_FACTOR_DISABLED: &str = "two_factor_disabled";
+/// Signed in with a backup code instead of a TOTP code.
+pub const BACKUP_CODE_USED: &str = "backup_code_used";
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = audit_log)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditEntry {
+    pub id: i32,
+    pub event: String,
+    /// The account concerned, or the username attempted at sign in.
+    pub username: Option<String>,
+    /// Address of the client, as seen by Rocket.
+    pub ip: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub at: PrimitiveDateTime,
+}
+
+pub fn record(event: &str, username: Option<&str>, ip: Option<IpAddr>, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::insert_into(audit_log::table)
+        .values((
+            audit_log::event.eq(event),
+            audit_log::username.eq(username),
+            audit_log::ip.eq(ip.map(|ip| ip.to_string())),
+            audit_log::at.eq(timestamp::now()),
+        ))
+        .execute(connection)
+        .map(|_| ())
+}
+
+/// Most recent events first, optionally of a single kind.
+#[get("/admin/audit?<event>&<limit>")]
+fn audit(event: Option<&str>, limit: Option<i64>, _admin: Admin, db: &State<DbConn>) -> Result<Json<Vec<AuditEntry>>, Status> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Status::BadRequest);
+    }
+
+    let mut query = audit_log::table.select(AuditEntry::as_select()).order(audit_log::id.desc()).limit(limit).into_boxed();
+    if let Some(event) = event {
+        query = query.filter(audit_log::event.eq(event));
+    }
+
+    query.load(&mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![audit]
+}
@@ -0,0 +1,57 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+
+use crate::config::AppConfig;
+
+/// Request guard for administrative endpoints: succeeds when the request
+/// carries `Authorization: Bearer <admin_token>`. Administrative endpoints
+/// are unreachable when no `admin_token` is configured.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        if is_admin(request) {
+            request::Outcome::Success(Admin)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// Whether the request carries the admin token, for code that can't use
+/// the guard, such as responders.
+pub fn is_admin(request: &Request<'_>) -> bool {
+    let expected = request
+        .rocket()
+        .state::<AppConfig>()
+        .and_then(|config| config.admin_token.as_deref());
+    let provided = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match (expected, provided) {
+        (Some(expected), Some(provided)) => constant_time_eq(expected.as_bytes(), provided.as_bytes()),
+        _ => false,
+    }
+}
+
+/// Compares secrets without leaking the position of the first difference.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Benchmarks of the hot paths against an in-memory database: serializing
+//! listings, searching and creating elus, so that the pooling and async
+//! refactors can be compared before release. They're ignored tests timed
+//! with the standard library, the crate being a binary which `benches/`
+//! targets can't link against; run them in release mode with
+//! `cargo test --release bench -- --ignored --nocapture --test-threads 1`.
+
+use std::hint::black_box;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::{self, NewPerson, SqliteRepository};
+use crate::repository::{Page, PersonFilter, PersonRepository};
+use crate::tests::setup_test_db;
+use crate::Person;
+
+/// Persons in the database the benchmarks run against.
+const PERSONS: usize = 1_000;
+
+/// Times `run` over batches for about a second and prints the median time
+/// per iteration, so that a slow batch doesn't skew it.
+fn measure(name: &str, mut run: impl FnMut()) {
+    run();
+    let mut batch = 1;
+    loop {
+        let start = Instant::now();
+        (0..batch).for_each(|_| run());
+        if start.elapsed() >= Duration::from_millis(10) {
+            break;
+        }
+        batch *= 2;
+    }
+
+    let mut samples = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while samples.len() < 10 || Instant::now() < deadline {
+        let start = Instant::now();
+        (0..batch).for_each(|_| run());
+        samples.push(start.elapsed() / batch);
+    }
+    samples.sort();
+    let median = samples[samples.len() / 2];
+    println!("{:<24} {:>12?}/iter {:>12.0} iter/s", name, median, 1.0 / median.as_secs_f64());
+}
+
+fn new_person(index: usize) -> NewPerson {
+    NewPerson {
+        name: format!("Élu Numéro {}", index).parse().unwrap(),
+        email: format!("elu{}@example.com", index).parse().unwrap(),
+        mandates: vec![if index.is_multiple_of(10) { "Maire" } else { "Conseiller municipal" }.to_string()],
+        commune_code: Some(format!("{:05}", 75000 + index % 100)),
+        ..Default::default()
+    }
+}
+
+fn repository() -> SqliteRepository {
+    let mut connection = setup_test_db();
+    for index in 0..PERSONS {
+        db::insert_person(&new_person(index), &mut connection).unwrap();
+    }
+    SqliteRepository::new(Arc::new(Mutex::new(connection)))
+}
+
+#[test]
+#[ignore]
+fn bench_list_serialization() {
+    let persons: Vec<Person> = repository().list().unwrap().into_iter().map(Person::from).collect();
+    measure("list serialization", || {
+        black_box(serde_json::to_string(black_box(&persons)).unwrap());
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_search() {
+    let repository = repository();
+    let by_name = PersonFilter { name: Some("numero 42".to_string()), ..Default::default() };
+    measure("search by name", || {
+        black_box(repository.search(black_box(&by_name)).unwrap());
+    });
+
+    let expression = crate::filter::parse(r#"mandate eq "Maire" and not commune eq "75000""#).unwrap();
+    let by_expression = PersonFilter { expression: Some(expression), ..Default::default() };
+    measure("search by expression", || {
+        black_box(repository.search(black_box(&by_expression)).unwrap());
+    });
+
+    let page = Page { offset: PERSONS as i64 / 2, limit: 50, ..Default::default() };
+    measure("search page", || {
+        black_box(repository.search_page(&PersonFilter::default(), black_box(&page)).unwrap());
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_create() {
+    let repository = repository();
+    let mut index = PERSONS;
+    measure("create", || {
+        index += 1;
+        black_box(repository.create(new_person(index)).unwrap());
+    });
+}
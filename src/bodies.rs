@@ -0,0 +1,283 @@
+//! Bodies elus sit on: municipal councils, commissions, groups. Each has
+//! members, possibly with a role such as "Président" or "Rapporteur", and
+//! the bodies of an elu are listed at `/elus/<key>/bodies`. Anyone can read
+//! bodies; only administrators can change them.
+
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::db;
+use crate::person_name::PersonName;
+use crate::repository::{PersonKey, PersonRepository};
+use crate::schema::{bodies, body_members};
+use crate::scraping::Probe;
+use crate::transaction::Transaction;
+use crate::visibility::Visible;
+use crate::DbConn;
+
+/// Kinds of bodies.
+const KINDS: &[&str] = &["council", "commission", "group"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = bodies)]
+#[serde(crate = "rocket::serde")]
+pub struct Body {
+    pub id: i32,
+    pub name: String,
+    pub kind: String,
+    /// INSEE code of the commune the body belongs to, if any.
+    pub commune_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Insertable, AsChangeset)]
+#[diesel(table_name = bodies, treat_none_as_null = true)]
+#[serde(crate = "rocket::serde")]
+pub struct NewBody {
+    name: String,
+    kind: String,
+    #[serde(default)]
+    commune_code: Option<String>,
+}
+
+impl NewBody {
+    fn check(&self, connection: &mut SqliteConnection) -> Result<(), Status> {
+        if self.name.trim().is_empty() || !KINDS.contains(&self.kind.as_str()) {
+            return Err(Status::UnprocessableEntity);
+        }
+        if let Some(code) = &self.commune_code {
+            db::get_commune(code, connection).map_err(|_| Status::UnprocessableEntity)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Member {
+    pub uuid: String,
+    pub name: PersonName,
+    pub role: Option<String>,
+}
+
+/// A body with its members, ordered by name.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyMembers {
+    #[serde(flatten)]
+    pub body: Body,
+    pub members: Vec<Member>,
+}
+
+/// A body an elu sits on, and their role in it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Membership {
+    #[serde(flatten)]
+    pub body: Body,
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewMember {
+    #[serde(default)]
+    role: Option<String>,
+}
+
+fn get_body(id: i32, connection: &mut SqliteConnection) -> Result<Body, Status> {
+    bodies::table
+        .find(id)
+        .select(Body::as_select())
+        .first(connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)
+}
+
+/// Lists the bodies, optionally only those of a commune.
+#[get("/bodies?<commune>")]
+fn list_bodies(commune: Option<&str>, db: &State<DbConn>) -> Result<Json<Vec<Body>>, Status> {
+    let mut query = bodies::table.order((bodies::name, bodies::id)).select(Body::as_select()).into_boxed();
+    if let Some(code) = commune {
+        query = query.filter(bodies::commune_code.eq(code));
+    }
+
+    query.load(&mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[post("/bodies", data = "<new_body>")]
+fn create_body(new_body: Json<NewBody>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Created<Json<Body>>, Status> {
+    let mut connection = db::lock(db)?;
+    new_body.check(&mut connection)?;
+    let body = diesel::insert_into(bodies::table)
+        .values(&*new_body)
+        .returning(Body::as_returning())
+        .get_result(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let location = format!("/bodies/{}", body.id);
+    Ok(Created::new(location).body(Json(body)))
+}
+
+#[get("/bodies/<id>")]
+fn get_body_members(id: i32, db: &State<DbConn>, repository: Visible) -> Result<Json<BodyMembers>, Status> {
+    let (body, rows) = {
+        let mut connection = db::lock(db)?;
+        let body = get_body(id, &mut connection)?;
+        let rows: Vec<(i32, Option<String>)> = body_members::table
+            .filter(body_members::body_id.eq(id))
+            .select((body_members::elu_id, body_members::role))
+            .load(&mut *connection)
+            .map_err(|_| Status::InternalServerError)?;
+        (body, rows)
+    };
+
+    let mut members = Vec::with_capacity(rows.len());
+    for (elu_id, role) in rows {
+        match repository.get(elu_id) {
+            Ok(person) => members.push(Member { uuid: person.uuid, name: person.name, role }),
+            Err(status) if status == Status::NotFound => {}
+            Err(status) => return Err(status),
+        }
+    }
+    members.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Json(BodyMembers { body, members }))
+}
+
+/// Replaces a body's name, kind and commune; members are kept.
+#[put("/bodies/<id>", data = "<new_body>")]
+fn update_body(id: i32, new_body: Json<NewBody>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Json<Body>, Status> {
+    let mut connection = db::lock(db)?;
+    new_body.check(&mut connection)?;
+    diesel::update(bodies::table.find(id))
+        .set(&*new_body)
+        .returning(Body::as_returning())
+        .get_result(&mut *connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .map(Json)
+        .ok_or(Status::NotFound)
+}
+
+#[delete("/bodies/<id>")]
+fn delete_body(id: i32, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Status, Status> {
+    let deleted = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            diesel::delete(body_members::table.filter(body_members::body_id.eq(id))).execute(connection)?;
+            diesel::delete(bodies::table.find(id)).execute(connection)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// Makes the elu a member of the body, or changes their role in it.
+#[put("/bodies/<id>/members/<key>", data = "<member>")]
+fn add_member(id: i32, key: &str, member: Option<Json<NewMember>>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let role = member.and_then(|member| member.into_inner().role);
+    let mut connection = db::lock(db)?;
+    get_body(id, &mut connection)?;
+    diesel::replace_into(body_members::table)
+        .values((body_members::body_id.eq(id), body_members::elu_id.eq(elu.id), body_members::role.eq(role)))
+        .execute(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+#[delete("/bodies/<id>/members/<key>")]
+fn remove_member(id: i32, key: &str, _admin: Admin, _transaction: Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let deleted = diesel::delete(body_members::table.find((id, elu.id)))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// Lists the bodies the elu sits on.
+#[get("/elus/<key>/bodies")]
+fn elu_bodies(key: &str, _probe: Probe, db: &State<DbConn>, repository: Visible) -> Result<Json<Vec<Membership>>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let memberships: Vec<(Body, Option<String>)> = body_members::table
+        .inner_join(bodies::table)
+        .filter(body_members::elu_id.eq(elu.id))
+        .order((bodies::name, bodies::id))
+        .select((Body::as_select(), body_members::role))
+        .load(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(memberships.into_iter().map(|(body, role)| Membership { body, role }).collect()))
+}
+
+/// Removes an elu from every body, before the elu itself is deleted.
+pub fn delete_memberships(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(body_members::table.filter(body_members::elu_id.eq(elu_id))).execute(connection)?;
+    Ok(())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_bodies, create_body, get_body_members, update_body, delete_body, add_member, remove_member, elu_bodies]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_bodies() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        db::upsert_communes(&[db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() }], &mut connection).unwrap();
+        let client = client(connection);
+
+        let council = json!({ "name": "Conseil de Paris", "kind": "council", "commune_code": "75056" });
+        assert_eq!(client.post("/bodies").json(&council).dispatch().status(), Status::Unauthorized);
+        let response = client.post("/bodies").header(admin()).json(&council).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let location = response.headers().get_one("Location").unwrap().to_string();
+        let body: Body = response.into_json().unwrap();
+        assert_eq!(location, format!("/bodies/{}", body.id));
+        let invalid = json!({ "name": "Commission", "kind": "committee" });
+        assert_eq!(client.post("/bodies").header(admin()).json(&invalid).dispatch().status(), Status::UnprocessableEntity);
+
+        let members = format!("/bodies/{}/members", body.id);
+        let response = client.put(format!("{}/jean.dupont@example.com", members)).header(admin()).json(&json!({ "role": "Président" })).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(client.put(format!("{}/marie.martin@example.com", members)).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.put("/bodies/999/members/marie.martin@example.com").header(admin()).dispatch().status(), Status::NotFound);
+
+        let found: BodyMembers = client.get(&location).dispatch().into_json().unwrap();
+        let names: Vec<(&str, Option<&str>)> = found.members.iter().map(|member| (member.name.as_str(), member.role.as_deref())).collect();
+        assert_eq!(names, [("Jean Dupont", Some("Président")), ("Marie Martin", None)]);
+        let memberships: Vec<Membership> = client.get("/elus/jean.dupont@example.com/bodies").dispatch().into_json().unwrap();
+        assert_eq!((memberships[0].body.name.as_str(), memberships[0].role.as_deref()), ("Conseil de Paris", Some("Président")));
+        let listed: Vec<Body> = client.get("/bodies?commune=75056").dispatch().into_json().unwrap();
+        assert_eq!(listed, [body]);
+
+        let renamed = json!({ "name": "Conseil municipal", "kind": "council", "commune_code": "75056" });
+        let updated: Body = client.put(&location).header(admin()).json(&renamed).dispatch().into_json().unwrap();
+        assert_eq!(updated.name, "Conseil municipal");
+
+        assert_eq!(client.delete(format!("{}/jean.dupont@example.com", members)).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete("/elus/marie.martin@example.com").header(admin()).dispatch().status(), Status::NoContent);
+        let found: BodyMembers = client.get(&location).dispatch().into_json().unwrap();
+        assert!(found.members.is_empty());
+        assert_eq!(client.delete(&location).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.get(&location).dispatch().status(), Status::NotFound);
+    }
+}
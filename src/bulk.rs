@@ -0,0 +1,153 @@
+//! Partial updates of every elu matching a filter at once, such as adding a
+//! mandate to all the "Conseiller municipal" of a commune:
+//! `PATCH /elus?mandate=...` takes the same criteria as `GET /elus` and
+//! applies the patch to all the matches in a single transaction. With
+//! `dry_run`, it only reports what it would modify.
+
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::criteria::Criteria;
+use crate::db::{self, NewPerson, Person};
+use crate::mandate_types::MandateTypes;
+use crate::repository::PersonRepository;
+use crate::transaction::Transaction;
+use crate::validation::{Schema, Validated};
+use crate::DbConn;
+
+/// The changes to make to each matching elu; unset fields are kept.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct Patch {
+    /// Mandates to add, by title or code, unless the elu already has them.
+    #[serde(default)]
+    add_mandates: Vec<String>,
+    #[serde(default)]
+    remove_mandates: Vec<String>,
+    commune_code: Option<String>,
+}
+
+impl Schema for Patch {
+    const NAME: &'static str = "Patch";
+}
+
+impl Patch {
+    /// The elu's data once patched, if that changes it.
+    fn apply(&self, person: &Person, mandate_types: &MandateTypes) -> Option<NewPerson> {
+        let removed: Vec<String> = self.remove_mandates.iter().map(|mandate| mandate_types.title(mandate)).collect();
+        let mut mandates: Vec<String> = person.mandates.iter().filter(|mandate| !removed.contains(mandate)).cloned().collect();
+        for mandate in self.add_mandates.iter().map(|mandate| mandate_types.title(mandate)) {
+            if !mandates.contains(&mandate) {
+                mandates.push(mandate);
+            }
+        }
+        let commune_code = self.commune_code.clone().or_else(|| person.commune_code.clone());
+
+        (mandates != person.mandates || commune_code != person.commune_code).then(|| NewPerson {
+            name: person.name.clone(),
+            email: person.email.clone(),
+            emails: person.emails.clone(),
+            mandates,
+            commune_code,
+            office_address: person.office_address.clone(),
+            latitude: person.latitude,
+            longitude: person.longitude,
+            may_contact_by_email: person.may_contact_by_email,
+            consent_date: person.consent_date,
+            consent_source: person.consent_source.clone(),
+            visibility: person.visibility,
+            custom: person.custom.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Summary {
+    /// How many elus matched the filter.
+    pub matched: usize,
+    /// UUIDs of the elus the patch changed, or would change on a dry run.
+    pub modified: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Applies the patch to the elus matching the criteria, which are those of
+/// `GET /elus`; at least one is required, so as not to patch everyone by
+/// mistake. The elus are matched and patched in one transaction.
+#[patch("/elus?<dry_run>&<criteria..>", data = "<patch>")]
+#[allow(clippy::too_many_arguments)]
+fn patch_elus(
+    criteria: Criteria,
+    dry_run: Option<bool>,
+    patch: Validated<Patch>,
+    _admin: Admin,
+    _transaction: Transaction,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    mandate_types: &State<MandateTypes>,
+) -> Result<Json<Summary>, Status> {
+    if criteria.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let filter = criteria.filter(db, mandate_types)?;
+    let patch = patch.into_inner();
+    if let Some(code) = &patch.commune_code {
+        db::get_commune(code, &mut *db::lock(db)?).map_err(|_| Status::UnprocessableEntity)?;
+    }
+
+    let matches = repository.search(&filter)?;
+    let updates: Vec<_> = matches.iter().filter_map(|person| Some((person.email.clone(), patch.apply(person, mandate_types)?))).collect();
+    let dry_run = dry_run.unwrap_or(false);
+    let modified = if dry_run {
+        let emails: Vec<_> = updates.into_iter().map(|(email, _)| email).collect();
+        matches.iter().filter(|person| emails.contains(&person.email)).map(|person| person.uuid.clone()).collect()
+    } else {
+        repository.update_many(updates)?.into_iter().map(|person| person.uuid).collect()
+    };
+
+    Ok(Json(Summary { matched: matches.len(), modified, dry_run }))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![patch_elus]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_patch_elus() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let patch = json!({ "add_mandates": ["Adjoint au maire"], "remove_mandates": ["Conseiller régional"] });
+
+        let response = client.patch("/elus?mandate=Maire&dry_run=true").header(admin()).json(&patch).dispatch();
+        let summary: Summary = response.into_json().unwrap();
+        assert_eq!((summary.matched, summary.modified.len(), summary.dry_run), (1, 1, true));
+        let persons: Vec<crate::Person> = client.get("/elus?mandate=Adjoint%20au%20maire").dispatch().into_json().unwrap();
+        assert!(persons.is_empty());
+
+        let summary: Summary = client.patch("/elus?mandate=Maire").header(admin()).json(&patch).dispatch().into_json().unwrap();
+        assert_eq!((summary.matched, summary.modified.len(), summary.dry_run), (1, 1, false));
+        let persons: Vec<crate::Person> = client.get("/elus?mandate=Adjoint%20au%20maire").dispatch().into_json().unwrap();
+        assert_eq!(persons[0].mandates, ["Maire", "Adjoint au maire"]);
+
+        // Patching again changes nothing.
+        let summary: Summary = client.patch("/elus?mandate=Maire").header(admin()).json(&patch).dispatch().into_json().unwrap();
+        assert_eq!((summary.matched, summary.modified.len()), (1, 0));
+
+        assert_eq!(client.patch("/elus?mandate=Maire").json(&patch).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.patch("/elus").header(admin()).json(&patch).dispatch().status(), Status::BadRequest);
+        let response = client.patch("/elus?mandate=Maire").header(admin()).json(&json!({ "commune_code": "99999" })).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+}
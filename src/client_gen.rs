@@ -0,0 +1,561 @@
+//! Generation of a typed Rust client from the route definitions, for other
+//! services to call the API without writing their own HTTP requests:
+//! `rocket-diesel --generate-client <dir>` writes a reqwest-based crate with
+//! an async method per mounted route. Routes the OpenAPI document describes
+//! take the name of their `operationId`, and its parameters, body and
+//! response types; the others are named after their handler, take the
+//! query parameters of their URI as strings and hand back the response as
+//! it is. As responses leave out the fields of `redaction.public`, every
+//! field of the schemas marked `x-redactable` is optional in the client.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use rocket::serde::json::Value;
+use rocket::Route;
+
+const CRATE_NAME: &str = "rckd-client";
+
+/// Words which can't be field names as they are.
+const KEYWORDS: &[&str] = &["type", "ref", "match", "move", "mod", "self", "use", "where", "loop", "impl"];
+
+/// The files of the client crate, by path within it.
+pub fn generate(routes: &[Route], spec: &Value) -> Vec<(&'static str, String)> {
+    vec![("Cargo.toml", manifest()), ("src/lib.rs", library(routes, spec))]
+}
+
+/// Writes the client crate to `dir`.
+pub fn write(dir: &Path, routes: &[Route], spec: &Value) -> std::io::Result<()> {
+    for (path, content) in generate(routes, spec) {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+fn manifest() -> String {
+    format!(
+        "[package]\n\
+         name = \"{}\"\n\
+         version = \"{}\"\n\
+         edition = \"2021\"\n\
+         description = \"Client of the elus API, generated from its route definitions.\"\n\
+         \n\
+         [dependencies]\n\
+         reqwest = {{ version = \"0.12\", features = [\"json\"] }}\n\
+         serde = {{ version = \"1.0\", features = [\"derive\"] }}\n\
+         serde_json = \"1.0\"\n",
+        CRATE_NAME,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn snake_case(identifier: &str) -> String {
+    let mut snake = String::new();
+    for c in identifier.chars() {
+        if c.is_uppercase() && !snake.is_empty() {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+fn pascal_case(snake: &str) -> String {
+    snake
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect()
+}
+
+fn field_name(property: &str) -> String {
+    let name = snake_case(property);
+    if KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else {
+        name
+    }
+}
+
+fn doc(comment: Option<&str>, indent: &str) -> String {
+    comment.map(|comment| comment.lines().map(|line| format!("{}/// {}\n", indent, line)).collect()).unwrap_or_default()
+}
+
+fn reference(schema: &Value) -> Option<&str> {
+    let reference = schema["$ref"].as_str()?;
+    Some(reference.rsplit('/').next().unwrap_or(reference))
+}
+
+/// The Rust type of values matching `schema`.
+fn rust_type(schema: &Value) -> String {
+    if let Some(name) = reference(schema) {
+        return name.to_string();
+    }
+
+    match &schema["type"] {
+        Value::Array(types) if types.iter().any(|kind| kind == "null") => {
+            let mut single = schema.clone();
+            single["type"] = types.iter().find(|kind| *kind != "null").cloned().unwrap_or(Value::Null);
+            format!("Option<{}>", rust_type(&single))
+        }
+        Value::String(kind) => match kind.as_str() {
+            "string" => "String".to_string(),
+            "integer" => "i64".to_string(),
+            "number" => "f64".to_string(),
+            "boolean" => "bool".to_string(),
+            "array" => format!("Vec<{}>", rust_type(&schema["items"])),
+            _ => "serde_json::Value".to_string(),
+        },
+        _ => "serde_json::Value".to_string(),
+    }
+}
+
+/// The fields of an object schema; all optional when `redactable`.
+fn fields(schema: &Value, redactable: bool) -> String {
+    let required: Vec<&str> = schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+    let mut code = String::new();
+    for (property, property_schema) in schema["properties"].as_object().into_iter().flatten() {
+        let mut kind = rust_type(property_schema);
+        if (redactable || !required.contains(&property.as_str())) && !kind.starts_with("Option<") {
+            kind = format!("Option<{}>", kind);
+        }
+        code.push_str(&doc(property_schema["description"].as_str(), "    "));
+        if kind.starts_with("Option<") {
+            code.push_str("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n");
+        }
+        let _ = writeln!(code, "    pub {}: {},", field_name(property), kind);
+    }
+    code
+}
+
+/// The type of a component schema: a struct for objects, whose `allOf`
+/// parts are flattened into it, and an untagged enum of the variants of
+/// `oneOf`, named by their titles.
+fn type_for(name: &str, schema: &Value) -> String {
+    let mut code = doc(schema["description"].as_str(), "");
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let _ = writeln!(code, "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\n#[serde(untagged)]\npub enum {} {{", name);
+        for (i, variant) in variants.iter().enumerate() {
+            let variant_name = variant["title"].as_str().map(str::to_string).unwrap_or_else(|| format!("Variant{}", i));
+            let _ = writeln!(code, "    {}({}),", variant_name, rust_type(variant));
+        }
+        code.push_str("}\n");
+        return code;
+    }
+
+    let _ = writeln!(code, "#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]\npub struct {} {{", name);
+    let redactable = schema["x-redactable"] == true;
+    for part in schema["allOf"].as_array().into_iter().flatten() {
+        match reference(part) {
+            Some(flattened) => {
+                let _ = writeln!(code, "    #[serde(flatten)]\n    pub {}: {},", snake_case(flattened), flattened);
+            }
+            None => code.push_str(&fields(part, redactable)),
+        }
+    }
+    code.push_str(&fields(schema, redactable));
+    code.push_str("}\n");
+    code
+}
+
+struct Parameter {
+    name: String,
+    /// Rust type of the value, `Option` aside.
+    kind: String,
+    required: bool,
+    description: Option<String>,
+}
+
+enum Body {
+    /// A JSON body of this type.
+    Json { kind: String, required: bool },
+    /// Bytes of the given content type, or of any type the caller gives
+    /// when `None`.
+    Raw(Option<String>),
+}
+
+enum Returned {
+    Json(String),
+    Nothing,
+    /// The response as it is, for non-JSON or undocumented responses.
+    Response,
+}
+
+/// What a method is generated from: a route, along with its operation in
+/// the OpenAPI document if it has one.
+struct Operation<'a> {
+    name: String,
+    method: String,
+    /// The path, with its parameters between braces as in the document.
+    path: String,
+    path_parameters: Vec<Parameter>,
+    query_parameters: Vec<Parameter>,
+    body: Option<Body>,
+    returned: Returned,
+    summary: Option<&'a str>,
+}
+
+fn parameter(documented: &Value) -> Parameter {
+    Parameter {
+        name: documented["name"].as_str().unwrap_or_default().to_string(),
+        kind: rust_type(&documented["schema"]),
+        required: documented["required"] == true,
+        description: documented["description"].as_str().map(str::to_string),
+    }
+}
+
+/// The name of a dynamic URI segment such as `<key>`; `None` for static
+/// segments and trailing ones such as `<criteria..>`, which stand for the
+/// fields of a form.
+fn dynamic(segment: &str) -> Option<&str> {
+    segment.strip_prefix('<')?.strip_suffix('>').filter(|name| !name.ends_with(".."))
+}
+
+fn operation<'a>(route: &'a Route, spec: &'a Value) -> Operation<'a> {
+    let path = route
+        .uri
+        .path()
+        .split('/')
+        .map(|segment| match segment.strip_prefix('<').and_then(|segment| segment.strip_suffix('>')) {
+            Some(name) => format!("{{{}}}", name.trim_end_matches("..")),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    let method = route.method.as_str().to_lowercase();
+    let item = &spec["paths"][&path];
+    let documented = item.get(&method);
+    let operation = documented.unwrap_or(&Value::Null);
+
+    let declared: Vec<&Value> = item["parameters"].as_array().into_iter().chain(operation["parameters"].as_array()).flatten().collect();
+    let path_parameters = route
+        .uri
+        .path()
+        .split('/')
+        .filter_map(dynamic)
+        .map(|name| match declared.iter().find(|parameter| parameter["in"] == "path" && parameter["name"] == name) {
+            Some(documented) => parameter(documented),
+            None => Parameter { name: name.to_string(), kind: "String".to_string(), required: true, description: None },
+        })
+        .collect();
+    let mut query_parameters: Vec<Parameter> = declared.iter().filter(|parameter| parameter["in"] == "query").map(|documented| parameter(documented)).collect();
+    for name in route.uri.query().into_iter().flat_map(|query| query.split('&')).filter_map(dynamic) {
+        if !query_parameters.iter().any(|parameter| parameter.name == name) {
+            query_parameters.push(Parameter { name: name.to_string(), kind: "String".to_string(), required: false, description: None });
+        }
+    }
+
+    let writes = ["post", "put", "patch"].contains(&method.as_str());
+    let body = match (documented, operation["requestBody"]["content"].as_object()) {
+        (Some(_), Some(content)) => Some(match content.get("application/json") {
+            Some(json) => Body::Json { kind: rust_type(&json["schema"]), required: operation["requestBody"]["required"] == true },
+            None => Body::Raw(content.keys().next().filter(|content_type| *content_type != "*/*").cloned()),
+        }),
+        (Some(_), None) => None,
+        (None, _) if writes => Some(Body::Json { kind: "serde_json::Value".to_string(), required: false }),
+        (None, _) => None,
+    };
+
+    let responses = &operation["responses"];
+    let returned = match ["200", "201"].iter().find_map(|status| responses.get(*status)) {
+        Some(response) => match response["content"]["application/json"].get("schema") {
+            Some(schema) => Returned::Json(rust_type(schema)),
+            None => Returned::Response,
+        },
+        None if documented.is_some() && responses.get("204").is_some() => Returned::Nothing,
+        None => Returned::Response,
+    };
+
+    let name = match operation["operationId"].as_str() {
+        Some(id) => snake_case(id),
+        None => route.name.as_deref().map(str::to_string).unwrap_or_else(|| format!("{}_{}", method, snake_case(&path.replace(['/', '{', '}', '.', '-'], "_")))),
+    };
+    Operation { name, method, path, path_parameters, query_parameters, body, returned, summary: operation["summary"].as_str() }
+}
+
+/// The type of the optional query parameters of `operation`, if it has any.
+fn query_type(operation: &Operation) -> Option<(String, String)> {
+    let optional: Vec<&Parameter> = operation.query_parameters.iter().filter(|parameter| !parameter.required).collect();
+    if optional.is_empty() {
+        return None;
+    }
+
+    let name = format!("{}Query", pascal_case(&operation.name));
+    let mut code = format!("/// Optional query parameters of `Client::{}`.\n", operation.name);
+    let _ = writeln!(code, "#[derive(Debug, Clone, Default, PartialEq, Serialize)]\npub struct {} {{", name);
+    for parameter in optional {
+        code.push_str(&doc(parameter.description.as_deref(), "    "));
+        code.push_str("    #[serde(skip_serializing_if = \"Option::is_none\")]\n");
+        let _ = writeln!(code, "    pub {}: Option<{}>,", field_name(&parameter.name), parameter.kind);
+    }
+    code.push_str("}\n");
+    Some((name, code))
+}
+
+/// The type of an argument taking `kind`: strings are borrowed.
+fn argument_type(kind: &str) -> &str {
+    if kind == "String" {
+        "&str"
+    } else {
+        kind
+    }
+}
+
+fn method_for(operation: &Operation, query_type: Option<&str>) -> String {
+    let mut arguments: Vec<String> = operation.path_parameters.iter().map(|parameter| format!("{}: {}", snake_case(&parameter.name), argument_type(&parameter.kind))).collect();
+    let required: Vec<&Parameter> = operation.query_parameters.iter().filter(|parameter| parameter.required).collect();
+    arguments.extend(required.iter().map(|parameter| format!("{}: {}", snake_case(&parameter.name), argument_type(&parameter.kind))));
+    match &operation.body {
+        Some(Body::Json { kind, required: true }) => arguments.push(format!("body: &{}", kind)),
+        Some(Body::Json { kind, required: false }) => arguments.push(format!("body: Option<&{}>", kind)),
+        Some(Body::Raw(None)) => arguments.extend(["content_type: &str".to_string(), "body: impl Into<reqwest::Body>".to_string()]),
+        Some(Body::Raw(Some(_))) => arguments.push("body: impl Into<reqwest::Body>".to_string()),
+        None => {}
+    }
+    if let Some(query_type) = query_type {
+        arguments.push(format!("query: &{}", query_type));
+    }
+    let returned = match &operation.returned {
+        Returned::Json(kind) => kind.as_str(),
+        Returned::Nothing => "()",
+        Returned::Response => "reqwest::Response",
+    };
+
+    let mut url = operation.path.clone();
+    let mut url_arguments = String::new();
+    for parameter in &operation.path_parameters {
+        url = url.replace(&format!("{{{}}}", parameter.name), "{}");
+        match parameter.kind.as_str() {
+            "String" => {
+                let _ = write!(url_arguments, ", segment({})", snake_case(&parameter.name));
+            }
+            _ => {
+                let _ = write!(url_arguments, ", segment(&{}.to_string())", snake_case(&parameter.name));
+            }
+        }
+    }
+
+    let mut code = doc(operation.summary, "    ");
+    let _ = writeln!(code, "    pub async fn {}(&self{}) -> Result<{}, Error> {{", operation.name, arguments.iter().map(|argument| format!(", {}", argument)).collect::<String>(), returned);
+    let _ = writeln!(code, "        let request = self.http.{}(format!(\"{{}}{}\", self.base_url{}));", operation.method, url, url_arguments);
+    if !required.is_empty() {
+        let pairs: Vec<String> = required.iter().map(|parameter| format!("(\"{}\", {}.to_string())", parameter.name, snake_case(&parameter.name))).collect();
+        let _ = writeln!(code, "        let request = request.query(&[{}]);", pairs.join(", "));
+    }
+    if query_type.is_some() {
+        code.push_str("        let request = request.query(query);\n");
+    }
+    match &operation.body {
+        Some(Body::Json { required: true, .. }) => code.push_str("        let request = request.json(body);\n"),
+        Some(Body::Json { required: false, .. }) => code.push_str("        let request = match body {\n            Some(body) => request.json(body),\n            None => request,\n        };\n"),
+        Some(Body::Raw(None)) => code.push_str("        let request = request.header(reqwest::header::CONTENT_TYPE, content_type).body(body);\n"),
+        Some(Body::Raw(Some(content_type))) => {
+            let _ = writeln!(code, "        let request = request.header(reqwest::header::CONTENT_TYPE, \"{}\").body(body);", content_type);
+        }
+        None => {}
+    }
+    match &operation.returned {
+        Returned::Json(_) => code.push_str("        let request = request.header(reqwest::header::ACCEPT, \"application/json\");\n        Ok(self.send(request).await?.json().await?)\n"),
+        Returned::Nothing => code.push_str("        self.send(request).await?;\n        Ok(())\n"),
+        Returned::Response => code.push_str("        self.send(request).await\n"),
+    }
+    code.push_str("    }\n");
+    code
+}
+
+const PRELUDE: &str = r#"//! Client of the elus API, generated from its route definitions; don't
+//! edit, regenerate it with `rocket-diesel --generate-client <dir>`.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    /// The server's explanation of a failed request.
+    Problem(Problem),
+    /// A failed request without an explanation.
+    Status(u16),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Problem(problem) => write!(f, "{} {}", problem.status, problem.title),
+            Error::Status(status) => write!(f, "HTTP {}", status),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Percent-encodes a path segment.
+fn segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub struct Client {
+    base_url: String,
+    token: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Self {
+        Client { base_url: base_url.trim_end_matches('/').to_string(), token: None, http: reqwest::Client::new() }
+    }
+
+    /// Sends the admin token or an API key as a bearer token.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status().as_u16();
+        match response.json::<Problem>().await {
+            Ok(problem) => Err(Error::Problem(problem)),
+            Err(_) => Err(Error::Status(status)),
+        }
+    }
+"#;
+
+fn library(routes: &[Route], spec: &Value) -> String {
+    let mut code = PRELUDE.to_string();
+    let mut query_types = Vec::new();
+    for route in routes {
+        let operation = operation(route, spec);
+        let query_type = query_type(&operation);
+        code.push('\n');
+        code.push_str(&method_for(&operation, query_type.as_ref().map(|(name, _)| name.as_str())));
+        query_types.extend(query_type.map(|(_, code)| code));
+    }
+    code.push_str("}\n");
+
+    for query_type in query_types {
+        code.push('\n');
+        code.push_str(&query_type);
+    }
+    for (name, schema) in spec["components"]["schemas"].as_object().into_iter().flatten() {
+        code.push('\n');
+        code.push_str(&type_for(name, schema));
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openapi;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_rust_type() {
+        assert_eq!(rust_type(&json!({ "$ref": "#/components/schemas/Person" })), "Person");
+        assert_eq!(rust_type(&json!({ "type": ["number", "null"] })), "Option<f64>");
+        assert_eq!(rust_type(&json!({ "type": "array", "items": { "type": "string" } })), "Vec<String>");
+        assert_eq!(rust_type(&json!({ "type": "object" })), "serde_json::Value");
+    }
+
+    #[test]
+    fn test_generated_client() {
+        let routes = crate::routes();
+        let files = generate(&routes, openapi::spec());
+        assert!(files[0].1.contains("name = \"rckd-client\""));
+        let library = &files[1].1;
+
+        assert!(library.contains("    pub async fn get_elu(&self, key: &str) -> Result<Person, Error> {\n        let request = self.http.get(format!(\"{}/elus/{}\", self.base_url, segment(key)));\n"));
+        assert!(library.contains("    pub async fn create_elu(&self, body: &Person) -> Result<Person, Error> {"));
+        assert!(library.contains("    pub async fn lookup_elus(&self, body: &Lookup) -> Result<Vec<Person>, Error> {"));
+        assert!(library.contains("    pub async fn delete_elu(&self, key: &str) -> Result<(), Error> {"));
+        assert!(library.contains("    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub latitude: Option<f64>,\n"));
+        assert!(library.contains("    pub r#type: String,\n"));
+
+        // Query parameters, required ones as arguments.
+        assert!(library.contains("    pub async fn list_elus(&self, query: &ListElusQuery) -> Result<Listing, Error> {"));
+        assert!(library.contains("pub struct ListElusQuery {\n    /// Part of the name.\n    #[serde(skip_serializing_if = \"Option::is_none\")]\n    pub name: Option<String>,\n"));
+        assert!(library.contains("    pub as_of: Option<String>,\n"));
+        assert!(library.contains("    pub async fn search_elus(&self, q: &str, query: &SearchElusQuery) -> Result<Vec<Hit>, Error> {"));
+        assert!(library.contains("        let request = request.query(&[(\"q\", q.to_string())]);\n"));
+        assert!(library.contains("pub struct Hit {\n    #[serde(flatten)]\n    pub person: Person,\n"));
+        assert!(library.contains("pub enum Listing {\n    All(Vec<Person>),\n    Page(ListingPage),\n}\n"));
+
+        // Fields which redaction may leave out are optional.
+        assert!(library.contains("    /// Primary address.\n    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n    pub email: Option<String>,\n"));
+
+        // The resources beside the elus, and the routes the document doesn't describe.
+        assert!(library.contains("    pub async fn get_commune(&self, code: &str) -> Result<Commune, Error> {"));
+        assert!(library.contains("    pub async fn create_tag(&self, body: &NewTag) -> Result<Tag, Error> {"));
+        assert!(library.contains("    pub async fn add_member(&self, id: i64, key: &str, body: Option<&NewMember>) -> Result<(), Error> {"));
+        assert!(library.contains("    pub async fn upload_document(&self, key: &str, title: &str, content_type: &str, body: impl Into<reqwest::Body>, query: &UploadDocumentQuery) -> Result<Document, Error> {"));
+        assert!(library.contains("    pub async fn download_document(&self, key: &str, id: i64) -> Result<reqwest::Response, Error> {"));
+        assert!(library.contains("    pub async fn list_elections(&self, query: &ListElectionsQuery) -> Result<reqwest::Response, Error> {"));
+        assert!(library.contains("    pub async fn create_webhook(&self, body: Option<&serde_json::Value>) -> Result<reqwest::Response, Error> {"));
+
+        // Every route gets a method, of its own name.
+        let names: Vec<&str> = library.lines().filter_map(|line| line.strip_prefix("    pub async fn ")).map(|line| &line[..line.find('(').unwrap()]).collect();
+        assert_eq!(names.len(), routes.len());
+        assert_eq!(names.iter().collect::<std::collections::HashSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn test_documented_operations_are_routed() {
+        let routes = crate::routes();
+        let routed: Vec<(String, String)> = routes.iter().map(|route| operation(route, openapi::spec())).map(|operation| (operation.method, operation.path)).collect();
+        for (path, item) in openapi::spec()["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys().filter(|method| *method != "parameters") {
+                assert!(routed.contains(&(method.clone(), path.clone())), "{} {} has no route", method, path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &crate::routes(), openapi::spec()).unwrap();
+        assert!(std::fs::read_to_string(dir.path().join("src/lib.rs")).unwrap().starts_with("//! Client of the elus API"));
+    }
+
+    /// Compiles the generated crate, fetching reqwest: run with
+    /// `cargo test -- --ignored` where the registry is reachable.
+    #[test]
+    #[ignore]
+    fn test_generated_client_compiles() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &crate::routes(), openapi::spec()).unwrap();
+        let status = std::process::Command::new(env!("CARGO"))
+            .args(["check", "--quiet", "--manifest-path"])
+            .arg(dir.path().join("Cargo.toml"))
+            .env("CARGO_TARGET_DIR", concat!(env!("CARGO_MANIFEST_DIR"), "/target/client"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
@@ -0,0 +1,113 @@
+//! Change events in the CloudEvents 1.0 JSON format (structured mode), the
+//! payload of webhooks and message queue publications alike, so consumers
+//! can use off-the-shelf CloudEvents SDKs.
+
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+use crate::events::Event;
+use crate::redaction::RedactionConfig;
+use crate::timestamp;
+
+pub const SPEC_VERSION: &str = "1.0";
+/// Version of the representation of persons in `data`, bumped on
+/// incompatible changes to it.
+pub const SCHEMA_VERSION: &str = "1";
+/// Media type of CloudEvents in structured mode.
+pub const CONTENT_TYPE: &str = "application/cloudevents+json; charset=utf-8";
+/// Prefix of the event types, followed by the change kind.
+const TYPE_PREFIX: &str = "fr.rckd.elu.";
+const SOURCE: &str = "/elus";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CloudEvent {
+    pub specversion: String,
+    /// The event's sequence number, unique within the source.
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// Id of the elu the event is about.
+    pub subject: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub time: PrimitiveDateTime,
+    pub datacontenttype: String,
+    /// Extension attribute holding `SCHEMA_VERSION`.
+    pub schemaversion: String,
+    pub data: Value,
+}
+
+impl From<&Event> for CloudEvent {
+    fn from(event: &Event) -> Self {
+        CloudEvent {
+            specversion: SPEC_VERSION.to_string(),
+            id: event.seq.to_string(),
+            source: SOURCE.to_string(),
+            event_type: format!("{}{}", TYPE_PREFIX, event.kind),
+            subject: event.elu_id.to_string(),
+            time: event.at,
+            datacontenttype: "application/json".to_string(),
+            schemaversion: SCHEMA_VERSION.to_string(),
+            data: event.person.clone(),
+        }
+    }
+}
+
+/// The event as webhooks and message queues carry it, with its emails made
+/// up on demo instances.
+pub fn payload(event: &Event, redaction: &RedactionConfig) -> Vec<u8> {
+    let json = serde_json::to_string(&CloudEvent::from(event)).expect("events serialize to JSON");
+    redaction.shown(json).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_from_event() {
+        let event = Event {
+            seq: 42,
+            kind: "updated".to_string(),
+            elu_id: 7,
+            person: json!({ "id": 7, "name": "Jean Dupont" }),
+            at: datetime!(2030-01-01 12:00:00),
+        };
+
+        assert_eq!(
+            serde_json::to_value(CloudEvent::from(&event)).unwrap(),
+            json!({
+                "specversion": "1.0",
+                "id": "42",
+                "source": "/elus",
+                "type": "fr.rckd.elu.updated",
+                "subject": "7",
+                "time": "2030-01-01T12:00:00Z",
+                "datacontenttype": "application/json",
+                "schemaversion": "1",
+                "data": { "id": 7, "name": "Jean Dupont" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let event = Event {
+            seq: 42,
+            kind: "created".to_string(),
+            elu_id: 7,
+            person: json!({ "id": 7, "email": "jean@mairie.example" }),
+            at: datetime!(2030-01-01 12:00:00),
+        };
+        let demo = RedactionConfig { demo: true, demo_key: Some("demo".to_string()), ..Default::default() };
+
+        let shown: CloudEvent = serde_json::from_slice(&payload(&event, &demo)).unwrap();
+        assert_eq!(shown.data["email"], demo.fake_email("jean@mairie.example"));
+        let shown: CloudEvent = serde_json::from_slice(&payload(&event, &RedactionConfig::default())).unwrap();
+        assert_eq!(shown.data["email"], "jean@mairie.example");
+    }
+}
@@ -0,0 +1,204 @@
+
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::config::AppConfig;
+use crate::criteria::Filters;
+use crate::db::{self, Commune};
+use crate::flags::{self, Enabled};
+use crate::pagination::{Pagination, SortSpec};
+use crate::problem::Problem;
+use crate::redaction::Redacted;
+use crate::repository::{PersonFilter, PersonSort};
+use crate::visibility::Visible;
+use crate::{listing, timeouts, DbConn, Listing};
+
+/// Commune types kept from the INSEE COG file: plain communes and the
+/// municipal arrondissements of Paris, Lyon and Marseille.
+const IMPORTED_TYPES: [&str; 2] = ["COM", "ARM"];
+
+/// Parses the official INSEE "code officiel géographique" communes CSV
+/// (`v_commune_<year>.csv`). Columns are located by header name so that
+/// yearly layout changes don't break the import.
+pub fn parse_cog_csv(content: &str) -> Result<Vec<Commune>, String> {
+    let mut lines = content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .filter(|line| !line.trim().is_empty());
+
+    let header = split_csv_line(lines.next().ok_or("empty file")?);
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|field| field.eq_ignore_ascii_case(name))
+            .ok_or(format!("missing column {}", name))
+    };
+    let (typecom, com, dep, libelle) = (column("TYPECOM")?, column("COM")?, column("DEP")?, column("LIBELLE")?);
+
+    let mut communes = vec![];
+    for (index, line) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let field = |column: usize| {
+            fields
+                .get(column)
+                .cloned()
+                .ok_or(format!("line {}: expected {} fields", index + 2, header.len()))
+        };
+        if !IMPORTED_TYPES.contains(&field(typecom)?.as_str()) {
+            continue;
+        }
+        communes.push(Commune {
+            code: field(com)?,
+            name: field(libelle)?,
+            department: field(dep)?,
+        });
+    }
+
+    Ok(communes)
+}
+
+/// Splits a comma-separated line, honouring double-quoted fields.
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    split_delimited(line, ',')
+}
+
+/// Splits a line of fields separated by `delimiter`, honouring
+/// double-quoted fields.
+pub fn split_delimited(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+/// Adds the communes of an INSEE COG file, updating those already known.
+#[post("/communes/import", data = "<csv>")]
+async fn import_communes(_enabled: Enabled<flags::Import>, _admin: Admin, csv: Data<'_>, db: &State<DbConn>, config: &State<AppConfig>) -> Result<Json<usize>, Status> {
+    let content = csv
+        .open(16.mebibytes())
+        .into_string()
+        .await
+        .map_err(|_| Status::BadRequest)?;
+    if !content.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let db = db.inner().clone();
+    let imported = timeouts::blocking(config.timeouts.request(), move || {
+        let communes = parse_cog_csv(&content).map_err(|_| Status::UnprocessableEntity)?;
+        db::upsert_communes(&communes, &mut *db::lock(&db)?)
+    })
+    .await?;
+
+    Ok(Json(imported))
+}
+
+#[get("/communes/<code>")]
+fn get_commune(code: String, db: &State<DbConn>) -> Result<Json<Commune>, Status> {
+    let mut connection = db::lock(db)?;
+
+    Ok(Json(db::get_commune(&code, &mut connection)?))
+}
+
+/// The elus of the commune, among those matching the other criteria of
+/// `GET /elus`, listed and paged as it does.
+#[get("/communes/<code>/elus")]
+fn commune_elus(code: String, filters: Filters, pagination: Pagination, sort: SortSpec<PersonSort>, db: &State<DbConn>, repository: Visible) -> Result<Redacted<Listing>, Problem> {
+    db::get_commune(&code, &mut *db::lock(db)?)?;
+
+    let filter = PersonFilter { commune_code: Some(code), ..filters.0 };
+    Ok(Redacted(listing(repository.as_ref(), &filter, pagination, sort)?))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![import_communes, get_commune, commune_elus]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use crate::Person;
+    use rocket::http::ContentType;
+
+    const COG_SAMPLE: &str = "\u{feff}TYPECOM,COM,REG,DEP,CTCD,ARR,TNCC,NCC,NCCENR,LIBELLE,CAN,COMPARENT
+COM,01001,84,01,01D,012,5,ABERGEMENT CLEMENCIAT,Abergement-Clémenciat,L'Abergement-Clémenciat,0108,
+COM,75056,11,75,75C,751,0,PARIS,Paris,Paris,75ZZ,
+ARM,75101,11,75,75C,751,0,PARIS 1ER ARRONDISSEMENT,Paris 1er Arrondissement,Paris 1er Arrondissement,,75056
+COMD,01015,84,01,01D,011,1,ARBIGNIEU,Arbignieu,Arbignieu,,01015
+";
+
+    #[test]
+    fn test_split_csv_line_quotes() {
+        assert_eq!(split_csv_line(r#"a,"b, c","say ""hi""",,"#), vec!["a", "b, c", r#"say "hi""#, "", ""]);
+    }
+
+    #[test]
+    fn test_parse_cog_csv() {
+        let communes = parse_cog_csv(COG_SAMPLE).expect("valid COG file");
+
+        let codes: Vec<&str> = communes.iter().map(|commune| commune.code.as_str()).collect();
+        assert_eq!(codes, vec!["01001", "75056", "75101"]);
+        assert_eq!(communes[0].name, "L'Abergement-Clémenciat");
+        assert_eq!(communes[0].department, "01");
+
+        assert!(parse_cog_csv("COM,LIBELLE\n75056,Paris\n").is_err());
+    }
+
+    #[test]
+    fn test_commune_elus_endpoint() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+
+        let client = client(connection);
+
+        let response = client.get("/communes/75056/elus").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.post("/communes/import").header(ContentType::CSV).body(COG_SAMPLE).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/communes/import")
+            .header(admin())
+            .header(ContentType::CSV)
+            .body(COG_SAMPLE)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_json::<usize>(), Some(3));
+
+        let response = client.get("/communes/75056").dispatch();
+        let commune: Commune = response.into_json().expect("valid JSON");
+        assert_eq!(commune.name, "Paris");
+
+        let response = client.get("/communes/75056/elus").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        let names: Vec<&str> = persons.iter().map(|person| person.name.as_str()).collect();
+        assert_eq!(names, vec!["Jean Dupont", "Marie Martin"]);
+        let persons: Vec<Person> = client.get("/communes/75056/elus?mandate=maire&commune=01001").dispatch().into_json().unwrap();
+        assert_eq!(persons.iter().map(|person| person.name.as_str()).collect::<Vec<_>>(), ["Jean Dupont"]);
+        let page: rocket::serde::json::Value = client.get("/communes/75056/elus?sort=-name&limit=1").dispatch().into_json().unwrap();
+        assert_eq!(page["elus"][0]["name"], "Marie Martin");
+
+        let response = client.get("/communes/01001/elus").dispatch();
+        assert_eq!(response.into_json::<Vec<Person>>().map(|persons| persons.len()), Some(0));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rocket::serde::Deserialize;
+
+use crate::alerts::AlertsConfig;
+use crate::db::{BusyRetryConfig, ReplicaConfig};
+use crate::discovery::DatasetConfig;
+use crate::encryption::EncryptionConfig;
+use crate::error_reporting::ReportingConfig;
+use crate::jobs::JobsConfig;
+use crate::mail::SmtpConfig;
+use crate::mail_queue::MailQueueConfig;
+#[cfg(feature = "tantivy")]
+use crate::full_text::FullTextConfig;
+#[cfg(feature = "nats")]
+use crate::nats::NatsConfig;
+use crate::open_data::OpenDataConfig;
+use crate::pagination::PaginationConfig;
+use crate::password_reset::PasswordResetConfig;
+use crate::redaction::RedactionConfig;
+use crate::repository::{Backend, PublicIds};
+use crate::scheduled_export::ScheduledExportConfig;
+use crate::scraping::ScrapingConfig;
+use crate::sessions::SessionConfig;
+use crate::storage::StorageConfig;
+use crate::sync::SyncConfig;
+use crate::telemetry::TracingConfig;
+use crate::timeouts::TimeoutConfig;
+use crate::uploads::UploadConfig;
+use crate::webhooks::WebhookConfig;
+
+/// Application settings read from `Rocket.toml` / `ROCKET_*` environment
+/// variables, next to Rocket's own configuration.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AppConfig {
+    /// Where persons are stored; `--backend` on the command line overrides
+    /// it.
+    pub backend: Backend,
+    /// Whether callers may designate elus by their database id, besides
+    /// their UUID and email.
+    pub public_ids: PublicIds,
+    /// Read replica serving person lookups and searches of the SQLite
+    /// backend; everything goes to `DATABASE_URL` when unset.
+    pub replica: Option<ReplicaConfig>,
+    /// Retries of the SQLite backend's operations while another connection
+    /// holds the database.
+    pub busy_retry: BusyRetryConfig,
+    /// Whether SQL queries are logged, with the ID of the request making
+    /// them; defaults to whether the profile is `debug`.
+    pub log_queries: Option<bool>,
+    /// Key the emails of the SQLite backend are encrypted with at rest;
+    /// stored in the clear when unset.
+    pub encryption: Option<EncryptionConfig>,
+    /// Base URL of an addok-compatible geocoding API (such as the BAN's
+    /// api-adresse); office addresses aren't geocoded when unset.
+    pub geocoder_url: Option<String>,
+    /// Interval, in seconds, between background runs checking the
+    /// deliverability of new email addresses; disabled when unset.
+    pub email_check_interval: Option<u64>,
+    /// SMTP relay used to send mail; messages are only logged when unset.
+    pub smtp: Option<SmtpConfig>,
+    /// Retry policy and polling interval of the outgoing mail queue.
+    pub mail_queue: MailQueueConfig,
+    /// Polling interval of the background job worker.
+    pub jobs: JobsConfig,
+    /// Checks for mandates coming to an end, and who is told about them.
+    pub alerts: AlertsConfig,
+    /// Nightly exports of the directory, and where they are written; not
+    /// made when unset.
+    pub exports: Option<ScheduledExportConfig>,
+    /// Bearer token granting access to administrative endpoints, which are
+    /// disabled when unset.
+    pub admin_token: Option<String>,
+    /// URL the directory is published at, which the absolute links of the
+    /// sitemap and dataset description start with; `https://` and the
+    /// request's host when unset.
+    pub public_url: Option<String>,
+    /// How the dataset is described to open-data portals.
+    pub dataset: DatasetConfig,
+    /// Serves the public open-data mirror: read-only routes, cached public
+    /// responses, relaxed quotas and no background workers. Off when unset.
+    pub open_data: Option<OpenDataConfig>,
+    /// Whether successful JSON responses are wrapped in a
+    /// `{ "data": ..., "meta": ... }` envelope.
+    pub envelope: bool,
+    /// Page sizes of the paged listings.
+    pub pagination: PaginationConfig,
+    /// What callers without the admin token don't get to see.
+    pub redaction: RedactionConfig,
+    /// Limits on lookups of single elus, response jitter and the access log
+    /// scrapers are reported from.
+    pub scraping: ScrapingConfig,
+    /// Dashboard sessions of users signed in with a password.
+    pub sessions: SessionConfig,
+    /// Mailing of password reset links to dashboard users; disabled when
+    /// unset.
+    pub password_reset: Option<PasswordResetConfig>,
+    /// Directory uploaded files are stored in with the local storage
+    /// backend, and staged in before being validated; `uploads` when unset.
+    pub upload_dir: Option<PathBuf>,
+    /// Where uploaded files are kept: local disk or S3-compatible storage.
+    pub storage: StorageConfig,
+    /// Size limits and virus scanning of uploaded files.
+    pub uploads: UploadConfig,
+    /// External systems created and updated elus are pushed to, and the
+    /// retry policy of their queue.
+    pub sync: SyncConfig,
+    /// NATS server change events are published to; not published when
+    /// unset.
+    #[cfg(feature = "nats")]
+    pub nats: Option<NatsConfig>,
+    /// Full-text index `GET /elus/search` uses; searches query the
+    /// database when unset.
+    #[cfg(feature = "tantivy")]
+    pub full_text: Option<FullTextConfig>,
+    /// Retry policy and polling interval of the webhook dispatcher.
+    pub webhooks: WebhookConfig,
+    /// Initial state of the feature flags, by name.
+    pub flags: BTreeMap<String, bool>,
+    /// Where server errors are reported; they are logged by default.
+    pub error_reporting: ReportingConfig,
+    /// OpenTelemetry collector request traces are exported to; requests
+    /// aren't traced when unset.
+    pub tracing: Option<TracingConfig>,
+    /// How long exports, imports, downloads and background job runs may
+    /// take.
+    pub timeouts: TimeoutConfig,
+}
+
+impl AppConfig {
+    pub fn upload_dir(&self) -> &Path {
+        self.upload_dir.as_deref().unwrap_or(Path::new("uploads"))
+    }
+}
@@ -0,0 +1,106 @@
+//! Explicit `charset=utf-8` and `Content-Language` on textual responses,
+//! so that clients and proxies don't guess the encoding of accented names
+//! or the language of mandate labels. JSON is in the language its mandates
+//! are labelled in, French unless the caller accepts another; problems
+//! are in English and the dashboard in French.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::mandate_types::DEFAULT_LANGUAGE;
+use crate::redaction::ContentLanguage;
+
+/// Fairing setting the headers.
+pub struct ContentHeaders;
+
+#[rocket::async_trait]
+impl Fairing for ContentHeaders {
+    fn info(&self) -> Info {
+        Info { name: "Content headers", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(content_type) = response.content_type() else {
+            return;
+        };
+        let json = content_type.is_json() || content_type.sub().as_str().ends_with("+json");
+        if !json && content_type.top() != "text" {
+            return;
+        }
+
+        let language = if content_type.sub() == "problem+json" {
+            "en".to_string()
+        } else if content_type.is_html() {
+            "fr".to_string()
+        } else {
+            request.local_cache(|| ContentLanguage(DEFAULT_LANGUAGE.to_string())).0.clone()
+        };
+        if content_type.param("charset").is_none() {
+            response.set_raw_header("Content-Type", format!("{}; charset=utf-8", content_type));
+        }
+        if !response.headers().contains("Content-Language") {
+            response.set_header(Header::new("Content-Language", language));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{admin, client, setup_test_db};
+    use crate::vcard::to_vcard;
+    use crate::Person;
+    use rocket::http::{Header, Status};
+    use rocket::serde::json::{json, Value};
+
+    const NAME: &str = "Hélène Lefèvre-Bénézet";
+
+    #[test]
+    fn test_accented_names_round_trip() {
+        let client = client(setup_test_db());
+        let person = json!({
+            "name": NAME,
+            "email": "helene@mairie.example",
+            "mandates": ["Conseillère régionale"],
+            "office_address": "Hôtel de Région, Île-de-France",
+            "latitude": 48.85,
+            "longitude": 2.35,
+        });
+        let response = client.post("/elus/create").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get("/elus/helene@mairie.example").header(admin()).dispatch();
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/json; charset=utf-8"));
+        assert_eq!(response.headers().get_one("Content-Language"), Some("fr"));
+        let fetched: Person = response.into_json().unwrap();
+        assert_eq!((fetched.name.as_str(), fetched.mandates[0].as_str()), (NAME, "Conseillère régionale"));
+
+        let response = client.get("/elus/export.geojson").dispatch();
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/geo+json; charset=utf-8"));
+        let collection: Value = response.into_json().unwrap();
+        assert_eq!(collection["features"][0]["properties"]["name"], NAME);
+
+        let vcard = to_vcard(&serde_json::to_value(&fetched).unwrap(), None);
+        let unfolded = vcard.replace("\r\n ", "");
+        assert!(unfolded.contains(&format!("FN:{}\r\n", NAME)));
+        assert!(unfolded.contains("ADR;TYPE=WORK:;;Hôtel de Région\\, Île-de-France;;;;"));
+    }
+
+    #[test]
+    fn test_content_language() {
+        let client = client(setup_test_db());
+
+        let response = client.get("/elus").header(Header::new("Accept-Language", "en-GB,en;q=0.9")).dispatch();
+        assert_eq!(response.headers().get_one("Content-Language"), Some("en"));
+        let response = client.get("/elus").header(Header::new("Accept-Language", "de")).dispatch();
+        assert_eq!(response.headers().get_one("Content-Language"), Some("en"));
+
+        let response = client.get("/elus/nobody@example.com").header(Header::new("Accept-Language", "fr")).dispatch();
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+        assert_eq!(response.headers().get_one("Content-Language"), Some("en"));
+
+        let response = client.get("/admin/login").dispatch();
+        assert_eq!(response.headers().get_one("Content-Type"), Some("text/html; charset=utf-8"));
+        assert_eq!(response.headers().get_one("Content-Language"), Some("fr"));
+    }
+}
@@ -0,0 +1,119 @@
+//! The criteria elus are selected by in `GET /elus`, which `PATCH /elus`
+//! and the exports take as well and saved searches store as a query
+//! string, so that every way of selecting elus reads them the same.
+
+use rocket::form::{Form, Strict};
+use rocket::http::{RawStr, Status};
+use rocket::request::{self, FromRequest, Request};
+
+use crate::deliverability::EmailStatus;
+use crate::mandate_types::MandateTypes;
+use crate::problem::{self, Problem};
+use crate::repository::PersonFilter;
+use crate::{db, filter, tags, DbConn};
+
+/// Part of the name; a mandate, by title or code; INSEE code of the
+/// commune; a tag; deliverability of the address; an expression of
+/// `filter`, which the other criteria narrow down. Unset criteria match
+/// everyone.
+#[derive(Debug, Default, FromForm)]
+pub struct Criteria {
+    pub name: Option<String>,
+    pub mandate: Option<String>,
+    pub commune: Option<String>,
+    pub tag: Option<String>,
+    pub email_status: Option<String>,
+    pub filter: Option<String>,
+}
+
+impl Criteria {
+    /// The criteria of a query string such as `mandate=maire&tag=budget`;
+    /// unknown fields are refused, so that typos don't go unnoticed.
+    pub fn parse(query: &str) -> Result<Criteria, Status> {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        let criteria = Form::<Strict<Criteria>>::parse_encoded(RawStr::new(query)).map_err(|_| Status::UnprocessableEntity)?.into_inner();
+        criteria.email_status().map_err(|_| Status::UnprocessableEntity)?;
+        criteria.expression().map_err(|_| Status::UnprocessableEntity)?;
+        Ok(criteria)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.mandate.is_none() && self.commune.is_none() && self.tag.is_none() && self.email_status.is_none() && self.filter.is_none()
+    }
+
+    fn email_status(&self) -> Result<Option<EmailStatus>, Status> {
+        self.email_status
+            .as_deref()
+            .map(|status| status.parse::<EmailStatus>().map_err(|_| Status::BadRequest))
+            .transpose()
+    }
+
+    fn expression(&self) -> Result<Option<filter::Expr>, Status> {
+        self.filter.as_deref().map(|input| filter::parse(input).map_err(|_| Status::BadRequest)).transpose()
+    }
+
+    /// The filter of the repository selecting the matching elus.
+    pub fn filter(&self, db: &DbConn, mandate_types: &MandateTypes) -> Result<PersonFilter, Status> {
+        let ids = match &self.tag {
+            Some(tag) => Some(tags::tagged(tag, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?),
+            None => None,
+        };
+
+        Ok(PersonFilter {
+            name: self.name.clone(),
+            mandate: self.mandate.as_deref().map(|mandate| mandate_types.title(mandate)),
+            commune_code: self.commune.clone(),
+            email_status: self.email_status()?,
+            ids,
+            expression: self.expression()?.map(|expression| expression.with_titles(mandate_types)),
+            ..Default::default()
+        })
+    }
+}
+
+/// The filter of the criteria in the query string, for routes listing
+/// elus to take as a guard; the other parameters, such as paging, are left
+/// to their own guards.
+#[derive(Debug)]
+pub struct Filters(pub PersonFilter);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Filters {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let (Some(db), Some(mandate_types)) = (request.rocket().state::<DbConn>(), request.rocket().state::<MandateTypes>()) else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        let query = request.uri().query().map_or(RawStr::new(""), |query| query.raw());
+        let Ok(criteria) = Form::<Criteria>::parse_encoded(query) else {
+            return request::Outcome::Error((Status::BadRequest, ()));
+        };
+        if let Some(Err(error)) = criteria.filter.as_deref().map(filter::parse) {
+            return problem::refuse(Problem { detail: Some(format!("Invalid filter: {}.", error)), ..Problem::new(Status::BadRequest) }, request);
+        }
+
+        match criteria.filter(db, mandate_types) {
+            Ok(filter) => request::Outcome::Success(Filters(filter)),
+            Err(status) => request::Outcome::Error((status, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let criteria = Criteria::parse("?mandate=Maire&commune=75056&tag=bureau-municipal").unwrap();
+        assert_eq!((criteria.mandate.as_deref(), criteria.commune.as_deref(), criteria.tag.as_deref()), (Some("Maire"), Some("75056"), Some("bureau-municipal")));
+        assert_eq!(Criteria::parse("name=jean%20dupont").unwrap().name.as_deref(), Some("jean dupont"));
+        assert!(Criteria::parse("").unwrap().is_empty());
+        assert_eq!(Criteria::parse("mandat=maire").unwrap_err(), Status::UnprocessableEntity);
+        assert_eq!(Criteria::parse("email_status=unknown").unwrap_err(), Status::UnprocessableEntity);
+        let criteria = Criteria::parse("filter=mandate%20eq%20%22Maire%22%20or%20commune%20eq%20%2275056%22").unwrap();
+        assert_eq!(criteria.filter.as_deref(), Some(r#"mandate eq "Maire" or commune eq "75056""#));
+        assert_eq!(Criteria::parse("filter=mandate%20eq%20Maire").unwrap_err(), Status::UnprocessableEntity);
+    }
+}
@@ -0,0 +1,123 @@
+//! Sync target pushing elus to a CRM's REST API as contacts, upserted by
+//! id with `PUT {base_url}/contacts/{id}` so that retried pushes are
+//! harmless. Only plain HTTP endpoints are supported.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use rocket::serde::Deserialize;
+
+use crate::db::Person;
+use crate::events::ChangeKind;
+use crate::sync::SyncTarget;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CrmConfig {
+    /// Base URL of the API, e.g. `http://crm.mairie.example/api`.
+    pub base_url: String,
+    /// Bearer token authenticating the pushes.
+    pub token: String,
+}
+
+pub struct CrmTarget {
+    config: CrmConfig,
+    client: Client<HttpConnector, Body>,
+}
+
+impl CrmTarget {
+    pub fn new(config: CrmConfig) -> Self {
+        CrmTarget {
+            config: CrmConfig {
+                base_url: config.base_url.trim_end_matches('/').to_string(),
+                ..config
+            },
+            client: Client::new(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl SyncTarget for CrmTarget {
+    fn name(&self) -> &str {
+        "crm"
+    }
+
+    async fn push(&self, _kind: ChangeKind, person: &Person) -> Result<(), String> {
+        let body = serde_json::to_vec(person).map_err(|e| e.to_string())?;
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(format!("{}/contacts/{}", self.config.base_url, person.id))
+            .header("authorization", format!("Bearer {}", self.config.token))
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .map_err(|e| format!("invalid CRM URL: {}", e))?;
+
+        let response = self.client.request(request).await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let content = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+        Err(format!("CRM returned {}: {}", status, String::from_utf8_lossy(&content)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::serve_once;
+    use std::net::TcpListener;
+    use time::macros::datetime;
+
+    fn target(listener: &TcpListener) -> CrmTarget {
+        CrmTarget::new(CrmConfig {
+            base_url: format!("http://{}/api/", listener.local_addr().unwrap()),
+            token: "secret".to_string(),
+        })
+    }
+
+    fn person() -> Person {
+        Person {
+            id: 7,
+            uuid: "0f8fad5b-d9cb-469f-a165-70867728950e".to_string(),
+            name: "Jean Dupont".parse().unwrap(),
+            email: "jean@mairie.example".parse().unwrap(),
+            emails: vec![],
+            mandates: vec!["maire".to_string()],
+            commune_code: None,
+            office_address: None,
+            latitude: None,
+            longitude: None,
+            may_contact_by_email: false,
+            consent_date: None,
+            consent_source: None,
+            visibility: Default::default(),
+            custom: Default::default(),
+            email_status: "unknown".to_string(),
+            updated_at: datetime!(2030-01-01 12:00:00),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_push_upserts_contact() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = target(&listener);
+        let server = serve_once(listener, "204 No Content");
+
+        assert_eq!(target.push(ChangeKind::Created, &person()).await, Ok(()));
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("PUT /api/contacts/7 HTTP/1.1\r\n"));
+        assert!(head.to_ascii_lowercase().contains("authorization: bearer secret\r\n"));
+        assert!(body.contains(r#""email":"jean@mairie.example""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_push_reports_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = target(&listener);
+        let server = serve_once(listener, "503 Service Unavailable");
+
+        assert_eq!(target.push(ChangeKind::Updated, &person()).await, Err("CRM returned 503 Service Unavailable: nope".to_string()));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Protection of dashboard sessions against cross-site request forgery.
+//! Every state-changing request made with a session cookie must carry the
+//! session's CSRF token, either in the `X-CSRF-Token` header or as the
+//! `csrf_token` field of a form, which must then be the form's first
+//! field. The fairing picks the field out of the form body before it's
+//! parsed, and the `Session` guard refuses requests whose token doesn't
+//! match, so that no handler taking a session can forget the check.
+
+use percent_encoding::percent_decode_str;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Method};
+use rocket::request::Request;
+use rocket::Data;
+
+use crate::auth::constant_time_eq;
+use crate::sessions::COOKIE;
+
+pub const HEADER: &str = "X-CSRF-Token";
+pub const FIELD: &str = "csrf_token";
+
+/// Bytes of the body the form field is looked for in; Rocket can't peek
+/// further ahead.
+const PEEK_LIMIT: usize = 512;
+
+/// The token found in a form body by the fairing.
+struct Submitted(Option<String>);
+
+/// Whether requests of this method change state and so need a token.
+pub fn is_unsafe(method: Method) -> bool {
+    !matches!(method, Method::Get | Method::Head | Method::Options)
+}
+
+/// Value of the first field of a URL-encoded form when it's the token;
+/// `complete` tells whether `body` is the whole body or only its start.
+fn form_token(body: &[u8], complete: bool) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let (field, rest) = match body.split_once('&') {
+        Some((field, rest)) => (field, Some(rest)),
+        None => (body, None),
+    };
+    if rest.is_none() && !complete {
+        return None;
+    }
+
+    let value = field.strip_prefix(FIELD)?.strip_prefix('=')?;
+    Some(percent_decode_str(value).decode_utf8().ok()?.into_owned())
+}
+
+/// Fairing picking the token out of form bodies, which `AdHoc` fairings
+/// can't peek into.
+pub struct Csrf;
+
+#[rocket::async_trait]
+impl Fairing for Csrf {
+    fn info(&self) -> Info {
+        Info { name: "CSRF token extraction", kind: Kind::Request }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if !is_unsafe(request.method()) || request.cookies().get(COOKIE).is_none() || request.content_type() != Some(&ContentType::Form) {
+            return;
+        }
+
+        let body = data.peek(PEEK_LIMIT).await.to_vec();
+        let token = form_token(&body, data.peek_complete());
+        request.local_cache(|| Submitted(token));
+    }
+}
+
+/// Whether the request carries the given session token, in the header or
+/// the form.
+pub fn verify(request: &Request<'_>, expected: &str) -> bool {
+    let submitted = match request.headers().get_one(HEADER) {
+        Some(token) => Some(token),
+        None => request.local_cache(|| Submitted(None)).0.as_deref(),
+    };
+
+    submitted.is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+/// Hidden form field carrying the token, to put first in every form of a
+/// session's pages.
+pub fn field(token: &str) -> String {
+    format!("<input type=\"hidden\" name=\"{}\" value=\"{}\">", FIELD, crate::dashboard::escape(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_token() {
+        assert_eq!(form_token(b"csrf_token=abc-_&comment=hello", false), Some("abc-_".to_string()));
+        assert_eq!(form_token(b"csrf_token=a%2Bb", true), Some("a+b".to_string()));
+        // The field may be cut short by the peek limit.
+        assert_eq!(form_token(b"csrf_token=abc", false), None);
+        assert_eq!(form_token(b"comment=hello&csrf_token=abc", true), None);
+        assert_eq!(form_token(b"csrf_tokens=abc", true), None);
+    }
+}
@@ -0,0 +1,427 @@
+//! Custom fields: attributes some collectivities need on their elus beyond
+//! the built-in ones, such as the number of a commission seat or the scope
+//! of a delegation. Admins define them under `/custom-fields`, with a type
+//! and validation rules; elus carry their values under `custom`, which is
+//! checked against the definitions wherever elus are written, imports
+//! included, and exported along with the other fields.
+//!
+//! Values are kept as a JSON object in `elus.custom`. Changing a field's
+//! definition leaves the values already stored as they are; removing a
+//! field removes its values.
+
+use std::str::FromStr;
+
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteConnection, SqliteValue};
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use serde_json::Map;
+
+use crate::auth::Admin;
+use crate::problem::Violation;
+use crate::schema::custom_fields;
+use crate::{db, validation, DbConn};
+
+/// Longest field name.
+const MAX_NAME_LEN: usize = 50;
+
+/// The values of an elu's custom fields, by field name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(crate = "rocket::serde", transparent)]
+#[diesel(sql_type = Text)]
+pub struct CustomValues(pub Map<String, Value>);
+
+impl ToSql<Text, Sqlite> for CustomValues {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for CustomValues {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let values = <String as FromSql<Text, Sqlite>>::from_sql(value)?;
+        Ok(CustomValues(serde_json::from_str(&values)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum FieldKind {
+    #[default]
+    Text,
+    Integer,
+    Number,
+    Boolean,
+    /// A calendar date, as `YYYY-MM-DD`.
+    Date,
+}
+
+const KINDS: [FieldKind; 5] = [FieldKind::Text, FieldKind::Integer, FieldKind::Number, FieldKind::Boolean, FieldKind::Date];
+
+impl FieldKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldKind::Text => "text",
+            FieldKind::Integer => "integer",
+            FieldKind::Number => "number",
+            FieldKind::Boolean => "boolean",
+            FieldKind::Date => "date",
+        }
+    }
+}
+
+impl FromStr for FieldKind {
+    type Err = ();
+
+    fn from_str(kind: &str) -> Result<Self, ()> {
+        KINDS.into_iter().find(|candidate| candidate.as_str() == kind).ok_or(())
+    }
+}
+
+/// The definition of a custom field. `options` restrict text fields to a
+/// list of values, `max_length` their length; `minimum` and `maximum`
+/// bound numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CustomField {
+    /// Lowercase letters, digits and `_`, starting with a letter.
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Whether every elu must have a value.
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub options: Option<Vec<String>>,
+    #[serde(default)]
+    pub minimum: Option<f64>,
+    #[serde(default)]
+    pub maximum: Option<f64>,
+    #[serde(default)]
+    pub max_length: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = custom_fields, treat_none_as_null = true)]
+struct CustomFieldRow {
+    name: String,
+    kind: String,
+    description: Option<String>,
+    required: bool,
+    /// JSON array.
+    options: Option<String>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    max_length: Option<i32>,
+}
+
+impl From<CustomFieldRow> for CustomField {
+    fn from(row: CustomFieldRow) -> Self {
+        CustomField {
+            name: row.name,
+            kind: row.kind.parse().unwrap_or_default(),
+            description: row.description,
+            required: row.required,
+            options: row.options.and_then(|options| serde_json::from_str(&options).ok()),
+            minimum: row.minimum,
+            maximum: row.maximum,
+            max_length: row.max_length,
+        }
+    }
+}
+
+impl From<&CustomField> for CustomFieldRow {
+    fn from(field: &CustomField) -> Self {
+        CustomFieldRow {
+            name: field.name.clone(),
+            kind: field.kind.as_str().to_string(),
+            description: field.description.clone(),
+            required: field.required,
+            options: field.options.as_ref().map(|options| json!(options).to_string()),
+            minimum: field.minimum,
+            maximum: field.maximum,
+            max_length: field.max_length,
+        }
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl CustomField {
+    /// Whether the definition makes sense: rules only apply to the kinds
+    /// of fields they're about.
+    fn is_valid(&self) -> bool {
+        let text = self.kind == FieldKind::Text;
+        let numeric = matches!(self.kind, FieldKind::Integer | FieldKind::Number);
+        is_name(&self.name)
+            && (self.options.is_none() || text)
+            && self.options.as_ref().is_none_or(|options| !options.is_empty())
+            && (self.max_length.is_none() || text)
+            && self.max_length.is_none_or(|max| max > 0)
+            && ((self.minimum.is_none() && self.maximum.is_none()) || numeric)
+            && self.minimum.zip(self.maximum).is_none_or(|(min, max)| min <= max)
+    }
+
+    /// The JSON Schema of the field's values.
+    pub fn schema(&self) -> Value {
+        let mut schema = match self.kind {
+            FieldKind::Text => json!({ "type": "string" }),
+            FieldKind::Integer => json!({ "type": "integer" }),
+            FieldKind::Number => json!({ "type": "number" }),
+            FieldKind::Boolean => json!({ "type": "boolean" }),
+            FieldKind::Date => json!({ "type": "string", "format": "date" }),
+        };
+        for (rule, value) in [
+            ("enum", self.options.as_ref().map(|options| json!(options))),
+            ("maxLength", self.max_length.map(|max| json!(max))),
+            ("minimum", self.minimum.map(|min| json!(min))),
+            ("maximum", self.maximum.map(|max| json!(max))),
+        ] {
+            if let Some(value) = value {
+                schema[rule] = value;
+            }
+        }
+        schema
+    }
+}
+
+/// The custom fields, by name.
+pub fn load(connection: &mut SqliteConnection) -> QueryResult<Vec<CustomField>> {
+    let rows = custom_fields::table.order(custom_fields::name).select(CustomFieldRow::as_select()).load(connection)?;
+    Ok(rows.into_iter().map(CustomField::from).collect())
+}
+
+/// The ways `custom`, the values of an elu's custom fields, doesn't match
+/// their definitions: missing required fields, unknown fields and invalid
+/// values.
+pub fn violations(custom: &Value, fields: &[CustomField]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let values = custom.as_object();
+    let pointer = |name: &str| format!("/custom/{}", validation::escape(name));
+
+    for field in fields.iter().filter(|field| field.required) {
+        if values.is_none_or(|values| !values.contains_key(&field.name)) {
+            violations.push(Violation { pointer: pointer(&field.name), detail: "is required".to_string() });
+        }
+    }
+    for (name, value) in values.into_iter().flatten() {
+        match fields.iter().find(|field| field.name == *name) {
+            Some(field) => validation::validate(value, &field.schema(), pointer(name), &mut violations),
+            None => violations.push(Violation { pointer: pointer(name), detail: "is not a custom field".to_string() }),
+        }
+    }
+    violations
+}
+
+/// Turns the values of `custom` which formats without types, such as CSV,
+/// hold as strings into the numbers or booleans their fields expect; those
+/// which don't parse are left for validation to report.
+pub fn coerce(custom: &mut Value, fields: &[CustomField]) {
+    let Some(values) = custom.as_object_mut() else {
+        return;
+    };
+    for field in fields {
+        let Some(Value::String(string)) = values.get(&field.name) else {
+            continue;
+        };
+        let string = string.trim();
+        let coerced = match field.kind {
+            FieldKind::Integer => string.parse::<i64>().ok().map(Value::from),
+            FieldKind::Number => string.parse::<f64>().ok().map(Value::from),
+            FieldKind::Boolean => string.parse::<bool>().ok().map(Value::from),
+            FieldKind::Text | FieldKind::Date => None,
+        };
+        if let Some(coerced) = coerced {
+            values.insert(field.name.clone(), coerced);
+        }
+    }
+}
+
+fn conflict_or_internal(error: diesel::result::Error) -> Status {
+    match error {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+        _ => Status::InternalServerError,
+    }
+}
+
+#[get("/custom-fields")]
+fn list_fields(db: &State<DbConn>) -> Result<Json<Vec<CustomField>>, Status> {
+    load(&mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[get("/custom-fields/<name>")]
+fn get_field(name: &str, db: &State<DbConn>) -> Result<Json<CustomField>, Status> {
+    custom_fields::table
+        .find(name)
+        .select(CustomFieldRow::as_select())
+        .first(&mut *db::lock(db)?)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .map(|row| Json(CustomField::from(row)))
+        .ok_or(Status::NotFound)
+}
+
+/// Defines a field; elus already saved without a value for it keep none,
+/// even if it's required, until they're next written.
+#[post("/custom-fields", data = "<field>")]
+fn create_field(field: Json<CustomField>, _admin: Admin, db: &State<DbConn>) -> Result<Created<Json<CustomField>>, Status> {
+    if !field.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+    diesel::insert_into(custom_fields::table)
+        .values(CustomFieldRow::from(&*field))
+        .execute(&mut *db::lock(db)?)
+        .map_err(conflict_or_internal)?;
+
+    Ok(Created::new(format!("/custom-fields/{}", field.name)).body(field))
+}
+
+/// Replaces the definition of a field, which can't be renamed.
+#[put("/custom-fields/<name>", data = "<field>")]
+fn update_field(name: &str, field: Json<CustomField>, _admin: Admin, db: &State<DbConn>) -> Result<Json<CustomField>, Status> {
+    let mut field = field.into_inner();
+    if field.name.is_empty() {
+        field.name = name.to_string();
+    }
+    if field.name != name || !field.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+    let updated = diesel::update(custom_fields::table.find(name))
+        .set(CustomFieldRow::from(&field))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if updated == 0 {
+        return Err(Status::NotFound);
+    }
+    Ok(Json(field))
+}
+
+/// Removes a field and its values from every elu.
+#[delete("/custom-fields/<name>")]
+fn delete_field(name: &str, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    if !is_name(name) {
+        return Err(Status::NotFound);
+    }
+    let deleted = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            let deleted = diesel::delete(custom_fields::table.find(name)).execute(connection)?;
+            diesel::sql_query("UPDATE elus SET custom = json_remove(custom, ?) WHERE json_type(custom, ?) IS NOT NULL")
+                .bind::<Text, _>(format!("$.{}", name))
+                .bind::<Text, _>(format!("$.{}", name))
+                .execute(connection)?;
+            Ok(deleted)
+        })
+        .map_err(|_: diesel::result::Error| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+    Ok(Status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_fields, get_field, create_field, update_field, delete_field]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+
+    fn field(name: &str, kind: FieldKind) -> CustomField {
+        CustomField { name: name.to_string(), kind, description: None, required: false, options: None, minimum: None, maximum: None, max_length: None }
+    }
+
+    #[test]
+    fn test_violations() {
+        let fields = [
+            CustomField { required: true, minimum: Some(1.0), ..field("siege", FieldKind::Integer) },
+            CustomField { options: Some(vec!["culture".to_string(), "sport".to_string()]), ..field("delegation", FieldKind::Text) },
+            field("installation", FieldKind::Date),
+        ];
+        let violations = |custom: Value| -> Vec<(String, String)> {
+            violations(&custom, &fields).into_iter().map(|violation| (violation.pointer, violation.detail)).collect()
+        };
+
+        assert!(violations(json!({ "siege": 12, "delegation": "sport", "installation": "2026-03-20" })).is_empty());
+        assert_eq!(violations(Value::Null), [("/custom/siege".to_string(), "is required".to_string())]);
+        assert_eq!(
+            violations(json!({ "siege": 0, "delegation": "voirie", "installation": "20/03/2026", "bureau": true })),
+            [
+                ("/custom/bureau".to_string(), "is not a custom field".to_string()),
+                ("/custom/delegation".to_string(), "must be one of \"culture\", \"sport\"".to_string()),
+                ("/custom/installation".to_string(), "must be a date".to_string()),
+                ("/custom/siege".to_string(), "must be at least 1".to_string()),
+            ]
+        );
+
+        let mut custom = json!({ "siege": " 12 ", "delegation": "12" });
+        coerce(&mut custom, &fields);
+        assert_eq!(custom, json!({ "siege": 12, "delegation": "12" }));
+
+        assert!(!field("Siège", FieldKind::Text).is_valid());
+        assert!(!CustomField { options: Some(vec!["1".to_string()]), ..field("siege", FieldKind::Integer) }.is_valid());
+        assert!(!CustomField { minimum: Some(2.0), maximum: Some(1.0), ..field("siege", FieldKind::Integer) }.is_valid());
+    }
+
+    #[test]
+    fn test_custom_fields() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let person = |custom: Value| json!({ "name": "Luc Bernard", "email": "luc.bernard@example.com", "mandates": ["Maire"], "custom": custom });
+
+        let siege = json!({ "name": "siege", "type": "integer", "required": true, "minimum": 1 });
+        assert_eq!(client.post("/custom-fields").json(&siege).dispatch().status(), Status::Unauthorized);
+        let response = client.post("/custom-fields").header(admin()).json(&siege).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Location"), Some("/custom-fields/siege"));
+        assert_eq!(client.post("/custom-fields").header(admin()).json(&siege).dispatch().status(), Status::Conflict);
+        let bad = json!({ "name": "delegation", "type": "text", "minimum": 1 });
+        assert_eq!(client.post("/custom-fields").header(admin()).json(&bad).dispatch().status(), Status::UnprocessableEntity);
+        let delegation = json!({ "name": "delegation", "type": "text", "options": ["culture", "sport"] });
+        assert_eq!(client.post("/custom-fields").header(admin()).json(&delegation).dispatch().status(), Status::Created);
+        let fields: Vec<CustomField> = client.get("/custom-fields").dispatch().into_json().unwrap();
+        assert_eq!(fields.iter().map(|field| field.name.as_str()).collect::<Vec<_>>(), ["delegation", "siege"]);
+
+        let response = client.post("/elus/create").header(admin()).json(&person(json!({ "delegation": "voirie" }))).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let problem: Value = response.into_json().unwrap();
+        assert_eq!(
+            problem["errors"],
+            json!([{ "pointer": "/custom/siege", "detail": "is required" }, { "pointer": "/custom/delegation", "detail": "must be one of \"culture\", \"sport\"" }])
+        );
+        let response = client.post("/elus/create").header(admin()).json(&person(json!({ "siege": 12, "delegation": "sport" }))).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let elu: Value = client.get("/elus/luc.bernard@example.com").dispatch().into_json().unwrap();
+        assert_eq!(elu["custom"], json!({ "siege": 12, "delegation": "sport" }));
+
+        let optional = json!({ "type": "integer", "minimum": 1 });
+        assert_eq!(client.put("/custom-fields/siege").header(admin()).json(&optional).dispatch().status(), Status::Ok);
+        assert_eq!(client.put("/custom-fields/inconnu").header(admin()).json(&optional).dispatch().status(), Status::NotFound);
+        assert_eq!(client.put("/elus/luc.bernard@example.com").header(admin()).json(&person(json!({ "delegation": "culture" }))).dispatch().status(), Status::Ok);
+
+        assert_eq!(client.delete("/custom-fields/delegation").header(admin()).dispatch().status(), Status::NoContent);
+        let elu: Value = client.get("/elus/luc.bernard@example.com").dispatch().into_json().unwrap();
+        assert_eq!(elu["custom"], json!({}));
+        assert_eq!(client.get("/custom-fields/delegation").dispatch().status(), Status::NotFound);
+    }
+}
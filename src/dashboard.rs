@@ -0,0 +1,459 @@
+//! Admin dashboard for the people running the directory, who sign in with
+//! a username and password rather than the admin token. `GET /dashboard`
+//! gives the figures of its home page in one request, each from a query of
+//! its own rather than through the repository, over the elus the caller may
+//! see.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel::dsl::{count, count_star, exists, sql};
+use diesel::expression::SqlLiteral;
+use diesel::sql_types::{Bool, Nullable, Text};
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+use time::{Date, PrimitiveDateTime};
+
+use crate::config::AppConfig;
+use crate::jobs::IMPORT;
+use crate::mail_queue::{FAILED, QUEUED};
+use crate::schema::{elus, events, jobs, mail_queue, mandate_terms, mandates};
+use crate::sessions::{self, Session, COOKIE};
+use crate::visibility::{Visibility, Visible};
+use crate::{audit, csrf, db, lockout, timestamp, two_factor, users, DbConn};
+
+/// Changes, expiring mandates and imports `GET /dashboard` lists at most.
+const RECENT_CHANGES: i64 = 10;
+const EXPIRING_MANDATES: i64 = 20;
+const RECENT_IMPORTS: i64 = 5;
+
+#[derive(FromForm)]
+struct Login<'r> {
+    username: &'r str,
+    password: &'r str,
+    /// TOTP or backup code, for users with a second factor.
+    code: Option<&'r str>,
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub(crate) fn page(title: &str, body: &str) -> RawHtml<String> {
+    RawHtml(format!(
+        "<!DOCTYPE html>\n<html lang=\"fr\">\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n{}\n</body>\n</html>\n",
+        escape(title),
+        body
+    ))
+}
+
+fn login_page(error: Option<&str>) -> RawHtml<String> {
+    let error = error.map(|error| format!("<p class=\"error\">{}</p>\n", escape(error))).unwrap_or_default();
+    page(
+        "Connexion",
+        &format!(
+            "<h1>Connexion</h1>\n{}<form method=\"post\" action=\"/admin/login\">\n\
+             <label>Identifiant <input name=\"username\" autocomplete=\"username\" required></label>\n\
+             <label>Mot de passe <input name=\"password\" type=\"password\" autocomplete=\"current-password\" required></label>\n\
+             <label>Code de vérification, si la double authentification est activée \
+             <input name=\"code\" inputmode=\"numeric\" autocomplete=\"one-time-code\"></label>\n\
+             <button type=\"submit\">Se connecter</button>\n</form>\n\
+             <p><a href=\"/auth/forgot\">Mot de passe oublié ?</a></p>",
+            error
+        ),
+    )
+}
+
+#[get("/admin/login")]
+fn login_form() -> RawHtml<String> {
+    login_page(None)
+}
+
+/// Signs in, unless the username or address is locked out after too many
+/// failed attempts; see `lockout`. Users with a second factor also need a
+/// code, and a wrong code fails the attempt like a wrong password, without
+/// telling which was wrong.
+#[post("/admin/login", data = "<login>")]
+fn login(login: Form<Login<'_>>, ip: Option<IpAddr>, cookies: &CookieJar<'_>, db: &State<DbConn>, config: &State<AppConfig>) -> Result<Redirect, (Status, RawHtml<String>)> {
+    let internal_error = |_| (Status::InternalServerError, login_page(Some("Erreur interne, veuillez réessayer.")));
+    let Ok(mut connection) = db::lock(db) else {
+        return Err((Status::ServiceUnavailable, login_page(Some("Erreur interne, veuillez réessayer."))));
+    };
+    let keys = lockout::keys(login.username, ip);
+    let now = timestamp::now();
+
+    if lockout::locked_until(&keys, now, &mut connection).map_err(internal_error)?.is_some() {
+        audit::record(audit::LOGIN_LOCKED_OUT, Some(login.username), ip, &mut connection).map_err(internal_error)?;
+        return Err((Status::TooManyRequests, login_page(Some("Trop de tentatives infructueuses, veuillez réessayer plus tard."))));
+    }
+
+    let authenticated = match users::authenticate(login.username, login.password, &mut connection).map_err(internal_error)? {
+        Some(user) => two_factor::check(&user, login.code.unwrap_or_default(), now.assume_utc().unix_timestamp(), &mut connection)
+            .map_err(internal_error)?
+            .map(|verified| (user, verified)),
+        None => None,
+    };
+    let Some((user, verified)) = authenticated else {
+        let locked_until = lockout::record_failure(&keys, now, &mut connection).map_err(internal_error)?;
+        audit::record(audit::LOGIN_FAILED, Some(login.username), ip, &mut connection).map_err(internal_error)?;
+        if locked_until.is_some() {
+            log::warn!("Locking out sign-in as {:?} from {:?} after repeated failures", login.username, ip);
+            audit::record(audit::LOCKOUT_STARTED, Some(login.username), ip, &mut connection).map_err(internal_error)?;
+        }
+        return Err((Status::Unauthorized, login_page(Some("Identifiant, mot de passe ou code incorrect."))));
+    };
+
+    lockout::clear(&keys, &mut connection).map_err(internal_error)?;
+    if verified == two_factor::Verified::BackupCode {
+        audit::record(audit::BACKUP_CODE_USED, Some(&user.username), ip, &mut connection).map_err(internal_error)?;
+    }
+    audit::record(audit::LOGIN_SUCCEEDED, Some(&user.username), ip, &mut connection).map_err(internal_error)?;
+    let token = sessions::start(&user, Duration::from_secs(config.sessions.idle_timeout), &mut connection).map_err(internal_error)?;
+
+    cookies.add(sessions::cookie(token));
+    Ok(Redirect::to("/admin"))
+}
+
+/// Elus in the directory `audience` may see, and queued and failed
+/// outgoing mail.
+fn counts(audience: Visibility, connection: &mut SqliteConnection) -> QueryResult<(i64, i64, i64)> {
+    let mail = |status: &str, connection: &mut SqliteConnection| mail_queue::table.filter(mail_queue::status.eq(status)).count().get_result(connection);
+    let elus = elus::table.filter(elus::visibility.eq_any(audience.seen().collect::<Vec<_>>())).count().get_result(connection)?;
+    Ok((elus, mail(QUEUED, connection)?, mail(FAILED, connection)?))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Counts {
+    elus: i64,
+    communes: i64,
+    queued_mail: i64,
+    failed_mail: i64,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Change {
+    seq: i64,
+    kind: String,
+    /// UUID and name of the elu as of the change.
+    uuid: Option<String>,
+    name: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct ExpiringMandate {
+    uuid: String,
+    name: String,
+    mandate: String,
+    #[serde(with = "timestamp::date::option")]
+    ends_on: Option<Date>,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Import {
+    id: i32,
+    status: String,
+    #[serde(with = "timestamp::rfc3339")]
+    created_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339::option")]
+    finished_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Imports {
+    /// Imports by status.
+    statuses: std::collections::BTreeMap<String, i64>,
+    recent: Vec<Import>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Overview {
+    counts: Counts,
+    recent_changes: Vec<Change>,
+    expiring_mandates: Vec<ExpiringMandate>,
+    imports: Imports,
+}
+
+/// Whether `audience` may see the elu of an event: as they are, or as of
+/// the event for deleted ones.
+fn event_seen(audience: Visibility) -> SqlLiteral<Bool> {
+    let seen: Vec<String> = audience.seen().map(|visibility| format!("'{}'", visibility.as_str())).collect();
+    sql(&format!(
+        "coalesce((SELECT visibility FROM elus WHERE elus.id = events.elu_id), json_extract(payload, '$.visibility'), 'public') IN ({})",
+        seen.join(", ")
+    ))
+}
+
+/// The latest changes to elus `audience` may see, newest first, naming the
+/// elus from the payloads so that deleted ones are named too.
+fn recent_changes(audience: Visibility, connection: &mut SqliteConnection) -> QueryResult<Vec<Change>> {
+    events::table
+        .filter(event_seen(audience))
+        .order(events::seq.desc())
+        .limit(RECENT_CHANGES)
+        .select((
+            events::seq,
+            events::kind,
+            sql::<Nullable<Text>>("json_extract(payload, '$.uuid')"),
+            sql::<Nullable<Text>>("json_extract(payload, '$.name')"),
+            events::at,
+        ))
+        .load(connection)
+}
+
+/// The mandates still held by elus `audience` may see which end between
+/// `today` and `within_days` later, soonest first.
+fn expiring_mandates(audience: Visibility, today: Date, within_days: u32, connection: &mut SqliteConnection) -> QueryResult<Vec<ExpiringMandate>> {
+    let held = mandates::table.filter(mandates::elu_id.eq(mandate_terms::elu_id)).filter(mandates::title.eq(mandate_terms::title));
+    mandate_terms::table
+        .inner_join(elus::table)
+        .filter(elus::visibility.eq_any(audience.seen().collect::<Vec<_>>()))
+        .filter(mandate_terms::ends_on.between(today, today + time::Duration::days(within_days.into())))
+        .filter(exists(held))
+        .order((mandate_terms::ends_on, elus::name, mandate_terms::title))
+        .limit(EXPIRING_MANDATES)
+        .select((elus::uuid, elus::name, mandate_terms::title, mandate_terms::ends_on))
+        .load(connection)
+}
+
+fn imports(connection: &mut SqliteConnection) -> QueryResult<Imports> {
+    let statuses = jobs::table.filter(jobs::kind.eq(IMPORT)).group_by(jobs::status).select((jobs::status, count_star())).load(connection)?;
+    let recent = jobs::table
+        .filter(jobs::kind.eq(IMPORT))
+        .order(jobs::id.desc())
+        .limit(RECENT_IMPORTS)
+        .select((jobs::id, jobs::status, jobs::created_at, jobs::finished_at))
+        .load(connection)?;
+    Ok(Imports { statuses: statuses.into_iter().collect(), recent })
+}
+
+fn overview(audience: Visibility, today: Date, within_days: u32, connection: &mut SqliteConnection) -> QueryResult<Overview> {
+    let (elus, queued_mail, failed_mail) = counts(audience, connection)?;
+    let communes = elus::table
+        .filter(elus::visibility.eq_any(audience.seen().collect::<Vec<_>>()))
+        .select(count(elus::commune_code).aggregate_distinct())
+        .get_result(connection)?;
+    Ok(Overview {
+        counts: Counts { elus, communes, queued_mail, failed_mail },
+        recent_changes: recent_changes(audience, connection)?,
+        expiring_mandates: expiring_mandates(audience, today, within_days, connection)?,
+        imports: imports(connection)?,
+    })
+}
+
+/// What the dashboard's home page shows, for signed-in users: counts, the
+/// latest changes, mandates ending within `alerts.within_days` and imports.
+#[get("/dashboard")]
+fn dashboard_overview(_session: Session, db: &State<DbConn>, config: &State<AppConfig>, repository: Visible) -> Result<Json<Overview>, Status> {
+    let connection = &mut *db::lock(db)?;
+    overview(repository.audience(), timestamp::now().date(), config.alerts.within_days, connection).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[get("/admin")]
+fn dashboard(session: Option<Session>, cookies: &CookieJar<'_>, db: &State<DbConn>, repository: Visible) -> Result<Result<RawHtml<String>, Redirect>, Status> {
+    let Some(session) = session else {
+        cookies.remove(COOKIE);
+        return Ok(Err(Redirect::to("/admin/login")));
+    };
+
+    let (elus, queued, failed) = counts(repository.audience(), &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Ok(page(
+        "Tableau de bord",
+        &format!(
+            "<h1>Tableau de bord</h1>\n<p>Connecté en tant que {}.</p>\n<ul>\n\
+             <li>{} élus</li>\n<li>{} messages en attente</li>\n<li>{} messages en échec</li>\n</ul>\n\
+             <p><a href=\"/admin/totp\">Double authentification</a></p>\n\
+             <form method=\"post\" action=\"/admin/logout\">\n\
+             {}\n\
+             <button type=\"submit\">Se déconnecter</button>\n</form>",
+            escape(&session.user.username),
+            elus,
+            queued,
+            failed,
+            csrf::field(&session.csrf_token)
+        ),
+    )))
+}
+
+#[post("/admin/logout")]
+fn logout(session: Session, ip: Option<IpAddr>, cookies: &CookieJar<'_>, db: &State<DbConn>) -> Result<Redirect, Status> {
+    let mut connection = db::lock(db)?;
+    sessions::end(&session, &mut connection).map_err(|_| Status::InternalServerError)?;
+    audit::record(audit::LOGOUT, Some(&session.user.username), ip, &mut connection).map_err(|_| Status::InternalServerError)?;
+    cookies.remove(COOKIE);
+    Ok(Redirect::to("/admin/login"))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![login_form, login, dashboard, dashboard_overview, logout]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, client, insert_test_persons, setup_test_db};
+    use crate::users::NewUser;
+    use rocket::http::ContentType;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn csrf_token(html: &str) -> &str {
+        let start = html.find("name=\"csrf_token\" value=\"").expect("a CSRF token") + 25;
+        &html[start..start + html[start..].find('"').unwrap()]
+    }
+
+    #[test]
+    fn test_dashboard_session() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        users::create(&NewUser { username: "secretariat".to_string(), password: PASSWORD.to_string(), email: None }, &mut connection).unwrap();
+        let client = build_client(|figment| figment.merge(("sessions.idle_timeout", 60)), connection);
+
+        let response = client.get("/admin").dispatch();
+        assert_eq!((response.status(), response.headers().get_one("Location")), (Status::SeeOther, Some("/admin/login")));
+
+        let response = client.post("/admin/login").header(ContentType::Form).body("username=secretariat&password=wrong").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.cookies().get(COOKIE).is_none());
+
+        let response = client.post("/admin/login").header(ContentType::Form).body(format!("username=secretariat&password={}", PASSWORD.replace(' ', "+"))).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let cookie = response.cookies().get(COOKIE).expect("a session cookie").clone();
+        assert_eq!((cookie.http_only(), cookie.secure()), (Some(true), Some(true)));
+
+        let html = client.get("/admin").dispatch().into_string().unwrap();
+        assert!(html.contains("Connecté en tant que secretariat."));
+        assert!(html.contains("3 élus"));
+
+        // Logging out takes the session's CSRF token.
+        let response = client.post("/admin/logout").header(ContentType::Form).body("csrf_token=forged").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        let body = format!("csrf_token={}", csrf_token(&html));
+        let response = client.post("/admin/logout").header(ContentType::Form).body(body).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        // The session is over, even for a client holding on to the cookie.
+        let response = client.get("/admin").cookie(cookie).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+    }
+
+    #[test]
+    fn test_dashboard_overview() {
+        use rocket::serde::json::{json, Value};
+
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        users::create(&NewUser { username: "secretariat".to_string(), password: PASSWORD.to_string(), email: None }, &mut connection).unwrap();
+        let today = timestamp::now().date();
+        for (email, mandate, days) in [("jean.dupont@example.com", "Maire", 10), ("marie.martin@example.com", "Maire", 5), ("pierre.durand@example.com", "Sénateur", 90)] {
+            let elu_id: i32 = elus::table.filter(elus::email.eq(email)).select(elus::id).first(&mut connection).unwrap();
+            let ends_on = today + time::Duration::days(days);
+            diesel::insert_into(mandate_terms::table)
+                .values((mandate_terms::elu_id.eq(elu_id), mandate_terms::title.eq(mandate), mandate_terms::ends_on.eq(ends_on)))
+                .execute(&mut connection)
+                .unwrap();
+        }
+        crate::jobs::enqueue(IMPORT, "csv", "name,email\n", &Default::default(), &mut connection).unwrap();
+        let client = client(connection);
+        assert_eq!(client.get("/dashboard").dispatch().status(), Status::Unauthorized);
+
+        let person = json!({ "name": "Sophie Bernard", "email": "sophie.bernard@example.com", "mandates": [] });
+        assert_eq!(client.post("/elus/create").header(admin()).json(&person).dispatch().status(), Status::Ok);
+        let body = format!("username=secretariat&password={}", PASSWORD.replace(' ', "+"));
+        assert_eq!(client.post("/admin/login").header(ContentType::Form).body(body).dispatch().status(), Status::SeeOther);
+
+        let overview: Value = client.get("/dashboard").dispatch().into_json().unwrap();
+        assert_eq!(overview["counts"], json!({ "elus": 4, "communes": 1, "queued_mail": 0, "failed_mail": 0 }));
+        assert_eq!((&overview["recent_changes"][0]["kind"], &overview["recent_changes"][0]["name"]), (&json!("created"), &json!("Sophie Bernard")));
+        // Marie isn't Maire, and Pierre's term ends after the alerts' window.
+        let expiring = overview["expiring_mandates"].as_array().unwrap();
+        assert_eq!((expiring.len(), &expiring[0]["name"], &expiring[0]["mandate"]), (1, &json!("Jean Dupont"), &json!("Maire")));
+        assert_eq!(expiring[0]["ends_on"], (today + time::Duration::days(10)).to_string());
+        assert_eq!(overview["imports"]["statuses"], json!({ "queued": 1 }));
+        assert_eq!(overview["imports"]["recent"][0]["status"], "queued");
+    }
+
+    #[test]
+    fn test_dashboard_overview_leaves_out_hidden_elus() {
+        use rocket::serde::json::{json, Value};
+
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        users::create(&NewUser { username: "secretariat".to_string(), password: PASSWORD.to_string(), email: None }, &mut connection).unwrap();
+        let client = client(connection);
+        let person = json!({ "name": "Paul Secret", "email": "paul.secret@example.com", "mandates": ["Maire"], "visibility": "hidden" });
+        assert_eq!(client.post("/elus/create").header(admin()).json(&person).dispatch().status(), Status::Ok);
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let elu_id: i32 = elus::table.filter(elus::email.eq("paul.secret@example.com")).select(elus::id).first(&mut *db::lock(db).unwrap()).unwrap();
+        diesel::insert_into(mandate_terms::table)
+            .values((mandate_terms::elu_id.eq(elu_id), mandate_terms::title.eq("Maire"), mandate_terms::ends_on.eq(timestamp::now().date() + time::Duration::days(10))))
+            .execute(&mut *db::lock(db).unwrap())
+            .unwrap();
+        let body = format!("username=secretariat&password={}", PASSWORD.replace(' ', "+"));
+        assert_eq!(client.post("/admin/login").header(ContentType::Form).body(body).dispatch().status(), Status::SeeOther);
+
+        let overview: Value = client.get("/dashboard").dispatch().into_json().unwrap();
+        assert_eq!(overview["counts"]["elus"], 3);
+        assert_eq!(overview["recent_changes"], json!([]));
+        assert_eq!(overview["expiring_mandates"], json!([]));
+        assert!(client.get("/admin").dispatch().into_string().unwrap().contains("<li>3 élus</li>"));
+
+        let overview: Value = client.get("/dashboard").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(overview["counts"]["elus"], 4);
+        assert_eq!(overview["recent_changes"][0]["name"], "Paul Secret");
+        assert_eq!(overview["expiring_mandates"][0]["name"], "Paul Secret");
+
+        assert_eq!(client.delete("/elus/paul.secret@example.com").header(admin()).dispatch().status(), Status::NoContent);
+        let overview: Value = client.get("/dashboard").dispatch().into_json().unwrap();
+        assert_eq!(overview["recent_changes"], json!([]));
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let mut connection = setup_test_db();
+        users::create(&NewUser { username: "secretariat".to_string(), password: PASSWORD.to_string(), email: None }, &mut connection).unwrap();
+        let client = client(connection);
+        let login = |password: &str| {
+            let body = format!("username=secretariat&password={}", password.replace(' ', "+"));
+            let remote = "192.0.2.1:40000".parse().unwrap();
+            client.post("/admin/login").header(ContentType::Form).remote(remote).body(body).dispatch().status()
+        };
+
+        for _ in 0..lockout::MAX_FAILURES {
+            assert_eq!(login("guess"), Status::Unauthorized);
+        }
+        // Even the right password is refused during the lockout.
+        assert_eq!(login(PASSWORD), Status::TooManyRequests);
+
+        let response = client.get("/admin/audit").header(admin()).dispatch();
+        let entries: Vec<audit::AuditEntry> = response.into_json().expect("valid JSON");
+        let events: Vec<&str> = entries.iter().map(|entry| entry.event.as_str()).collect();
+        assert_eq!(events[..3], [audit::LOGIN_LOCKED_OUT, audit::LOCKOUT_STARTED, audit::LOGIN_FAILED]);
+        assert_eq!(events.len(), 2 + lockout::MAX_FAILURES as usize);
+        assert_eq!((entries[0].username.as_deref(), entries[0].ip.as_deref()), (Some("secretariat"), Some("192.0.2.1")));
+
+        let response = client.get("/admin/audit?event=login_failed&limit=2").header(admin()).dispatch();
+        assert_eq!(response.into_json::<Vec<audit::AuditEntry>>().map(|entries| entries.len()), Some(2));
+        assert_eq!(client.get("/admin/audit").dispatch().status(), Status::Unauthorized);
+    }
+}
@@ -1,90 +1,1319 @@
 use diesel::prelude::*;
-use diesel::sqlite::SqliteConnection;
+use diesel::sql_types::Double;
+use diesel::dsl::{AsSelect, SqlTypeOf};
+use diesel::sqlite::{Sqlite, SqliteConnection};
 use rocket::serde::{Serialize, Deserialize};
 use rocket::http::Status;
+use rocket::tokio::runtime::{Handle, RuntimeFlavor};
+use rocket::tokio::task;
 use dotenvy::dotenv;
-use std::env;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
-use crate::schema;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
-#[diesel(table_name = schema::elus)]
+use crate::custom_fields::CustomValues;
+use crate::deliverability::EmailStatus;
+use crate::email::Email;
+use crate::encryption::Cipher;
+use crate::person_name::PersonName;
+use crate::problem::Problem;
+use crate::events::{ChangeKind, Outbox};
+use crate::visibility::Visibility;
+use crate::repository::{normalize_name, Page, PersonFilter, PersonKey, PersonRepository, PersonSort};
+use crate::{phonetic, schema, secrets, timestamp, transaction, DbConn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Person {
     pub id: i32,
-    pub name: String,
-    pub email: String,
-    pub mandates: String,
+    /// Public identifier, for use outside of this database.
+    pub uuid: String,
+    pub name: PersonName,
+    /// Primary address.
+    pub email: Email,
+    /// Addresses besides the primary one.
+    #[serde(default)]
+    pub emails: Vec<Email>,
+    pub mandates: Vec<String>,
+    pub commune_code: Option<String>,
+    pub office_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Whether the person agreed to receive mailings.
+    #[serde(default)]
+    pub may_contact_by_email: bool,
+    /// When and how consent was given or withdrawn.
+    #[serde(default, with = "timestamp::rfc3339::option")]
+    pub consent_date: Option<PrimitiveDateTime>,
+    #[serde(default)]
+    pub consent_source: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub custom: CustomValues,
+    pub email_status: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub updated_at: PrimitiveDateTime,
 }
 
-#[derive(Insertable)]
+/// A person as stored in `elus`, without the mandates, which live in their
+/// own table.
+#[derive(Queryable, Selectable)]
 #[diesel(table_name = schema::elus)]
+struct PersonRow {
+    id: i32,
+    uuid: String,
+    name: PersonName,
+    email: Email,
+    commune_code: Option<String>,
+    office_address: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    may_contact_by_email: bool,
+    consent_date: Option<PrimitiveDateTime>,
+    consent_source: Option<String>,
+    visibility: Visibility,
+    custom: CustomValues,
+    email_status: String,
+    updated_at: PrimitiveDateTime,
+}
+
+impl PersonRow {
+    fn with_mandates(self, mandates: Vec<String>) -> Person {
+        Person {
+            id: self.id,
+            uuid: self.uuid,
+            name: self.name,
+            email: self.email,
+            emails: Vec::new(),
+            mandates,
+            commune_code: self.commune_code,
+            office_address: self.office_address,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            may_contact_by_email: self.may_contact_by_email,
+            consent_date: self.consent_date,
+            consent_source: self.consent_source,
+            visibility: self.visibility,
+            custom: self.custom,
+            email_status: self.email_status,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[cfg_attr(test, derive(Default))]
+#[diesel(table_name = schema::elus, treat_none_as_null = true)]
 pub struct NewPerson {
+    pub name: PersonName,
+    pub email: Email,
+    /// Addresses besides `email`, stored in `elu_emails` only.
+    #[diesel(skip_insertion, skip_update)]
+    pub emails: Vec<Email>,
+    #[diesel(skip_insertion, skip_update)]
+    pub mandates: Vec<String>,
+    pub commune_code: Option<String>,
+    pub office_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub may_contact_by_email: bool,
+    pub consent_date: Option<PrimitiveDateTime>,
+    pub consent_source: Option<String>,
+    pub visibility: Visibility,
+    pub custom: CustomValues,
+}
+
+impl Person {
+    pub fn has_email(&self, address: &Email) -> bool {
+        self.email == *address || self.emails.contains(address)
+    }
+}
+
+impl NewPerson {
+    /// The primary then other emails of the person.
+    pub fn addresses(&self) -> impl Iterator<Item = &Email> {
+        std::iter::once(&self.email).chain(&self.emails)
+    }
+}
+
+/// The addresses of `emails` besides `primary`, without duplicates.
+pub fn other_emails(primary: &Email, emails: &[Email]) -> Vec<Email> {
+    let mut others: Vec<Email> = vec![];
+    for address in emails {
+        if address != primary && !others.contains(address) {
+            others.push(address.clone());
+        }
+    }
+    others
+}
+
+define_sql_function! {
+    /// Great-circle distance in kilometers between two WGS84 points.
+    fn haversine_km(lat1: Double, lon1: Double, lat2: Double, lon2: Double) -> Double;
+}
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Registers the custom SQL functions used by the queries below. SQLite
+/// functions are per-connection, so this must run on every new connection.
+pub fn register_sql_functions(connection: &mut SqliteConnection) -> QueryResult<()> {
+    haversine_km_utils::register_impl(connection, haversine)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::communes)]
+#[serde(crate = "rocket::serde")]
+pub struct Commune {
+    pub code: String,
     pub name: String,
-    pub email: String,
-    pub mandates: String,
+    pub department: String,
+}
+
+/// Embeds the `up.sql` of migrations along with their names.
+macro_rules! migrations {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("../migrations/", $name, "/up.sql")))),*]
+    };
+}
+
+/// The schema, for databases that don't go through the diesel CLI; new
+/// migrations must be appended here.
+const MIGRATIONS: &[(&str, &str)] = migrations![
+    "2025-10-24-131756-0000_create_elus",
+    "2025-10-27-091500-0000_create_communes",
+    "2025-10-29-143000-0000_add_elus_location",
+    "2025-11-03-101500-0000_add_elus_email_status",
+    "2025-11-05-160000-0000_create_notifications",
+    "2025-11-10-093000-0000_create_mail_queue",
+    "2025-11-12-140000-0000_create_documents",
+    "2025-11-14-103000-0000_add_documents_filename",
+    "2025-11-17-091500-0000_create_mandates",
+    "2025-11-19-101500-0000_index_elus",
+    "2025-11-21-140000-0000_create_api_keys",
+    "2025-11-24-100000-0000_create_users_and_sessions",
+    "2025-11-26-093000-0000_create_audit_log_and_login_failures",
+    "2025-11-28-110000-0000_add_users_email",
+    "2025-12-01-100000-0000_add_users_totp",
+    "2025-12-03-090000-0000_create_sync_queue",
+    "2025-12-05-100000-0000_create_events",
+    "2025-12-08-093000-0000_create_event_cursors",
+    "2025-12-10-100000-0000_create_webhooks",
+    "2025-12-12-100000-0000_create_webhook_deliveries",
+    "2025-12-15-093000-0000_add_webhooks_secrets",
+    "2025-12-17-100000-0000_add_elus_uuid",
+    "2025-12-19-100000-0000_create_email_aliases",
+    "2025-12-22-100000-0000_create_mandate_types",
+    "2025-12-29-100000-0000_create_jobs",
+    "2025-12-31-100000-0000_add_jobs_rejection_report",
+    "2026-01-05-100000-0000_normalize_elus_emails",
+    "2026-01-07-100000-0000_normalize_elus_names",
+    "2026-01-09-100000-0000_create_relations",
+    "2026-01-12-100000-0000_create_bodies",
+    "2026-01-14-100000-0000_create_mandate_terms",
+    "2026-01-16-100000-0000_create_elections",
+    "2026-01-19-100000-0000_create_exports",
+    "2026-01-21-100000-0000_add_elus_search_phonetic",
+    "2026-01-23-100000-0000_create_elu_emails",
+    "2026-01-25-100000-0000_add_elus_consent",
+    "2026-01-27-100000-0000_add_elus_visibility",
+    "2026-01-29-100000-0000_create_tags",
+    "2026-01-31-100000-0000_create_saved_searches",
+    "2026-02-02-100000-0000_create_custom_fields",
+    "2026-02-04-100000-0000_create_collectivite_settings",
+];
+
+/// A private, throwaway database with the full schema, for tests and for
+/// running without a database file.
+pub fn establish_in_memory() -> SqliteConnection {
+    use diesel::connection::SimpleConnection;
+
+    let mut connection = SqliteConnection::establish(":memory:")
+        .expect("Failed to create in-memory database");
+    register_sql_functions(&mut connection)
+        .expect("Error registering SQL functions");
+    connection
+        .batch_execute(RECORD_MIGRATIONS)
+        .expect("Failed to create the migrations table");
+    for (name, migration) in MIGRATIONS {
+        connection
+            .batch_execute(migration)
+            .expect("Failed to run migration");
+        diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES (?)")
+            .bind::<diesel::sql_types::Text, _>(migration_version(name))
+            .execute(&mut connection)
+            .expect("Failed to record migration");
+    }
+    connection
+}
+
+/// The table the diesel CLI records applied migrations in.
+const RECORD_MIGRATIONS: &str = "CREATE TABLE __diesel_schema_migrations (
+  version VARCHAR(50) PRIMARY KEY NOT NULL,
+  run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+
+/// The version the diesel CLI records a migration under: its timestamp,
+/// digits only.
+fn migration_version(name: &str) -> String {
+    name.split('_').next().unwrap_or_default().replace('-', "")
+}
+
+/// The version of the latest migration this build knows of.
+pub fn embedded_schema_version() -> String {
+    MIGRATIONS.last().map(|(name, _)| migration_version(name)).unwrap_or_default()
+}
+
+diesel::table! {
+    __diesel_schema_migrations (version) {
+        version -> Text,
+    }
+}
+
+/// The version of the latest migration applied to the database.
+pub fn schema_version(connection: &mut SqliteConnection) -> QueryResult<Option<String>> {
+    use diesel::dsl::max;
+
+    __diesel_schema_migrations::table.select(max(__diesel_schema_migrations::version)).first(connection)
+}
+
+/// Refuses to start on a database missing migrations of this build, which
+/// queries would fail on; a database migrated by a newer build only gets a
+/// warning, so that rolling back stays possible.
+pub fn check_schema(connection: &mut SqliteConnection) {
+    let expected = embedded_schema_version();
+    match schema_version(connection) {
+        Ok(Some(version)) if version == expected => log::info!("Database schema at version {}", version),
+        Ok(Some(version)) if version > expected => log::warn!("Database schema at version {}, newer than this build's {}", version, expected),
+        Ok(found) => panic!("Database schema at version {}, expected {}: run `diesel migration run`", found.as_deref().unwrap_or("none"), expected),
+        Err(e) => panic!("Could not read the database schema version ({}): run `diesel migration run`", e),
+    }
 }
 
 pub fn establish_connection() -> SqliteConnection {
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
-    SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+    let database_url = secrets::required("DATABASE_URL").unwrap_or_else(|e| panic!("{}", e));
+    establish(&database_url)
 }
 
-pub fn email_exists(email_to_check: &str, connection: &mut SqliteConnection) -> bool {
-    use self::schema::elus::dsl::*;
+pub fn establish(database_url: &str) -> SqliteConnection {
+    let mut connection = SqliteConnection::establish(database_url)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    register_sql_functions(&mut connection)
+        .expect("Error registering SQL functions");
+    connection
+}
 
-    elus
-        .filter(email.eq(email_to_check))
-        .select(Person::as_select())
-        .first(connection)
-        .is_ok()
+/// A connection to a read replica, which refuses writes so none can be
+/// sent to it by mistake.
+pub fn establish_replica(database_url: &str) -> SqliteConnection {
+    use diesel::connection::SimpleConnection;
+
+    let mut connection = establish(database_url);
+    connection
+        .batch_execute("PRAGMA query_only = ON")
+        .expect("Error making the replica read-only");
+    connection
 }
 
-pub fn name_exists(name_to_check: &str, connection: &mut SqliteConnection) -> bool {
-    use self::schema::elus::dsl::*;
+/// A read-only copy of the database, kept up to date by external
+/// replication (e.g. Litestream).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaConfig {
+    pub database_url: String,
+    /// How far behind the primary the replica may be, in seconds: reads
+    /// within that delay after a write go to the primary, so clients see
+    /// their own changes.
+    #[serde(default = "default_max_lag")]
+    pub max_lag: u64,
+}
 
-    elus
-        .filter(name.eq(name_to_check))
-        .select(Person::as_select())
+fn default_max_lag() -> u64 {
+    2
+}
+
+/// How operations failing because another connection holds the database
+/// are retried, such contention being expected in WAL mode with writers
+/// outside the service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BusyRetryConfig {
+    /// Attempts after which the request is answered with a 503.
+    pub max_attempts: u32,
+    /// Delay, in milliseconds, before the first retry; doubled after each
+    /// failure, and up to twice as long with the jitter keeping concurrent
+    /// retries from colliding again.
+    pub retry_delay: u64,
+}
+
+impl Default for BusyRetryConfig {
+    fn default() -> Self {
+        BusyRetryConfig { max_attempts: 5, retry_delay: 10 }
+    }
+}
+
+impl BusyRetryConfig {
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let delay = self.retry_delay.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+        Duration::from_millis(delay + rand::random::<u64>() % (delay + 1))
+    }
+}
+
+/// Waits out a backoff within a repository call or `lock`. On the workers
+/// of the async runtime, which handlers call the repository from, the
+/// worker's other tasks move to another thread in the meantime rather than
+/// stall.
+fn wait(delay: Duration) {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => task::block_in_place(|| std::thread::sleep(delay)),
+        _ => std::thread::sleep(delay),
+    }
+}
+
+pub fn get_commune(code_to_find: &str, connection: &mut SqliteConnection) -> Result<Commune, Status> {
+    use self::schema::communes::dsl::*;
+
+    communes
+        .find(code_to_find)
+        .select(Commune::as_select())
         .first(connection)
-        .is_ok()
+        .map_err(|_| Status::NotFound)
+}
+
+pub fn upsert_communes(new_communes: &[Commune], connection: &mut SqliteConnection) -> Result<usize, Status> {
+    use self::schema::communes::dsl::*;
+
+    // SQLite caps the number of bound parameters per statement, and the
+    // official COG file holds ~35k communes.
+    connection
+        .transaction(|connection| {
+            new_communes.chunks(1000).try_fold(0, |count, chunk| {
+                diesel::replace_into(communes)
+                    .values(chunk)
+                    .execute(connection)
+                    .map(|inserted| count + inserted)
+            })
+        })
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// `PersonRepository` over the `elus` table.
+pub struct SqliteRepository {
+    db: DbConn,
+    replica: Option<Replica>,
+    outbox: Option<Outbox>,
+    cipher: Option<Cipher>,
+    retry: BusyRetryConfig,
+}
+
+struct Replica {
+    db: DbConn,
+    max_lag: Duration,
+    last_write: Mutex<Option<Instant>>,
+}
+
+impl SqliteRepository {
+    pub fn new(db: DbConn) -> Self {
+        SqliteRepository { db, replica: None, outbox: None, cipher: None, retry: BusyRetryConfig::default() }
+    }
+
+    /// Retries operations finding the database busy as `retry` says.
+    pub fn with_retry(self, retry: BusyRetryConfig) -> Self {
+        SqliteRepository { retry, ..self }
+    }
+
+    /// Stores emails sealed with `cipher`, whose stored emails must have
+    /// gone through `encryption::encrypt_existing`.
+    pub fn with_cipher(self, cipher: Cipher) -> Self {
+        SqliteRepository { cipher: Some(cipher), ..self }
+    }
+
+    /// Publishes changes to `outbox`, in the transactions making them.
+    pub fn with_outbox(self, outbox: Outbox) -> Self {
+        SqliteRepository { outbox: Some(outbox), ..self }
+    }
+
+    /// Serves lookups and searches from `replica`, except within `max_lag`
+    /// of the last write.
+    pub fn with_replica(self, replica: DbConn, max_lag: Duration) -> Self {
+        SqliteRepository {
+            replica: Some(Replica { db: replica, max_lag, last_write: Mutex::new(None) }),
+            ..self
+        }
+    }
+
+    /// The connection reads go to.
+    fn reader(&self) -> &DbConn {
+        match &self.replica {
+            Some(replica) if replica.last_write.lock().unwrap_or_else(PoisonError::into_inner).is_none_or(|at| at.elapsed() >= replica.max_lag) => &replica.db,
+            _ => &self.db,
+        }
+    }
+
+    /// Runs `operation` until it doesn't find the database busy, or the
+    /// attempts are exhausted; it must not hold the connection in between.
+    fn retrying<T>(&self, mut operation: impl FnMut() -> Result<T, DbError>) -> Result<T, DbError> {
+        let mut attempts = 1;
+        loop {
+            match operation() {
+                Err(DbError::Busy) if attempts < self.retry.max_attempts => {
+                    wait(self.retry.backoff(attempts));
+                    attempts += 1;
+                }
+                Err(DbError::Busy) => {
+                    log::warn!("Database still busy after {} attempts", attempts);
+                    return Err(DbError::Busy);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn publish(&self, kind: ChangeKind, person: &Person, connection: &mut SqliteConnection) -> QueryResult<()> {
+        match &self.outbox {
+            Some(outbox) => outbox.publish(kind, person, connection),
+            None => Ok(()),
+        }
+    }
+
+    /// The email as stored.
+    fn sealed(&self, email: &Email) -> Email {
+        match &self.cipher {
+            Some(cipher) => cipher.seal_email(email),
+            None => email.clone(),
+        }
+    }
+
+    fn sealed_person(&self, person: &NewPerson) -> NewPerson {
+        NewPerson { email: self.sealed(&person.email), emails: person.emails.iter().map(|email| self.sealed(email)).collect(), ..person.clone() }
+    }
+
+    /// The person as stored, with their emails in the clear.
+    fn opened(&self, person: Person) -> Result<Person, DbError> {
+        match &self.cipher {
+            Some(cipher) => {
+                let open = |email: &Email| cipher.open_email(email).map_err(|_| DbError::Query(diesel::result::Error::DeserializationError("undecryptable email".into())));
+                let email = open(&person.email)?;
+                let emails = person.emails.iter().map(open).collect::<Result<_, _>>()?;
+                Ok(Person { email, emails, ..person })
+            }
+            None => Ok(person),
+        }
+    }
+
+    fn opened_all(&self, persons: Vec<Person>) -> Result<Vec<Person>, DbError> {
+        persons.into_iter().map(|person| self.opened(person)).collect()
+    }
+
+    fn wrote(&self) {
+        if let Some(replica) = &self.replica {
+            *replica.last_write.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+        }
+    }
+
+    /// Replaces the data of the person having `email_to_find`, within the
+    /// caller's transaction.
+    fn update_in(&self, email_to_find: &Email, person: NewPerson, connection: &mut SqliteConnection) -> Result<Person, DbError> {
+        use self::schema::elus::dsl::*;
+        use self::schema::email_aliases;
+
+        let person = self.sealed_person(&person);
+        let current_id = owner(&self.sealed(email_to_find), connection)?.ok_or(DbError::NotFound)?;
+        let current = elus.find(current_id).select(PersonRow::as_select()).first(connection)?;
+        if is_taken(&person, Some(current.id), connection)? {
+            return Err(DbError::Conflict);
+        }
+
+        let status = if person.email == current.email { current.email_status } else { EmailStatus::Unchecked.as_str().to_string() };
+        let row = diesel::update(elus.find(current.id))
+            .set((&person, email_status.eq(status), search_name.eq(normalize_name(&person.name)), search_phonetic.eq(phonetic::key(&person.name)), updated_at.eq(timestamp::now())))
+            .returning(PersonRow::as_returning())
+            .get_result(connection)?;
+        replace_mandates(row.id, &person.mandates, connection)?;
+        let emails = replace_emails(row.id, &person, connection)?;
+        for address in person.addresses() {
+            release_alias(address, connection)?;
+        }
+        if !person.addresses().any(|address| *address == current.email) {
+            diesel::replace_into(email_aliases::table)
+                .values((email_aliases::email.eq(&current.email), email_aliases::elu_id.eq(row.id)))
+                .execute(connection)?;
+        }
+        let updated = self.opened(Person { emails, ..row.with_mandates(person.mandates) })?;
+        self.publish(ChangeKind::Updated, &updated, connection)?;
+        Ok(updated)
+    }
+}
+
+/// Why a database operation failed; handlers answer with the status it
+/// converts to rather than panicking, whatever SQLite returned.
+#[derive(Debug)]
+pub enum DbError {
+    NotFound,
+    /// A name or email is already taken by someone else.
+    Conflict,
+    /// The connection couldn't be had, a request having panicked with it.
+    Pool,
+    /// Another connection holds the database locked.
+    Busy,
+    Query(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for DbError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => DbError::NotFound,
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => DbError::Conflict,
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::Unknown, info) if is_busy(info.as_ref()) => DbError::Busy,
+            error => DbError::Query(error),
+        }
+    }
+}
+
+/// Whether the error is SQLITE_BUSY or SQLITE_LOCKED. Diesel doesn't hand
+/// out SQLite's result code, only its message, so the codes are told by
+/// the messages `sqlite3_errstr` gives for them, SQLITE_LOCKED's being
+/// followed by the table at times.
+fn is_busy(info: &dyn diesel::result::DatabaseErrorInformation) -> bool {
+    let message = info.message();
+    message == "database is locked" || message == "database table is locked" || message.starts_with("database table is locked: ")
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::NotFound => write!(f, "not found"),
+            DbError::Conflict => write!(f, "already taken"),
+            DbError::Pool => write!(f, "connection unavailable"),
+            DbError::Busy => write!(f, "database is locked"),
+            DbError::Query(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<DbError> for Status {
+    fn from(error: DbError) -> Self {
+        match error {
+            DbError::NotFound => Status::NotFound,
+            DbError::Conflict => Status::Conflict,
+            DbError::Pool | DbError::Busy => Status::ServiceUnavailable,
+            DbError::Query(error) => {
+                log::error!("Database error: {}", error);
+                Status::InternalServerError
+            }
+        }
+    }
+}
+
+/// For the callers answering with diesel's errors, such as the workers.
+impl From<DbError> for diesel::result::Error {
+    fn from(error: DbError) -> Self {
+        use diesel::result::DatabaseErrorKind;
+
+        match error {
+            DbError::NotFound => diesel::result::Error::NotFound,
+            DbError::Conflict => diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(error.to_string())),
+            DbError::Pool | DbError::Busy => diesel::result::Error::DatabaseError(DatabaseErrorKind::Unknown, Box::new(error.to_string())),
+            DbError::Query(error) => error,
+        }
+    }
+}
+
+impl From<DbError> for Problem {
+    fn from(error: DbError) -> Self {
+        Problem::new(error.into())
+    }
+}
+
+/// The connection, locked for the caller. While another task or thread
+/// has a transaction open on it, waits for that to end, backing off as
+/// `busy_retry` says, and finds the database busy past the attempts.
+pub fn lock(db: &DbConn) -> Result<MutexGuard<'_, SqliteConnection>, DbError> {
+    let retry = transaction::busy_retry(db);
+    let mut attempts = 1;
+    loop {
+        let connection = db.lock().map_err(|_| DbError::Pool)?;
+        if transaction::is_free(db) {
+            return Ok(connection);
+        }
+        drop(connection);
+        if attempts >= retry.max_attempts {
+            return Err(DbError::Busy);
+        }
+        wait(retry.backoff(attempts));
+        attempts += 1;
+    }
 }
 
-pub fn insert_person(person_name: String, person_email: String, person_mandates: String, connection: &mut SqliteConnection) -> Result<(), Status> {
+/// Whether another person than `except` already has the name or one of
+/// the emails.
+fn is_taken(person: &NewPerson, except: Option<i32>, connection: &mut SqliteConnection) -> QueryResult<bool> {
+    use self::schema::elu_emails;
     use self::schema::elus::dsl::*;
 
-    let new_person = NewPerson {
-        name: person_name,
-        email: person_email,
-        mandates: person_mandates,
-    };
+    let mut query = elus
+        .filter(email.eq(&person.email).or(name.eq(&person.name)))
+        .select(id)
+        .into_boxed();
+    let mut owners = elu_emails::table
+        .filter(elu_emails::email.eq_any(person.addresses()))
+        .select(elu_emails::elu_id)
+        .into_boxed();
+    if let Some(except) = except {
+        query = query.filter(id.ne(except));
+        owners = owners.filter(elu_emails::elu_id.ne(except));
+    }
+
+    Ok(query.first::<i32>(connection).optional()?.is_some() || owners.first::<i32>(connection).optional()?.is_some())
+}
+
+/// The person having `address` among their emails, stored as given.
+fn owner(address: &Email, connection: &mut SqliteConnection) -> QueryResult<Option<i32>> {
+    use self::schema::elu_emails::dsl::*;
+
+    elu_emails.find(address).select(elu_id).first(connection).optional()
+}
+
+/// Replaces the emails of the person, returning the other addresses as
+/// stored.
+fn replace_emails(person_id: i32, person: &NewPerson, connection: &mut SqliteConnection) -> QueryResult<Vec<Email>> {
+    use self::schema::elu_emails::dsl::*;
+
+    diesel::delete(elu_emails.filter(elu_id.eq(person_id))).execute(connection)?;
+    let others = other_emails(&person.email, &person.emails);
+    let rows: Vec<_> = std::iter::once((&person.email, true))
+        .chain(others.iter().map(|address| (address, false)))
+        .map(|(address, primary)| (email.eq(address), elu_id.eq(person_id), is_primary.eq(primary)))
+        .collect();
+    diesel::insert_into(elu_emails).values(&rows).execute(connection)?;
+    Ok(others)
+}
+
+/// Stops `address` from being an alias before an elu takes it.
+fn release_alias(address: &str, connection: &mut SqliteConnection) -> QueryResult<()> {
+    use self::schema::email_aliases::dsl::*;
 
-    diesel::insert_into(elus)
-        .values(&new_person)
-        .execute(connection)
-        .map_err(|_| Status::InternalServerError)?;
+    diesel::delete(email_aliases.find(address)).execute(connection)?;
+    Ok(())
+}
+
+pub fn escape_like(pattern: &str) -> String {
+    pattern.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
 
+/// Fetches the mandates and other emails of `rows` with one query each
+/// per thousand persons, rather than per person.
+fn completed(rows: Vec<PersonRow>, connection: &mut SqliteConnection) -> QueryResult<Vec<Person>> {
+    use self::schema::{elu_emails, mandates};
+
+    let mut by_person: HashMap<i32, Vec<String>> = HashMap::new();
+    let mut emails_by_person: HashMap<i32, Vec<Email>> = HashMap::new();
+    for chunk in rows.chunks(1000) {
+        let found: Vec<(i32, String)> = mandates::table
+            .filter(mandates::elu_id.eq_any(chunk.iter().map(|row| row.id)))
+            .order((mandates::elu_id, mandates::position))
+            .select((mandates::elu_id, mandates::title))
+            .load(connection)?;
+        for (person_id, title) in found {
+            by_person.entry(person_id).or_default().push(title);
+        }
+        let found: Vec<(i32, Email)> = elu_emails::table
+            .filter(elu_emails::elu_id.eq_any(chunk.iter().map(|row| row.id)))
+            .filter(elu_emails::is_primary.eq(false))
+            .order((elu_emails::elu_id, elu_emails::email))
+            .select((elu_emails::elu_id, elu_emails::email))
+            .load(connection)?;
+        for (person_id, address) in found {
+            emails_by_person.entry(person_id).or_default().push(address);
+        }
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let mandates = by_person.remove(&row.id).unwrap_or_default();
+            let emails = emails_by_person.remove(&row.id).unwrap_or_default();
+            Person { emails, ..row.with_mandates(mandates) }
+        })
+        .collect())
+}
+
+fn replace_mandates(person_id: i32, titles: &[String], connection: &mut SqliteConnection) -> QueryResult<()> {
+    use self::schema::mandates::dsl::*;
+
+    diesel::delete(mandates.filter(elu_id.eq(person_id))).execute(connection)?;
+    let rows: Vec<_> = titles
+        .iter()
+        .enumerate()
+        .map(|(index, mandate)| (elu_id.eq(person_id), position.eq(index as i32), title.eq(mandate)))
+        .collect();
+    diesel::insert_into(mandates).values(&rows).execute(connection)?;
     Ok(())
 }
 
-pub fn elus(connection: &mut SqliteConnection) -> Result<Vec<Person>, Status> {
+type PersonQuery<'a> = schema::elus::BoxedQuery<'a, Sqlite, SqlTypeOf<AsSelect<PersonRow, Sqlite>>>;
+
+/// The persons matching `filter`, in no particular order.
+fn filtered(filter: &PersonFilter) -> PersonQuery<'_> {
     use self::schema::elus::dsl::*;
+    use self::schema::mandates;
 
-    elus
-        .select(Person::as_select())
-        .load(connection)
-        .map_err(|_| Status::InternalServerError)
+    let mut query = elus.select(PersonRow::as_select()).into_boxed();
+    if let Some(pattern) = &filter.name {
+        query = query.filter(search_name.like(format!("%{}%", escape_like(&normalize_name(pattern)))).escape('\\'));
+    }
+    if let Some(key) = &filter.phonetic {
+        query = query.filter(search_phonetic.like(format!("%{}%", escape_like(key))).escape('\\'));
+    }
+    if let Some(mandate) = &filter.mandate {
+        let holders = mandates::table.filter(mandates::title.eq(mandate)).select(mandates::elu_id);
+        query = query.filter(id.eq_any(holders));
+    }
+    if let Some(code) = &filter.commune_code {
+        query = query.filter(commune_code.eq(code));
+    }
+    if let Some(status) = filter.email_status {
+        query = query.filter(email_status.eq(status.as_str()));
+    }
+    if let Some(ids) = &filter.ids {
+        query = query.filter(id.eq_any(ids.clone()));
+    }
+    if let Some(near) = filter.near {
+        let distance = haversine_km(latitude.assume_not_null(), longitude.assume_not_null(), near.latitude, near.longitude);
+        query = query
+            .filter(latitude.is_not_null().and(longitude.is_not_null()))
+            .filter(distance.le(near.radius_km));
+    }
+    if let Some(audience) = filter.audience {
+        query = query.filter(visibility.eq_any(audience.seen().collect::<Vec<_>>()));
+    }
+    if let Some(expression) = &filter.expression {
+        query = query.filter(expression.to_sql());
+    }
+    query
 }
 
-pub fn get_elu_by_email(email_to_find: &str, connection: &mut SqliteConnection) -> Result<Person, Status> {
+/// Computes the phonetic key of the persons who have none yet, the rows
+/// written before the column existed; returns how many were updated.
+pub fn index_phonetic(connection: &mut SqliteConnection) -> QueryResult<usize> {
     use self::schema::elus::dsl::*;
 
-    elus
-        .filter(email.eq(email_to_find))
-        .select(Person::as_select())
-        .first(connection)
-        .map_err(|_| Status::NotFound)
+    let rows: Vec<(i32, String)> = elus.filter(search_phonetic.eq("")).select((id, name)).load(connection)?;
+    connection.transaction(|connection| {
+        let mut updated = 0;
+        for (row_id, row_name) in &rows {
+            let key = phonetic::key(row_name);
+            if !key.is_empty() {
+                updated += diesel::update(elus.find(row_id)).set(search_phonetic.eq(key)).execute(connection)?;
+            }
+        }
+        Ok(updated)
+    })
+}
+
+/// Inserts a person along with their mandates.
+pub fn insert_person(person: &NewPerson, connection: &mut SqliteConnection) -> QueryResult<Person> {
+    use self::schema::elus::dsl::*;
+
+    connection.transaction(|connection| {
+        let row = diesel::insert_into(elus)
+            .values((person, uuid.eq(Uuid::new_v4().to_string()), search_name.eq(normalize_name(&person.name)), search_phonetic.eq(phonetic::key(&person.name)), updated_at.eq(timestamp::now())))
+            .returning(PersonRow::as_returning())
+            .get_result(connection)?;
+        replace_mandates(row.id, &person.mandates, connection)?;
+        let emails = replace_emails(row.id, person, connection)?;
+        Ok(Person { emails, ..row.with_mandates(person.mandates.clone()) })
+    })
+}
+
+impl PersonRepository for SqliteRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        use self::schema::elus::dsl::*;
+
+        Ok(self.retrying(|| {
+            let mut connection = lock(self.reader())?;
+            let rows = elus.order(id).select(PersonRow::as_select()).load(&mut *connection)?;
+            self.opened_all(completed(rows, &mut connection)?)
+        })?)
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        use self::schema::elus::dsl::*;
+
+        let query = || {
+            let query = elus.select(PersonRow::as_select()).into_boxed();
+            match key {
+                PersonKey::Id(key) => query.filter(id.eq(*key)),
+                PersonKey::Uuid(key) => query.filter(uuid.eq(key)),
+                PersonKey::Email(key) => {
+                    let owners = schema::elu_emails::table.filter(schema::elu_emails::email.eq(self.sealed(key))).select(schema::elu_emails::elu_id);
+                    query.filter(id.eq_any(owners))
+                }
+            }
+        };
+
+        Ok(self.retrying(|| {
+            let mut connection = lock(self.reader())?;
+            let row = query().first(&mut *connection)?;
+            let mut found = completed(vec![row], &mut connection)?;
+            self.opened(found.remove(0))
+        })?)
+    }
+
+    fn find_alias(&self, alias: &Email) -> Result<Person, Status> {
+        use self::schema::email_aliases;
+
+        let person_id = self.retrying(|| {
+            Ok(email_aliases::table
+                .find(self.sealed(alias))
+                .select(email_aliases::elu_id)
+                .first(&mut *lock(self.reader())?)?)
+        })?;
+
+        self.get(person_id)
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        use self::schema::elus::dsl::*;
+
+        Ok(self.retrying(|| {
+            let mut connection = lock(self.reader())?;
+            let owners = schema::elu_emails::table
+                .filter(schema::elu_emails::email.eq_any(emails.iter().map(|key| self.sealed(key))))
+                .select(schema::elu_emails::elu_id);
+            let rows = elus
+                .filter(id.eq_any(owners))
+                .select(PersonRow::as_select())
+                .load(&mut *connection)?;
+            self.opened_all(completed(rows, &mut connection)?)
+        })?)
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        let person = self.sealed_person(&person);
+        let created = self.retrying(|| {
+            let mut connection = lock(&self.db)?;
+            if is_taken(&person, None, &mut connection)? {
+                return Err(DbError::Conflict);
+            }
+
+            connection.transaction::<_, DbError, _>(|connection| {
+                for address in person.addresses() {
+                    release_alias(address, connection)?;
+                }
+                let created = self.opened(insert_person(&person, connection)?)?;
+                self.publish(ChangeKind::Created, &created, connection)?;
+                Ok(created)
+            })
+        })?;
+        self.wrote();
+
+        Ok(created)
+    }
+
+    fn update(&self, email_to_find: &Email, person: NewPerson) -> Result<Person, Status> {
+        let updated = self.retrying(|| {
+            lock(&self.db)?.transaction::<_, DbError, _>(|connection| self.update_in(email_to_find, person.clone(), connection))
+        })?;
+        self.wrote();
+
+        Ok(updated)
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        let updated = self.retrying(|| {
+            lock(&self.db)?.transaction::<_, DbError, _>(|connection| {
+                updates
+                    .iter()
+                    .map(|(email_to_find, person)| self.update_in(email_to_find, person.clone(), connection))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+        })?;
+        self.wrote();
+
+        Ok(updated)
+    }
+
+    fn delete(&self, email_to_find: &Email) -> Result<Person, Status> {
+        use self::schema::elus::dsl::*;
+        use self::schema::{elu_emails, email_aliases, mandates};
+
+        let deleted = self.retrying(|| {
+            lock(&self.db)?.transaction::<_, DbError, _>(|connection| {
+                let person_id = owner(&self.sealed(email_to_find), connection)?.ok_or(DbError::NotFound)?;
+                let row = diesel::delete(elus.find(person_id))
+                    .returning(PersonRow::as_returning())
+                    .get_result(connection)?;
+                let titles = diesel::delete(mandates::table.filter(mandates::elu_id.eq(row.id)))
+                    .returning(mandates::title)
+                    .get_results(connection)?;
+                let emails = diesel::delete(elu_emails::table.filter(elu_emails::elu_id.eq(row.id)).filter(elu_emails::is_primary.eq(false)))
+                    .returning(elu_emails::email)
+                    .get_results(connection)?;
+                diesel::delete(elu_emails::table.filter(elu_emails::elu_id.eq(row.id))).execute(connection)?;
+                diesel::delete(email_aliases::table.filter(email_aliases::elu_id.eq(row.id))).execute(connection)?;
+                let deleted = self.opened(Person { emails, ..row.with_mandates(titles) })?;
+                self.publish(ChangeKind::Deleted, &deleted, connection)?;
+                Ok(deleted)
+            })
+        })?;
+        self.wrote();
+
+        Ok(deleted)
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        use self::schema::elus::dsl::*;
+
+        Ok(self.retrying(|| {
+            let query = match filter.near {
+                Some(near) => filtered(filter).order(haversine_km(latitude.assume_not_null(), longitude.assume_not_null(), near.latitude, near.longitude)),
+                None => filtered(filter).order(id),
+            };
+
+            let mut connection = lock(self.reader())?;
+            let rows = query.load(&mut *connection)?;
+            self.opened_all(completed(rows, &mut connection)?)
+        })?)
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        use self::schema::elus::dsl::*;
+
+        Ok(self.retrying(|| {
+            let query = filtered(filter).offset(page.offset).limit(page.limit);
+            let mut query = match (page.sort.key.unwrap_or(PersonSort::Name), page.sort.descending) {
+                (PersonSort::Name, false) => query.order((name, id)),
+                (PersonSort::Name, true) => query.order((name.desc(), id.desc())),
+                (PersonSort::UpdatedAt, false) => query.order((updated_at, id)),
+                (PersonSort::UpdatedAt, true) => query.order((updated_at.desc(), id.desc())),
+            };
+            if let Some((last_name, last_id)) = &page.after {
+                query = query.filter(name.gt(last_name.clone()).or(name.eq(last_name.clone()).and(id.gt(*last_id))));
+            }
+
+            let mut connection = lock(self.reader())?;
+            let rows = query.load(&mut *connection)?;
+            self.opened_all(completed(rows, &mut connection)?)
+        })?)
+    }
+
+    fn set_email_status(&self, person_id: i32, status: EmailStatus) -> Result<(), Status> {
+        use self::schema::elus::dsl::*;
+
+        self.retrying(|| {
+            diesel::update(elus.find(person_id))
+                .set((email_status.eq(status.as_str()), updated_at.eq(timestamp::now())))
+                .execute(&mut *lock(&self.db)?)?;
+            Ok(())
+        })?;
+        self.wrote();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{insert_test_persons, setup_test_db};
+    use diesel::connection::SimpleConnection;
+    use std::sync::Arc;
+
+    fn repository() -> SqliteRepository {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        SqliteRepository::new(Arc::new(Mutex::new(connection)))
+    }
+
+    fn names(persons: Vec<Person>) -> Vec<String> {
+        persons.into_iter().map(|person| person.name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let repository = repository();
+        let search = |filter: PersonFilter| names(repository.search(&filter).unwrap());
+
+        assert_eq!(search(PersonFilter { name: Some("DUR".to_string()), ..Default::default() }), vec!["Pierre Durand"]);
+        assert_eq!(search(PersonFilter { name: Some("MARTÎN".to_string()), ..Default::default() }), vec!["Marie Martin"]);
+        assert_eq!(search(PersonFilter { name: Some("%".to_string()), ..Default::default() }), Vec::<String>::new());
+        // Mandates match exactly, not as substrings of one another.
+        assert_eq!(search(PersonFilter { mandate: Some("Conseiller".to_string()), ..Default::default() }), Vec::<String>::new());
+        assert_eq!(search(PersonFilter { mandate: Some("Conseiller régional".to_string()), ..Default::default() }), vec!["Jean Dupont"]);
+        assert_eq!(
+            search(PersonFilter { commune_code: Some("75056".to_string()), mandate: Some("Députée".to_string()), ..Default::default() }),
+            vec!["Marie Martin"]
+        );
+    }
+
+    #[test]
+    fn test_schema_version() {
+        assert_eq!(migration_version("2025-10-24-131756-0000_create_elus"), "202510241317560000");
+        assert_eq!(schema_version(&mut setup_test_db()).unwrap(), Some(embedded_schema_version()));
+    }
+
+    #[test]
+    fn test_create_update_delete() {
+        let repository = repository();
+        let person = NewPerson {
+            name: "Alice Wonderland".parse().unwrap(),
+            email: "alice@example.com".parse().unwrap(),
+            mandates: vec![],
+            ..Default::default()
+        };
+
+        let created = repository.create(person.clone()).unwrap();
+        assert_eq!(repository.create(person.clone()).unwrap_err(), Status::Conflict);
+        repository.set_email_status(created.id, EmailStatus::Deliverable).unwrap();
+
+        let renamed = NewPerson { name: "Alice Liddell".parse().unwrap(), ..person.clone() };
+        assert_eq!(repository.update(&"alice@example.com".parse().unwrap(), renamed).unwrap().email_status, "deliverable");
+        let taken = NewPerson { name: "Jean Dupont".parse().unwrap(), ..person.clone() };
+        assert_eq!(repository.update(&"alice@example.com".parse().unwrap(), taken).unwrap_err(), Status::Conflict);
+        let moved = NewPerson { email: "alice@wonderland.example".parse().unwrap(), ..person };
+        assert_eq!(repository.update(&"alice@example.com".parse().unwrap(), moved).unwrap().email_status, "unchecked");
+        assert_eq!(repository.find_alias(&"alice@example.com".parse().unwrap()).unwrap().id, created.id);
+
+        assert_eq!(repository.delete(&"alice@wonderland.example".parse().unwrap()).unwrap().id, created.id);
+        assert_eq!(repository.find_alias(&"alice@example.com".parse().unwrap()).unwrap_err(), Status::NotFound);
+        assert_eq!(repository.find(&PersonKey::Email("alice@wonderland.example".parse().unwrap())).unwrap_err(), Status::NotFound);
+        assert_eq!(repository.delete(&"alice@wonderland.example".parse().unwrap()).unwrap_err(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_emails() {
+        let repository = repository();
+        let email = |address: &str| -> Email { address.parse().unwrap() };
+        let person = NewPerson {
+            name: "Alice Wonderland".parse().unwrap(),
+            email: email("alice@mairie.example"),
+            emails: vec![email("alice@example.com"), email("alice@mairie.example"), email("alice@example.com")],
+            ..Default::default()
+        };
+
+        let created = repository.create(person.clone()).unwrap();
+        assert_eq!(created.emails, [email("alice@example.com")]);
+        assert_eq!(repository.find(&PersonKey::Email(email("alice@example.com"))).unwrap().id, created.id);
+        assert_eq!(repository.get_many(&[email("alice@example.com")]).unwrap().len(), 1);
+        let taken = NewPerson { name: "Alice Liddell".parse().unwrap(), email: email("liddell@example.com"), emails: vec![email("alice@example.com")], ..Default::default() };
+        assert_eq!(repository.create(taken).unwrap_err(), Status::Conflict);
+
+        // The personal address becomes the primary one, the former primary
+        // one is kept and doesn't become an alias.
+        let swapped = NewPerson { email: email("alice@example.com"), emails: vec![email("alice@mairie.example")], ..person };
+        let updated = repository.update(&email("alice@example.com"), swapped).unwrap();
+        assert_eq!((updated.email, updated.emails), (email("alice@example.com"), vec![email("alice@mairie.example")]));
+        assert_eq!(repository.find_alias(&email("alice@mairie.example")).unwrap_err(), Status::NotFound);
+
+        assert_eq!(repository.delete(&email("alice@mairie.example")).unwrap().id, created.id);
+        assert_eq!(repository.find(&PersonKey::Email(email("alice@example.com"))).unwrap_err(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_update_many() {
+        let repository = repository();
+        let jean = repository.find(&PersonKey::Email("jean.dupont@example.com".parse().unwrap())).unwrap();
+        let patched = |person: &Person, name: &str| NewPerson {
+            name: name.parse().unwrap(),
+            email: person.email.clone(),
+            mandates: vec!["Maire".to_string()],
+            ..Default::default()
+        };
+
+        // Marie can't take Jean's name, so Jean isn't updated either.
+        let marie = repository.find(&PersonKey::Email("marie.martin@example.com".parse().unwrap())).unwrap();
+        let updates = vec![(jean.email.clone(), patched(&jean, "Jean Dupont")), (marie.email.clone(), patched(&marie, "Jean Dupont"))];
+        assert_eq!(repository.update_many(updates).unwrap_err(), Status::Conflict);
+        assert_eq!(repository.find(&PersonKey::Id(jean.id)).unwrap().mandates, jean.mandates);
+
+        let updates = vec![(jean.email.clone(), patched(&jean, "Jean Dupont")), (marie.email.clone(), patched(&marie, "Marie Martin"))];
+        assert_eq!(names(repository.update_many(updates).unwrap()), vec!["Jean Dupont", "Marie Martin"]);
+        assert_eq!(repository.find(&PersonKey::Id(marie.id)).unwrap().mandates, ["Maire"]);
+    }
+
+    #[test]
+    fn test_list_query_count() {
+        use diesel::connection::InstrumentationEvent;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        for index in 0..20 {
+            let person = NewPerson {
+                name: format!("Conseiller {}", index).parse().unwrap(),
+                email: format!("conseiller{}@example.com", index).parse().unwrap(),
+                mandates: vec!["Conseiller municipal".to_string(), "Maire adjoint".to_string()],
+                ..Default::default()
+            };
+            insert_person(&person, &mut connection).unwrap();
+        }
+        connection.set_instrumentation(move |event: InstrumentationEvent<'_>| {
+            if matches!(event, InstrumentationEvent::StartQuery { .. }) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let repository = SqliteRepository::new(Arc::new(Mutex::new(connection)));
+
+        // One query for the persons, one for all of their mandates and one
+        // for their other emails, however many persons there are.
+        let persons = repository.list().unwrap();
+        assert_eq!(persons.len(), 23);
+        assert_eq!(persons[0].mandates, ["Maire", "Conseiller régional"]);
+        assert_eq!(persons[22].mandates, ["Conseiller municipal", "Maire adjoint"]);
+        assert_eq!(queries.swap(0, Ordering::Relaxed), 3);
+
+        let mandate = PersonFilter { mandate: Some("Conseiller municipal".to_string()), ..Default::default() };
+        assert_eq!(repository.search(&mandate).unwrap().len(), 21);
+        assert_eq!(queries.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_replica() {
+        let replica = Arc::new(Mutex::new(setup_test_db()));
+        let person = NewPerson {
+            name: "Alice Wonderland".parse().unwrap(),
+            email: "alice@example.com".parse().unwrap(),
+            mandates: vec![],
+            ..Default::default()
+        };
+
+        // The replica answers, even if it hasn't caught up yet...
+        let lagging = repository().with_replica(replica.clone(), Duration::from_secs(3600));
+        assert!(lagging.list().unwrap().is_empty());
+        // ...except right after a write, which clients must see.
+        lagging.create(person.clone()).unwrap();
+        assert!(lagging.find(&PersonKey::Email("alice@example.com".parse().unwrap())).is_ok());
+
+        let up_to_date = repository().with_replica(replica, Duration::ZERO);
+        up_to_date.create(person).unwrap();
+        assert_eq!(up_to_date.find(&PersonKey::Email("alice@example.com".parse().unwrap())).unwrap_err(), Status::NotFound);
+
+        let mut read_only = establish_replica(":memory:");
+        assert!(read_only.batch_execute("CREATE TABLE scratch (id INTEGER)").is_err());
+    }
+
+    #[test]
+    fn test_encryption() {
+        use crate::{encryption, schema::email_aliases};
+
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        insert_test_persons(&mut db.lock().unwrap());
+        let cipher = encryption::Cipher::new(&[7; 32]);
+        encryption::encrypt_existing(&cipher, &mut db.lock().unwrap()).unwrap();
+        let repository = SqliteRepository::new(db.clone()).with_cipher(encryption::Cipher::new(&[7; 32]));
+
+        let jean = repository.find(&PersonKey::Email("jean.dupont@example.com".parse().unwrap())).unwrap();
+        assert_eq!(jean.email, "jean.dupont@example.com");
+        let moved = NewPerson { email: "jean@mairie.example".parse().unwrap(), mandates: jean.mandates.clone(), name: jean.name.clone(), ..Default::default() };
+        assert_eq!(repository.update(&jean.email, moved).unwrap().email, "jean@mairie.example");
+        assert_eq!(repository.find_alias(&"jean.dupont@example.com".parse().unwrap()).unwrap().id, jean.id);
+        assert_eq!(repository.get_many(&["jean@mairie.example".parse().unwrap()]).unwrap().len(), 1);
+
+        let mut connection = db.lock().unwrap();
+        let stored: Vec<Email> = schema::elus::table.select(schema::elus::email).load(&mut *connection).unwrap();
+        let aliases: Vec<Email> = email_aliases::table.select(email_aliases::email).load(&mut *connection).unwrap();
+        assert!(stored.iter().chain(&aliases).all(|email| encryption::is_sealed(email) && !email.contains("example")));
+    }
+
+    #[test]
+    fn test_outbox() {
+        use crate::schema::events;
+
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        let repository = SqliteRepository::new(db.clone()).with_outbox(Outbox::default());
+        let person = NewPerson {
+            name: "Alice Wonderland".parse().unwrap(),
+            email: "alice@example.com".parse().unwrap(),
+            mandates: vec![],
+            ..Default::default()
+        };
+
+        repository.create(person.clone()).unwrap();
+        repository.delete(&"alice@example.com".parse().unwrap()).unwrap();
+        let kinds: Vec<String> = events::table.order(events::seq).select(events::kind).load(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!(kinds, ["created", "deleted"]);
+
+        // A change whose event can't be written isn't made either.
+        db.lock().unwrap().batch_execute("DROP TABLE events").unwrap();
+        assert_eq!(repository.create(person).unwrap_err(), Status::InternalServerError);
+        assert_eq!(repository.find(&PersonKey::Email("alice@example.com".parse().unwrap())).unwrap_err(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_errors() {
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        let repository = SqliteRepository::new(db.clone());
+        let person = NewPerson { name: "Alice Wonderland".parse().unwrap(), email: "alice@example.com".parse().unwrap(), ..Default::default() };
+        repository.create(person.clone()).unwrap();
+        assert_eq!(repository.create(person).unwrap_err(), Status::Conflict);
+
+        let failed = diesel::sql_query("SELECT * FROM nowhere").execute(&mut *lock(&db).unwrap()).unwrap_err();
+        assert_eq!(Status::from(DbError::from(failed)), Status::InternalServerError);
+
+        // A request panicking with the connection leaves it poisoned, which
+        // the next ones are refused over instead of panicking in turn.
+        let poisoner = db.clone();
+        std::thread::spawn(move || {
+            let _connection = poisoner.lock().unwrap();
+            panic!("poisoning the connection");
+        })
+        .join()
+        .unwrap_err();
+        assert!(matches!(lock(&db), Err(DbError::Pool)));
+        assert_eq!(repository.list().unwrap_err(), Status::ServiceUnavailable);
+    }
+
+    #[test]
+    fn test_busy_retry() {
+        let path = std::env::temp_dir().join(format!("rckd-busy-{}.db", std::process::id()));
+        let url = path.to_str().unwrap();
+        let mut holder = SqliteConnection::establish(url).unwrap();
+        holder.batch_execute("CREATE TABLE scratch (id INTEGER); BEGIN EXCLUSIVE").unwrap();
+        let error = diesel::sql_query("SELECT * FROM scratch").execute(&mut SqliteConnection::establish(url).unwrap()).unwrap_err();
+        assert!(matches!(DbError::from(error), DbError::Busy));
+        drop(holder);
+        std::fs::remove_file(path).unwrap();
+
+        let repository = repository().with_retry(BusyRetryConfig { max_attempts: 3, retry_delay: 0 });
+        let mut attempts = 0;
+        let busy = repository.retrying(|| {
+            attempts += 1;
+            Err::<(), _>(DbError::Busy)
+        });
+        assert!(matches!(busy, Err(DbError::Busy)));
+        assert_eq!(attempts, 3);
+        attempts = 0;
+        let recovered = repository.retrying(|| {
+            attempts += 1;
+            if attempts < 2 { Err(DbError::Busy) } else { Ok(attempts) }
+        });
+        assert_eq!(recovered.unwrap(), 2);
+        assert_eq!(Status::from(DbError::Busy), Status::ServiceUnavailable);
+
+        // Handlers retry on the workers of the async runtime too.
+        let runtime = rocket::tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        let repository = repository.with_retry(BusyRetryConfig { max_attempts: 3, retry_delay: 1 });
+        let retried = runtime.spawn(async move { repository.retrying(|| Err::<(), _>(DbError::Busy)) });
+        assert!(matches!(runtime.block_on(retried).unwrap(), Err(DbError::Busy)));
+
+        let config = BusyRetryConfig::default();
+        assert!((Duration::from_millis(40)..=Duration::from_millis(80)).contains(&config.backoff(3)));
+    }
 }
@@ -0,0 +1,220 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::dns::Resolver;
+use crate::repository::{PersonFilter, PersonRepository};
+use crate::shutdown;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum EmailStatus {
+    /// Not checked yet, or the last check couldn't reach a DNS server.
+    #[default]
+    Unchecked,
+    Deliverable,
+    InvalidSyntax,
+    /// The domain doesn't exist or doesn't accept mail.
+    NoMailServer,
+}
+
+impl EmailStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailStatus::Unchecked => "unchecked",
+            EmailStatus::Deliverable => "deliverable",
+            EmailStatus::InvalidSyntax => "invalid_syntax",
+            EmailStatus::NoMailServer => "no_mail_server",
+        }
+    }
+
+}
+
+impl FromStr for EmailStatus {
+    type Err = ();
+
+    fn from_str(status: &str) -> Result<Self, ()> {
+        [EmailStatus::Unchecked, EmailStatus::Deliverable, EmailStatus::InvalidSyntax, EmailStatus::NoMailServer]
+            .into_iter()
+            .find(|candidate| candidate.as_str() == status)
+            .ok_or(())
+    }
+}
+
+/// Looks up whether a domain can receive mail.
+pub trait MailDomainResolver: Send + Sync {
+    fn accepts_mail(&self, domain: &str) -> std::io::Result<bool>;
+}
+
+impl MailDomainResolver for Resolver {
+    fn accepts_mail(&self, domain: &str) -> std::io::Result<bool> {
+        Resolver::accepts_mail(self, domain)
+    }
+}
+
+/// Pragmatic address syntax check: a dot-atom local part and a domain made
+/// of valid DNS labels, without the RFC 5322 corner cases (quoted local
+/// parts, IP literals) that mail servers hardly accept anyway.
+pub fn is_valid_syntax(email: &str) -> bool {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && local.split('.').all(|atom| {
+            !atom.is_empty() && atom.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+        });
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    local_ok && domain_ok && email.len() <= 254
+}
+
+/// Checks one address; `None` means the lookup failed and should be retried.
+pub fn check(email: &str, resolver: &dyn MailDomainResolver) -> Option<EmailStatus> {
+    if !is_valid_syntax(email) {
+        return Some(EmailStatus::InvalidSyntax);
+    }
+
+    let (_, domain) = email.rsplit_once('@')?;
+    match resolver.accepts_mail(domain) {
+        Ok(true) => Some(EmailStatus::Deliverable),
+        Ok(false) => Some(EmailStatus::NoMailServer),
+        Err(e) => {
+            log::warn!("MX lookup for {} failed: {}", domain, e);
+            None
+        }
+    }
+}
+
+/// Checks every unchecked address, returning how many were flagged. The
+/// database lock isn't held during DNS lookups.
+pub fn check_pending(repository: &dyn PersonRepository, resolver: &dyn MailDomainResolver) -> Result<usize, Status> {
+    let pending = repository.search(&PersonFilter { email_status: Some(EmailStatus::Unchecked), ..Default::default() })?;
+
+    let mut checked = 0;
+    for person in pending {
+        if let Some(status) = check(&person.email, resolver) {
+            repository.set_email_status(person.id, status)?;
+            checked += 1;
+        }
+    }
+
+    Ok(checked)
+}
+
+/// Periodically checks new addresses in the background.
+pub fn fairing(period: Duration) -> AdHoc {
+    AdHoc::on_liftoff("Email deliverability checker", move |rocket| Box::pin(async move {
+        let repository = rocket.state::<Arc<dyn PersonRepository>>().expect("person repository is managed").clone();
+        let resolver: Arc<dyn MailDomainResolver> = match Resolver::from_system() {
+            Ok(resolver) => Arc::new(resolver),
+            Err(e) => {
+                log::error!("Email deliverability checks disabled: {}", e);
+                return;
+            }
+        };
+
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(period);
+            while shutdown::tick(&mut interval, &shutdown).await {
+                let (repository, resolver) = (repository.clone(), resolver.clone());
+                match rocket::tokio::task::spawn_blocking(move || check_pending(repository.as_ref(), resolver.as_ref())).await {
+                    Ok(Ok(checked)) if checked > 0 => log::info!("Checked deliverability of {} email addresses", checked),
+                    Ok(Ok(_)) => {}
+                    Ok(Err(status)) => log::error!("Email deliverability check failed: {}", status),
+                    Err(e) => log::error!("Email deliverability check panicked: {}", e),
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, insert_test_persons, setup_test_db};
+    use crate::db::{self, SqliteRepository};
+    use crate::{DbConn, Person};
+    use std::sync::Mutex;
+    use diesel::RunQueryDsl;
+
+    struct FakeResolver;
+
+    impl MailDomainResolver for FakeResolver {
+        fn accepts_mail(&self, domain: &str) -> std::io::Result<bool> {
+            match domain {
+                "example.com" => Ok(true),
+                "unreachable.example" => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout")),
+                _ => Ok(false),
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_valid_syntax() {
+        assert!(is_valid_syntax("jean.dupont@example.com"));
+        assert!(is_valid_syntax("j+mairie@sub.example.fr"));
+        assert!(!is_valid_syntax("jean.dupont"));
+        assert!(!is_valid_syntax("jean..dupont@example.com"));
+        assert!(!is_valid_syntax("jean@localhost"));
+        assert!(!is_valid_syntax("jean@-example.com"));
+        assert!(!is_valid_syntax("jean dupont@example.com"));
+    }
+
+    #[test]
+    fn test_check_pending_flags_undeliverable_addresses() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        for person in [
+            db::NewPerson { name: "Typo".parse().unwrap(), email: "typo@example.com".parse().unwrap(), ..Default::default() },
+            db::NewPerson { name: "Gone".parse().unwrap(), email: "gone@closed.example".parse().unwrap(), ..Default::default() },
+            db::NewPerson { name: "Later".parse().unwrap(), email: "later@unreachable.example".parse().unwrap(), ..Default::default() },
+        ] {
+            db::insert_person(&person, &mut connection).unwrap();
+        }
+        // Stored before addresses were checked on the way in.
+        diesel::sql_query("UPDATE elus SET email = 'typo@@example.com' WHERE name = 'Typo'").execute(&mut connection).unwrap();
+
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        let repository = SqliteRepository::new(db.clone());
+        assert_eq!(check_pending(&repository, &FakeResolver), Ok(5));
+        // Only the address whose lookup failed is left to check.
+        assert_eq!(check_pending(&repository, &FakeResolver), Ok(0));
+        drop(repository);
+
+        let connection = Arc::try_unwrap(db).ok().unwrap().into_inner().unwrap();
+        let client = client(connection);
+
+        let response = client.get("/elus?email_status=no_mail_server").dispatch();
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 1);
+        assert_eq!(persons[0].email, "gone@closed.example");
+        assert_eq!(persons[0].email_status, EmailStatus::NoMailServer);
+
+        let response = client.get("/elus?email_status=unchecked").dispatch();
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 1);
+
+        let response = client.get("/elus?email_status=deliverable").dispatch();
+        assert_eq!(response.into_json::<Vec<Person>>().map(|persons| persons.len()), Some(3));
+
+        let response = client.get("/elus?email_status=bogus").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
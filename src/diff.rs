@@ -0,0 +1,172 @@
+//! What changed in the directory between two points in time, for periodic
+//! reports: `GET /elus/diff?from=...&to=...` replays the event log up to
+//! each point and compares the elus it finds, telling which were added,
+//! removed or modified, and how. Points are RFC 3339 timestamps, or dates
+//! standing for their midnight UTC; `to` defaults to now.
+
+use std::collections::BTreeMap;
+
+use rocket::http::Status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::encryption::Sealer;
+use crate::events::{self, ChangeKind, Event};
+use crate::{db, timestamp, DbConn};
+
+/// Fields which change along with every other, and so tell nothing.
+const IGNORED_FIELDS: &[&str] = &["updated_at"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FieldChange {
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Modified {
+    pub elu_id: i32,
+    /// The person as of `to`.
+    pub person: Value,
+    /// The fields which differ, by name.
+    pub changes: BTreeMap<String, FieldChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Changeset {
+    #[serde(with = "timestamp::rfc3339")]
+    pub from: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub to: PrimitiveDateTime,
+    /// Persons as of `to`.
+    pub added: Vec<Value>,
+    /// Persons as they were when removed.
+    pub removed: Vec<Value>,
+    pub modified: Vec<Modified>,
+}
+
+fn changes(before: &Value, after: &Value) -> BTreeMap<String, FieldChange> {
+    let (Value::Object(before), Value::Object(after)) = (before, after) else {
+        return BTreeMap::new();
+    };
+    let mut changes = BTreeMap::new();
+    for field in before.keys().chain(after.keys()) {
+        if IGNORED_FIELDS.contains(&field.as_str()) || changes.contains_key(field) {
+            continue;
+        }
+        let (from, to) = (before.get(field).cloned().unwrap_or(Value::Null), after.get(field).cloned().unwrap_or(Value::Null));
+        if from != to {
+            changes.insert(field.clone(), FieldChange { from, to });
+        }
+    }
+    changes
+}
+
+/// The changeset between `from` and `to` of the events, in sequence order
+/// and up to `to`. Elus which came and went in between don't appear.
+fn changeset(events: Vec<Event>, from: PrimitiveDateTime, to: PrimitiveDateTime) -> Changeset {
+    // Each elu as of each point, if they existed then.
+    let mut before: BTreeMap<i32, Option<Value>> = BTreeMap::new();
+    let mut after: BTreeMap<i32, Option<Value>> = BTreeMap::new();
+    for event in events.into_iter().filter(|event| event.at <= to) {
+        let person = (event.kind != ChangeKind::Deleted.as_str()).then_some(event.person);
+        if event.at <= from {
+            before.insert(event.elu_id, person.clone());
+        }
+        after.insert(event.elu_id, person);
+    }
+
+    let mut changeset = Changeset { from, to, added: vec![], removed: vec![], modified: vec![] };
+    for (elu_id, latest) in after {
+        match (before.remove(&elu_id).flatten(), latest) {
+            (None, Some(added)) => changeset.added.push(added),
+            (None, None) => {}
+            (Some(removed), None) => changeset.removed.push(removed),
+            (Some(earlier), Some(person)) => {
+                let changes = changes(&earlier, &person);
+                if !changes.is_empty() {
+                    changeset.modified.push(Modified { elu_id, person, changes });
+                }
+            }
+        }
+    }
+    changeset
+}
+
+#[get("/elus/diff?<from>&<to>")]
+fn diff(from: &str, to: Option<&str>, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<Changeset>, Status> {
+    let from = timestamp::parse_point(from).ok_or(Status::BadRequest)?;
+    let to = match to {
+        Some(to) => timestamp::parse_point(to).ok_or(Status::BadRequest)?,
+        None => timestamp::now(),
+    };
+    if from > to {
+        return Err(Status::BadRequest);
+    }
+
+    let events = events::until(to, sealer, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(changeset(events, from, to)))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![diff]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, setup_test_db};
+    use rocket::serde::json::json;
+    use time::macros::datetime;
+
+    fn event(seq: i64, kind: ChangeKind, elu_id: i32, person: Value, at: PrimitiveDateTime) -> Event {
+        Event { seq, kind: kind.as_str().to_string(), elu_id, person, at }
+    }
+
+    #[test]
+    fn test_changeset() {
+        let jean = json!({ "name": "Jean Dupont", "mandates": ["Maire"], "updated_at": "2026-01-01T00:00:00Z" });
+        let mayor = json!({ "name": "Jean Dupont", "mandates": ["Maire", "Conseiller régional"], "updated_at": "2026-02-01T00:00:00Z" });
+        let events = vec![
+            event(1, ChangeKind::Created, 1, jean.clone(), datetime!(2026-01-01 00:00:00)),
+            event(2, ChangeKind::Created, 2, json!({ "name": "Marie Martin" }), datetime!(2026-01-02 00:00:00)),
+            event(3, ChangeKind::Updated, 1, mayor.clone(), datetime!(2026-02-01 00:00:00)),
+            event(4, ChangeKind::Deleted, 2, json!({ "name": "Marie Martin" }), datetime!(2026-02-02 00:00:00)),
+            event(5, ChangeKind::Created, 3, json!({ "name": "Pierre Durand" }), datetime!(2026-02-03 00:00:00)),
+            // Came and went within the period.
+            event(6, ChangeKind::Created, 4, json!({ "name": "Lucie Bernard" }), datetime!(2026-02-04 00:00:00)),
+            event(7, ChangeKind::Deleted, 4, json!({ "name": "Lucie Bernard" }), datetime!(2026-02-05 00:00:00)),
+            // After the period.
+            event(8, ChangeKind::Created, 5, json!({ "name": "Paul Petit" }), datetime!(2026-04-01 00:00:00)),
+        ];
+
+        let changeset = changeset(events, datetime!(2026-01-15 00:00:00), datetime!(2026-03-01 00:00:00));
+        assert_eq!(changeset.added, [json!({ "name": "Pierre Durand" })]);
+        assert_eq!(changeset.removed, [json!({ "name": "Marie Martin" })]);
+        assert_eq!(changeset.modified.len(), 1);
+        assert_eq!(
+            changeset.modified[0].changes,
+            BTreeMap::from([("mandates".to_string(), FieldChange { from: jean["mandates"].clone(), to: mayor["mandates"].clone() })])
+        );
+    }
+
+    #[test]
+    fn test_diff_endpoint() {
+        let client = client(setup_test_db());
+        client.post("/elus/create").json(&json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": [] })).dispatch();
+
+        assert_eq!(client.get("/elus/diff?from=2020-01-01").dispatch().status(), Status::Unauthorized);
+        let changeset: Changeset = client.get("/elus/diff?from=2020-01-01").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(changeset.added[0]["name"], "Jean Dupont");
+        assert!(changeset.removed.is_empty() && changeset.modified.is_empty());
+        assert_eq!(client.get("/elus/diff?from=2026-02-01&to=2026-01-01").header(admin()).dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/elus/diff?from=soon").header(admin()).dispatch().status(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,202 @@
+//! Discovery of the directory by search engines and open-data portals:
+//! `/sitemap.xml` lists the page of every elu, split in sitemaps of
+//! `SITEMAP_SIZE` pages listed by an index beyond that, and
+//! `/.well-known/dcat.json` describes the dataset and where to download
+//! it, as DCAT and schema.org JSON-LD. Links are absolute, based on
+//! `public_url` or else on the request's host.
+
+use std::fmt::Write;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::{json, Value};
+use rocket::serde::Deserialize;
+use rocket::State;
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::AppConfig;
+use crate::db::Person;
+use crate::visibility::Visible;
+
+/// Pages per sitemap, the most the protocol allows.
+const SITEMAP_SIZE: usize = 50_000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DatasetConfig {
+    pub title: String,
+    pub description: String,
+    /// Organization publishing the directory.
+    pub publisher: String,
+    /// URL of the license the data is published under.
+    pub license: String,
+}
+
+impl Default for DatasetConfig {
+    fn default() -> Self {
+        DatasetConfig {
+            title: "Annuaire des élus".to_string(),
+            description: "Élus, leurs mandats et leurs coordonnées professionnelles.".to_string(),
+            publisher: "Annuaire des élus".to_string(),
+            license: "https://www.etalab.gouv.fr/licence-ouverte-open-licence/".to_string(),
+        }
+    }
+}
+
+/// Base URL absolute links start with, without a trailing slash.
+pub struct BaseUrl(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BaseUrl {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let configured = request.rocket().state::<AppConfig>().and_then(|config| config.public_url.as_deref());
+        let base = match (configured, request.host()) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(host)) => format!("https://{}", host),
+            (None, None) => format!("http://{}:{}", request.rocket().config().address, request.rocket().config().port),
+        };
+        request::Outcome::Success(BaseUrl(base))
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn urlset(base: &str, persons: &[Person]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for person in persons {
+        let lastmod = person.updated_at.assume_utc().format(&Rfc3339).expect("valid timestamp");
+        let _ = writeln!(xml, "  <url><loc>{}/elus/{}</loc><lastmod>{}</lastmod></url>", escape_xml(base), person.uuid, lastmod);
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn sitemap_index(base: &str, sitemaps: usize) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for number in 1..=sitemaps {
+        let _ = writeln!(xml, "  <sitemap><loc>{}/sitemaps/{}.xml</loc></sitemap>", escape_xml(base), number);
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+/// The sitemap of every elu's page, or the index of the sitemaps when they
+/// don't fit in one.
+#[get("/sitemap.xml")]
+fn sitemap(base: BaseUrl, repository: Visible) -> Result<(ContentType, String), Status> {
+    let persons = repository.list()?;
+    if persons.len() <= SITEMAP_SIZE {
+        return Ok((ContentType::XML, urlset(&base.0, &persons)));
+    }
+
+    Ok((ContentType::XML, sitemap_index(&base.0, persons.len().div_ceil(SITEMAP_SIZE))))
+}
+
+/// One of the sitemaps of the index, numbered from 1.
+#[get("/sitemaps/<number>")]
+fn numbered_sitemap(number: &str, base: BaseUrl, repository: Visible) -> Result<(ContentType, String), Status> {
+    let number: usize = number.strip_suffix(".xml").and_then(|number| number.parse().ok()).filter(|number| *number > 0).ok_or(Status::NotFound)?;
+    let persons = repository.list()?;
+    let page = persons.chunks(SITEMAP_SIZE).nth(number - 1).ok_or(Status::NotFound)?;
+
+    Ok((ContentType::XML, urlset(&base.0, page)))
+}
+
+fn dataset(base: &str, config: &DatasetConfig, persons: &[Person]) -> Value {
+    let modified = persons.iter().map(|person| person.updated_at).max().map(|at| at.assume_utc().format(&Rfc3339).expect("valid timestamp"));
+    let distribution = |url: String, media_type: &str| {
+        json!({
+            "@type": ["DataDownload", "dcat:Distribution"],
+            "contentUrl": url,
+            "dcat:downloadURL": url,
+            "encodingFormat": media_type,
+            "dcat:mediaType": media_type,
+        })
+    };
+
+    json!({
+        "@context": {
+            "@vocab": "https://schema.org/",
+            "dcat": "http://www.w3.org/ns/dcat#",
+            "dct": "http://purl.org/dc/terms/",
+        },
+        "@id": format!("{}/.well-known/dcat.json", base),
+        "@type": ["Dataset", "dcat:Dataset"],
+        "name": config.title,
+        "dct:title": config.title,
+        "description": config.description,
+        "dct:description": config.description,
+        "url": base,
+        "dcat:landingPage": base,
+        "license": config.license,
+        "dct:license": config.license,
+        "publisher": { "@type": "Organization", "name": config.publisher },
+        "inLanguage": "fr",
+        "spatialCoverage": "France",
+        "keywords": ["élus", "mandats", "annuaire"],
+        "dateModified": modified,
+        "dct:modified": modified,
+        "size": persons.len(),
+        "documentation": format!("{}/openapi.json", base),
+        "distribution": [
+            distribution(format!("{}/elus", base), "application/json"),
+            distribution(format!("{}/elus/export.geojson", base), "application/geo+json"),
+        ],
+    })
+}
+
+/// The dataset description, as DCAT and schema.org JSON-LD.
+#[get("/.well-known/dcat.json")]
+fn dcat(base: BaseUrl, config: &State<AppConfig>, repository: Visible) -> Result<(ContentType, String), Status> {
+    let persons = repository.list()?;
+
+    Ok((ContentType::new("application", "ld+json"), dataset(&base.0, &config.dataset, &persons).to_string()))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![sitemap, numbered_sitemap, dcat]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_sitemap_index() {
+        assert_eq!(
+            sitemap_index("https://annuaire.example", 2),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+             <sitemap><loc>https://annuaire.example/sitemaps/1.xml</loc></sitemap>\n  \
+             <sitemap><loc>https://annuaire.example/sitemaps/2.xml</loc></sitemap>\n</sitemapindex>\n"
+        );
+    }
+
+    #[test]
+    fn test_discovery() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("public_url", "https://annuaire.example/")), connection);
+        let persons: Vec<crate::Person> = client.get("/elus").dispatch().into_json().unwrap();
+
+        let response = client.get("/sitemap.xml").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::XML));
+        let xml = response.into_string().unwrap();
+        assert_eq!(xml.matches("<url>").count(), 3);
+        assert!(xml.contains(&format!("<loc>https://annuaire.example/elus/{}</loc>", persons[0].uuid)));
+        assert_eq!(client.get("/sitemaps/1.xml").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/sitemaps/2.xml").dispatch().status(), Status::NotFound);
+
+        let response = client.get("/.well-known/dcat.json").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::new("application", "ld+json")));
+        let dataset: Value = response.into_json().unwrap();
+        assert_eq!(dataset["@type"], json!(["Dataset", "dcat:Dataset"]));
+        assert_eq!(dataset["size"], 3);
+        assert_eq!(dataset["distribution"][1]["contentUrl"], "https://annuaire.example/elus/export.geojson");
+        assert!(dataset["dateModified"].is_string());
+    }
+}
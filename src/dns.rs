@@ -0,0 +1,203 @@
+//! Minimal DNS stub resolver, just enough to tell whether a domain accepts
+//! mail (RFC 5321 section 5.1).
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+const TYPE_A: u16 = 1;
+const TYPE_MX: u16 = 15;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+pub struct Resolver {
+    nameserver: SocketAddr,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(nameserver: SocketAddr) -> Self {
+        Resolver {
+            nameserver,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Uses the first nameserver listed in `/etc/resolv.conf`.
+    pub fn from_system() -> io::Result<Self> {
+        let resolv_conf = fs::read_to_string("/etc/resolv.conf")?;
+        let nameserver = resolv_conf
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .find_map(|address| address.trim().parse().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no nameserver in /etc/resolv.conf"))?;
+
+        Ok(Resolver::new(SocketAddr::new(nameserver, 53)))
+    }
+
+    /// Whether `domain` has a mail exchanger: an MX record other than a
+    /// "null MX" (RFC 7505), or else an address record acting as implicit MX.
+    pub fn accepts_mail(&self, domain: &str) -> io::Result<bool> {
+        let Some(exchanges) = self.query(domain, TYPE_MX)? else {
+            return Ok(false);
+        };
+        if !exchanges.is_empty() {
+            return Ok(exchanges.iter().any(|rdata| !is_null_mx(rdata)));
+        }
+
+        for record_type in [TYPE_A, TYPE_AAAA] {
+            if self.query(domain, record_type)?.is_some_and(|records| !records.is_empty()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the RDATA of the answers of the requested type, or `None` if
+    /// the domain doesn't exist.
+    fn query(&self, domain: &str, record_type: u16) -> io::Result<Option<Vec<Vec<u8>>>> {
+        let id: u16 = rand::random();
+        let socket = UdpSocket::bind(if self.nameserver.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(self.nameserver)?;
+        socket.send(&build_query(id, domain, record_type)?)?;
+
+        let mut response = [0u8; 4096];
+        let len = socket.recv(&mut response)?;
+        parse_response(id, record_type, &response[..len])
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn build_query(id: u16, domain: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(domain.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "invalid domain name"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn parse_response(id: u16, record_type: u16, message: &[u8]) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let read_u16 = |at: usize| {
+        message
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| invalid("truncated DNS response"))
+    };
+
+    if message.len() < 12 {
+        return Err(invalid("truncated DNS response"));
+    }
+    if read_u16(0)? != id || message[2] & 0x80 == 0 {
+        return Err(invalid("unexpected DNS response"));
+    }
+    match message[3] & 0x0F {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(None),
+        rcode => return Err(invalid(&format!("DNS server returned rcode {}", rcode))),
+    }
+
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+    let mut position = 12;
+    for _ in 0..questions {
+        position = skip_name(message, position)? + 4;
+    }
+
+    let mut records = vec![];
+    for _ in 0..answers {
+        position = skip_name(message, position)?;
+        let answer_type = read_u16(position)?;
+        let rdata_len = read_u16(position + 8)? as usize;
+        let rdata = message
+            .get(position + 10..position + 10 + rdata_len)
+            .ok_or_else(|| invalid("truncated DNS response"))?;
+        if answer_type == record_type {
+            records.push(rdata.to_vec());
+        }
+        position += 10 + rdata_len;
+    }
+
+    Ok(Some(records))
+}
+
+fn skip_name(message: &[u8], mut position: usize) -> io::Result<usize> {
+    loop {
+        let len = *message.get(position).ok_or_else(|| invalid("truncated DNS name"))?;
+        match len {
+            0 => return Ok(position + 1),
+            // Compression pointer.
+            len if len & 0xC0 == 0xC0 => return Ok(position + 2),
+            len => position += 1 + len as usize,
+        }
+    }
+}
+
+/// A "null MX" is a single MX record pointing at the root, explicitly
+/// stating that the domain doesn't accept mail.
+fn is_null_mx(rdata: &[u8]) -> bool {
+    rdata.len() == 3 && rdata[2] == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(id: u16, rcode: u8, answers: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut message = build_query(id, "example.com", TYPE_MX).unwrap();
+        message[2] |= 0x80;
+        message[3] |= rcode;
+        message[7] = answers.len() as u8;
+        for (answer_type, rdata) in answers {
+            // Pointer to the name in the question.
+            message.extend_from_slice(&[0xC0, 12]);
+            message.extend_from_slice(&answer_type.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&300u32.to_be_bytes());
+            message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            message.extend_from_slice(rdata);
+        }
+        message
+    }
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query(0x1234, "example.com.", TYPE_MX).unwrap();
+
+        assert_eq!(query[..4], [0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x07example\x03com\x00\x00\x0f\x00\x01");
+        assert!(build_query(1, "invalid..com", TYPE_MX).is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mx = b"\x00\x0a\x04mail\xc0\x0c";
+        let records = parse_response(7, TYPE_MX, &response(7, 0, &[(TYPE_MX, mx), (TYPE_A, &[192, 0, 2, 1])])).unwrap();
+        assert_eq!(records, Some(vec![mx.to_vec()]));
+        assert!(!is_null_mx(mx));
+        assert!(is_null_mx(b"\x00\x00\x00"));
+
+        assert_eq!(parse_response(7, TYPE_MX, &response(7, RCODE_NXDOMAIN, &[])).unwrap(), None);
+        assert!(parse_response(8, TYPE_MX, &response(7, 0, &[])).is_err());
+        assert!(parse_response(7, TYPE_MX, &response(7, 2, &[])).is_err());
+
+        let mut truncated = response(7, 0, &[(TYPE_MX, mx)]);
+        truncated.truncate(truncated.len() - 3);
+        assert!(parse_response(7, TYPE_MX, &truncated).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+use std::io;
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::fs;
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::config::AppConfig;
+use crate::schema::documents;
+use crate::storage::BlobStore;
+use crate::uploads::{self, PDF};
+use crate::repository::{PersonKey, PersonRepository};
+use crate::visibility::Visible;
+use crate::{db, timeouts, timestamp, DbConn};
+
+/// Documents anyone can download.
+pub const PUBLIC: &str = "public";
+/// Documents only administrators can list and download.
+pub const PRIVATE: &str = "private";
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = documents)]
+#[serde(crate = "rocket::serde")]
+pub struct Document {
+    pub id: i32,
+    pub title: String,
+    /// Name of the uploaded file.
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub size: i64,
+    pub visibility: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+}
+
+impl Document {
+    fn readable(&self, admin: Option<&Admin>) -> bool {
+        self.visibility == PUBLIC || admin.is_some()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = documents)]
+struct NewDocument<'a> {
+    elu_id: i32,
+    title: &'a str,
+    filename: Option<&'a str>,
+    content_type: &'a str,
+    size: i64,
+    visibility: &'a str,
+}
+
+/// Query parameters of an upload; `title` names the document in listings.
+#[derive(FromForm)]
+pub struct UploadMetadata<'r> {
+    title: &'r str,
+    filename: Option<&'r str>,
+    visibility: Option<&'r str>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DocumentUpdate {
+    pub title: Option<String>,
+    pub visibility: Option<String>,
+}
+
+fn parse_visibility(visibility: &str) -> Result<&'static str, Status> {
+    match visibility {
+        PUBLIC => Ok(PUBLIC),
+        PRIVATE => Ok(PRIVATE),
+        _ => Err(Status::UnprocessableEntity),
+    }
+}
+
+fn document_key(id: i32) -> String {
+    format!("documents/{}", id)
+}
+
+fn get_document(elu_id: i32, id: i32, connection: &mut SqliteConnection) -> Result<Document, Status> {
+    documents::table
+        .find(id)
+        .filter(documents::elu_id.eq(elu_id))
+        .select(Document::as_select())
+        .first(connection)
+        .map_err(|_| Status::NotFound)
+}
+
+/// The single byte range requested with a `Range` header. Multiple ranges
+/// aren't supported, so such requests get the whole document.
+pub struct ByteRange(Option<String>);
+
+impl ByteRange {
+    /// Resolves the range against a document of `len` bytes, as inclusive
+    /// bounds; `Err` when the range can't be satisfied.
+    fn resolve(&self, len: u64) -> Result<Option<(u64, u64)>, ()> {
+        let Some(spec) = self.0.as_deref().and_then(|header| header.trim().strip_prefix("bytes=")) else {
+            return Ok(None);
+        };
+        let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+            return Ok(None);
+        };
+
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return Err(()),
+                Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+                Err(_) => return Ok(None),
+            },
+            (start, "") => match start.parse::<u64>() {
+                Ok(start) => (start, len.saturating_sub(1)),
+                Err(_) => return Ok(None),
+            },
+            (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+                _ => return Ok(None),
+            },
+        };
+
+        if start >= len {
+            return Err(());
+        }
+        Ok(Some((start, end)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ByteRange {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(ByteRange(request.headers().get_one("Range").map(String::from)))
+    }
+}
+
+#[derive(Responder)]
+pub enum DocumentFile {
+    Full(Vec<u8>, ContentType, Header<'static>),
+    #[response(status = 206)]
+    Partial(Vec<u8>, ContentType, Header<'static>, Header<'static>),
+    #[response(status = 416)]
+    Unsatisfiable((), Header<'static>),
+}
+
+fn accept_ranges() -> Header<'static> {
+    Header::new("Accept-Ranges", "bytes")
+}
+
+/// Stores a PDF for the elu once it passed the upload checks; documents are
+/// private unless stated otherwise.
+#[post("/elus/<key>/documents?<metadata..>", data = "<file>")]
+#[allow(clippy::too_many_arguments)]
+async fn upload_document(
+    key: &str,
+    metadata: UploadMetadata<'_>,
+    content_type: &ContentType,
+    file: Data<'_>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    store: &State<Box<dyn BlobStore>>,
+    config: &State<AppConfig>,
+) -> Result<Created<Json<Document>>, Status> {
+    let visibility = parse_visibility(metadata.visibility.unwrap_or(PRIVATE))?;
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+
+    let directory = config.upload_dir().join("staging");
+    fs::create_dir_all(&directory).await.map_err(|_| Status::InternalServerError)?;
+    let partial = directory.join(format!(".upload-{:016x}", rand::random::<u64>()));
+    let written = file
+        .open(config.uploads.max_document_size.bytes())
+        .into_file(&partial)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if !written.is_complete() {
+        let _ = fs::remove_file(&partial).await;
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let (path, declared, filename, upload_config) =
+        (partial.clone(), content_type.clone(), metadata.filename.map(String::from), config.uploads.clone());
+    let validated = rocket::tokio::task::spawn_blocking(move || {
+        uploads::validate(&path, &declared, filename.as_deref(), &[PDF], &upload_config)
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+    let file_type = match validated {
+        Ok(file_type) => file_type,
+        Err(rejection) => {
+            let _ = fs::remove_file(&partial).await;
+            return Err(rejection.into());
+        }
+    };
+
+    let inserted = diesel::insert_into(documents::table)
+        .values(NewDocument {
+            elu_id: elu.id,
+            title: metadata.title,
+            filename: metadata.filename,
+            content_type: file_type.mime,
+            size: written.n.written as i64,
+            visibility,
+        })
+        .returning(Document::as_returning())
+        .get_result(&mut *db::lock(db)?);
+    let document = match inserted {
+        Ok(document) => document,
+        Err(_) => {
+            let _ = fs::remove_file(&partial).await;
+            return Err(Status::InternalServerError);
+        }
+    };
+    if let Err(e) = store.put(&document_key(document.id), &partial).await {
+        log::error!("Could not store document {}: {}", document.id, e);
+        let _ = fs::remove_file(&partial).await;
+        let _ = diesel::delete(documents::table.find(document.id)).execute(&mut *db::lock(db)?);
+        return Err(Status::InternalServerError);
+    }
+
+    let location = format!("/elus/{}/documents/{}", elu.uuid, document.id);
+    Ok(Created::new(location).body(Json(document)))
+}
+
+/// Lists the elu's documents; private ones are only listed to administrators.
+#[get("/elus/<key>/documents")]
+fn list_documents(key: &str, admin: Option<Admin>, db: &State<DbConn>, repository: Visible) -> Result<Json<Vec<Document>>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let mut connection = db::lock(db)?;
+
+    let documents = documents::table
+        .filter(documents::elu_id.eq(elu.id))
+        .order(documents::id)
+        .select(Document::as_select())
+        .load(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(documents.into_iter().filter(|document| document.readable(admin.as_ref())).collect()))
+}
+
+/// Serves a document, honouring single `Range` requests. Private documents
+/// are reported as missing to anyone but administrators.
+#[get("/elus/<key>/documents/<id>")]
+#[allow(clippy::too_many_arguments)]
+async fn download_document(
+    key: &str,
+    id: i32,
+    range: ByteRange,
+    admin: Option<Admin>,
+    db: &State<DbConn>,
+    repository: Visible,
+    store: &State<Box<dyn BlobStore>>,
+    config: &State<AppConfig>,
+) -> Result<DocumentFile, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let document = get_document(elu.id, id, &mut *db::lock(db)?)?;
+    if !document.readable(admin.as_ref()) {
+        return Err(Status::NotFound);
+    }
+
+    let content_type = ContentType::parse_flexible(&document.content_type).unwrap_or(ContentType::Binary);
+    let len = document.size as u64;
+    let read = |range| timeouts::within(config.timeouts.request(), async move {
+        store.get(&document_key(document.id), range).await.map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Status::NotFound,
+            _ => {
+                log::error!("Could not read document {}: {}", document.id, e);
+                Status::InternalServerError
+            }
+        })
+    });
+
+    match range.resolve(len) {
+        Ok(None) => Ok(DocumentFile::Full(read(None).await?, content_type, accept_ranges())),
+        Ok(Some((start, end))) => {
+            let bytes = read(Some((start, end))).await?;
+            let content_range = Header::new("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            Ok(DocumentFile::Partial(bytes, content_type, content_range, accept_ranges()))
+        }
+        Err(()) => Ok(DocumentFile::Unsatisfiable((), Header::new("Content-Range", format!("bytes */{}", len)))),
+    }
+}
+
+#[patch("/elus/<key>/documents/<id>", data = "<update>")]
+fn update_document(
+    key: &str,
+    id: i32,
+    update: Json<DocumentUpdate>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+) -> Result<Json<Document>, Status> {
+    let visibility = update.visibility.as_deref().map(parse_visibility).transpose()?;
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let mut connection = db::lock(db)?;
+    let document = get_document(elu.id, id, &mut connection)?;
+
+    diesel::update(documents::table.find(document.id))
+        .set((
+            documents::title.eq(update.title.as_deref().unwrap_or(&document.title)),
+            documents::visibility.eq(visibility.unwrap_or(&document.visibility)),
+        ))
+        .returning(Document::as_returning())
+        .get_result(&mut *connection)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[delete("/elus/<key>/documents/<id>")]
+async fn delete_document(
+    key: &str,
+    id: i32,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    store: &State<Box<dyn BlobStore>>,
+) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    {
+        let mut connection = db::lock(db)?;
+        let document = get_document(elu.id, id, &mut connection)?;
+        diesel::delete(documents::table.find(document.id))
+            .execute(&mut *connection)
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    if let Err(e) = store.delete(&document_key(id)).await {
+        log::warn!("Could not remove the file of document {}: {}", id, e);
+    }
+
+    Ok(Status::NoContent)
+}
+
+/// Deletes every document of an elu, before the elu itself is deleted,
+/// returning their ids for `remove_files` to remove their files once the
+/// deletion is committed.
+pub fn delete_all(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<Vec<i32>> {
+    diesel::delete(documents::table.filter(documents::elu_id.eq(elu_id))).returning(documents::id).get_results(connection)
+}
+
+/// Removes the files of deleted documents.
+pub async fn remove_files(ids: &[i32], store: &dyn BlobStore) {
+    for id in ids {
+        if let Err(e) = store.delete(&document_key(*id)).await {
+            log::warn!("Could not remove the file of document {}: {}", id, e);
+        }
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![upload_document, list_documents, download_document, update_document, delete_document]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+    use rocket::local::blocking::Client;
+
+    const PDF: &[u8] = b"%PDF-1.4\n1 0 obj << >> endobj\ntrailer << >>\n%%EOF\n";
+
+    fn client(upload_dir: &std::path::Path) -> Client {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        build_client(|figment| figment.merge(("upload_dir", upload_dir)), connection)
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = |header: &str| ByteRange(Some(header.to_string())).resolve(100);
+
+        assert_eq!(range("bytes=0-9"), Ok(Some((0, 9))));
+        assert_eq!(range("bytes=90-"), Ok(Some((90, 99))));
+        assert_eq!(range("bytes=-10"), Ok(Some((90, 99))));
+        assert_eq!(range("bytes=50-500"), Ok(Some((50, 99))));
+        assert_eq!(range("bytes=100-"), Err(()));
+        assert_eq!(range("bytes=0-1,5-6"), Ok(None));
+        assert_eq!(range("items=0-1"), Ok(None));
+        assert_eq!(ByteRange(None).resolve(100), Ok(None));
+    }
+
+    #[test]
+    fn test_document_lifecycle() {
+        let upload_dir = tempfile::tempdir().unwrap();
+        let client = client(upload_dir.path());
+        let documents = "/elus/jean.dupont@example.com/documents";
+
+        let response = client.post(format!("{}?title=D%C3%A9claration", documents)).header(ContentType::PDF).body(PDF).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.post(format!("{}?title=D%C3%A9claration", documents)).header(admin()).header(ContentType::Plain).body(PDF).dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+        let response = client
+            .post(format!("{}?title=D%C3%A9claration%20d'int%C3%A9r%C3%AAts&visibility=public", documents))
+            .header(admin())
+            .header(ContentType::PDF)
+            .body(PDF)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let document: Document = response.into_json().expect("valid JSON");
+        assert_eq!((document.title.as_str(), document.size), ("Déclaration d'intérêts", PDF.len() as i64));
+
+        let response = client.get(format!("{}/{}", documents, document.id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PDF));
+        assert_eq!(response.into_bytes().as_deref(), Some(PDF));
+
+        let response = client.get(format!("{}/{}", documents, document.id)).header(Header::new("Range", "bytes=0-7")).dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.headers().get_one("Content-Range"), Some(format!("bytes 0-7/{}", PDF.len()).as_str()));
+        assert_eq!(response.into_bytes().as_deref(), Some(&b"%PDF-1.4"[..]));
+
+        let response = client.get(format!("{}/{}", documents, document.id)).header(Header::new("Range", "bytes=1000-")).dispatch();
+        assert_eq!(response.status(), Status::RangeNotSatisfiable);
+
+        let update = DocumentUpdate { visibility: Some(PRIVATE.to_string()), ..Default::default() };
+        let response = client.patch(format!("{}/{}", documents, document.id)).header(admin()).json(&update).dispatch();
+        assert_eq!(response.into_json::<Document>().map(|document| document.visibility), Some(PRIVATE.to_string()));
+
+        let response = client.get(format!("{}/{}", documents, document.id)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let listed: Vec<Document> = client.get(documents).dispatch().into_json().expect("valid JSON");
+        assert!(listed.is_empty());
+        let listed: Vec<Document> = client.get(documents).header(admin()).dispatch().into_json().expect("valid JSON");
+        assert_eq!(listed.len(), 1);
+
+        // Documents are scoped to their elu.
+        let response = client.get(format!("/elus/marie.martin@example.com/documents/{}", document.id)).header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.delete(format!("{}/{}", documents, document.id)).header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert!(!upload_dir.path().join("documents").join(document.id.to_string()).exists());
+        let response = client.get(format!("{}/{}", documents, document.id)).header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_upload_validation() {
+        let upload_dir = tempfile::tempdir().unwrap();
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("upload_dir", upload_dir.path())).merge(("uploads.max_document_size", 64)), connection);
+        let upload = |filename: &str, body: &[u8]| {
+            client
+                .post(format!("/elus/jean.dupont@example.com/documents?title=Mandat&filename={}", filename))
+                .header(admin())
+                .header(ContentType::PDF)
+                .body(body)
+                .dispatch()
+                .status()
+        };
+
+        assert_eq!(upload("mandat.pdf", PDF), Status::Created);
+        assert_eq!(upload("mandat.pdf", &[b'%'; 65]), Status::PayloadTooLarge);
+        assert_eq!(upload("mandat.pdf", b"MZ\x90\x00 not a PDF"), Status::UnsupportedMediaType);
+        assert_eq!(upload("mandat.exe", PDF), Status::UnsupportedMediaType);
+        assert_eq!(upload("..%2Fmandat.pdf", PDF), Status::UnprocessableEntity);
+
+        // Rejected uploads leave nothing behind.
+        let stored = std::fs::read_dir(upload_dir.path().join("documents")).unwrap().count();
+        assert_eq!(stored, 1);
+    }
+
+    #[test]
+    fn test_upload_fails_closed_without_scanner() {
+        let upload_dir = tempfile::tempdir().unwrap();
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let socket = upload_dir.path().join("missing.sock");
+        let client = build_client(|figment| figment.merge(("upload_dir", upload_dir.path())).merge(("uploads.clamd_socket", socket)), connection);
+
+        let response = client
+            .post("/elus/jean.dupont@example.com/documents?title=Mandat")
+            .header(admin())
+            .header(ContentType::PDF)
+            .body(PDF)
+            .dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+    }
+}
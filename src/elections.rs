@@ -0,0 +1,502 @@
+//! Elections and the mandates they renew. An election is recorded ahead of
+//! time with its date, scope and type; once it is marked `completed`, its
+//! results are applied with `POST /elections/<id>/transition`, taking a CSV
+//! file of the elected and their mandates. Incumbents of the scope holding
+//! one of those mandates who weren't reelected lose it, the elected gain
+//! it, those unknown to the directory being created, and the terms of the
+//! mandates are closed and opened on the election's date. With `dry_run`,
+//! the transition is only reported, to be reviewed before it's applied; an
+//! election is transitioned once.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::{Date, PrimitiveDateTime};
+
+use crate::auth::Admin;
+use crate::communes::split_delimited;
+use crate::config::AppConfig;
+use crate::db::{self, NewPerson, Person};
+use crate::email::Email;
+use crate::mandate_types::MandateTypes;
+use crate::person_name::PersonName;
+use crate::repository::{PersonFilter, PersonKey, PersonRepository};
+use crate::schema::{communes, elections};
+use crate::transaction::Transaction;
+use crate::{terms, timeouts, timestamp, DbConn};
+
+/// Types of elections.
+const KINDS: &[&str] = &["municipal", "departmental", "regional", "legislative", "senatorial", "european", "presidential"];
+
+pub const SCHEDULED: &str = "scheduled";
+pub const COMPLETED: &str = "completed";
+
+/// Where an election is held: `national`, `department:<code>` or
+/// `commune:<INSEE code>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope<'a> {
+    National,
+    Department(&'a str),
+    Commune(&'a str),
+}
+
+impl Scope<'_> {
+    fn parse(scope: &str) -> Option<Scope<'_>> {
+        match scope.split_once(':') {
+            None if scope == "national" => Some(Scope::National),
+            Some(("department", code)) if !code.is_empty() => Some(Scope::Department(code)),
+            Some(("commune", code)) if !code.is_empty() => Some(Scope::Commune(code)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = elections)]
+#[serde(crate = "rocket::serde")]
+pub struct Election {
+    pub id: i32,
+    #[serde(with = "timestamp::date")]
+    pub date: Date,
+    pub scope: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: String,
+    /// When the results were applied to the elus' mandates.
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub transitioned_at: Option<PrimitiveDateTime>,
+}
+
+fn scheduled() -> String {
+    SCHEDULED.to_string()
+}
+
+#[derive(Debug, Deserialize, Insertable, AsChangeset)]
+#[diesel(table_name = elections)]
+#[serde(crate = "rocket::serde")]
+pub struct NewElection {
+    #[serde(with = "timestamp::date")]
+    date: Date,
+    scope: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "scheduled")]
+    status: String,
+}
+
+impl NewElection {
+    fn check(&self, connection: &mut SqliteConnection) -> Result<(), Status> {
+        if !KINDS.contains(&self.kind.as_str()) || ![SCHEDULED, COMPLETED].contains(&self.status.as_str()) {
+            return Err(Status::UnprocessableEntity);
+        }
+        match Scope::parse(&self.scope).ok_or(Status::UnprocessableEntity)? {
+            Scope::Commune(code) => db::get_commune(code, connection).map(|_| ()).map_err(|_| Status::UnprocessableEntity),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn get_election(id: i32, connection: &mut SqliteConnection) -> Result<Election, Status> {
+    elections::table
+        .find(id)
+        .select(Election::as_select())
+        .first(connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)
+}
+
+/// Lists the elections, latest first, optionally only those with a status.
+#[get("/elections?<status>")]
+fn list_elections(status: Option<&str>, db: &State<DbConn>) -> Result<Json<Vec<Election>>, Status> {
+    let mut query = elections::table.order((elections::date.desc(), elections::id)).select(Election::as_select()).into_boxed();
+    if let Some(status) = status {
+        query = query.filter(elections::status.eq(status));
+    }
+
+    query.load(&mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[post("/elections", data = "<new_election>")]
+fn create_election(new_election: Json<NewElection>, _admin: Admin, db: &State<DbConn>) -> Result<Created<Json<Election>>, Status> {
+    let mut connection = db::lock(db)?;
+    new_election.check(&mut connection)?;
+    let election = diesel::insert_into(elections::table)
+        .values(&*new_election)
+        .returning(Election::as_returning())
+        .get_result(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let location = format!("/elections/{}", election.id);
+    Ok(Created::new(location).body(Json(election)))
+}
+
+#[get("/elections/<id>")]
+fn get_election_by_id(id: i32, db: &State<DbConn>) -> Result<Json<Election>, Status> {
+    get_election(id, &mut *db::lock(db)?).map(Json)
+}
+
+/// Replaces an election, typically to record it as completed. Once
+/// transitioned, it can no longer be changed.
+#[put("/elections/<id>", data = "<new_election>")]
+fn update_election(id: i32, new_election: Json<NewElection>, _admin: Admin, db: &State<DbConn>) -> Result<Json<Election>, Status> {
+    let mut connection = db::lock(db)?;
+    new_election.check(&mut connection)?;
+    if get_election(id, &mut connection)?.transitioned_at.is_some() {
+        return Err(Status::Conflict);
+    }
+    diesel::update(elections::table.find(id))
+        .set(&*new_election)
+        .returning(Election::as_returning())
+        .get_result(&mut *connection)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[delete("/elections/<id>")]
+fn delete_election(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let deleted = diesel::delete(elections::table.find(id))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// A row of the results file.
+#[derive(Debug, Clone, PartialEq)]
+struct Elected {
+    email: Email,
+    name: PersonName,
+    mandate: String,
+    commune_code: Option<String>,
+}
+
+/// The elected listed in a results file: comma-separated `email`, `name`,
+/// `mandate` (title or code) and optional `commune_code` columns, the
+/// commune defaulting to that of communal elections. Unknown columns are
+/// ignored.
+fn results(content: &str, scope: Scope, mandate_types: &MandateTypes) -> Result<Vec<Elected>, String> {
+    let mut lines = content.trim_start_matches('\u{feff}').lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<String> = split_delimited(lines.next().ok_or("empty file")?, ',').iter().map(|column| column.trim().to_string()).collect();
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let (email, name, mandate) = match (column("email"), column("name"), column("mandate")) {
+        (Some(email), Some(name), Some(mandate)) => (email, name, mandate),
+        _ => return Err("email, name and mandate columns are required".to_string()),
+    };
+    let commune_code = column("commune_code");
+
+    lines
+        .enumerate()
+        .map(|(index, line)| {
+            let fields = split_delimited(line, ',');
+            let field = |column: usize| fields.get(column).map(|value| value.trim()).filter(|value| !value.is_empty());
+            let row = index + 2;
+            Ok(Elected {
+                email: field(email).and_then(|email| email.parse().ok()).ok_or(format!("line {}: invalid email", row))?,
+                name: field(name).and_then(|name| name.parse().ok()).ok_or(format!("line {}: missing name", row))?,
+                mandate: mandate_types.title(field(mandate).ok_or(format!("line {}: missing mandate", row))?),
+                commune_code: commune_code.and_then(field).map(str::to_string).or_else(|| match scope {
+                    Scope::Commune(code) => Some(code.to_string()),
+                    _ => None,
+                }),
+            })
+        })
+        .collect()
+}
+
+/// The elus of the scope.
+fn incumbents(scope: Scope, db: &DbConn, repository: &dyn PersonRepository) -> Result<Vec<Person>, Status> {
+    match scope {
+        Scope::National => repository.list(),
+        Scope::Commune(code) => repository.search(&PersonFilter { commune_code: Some(code.to_string()), ..Default::default() }),
+        Scope::Department(department) => {
+            let codes: Vec<String> = communes::table
+                .filter(communes::department.eq(department))
+                .select(communes::code)
+                .load(&mut *db::lock(db)?)
+                .map_err(|_| Status::InternalServerError)?;
+            let persons = repository.list()?;
+            Ok(persons.into_iter().filter(|person| person.commune_code.as_ref().is_some_and(|code| codes.contains(code))).collect())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Change {
+    pub email: Email,
+    pub name: PersonName,
+    pub mandate: String,
+}
+
+/// What a transition does, or would do on a dry run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Transition {
+    /// Mandates lost by incumbents who weren't reelected.
+    pub closed: Vec<Change>,
+    /// Mandates kept by reelected incumbents.
+    pub renewed: Vec<Change>,
+    /// Mandates gained by the newly elected.
+    pub opened: Vec<Change>,
+    /// Emails of the elected who aren't in the directory yet.
+    pub created: Vec<Email>,
+    pub dry_run: bool,
+}
+
+/// An elu touched by a transition: as they are, if they exist, and once
+/// transitioned.
+struct Entry {
+    current: Option<Person>,
+    next: NewPerson,
+}
+
+fn edited(person: &Person) -> NewPerson {
+    NewPerson {
+        name: person.name.clone(),
+        email: person.email.clone(),
+        emails: person.emails.clone(),
+        mandates: person.mandates.clone(),
+        commune_code: person.commune_code.clone(),
+        office_address: person.office_address.clone(),
+        latitude: person.latitude,
+        longitude: person.longitude,
+        may_contact_by_email: person.may_contact_by_email,
+        consent_date: person.consent_date,
+        consent_source: person.consent_source.clone(),
+        visibility: person.visibility,
+        custom: person.custom.clone(),
+    }
+}
+
+/// The transition from the incumbents to the elected, and the elus it
+/// touches by email.
+fn plan(elected: &[Elected], incumbents: Vec<Person>, repository: &dyn PersonRepository) -> Result<(Transition, BTreeMap<Email, Entry>), Status> {
+    let mut renewed_mandates: Vec<&str> = elected.iter().map(|elected| elected.mandate.as_str()).collect();
+    renewed_mandates.sort_unstable();
+    renewed_mandates.dedup();
+    let mut transition = Transition::default();
+    let mut entries = BTreeMap::new();
+
+    for incumbent in incumbents {
+        for mandate in incumbent.mandates.iter().filter(|mandate| renewed_mandates.contains(&mandate.as_str())) {
+            if !elected.iter().any(|elected| elected.email == incumbent.email && elected.mandate == *mandate) {
+                transition.closed.push(Change { email: incumbent.email.clone(), name: incumbent.name.clone(), mandate: mandate.clone() });
+            }
+        }
+        let mut next = edited(&incumbent);
+        next.mandates.retain(|mandate| !transition.closed.iter().any(|closed| closed.email == incumbent.email && closed.mandate == *mandate));
+        entries.insert(incumbent.email.clone(), Entry { current: Some(incumbent), next });
+    }
+
+    for elected in elected {
+        if !entries.contains_key(&elected.email) {
+            let entry = match repository.find(&PersonKey::Email(elected.email.clone())) {
+                Ok(person) => Entry { next: edited(&person), current: Some(person) },
+                Err(status) if status == Status::NotFound => {
+                    transition.created.push(elected.email.clone());
+                    let next = NewPerson {
+                        name: elected.name.clone(),
+                        email: elected.email.clone(),
+                        emails: vec![],
+                        mandates: vec![],
+                        commune_code: elected.commune_code.clone(),
+                        office_address: None,
+                        latitude: None,
+                        longitude: None,
+                        may_contact_by_email: false,
+                        consent_date: None,
+                        consent_source: None,
+                        visibility: Default::default(),
+                        custom: Default::default(),
+                    };
+                    Entry { current: None, next }
+                }
+                Err(status) => return Err(status),
+            };
+            entries.insert(elected.email.clone(), entry);
+        }
+        let entry = entries.get_mut(&elected.email).expect("entry was just inserted");
+        let change = Change { email: elected.email.clone(), name: entry.next.name.clone(), mandate: elected.mandate.clone() };
+        if entry.current.as_ref().is_some_and(|person| person.mandates.contains(&elected.mandate)) {
+            transition.renewed.push(change);
+        } else if !entry.next.mandates.contains(&elected.mandate) {
+            entry.next.mandates.push(elected.mandate.clone());
+            transition.opened.push(change);
+        }
+    }
+
+    Ok((transition, entries))
+}
+
+/// Applies the planned transition, recording the terms of the mandates
+/// and that the election was transitioned.
+fn apply(election: &Election, transition: &Transition, entries: BTreeMap<Email, Entry>, db: &DbConn, repository: &dyn PersonRepository) -> Result<(), Status> {
+    let mut ids = BTreeMap::new();
+    let mut updates = vec![];
+    for (email, entry) in entries {
+        match entry.current {
+            Some(person) if person.mandates == entry.next.mandates => {
+                ids.insert(email, person.id);
+            }
+            Some(_) => updates.push((email, entry.next)),
+            None => {
+                let created = repository.create(entry.next)?;
+                ids.insert(email, created.id);
+            }
+        }
+    }
+    for updated in repository.update_many(updates)? {
+        ids.insert(updated.email, updated.id);
+    }
+
+    db::lock(db)?
+        .transaction(|connection| {
+            for closed in &transition.closed {
+                terms::close(ids[&closed.email], &closed.mandate, election.date, connection)?;
+            }
+            for opened in transition.renewed.iter().chain(&transition.opened) {
+                terms::open(ids[&opened.email], &opened.mandate, election.date, connection)?;
+            }
+            diesel::update(elections::table.find(election.id)).set(elections::transitioned_at.eq(timestamp::now())).execute(connection)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(())
+}
+
+/// Applies the results of a completed election, or only reports what that
+/// would do with `dry_run`, planning and applying it in one transaction.
+#[post("/elections/<id>/transition?<dry_run>", data = "<file>")]
+#[allow(clippy::too_many_arguments)]
+async fn transition(
+    id: i32,
+    dry_run: Option<bool>,
+    file: Data<'_>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    mandate_types: &State<MandateTypes>,
+    config: &State<AppConfig>,
+) -> Result<Json<Transition>, Status> {
+    let content = file.open(16.mebibytes()).into_string().await.map_err(|_| Status::BadRequest)?;
+    if !content.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let dry_run = dry_run.unwrap_or(false);
+    let (db, repository, mandate_types, retry) = (db.inner().clone(), repository.inner().clone(), mandate_types.inner().clone(), config.busy_retry.clone());
+    timeouts::blocking(config.timeouts.request(), move || {
+        let transaction = Transaction::begin_blocking(&db, &retry)?;
+        let election = get_election(id, &mut *db::lock(&db)?)?;
+        if election.status != COMPLETED || election.transitioned_at.is_some() {
+            return Err(Status::Conflict);
+        }
+        let scope = Scope::parse(&election.scope).ok_or(Status::InternalServerError)?;
+        let elected = results(&content, scope, &mandate_types).map_err(|_| Status::UnprocessableEntity)?;
+        let incumbents = incumbents(scope, &db, repository.as_ref())?;
+        let (mut transition, entries) = plan(&elected, incumbents, repository.as_ref())?;
+        if !dry_run {
+            apply(&election, &transition, entries, &db, repository.as_ref())?;
+        }
+        transaction.commit()?;
+        transition.dry_run = dry_run;
+        Ok(Json(transition))
+    })
+    .await
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_elections, create_election, get_election_by_id, update_election, delete_election, transition]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::http::ContentType;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_scope() {
+        assert_eq!(Scope::parse("national"), Some(Scope::National));
+        assert_eq!(Scope::parse("commune:75056"), Some(Scope::Commune("75056")));
+        assert_eq!(Scope::parse("department:2A"), Some(Scope::Department("2A")));
+        assert_eq!(Scope::parse("department:"), None);
+        assert_eq!(Scope::parse("region:11"), None);
+    }
+
+    #[test]
+    fn test_transition() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        db::upsert_communes(&[db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() }], &mut connection).unwrap();
+        let client = client(connection);
+
+        let election = json!({ "date": "2026-03-22", "scope": "commune:75056", "type": "municipal" });
+        assert_eq!(client.post("/elections").json(&election).dispatch().status(), Status::Unauthorized);
+        let response = client.post("/elections").header(admin()).json(&election).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let created: Election = response.into_json().unwrap();
+        assert_eq!(created.status, SCHEDULED);
+        let invalid = json!({ "date": "2026-03-22", "scope": "commune:99999", "type": "municipal" });
+        assert_eq!(client.post("/elections").header(admin()).json(&invalid).dispatch().status(), Status::UnprocessableEntity);
+
+        let uri = format!("/elections/{}/transition", created.id);
+        let results = "email,name,mandate\n\
+            marie.martin@example.com,Marie Martin,Maire\n\
+            jean.dupont@example.com,Jean Dupont,Conseiller régional\n\
+            lucie.bernard@example.com,Lucie Bernard,Conseiller municipal\n";
+        let response = client.post(&uri).header(admin()).header(ContentType::CSV).body(results).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let completed = json!({ "date": "2026-03-22", "scope": "commune:75056", "type": "municipal", "status": "completed" });
+        assert_eq!(client.put(format!("/elections/{}", created.id)).header(admin()).json(&completed).dispatch().status(), Status::Ok);
+        let dry_run: Transition = client.post(format!("{}?dry_run=true", uri)).header(admin()).body(results).dispatch().into_json().unwrap();
+        assert!(dry_run.dry_run);
+        let mandates = |transition: &[Change]| transition.iter().map(|change| (change.name.to_string(), change.mandate.clone())).collect::<Vec<_>>();
+        assert_eq!(mandates(&dry_run.closed), [("Jean Dupont".to_string(), "Maire".to_string())]);
+        assert_eq!(mandates(&dry_run.renewed), [("Jean Dupont".to_string(), "Conseiller régional".to_string())]);
+        assert_eq!(
+            mandates(&dry_run.opened),
+            [("Marie Martin".to_string(), "Maire".to_string()), ("Lucie Bernard".to_string(), "Conseiller municipal".to_string())]
+        );
+        assert_eq!(dry_run.created, ["lucie.bernard@example.com"]);
+        assert_eq!(client.get("/elus/lucie.bernard@example.com").dispatch().status(), Status::NotFound);
+
+        let applied: Transition = client.post(&uri).header(admin()).body(results).dispatch().into_json().unwrap();
+        assert!(!applied.dry_run);
+        let jean: crate::Person = client.get("/elus/jean.dupont@example.com").dispatch().into_json().unwrap();
+        assert_eq!(jean.mandates, ["Conseiller régional"]);
+        let marie: crate::Person = client.get("/elus/marie.martin@example.com").dispatch().into_json().unwrap();
+        assert_eq!(marie.mandates, ["Députée", "Maire"]);
+        let lucie: crate::Person = client.get("/elus/lucie.bernard@example.com").dispatch().into_json().unwrap();
+        assert_eq!((lucie.mandates, lucie.commune_code), (vec!["Conseiller municipal".to_string()], Some("75056".to_string())));
+        // Pierre Durand has no commune, so isn't an incumbent of Paris.
+        let pierre: crate::Person = client.get("/elus/pierre.durand@example.com").dispatch().into_json().unwrap();
+        assert_eq!(pierre.mandates, ["Sénateur", "Conseiller municipal"]);
+        let terms: Vec<terms::Term> = client.get("/elus/marie.martin@example.com/terms").dispatch().into_json().unwrap();
+        assert_eq!((terms[0].title.as_str(), terms[0].started_on), ("Maire", Some(time::macros::date!(2026 - 03 - 22))));
+
+        assert_eq!(client.post(&uri).header(admin()).body(results).dispatch().status(), Status::Conflict);
+        assert_eq!(client.put(format!("/elections/{}", created.id)).header(admin()).json(&completed).dispatch().status(), Status::Conflict);
+        let elections: Vec<Election> = client.get("/elections?status=completed").dispatch().into_json().unwrap();
+        assert!(elections[0].transitioned_at.is_some());
+    }
+
+    #[test]
+    fn test_results() {
+        let mandate_types = MandateTypes::default();
+        let elected = results("name,email,mandate\nMarie Martin,marie.martin@example.com,Maire\n", Scope::Commune("75056"), &mandate_types).unwrap();
+        assert_eq!(elected[0].commune_code.as_deref(), Some("75056"));
+        assert_eq!(results("email,name\n", Scope::National, &mandate_types), Err("email, name and mandate columns are required".to_string()));
+        assert_eq!(results("email,name,mandate\nnobody,Jean,Maire\n", Scope::National, &mandate_types), Err("line 2: invalid email".to_string()));
+    }
+}
@@ -0,0 +1,175 @@
+//! Email addresses of elus, checked and normalized wherever they come in:
+//! JSON bodies, path segments and `parse`. Surrounding whitespace is
+//! trimmed and the domain, which is case-insensitive, lowercased, so that
+//! `Jean.Dupont@Mairie.FR ` and `Jean.Dupont@mairie.fr` are the same
+//! address. Addresses read back from the database are trusted as stored.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteValue};
+use rocket::request::FromParam;
+use rocket::serde::json::Value;
+use rocket::serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::deliverability;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub struct Email(String);
+
+/// Why a string isn't an `Email`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidEmail;
+
+impl fmt::Display for InvalidEmail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("must be an email address")
+    }
+}
+
+impl Email {
+    pub fn parse(address: &str) -> Result<Email, InvalidEmail> {
+        let address = address.trim();
+        if !deliverability::is_valid_syntax(address) {
+            return Err(InvalidEmail);
+        }
+
+        let (local, domain) = address.rsplit_once('@').ok_or(InvalidEmail)?;
+        Ok(Email(format!("{}@{}", local, domain.to_ascii_lowercase())))
+    }
+
+    /// A value read back from storage, such as a sealed address, which is
+    /// trusted as it is.
+    pub fn stored(value: String) -> Email {
+        Email(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default()
+    }
+}
+
+/// The addresses of a serialized person: their `email` and `emails`.
+pub fn addresses_mut(person: &mut Value) -> Vec<&mut String> {
+    let Value::Object(object) = person else {
+        return Vec::new();
+    };
+    let mut addresses = Vec::new();
+    for (field, value) in object.iter_mut() {
+        match (field.as_str(), value) {
+            ("email", Value::String(address)) => addresses.push(address),
+            ("emails", Value::Array(emails)) => addresses.extend(emails.iter_mut().filter_map(|email| match email {
+                Value::String(address) => Some(address),
+                _ => None,
+            })),
+            _ => {}
+        }
+    }
+    addresses
+}
+
+/// For test fixtures which don't care about the address.
+#[cfg(test)]
+impl Default for Email {
+    fn default() -> Self {
+        Email("nobody@example.com".to_string())
+    }
+}
+
+impl FromStr for Email {
+    type Err = InvalidEmail;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        Email::parse(address)
+    }
+}
+
+impl Deref for Email {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Email {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Email {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Serialize for Email {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Email {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        Email::parse(&address).map_err(de::Error::custom)
+    }
+}
+
+impl<'a> FromParam<'a> for Email {
+    type Error = InvalidEmail;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        Email::parse(param)
+    }
+}
+
+impl ToSql<Text, Sqlite> for Email {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Email {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        <String as FromSql<Text, Sqlite>>::from_sql(value).map(Email)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Email::parse(" Jean.Dupont@Mairie.FR "), Ok(Email("Jean.Dupont@mairie.fr".to_string())));
+        assert_eq!("jean@example.com".parse::<Email>().map(|email| email.domain().to_string()), Ok("example.com".to_string()));
+        assert_eq!(Email::parse("jean"), Err(InvalidEmail));
+        assert_eq!(Email::parse("typo@@example.com"), Err(InvalidEmail));
+        assert_eq!(Email::from_param("jean@example.com%"), Err(InvalidEmail));
+    }
+
+    #[test]
+    fn test_serde() {
+        let email: Email = serde_json::from_str("\"jean@EXAMPLE.com\"").unwrap();
+        assert_eq!(serde_json::to_string(&email).unwrap(), "\"jean@example.com\"");
+        assert_eq!(serde_json::from_str::<Email>("\"jean\"").unwrap_err().to_string(), "must be an email address");
+    }
+}
@@ -0,0 +1,144 @@
+//! Widget commune websites embed in an iframe to list their elus without
+//! writing any JavaScript: `GET /embed/elus` takes the criteria of
+//! `GET /elus` and answers with a self-contained HTML page, its styles
+//! inline and no script, which its Content-Security-Policy enforces while
+//! allowing any site to frame it. Elus are shown as the API would show
+//! them to the caller, which in an iframe is anonymous.
+
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::content::RawHtml;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Value;
+use rocket::State;
+
+use crate::criteria::Filters;
+use crate::dashboard::escape;
+use crate::pagination::{self, Pagination, SortSpec};
+use crate::problem::Problem;
+use crate::redaction::redact;
+use crate::repository::{Page, PersonSort};
+use crate::settings::{self, Settings};
+use crate::visibility::Visible;
+use crate::{db, DbConn, Person};
+
+/// Elus listed when the embedding page doesn't page them.
+const DEFAULT_LIMIT: i64 = 50;
+
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'; frame-ancestors *";
+
+const STYLE: &str = "body{margin:0;font:14px/1.4 system-ui,sans-serif;color:#1e1e1e;background:transparent}\
+h2{font-size:16px;margin:0 0 8px}ul{list-style:none;margin:0;padding:0}\
+li{padding:6px 0;border-bottom:1px solid #e5e5e5}li:last-child{border-bottom:0}\
+a{color:#000091;font-weight:600;text-decoration:none}a:hover{text-decoration:underline}\
+.mandates{display:block;color:#555}.empty{color:#555}";
+
+/// The widget listing the elus, redacted persons, of the commune the
+/// settings are those of, if any.
+fn widget(title: Option<&str>, persons: &[Value], settings: Option<&Settings>) -> String {
+    let mut body = title.map(|title| format!("<h2>{}</h2>\n", escape(title))).unwrap_or_default();
+    if persons.is_empty() {
+        body.push_str("<p class=\"empty\">Aucun élu.</p>\n");
+    } else {
+        body.push_str("<ul>\n");
+        for person in persons {
+            let mandates: Vec<&str> = person["mandates"].as_array().map(|mandates| mandates.iter().filter_map(Value::as_str).collect()).unwrap_or_default();
+            body.push_str(&format!(
+                "<li><a href=\"/elus/{}\" target=\"_blank\" rel=\"noopener\">{}</a><span class=\"mandates\">{}</span></li>\n",
+                escape(person["uuid"].as_str().unwrap_or_default()),
+                escape(person["name"].as_str().unwrap_or_default()),
+                escape(&mandates.join(", "))
+            ));
+        }
+        body.push_str("</ul>\n");
+    }
+    body.push_str(&settings.map(settings::footer).unwrap_or_default());
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <style>{}</style></head>\n<body>\n{}</body>\n</html>\n",
+        escape(settings::lang(settings)),
+        STYLE,
+        body
+    )
+}
+
+pub struct Widget {
+    title: Option<String>,
+    persons: Vec<Person>,
+    settings: Option<Settings>,
+}
+
+impl<'r> Responder<'r, 'static> for Widget {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let persons = redact(&self.persons, request)?;
+        let persons = persons.as_array().map(Vec::as_slice).unwrap_or_default();
+        let mut response = RawHtml(widget(self.title.as_deref(), persons, self.settings.as_ref())).respond_to(request)?;
+        response.set_header(Header::new("Content-Security-Policy", CONTENT_SECURITY_POLICY));
+        response.set_header(Header::new("X-Content-Type-Options", "nosniff"));
+        response.set_header(Header::new("Referrer-Policy", "no-referrer"));
+        response.set_header(Header::new("Cache-Control", "public, max-age=300"));
+        Ok(response)
+    }
+}
+
+/// Lists the elus matching the criteria, by name unless sorted otherwise
+/// and paged as `GET /elus` does, with an optional title, which is the
+/// commune's display name by default when there's one.
+#[get("/embed/elus?<title>")]
+fn embed_elus(title: Option<String>, filters: Filters, pagination: Pagination, sort: SortSpec<PersonSort>, db: &State<DbConn>, repository: Visible) -> Result<Widget, Problem> {
+    let settings = match &filters.0.commune_code {
+        Some(code) => settings::find(code, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?,
+        None => None,
+    };
+    let title = title.or_else(|| settings.as_ref().map(|settings| settings.display_name.clone()));
+    let page = pagination.0.unwrap_or(Page { limit: DEFAULT_LIMIT, ..Default::default() });
+    let (persons, _) = pagination::fetch(repository.as_ref(), &filters.0, Page { sort, ..page })?;
+
+    Ok(Widget { title, persons: persons.into_iter().map(Person::from).collect(), settings })
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![embed_elus]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, insert_test_persons, setup_test_db};
+    use rocket::http::ContentType;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_widget() {
+        let persons = [json!({ "uuid": "abc", "name": "Jean <Dupont>", "mandates": ["Maire", "Conseiller régional"] })];
+        let html = widget(Some("Vos élus"), &persons, None);
+        assert!(html.contains("<h2>Vos élus</h2>"));
+        assert!(html.contains("<a href=\"/elus/abc\" target=\"_blank\" rel=\"noopener\">Jean &lt;Dupont&gt;</a><span class=\"mandates\">Maire, Conseiller régional</span>"));
+        assert!(!html.contains("<script"));
+        assert!(widget(None, &[], None).contains("Aucun élu."));
+    }
+
+    #[test]
+    fn test_embed_elus() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/embed/elus?mandate=Maire&title=Le%20maire").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        assert_eq!(response.headers().get_one("Content-Security-Policy"), Some(CONTENT_SECURITY_POLICY));
+        let html = response.into_string().unwrap();
+        assert!(html.contains("<h2>Le maire</h2>"));
+        assert_eq!(html.matches("<li>").count(), 1);
+        assert!(html.contains("Jean Dupont"));
+
+        let html = client.get("/embed/elus?limit=2").dispatch().into_string().unwrap();
+        assert_eq!(html.matches("<li>").count(), 2);
+        let html = client.get("/embed/elus?sort=-name&page=2&per_page=2").dispatch().into_string().unwrap();
+        assert_eq!(html.matches("<li>").count(), 1);
+        assert!(html.contains("Jean Dupont"));
+        assert_eq!(client.get("/embed/elus?limit=0").dispatch().status(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,249 @@
+//! Encryption at rest of the elus' email addresses, so that a copy of the
+//! database file doesn't hand out their contact details. Addresses are
+//! sealed with AES-SIV (RFC 5297), a deterministic authenticated cipher:
+//! the same address always seals to the same value, which keeps lookups,
+//! uniqueness and aliases working in SQL. Sealed values are
+//! prefixed, telling them apart from addresses stored before encryption
+//! was turned on, which `encrypt_existing` seals at startup.
+//!
+//! The other tables holding addresses are sealed by a `Sealer`: the emails
+//! of the persons in the change log and the sync queue, the recipients of
+//! the mail queue, and the files of import jobs along with the records
+//! they rejected. Webhook deliveries only refer to change events.
+//!
+//! Elus have no phone number to seal: the model stores none.
+
+use std::sync::Arc;
+
+use aes_siv::siv::Aes128Siv;
+use aes_siv::KeyInit;
+use base64::prelude::{Engine as _, BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::email::{self, Email};
+use crate::schema::{elu_emails, elus, email_aliases, events, jobs, mail_queue, sync_queue};
+
+const PREFIX: &str = "enc1:";
+/// Values are sealed without associated data.
+const NO_HEADERS: [&[u8]; 0] = [];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EncryptionConfig {
+    /// Base64 of the 32-byte key, as written to the config file or
+    /// provided by a KMS through `ROCKET_ENCRYPTION={key="..."}`.
+    pub key: String,
+}
+
+#[derive(Clone)]
+pub struct Cipher {
+    /// The AES-SIV key: its first half keys the CMAC deriving the IV, its
+    /// second half the AES-CTR encryption.
+    key: [u8; 32],
+}
+
+/// Why a stored value couldn't be opened: tampered with, or sealed with
+/// another key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undecryptable;
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Cipher { key: *key }
+    }
+
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self, String> {
+        let key = BASE64_STANDARD.decode(config.key.trim()).map_err(|_| "encryption.key must be base64")?;
+        let key: [u8; 32] = key.try_into().map_err(|key: Vec<u8>| format!("encryption.key must be 32 bytes, not {}", key.len()))?;
+        Ok(Cipher::new(&key))
+    }
+
+    fn siv(&self) -> Aes128Siv {
+        Aes128Siv::new(&self.key.into())
+    }
+
+    pub fn seal(&self, plaintext: &str) -> String {
+        let sealed = self.siv().encrypt(NO_HEADERS, plaintext.as_bytes()).expect("sealing takes no headers");
+        format!("{}{}", PREFIX, BASE64_URL_SAFE_NO_PAD.encode(&sealed))
+    }
+
+    /// The plaintext of a sealed value; values stored unsealed are returned
+    /// as they are.
+    pub fn open(&self, stored: &str) -> Result<String, Undecryptable> {
+        let Some(encoded) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let sealed = BASE64_URL_SAFE_NO_PAD.decode(encoded).map_err(|_| Undecryptable)?;
+        let data = self.siv().decrypt(NO_HEADERS, &sealed).map_err(|_| Undecryptable)?;
+        String::from_utf8(data).map_err(|_| Undecryptable)
+    }
+
+    pub fn seal_email(&self, email: &Email) -> Email {
+        Email::stored(self.seal(email))
+    }
+
+    pub fn open_email(&self, email: &Email) -> Result<Email, Undecryptable> {
+        self.open(email).map(Email::stored)
+    }
+}
+
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+/// Seals what the other tables store, when encryption is on; without a
+/// key, values are stored and read as they are.
+#[derive(Clone, Default)]
+pub struct Sealer(Option<Arc<Cipher>>);
+
+impl Sealer {
+    pub fn new(cipher: Cipher) -> Self {
+        Sealer(Some(Arc::new(cipher)))
+    }
+
+    pub fn seal(&self, text: &str) -> String {
+        match &self.0 {
+            Some(cipher) if !is_sealed(text) => cipher.seal(text),
+            _ => text.to_string(),
+        }
+    }
+
+    pub fn open(&self, stored: &str) -> Result<String, Undecryptable> {
+        match &self.0 {
+            Some(cipher) => cipher.open(stored),
+            None => Ok(stored.to_string()),
+        }
+    }
+
+    /// The JSON of `person`, to be stored, with their emails sealed.
+    pub fn seal_person<T: Serialize>(&self, person: &T) -> String {
+        let mut person = serde_json::to_value(person).expect("persons serialize to JSON");
+        for address in email::addresses_mut(&mut person) {
+            *address = self.seal(address);
+        }
+        person.to_string()
+    }
+
+    /// A person stored by `seal_person`, with their emails in the clear;
+    /// `Null` if the JSON is invalid.
+    pub fn open_person(&self, stored: &str) -> Result<Value, Undecryptable> {
+        let mut person = serde_json::from_str(stored).unwrap_or(Value::Null);
+        for address in email::addresses_mut(&mut person) {
+            *address = self.open(address)?;
+        }
+        Ok(person)
+    }
+}
+
+/// Seals the addresses stored before encryption was turned on, returning
+/// how many elus had theirs sealed. Fails if the addresses already sealed
+/// don't open with this key, rather than mixing keys.
+pub fn encrypt_existing(cipher: &Cipher, connection: &mut SqliteConnection) -> Result<usize, String> {
+    connection
+        .transaction(|connection| {
+            let stored: Vec<(i32, Email)> = elus::table.select((elus::id, elus::email)).load(connection)?;
+            if let Some((id, email)) = stored.iter().find(|(_, email)| is_sealed(email)) {
+                if cipher.open_email(email).is_err() {
+                    return Ok(Err(format!("the email of elu {} doesn't decrypt with encryption.key", id)));
+                }
+            }
+
+            let mut sealed = 0;
+            for (id, email) in stored.iter().filter(|(_, email)| !is_sealed(email)) {
+                diesel::update(elus::table.find(id)).set(elus::email.eq(cipher.seal_email(email))).execute(connection)?;
+                sealed += 1;
+            }
+            let aliases: Vec<Email> = email_aliases::table.select(email_aliases::email).load(connection)?;
+            for alias in aliases.iter().filter(|alias| !is_sealed(alias)) {
+                diesel::update(email_aliases::table.find(alias)).set(email_aliases::email.eq(cipher.seal_email(alias))).execute(connection)?;
+            }
+            let addresses: Vec<Email> = elu_emails::table.select(elu_emails::email).load(connection)?;
+            for address in addresses.iter().filter(|address| !is_sealed(address)) {
+                diesel::update(elu_emails::table.find(address)).set(elu_emails::email.eq(cipher.seal_email(address))).execute(connection)?;
+            }
+            seal_others(&Sealer::new(cipher.clone()), connection)?;
+            QueryResult::Ok(Ok(sealed))
+        })
+        .map_err(|e| format!("could not encrypt the stored emails: {}", e))?
+}
+
+/// Seals the values of the other tables stored before encryption was
+/// turned on; those already sealed are left as they are.
+fn seal_others(sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<()> {
+    let seal_person = |payload: &str| serde_json::from_str::<Value>(payload).ok().map(|person| sealer.seal_person(&person));
+
+    let payloads: Vec<(i64, String)> = events::table.select((events::seq, events::payload)).load(connection)?;
+    for (seq, payload) in payloads {
+        if let Some(sealed) = seal_person(&payload).filter(|sealed| *sealed != payload) {
+            diesel::update(events::table.find(seq)).set(events::payload.eq(sealed)).execute(connection)?;
+        }
+    }
+    let payloads: Vec<(i32, String)> = sync_queue::table.select((sync_queue::id, sync_queue::payload)).load(connection)?;
+    for (id, payload) in payloads {
+        if let Some(sealed) = seal_person(&payload).filter(|sealed| *sealed != payload) {
+            diesel::update(sync_queue::table.find(id)).set(sync_queue::payload.eq(sealed)).execute(connection)?;
+        }
+    }
+    let mails: Vec<(i32, String, String)> = mail_queue::table.select((mail_queue::id, mail_queue::recipient, mail_queue::body)).load(connection)?;
+    for (id, recipient, body) in mails.iter().filter(|(_, recipient, body)| !is_sealed(recipient) || !is_sealed(body)) {
+        diesel::update(mail_queue::table.find(id)).set((mail_queue::recipient.eq(sealer.seal(recipient)), mail_queue::body.eq(sealer.seal(body)))).execute(connection)?;
+    }
+    let files: Vec<(i32, String, Option<String>)> = jobs::table.select((jobs::id, jobs::input, jobs::rejection_report)).load(connection)?;
+    for (id, input, report) in files.iter().filter(|(_, input, report)| !is_sealed(input) || report.as_deref().is_some_and(|report| !is_sealed(report))) {
+        diesel::update(jobs::table.find(id))
+            .set((jobs::input.eq(sealer.seal(input)), jobs::rejection_report.eq(report.as_deref().map(|report| sealer.seal(report)))))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_seal_open() {
+        let cipher = Cipher::new(&[7; 32]);
+        let sealed = cipher.seal("jean.dupont@example.com");
+        assert!(is_sealed(&sealed) && !sealed.contains("dupont"));
+        assert_eq!(cipher.seal("jean.dupont@example.com"), sealed);
+        assert_ne!(cipher.seal("jean.dupont@example.org"), sealed);
+        assert_eq!(cipher.open(&sealed).as_deref(), Ok("jean.dupont@example.com"));
+        assert_eq!(cipher.open("jean@example.com").as_deref(), Ok("jean@example.com"));
+
+        assert_eq!(Cipher::new(&[8; 32]).open(&sealed), Err(Undecryptable));
+        assert_eq!(cipher.open("enc1:AAAA"), Err(Undecryptable));
+        let mut tampered = sealed.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        assert_eq!(cipher.open(std::str::from_utf8(&tampered).unwrap()), Err(Undecryptable));
+    }
+
+    #[test]
+    fn test_encrypt_existing() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let cipher = Cipher::new(&[7; 32]);
+
+        let person = crate::db::insert_person(&crate::db::NewPerson { name: "Luc Soleil".parse().unwrap(), email: "luc@example.com".parse().unwrap(), ..Default::default() }, &mut connection).unwrap();
+        crate::events::record(crate::events::ChangeKind::Created, &person, &Sealer::default(), &mut connection).unwrap();
+        let message = crate::mail::Message { to: "luc@example.com".to_string(), subject: "Bonjour".to_string(), body: "...".to_string() };
+        crate::mail_queue::enqueue(&message, None, &Sealer::default(), &mut connection).unwrap();
+
+        assert_eq!(encrypt_existing(&cipher, &mut connection), Ok(4));
+        assert_eq!(encrypt_existing(&cipher, &mut connection), Ok(0));
+        let stored: Vec<Email> = elus::table.select(elus::email).load(&mut connection).unwrap();
+        assert!(stored.iter().all(|email| is_sealed(email)));
+        let payload: String = events::table.select(events::payload).first(&mut connection).unwrap();
+        assert!(payload.contains("Luc Soleil") && !payload.contains("luc@example.com"));
+        assert_eq!(Sealer::new(cipher.clone()).open_person(&payload).unwrap()["email"], "luc@example.com");
+        let recipient: String = mail_queue::table.select(mail_queue::recipient).first(&mut connection).unwrap();
+        assert_eq!(cipher.open(&recipient).as_deref(), Ok("luc@example.com"));
+        assert!(encrypt_existing(&Cipher::new(&[8; 32]), &mut connection).is_err());
+    }
+}
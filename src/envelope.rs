@@ -0,0 +1,82 @@
+//! Optional `{ "data": ..., "meta": ... }` envelope around successful JSON
+//! responses, for client frameworks expecting one. Pages of a listing
+//! carry their `next_cursor` in `meta` instead of next to the results.
+
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::StatusClass;
+use rocket::serde::json::{json, Value};
+use rocket::{Request, Response};
+
+/// Fairing wrapping the bodies, attached when `envelope` is set.
+pub struct Envelope;
+
+/// The envelope around a response body.
+fn wrap(body: Value) -> Value {
+    match body {
+        Value::Object(mut fields) if fields.len() == 2 && fields.contains_key("next_cursor") => {
+            let next_cursor = fields.remove("next_cursor");
+            let data = fields.into_iter().next().map(|(_, results)| results);
+            json!({ "data": data, "meta": { "next_cursor": next_cursor } })
+        }
+        data => json!({ "data": data, "meta": {} }),
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Envelope {
+    fn info(&self) -> Info {
+        Info { name: "Response envelope", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status().class() != StatusClass::Success || !response.content_type().is_some_and(|content_type| content_type.is_json()) {
+            return;
+        }
+
+        let Ok(body) = response.body_mut().to_string().await else {
+            return;
+        };
+        let body = match serde_json::from_str(&body) {
+            Ok(value) => wrap(value).to_string(),
+            Err(_) => body,
+        };
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_client, insert_test_persons, setup_test_db};
+    use rocket::http::Status;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap(json!([1, 2])), json!({ "data": [1, 2], "meta": {} }));
+        assert_eq!(
+            wrap(json!({ "elus": [1], "next_cursor": "abc" })),
+            json!({ "data": [1], "meta": { "next_cursor": "abc" } })
+        );
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("envelope", true)), connection);
+
+        let body: Value = client.get("/elus?limit=2").dispatch().into_json().unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        assert!(body["meta"]["next_cursor"].is_string());
+
+        let body: Value = client.get("/elus/1").dispatch().into_json().unwrap();
+        assert_eq!(body["data"]["name"], "Jean Dupont");
+        assert_eq!(body["meta"], json!({}));
+
+        let response = client.get("/elus/nobody@example.com").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert!(response.into_json::<Value>().is_none_or(|body| body.get("data").is_none()));
+    }
+}
@@ -0,0 +1,375 @@
+//! Reporting of server errors: every 5xx response, including those of
+//! handlers which panicked, is reported with its request's context (route,
+//! request ID and JSON payload, credentials removed) to the backend of the
+//! `error_reporting` configuration: the log, a self-hosted Sentry or any
+//! HTTP endpoint accepting the report as JSON. Only plain HTTP backends
+//! are supported. Reports are sent in the background, and lost if the
+//! backend can't be reached.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once, OnceLock};
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method};
+use rocket::data::Data;
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::serde::json::{json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::task;
+use rocket::{Build, Request, Response, Rocket};
+use time::PrimitiveDateTime;
+
+use crate::config::AppConfig;
+use crate::sha256::hex;
+use crate::{request_id, timestamp};
+
+/// Bytes of a JSON body kept for the report; mostly enough for the bodies
+/// of this API.
+const PAYLOAD_LIMIT: usize = 512;
+
+/// Payload properties whose values aren't reported.
+const SENSITIVE: &[&str] = &["password", "secret", "token", "totp", "api_key"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "backend", rename_all = "lowercase")]
+pub enum ReportingConfig {
+    /// Errors are logged.
+    #[default]
+    Log,
+    Sentry(SentryConfig),
+    Http(HttpReportingConfig),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SentryConfig {
+    /// DSN of the Sentry project, `http://<public key>@<host>/<project id>`.
+    pub dsn: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HttpReportingConfig {
+    /// URL the reports are POSTed to.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorReport {
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    /// Path of the route which handled the request, if one did.
+    pub route: Option<String>,
+    pub status: u16,
+    /// What the handler panicked with, if it did.
+    pub panic: Option<String>,
+    /// The JSON body of the request, without credentials.
+    pub payload: Option<Value>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub at: PrimitiveDateTime,
+}
+
+#[rocket::async_trait]
+pub trait ErrorReporter: Send + Sync {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String>;
+}
+
+pub struct LogReporter;
+
+#[rocket::async_trait]
+impl ErrorReporter for LogReporter {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        log::error!(
+            "{} {} answered {} (request {}){}",
+            report.method,
+            report.path,
+            report.status,
+            report.request_id,
+            report.panic.as_ref().map(|panic| format!(": {}", panic)).unwrap_or_default()
+        );
+        Ok(())
+    }
+}
+
+async fn post(client: &Client<HttpConnector, Body>, url: &str, headers: &[(&str, &str)], body: Value) -> Result<(), String> {
+    let mut request = hyper::Request::builder().method(Method::POST).uri(url).header("content-type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request.body(Body::from(body.to_string())).map_err(|e| format!("invalid URL: {}", e))?;
+
+    match client.request(request).await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("error reporting backend returned {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// POSTs the reports as they are.
+pub struct HttpReporter {
+    url: String,
+    client: Client<HttpConnector, Body>,
+}
+
+impl HttpReporter {
+    pub fn new(config: &HttpReportingConfig) -> Self {
+        HttpReporter { url: config.url.clone(), client: Client::new() }
+    }
+}
+
+#[rocket::async_trait]
+impl ErrorReporter for HttpReporter {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        post(&self.client, &self.url, &[], json!(report)).await
+    }
+}
+
+/// Sends the reports as events to Sentry's store endpoint.
+pub struct SentryReporter {
+    store_url: String,
+    auth: String,
+    client: Client<HttpConnector, Body>,
+}
+
+impl SentryReporter {
+    pub fn new(config: &SentryConfig) -> Result<Self, String> {
+        let invalid = || format!("invalid Sentry DSN {}", config.dsn);
+        let (scheme, rest) = config.dsn.split_once("://").ok_or_else(invalid)?;
+        let (key, rest) = rest.split_once('@').ok_or_else(invalid)?;
+        let (host, project) = rest.rsplit_once('/').ok_or_else(invalid)?;
+        let key = key.split(':').next().unwrap_or_default();
+        if key.is_empty() || host.is_empty() || project.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(SentryReporter {
+            store_url: format!("{}://{}/api/{}/store/", scheme, host, project),
+            auth: format!("Sentry sentry_version=7, sentry_key={}, sentry_client=rckd/{}", key, env!("CARGO_PKG_VERSION")),
+            client: Client::new(),
+        })
+    }
+}
+
+/// The Sentry event of a report.
+fn sentry_event(report: &ErrorReport) -> Value {
+    let message = match &report.panic {
+        Some(panic) => format!("Handler panicked: {}", panic),
+        None => format!("{} {} answered {}", report.method, report.path, report.status),
+    };
+
+    json!({
+        "event_id": hex(&rand::random::<[u8; 16]>()),
+        "timestamp": report.at.assume_utc().unix_timestamp(),
+        "platform": "other",
+        "level": if report.panic.is_some() { "fatal" } else { "error" },
+        "logger": "rckd",
+        "release": concat!("rckd@", env!("CARGO_PKG_VERSION")),
+        "transaction": report.route.as_deref().unwrap_or(&report.path),
+        "message": { "formatted": message },
+        "request": {
+            "method": report.method,
+            "url": report.path,
+            "data": report.payload,
+            "headers": { request_id::HEADER: report.request_id },
+        },
+        "tags": { "request_id": report.request_id, "status": report.status.to_string() },
+    })
+}
+
+#[rocket::async_trait]
+impl ErrorReporter for SentryReporter {
+    async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+        post(&self.client, &self.store_url, &[("x-sentry-auth", &self.auth)], sentry_event(report)).await
+    }
+}
+
+pub fn from_config(config: &AppConfig) -> Arc<dyn ErrorReporter> {
+    match &config.error_reporting {
+        ReportingConfig::Log => Arc::new(LogReporter),
+        ReportingConfig::Sentry(sentry) => Arc::new(SentryReporter::new(sentry).expect("invalid configuration")),
+        ReportingConfig::Http(http) => Arc::new(HttpReporter::new(http)),
+    }
+}
+
+/// Replaces the values of sensitive properties, at any depth.
+fn sanitize(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (property, value) in object {
+                let property = property.to_ascii_lowercase();
+                if SENSITIVE.iter().any(|sensitive| property.contains(sensitive)) {
+                    *value = json!("[removed]");
+                } else {
+                    sanitize(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sanitize),
+        _ => {}
+    }
+}
+
+/// What the handler each task is running panicked with.
+fn panics() -> &'static Mutex<HashMap<task::Id, String>> {
+    static PANICS: OnceLock<Mutex<HashMap<task::Id, String>>> = OnceLock::new();
+    PANICS.get_or_init(Default::default)
+}
+
+/// Keeps the messages of panics happening in tasks, for the report of the
+/// response the task then makes.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Some(id) = task::try_id() {
+                panics().lock().unwrap().insert(id, info.to_string());
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// The sanitized JSON body of the request, until it's answered.
+struct Payload(Option<Value>);
+
+/// Fairing reporting the server errors to the managed `ErrorReporter`.
+pub struct ErrorReporting;
+
+#[rocket::async_trait]
+impl Fairing for ErrorReporting {
+    fn info(&self) -> Info {
+        Info { name: "Error reporting", kind: Kind::Ignite | Kind::Request | Kind::Response }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        install_panic_hook();
+        Ok(rocket)
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if !request.content_type().is_some_and(|content_type| content_type.is_json()) {
+            return;
+        }
+
+        let peeked = data.peek(PAYLOAD_LIMIT).await;
+        let payload = match serde_json::from_slice::<Value>(peeked) {
+            Ok(mut payload) => {
+                sanitize(&mut payload);
+                payload
+            }
+            Err(_) if !data.peek_complete() => json!(format!("[more than {} bytes]", PAYLOAD_LIMIT)),
+            Err(_) => json!("[invalid JSON]"),
+        };
+        request.local_cache(|| Payload(Some(payload)));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let panic = task::try_id().and_then(|id| panics().lock().unwrap().remove(&id));
+        if response.status().code < 500 {
+            return;
+        }
+        let Some(reporter) = request.rocket().state::<Arc<dyn ErrorReporter>>().cloned() else {
+            return;
+        };
+
+        let report = ErrorReport {
+            request_id: request_id::of(request).to_string(),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            route: request.route().map(|route| route.uri.origin.path().to_string()),
+            status: response.status().code,
+            panic,
+            payload: request.local_cache(|| Payload(None)).0.clone(),
+            at: timestamp::now(),
+        };
+        rocket::tokio::spawn(async move {
+            if let Err(e) = reporter.report(&report).await {
+                log::warn!("Could not report the error of request {}: {}", report.request_id, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::serve_once;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client as LocalClient;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    struct ChannelReporter(Mutex<mpsc::Sender<ErrorReport>>);
+
+    #[rocket::async_trait]
+    impl ErrorReporter for ChannelReporter {
+        async fn report(&self, report: &ErrorReport) -> Result<(), String> {
+            self.0.lock().unwrap().send(report.clone()).map_err(|e| e.to_string())
+        }
+    }
+
+    #[post("/fail", data = "<_body>")]
+    fn fail(_body: &str) -> Status {
+        Status::ServiceUnavailable
+    }
+
+    #[get("/panic")]
+    fn panicking() -> &'static str {
+        panic!("oh no")
+    }
+
+    #[rocket::async_test]
+    async fn test_reports_server_errors() {
+        let (sender, reports) = mpsc::channel();
+        let reporter: Arc<dyn ErrorReporter> = Arc::new(ChannelReporter(Mutex::new(sender)));
+        let rocket = rocket::build().manage(reporter).attach(ErrorReporting).mount("/", routes![fail, panicking]);
+        let client = LocalClient::untracked(rocket).await.unwrap();
+
+        let body = r#"{ "email": "jean@example.com", "password": "hunter2", "tokens": [{ "api_key": "k" }] }"#;
+        let status = client.post("/fail").header(ContentType::JSON).body(body).dispatch().await.status();
+        assert_eq!(status, Status::ServiceUnavailable);
+        let report = reports.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((report.method.as_str(), report.route.as_deref(), report.status), ("POST", Some("/fail"), 503));
+        assert_eq!(report.payload, Some(json!({ "email": "jean@example.com", "password": "[removed]", "tokens": "[removed]" })));
+        assert_eq!(report.panic, None);
+        assert_eq!(report.request_id.len(), 32);
+
+        // Handlers only run in a task of their own when served.
+        let status = rocket::tokio::spawn(async move { client.get("/panic").dispatch().await.status() }).await.unwrap();
+        assert_eq!(status, Status::InternalServerError);
+        let report = reports.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(report.panic.unwrap().contains("oh no"));
+        assert_eq!(report.payload, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_sentry_reporter() {
+        assert!(SentryReporter::new(&SentryConfig { dsn: "http://sentry.example/1".to_string() }).is_err());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dsn = format!("http://abc123@{}/42", listener.local_addr().unwrap());
+        let server = serve_once(listener, "200 OK");
+        let reporter = SentryReporter::new(&SentryConfig { dsn }).unwrap();
+        let report = ErrorReport {
+            request_id: "proxy-42".to_string(),
+            method: "GET".to_string(),
+            path: "/elus/1".to_string(),
+            route: Some("/elus/<key>".to_string()),
+            status: 500,
+            panic: None,
+            payload: None,
+            at: timestamp::now(),
+        };
+        assert_eq!(reporter.report(&report).await, Ok(()));
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /api/42/store/ HTTP/1.1"));
+        assert!(head.to_ascii_lowercase().contains("x-sentry-auth: sentry sentry_version=7, sentry_key=abc123"));
+        let event: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(event["transaction"], "/elus/<key>");
+        assert_eq!(event["tags"]["request_id"], "proxy-42");
+    }
+}
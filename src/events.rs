@@ -0,0 +1,361 @@
+//! Change data capture: every person created, updated or deleted is
+//! appended to the `events` table under an increasing sequence number,
+//! which external systems replicate from with `GET /events?since=<seq>`.
+//! The same changes feed the sync queue.
+//!
+//! The table is an outbox: with the SQLite backend, events are written in
+//! the transaction making the change, so there are neither lost events for
+//! changes made nor phantom events for changes rolled back, and the
+//! dispatchers (webhooks, NATS) deliver from it at their own pace, picking
+//! up where they left off after a crash.
+
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::db::{self, NewPerson, Person};
+use crate::deliverability::EmailStatus;
+use crate::email::Email;
+use crate::encryption::Sealer;
+use crate::repository::{Page, PersonFilter, PersonKey, PersonRepository};
+use crate::schema::{event_cursors, events};
+use crate::sync::{self, SyncTarget};
+use crate::{timestamp, DbConn};
+
+/// Events returned by `GET /events` when no limit is given, and at most.
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Deleted => "deleted",
+        }
+    }
+}
+
+impl std::str::FromStr for ChangeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(ChangeKind::Created),
+            "updated" => Ok(ChangeKind::Updated),
+            "deleted" => Ok(ChangeKind::Deleted),
+            _ => Err(format!("unknown change kind {:?}", s)),
+        }
+    }
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = events)]
+struct EventRow {
+    seq: i64,
+    kind: String,
+    elu_id: i32,
+    payload: String,
+    at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Event {
+    pub seq: i64,
+    pub kind: String,
+    pub elu_id: i32,
+    /// The person as of the change.
+    pub person: Value,
+    #[serde(with = "timestamp::rfc3339")]
+    pub at: PrimitiveDateTime,
+}
+
+impl EventRow {
+    /// The event, the emails of its person opened with `sealer`; `Null`
+    /// for persons which don't open.
+    fn open(self, sealer: &Sealer) -> Event {
+        Event {
+            seq: self.seq,
+            kind: self.kind,
+            elu_id: self.elu_id,
+            person: sealer.open_person(&self.payload).unwrap_or(Value::Null),
+            at: self.at,
+        }
+    }
+}
+
+/// Appends a change to the log, returning its sequence number.
+pub fn record(kind: ChangeKind, person: &Person, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<i64> {
+    diesel::insert_into(events::table)
+        .values((
+            events::kind.eq(kind.as_str()),
+            events::elu_id.eq(person.id),
+            events::payload.eq(sealer.seal_person(person)),
+        ))
+        .returning(events::seq)
+        .get_result(connection)
+}
+
+/// The events following `since`, in sequence order.
+pub fn since(since: i64, limit: i64, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Vec<Event>> {
+    events::table
+        .filter(events::seq.gt(since))
+        .order(events::seq)
+        .limit(limit)
+        .select(EventRow::as_select())
+        .load(connection)
+        .map(|rows| rows.into_iter().map(|row| row.open(sealer)).collect())
+}
+
+/// The events up to `at`, in sequence order.
+pub fn until(at: PrimitiveDateTime, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Vec<Event>> {
+    events::table
+        .filter(events::at.le(at))
+        .order(events::seq)
+        .select(EventRow::as_select())
+        .load(connection)
+        .map(|rows| rows.into_iter().map(|row| row.open(sealer)).collect())
+}
+
+/// The persons of the directory as of `at`, replayed from the log: each
+/// as their last change before then left them, unless it deleted them.
+pub fn as_of(at: PrimitiveDateTime, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Vec<Person>> {
+    let mut persons = std::collections::BTreeMap::new();
+    for event in until(at, sealer, connection)? {
+        if event.kind == ChangeKind::Deleted.as_str() {
+            persons.remove(&event.elu_id);
+        } else if let Ok(person) = serde_json::from_value::<Person>(event.person) {
+            persons.insert(event.elu_id, person);
+        }
+    }
+    Ok(persons.into_values().collect())
+}
+
+pub fn get(seq: i64, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Event> {
+    events::table
+        .find(seq)
+        .select(EventRow::as_select())
+        .first(connection)
+        .map(|row| row.open(sealer))
+}
+
+/// Sequence number of the latest event; 0 before the first one.
+pub fn latest(connection: &mut SqliteConnection) -> QueryResult<i64> {
+    events::table
+        .select(diesel::dsl::max(events::seq))
+        .first::<Option<i64>>(connection)
+        .map(|seq| seq.unwrap_or(0))
+}
+
+/// The last event `consumer` handled; 0 for consumers starting afresh.
+pub fn cursor(consumer: &str, connection: &mut SqliteConnection) -> QueryResult<i64> {
+    event_cursors::table
+        .find(consumer)
+        .select(event_cursors::seq)
+        .first(connection)
+        .optional()
+        .map(|seq| seq.unwrap_or(0))
+}
+
+pub fn advance(consumer: &str, seq: i64, connection: &mut SqliteConnection) -> QueryResult<usize> {
+    diesel::insert_into(event_cursors::table)
+        .values((event_cursors::consumer.eq(consumer), event_cursors::seq.eq(seq)))
+        .on_conflict(event_cursors::consumer)
+        .do_update()
+        .set(event_cursors::seq.eq(seq))
+        .execute(connection)
+}
+
+/// Where changes are written for their consumers: the events table, and
+/// the sync queue, with the emails sealed by `sealer`.
+#[derive(Clone, Default)]
+pub struct Outbox {
+    sync_targets: Vec<String>,
+    sealer: Sealer,
+}
+
+impl Outbox {
+    pub fn new(sync_targets: &[Arc<dyn SyncTarget>], sealer: Sealer) -> Self {
+        Outbox { sync_targets: sync_targets.iter().map(|target| target.name().to_string()).collect(), sealer }
+    }
+
+    /// Writes the change; meant to be called within the transaction making
+    /// it.
+    pub fn publish(&self, kind: ChangeKind, person: &Person, connection: &mut SqliteConnection) -> QueryResult<()> {
+        record(kind, person, &self.sealer, connection)?;
+        if kind != ChangeKind::Deleted {
+            sync::enqueue(kind, person, &self.sync_targets, &self.sealer, connection)?;
+        }
+        Ok(())
+    }
+}
+
+/// Repository publishing the changes made through it, for backends which
+/// don't store persons in SQLite and so can't publish them atomically.
+pub struct RecordingRepository {
+    inner: Arc<dyn PersonRepository>,
+    db: DbConn,
+    outbox: Outbox,
+}
+
+impl RecordingRepository {
+    pub fn new(inner: Arc<dyn PersonRepository>, db: DbConn, outbox: Outbox) -> Self {
+        RecordingRepository { inner, db, outbox }
+    }
+
+    /// The change is already saved, so failing to record it doesn't fail
+    /// the request.
+    fn publish(&self, kind: ChangeKind, person: &Person) {
+        let result = db::lock(&self.db).map_err(Into::into).and_then(|mut connection| connection.transaction(|connection| self.outbox.publish(kind, person, connection)));
+        if let Err(e) = result {
+            log::error!("Could not record {} person {}: {}", kind.as_str(), person.id, e);
+        }
+    }
+}
+
+impl PersonRepository for RecordingRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        self.inner.list()
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        self.inner.find(key)
+    }
+
+    fn get(&self, id: i32) -> Result<Person, Status> {
+        self.inner.get(id)
+    }
+
+    fn find_alias(&self, email: &Email) -> Result<Person, Status> {
+        self.inner.find_alias(email)
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        self.inner.get_many(emails)
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        let created = self.inner.create(person)?;
+        self.publish(ChangeKind::Created, &created);
+        Ok(created)
+    }
+
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status> {
+        let updated = self.inner.update(email, person)?;
+        self.publish(ChangeKind::Updated, &updated);
+        Ok(updated)
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        let updated = self.inner.update_many(updates)?;
+        for person in &updated {
+            self.publish(ChangeKind::Updated, person);
+        }
+        Ok(updated)
+    }
+
+    fn delete(&self, email: &Email) -> Result<Person, Status> {
+        let deleted = self.inner.delete(email)?;
+        self.publish(ChangeKind::Deleted, &deleted);
+        Ok(deleted)
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        self.inner.search(filter)
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        self.inner.search_page(filter, page)
+    }
+
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status> {
+        self.inner.set_email_status(id, status)
+    }
+}
+
+/// The changes following sequence number `since` (all of them by default),
+/// oldest first; consumers pass the last `seq` they got to fetch the next
+/// ones.
+#[get("/events?<since>&<limit>")]
+fn list_events(since: Option<i64>, limit: Option<i64>, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<Vec<Event>>, Status> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(Status::BadRequest);
+    }
+
+    self::since(since.unwrap_or(0), limit, sealer, &mut *db::lock(db)?)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_events]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_list_events() {
+        let client = client(setup_test_db());
+        let person = json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": [] });
+        client.post("/elus/create").json(&person).dispatch();
+        client.put("/elus/jean@mairie.example").header(admin()).json(&json!({ "name": "Jean Dupont", "email": "jean.dupont@mairie.example", "mandates": ["maire"] })).dispatch();
+        client.delete("/elus/jean.dupont@mairie.example").header(admin()).dispatch();
+
+        assert_eq!(client.get("/events").dispatch().status(), Status::Unauthorized);
+        let events: Vec<Event> = client.get("/events").header(admin()).dispatch().into_json().unwrap();
+        let kinds: Vec<&str> = events.iter().map(|event| event.kind.as_str()).collect();
+        assert_eq!(kinds, ["created", "updated", "deleted"]);
+        assert_eq!(events[1].person["mandates"], json!(["Maire"]));
+        assert!(events.iter().all(|event| event.elu_id == events[0].elu_id));
+
+        let uri = format!("/events?since={}&limit=1", events[0].seq);
+        let next: Vec<Event> = client.get(uri).header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(next.iter().map(|event| event.seq).collect::<Vec<_>>(), [events[1].seq]);
+        assert_eq!(client.get("/events?limit=0").header(admin()).dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_as_of() {
+        let client = client(setup_test_db());
+        client.post("/elus/create").json(&json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": ["maire"] })).dispatch();
+        client.post("/elus/create").json(&json!({ "name": "Marie Martin", "email": "marie@mairie.example", "mandates": [] })).dispatch();
+        client.put("/elus/jean@mairie.example").header(admin()).json(&json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": [] })).dispatch();
+        client.delete("/elus/marie@mairie.example").header(admin()).dispatch();
+        // Spreads the changes over four days.
+        let db = client.rocket().state::<DbConn>().unwrap();
+        diesel::sql_query("UPDATE events SET at = datetime('2026-01-01', '+' || seq || ' days')").execute(&mut *db.lock().unwrap()).unwrap();
+
+        let names = |uri: &str| {
+            let persons: Vec<crate::Person> = client.get(uri).header(admin()).dispatch().into_json().unwrap();
+            persons.into_iter().map(|person| (person.name.to_string(), person.mandates)).collect::<Vec<_>>()
+        };
+        assert_eq!(names("/elus?as_of=2026-01-01"), []);
+        assert_eq!(names("/elus?as_of=2026-01-03"), [("Jean Dupont".to_string(), vec!["Maire".to_string()]), ("Marie Martin".to_string(), vec![])]);
+        assert_eq!(names("/elus?as_of=2026-01-04&mandate=maire"), []);
+        assert_eq!(names("/elus?as_of=2026-01-05"), [("Jean Dupont".to_string(), vec![])]);
+
+        assert_eq!(client.get("/elus?as_of=2026-01-03").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get("/elus?as_of=soon").header(admin()).dispatch().status(), Status::BadRequest);
+    }
+}
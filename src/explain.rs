@@ -0,0 +1,84 @@
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rocket::http::Status;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::{db, DbConn};
+
+/// The statements `SqliteRepository` runs most, as it runs them.
+const HOT_QUERIES: &[(&str, &str)] = &[
+    ("person_by_email", "SELECT * FROM elus WHERE email = ? LIMIT 1"),
+    ("name_or_email_taken", "SELECT id FROM elus WHERE (email = ? OR name = ?) AND id != ? LIMIT 1"),
+    ("search_by_name", "SELECT * FROM elus WHERE search_name LIKE ? ESCAPE '\\' ORDER BY id"),
+    ("search_by_mandate", "SELECT * FROM elus WHERE id IN (SELECT elu_id FROM mandates WHERE title = ?) ORDER BY id"),
+    ("search_by_commune", "SELECT * FROM elus WHERE commune_code = ? ORDER BY id"),
+    ("mandates_of_persons", "SELECT elu_id, title FROM mandates WHERE elu_id IN (?, ?, ?) ORDER BY elu_id, position"),
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QueryPlan {
+    pub name: String,
+    pub sql: String,
+    /// The `detail` column of `EXPLAIN QUERY PLAN`, one entry per step.
+    pub plan: Vec<String>,
+    /// Whether a table is read in full, rather than through an index.
+    pub full_scan: bool,
+}
+
+#[derive(QueryableByName)]
+struct PlanStep {
+    #[diesel(sql_type = Text)]
+    detail: String,
+}
+
+pub fn explain(name: &str, sql: &str, connection: &mut SqliteConnection) -> QueryResult<QueryPlan> {
+    let plan: Vec<String> = diesel::sql_query(format!("EXPLAIN QUERY PLAN {}", sql))
+        .load::<PlanStep>(connection)?
+        .into_iter()
+        .map(|step| step.detail)
+        .collect();
+    let full_scan = plan.iter().any(|step| step.starts_with("SCAN ") && !step.contains(" USING "));
+
+    Ok(QueryPlan { name: name.to_string(), sql: sql.to_string(), plan, full_scan })
+}
+
+/// Shows how SQLite runs the hot queries, to check they use the indexes.
+#[get("/admin/query-plans")]
+fn query_plans(_admin: Admin, db: &State<DbConn>) -> Result<Json<Vec<QueryPlan>>, Status> {
+    let mut connection = db::lock(db)?;
+    HOT_QUERIES
+        .iter()
+        .map(|(name, sql)| explain(name, sql, &mut connection))
+        .collect::<QueryResult<Vec<_>>>()
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![query_plans]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, setup_test_db};
+
+    #[test]
+    fn test_query_plans() {
+        let client = client(setup_test_db());
+
+        let response = client.get("/admin/query-plans").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/admin/query-plans").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let plans: Vec<QueryPlan> = response.into_json().expect("valid JSON");
+        let scanning: Vec<&str> = plans.iter().filter(|plan| plan.full_scan).map(|plan| plan.name.as_str()).collect();
+        // Substring matches can't use the search_name index.
+        assert_eq!(scanning, vec!["search_by_name"]);
+        assert!(plans[1].plan.iter().any(|step| step.contains("elus_name")));
+    }
+}
@@ -0,0 +1,173 @@
+//! Exports of the elus matching the same criteria as `GET /elus`, so that
+//! what a listing shows can be exported: as GeoJSON, for maps, and as the
+//! CSV the importer takes back. Both name the elus' commune as it goes by
+//! in its settings.
+
+use std::collections::HashMap;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+
+use crate::config::AppConfig;
+use crate::criteria::Filters;
+use crate::pagination::SortSpec;
+use crate::redaction::redact;
+use crate::repository::PersonSort;
+use crate::settings::{self, Settings};
+use crate::visibility::Visible;
+use crate::{db, scheduled_export, timeouts, DbConn, Person};
+
+/// Builds a GeoJSON FeatureCollection of the persons having coordinates,
+/// suitable for plotting with Leaflet's `L.geoJSON`, with the display name
+/// of their commune among `settings`, if any.
+pub fn geojson(persons: &[Person], settings: &HashMap<String, Settings>) -> Value {
+    let features: Vec<Value> = persons
+        .iter()
+        .filter_map(|person| Some((person, person.latitude?, person.longitude?)))
+        .map(|(person, latitude, longitude)| json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": [longitude, latitude],
+            },
+            "properties": {
+                "name": person.name,
+                "mandates": person.mandates,
+                "organization": person.commune_code.as_ref().and_then(|code| settings.get(code)).map(|settings| &settings.display_name),
+            },
+        }))
+        .collect();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// The persons matching the filters, as `GET /elus` lists them in the
+/// order of `sort`.
+async fn matching(filters: Filters, sort: SortSpec<PersonSort>, repository: Visible, config: &AppConfig) -> Result<Vec<Person>, Status> {
+    let repository = repository.into_inner();
+    let mut persons = timeouts::blocking(config.timeouts.request(), move || repository.search(&filters.0)).await?;
+    if sort.key.is_some() {
+        persons.sort_by(|a, b| sort.compare(a, b));
+    }
+    Ok(persons.into_iter().map(Person::from).collect())
+}
+
+/// The settings of the communes of the persons.
+fn organizations(persons: &[Person], db: &DbConn) -> Result<HashMap<String, Settings>, Status> {
+    let codes = persons.iter().filter_map(|person| person.commune_code.as_deref());
+    settings::find_all(codes, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)
+}
+
+/// The elus matching the criteria of `GET /elus` which have coordinates.
+#[get("/elus/export.geojson")]
+async fn export_geojson(filters: Filters, repository: Visible, db: &State<DbConn>, config: &State<AppConfig>) -> Result<(ContentType, Json<Value>), Status> {
+    let persons = matching(filters, SortSpec::default(), repository, config).await?;
+    let settings = organizations(&persons, db)?;
+
+    Ok((ContentType::new("application", "geo+json"), Json(geojson(&persons, &settings))))
+}
+
+/// The elus, as the CSV of the nightly exports, shaped for the caller as
+/// `GET /elus` would show them, along with the settings of their communes.
+pub struct CsvExport(Vec<Person>, HashMap<String, Settings>);
+
+impl<'r> Responder<'r, 'static> for CsvExport {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut persons = redact(&self.0, request)?;
+        let persons = persons.as_array_mut().map(Vec::as_mut_slice).unwrap_or_default();
+        settings::add_organizations(persons, &self.1);
+        let csv = scheduled_export::csv(persons);
+        let mut response = (ContentType::CSV, csv).respond_to(request)?;
+        response.set_header(Header::new("Content-Disposition", "attachment; filename=\"elus.csv\""));
+        Ok(response)
+    }
+}
+
+/// The elus matching the criteria of `GET /elus`, in its order, so that
+/// what is listed can be exported as it is.
+#[get("/elus/export.csv")]
+async fn export_csv(filters: Filters, sort: SortSpec<PersonSort>, repository: Visible, db: &State<DbConn>, config: &State<AppConfig>) -> Result<CsvExport, Status> {
+    let persons = matching(filters, sort, repository, config).await?;
+    let settings = organizations(&persons, db)?;
+    Ok(CsvExport(persons, settings))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![export_geojson, export_csv]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::tests::{admin, build_client, client, insert_test_persons, setup_test_db};
+
+    fn paris() -> db::Commune {
+        db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() }
+    }
+
+    #[test]
+    fn test_export_geojson() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        db::upsert_communes(&[paris()], &mut connection).unwrap();
+        let client = client(connection);
+        client.put("/collectivites/75056/settings").header(admin()).json(&json!({ "display_name": "Ville de Paris" })).dispatch();
+
+        let response = client.get("/elus/export.geojson").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::new("application", "geo+json")));
+
+        let collection: Value = response.into_json().expect("valid JSON");
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().expect("features array");
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([2.3522, 48.8566]));
+        assert_eq!(features[0]["properties"]["name"], "Jean Dupont");
+        assert_eq!(features[0]["properties"]["mandates"], json!(["Maire", "Conseiller régional"]));
+        assert_eq!(features[0]["properties"]["organization"], "Ville de Paris");
+        assert!(features[0]["properties"].get("email").is_none());
+
+        let collection: Value = client.get("/elus/export.geojson?mandate=maire").dispatch().into_json().unwrap();
+        assert_eq!(collection["features"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        db::upsert_communes(&[paris()], &mut connection).unwrap();
+        let client = build_client(|figment| figment.merge(("redaction.public", ["email"])), connection);
+
+        let response = client.get("/elus/export.csv?commune=75056").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        assert_eq!(response.headers().get_one("Content-Disposition"), Some("attachment; filename=\"elus.csv\""));
+        let csv = response.into_string().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("uuid,name,email,mandates,"));
+        assert!(lines[1].contains(",Jean Dupont,,Maire|Conseiller régional,75056,"));
+        assert!(lines[1].contains(",48.8566,2.3522,Paris,"));
+        assert!(lines[2].contains(",Marie Martin,,"));
+
+        let csv = client.get("/elus/export.csv?name=marie").header(admin()).dispatch().into_string().unwrap();
+        assert!(csv.lines().nth(1).unwrap().contains(",Marie Martin,marie.martin@example.com,"));
+        assert_eq!(client.get("/elus/export.csv?email_status=unknown").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_geojson_skips_persons_without_coordinates() {
+        let persons = vec![Person {
+            name: "Sans Adresse".parse().unwrap(),
+            ..Default::default()
+        }];
+
+        assert_eq!(geojson(&persons, &HashMap::new())["features"], json!([]));
+    }
+}
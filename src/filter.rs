@@ -0,0 +1,378 @@
+//! Filter expressions of `GET /elus?filter=...`, which combine criteria
+//! the plain query parameters can't, such as
+//! `mandate eq "Maire" and (commune eq "75056" or commune eq "69123")`.
+//!
+//! An expression compares allow-listed fields to quoted values, with `eq`,
+//! `ne` and, for names, `contains`; comparisons are joined by `and` and
+//! `or`, negated by `not` and grouped in parentheses, `and` binding
+//! tighter than `or`. The database compiles it to a boxed Diesel
+//! expression, the memory repository evaluates it person by person.
+
+use std::fmt;
+
+use diesel::dsl::not;
+use diesel::expression::BoxableExpression;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use diesel::sqlite::Sqlite;
+
+use crate::db::{self, Person};
+use crate::deliverability::EmailStatus;
+use crate::mandate_types::MandateTypes;
+use crate::repository::normalize_name;
+use crate::schema::{elus, mandates};
+use crate::visibility::Visibility;
+
+/// Deepest nesting of parentheses and `not`, so that a crafted filter
+/// can't exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Mandate,
+    Commune,
+    EmailStatus,
+    Visibility,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "name" => Some(Field::Name),
+            "mandate" => Some(Field::Mandate),
+            "commune" => Some(Field::Commune),
+            "email_status" => Some(Field::EmailStatus),
+            "visibility" => Some(Field::Visibility),
+            _ => None,
+        }
+    }
+
+    fn allows(self, operator: Operator) -> bool {
+        operator != Operator::Contains || self == Field::Name
+    }
+
+    /// Whether the field may be compared to `value`, for those having a
+    /// fixed set of values.
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            Field::EmailStatus => value.parse::<EmailStatus>().is_ok(),
+            Field::Visibility => value.parse::<Visibility>().is_ok(),
+            Field::Name | Field::Mandate | Field::Commune => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    /// Part of the value, ignoring case and accents.
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Compare(Field, Operator, String),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// Why a filter was refused, with the offset in bytes where it went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.position)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push((start, if c == '(' { Token::Open } else { Token::Close }));
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => value.push(c),
+                            _ => return Err(ParseError { position: start, message: "invalid escape in string".to_string() }),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(ParseError { position: start, message: "unterminated string".to_string() }),
+                    }
+                }
+                tokens.push((start, Token::Quoted(value)));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((start, Token::Word(word)));
+            }
+            c => return Err(ParseError { position: start, message: format!("unexpected '{}'", c) }),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let position = self.tokens.get(self.next).map_or(self.end, |(position, _)| *position);
+        ParseError { position, message: message.into() }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn disjunction(&mut self, depth: usize) -> Result<Expr, ParseError> {
+        let mut expr = self.conjunction(depth)?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.conjunction(depth)?));
+        }
+        Ok(expr)
+    }
+
+    fn conjunction(&mut self, depth: usize) -> Result<Expr, ParseError> {
+        let mut expr = self.term(depth)?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.term(depth)?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self, depth: usize) -> Result<Expr, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.term(depth + 1)?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let expr = self.disjunction(depth + 1)?;
+            if self.advance() != Some(Token::Close) {
+                self.next -= 1;
+                return Err(self.error("expected ')'"));
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let field = match self.peek() {
+            Some(Token::Word(name)) => Field::parse(name).ok_or_else(|| self.error(format!("unknown field '{}'", name)))?,
+            _ => return Err(self.error("expected a field")),
+        };
+        self.next += 1;
+        let operator = match self.peek() {
+            Some(Token::Word(word)) if word == "eq" => Operator::Eq,
+            Some(Token::Word(word)) if word == "ne" => Operator::Ne,
+            Some(Token::Word(word)) if word == "contains" => Operator::Contains,
+            _ => return Err(self.error("expected eq, ne or contains")),
+        };
+        if !field.allows(operator) {
+            return Err(self.error("contains only applies to name"));
+        }
+        self.next += 1;
+        let value = match self.peek() {
+            Some(Token::Quoted(value)) => value.clone(),
+            _ => return Err(self.error("expected a quoted value")),
+        };
+        if !field.accepts(&value) {
+            return Err(self.error(format!("invalid value \"{}\"", value)));
+        }
+        self.next += 1;
+        Ok(Expr::Compare(field, operator, value))
+    }
+}
+
+/// Parses a filter such as `mandate eq "Maire" and commune eq "75056"`.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser { tokens: tokenize(input)?, next: 0, end: input.len() };
+    let expr = parser.disjunction(0)?;
+    if parser.peek().is_some() {
+        return Err(parser.error("expected and, or or the end"));
+    }
+    Ok(expr)
+}
+
+type Condition = Box<dyn BoxableExpression<elus::table, Sqlite, SqlType = Bool>>;
+
+impl Expr {
+    /// The expression with mandates given by code replaced by their title,
+    /// as `GET /elus?mandate=` takes them.
+    pub fn with_titles(self, mandate_types: &MandateTypes) -> Expr {
+        match self {
+            Expr::Compare(Field::Mandate, operator, value) => Expr::Compare(Field::Mandate, operator, mandate_types.title(&value)),
+            Expr::Compare(..) => self,
+            Expr::And(left, right) => Expr::And(Box::new(left.with_titles(mandate_types)), Box::new(right.with_titles(mandate_types))),
+            Expr::Or(left, right) => Expr::Or(Box::new(left.with_titles(mandate_types)), Box::new(right.with_titles(mandate_types))),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.with_titles(mandate_types))),
+        }
+    }
+
+    /// The condition on the `elus` table selecting the matching persons.
+    pub fn to_sql(&self) -> Condition {
+        match self {
+            Expr::Compare(field, operator, value) => {
+                let condition: Condition = match field {
+                    Field::Name if *operator == Operator::Contains => {
+                        Box::new(elus::search_name.like(format!("%{}%", db::escape_like(&normalize_name(value)))).escape('\\'))
+                    }
+                    Field::Name => Box::new(elus::search_name.eq(normalize_name(value))),
+                    Field::Mandate => {
+                        let holders = mandates::table.filter(mandates::title.eq(value.clone())).select(mandates::elu_id);
+                        Box::new(elus::id.eq_any(holders))
+                    }
+                    Field::Commune => Box::new(elus::commune_code.is(value.clone())),
+                    Field::EmailStatus => Box::new(elus::email_status.eq(value.clone())),
+                    Field::Visibility => Box::new(elus::visibility.eq(value.clone())),
+                };
+                match operator {
+                    Operator::Ne => Box::new(not(condition)),
+                    Operator::Eq | Operator::Contains => condition,
+                }
+            }
+            Expr::And(left, right) => Box::new(left.to_sql().and(right.to_sql())),
+            Expr::Or(left, right) => Box::new(left.to_sql().or(right.to_sql())),
+            Expr::Not(expr) => Box::new(not(expr.to_sql())),
+        }
+    }
+
+    pub fn matches(&self, person: &Person) -> bool {
+        match self {
+            Expr::Compare(field, operator, value) => {
+                let found = match field {
+                    Field::Name if *operator == Operator::Contains => normalize_name(&person.name).contains(&normalize_name(value)),
+                    Field::Name => normalize_name(&person.name) == normalize_name(value),
+                    Field::Mandate => person.mandates.contains(value),
+                    Field::Commune => person.commune_code.as_ref() == Some(value),
+                    Field::EmailStatus => person.email_status == *value,
+                    Field::Visibility => person.visibility.as_str() == value,
+                };
+                found != (*operator == Operator::Ne)
+            }
+            Expr::And(left, right) => left.matches(person) && right.matches(person),
+            Expr::Or(left, right) => left.matches(person) || right.matches(person),
+            Expr::Not(expr) => !expr.matches(person),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(field: Field, operator: Operator, value: &str) -> Box<Expr> {
+        Box::new(Expr::Compare(field, operator, value.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(r#"mandate eq "Maire""#).unwrap(), *compare(Field::Mandate, Operator::Eq, "Maire"));
+        assert_eq!(
+            parse(r#"mandate eq "Maire" and commune eq "75056" or not name contains "du\"pont""#).unwrap(),
+            Expr::Or(
+                Box::new(Expr::And(compare(Field::Mandate, Operator::Eq, "Maire"), compare(Field::Commune, Operator::Eq, "75056"))),
+                Box::new(Expr::Not(compare(Field::Name, Operator::Contains, "du\"pont"))),
+            )
+        );
+        assert_eq!(
+            parse(r#"mandate ne "Maire" and (commune eq "75056" or commune eq "69123")"#).unwrap(),
+            Expr::And(
+                compare(Field::Mandate, Operator::Ne, "Maire"),
+                Box::new(Expr::Or(compare(Field::Commune, Operator::Eq, "75056"), compare(Field::Commune, Operator::Eq, "69123"))),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &str| parse(input).unwrap_err();
+        assert_eq!(error(r#"email eq "jean@example.com""#), ParseError { position: 0, message: "unknown field 'email'".to_string() });
+        assert_eq!(error(r#"commune contains "75""#).position, 8);
+        assert_eq!(error(r#"mandate eq Maire"#).message, "expected a quoted value");
+        assert_eq!(error(r#"mandate eq "Maire"#).message, "unterminated string");
+        assert_eq!(error(r#"email_status eq "unknown""#).message, "invalid value \"unknown\"");
+        assert_eq!(error(r#"(mandate eq "Maire""#), ParseError { position: 19, message: "expected ')'".to_string() });
+        assert_eq!(error(r#"mandate eq "Maire" commune eq "75056""#).position, 19);
+        assert_eq!(error("").message, "expected a field");
+        assert_eq!(error(&"(".repeat(100)).message, "too deeply nested");
+    }
+
+    #[test]
+    fn test_matches() {
+        let person = Person {
+            id: 1,
+            uuid: "00000000-0000-4000-8000-000000000001".to_string(),
+            name: "Jean Dupont".parse().unwrap(),
+            email: "jean@example.com".parse().unwrap(),
+            emails: vec![],
+            mandates: vec!["Maire".to_string()],
+            commune_code: Some("75056".to_string()),
+            office_address: None,
+            latitude: None,
+            longitude: None,
+            may_contact_by_email: false,
+            consent_date: None,
+            consent_source: None,
+            visibility: Visibility::Public,
+            custom: Default::default(),
+            email_status: "unchecked".to_string(),
+            updated_at: time::macros::datetime!(2030-01-01 12:00:00),
+        };
+        assert!(parse(r#"mandate eq "Maire" and commune eq "75056""#).unwrap().matches(&person));
+        assert!(parse(r#"name contains "DUPONT" and not commune eq "69123""#).unwrap().matches(&person));
+        assert!(parse(r#"commune ne "75056" or name eq "jean dupont""#).unwrap().matches(&person));
+        assert!(!parse(r#"mandate ne "Maire" or visibility eq "internal""#).unwrap().matches(&person));
+    }
+}
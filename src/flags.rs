@@ -0,0 +1,135 @@
+//! Runtime feature flags gating experimental routes. Flags start with the
+//! values of the `flags` table of the configuration and can be switched
+//! with `PUT /admin/flags` until the next restart or reload of the
+//! configuration. Routes behind a disabled flag answer 404, as if they
+//! didn't exist.
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::auth::Admin;
+
+/// A flag, gating routes through the `Enabled` guard.
+pub trait Flag {
+    const NAME: &'static str;
+    /// Whether the flag is on when the configuration doesn't say.
+    const DEFAULT: bool;
+}
+
+/// Importing communes from COG files.
+pub struct Import;
+
+impl Flag for Import {
+    const NAME: &'static str = "import";
+    const DEFAULT: bool = true;
+}
+
+/// The flags there are, with their defaults.
+const KNOWN: &[(&str, bool)] = &[(Import::NAME, Import::DEFAULT)];
+
+pub struct Flags(RwLock<BTreeMap<String, bool>>);
+
+fn configured_flags(configured: &BTreeMap<String, bool>) -> BTreeMap<String, bool> {
+    let mut flags: BTreeMap<String, bool> = KNOWN.iter().map(|(name, default)| (name.to_string(), *default)).collect();
+    for (name, enabled) in configured {
+        match flags.get_mut(name) {
+            Some(flag) => *flag = *enabled,
+            None => log::warn!("Ignoring unknown feature flag {}", name),
+        }
+    }
+    flags
+}
+
+impl Flags {
+    pub fn new(configured: &BTreeMap<String, bool>) -> Self {
+        Flags(RwLock::new(configured_flags(configured)))
+    }
+
+    /// Switches every flag back to its configured value.
+    pub fn reset(&self, configured: &BTreeMap<String, bool>) {
+        *self.0.write().unwrap() = configured_flags(configured);
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.read().unwrap().get(name).copied().unwrap_or(false)
+    }
+}
+
+/// Request guard succeeding when the flag `F` is on.
+pub struct Enabled<F>(PhantomData<fn() -> F>);
+
+#[rocket::async_trait]
+impl<'r, F: Flag> FromRequest<'r> for Enabled<F> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.rocket().state::<Flags>() {
+            Some(flags) if flags.is_enabled(F::NAME) => request::Outcome::Success(Enabled(PhantomData)),
+            _ => request::Outcome::Error((Status::NotFound, ())),
+        }
+    }
+}
+
+#[get("/admin/flags")]
+fn list_flags(_admin: Admin, flags: &State<Flags>) -> Json<BTreeMap<String, bool>> {
+    Json(flags.0.read().unwrap().clone())
+}
+
+/// Switches the given flags, leaving the others alone.
+#[put("/admin/flags", data = "<changes>")]
+fn set_flags(changes: Json<BTreeMap<String, bool>>, _admin: Admin, flags: &State<Flags>) -> Result<Json<BTreeMap<String, bool>>, Status> {
+    let mut current = flags.0.write().unwrap();
+    if changes.keys().any(|name| !current.contains_key(name)) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    for (name, enabled) in changes.into_inner() {
+        log::info!("Feature flag {} turned {}", name, if enabled { "on" } else { "off" });
+        current.insert(name, enabled);
+    }
+
+    Ok(Json(current.clone()))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_flags, set_flags]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, client, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_configured_flags() {
+        let flags = Flags::new(&BTreeMap::from([("import".to_string(), false), ("typo".to_string(), true)]));
+        assert!(!flags.is_enabled("import"));
+        assert!(!flags.is_enabled("typo"));
+        assert!(Flags::new(&BTreeMap::new()).is_enabled("import"));
+    }
+
+    #[test]
+    fn test_switch_flags() {
+        let client = client(setup_test_db());
+        let import = || client.post("/communes/import").header(admin()).body("").dispatch().status();
+        assert_ne!(import(), Status::NotFound);
+
+        assert_eq!(client.get("/admin/flags").dispatch().status(), Status::Unauthorized);
+        let response = client.put("/admin/flags").header(admin()).json(&json!({ "import": false })).dispatch();
+        assert!(!response.into_json::<BTreeMap<String, bool>>().unwrap()["import"]);
+        assert_eq!(import(), Status::NotFound);
+
+        let response = client.put("/admin/flags").header(admin()).json(&json!({ "graphql": true })).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let client = build_client(|figment| figment.merge(("flags.import", false)), setup_test_db());
+        assert_eq!(client.post("/communes/import").header(admin()).body("").dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,503 @@
+//! Full-text index of the elus, for `GET /elus/search` on directories too
+//! large to search with `LIKE` queries: a Tantivy index, on disk or in
+//! memory, holding each elu's name, phonetic key, email and mandates along
+//! with the elu as of their last change. It is kept up to date from the
+//! events table, and remembers the last event it holds in its commits, so
+//! an index on disk only catches up on the events it missed. Rebuilt from
+//! the repository when new, or on `POST /admin/search/reindex`.
+//!
+//! An index on disk can be saved to a local directory or an S3 bucket with
+//! `POST /admin/search/snapshot`, under `search-index/`; a server starting
+//! without an index restores the latest snapshot, so that a redeploy only
+//! catches up on the events since, rather than reindexing everything.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Query, RegexQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, Value, INDEXED, STORED, STRING, TEXT};
+use tantivy::{Index, IndexReader, IndexWriter, TantivyDocument, Term};
+
+use crate::auth::Admin;
+use crate::db;
+use crate::email::Email;
+use crate::encryption::Sealer;
+use crate::events::{self, ChangeKind};
+use crate::phonetic;
+use crate::repository::{normalize_name, PersonRepository};
+use crate::scheduled_export::{self, ExportDestination};
+use crate::search::SearchIndex;
+use crate::storage::BlobStore;
+use crate::{search, shutdown, DbConn};
+
+/// Events applied per commit.
+const BATCH_SIZE: i64 = 1000;
+/// Matches considered by a search, best first by Tantivy's own scoring.
+const MAX_CANDIDATES: usize = 1000;
+/// Memory the writer may buffer documents in before flushing them.
+const WRITER_MEMORY: usize = 15_000_000;
+/// Where snapshots are stored, and the key of the snapshot's manifest,
+/// which is written last.
+const SNAPSHOT_PREFIX: &str = "search-index";
+const MANIFEST: &str = "search-index/MANIFEST";
+/// The file of the index listing its segments, and the last commit.
+const META: &str = "meta.json";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct FullTextConfig {
+    /// Directory of the index; kept in memory, and so rebuilt on each
+    /// start, when unset.
+    pub path: Option<String>,
+    /// Seconds between two checks for new events.
+    pub poll_interval: u64,
+    /// Where snapshots of the index are saved and restored from.
+    pub snapshots: Option<ExportDestination>,
+}
+
+impl Default for FullTextConfig {
+    fn default() -> Self {
+        FullTextConfig { path: None, poll_interval: 1, snapshots: None }
+    }
+}
+
+/// The files of a snapshot, and the `meta.json` naming their segments.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Manifest {
+    files: Vec<String>,
+    meta: String,
+}
+
+struct Fields {
+    id: Field,
+    name: Field,
+    phonetic: Field,
+    email: Field,
+    mandates: Field,
+    person: Field,
+}
+
+pub struct FullTextIndex {
+    /// Directory of the index; None when in memory.
+    path: Option<PathBuf>,
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+
+fn schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+    let fields = Fields {
+        id: builder.add_i64_field("id", INDEXED | STORED),
+        name: builder.add_text_field("name", TEXT),
+        phonetic: builder.add_text_field("phonetic", TEXT),
+        email: builder.add_text_field("email", STRING),
+        mandates: builder.add_text_field("mandates", STRING),
+        person: builder.add_text_field("person", STORED),
+    };
+    (builder.build(), fields)
+}
+
+/// Words of `text`, as the index splits names into.
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+impl FullTextIndex {
+    pub fn open(config: &FullTextConfig) -> tantivy::Result<Self> {
+        let (schema, fields) = schema();
+        let index = match &config.path {
+            Some(path) => {
+                fs::create_dir_all(path)?;
+                Index::open_or_create(MmapDirectory::open(path)?, schema)?
+            }
+            None => Index::create_in_ram(schema),
+        };
+        let reader = index.reader()?;
+        let writer = Mutex::new(index.writer_with_num_threads(1, WRITER_MEMORY)?);
+        Ok(FullTextIndex { path: config.path.as_ref().map(PathBuf::from), index, reader, writer, fields })
+    }
+
+    /// Sequence number of the last event the index holds; None for an
+    /// index never built.
+    fn seq(&self) -> tantivy::Result<Option<i64>> {
+        Ok(self.index.load_metas()?.payload.and_then(|payload| payload.parse().ok()))
+    }
+
+    fn document(&self, person: &db::Person) -> TantivyDocument {
+        let mut document = TantivyDocument::new();
+        document.add_i64(self.fields.id, person.id.into());
+        document.add_text(self.fields.name, normalize_name(&person.name));
+        document.add_text(self.fields.phonetic, phonetic::key(&person.name));
+        for email in std::iter::once(&person.email).chain(&person.emails) {
+            document.add_text(self.fields.email, email.as_str());
+        }
+        for mandate in &person.mandates {
+            document.add_text(self.fields.mandates, mandate);
+        }
+        document.add_text(self.fields.person, serde_json::to_string(person).expect("persons serialize to JSON"));
+        document
+    }
+
+    fn commit(&self, writer: &mut IndexWriter, seq: i64) -> tantivy::Result<()> {
+        let mut commit = writer.prepare_commit()?;
+        commit.set_payload(&seq.to_string());
+        commit.commit()?;
+        self.reader.reload()
+    }
+
+    /// Replaces the contents of the index with the persons of
+    /// `repository`, returning how many were indexed.
+    pub fn rebuild(&self, repository: &dyn PersonRepository, db: &DbConn) -> Result<usize, String> {
+        let mut writer = self.writer.lock().unwrap();
+        // Changes made while listing are caught up on afterwards.
+        let seq = events::latest(&mut *db::lock(db).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        let persons = repository.list().map_err(|status| format!("listing the elus failed with {}", status))?;
+
+        writer.delete_all_documents().map_err(|e| e.to_string())?;
+        for person in &persons {
+            writer.add_document(self.document(person)).map_err(|e| e.to_string())?;
+        }
+        self.commit(&mut writer, seq).map_err(|e| e.to_string())?;
+        Ok(persons.len())
+    }
+
+    /// Applies the events the index doesn't hold yet, returning how many
+    /// there were.
+    pub fn catch_up(&self, db: &DbConn, sealer: &Sealer) -> Result<usize, String> {
+        let mut writer = self.writer.lock().unwrap();
+        let mut applied = 0;
+        loop {
+            let seq = self.seq().map_err(|e| e.to_string())?.unwrap_or(0);
+            let pending = events::since(seq, BATCH_SIZE, sealer, &mut *db::lock(db).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+            let Some(last) = pending.last() else {
+                return Ok(applied);
+            };
+
+            for event in &pending {
+                writer.delete_term(Term::from_field_i64(self.fields.id, event.elu_id.into()));
+                if event.kind != ChangeKind::Deleted.as_str() {
+                    let person: db::Person = serde_json::from_value(event.person.clone()).map_err(|e| format!("event {}: {}", event.seq, e))?;
+                    writer.add_document(self.document(&person)).map_err(|e| e.to_string())?;
+                }
+            }
+            self.commit(&mut writer, last.seq).map_err(|e| e.to_string())?;
+            applied += pending.len();
+        }
+    }
+
+    /// Builds the index if it's new, then catches up on the events.
+    pub fn prepare(&self, repository: &dyn PersonRepository, db: &DbConn, sealer: &Sealer) -> Result<(), String> {
+        if self.seq().map_err(|e| e.to_string())?.is_none() {
+            let indexed = self.rebuild(repository, db)?;
+            log::info!("Indexed {} elus for full-text search", indexed);
+        }
+        self.catch_up(db, sealer).map(|_| ())
+    }
+
+    /// The persons whose email is `query` if `by_email`, who hold
+    /// `mandate`, or have a word of the name containing a word of `query`,
+    /// or sounding like it if `fuzzy`.
+    fn find(&self, query: &str, mandate: &str, fuzzy: bool, by_email: bool) -> tantivy::Result<Vec<db::Person>> {
+        let mut queries: Vec<Box<dyn Query>> = vec![Box::new(TermQuery::new(Term::from_field_text(self.fields.mandates, mandate), IndexRecordOption::Basic))];
+        if let Some(email) = query.parse::<Email>().ok().filter(|_| by_email) {
+            queries.push(Box::new(TermQuery::new(Term::from_field_text(self.fields.email, email.as_str()), IndexRecordOption::Basic)));
+        }
+        for word in words(&normalize_name(query)) {
+            queries.push(Box::new(RegexQuery::from_pattern(&format!(".*{}.*", word), self.fields.name)?));
+        }
+        if fuzzy {
+            for word in words(&phonetic::key(query)) {
+                queries.push(Box::new(RegexQuery::from_pattern(&format!(".*{}.*", word), self.fields.phonetic)?));
+            }
+        }
+
+        let searcher = self.reader.searcher();
+        let found = searcher.search(&BooleanQuery::union(queries), &TopDocs::with_limit(MAX_CANDIDATES).order_by_score())?;
+        let mut persons = Vec::with_capacity(found.len());
+        for (_, address) in found {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if let Some(person) = document.get_first(self.fields.person).and_then(|value| value.as_str()) {
+                persons.push(serde_json::from_str(person).map_err(|e| tantivy::TantivyError::InternalError(e.to_string()))?);
+            }
+        }
+        Ok(persons)
+    }
+}
+
+impl FullTextIndex {
+    /// Copies the files of the last commit to `staging`, returning their
+    /// manifest; merges may rewrite `meta.json` at any time, so the
+    /// segments are those of the copy.
+    fn stage(&self, staging: &Path) -> Result<Manifest, String> {
+        let path = self.path.as_ref().ok_or("the index is in memory")?;
+        let _writer = self.writer.lock().unwrap();
+        let meta = fs::read_to_string(path.join(META)).map_err(|e| format!("could not read {}: {}", META, e))?;
+        let segments: HashSet<String> = serde_json::from_str::<serde_json::Value>(&meta)
+            .map_err(|e| format!("invalid {}: {}", META, e))?["segments"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|segment| segment["segment_id"].as_str())
+            .map(|id| id.replace('-', ""))
+            .collect();
+
+        let mut files = vec![];
+        for entry in fs::read_dir(path).map_err(|e| e.to_string())? {
+            let name = entry.map_err(|e| e.to_string())?.file_name().to_string_lossy().into_owned();
+            if name.split('.').next().is_some_and(|segment| segments.contains(segment)) {
+                fs::copy(path.join(&name), staging.join(&name)).map_err(|e| format!("could not stage {}: {}", name, e))?;
+                files.push(name);
+            }
+        }
+        files.sort();
+        Ok(Manifest { files, meta })
+    }
+
+    /// Saves the index to `store`, then removes the files of the previous
+    /// snapshot no longer needed.
+    pub async fn snapshot(&self, store: &dyn BlobStore) -> Result<Snapshot, String> {
+        let staging = std::env::temp_dir().join(format!(".search-index-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+        let result = self.upload(&staging, store).await;
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    async fn upload(&self, staging: &Path, store: &dyn BlobStore) -> Result<Snapshot, String> {
+        let seq = self.seq().map_err(|e| e.to_string())?.unwrap_or(0);
+        let manifest = self.stage(staging)?;
+        let previous = read_manifest(store).await?;
+        for name in &manifest.files {
+            store.put(&format!("{}/{}", SNAPSHOT_PREFIX, name), &staging.join(name)).await.map_err(|e| format!("could not store {}: {}", name, e))?;
+        }
+        let staged = staging.join("MANIFEST");
+        fs::write(&staged, serde_json::to_vec(&manifest).expect("manifests serialize to JSON")).map_err(|e| e.to_string())?;
+        store.put(MANIFEST, &staged).await.map_err(|e| format!("could not store the manifest: {}", e))?;
+
+        for name in previous.iter().flat_map(|previous| &previous.files).filter(|name| !manifest.files.contains(name)) {
+            if let Err(e) = store.delete(&format!("{}/{}", SNAPSHOT_PREFIX, name)).await {
+                log::warn!("Could not remove {} of the previous index snapshot: {}", name, e);
+            }
+        }
+        Ok(Snapshot { files: manifest.files.len(), seq })
+    }
+}
+
+async fn read_manifest(store: &dyn BlobStore) -> Result<Option<Manifest>, String> {
+    match store.get(MANIFEST, None).await {
+        Ok(content) => serde_json::from_slice(&content).map(Some).map_err(|e| format!("invalid index snapshot manifest: {}", e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("could not read the index snapshot manifest: {}", e)),
+    }
+}
+
+/// Restores the latest snapshot of `store` to `path` unless there's an
+/// index there already, telling whether there was one to restore.
+pub async fn restore(path: &Path, store: &dyn BlobStore) -> Result<bool, String> {
+    if path.join(META).exists() {
+        return Ok(false);
+    }
+    let Some(manifest) = read_manifest(store).await? else {
+        return Ok(false);
+    };
+
+    fs::create_dir_all(path).map_err(|e| e.to_string())?;
+    for name in &manifest.files {
+        let content = store.get(&format!("{}/{}", SNAPSHOT_PREFIX, name), None).await.map_err(|e| format!("could not fetch {}: {}", name, e))?;
+        fs::write(path.join(name), content).map_err(|e| format!("could not write {}: {}", name, e))?;
+    }
+    // Written last: the index is only valid once its segments are there.
+    fs::write(path.join(META), manifest.meta).map_err(|e| format!("could not write {}: {}", META, e))?;
+    Ok(true)
+}
+
+impl search::Index for FullTextIndex {
+    fn candidates(&self, query: &str, mandate: &str, fuzzy: bool, by_email: bool) -> Result<Vec<db::Person>, Status> {
+        self.find(query, mandate, fuzzy, by_email).map_err(|e| {
+            log::error!("Full-text search failed: {}", e);
+            Status::InternalServerError
+        })
+    }
+}
+
+/// Opens the index, restoring the latest snapshot if there's no index yet,
+/// and makes searches use it.
+pub fn fairing(config: FullTextConfig) -> AdHoc {
+    AdHoc::try_on_ignite("Full-text index", |rocket| async move {
+        if let (Some(path), Some(snapshots)) = (&config.path, &config.snapshots) {
+            match restore(Path::new(path), scheduled_export::store(snapshots).as_ref()).await {
+                Ok(true) => log::info!("Restored the full-text index from its snapshot"),
+                Ok(false) => {}
+                Err(e) => log::warn!("Could not restore the full-text index, rebuilding it: {}", e),
+            }
+        }
+
+        let repository = rocket.state::<Arc<dyn PersonRepository>>().expect("repository is managed").clone();
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let opened = rocket::tokio::task::spawn_blocking(move || {
+            let index = FullTextIndex::open(&config).map_err(|e| format!("could not open the index: {}", e))?;
+            index.prepare(repository.as_ref(), &db, &sealer)?;
+            Ok::<_, String>((Arc::new(index), config))
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        let (index, config) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                log::error!("Full-text index: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        rocket.state::<SearchIndex>().expect("search index is managed").set(index.clone());
+        Ok(rocket.manage(index.clone()).manage(config.clone()).mount("/", routes()).attach(indexer(index, config.poll_interval)))
+    })
+}
+
+/// Background worker applying new events to the index.
+fn indexer(index: Arc<FullTextIndex>, poll_interval: u64) -> AdHoc {
+    AdHoc::on_liftoff("Full-text indexer", move |rocket| Box::pin(async move {
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(poll_interval.max(1)));
+            while shutdown::tick(&mut interval, &shutdown).await {
+                let (index, db, sealer) = (index.clone(), db.clone(), sealer.clone());
+                match rocket::tokio::task::spawn_blocking(move || index.catch_up(&db, &sealer)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => log::warn!("Updating the full-text index failed: {}", e),
+                    Err(e) => log::error!("Full-text indexer panicked: {}", e),
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Reindexed {
+    pub indexed: usize,
+}
+
+#[post("/admin/search/reindex")]
+async fn reindex(_admin: Admin, index: &State<Arc<FullTextIndex>>, repository: &State<Arc<dyn PersonRepository>>, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<Reindexed>, Status> {
+    let (index, repository, db, sealer) = (index.inner().clone(), repository.inner().clone(), db.inner().clone(), sealer.inner().clone());
+    let indexed = rocket::tokio::task::spawn_blocking(move || {
+        let indexed = index.rebuild(repository.as_ref(), &db)?;
+        index.catch_up(&db, &sealer)?;
+        Ok::<_, String>(indexed)
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)?
+    .map_err(|e| {
+        log::error!("Rebuilding the full-text index failed: {}", e);
+        Status::InternalServerError
+    })?;
+    log::info!("Indexed {} elus for full-text search", indexed);
+
+    Ok(Json(Reindexed { indexed }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Snapshot {
+    pub files: usize,
+    /// Last event the snapshot holds.
+    pub seq: i64,
+}
+
+#[post("/admin/search/snapshot")]
+async fn snapshot(_admin: Admin, index: &State<Arc<FullTextIndex>>, config: &State<FullTextConfig>) -> Result<Json<Snapshot>, Status> {
+    let snapshots = config.snapshots.as_ref().ok_or(Status::NotFound)?;
+    if index.path.is_none() {
+        return Err(Status::Conflict);
+    }
+
+    let snapshot = index.snapshot(scheduled_export::store(snapshots).as_ref()).await.map_err(|e| {
+        log::error!("Snapshotting the full-text index failed: {}", e);
+        Status::InternalServerError
+    })?;
+    Ok(Json(snapshot))
+}
+
+fn routes() -> Vec<rocket::Route> {
+    routes![reindex, snapshot]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Hit;
+    use crate::storage::LocalStore;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_search() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("full_text.poll_interval", 1)), connection);
+        let search = |q: &str| -> Vec<(String, u32)> {
+            let hits: Vec<Hit> = client.get(format!("/elus/search?q={}", q)).dispatch().into_json().unwrap();
+            hits.into_iter().map(|hit| (hit.elu.name.to_string(), hit.score)).collect()
+        };
+
+        assert_eq!(search("pierre.durand@example.com")[0].0, "Pierre Durand");
+        assert_eq!(search("upon")[0].0, "Jean Dupont");
+        assert_eq!(search("maire")[0].0, "Jean Dupont");
+        assert_eq!(search("dupond&fuzzy=true")[0].0, "Jean Dupont");
+        assert!(search("dupond").is_empty());
+
+        let marc = json!({ "name": "Marc Dupuis", "email": "marc.dupuis@example.com", "mandates": [] });
+        client.post("/elus/create").json(&marc).dispatch();
+        client.delete("/elus/jean.dupont@example.com").header(admin()).dispatch();
+        let index = client.rocket().state::<Arc<FullTextIndex>>().unwrap();
+        index.catch_up(client.rocket().state::<DbConn>().unwrap(), client.rocket().state::<Sealer>().unwrap()).unwrap();
+        assert_eq!(search("dup").into_iter().map(|(name, _)| name).collect::<Vec<_>>(), ["Marc Dupuis"]);
+
+        assert_eq!(client.post("/admin/search/reindex").dispatch().status(), Status::Unauthorized);
+        let reindexed: Reindexed = client.post("/admin/search/reindex").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(reindexed.indexed, 3);
+    }
+
+    #[rocket::async_test]
+    async fn test_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        let repository = db::SqliteRepository::new(db.clone());
+        let config = |name: &str| FullTextConfig { path: Some(dir.path().join(name).to_str().unwrap().to_string()), ..Default::default() };
+        let store = LocalStore::new(&dir.path().join("snapshots"));
+
+        let index = FullTextIndex::open(&config("index")).unwrap();
+        index.prepare(&repository, &db, &Sealer::default()).unwrap();
+        let snapshot = index.snapshot(&store).await.unwrap();
+        assert!(snapshot.files > 0);
+
+        let restored = dir.path().join("restored");
+        assert!(restore(&restored, &store).await.unwrap());
+        assert!(!restore(&restored, &store).await.unwrap());
+        let index = FullTextIndex::open(&config("restored")).unwrap();
+        assert_eq!(index.seq().unwrap(), Some(snapshot.seq));
+        let found = index.find("dupont", "", false, false).unwrap();
+        assert_eq!(found.iter().map(|person| person.name.to_string()).collect::<Vec<_>>(), ["Jean Dupont"]);
+    }
+}
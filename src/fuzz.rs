@@ -0,0 +1,113 @@
+//! Fuzzing of the parsers fed by callers: the JSON payload of
+//! `POST /elus/create` and the files of the importers, down to the
+//! validation and creation of their records. Inputs are mutations of
+//! valid samples, so that they get past the first bytes of the parsers;
+//! a panic fails the test with the input which caused it. The crate being
+//! a binary, which `cargo fuzz` targets can't link against, they run as
+//! tests from a fixed seed, as many iterations as `FUZZ_ITERATIONS` says
+//! from the seed `FUZZ_SEED` says for longer or other runs.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rocket::http::{ContentType, Status};
+
+use crate::import;
+use crate::mandate_types::MandateTypes;
+use crate::repository::MemoryRepository;
+use crate::tests::{admin, client, setup_test_db};
+
+/// Iterations of each target when `FUZZ_ITERATIONS` isn't set.
+const ITERATIONS: usize = 300;
+
+/// Fragments which parsers give meaning to, spliced into the samples.
+const DICTIONARY: &[&str] = &[
+    "\"", "\\", ",", ";", "|", ":", "::", ":<", "\n", "\r\n", "\n ", "{", "}", "[", "]", "null", "true", "-1", "1e999", "NaN", "é",
+    "\u{feff}", "\u{0}", "@", "custom.", "dn: ", "=", "$",
+];
+
+fn setting(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn mutate(sample: &[u8], rng: &mut impl Rng) -> Vec<u8> {
+    let mut input = sample.to_vec();
+    for _ in 0..rng.gen_range(1..=8) {
+        let at = rng.gen_range(0..=input.len());
+        match rng.gen_range(0..6) {
+            0 if at < input.len() => input[at] ^= 1 << rng.gen_range(0..8),
+            1 => input.splice(at..at, DICTIONARY.choose(rng).unwrap().bytes()).for_each(drop),
+            2 => {
+                let end = rng.gen_range(at..=input.len().min(at + 16));
+                input.drain(at..end);
+            }
+            3 => {
+                let end = rng.gen_range(at..=input.len().min(at + 32));
+                let copy = input[at..end].to_vec();
+                input.splice(at..at, copy).for_each(drop);
+            }
+            4 => input.truncate(at),
+            _ => input.insert(at, rng.gen()),
+        }
+    }
+    input
+}
+
+/// Feeds `target` mutations of the samples, failing with the input of the
+/// first panic.
+fn fuzz(samples: &[&str], mut target: impl FnMut(&[u8])) {
+    let seed = setting("FUZZ_SEED", 0);
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..setting("FUZZ_ITERATIONS", ITERATIONS as u64) {
+        let input = mutate(samples.choose(&mut rng).unwrap().as_bytes(), &mut rng);
+        if panic::catch_unwind(AssertUnwindSafe(|| target(&input))).is_err() {
+            panic!("panicked on {:?} (FUZZ_SEED={})", String::from_utf8_lossy(&input), seed);
+        }
+    }
+}
+
+#[test]
+fn fuzz_create_payload() {
+    let client = client(setup_test_db());
+    let samples = [
+        r#"{"name":"Jean Dupont","email":"jean@example.com","mandates":["Maire","Conseiller régional"],"commune_code":"75056"}"#,
+        r#"{"name":"Marie Martin","email":"marie@example.com","emails":["m.martin@example.org"],"mandates":[],"latitude":48.85,"longitude":2.35,"may_contact_by_email":true,"consent_date":"2025-01-01T00:00:00Z","visibility":"internal","custom":{"siege":"12"}}"#,
+    ];
+    fuzz(&samples, |input| {
+        let response = client.post("/elus/create").header(admin()).header(ContentType::JSON).body(input).dispatch();
+        // Rocket answers panicking handlers with a 500.
+        assert_ne!(response.status(), Status::InternalServerError);
+    });
+}
+
+#[test]
+fn fuzz_import() {
+    let db = Arc::new(Mutex::new(setup_test_db()));
+    let repository = MemoryRepository::default();
+    let mandate_types = MandateTypes::default();
+    let samples = [
+        ("csv", "name,email,mandates,latitude,extra,custom.siege\n\"Dupont, Jean\",jean@example.com,maire|Conseiller régional,48.85,x,12\nMarie,,,nord,,\n"),
+        ("json", r#"[{"name":"Jean Dupont","email":"jean@example.com","mandates":["Maire"]},{"name":"Marie Martin","email":"marie@example.com"}]"#),
+        (
+            "rne",
+            "\u{feff}Code du département;Code de la commune;Libellé de la commune;Nom de l'élu;Prénom de l'élu;Code sexe;Libellé de la fonction\n\
+             75;75056;Paris;LE GALL-D'ARC;Anne;F;Maire\n75;75056;Paris;MARTIN;Luc;M;\n",
+        ),
+        (
+            "ldif",
+            "version: 1\n\ndn: cn=Jean Dupont,ou=elus,dc=mairie,dc=example\ncn: Jean Dupont\nmail: jean@example.com\n\
+             title: Maire\ntitle:: Q29uc2VpbGxlciByw6lnaW9uYWw=\npostalAddress: Place de l'H\n \u{f4}tel de Ville$75004 Paris\n",
+        ),
+    ];
+    for (format, sample) in samples {
+        let importer = import::importer(format).unwrap();
+        fuzz(&[sample], |input| {
+            if let Ok(records) = importer.records(&String::from_utf8_lossy(input)) {
+                import::run(&records, &repository, &mandate_types, &db, |_| {});
+            }
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Uri};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rocket::serde::json::Value;
+
+use crate::config::AppConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Resolves a postal address to coordinates. Geocoding is best-effort: an
+/// address which can't be resolved is stored without coordinates.
+#[rocket::async_trait]
+pub trait Geocoder: Send + Sync {
+    async fn geocode(&self, address: &str) -> Option<Coordinates>;
+}
+
+pub struct NoGeocoder;
+
+#[rocket::async_trait]
+impl Geocoder for NoGeocoder {
+    async fn geocode(&self, _address: &str) -> Option<Coordinates> {
+        None
+    }
+}
+
+/// Geocoder for addok, the engine behind the French Base Adresse Nationale.
+pub struct AddokGeocoder {
+    base_url: String,
+    client: Client<HttpConnector, Body>,
+}
+
+impl AddokGeocoder {
+    pub fn new(base_url: &str) -> Self {
+        AddokGeocoder {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+        }
+    }
+
+    async fn search(&self, address: &str) -> Result<Option<Coordinates>, String> {
+        let uri: Uri = format!("{}/search/?limit=1&q={}", self.base_url, utf8_percent_encode(address, NON_ALPHANUMERIC))
+            .parse()
+            .map_err(|e| format!("invalid geocoder URL: {}", e))?;
+
+        let response = self.client.get(uri).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("geocoder returned {}", response.status()));
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+
+        parse_addok_response(&body)
+    }
+}
+
+#[rocket::async_trait]
+impl Geocoder for AddokGeocoder {
+    async fn geocode(&self, address: &str) -> Option<Coordinates> {
+        self.search(address)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to geocode {:?}: {}", address, e);
+                None
+            })
+    }
+}
+
+/// Extracts the best match from an addok GeoJSON FeatureCollection.
+fn parse_addok_response(body: &[u8]) -> Result<Option<Coordinates>, String> {
+    let collection: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+
+    let Some(feature) = collection["features"].get(0) else {
+        return Ok(None);
+    };
+
+    // GeoJSON positions are (longitude, latitude).
+    let position = &feature["geometry"]["coordinates"];
+    match (position[1].as_f64(), position[0].as_f64()) {
+        (Some(latitude), Some(longitude)) => Ok(Some(Coordinates { latitude, longitude })),
+        _ => Err("feature without point geometry".to_string()),
+    }
+}
+
+pub fn from_config(config: &AppConfig) -> Box<dyn Geocoder> {
+    match &config.geocoder_url {
+        Some(url) => Box::new(AddokGeocoder::new(url)),
+        None => Box::new(NoGeocoder),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_addok_response() {
+        let body = br#"{"type": "FeatureCollection", "features": [{"type": "Feature",
+            "geometry": {"type": "Point", "coordinates": [2.351, 48.857]},
+            "properties": {"label": "Place de l'Hotel de Ville 75004 Paris", "score": 0.93}}]}"#;
+        assert_eq!(
+            parse_addok_response(body),
+            Ok(Some(Coordinates { latitude: 48.857, longitude: 2.351 }))
+        );
+
+        assert_eq!(parse_addok_response(br#"{"type": "FeatureCollection", "features": []}"#), Ok(None));
+        assert!(parse_addok_response(b"<html>").is_err());
+    }
+}
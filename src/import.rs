@@ -0,0 +1,541 @@
+//! Bulk import of elus from the formats they come in: this API's CSV and
+//! JSON, the ministry of the Interior's Répertoire national des élus (RNE)
+//! and LDIF exports of LDAP directories. Each format has an `Importer`
+//! turning the file into records shaped like the `Person` schema; every
+//! record then goes through the same pipeline: validation against the
+//! schema, then creation, or update of the elu with the same email.
+//!
+//! Records without an email, such as those of the RNE which doesn't
+//! publish any, update the elu of the same name in the same commune if
+//! there is exactly one. Office addresses aren't geocoded on import.
+
+use std::sync::Arc;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::Serialize;
+use rocket::State;
+use serde_json::Map;
+
+use crate::auth::Admin;
+use crate::communes::split_delimited;
+use crate::config::AppConfig;
+use crate::custom_fields::{self, CustomField};
+use crate::mandate_types::MandateTypes;
+use crate::problem::Violation;
+use crate::repository::{PersonFilter, PersonKey, PersonRepository};
+use crate::transaction::Transaction;
+use crate::{db, openapi, person_name, settings, timeouts, validation, DbConn, Person};
+
+/// A format elus can be imported from.
+pub trait Importer: Send + Sync {
+    /// The records of the file, as JSON objects shaped like the `Person`
+    /// schema.
+    fn records(&self, content: &str) -> Result<Vec<Value>, String>;
+}
+
+/// The importer of a `format` query parameter.
+pub fn importer(format: &str) -> Option<Box<dyn Importer>> {
+    match format {
+        "csv" => Some(Box::new(CsvImporter)),
+        "json" => Some(Box::new(JsonImporter)),
+        "rne" => Some(Box::new(RneImporter)),
+        "ldif" => Some(Box::new(LdifImporter)),
+        _ => None,
+    }
+}
+
+/// Lines of a delimited file, the header first, without the blank ones.
+fn lines(content: &str) -> impl Iterator<Item = &str> {
+    content.trim_start_matches('\u{feff}').lines().filter(|line| !line.trim().is_empty())
+}
+
+/// Sets `field` unless `value` is empty.
+fn set(record: &mut Map<String, Value>, field: &str, value: &str) {
+    let value = value.trim();
+    if !value.is_empty() {
+        record.insert(field.to_string(), json!(value));
+    }
+}
+
+/// Comma-separated columns named after the fields of `Person`, mandates
+/// being separated by `|` and custom fields being `custom.<name>`; unknown
+/// columns are ignored.
+pub struct CsvImporter;
+
+impl Importer for CsvImporter {
+    fn records(&self, content: &str) -> Result<Vec<Value>, String> {
+        let mut lines = lines(content);
+        let header = split_delimited(lines.next().ok_or("empty file")?, ',');
+
+        Ok(lines
+            .map(|line| {
+                let mut record = Map::new();
+                for (column, value) in header.iter().zip(split_delimited(line, ',')) {
+                    match column.trim() {
+                        "mandates" => {
+                            record.insert("mandates".to_string(), value.split('|').map(str::trim).filter(|mandate| !mandate.is_empty()).collect());
+                        }
+                        // Left as strings when they aren't numbers, for validation to report.
+                        "latitude" | "longitude" => match value.trim().parse::<f64>() {
+                            Ok(number) => {
+                                record.insert(column.trim().to_string(), json!(number));
+                            }
+                            Err(_) => set(&mut record, column.trim(), &value),
+                        },
+                        column @ ("name" | "email" | "commune_code" | "office_address") => set(&mut record, column, &value),
+                        column if let Some(field) = column.strip_prefix("custom.") && !value.trim().is_empty() => {
+                            let custom = record.entry("custom").or_insert_with(|| json!({}));
+                            custom[field] = json!(value.trim());
+                        }
+                        _ => {}
+                    }
+                }
+                Value::Object(record)
+            })
+            .collect())
+    }
+}
+
+/// An array of persons, or a page of the `/elus` listing.
+pub struct JsonImporter;
+
+impl Importer for JsonImporter {
+    fn records(&self, content: &str) -> Result<Vec<Value>, String> {
+        match serde_json::from_str(content).map_err(|e| e.to_string())? {
+            Value::Array(records) => Ok(records),
+            Value::Object(mut page) => match page.remove("elus") {
+                Some(Value::Array(records)) => Ok(records),
+                _ => Err("expected an array of persons".to_string()),
+            },
+            _ => Err("expected an array of persons".to_string()),
+        }
+    }
+}
+
+/// Family names are in capitals in the RNE.
+fn capitalize(name: &str) -> String {
+    if name.chars().any(char::is_lowercase) {
+        return name.to_string();
+    }
+
+    let mut capitalized = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        if word_start {
+            capitalized.extend(c.to_uppercase());
+        } else {
+            capitalized.extend(c.to_lowercase());
+        }
+        word_start = matches!(c, ' ' | '-' | '\'');
+    }
+    capitalized
+}
+
+/// Semicolon-separated files of the Répertoire national des élus, such as
+/// `elus-maires.csv`. The RNE has no emails: an `Adresse électronique`
+/// column is used if one was added.
+pub struct RneImporter;
+
+impl Importer for RneImporter {
+    fn records(&self, content: &str) -> Result<Vec<Value>, String> {
+        let mut lines = lines(content);
+        let header = split_delimited(lines.next().ok_or("empty file")?, ';');
+        let column = |name: &str| header.iter().position(|field| field.trim() == name);
+        let required = |name: &str| column(name).ok_or(format!("missing column {}", name));
+        let (family_name, given_name, commune) = (required("Nom de l'élu")?, required("Prénom de l'élu")?, required("Code de la commune")?);
+        let (function, sex, email) = (column("Libellé de la fonction"), column("Code sexe"), column("Adresse électronique"));
+
+        Ok(lines
+            .map(|line| {
+                let fields = split_delimited(line, ';');
+                let field = |column: usize| fields.get(column).map(|field| field.trim()).unwrap_or_default();
+                let optional = |column: Option<usize>| column.map(field).unwrap_or_default();
+                let mandate = match (optional(function), optional(sex)) {
+                    ("", "F") => "Conseillère municipale",
+                    ("", _) => "Conseiller municipal",
+                    (function, _) => function,
+                };
+
+                let mut record = Map::new();
+                set(&mut record, "name", &format!("{} {}", field(given_name), capitalize(field(family_name))));
+                set(&mut record, "email", optional(email));
+                record.insert("mandates".to_string(), json!([mandate]));
+                set(&mut record, "commune_code", field(commune));
+                Value::Object(record)
+            })
+            .collect())
+    }
+}
+
+/// The entries of an LDIF file, as their attributes, names lowercased and
+/// without options.
+fn parse_ldif(content: &str) -> Result<Vec<Vec<(String, String)>>, String> {
+    // Continuation lines start with a space.
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines().map(|line| line.trim_end_matches('\r')) {
+        match (line.strip_prefix(' '), lines.last_mut()) {
+            (Some(continuation), Some(last)) if !last.is_empty() => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut entry = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            if !entry.is_empty() {
+                entries.push(std::mem::take(&mut entry));
+            }
+            continue;
+        }
+        if line.starts_with('#') || line.starts_with("version:") {
+            continue;
+        }
+
+        let (attribute, value) = line.split_once(':').ok_or(format!("line {}: expected an attribute", index + 1))?;
+        let attribute = attribute.split(';').next().unwrap_or_default().to_ascii_lowercase();
+        let value = match value.strip_prefix(':') {
+            Some(encoded) => BASE64_STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or(format!("line {}: invalid base64 value", index + 1))?,
+            None if value.starts_with('<') => return Err(format!("line {}: values from URLs aren't supported", index + 1)),
+            None => value.trim_start().to_string(),
+        };
+        entry.push((attribute, value));
+    }
+    if !entry.is_empty() {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// LDIF exports of LDAP directories: `cn` (or `displayName`), `mail`,
+/// `title` for each mandate and `postalAddress`. Entries with neither a
+/// name nor an email, such as organizational units, are skipped.
+pub struct LdifImporter;
+
+impl Importer for LdifImporter {
+    fn records(&self, content: &str) -> Result<Vec<Value>, String> {
+        Ok(parse_ldif(content)?
+            .into_iter()
+            .filter_map(|entry| {
+                let values = |attribute: &'static str| entry.iter().filter(move |(name, _)| name == attribute).map(|(_, value)| value.as_str());
+                let name = values("cn").next().or_else(|| values("displayname").next());
+                let email = values("mail").next();
+                if name.is_none() && email.is_none() {
+                    return None;
+                }
+
+                let mut record = Map::new();
+                set(&mut record, "name", name.unwrap_or_default());
+                set(&mut record, "email", email.unwrap_or_default());
+                record.insert("mandates".to_string(), values("title").collect());
+                if let Some(address) = values("postaladdress").next() {
+                    set(&mut record, "office_address", &address.split('$').map(str::trim).collect::<Vec<_>>().join(", "));
+                }
+                Some(Value::Object(record))
+            })
+            .collect())
+    }
+}
+
+/// A record which wasn't imported.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Rejection {
+    /// Position of the record in the file, from 1.
+    pub record: usize,
+    pub errors: Vec<Violation>,
+}
+
+/// How far an import got.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ImportProgress {
+    pub total: usize,
+    pub processed: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub rejected: Vec<Rejection>,
+}
+
+fn violation(pointer: &str, detail: impl Into<String>) -> Vec<Violation> {
+    vec![Violation { pointer: pointer.to_string(), detail: detail.into() }]
+}
+
+/// The email of the only elu of the record's name in its commune.
+fn resolve_email(record: &Value, repository: &dyn PersonRepository) -> Option<String> {
+    let name = person_name::normalize(record["name"].as_str()?);
+    let filter = PersonFilter { name: Some(name.clone()), commune_code: record["commune_code"].as_str().map(str::to_string), ..Default::default() };
+    let mut matches = repository.search(&filter).ok()?.into_iter().filter(|person| person.name.as_str() == name);
+    match (matches.next(), matches.next()) {
+        (Some(person), None) => Some(person.email.to_string()),
+        _ => None,
+    }
+}
+
+/// Validates and saves a record, returning whether it created an elu.
+fn import_record(mut record: Value, fields: &[CustomField], repository: &dyn PersonRepository, mandate_types: &MandateTypes, db: &DbConn) -> Result<bool, Vec<Violation>> {
+    if record.get("email").is_none() {
+        if let Some(email) = resolve_email(&record, repository) {
+            record["email"] = json!(email);
+        }
+    }
+
+    if let Some(custom) = record.get_mut("custom") {
+        custom_fields::coerce(custom, fields);
+    }
+
+    let mut violations = Vec::new();
+    validation::validate(&record, openapi::schema("Person").expect("schema is documented"), String::new(), &mut violations);
+    violations.extend(custom_fields::violations(&record["custom"], fields));
+    if !violations.is_empty() {
+        return Err(violations);
+    }
+    let person: Person = serde_json::from_value(record).map_err(|e| violation("", e.to_string()))?;
+    if let Some(code) = &person.commune_code {
+        db::get_commune(code, &mut *db::lock(db).map_err(|_| violation("/commune_code", "could not be checked"))?).map_err(|_| violation("/commune_code", "is not a known commune"))?;
+    }
+    let visibility = match person.visibility {
+        Some(visibility) => visibility,
+        None => settings::default_visibility(person.commune_code.as_deref(), &mut *db::lock(db).map_err(|_| violation("", "could not be saved"))?).map_err(|_| violation("", "could not be saved"))?,
+    };
+
+    let new_person = db::NewPerson {
+        mandates: person.mandates.iter().map(|mandate| mandate_types.title(mandate)).collect(),
+        name: person.name,
+        emails: db::other_emails(&person.email, &person.emails),
+        email: person.email,
+        commune_code: person.commune_code,
+        office_address: person.office_address,
+        latitude: person.latitude,
+        longitude: person.longitude,
+        may_contact_by_email: person.may_contact_by_email,
+        consent_date: person.consent_date,
+        consent_source: person.consent_source,
+        visibility,
+        custom: person.custom,
+    };
+    let saved = match repository.find(&PersonKey::Email(new_person.email.clone())) {
+        Ok(existing) => repository.update(&existing.email, new_person).map(|_| false),
+        Err(status) if status == Status::NotFound => repository.create(new_person).map(|_| true),
+        Err(status) => Err(status),
+    };
+    saved.map_err(|status| violation("", format!("could not be saved: {}", status.reason_lossy())))
+}
+
+/// Imports the records, calling `report` with the progress after each one.
+pub fn run(records: &[Value], repository: &dyn PersonRepository, mandate_types: &MandateTypes, db: &DbConn, mut report: impl FnMut(&ImportProgress)) -> ImportProgress {
+    let mut progress = ImportProgress { total: records.len(), ..Default::default() };
+    let fields = db::lock(db).map_err(Into::into).and_then(|mut connection| custom_fields::load(&mut connection));
+    for (index, record) in records.iter().enumerate() {
+        let imported = match &fields {
+            Ok(fields) => import_record(record.clone(), fields, repository, mandate_types, db),
+            Err(_) => Err(violation("/custom", "could not be checked")),
+        };
+        match imported {
+            Ok(true) => progress.created += 1,
+            Ok(false) => progress.updated += 1,
+            Err(errors) => progress.rejected.push(Rejection { record: index + 1, errors }),
+        }
+        progress.processed += 1;
+        report(&progress);
+    }
+
+    progress
+}
+
+/// Columns of the rejection reports, after the record's position and errors.
+pub const REPORT_COLUMNS: [&str; 7] = ["name", "email", "mandates", "commune_code", "office_address", "latitude", "longitude"];
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A JSON value as the CSV importer takes it, mandates separated by `|`.
+pub fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        Value::Array(mandates) => mandates.iter().map(|mandate| mandate.as_str().map(str::to_string).unwrap_or_else(|| mandate.to_string())).collect::<Vec<_>>().join("|"),
+        value => value.to_string(),
+    }
+}
+
+/// The rejected records with their errors, as CSV the CSV importer takes
+/// back once the records are fixed.
+pub fn rejection_report(records: &[Value], rejected: &[Rejection]) -> String {
+    let mut report = format!("record,errors,{}\n", REPORT_COLUMNS.join(","));
+    for rejection in rejected {
+        let errors: Vec<String> = rejection
+            .errors
+            .iter()
+            .map(|violation| match violation.pointer.trim_start_matches('/') {
+                "" => violation.detail.clone(),
+                field => format!("{} {}", field, violation.detail),
+            })
+            .collect();
+        let mut fields = vec![rejection.record.to_string(), errors.join("; ")];
+        let record = records.get(rejection.record - 1).unwrap_or(&Value::Null);
+        fields.extend(REPORT_COLUMNS.iter().map(|column| csv_value(&record[column])));
+        report.push_str(&fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
+        report.push('\n');
+    }
+
+    report
+}
+
+/// Imports the records of the file, skipping and reporting the rejected
+/// ones, in one transaction: an import failing midway leaves nothing
+/// behind.
+#[post("/elus/import?<format>", data = "<file>")]
+async fn import_elus(
+    format: &str,
+    file: Data<'_>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    mandate_types: &State<MandateTypes>,
+    config: &State<AppConfig>,
+) -> Result<Json<ImportProgress>, Status> {
+    let importer = importer(format).ok_or(Status::BadRequest)?;
+    let content = file.open(16.mebibytes()).into_string().await.map_err(|_| Status::BadRequest)?;
+    if !content.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let (db, repository, mandate_types, retry) = (db.inner().clone(), repository.inner().clone(), mandate_types.inner().clone(), config.busy_retry.clone());
+    let progress = timeouts::blocking(config.timeouts.request(), move || {
+        let records = importer.records(&content).map_err(|_| Status::UnprocessableEntity)?;
+        let transaction = Transaction::begin_blocking(&db, &retry)?;
+        let progress = run(&records, repository.as_ref(), &mandate_types, &db, |_| {});
+        transaction.commit()?;
+        Ok(progress)
+    })
+    .await?;
+
+    Ok(Json(progress))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![import_elus]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_csv_records() {
+        let csv = "name,email,mandates,latitude,extra,custom.siege\n\"Dupont, Jean\",jean@example.com,maire|Conseiller régional,48.85,x,12\nMarie,,,nord,,\n";
+        assert_eq!(
+            CsvImporter.records(csv).unwrap(),
+            vec![
+                json!({ "name": "Dupont, Jean", "email": "jean@example.com", "mandates": ["maire", "Conseiller régional"], "latitude": 48.85, "custom": { "siege": "12" } }),
+                json!({ "name": "Marie", "mandates": [], "latitude": "nord" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rne_records() {
+        let rne = "\u{feff}Code du département;Code de la commune;Libellé de la commune;Nom de l'élu;Prénom de l'élu;Code sexe;Libellé de la fonction\n\
+                   75;75056;Paris;LE GALL-D'ARC;Anne;F;Maire\n\
+                   75;75056;Paris;MARTIN;Luc;M;\n";
+        assert_eq!(
+            RneImporter.records(rne).unwrap(),
+            vec![
+                json!({ "name": "Anne Le Gall-D'Arc", "mandates": ["Maire"], "commune_code": "75056" }),
+                json!({ "name": "Luc Martin", "mandates": ["Conseiller municipal"], "commune_code": "75056" }),
+            ]
+        );
+        assert_eq!(RneImporter.records("Nom;Prénom\n").unwrap_err(), "missing column Nom de l'élu");
+    }
+
+    #[test]
+    fn test_ldif_records() {
+        let ldif = "version: 1\n\ndn: ou=elus,dc=mairie,dc=example\nou: elus\n\n\
+                    dn: cn=Jean Dupont,ou=elus,dc=mairie,dc=example\ncn: Jean Dupont\nmail: jean@example.com\n\
+                    title: Maire\ntitle:: Q29uc2VpbGxlciByw6lnaW9uYWw=\npostalAddress: Place de l'H\n \u{f4}tel de Ville$75004 Paris\n";
+        assert_eq!(
+            LdifImporter.records(ldif).unwrap(),
+            vec![json!({
+                "name": "Jean Dupont",
+                "email": "jean@example.com",
+                "mandates": ["Maire", "Conseiller régional"],
+                "office_address": "Place de l'Hôtel de Ville, 75004 Paris",
+            })]
+        );
+        assert!(LdifImporter.records("cn:< file:///etc/passwd\n").is_err());
+    }
+
+    #[test]
+    fn test_rejection_report() {
+        let records = [json!({ "name": "A" }), json!({ "name": "Dupont, \"Jean\"", "mandates": ["Maire", "Adjoint"], "latitude": 48.5 })];
+        let rejected = [Rejection {
+            record: 2,
+            errors: vec![
+                Violation { pointer: "/email".to_string(), detail: "is required".to_string() },
+                Violation { pointer: String::new(), detail: "could not be saved".to_string() },
+            ],
+        }];
+        let report = rejection_report(&records, &rejected);
+        assert_eq!(
+            report,
+            "record,errors,name,email,mandates,commune_code,office_address,latitude,longitude\n\
+             2,email is required; could not be saved,\"Dupont, \"\"Jean\"\"\",,Maire|Adjoint,,,48.5,\n"
+        );
+        assert_eq!(CsvImporter.records(&report).unwrap(), vec![records[1].clone()]);
+    }
+
+    #[test]
+    fn test_import_elus() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        let client = client(connection);
+
+        let json = json!([
+            { "name": "Jean Dupont", "email": "jean.dupont@example.com", "mandates": ["maire"], "commune_code": "75056" },
+            { "name": "Claire Lune", "email": "claire@example.com", "mandates": ["Adjointe au maire"] },
+            { "name": "", "email": "nobody", "mandates": [] },
+        ]);
+        let response = client.post("/elus/import?format=json").header(admin()).body(json.to_string()).dispatch();
+        let progress: Value = response.into_json().unwrap();
+        assert_eq!((progress["total"].as_u64(), progress["created"].as_u64(), progress["updated"].as_u64()), (Some(3), Some(1), Some(1)));
+        assert_eq!(progress["rejected"][0]["record"], 3);
+        assert_eq!(progress["rejected"][0]["errors"][0]["pointer"], "/email");
+
+        // Without an email, Jean Dupont is found by name.
+        let csv = "name,mandates,commune_code\nJean Dupont,maire|conseiller_regional,75056\nInconnu,maire,75056\n";
+        let progress: Value = client.post("/elus/import?format=csv").header(admin()).body(csv).dispatch().into_json().unwrap();
+        assert_eq!((progress["updated"].as_u64(), progress["rejected"][0]["record"].as_u64()), (Some(1), Some(2)));
+        let jean: Value = client.get("/elus/jean.dupont@example.com").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(jean["mandates"], json!(["Maire", "Conseiller régional"]));
+
+        // Custom fields are typed from their definitions.
+        let siege = json!({ "name": "siege", "type": "integer", "minimum": 1 });
+        assert_eq!(client.post("/custom-fields").header(admin()).json(&siege).dispatch().status(), Status::Created);
+        let csv = "name,email,mandates,custom.siege,custom.bureau\nJean Dupont,jean.dupont@example.com,maire,12,\nClaire Lune,claire@example.com,maire,0,oui\n";
+        let progress: Value = client.post("/elus/import?format=csv").header(admin()).body(csv).dispatch().into_json().unwrap();
+        assert_eq!(progress["updated"], 1);
+        assert_eq!(progress["rejected"][0]["errors"], json!([{ "pointer": "/custom/bureau", "detail": "is not a custom field" }, { "pointer": "/custom/siege", "detail": "must be at least 1" }]));
+        let jean: Value = client.get("/elus/jean.dupont@example.com").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(jean["custom"], json!({ "siege": 12 }));
+
+        assert_eq!(client.post("/elus/import?format=xlsx").header(admin()).body("").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.post("/elus/import?format=json").body("[]").dispatch().status(), Status::Unauthorized);
+    }
+}
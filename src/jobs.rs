@@ -0,0 +1,402 @@
+//! Background jobs, for imports too large to be run while the client
+//! waits: `POST /jobs/import` queues the file and answers with the job
+//! right away, its progress and result being polled from `GET /jobs/<id>`
+//! or followed as server-sent events from `GET /jobs/<id>/events`. The
+//! records an import rejected can be downloaded once it is finished from
+//! `GET /jobs/<id>/errors.csv`, to be fixed and imported again.
+//! Jobs are kept in the database, so that those queued or interrupted by a
+//! restart are run once the server is back; imports being upserts, running
+//! one again from the start is harmless.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::data::{Data, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::{Deserialize, Serialize, Serializer};
+use rocket::{Shutdown, State};
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::encryption::Sealer;
+use crate::import::{self, ImportProgress};
+use crate::mandate_types::MandateTypes;
+use crate::repository::PersonRepository;
+use crate::schema::jobs;
+use crate::{db, shutdown, timeouts, timestamp, DbConn};
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const SUCCEEDED: &str = "succeeded";
+pub const FAILED: &str = "failed";
+
+pub const IMPORT: &str = "import";
+
+/// Records imported between two saves of a job's progress.
+const PROGRESS_INTERVAL: usize = 100;
+
+/// How often event streams check for new progress.
+const EVENTS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct JobsConfig {
+    /// Seconds between two checks for queued jobs; 0 disables the worker.
+    pub poll_interval: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig { poll_interval: 5 }
+    }
+}
+
+/// Serializes the JSON text stored in the database as the JSON it holds.
+fn raw_json<S: Serializer>(json: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    use rocket::serde::ser::Error;
+
+    json.as_deref().map(serde_json::from_str::<Value>).transpose().map_err(S::Error::custom)?.serialize(serializer)
+}
+
+#[derive(Debug, Serialize, Queryable, Selectable)]
+#[diesel(table_name = jobs)]
+#[serde(crate = "rocket::serde")]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub format: String,
+    pub status: String,
+    /// `ImportProgress` of imports, as of the last save while running.
+    #[serde(serialize_with = "raw_json")]
+    pub progress: Option<String>,
+    pub error: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub started_at: Option<PrimitiveDateTime>,
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub finished_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = jobs)]
+struct NewJob<'a> {
+    kind: &'a str,
+    format: &'a str,
+    input: &'a str,
+}
+
+/// Queues a job, its input sealed.
+pub fn enqueue(kind: &str, format: &str, input: &str, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Job> {
+    diesel::insert_into(jobs::table)
+        .values(NewJob { kind, format, input: &sealer.seal(input) })
+        .returning(Job::as_returning())
+        .get_result(connection)
+}
+
+pub fn get(id: i32, connection: &mut SqliteConnection) -> QueryResult<Job> {
+    jobs::table.find(id).select(Job::as_select()).first(connection)
+}
+
+/// Queues again the jobs which were running when the server stopped.
+pub fn requeue_interrupted(connection: &mut SqliteConnection) -> QueryResult<usize> {
+    diesel::update(jobs::table.filter(jobs::status.eq(RUNNING)))
+        .set((jobs::status.eq(QUEUED), jobs::started_at.eq(None::<PrimitiveDateTime>)))
+        .execute(connection)
+}
+
+fn save_progress(id: i32, progress: &ImportProgress, connection: &mut SqliteConnection) -> QueryResult<usize> {
+    let progress = serde_json::to_string(progress).expect("progress serializes");
+    diesel::update(jobs::table.find(id)).set(jobs::progress.eq(progress)).execute(connection)
+}
+
+/// Runs the oldest queued job to completion, returning its id, or `None`
+/// when there is nothing to run.
+pub fn run_next(db: &DbConn, repository: &dyn PersonRepository, mandate_types: &MandateTypes, sealer: &Sealer) -> QueryResult<Option<i32>> {
+    let next = jobs::table
+        .filter(jobs::status.eq(QUEUED))
+        .order(jobs::id)
+        .select((jobs::id, jobs::format, jobs::input))
+        .first::<(i32, String, String)>(&mut *db::lock(db)?)
+        .optional()?;
+    let Some((id, format, input)) = next else {
+        return Ok(None);
+    };
+
+    diesel::update(jobs::table.find(id))
+        .set((jobs::status.eq(RUNNING), jobs::started_at.eq(timestamp::now())))
+        .execute(&mut *db::lock(db)?)?;
+
+    let records = import::importer(&format)
+        .ok_or_else(|| format!("unknown format {}", format))
+        .and_then(|importer| importer.records(&sealer.open(&input).map_err(|_| "undecryptable input".to_string())?));
+    let result = records.map(|records| {
+        let progress = import::run(&records, repository, mandate_types, db, |progress| {
+            if progress.processed % PROGRESS_INTERVAL == 0 {
+                if let Err(e) = db::lock(db).map_err(Into::into).and_then(|mut connection| save_progress(id, progress, &mut connection)) {
+                    log::warn!("Could not save the progress of job {}: {}", id, e);
+                }
+            }
+        });
+        let report = (!progress.rejected.is_empty()).then(|| sealer.seal(&import::rejection_report(&records, &progress.rejected)));
+        (progress, report)
+    });
+
+    let mut connection = db::lock(db)?;
+    let finished = jobs::table.find(id);
+    match result {
+        Ok((progress, report)) => {
+            save_progress(id, &progress, &mut connection)?;
+            diesel::update(finished)
+                .set((jobs::status.eq(SUCCEEDED), jobs::rejection_report.eq(report), jobs::finished_at.eq(timestamp::now())))
+                .execute(&mut *connection)?
+        }
+        Err(e) => diesel::update(finished)
+            .set((jobs::status.eq(FAILED), jobs::error.eq(e), jobs::finished_at.eq(timestamp::now())))
+            .execute(&mut *connection)?,
+    };
+
+    Ok(Some(id))
+}
+
+/// Background worker running queued jobs, one at a time.
+pub fn fairing(config: JobsConfig) -> AdHoc {
+    AdHoc::on_liftoff("Job worker", move |rocket| Box::pin(async move {
+        if config.poll_interval == 0 {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let repository = rocket.state::<Arc<dyn PersonRepository>>().expect("repository is managed").clone();
+        let mandate_types = rocket.state::<MandateTypes>().expect("mandate types are managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        match db::lock(&db).map_err(Into::into).and_then(|mut connection| requeue_interrupted(&mut connection)) {
+            Ok(0) => {}
+            Ok(requeued) => log::info!("Resuming {} jobs interrupted by the last shutdown", requeued),
+            Err(e) => log::error!("Could not requeue interrupted jobs: {}", e),
+        }
+
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval));
+            // Jobs left when shutting down are run after the next start.
+            while shutdown::tick(&mut interval, &shutdown).await {
+                loop {
+                    let (db, repository, mandate_types, sealer) = (db.clone(), repository.clone(), mandate_types.clone(), sealer.clone());
+                    let run = rocket::tokio::task::spawn_blocking(move || run_next(&db, repository.as_ref(), &mandate_types, &sealer));
+                    match rocket::tokio::time::timeout(job, run).await {
+                        Ok(Ok(Ok(Some(_)))) => {}
+                        Ok(Ok(Ok(None))) => break,
+                        Ok(Ok(Err(e))) => {
+                            log::error!("Job run failed: {}", e);
+                            break;
+                        }
+                        Ok(Err(e)) => {
+                            log::error!("Job run panicked: {}", e);
+                            break;
+                        }
+                        Err(_) => log::warn!("Job still running after {} seconds, no longer waiting for it", job.as_secs()),
+                    }
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[derive(Responder)]
+#[response(status = 202)]
+struct Accepted(Json<Job>, Header<'static>);
+
+/// Queues the import of a file in one of the formats of `POST /elus/import`.
+#[post("/jobs/import?<format>", data = "<file>")]
+async fn import_job(format: &str, file: Data<'_>, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Accepted, Status> {
+    import::importer(format).ok_or(Status::BadRequest)?;
+    let content = file.open(64.mebibytes()).into_string().await.map_err(|_| Status::BadRequest)?;
+    if !content.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let job = enqueue(IMPORT, format, &content, sealer, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+    let location = Header::new("Location", format!("/jobs/{}", job.id));
+    Ok(Accepted(Json(job), location))
+}
+
+fn found<T>(result: QueryResult<T>) -> Result<T, Status> {
+    result.map_err(|e| match e {
+        diesel::result::Error::NotFound => Status::NotFound,
+        _ => Status::InternalServerError,
+    })
+}
+
+#[get("/jobs/<id>")]
+fn get_job(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Json<Job>, Status> {
+    found(get(id, &mut *db::lock(db)?)).map(Json)
+}
+
+#[derive(Responder)]
+struct Attachment(String, ContentType, Header<'static>);
+
+/// The records a finished import rejected, with the reasons; 404 if there
+/// was none.
+#[get("/jobs/<id>/errors.csv")]
+fn job_errors(id: i32, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Attachment, Status> {
+    let report: Option<String> = found(jobs::table.find(id).select(jobs::rejection_report).first(&mut *db::lock(db)?))?;
+    let report = report.map(|report| sealer.open(&report)).transpose().map_err(|_| Status::InternalServerError)?;
+    let disposition = Header::new("Content-Disposition", format!("attachment; filename=\"job-{}-errors.csv\"", id));
+    report.map(|report| Attachment(report, ContentType::CSV, disposition)).ok_or(Status::NotFound)
+}
+
+/// The state of a job as streamed: its counts so far, without the
+/// rejections themselves, which can be many.
+fn summary(job: &Job) -> Value {
+    let progress: Value = job.progress.as_deref().and_then(|progress| serde_json::from_str(progress).ok()).unwrap_or_default();
+    json!({
+        "status": job.status,
+        "total": progress["total"],
+        "processed": progress["processed"],
+        "created": progress["created"],
+        "updated": progress["updated"],
+        "rejected": progress["rejected"].as_array().map(Vec::len),
+        "error": job.error,
+    })
+}
+
+/// Streams the progress of a job, as an event named after its status each
+/// time it changed, until it is finished.
+#[get("/jobs/<id>/events")]
+fn job_events(id: i32, _admin: Admin, db: &State<DbConn>, shutdown: Shutdown) -> Result<EventStream![], Status> {
+    found(get(id, &mut *db::lock(db)?))?;
+
+    let db = db.inner().clone();
+    Ok(EventStream! {
+        let mut interval = rocket::tokio::time::interval(EVENTS_INTERVAL);
+        let mut last = Value::Null;
+        while shutdown::tick(&mut interval, &shutdown).await {
+            let Some(job) = db::lock(&db).ok().and_then(|mut connection| get(id, &mut connection).ok()) else {
+                break;
+            };
+            let current = summary(&job);
+            if current != last {
+                yield Event::json(&current).event(job.status.clone());
+                last = current;
+            }
+            if job.status == SUCCEEDED || job.status == FAILED {
+                break;
+            }
+        }
+    })
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![import_job, get_job, job_events, job_errors]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::Cipher;
+    use crate::repository::PersonKey;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_interrupted_jobs_run_again() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let mandate_types = MandateTypes::load(&mut connection).unwrap();
+        let sealer = Sealer::new(Cipher::new(&[7; 32]));
+        let job = enqueue(IMPORT, "csv", "name,email,mandates\nClaire Lune,claire@example.com,maire\n", &sealer, &mut connection).unwrap();
+        let failing = enqueue(IMPORT, "rne", "Nom;Prénom\n", &sealer, &mut connection).unwrap();
+        let input: String = jobs::table.find(job.id).select(jobs::input).first(&mut connection).unwrap();
+        assert!(!input.contains("claire"));
+        diesel::update(jobs::table.find(job.id)).set(jobs::status.eq(RUNNING)).execute(&mut connection).unwrap();
+        let db: DbConn = Arc::new(std::sync::Mutex::new(connection));
+        let repository = crate::db::SqliteRepository::new(db.clone());
+
+        // A job left running by a restart isn't picked up until requeued.
+        assert_eq!(run_next(&db, &repository, &mandate_types, &sealer), Ok(Some(failing.id)));
+        assert_eq!(run_next(&db, &repository, &mandate_types, &sealer), Ok(None));
+        assert_eq!(requeue_interrupted(&mut db.lock().unwrap()), Ok(1));
+        assert_eq!(run_next(&db, &repository, &mandate_types, &sealer), Ok(Some(job.id)));
+
+        let job = get(job.id, &mut db.lock().unwrap()).unwrap();
+        assert_eq!(job.status, SUCCEEDED);
+        assert!(job.finished_at.is_some());
+        assert_eq!(repository.find(&PersonKey::Email("claire@example.com".parse().unwrap())).unwrap().mandates, ["Maire"]);
+        let failing = get(failing.id, &mut db.lock().unwrap()).unwrap();
+        assert_eq!((failing.status.as_str(), failing.error.as_deref()), (FAILED, Some("missing column Nom de l'élu")));
+    }
+
+    #[test]
+    fn test_job_endpoints() {
+        let client = client(setup_test_db());
+
+        let response = client.post("/jobs/import?format=json").header(admin()).body(json!([]).to_string()).dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response.headers().get_one("Location").unwrap().to_string();
+        let job: Value = response.into_json().unwrap();
+        assert_eq!((job["status"].as_str(), job["progress"].as_null()), (Some(QUEUED), Some(())));
+        assert_eq!(location, format!("/jobs/{}", job["id"]));
+
+        let response = client.get(location).header(admin()).dispatch();
+        assert_eq!(response.into_json::<Value>().unwrap()["kind"], IMPORT);
+
+        assert_eq!(client.get("/jobs/999").header(admin()).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/jobs/999/events").header(admin()).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/jobs/1").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/jobs/import?format=xlsx").header(admin()).body("").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_job_events() {
+        let mut connection = setup_test_db();
+        let mandate_types = MandateTypes::load(&mut connection).unwrap();
+        let client = client(connection);
+        let csv = "name,email,mandates\nClaire Lune,claire@example.com,maire\nInconnu,,maire\n";
+        let response = client.post("/jobs/import?format=csv").header(admin()).body(csv).dispatch();
+        let id = response.into_json::<Value>().unwrap()["id"].clone();
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let repository = client.rocket().state::<Arc<dyn PersonRepository>>().unwrap();
+        run_next(db, repository.as_ref(), &mandate_types, &Sealer::default()).unwrap();
+
+        // The stream of a finished job ends after its final state.
+        let response = client.get(format!("/jobs/{}/events", id)).header(admin()).dispatch();
+        assert_eq!(response.headers().get_one("Content-Type"), Some("text/event-stream; charset=utf-8"));
+        let body = response.into_string().unwrap();
+        assert_eq!(
+            body.lines().filter(|line| !line.is_empty() && !line.starts_with(':')).collect::<Vec<_>>(),
+            [
+                "event:succeeded",
+                r#"data:{"created":1,"error":null,"processed":2,"rejected":1,"status":"succeeded","total":2,"updated":0}"#,
+            ]
+        );
+
+        let response = client.get(format!("/jobs/{}/errors.csv", id)).header(admin()).dispatch();
+        assert_eq!(response.headers().get_one("Content-Disposition"), Some(format!("attachment; filename=\"job-{}-errors.csv\"", id).as_str()));
+        assert_eq!(
+            response.into_string().unwrap(),
+            "record,errors,name,email,mandates,commune_code,office_address,latitude,longitude\n2,email is required,Inconnu,,maire,,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_no_errors_report() {
+        let client = client(setup_test_db());
+        let response = client.post("/jobs/import?format=json").header(admin()).body("[]").dispatch();
+        let id = response.into_json::<Value>().unwrap()["id"].clone();
+
+        // Not until the job is finished, nor when nothing was rejected.
+        assert_eq!(client.get(format!("/jobs/{}/errors.csv", id)).header(admin()).dispatch().status(), Status::NotFound);
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let repository = client.rocket().state::<Arc<dyn PersonRepository>>().unwrap();
+        run_next(db, repository.as_ref(), &MandateTypes::default(), &Sealer::default()).unwrap();
+        assert_eq!(client.get(format!("/jobs/{}/errors.csv", id)).header(admin()).dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,89 @@
+//! The schemas of the API's resources as a standalone JSON Schema document,
+//! served at `/schema` for code generators and form builders which don't
+//! read OpenAPI. It is made from the components of the OpenAPI document,
+//! which request bodies are validated against, so it can't drift from the
+//! models either. Its `version` is a digest of the schemas: it changes
+//! whenever they do, and is sent as the `ETag`.
+
+use std::sync::OnceLock;
+
+use rocket::http::Header;
+use rocket::serde::json::{json, Json, Value};
+
+use crate::openapi;
+use crate::sha256::{hex, sha256};
+
+/// Makes OpenAPI references to components point at the document's `$defs`.
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => *reference = reference.replace("#/components/schemas/", "#/$defs/"),
+                    (_, value) => rewrite_refs(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+/// The JSON Schema document, built once.
+pub fn document() -> &'static Value {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+
+    DOCUMENT.get_or_init(|| {
+        let mut defs = openapi::spec()["components"]["schemas"].clone();
+        rewrite_refs(&mut defs);
+        let version = hex(&sha256(defs.to_string().as_bytes())[..8]);
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": "/schema",
+            "title": "Annuaire des élus",
+            "version": version,
+            "$defs": defs,
+        })
+    })
+}
+
+#[derive(Responder)]
+struct Versioned(Json<&'static Value>, Header<'static>);
+
+#[get("/schema")]
+fn schema() -> Versioned {
+    let document = document();
+    let etag = format!("\"{}\"", document["version"].as_str().unwrap_or_default());
+    Versioned(Json(document), Header::new("ETag", etag))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![schema]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, setup_test_db};
+
+    #[test]
+    fn test_schema() {
+        let client = client(setup_test_db());
+        let response = client.get("/schema").dispatch();
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        let document: Value = response.into_json().unwrap();
+
+        assert_eq!(etag, format!("\"{}\"", document["version"].as_str().unwrap()));
+        assert_eq!(document["$defs"]["Lookup"], json!(openapi::schema("Lookup")));
+        assert_eq!(document["$defs"]["Person"]["properties"]["related"]["items"]["$ref"], "#/$defs/Link");
+        assert_eq!(document["$defs"]["Problem"]["properties"]["status"]["type"], "integer");
+        assert!(!document.to_string().contains("#/components/"));
+    }
+
+    #[test]
+    fn test_rewrite_refs() {
+        let mut value = json!({ "items": [{ "$ref": "#/components/schemas/Person" }], "$ref": "https://example.com/other" });
+        rewrite_refs(&mut value);
+        assert_eq!(value, json!({ "items": [{ "$ref": "#/$defs/Person" }], "$ref": "https://example.com/other" }));
+    }
+}
@@ -0,0 +1,126 @@
+//! Brute-force protection of dashboard sign-in. Failed attempts are
+//! counted both per attempted username and per client address; once either
+//! reaches `MAX_FAILURES`, further attempts are refused for a lockout that
+//! doubles with every failure after that, up to `MAX_LOCKOUT`. Unknown
+//! usernames are counted like existing ones, not to reveal which exist.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use time::PrimitiveDateTime;
+
+use crate::schema::login_failures;
+
+/// Failures allowed before the first lockout.
+pub const MAX_FAILURES: i32 = 5;
+const FIRST_LOCKOUT: Duration = Duration::from_secs(60);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+/// Failures this old are forgotten, and counting starts over.
+const FAILURE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What attempts to sign in as `username` from `ip` are counted against.
+pub fn keys(username: &str, ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![format!("user:{}", username)];
+    keys.extend(ip.map(|ip| format!("ip:{}", ip)));
+    keys
+}
+
+/// How long the `failures`th consecutive failure locks out for.
+pub fn lockout(failures: i32) -> Option<Duration> {
+    if failures < MAX_FAILURES {
+        return None;
+    }
+    let doublings = (failures - MAX_FAILURES).min(31) as u32;
+    Some(FIRST_LOCKOUT.saturating_mul(1 << doublings).min(MAX_LOCKOUT))
+}
+
+/// End of the lockout in effect for any of the keys, if any.
+pub fn locked_until(keys: &[String], now: PrimitiveDateTime, connection: &mut SqliteConnection) -> QueryResult<Option<PrimitiveDateTime>> {
+    login_failures::table
+        .filter(login_failures::key.eq_any(keys))
+        .filter(login_failures::locked_until.gt(now))
+        .select(diesel::dsl::max(login_failures::locked_until))
+        .first::<Option<PrimitiveDateTime>>(connection)
+}
+
+/// Counts a failed attempt against the keys, returning the end of the
+/// lockout it starts, if any.
+pub fn record_failure(keys: &[String], now: PrimitiveDateTime, connection: &mut SqliteConnection) -> QueryResult<Option<PrimitiveDateTime>> {
+    connection.transaction(|connection| {
+        let mut locked_until = None;
+        for key in keys {
+            let previous: Option<(i32, PrimitiveDateTime)> = login_failures::table
+                .find(key)
+                .select((login_failures::failures, login_failures::last_failure_at))
+                .first(connection)
+                .optional()?;
+            let failures = match previous {
+                Some((failures, last_failure_at)) if last_failure_at > now - FAILURE_WINDOW => failures + 1,
+                _ => 1,
+            };
+            let until = lockout(failures).map(|lockout| now + lockout);
+
+            diesel::replace_into(login_failures::table)
+                .values((
+                    login_failures::key.eq(key),
+                    login_failures::failures.eq(failures),
+                    login_failures::last_failure_at.eq(now),
+                    login_failures::locked_until.eq(until),
+                ))
+                .execute(connection)?;
+            locked_until = locked_until.max(until);
+        }
+        Ok(locked_until)
+    })
+}
+
+/// Forgets the failures counted against the keys, after a successful
+/// attempt.
+pub fn clear(keys: &[String], connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(login_failures::table.filter(login_failures::key.eq_any(keys))).execute(connection).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup_test_db;
+    use crate::timestamp;
+
+    #[test]
+    fn test_lockout() {
+        assert_eq!(lockout(MAX_FAILURES - 1), None);
+        assert_eq!(lockout(MAX_FAILURES), Some(Duration::from_secs(60)));
+        assert_eq!(lockout(MAX_FAILURES + 2), Some(Duration::from_secs(240)));
+        assert_eq!(lockout(MAX_FAILURES + 10), Some(MAX_LOCKOUT));
+        assert_eq!(lockout(i32::MAX), Some(MAX_LOCKOUT));
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let mut connection = setup_test_db();
+        let now = timestamp::now();
+        let ip = "192.0.2.1".parse().ok();
+        let keys = keys("secretariat", ip);
+
+        for _ in 1..MAX_FAILURES {
+            assert_eq!(record_failure(&keys, now, &mut connection), Ok(None));
+        }
+        assert_eq!(locked_until(&keys, now, &mut connection), Ok(None));
+        assert_eq!(record_failure(&keys, now, &mut connection), Ok(Some(now + FIRST_LOCKOUT)));
+        assert_eq!(locked_until(&keys, now, &mut connection), Ok(Some(now + FIRST_LOCKOUT)));
+        // The address is locked out for other accounts too.
+        assert!(locked_until(&super::keys("maire", ip), now, &mut connection).unwrap().is_some());
+        assert_eq!(locked_until(&keys, now + FIRST_LOCKOUT, &mut connection), Ok(None));
+
+        let later = now + FIRST_LOCKOUT;
+        assert_eq!(record_failure(&keys, later, &mut connection), Ok(Some(later + 2 * FIRST_LOCKOUT)));
+        // Counting starts over after a day without failures.
+        let next_day = later + FAILURE_WINDOW;
+        assert_eq!(record_failure(&keys, next_day, &mut connection), Ok(None));
+
+        clear(&keys, &mut connection).unwrap();
+        assert_eq!(login_failures::table.count().get_result::<i64>(&mut connection), Ok(0));
+    }
+}
@@ -0,0 +1,238 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use rocket::serde::Deserialize;
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+
+use crate::config::AppConfig;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), String>;
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Envelope sender and `From` header of outgoing mail.
+    pub from: String,
+}
+
+/// Plain SMTP submission, meant for a relay on the local network; the relay
+/// is responsible for TLS towards the outside world.
+pub struct SmtpMailer {
+    config: SmtpConfig,
+}
+
+impl SmtpMailer {
+    pub fn new(config: SmtpConfig) -> Self {
+        SmtpMailer { config }
+    }
+
+    fn transaction(&self, message: &Message) -> io::Result<()> {
+        let stream = TcpStream::connect((self.config.host.as_str(), self.config.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        expect_reply(&mut reader, 220)?;
+        command(&mut writer, &mut reader, "EHLO localhost", 250)?;
+        if let (Some(username), Some(password)) = (&self.config.username, &self.config.password) {
+            let credentials = BASE64_STANDARD.encode(format!("\0{}\0{}", username, password).as_bytes());
+            command(&mut writer, &mut reader, &format!("AUTH PLAIN {}", credentials), 235)?;
+        }
+        command(&mut writer, &mut reader, &format!("MAIL FROM:<{}>", self.config.from), 250)?;
+        command(&mut writer, &mut reader, &format!("RCPT TO:<{}>", message.to), 250)?;
+        command(&mut writer, &mut reader, "DATA", 354)?;
+        writer.write_all(format_message(&self.config.from, message).as_bytes())?;
+        command(&mut writer, &mut reader, ".", 250)?;
+        command(&mut writer, &mut reader, "QUIT", 221)
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        self.transaction(message).map_err(|e| e.to_string())
+    }
+}
+
+/// Used when no SMTP relay is configured: messages are only logged.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        log::info!("Not sending mail to {} (no SMTP relay configured): {}", message.to, message.subject);
+        Ok(())
+    }
+}
+
+pub fn from_config(config: &AppConfig) -> Arc<dyn Mailer> {
+    match &config.smtp {
+        Some(smtp) => Arc::new(SmtpMailer::new(smtp.clone())),
+        None => Arc::new(LogMailer),
+    }
+}
+
+fn command(writer: &mut TcpStream, reader: &mut impl BufRead, line: &str, expected: u16) -> io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes())?;
+    expect_reply(reader, expected)
+}
+
+/// Reads a possibly multiline reply and checks its code.
+fn expect_reply(reader: &mut impl BufRead, expected: u16) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "SMTP server closed the connection"));
+        }
+        let code: u16 = line.get(..3).and_then(|code| code.parse().ok()).unwrap_or(0);
+        if code != expected {
+            return Err(io::Error::other(format!("unexpected SMTP reply: {}", line.trim_end())));
+        }
+        // "250-" announces continuation lines, "250 " the last one.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+/// RFC 5322 message with a base64 body, so that no line length or 8-bit
+/// support is required from the relay.
+fn format_message(from: &str, message: &Message) -> String {
+    let date = OffsetDateTime::now_utc().format(&Rfc2822).unwrap_or_default();
+    let domain = from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+    let body = BASE64_STANDARD.encode(message.body.replace('\n', "\r\n").as_bytes());
+
+    let mut formatted = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{:032x}@{}>\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        from, message.to, encode_header(&message.subject), date, rand::random::<u128>(), domain,
+    );
+    for line in body.as_bytes().chunks(76) {
+        formatted.push_str(std::str::from_utf8(line).unwrap());
+        formatted.push_str("\r\n");
+    }
+    formatted
+}
+
+/// RFC 2047 encoded-word for non-ASCII header values.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64_STANDARD.encode(value.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// Records messages instead of sending them.
+    #[derive(Default)]
+    pub(crate) struct RecordingMailer {
+        pub(crate) sent: Mutex<Vec<Message>>,
+    }
+
+    impl Mailer for RecordingMailer {
+        fn send(&self, message: &Message) -> Result<(), String> {
+            if message.to.ends_with("@bounce.example") {
+                return Err("550 mailbox unavailable".to_string());
+            }
+            self.sent.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(encode_header("Conseil municipal"), "Conseil municipal");
+        assert_eq!(encode_header("Réunion"), "=?UTF-8?B?UsOpdW5pb24=?=");
+    }
+
+    #[test]
+    fn test_smtp_transaction() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = vec![];
+            let reply = |writer: &mut TcpStream, text: &str| writer.write_all(text.as_bytes()).unwrap();
+
+            reply(&mut writer, "220 relay ESMTP\r\n");
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                transcript.push(line.trim_end().to_string());
+                match line.trim_end() {
+                    "EHLO localhost" => reply(&mut writer, "250-relay\r\n250 AUTH PLAIN\r\n"),
+                    line if line.starts_with("AUTH") => reply(&mut writer, "235 ok\r\n"),
+                    "DATA" => {
+                        reply(&mut writer, "354 go ahead\r\n");
+                        loop {
+                            let mut data = String::new();
+                            reader.read_line(&mut data).unwrap();
+                            if data == ".\r\n" {
+                                break;
+                            }
+                            transcript.push(data.trim_end().to_string());
+                        }
+                        reply(&mut writer, "250 queued\r\n");
+                    }
+                    "QUIT" => {
+                        reply(&mut writer, "221 bye\r\n");
+                        return transcript;
+                    }
+                    _ => reply(&mut writer, "250 ok\r\n"),
+                }
+            }
+        });
+
+        let mailer = SmtpMailer::new(SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            from: "annuaire@mairie.example".to_string(),
+        });
+        let message = Message {
+            to: "jean.dupont@example.com".to_string(),
+            subject: "Conseil".to_string(),
+            body: "Bonjour Jean".to_string(),
+        };
+        assert_eq!(mailer.send(&message), Ok(()));
+
+        let transcript = server.join().unwrap();
+        assert_eq!(transcript[1], format!("AUTH PLAIN {}", BASE64_STANDARD.encode(b"\0user\0secret")));
+        assert_eq!(transcript[2], "MAIL FROM:<annuaire@mairie.example>");
+        assert_eq!(transcript[3], "RCPT TO:<jean.dupont@example.com>");
+        assert!(transcript.contains(&"Subject: Conseil".to_string()));
+        assert!(transcript.contains(&BASE64_STANDARD.encode(b"Bonjour Jean")));
+        assert_eq!(transcript.last().unwrap(), "QUIT");
+    }
+}
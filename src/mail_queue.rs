@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::encryption::Sealer;
+use crate::mail::{Mailer, Message};
+use crate::schema::mail_queue;
+use crate::{db, shutdown, timeouts, timestamp, DbConn};
+
+pub const QUEUED: &str = "queued";
+pub const SENT: &str = "sent";
+pub const FAILED: &str = "failed";
+
+/// Messages sent per worker run, to bound how long the database stays busy.
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MailQueueConfig {
+    /// Seconds between two runs of the queue worker; 0 disables the worker.
+    pub poll_interval: u64,
+    /// Attempts after which a message is marked as failed.
+    pub max_attempts: i32,
+    /// Delay, in seconds, before the first retry; doubled after each failure.
+    pub retry_delay: u64,
+}
+
+impl Default for MailQueueConfig {
+    fn default() -> Self {
+        MailQueueConfig {
+            poll_interval: 30,
+            max_attempts: 5,
+            retry_delay: 60,
+        }
+    }
+}
+
+impl MailQueueConfig {
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = (attempts - 1).clamp(0, 16) as u32;
+        Duration::from_secs(self.retry_delay.saturating_mul(1 << exponent))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = mail_queue)]
+#[serde(crate = "rocket::serde")]
+pub struct QueuedMail {
+    pub id: i32,
+    pub notification_id: Option<i32>,
+    pub recipient: String,
+    pub subject: String,
+    #[serde(skip)]
+    pub body: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub next_attempt_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub sent_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mail_queue)]
+struct NewMail<'a> {
+    notification_id: Option<i32>,
+    recipient: &'a str,
+    subject: &'a str,
+    body: &'a str,
+}
+
+/// Queues a message for the worker, its recipient and body sealed; meant
+/// to be called within the transaction recording whatever triggered the
+/// message.
+pub fn enqueue(message: &Message, notification_id: Option<i32>, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<i32> {
+    diesel::insert_into(mail_queue::table)
+        .values(NewMail {
+            notification_id,
+            recipient: &sealer.seal(&message.to),
+            subject: &message.subject,
+            body: &sealer.seal(&message.body),
+        })
+        .returning(mail_queue::id)
+        .get_result(connection)
+}
+
+/// The mails with their recipient and body opened.
+fn opened(mails: Vec<QueuedMail>, sealer: &Sealer) -> QueryResult<Vec<QueuedMail>> {
+    let open = |stored: &str| sealer.open(stored).map_err(|_| diesel::result::Error::DeserializationError("undecryptable mail".into()));
+    mails
+        .into_iter()
+        .map(|mail| Ok(QueuedMail { recipient: open(&mail.recipient)?, body: open(&mail.body)?, ..mail }))
+        .collect()
+}
+
+pub fn by_notification(notification_id: i32, sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<Vec<QueuedMail>> {
+    let mails = mail_queue::table
+        .filter(mail_queue::notification_id.eq(notification_id))
+        .order(mail_queue::id)
+        .select(QueuedMail::as_select())
+        .load(connection)?;
+    opened(mails, sealer)
+}
+
+/// Sends the messages due at `now`, rescheduling failed attempts with an
+/// exponential backoff. Returns the number of messages attempted.
+pub fn process_due(db: &DbConn, mailer: &dyn Mailer, config: &MailQueueConfig, sealer: &Sealer, now: PrimitiveDateTime) -> QueryResult<usize> {
+    let due = mail_queue::table
+        .filter(mail_queue::status.eq(QUEUED))
+        .filter(mail_queue::next_attempt_at.le(now))
+        .order(mail_queue::id)
+        .limit(BATCH_SIZE)
+        .select(QueuedMail::as_select())
+        .load(&mut *db::lock(db)?)?;
+    let due = opened(due, sealer)?;
+
+    for mail in &due {
+        let result = mailer.send(&Message {
+            to: mail.recipient.clone(),
+            subject: mail.subject.clone(),
+            body: mail.body.clone(),
+        });
+
+        let attempts = mail.attempts + 1;
+        let row = mail_queue::table.find(mail.id);
+        let mut connection = db::lock(db)?;
+        match result {
+            Ok(()) => diesel::update(row)
+                .set((
+                    mail_queue::status.eq(SENT),
+                    mail_queue::attempts.eq(attempts),
+                    mail_queue::sent_at.eq(now),
+                ))
+                .execute(&mut *connection)?,
+            Err(e) if attempts >= config.max_attempts => diesel::update(row)
+                .set((
+                    mail_queue::status.eq(FAILED),
+                    mail_queue::attempts.eq(attempts),
+                    mail_queue::last_error.eq(e),
+                ))
+                .execute(&mut *connection)?,
+            Err(e) => diesel::update(row)
+                .set((
+                    mail_queue::attempts.eq(attempts),
+                    mail_queue::last_error.eq(e),
+                    mail_queue::next_attempt_at.eq(now + config.backoff(attempts)),
+                ))
+                .execute(&mut *connection)?,
+        };
+    }
+
+    Ok(due.len())
+}
+
+/// Background worker delivering the queue.
+pub fn fairing(config: MailQueueConfig) -> AdHoc {
+    AdHoc::on_liftoff("Mail queue worker", move |rocket| Box::pin(async move {
+        if config.poll_interval == 0 {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let mailer = rocket.state::<Arc<dyn Mailer>>().expect("mailer is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval));
+            loop {
+                // Sends what's due one last time when shutting down.
+                let running = shutdown::tick(&mut interval, &shutdown).await;
+                let (db, mailer, config, sealer) = (db.clone(), mailer.clone(), config.clone(), sealer.clone());
+                let run = rocket::tokio::task::spawn_blocking(move || {
+                    process_due(&db, mailer.as_ref(), &config, &sealer, timestamp::now())
+                });
+                match rocket::tokio::time::timeout(job, run).await {
+                    Ok(Ok(Ok(_))) => {}
+                    Ok(Ok(Err(e))) => log::error!("Mail queue run failed: {}", e),
+                    Ok(Err(e)) => log::error!("Mail queue run panicked: {}", e),
+                    Err(_) => log::warn!("Mail queue run still going after {} seconds, no longer waiting for it", job.as_secs()),
+                }
+                if !running {
+                    break;
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+/// Lists queued and failed messages, or only those with the given status.
+#[get("/admin/mail?<status>")]
+fn list_mail(status: Option<&str>, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<Vec<QueuedMail>>, Status> {
+    let statuses = match status {
+        Some(status @ (QUEUED | SENT | FAILED)) => vec![status],
+        Some(_) => return Err(Status::BadRequest),
+        None => vec![QUEUED, FAILED],
+    };
+
+    let mut connection = db::lock(db)?;
+    mail_queue::table
+        .filter(mail_queue::status.eq_any(statuses))
+        .order(mail_queue::id)
+        .select(QueuedMail::as_select())
+        .load(&mut *connection)
+        .and_then(|mails| opened(mails, sealer))
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_mail]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::Cipher;
+    use crate::mail::tests::RecordingMailer;
+    use crate::tests::{admin, client, setup_test_db};
+    use std::sync::Mutex;
+    use time::macros::datetime;
+
+    fn message(to: &str) -> Message {
+        Message {
+            to: to.to_string(),
+            subject: "Conseil municipal".to_string(),
+            body: "Bonjour".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = MailQueueConfig::default();
+
+        assert_eq!(config.backoff(1), Duration::from_secs(60));
+        assert_eq!(config.backoff(3), Duration::from_secs(240));
+    }
+
+    #[test]
+    fn test_process_due_retries_with_backoff() {
+        let mut connection = setup_test_db();
+        let sealer = Sealer::new(Cipher::new(&[7; 32]));
+        enqueue(&message("jean.dupont@example.com"), None, &sealer, &mut connection).unwrap();
+        enqueue(&message("paul@bounce.example"), None, &sealer, &mut connection).unwrap();
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        let mailer = RecordingMailer::default();
+        let config = MailQueueConfig { max_attempts: 2, ..Default::default() };
+
+        let now = datetime!(2030-01-01 12:00:00);
+        assert_eq!(process_due(&db, &mailer, &config, &sealer, now), Ok(2));
+        assert_eq!(mailer.sent.lock().unwrap().iter().map(|message| message.to.as_str()).collect::<Vec<_>>(), ["jean.dupont@example.com"]);
+
+        // The failed message waits for its retry delay.
+        assert_eq!(process_due(&db, &mailer, &config, &sealer, now + Duration::from_secs(30)), Ok(0));
+        assert_eq!(process_due(&db, &mailer, &config, &sealer, now + Duration::from_secs(60)), Ok(1));
+        assert_eq!(process_due(&db, &mailer, &config, &sealer, now + Duration::from_secs(3600)), Ok(0));
+
+        let mails = mail_queue::table
+            .order(mail_queue::id)
+            .select(QueuedMail::as_select())
+            .load(&mut *db.lock().unwrap())
+            .unwrap();
+        assert!(mails.iter().all(|mail| !mail.recipient.contains("example") && mail.body != "Bonjour"));
+        assert_eq!((mails[0].status.as_str(), mails[0].sent_at), (SENT, Some(now)));
+        assert_eq!((mails[1].status.as_str(), mails[1].attempts), (FAILED, 2));
+        assert_eq!(mails[1].last_error.as_deref(), Some("550 mailbox unavailable"));
+    }
+
+    #[test]
+    fn test_list_mail_endpoint() {
+        let mut connection = setup_test_db();
+        enqueue(&message("jean.dupont@example.com"), None, &Sealer::default(), &mut connection).unwrap();
+        let client = client(connection);
+
+        let response = client.get("/admin/mail").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/admin/mail").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mails: Vec<QueuedMail> = response.into_json().expect("valid JSON");
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].recipient, "jean.dupont@example.com");
+
+        let response = client.get("/admin/mail?status=sent").header(admin()).dispatch();
+        assert_eq!(response.into_json::<Vec<QueuedMail>>().map(|mails| mails.len()), Some(0));
+
+        let response = client.get("/admin/mail?status=bogus").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
@@ -2,161 +2,844 @@
 
 mod schema;
 mod db;
+mod alerts;
+mod api_keys;
+mod audit;
+mod auth;
+mod base32;
+#[cfg(test)]
+mod bench;
+mod bodies;
+mod bulk;
+mod client_gen;
+mod cloudevents;
+mod config;
+mod content_headers;
+mod communes;
+mod criteria;
+mod crm;
+mod csrf;
+mod custom_fields;
+mod dashboard;
+mod deliverability;
+mod diff;
+mod discovery;
+mod dns;
+mod documents;
+mod elections;
+mod email;
+mod embed;
+mod encryption;
+mod envelope;
+mod error_reporting;
+mod events;
+mod explain;
+mod export;
+mod filter;
+mod flags;
+#[cfg(feature = "tantivy")]
+mod full_text;
+#[cfg(test)]
+mod fuzz;
+mod geocoding;
+mod import;
+mod jobs;
+mod json_schema;
+mod lockout;
+mod mail;
+mod mail_queue;
+mod maintenance;
+mod mandate_types;
+#[cfg(feature = "nats")]
+mod nats;
+mod normalize;
+mod notify;
+mod open_data;
+mod openapi;
+mod pagination;
+mod password_reset;
+mod person_name;
+mod phonetic;
+mod png;
+mod problem;
+mod profile;
+mod query_log;
+mod redaction;
+mod reload;
+mod related;
+mod repository;
+mod request_id;
+mod saved_searches;
+mod scheduled_export;
+mod scraping;
+mod search;
+mod secrets;
+mod sessions;
+mod settings;
+mod sha1;
+mod sha256;
+mod shutdown;
+mod stats;
+mod storage;
+mod sync;
+mod tags;
+mod telemetry;
+mod terms;
+#[cfg(feature = "testdata")]
+mod testdata;
+mod timeouts;
+mod timestamp;
+mod totp;
+mod transaction;
+mod two_factor;
+mod uploads;
+mod users;
+mod validation;
+mod vcard;
+mod version;
+mod visibility;
+mod webhooks;
 
-use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
+use rocket::figment::Figment;
 use rocket::serde::{Serialize, Deserialize, json::Json};
-use rocket::State;
-use rocket::http::Status;
-use std::sync::Mutex;
+use rocket::{Build, Rocket, State};
+use rocket::http::{Header, Status};
+use rocket::response::Redirect;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::AppConfig;
+use crate::criteria::Filters;
+use crate::email::Email;
+use crate::person_name::PersonName;
+use crate::deliverability::EmailStatus;
+use crate::geocoding::Geocoder;
+use crate::mandate_types::MandateTypes;
+use crate::pagination::{Pagination, SortSpec};
+use crate::problem::Problem;
+use crate::redaction::{Redactable, Redacted};
+use crate::repository::{Backend, Near, Page, PersonFilter, PersonKey, PersonRepository, PersonSort, PublicIds};
+use crate::validation::{Schema, Validated};
+use crate::visibility::Visible;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Default))]
 #[serde(crate = "rocket::serde")]
 struct Person {
-    name: String,
-    email: String,
+    /// Public identifier; ignored on input.
+    #[serde(default)]
+    uuid: String,
+    name: PersonName,
+    /// Primary address.
+    email: Email,
+    /// Every address of the elu, the primary one first; on input, the
+    /// others may be listed alone.
+    #[serde(default)]
+    emails: Vec<Email>,
     mandates: Vec<String>,
+    /// INSEE code of the commune the mandates are held in.
+    #[serde(default)]
+    commune_code: Option<String>,
+    #[serde(default)]
+    office_address: Option<String>,
+    /// WGS84 coordinates of the office, geocoded from `office_address`
+    /// unless provided.
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+    /// Whether the elu agreed to receive mailings; notifications only go to
+    /// those who did, on a recorded `consent_date`. Consent is only taken
+    /// from admins, and cleared on elus created by anyone else.
+    #[serde(default)]
+    may_contact_by_email: bool,
+    /// When consent was given or withdrawn.
+    #[serde(default, with = "timestamp::rfc3339::option")]
+    consent_date: Option<time::PrimitiveDateTime>,
+    /// How consent was collected, such as a signed form or a web page.
+    #[serde(default)]
+    consent_source: Option<String>,
+    /// Who may see the elu: everyone, signed-in users or admins only; new
+    /// elus get the default of their commune when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    visibility: Option<visibility::Visibility>,
+    /// Values of the custom fields `GET /custom-fields` defines.
+    #[serde(default)]
+    custom: custom_fields::CustomValues,
+    /// Result of the background deliverability check; ignored on input.
+    #[serde(default)]
+    email_status: EmailStatus,
+    /// Relations with other elus, listed when fetching a single elu;
+    /// ignored on input.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    related: Vec<related::Link>,
+}
+
+impl Person {
+    /// The data of a caller without the admin token, who may neither record
+    /// consent nor choose who sees the elu.
+    fn without_admin_fields(self) -> Self {
+        Person { may_contact_by_email: false, consent_date: None, consent_source: None, visibility: None, ..self }
+    }
+}
+
+impl Redactable for Person {
+    fn persons(value: &mut rocket::serde::json::Value) -> Vec<&mut rocket::serde::json::Value> {
+        vec![value]
+    }
+}
+
+impl Schema for Person {
+    const NAME: &'static str = "Person";
+
+    fn check(value: &rocket::serde::json::Value, request: &rocket::Request<'_>) -> Result<Vec<problem::Violation>, Status> {
+        let db = request.rocket().state::<DbConn>().ok_or(Status::InternalServerError)?;
+        let fields = custom_fields::load(&mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+        Ok(custom_fields::violations(&value["custom"], &fields))
+    }
 }
 
 impl From<db::Person> for Person {
     fn from(person: db::Person) -> Self {
-        let mandates: Vec<String> = serde_json::from_str(&person.mandates)
-            .unwrap_or_else(|_| vec![]);
         Person {
+            uuid: person.uuid,
             name: person.name,
+            emails: std::iter::once(person.email.clone()).chain(person.emails).collect(),
             email: person.email,
-            mandates,
+            mandates: person.mandates,
+            commune_code: person.commune_code,
+            office_address: person.office_address,
+            latitude: person.latitude,
+            longitude: person.longitude,
+            may_contact_by_email: person.may_contact_by_email,
+            consent_date: person.consent_date,
+            consent_source: person.consent_source,
+            visibility: Some(person.visibility),
+            custom: person.custom,
+            email_status: person.email_status.parse().unwrap_or_default(),
+            related: Vec::new(),
         }
     }
 }
 
-type DbConn = Mutex<SqliteConnection>;
+type DbConn = Arc<Mutex<SqliteConnection>>;
 
 #[get("/")]
 fn index() -> &'static str {
     "hello world"
 }
 
-#[get("/elus")]
-fn elus(db: &State<DbConn>) -> Result<Json<Vec<Person>>, Status> {
-    let mut connection = db.lock().unwrap();
-    let results = db::elus(&mut connection)?;
+/// A listing of persons: all of them, or a page when paging parameters
+/// were given.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum Listing {
+    All(Vec<Person>),
+    Page {
+        elus: Vec<Person>,
+        /// `after` parameter fetching the next page; null on the last one.
+        next_cursor: Option<String>,
+    },
+}
+
+impl Redactable for Listing {
+    fn persons(value: &mut rocket::serde::json::Value) -> Vec<&mut rocket::serde::json::Value> {
+        if value.get("elus").is_some() {
+            return Vec::<Person>::persons(&mut value["elus"]);
+        }
+        Vec::<Person>::persons(value)
+    }
+}
 
-    let responses: Vec<Person> = results.into_iter()
-        .map(Person::from)
-        .collect();
+/// Lists the elus, optionally filtered by (part of) their name, a mandate
+/// (by title or code), their commune, a tag, the deliverability of their
+/// address or a `filter` expression combining those; admins may list them
+/// as they were `as_of` a past moment, replayed from the event log.
+#[get("/elus?<as_of>")]
+#[allow(clippy::too_many_arguments)]
+fn elus(
+    as_of: Option<&str>,
+    filters: Filters,
+    pagination: Pagination,
+    sort: SortSpec<PersonSort>,
+    admin: Option<auth::Admin>,
+    db: &State<DbConn>,
+    sealer: &State<encryption::Sealer>,
+    repository: Visible,
+) -> Result<Redacted<Listing>, Problem> {
+    let snapshot;
+    let repository: &dyn PersonRepository = match as_of {
+        Some(_) if admin.is_none() => return Err(Status::Unauthorized.into()),
+        Some(as_of) => {
+            let at = timestamp::parse_point(as_of).ok_or(Status::BadRequest)?;
+            let persons = events::as_of(at, sealer, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+            snapshot = repository::MemoryRepository::with_persons(persons);
+            &snapshot
+        }
+        None => repository.as_ref(),
+    };
 
-    Ok(Json(responses))
+    Ok(Redacted(listing(repository, &filters.0, pagination, sort)?))
 }
 
-#[get("/elus/<search_email>")]
-fn get_person_by_email(search_email: String, db: &State<DbConn>) -> Result<Json<Person>, Status> {
-    let mut connection = db.lock().unwrap();
-    let result = db::get_elu_by_email(&search_email, &mut connection)?;
+/// The matches of the filter in the order of `sort`: all of them, or the
+/// page `pagination` asks for.
+fn listing(repository: &dyn PersonRepository, filter: &PersonFilter, pagination: Pagination, sort: SortSpec<PersonSort>) -> Result<Listing, Problem> {
+    Ok(match pagination.0 {
+        Some(page) if page.after.is_some() && !sort.is_by_name() => {
+            return Err(Problem { detail: Some("after only follows the order by name; page by number to sort otherwise.".to_string()), ..Problem::new(Status::BadRequest) });
+        }
+        Some(page) => {
+            let (persons, next_cursor) = pagination::fetch(repository, filter, Page { sort, ..page })?;
+            Listing::Page { elus: persons.into_iter().map(Person::from).collect(), next_cursor }
+        }
+        None => {
+            let mut persons = repository.search(filter)?;
+            if sort.key.is_some() {
+                persons.sort_by(|a, b| sort.compare(a, b));
+            }
+            Listing::All(persons.into_iter().map(Person::from).collect())
+        }
+    })
+}
+
+/// A response about an elu, pointing at their canonical `/elus/<uuid>`
+/// path so links stay valid when their email changes.
+#[derive(Responder)]
+struct Canonical<R>(R, Header<'static>);
+
+impl<R> Canonical<R> {
+    fn new(response: R, uuid: &str) -> Self {
+        Canonical(response, Header::new("Content-Location", format!("/elus/{}", uuid)))
+    }
+}
 
-    Ok(Json(Person::from(result)))
+/// Fetches an elu by UUID, the canonical key, or by email or id; former
+/// emails redirect to the elu's canonical path. Browsers get their profile
+/// page rather than JSON.
+#[get("/elus/<key>")]
+fn get_person(key: &str, _probe: scraping::Probe, db: &State<DbConn>, repository: Visible) -> Result<Result<Canonical<profile::Negotiated>, Redirect>, Status> {
+    let key = PersonKey::parse(key)?;
+    let result = match (repository.find(&key), &key) {
+        (Err(status), PersonKey::Email(email)) if status == Status::NotFound => {
+            let person = repository.find_alias(email)?;
+            return Ok(Err(Redirect::permanent(format!("/elus/{}", person.uuid))));
+        }
+        (result, _) => result?,
+    };
+    let uuid = result.uuid.clone();
+    let related = related::links(result.id, db, repository.as_ref())?;
+
+    Ok(Ok(Canonical::new(profile::Negotiated(Person { related, ..Person::from(result) }), &uuid)))
+}
+
+/// Most emails `POST /elus/lookup` accepts at once, keeping its query
+/// well under SQLite's limit on bound parameters.
+const MAX_LOOKUP_EMAILS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Lookup {
+    emails: Vec<Email>,
+}
+
+impl Schema for Lookup {
+    const NAME: &'static str = "Lookup";
+}
+
+/// Fetches many persons in one request, in the order of the given emails;
+/// unknown emails are left out.
+#[post("/elus/lookup", data = "<lookup>")]
+fn lookup_persons(lookup: Validated<Lookup>, _probe: scraping::Probe, repository: Visible) -> Result<Redacted<Vec<Person>>, Status> {
+    if lookup.emails.len() > MAX_LOOKUP_EMAILS {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let mut found = repository.get_many(&lookup.emails)?;
+    found.sort_by_key(|person| lookup.emails.iter().position(|email| *email == person.email));
+
+    Ok(Redacted(found.into_iter().map(Person::from).collect()))
+}
+
+/// The elus within `radius_km` of a point, nearest first unless sorted or
+/// paged, among those matching the criteria of `GET /elus`.
+#[get("/elus/near?<lat>&<lon>&<radius_km>")]
+#[allow(clippy::too_many_arguments)]
+fn elus_near(lat: f64, lon: f64, radius_km: f64, filters: Filters, pagination: Pagination, sort: SortSpec<PersonSort>, repository: Visible) -> Result<Redacted<Listing>, Problem> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || radius_km.is_nan() || radius_km < 0.0 {
+        return Err(Status::BadRequest.into());
+    }
+
+    let near = Near { latitude: lat, longitude: lon, radius_km };
+    Ok(Redacted(listing(repository.as_ref(), &PersonFilter { near: Some(near), ..filters.0 }, pagination, sort)?))
 }
 
 #[post("/elus/new", data = "<person_data>")]
-fn create_person_new(person_data: Json<Person>, db: &State<DbConn>) -> Result<Json<Person>, Status> {
-    create_person(person_data, db)
+async fn create_person_new(person_data: Validated<Person>, admin: Option<auth::Admin>, db: &State<DbConn>, config: &State<AppConfig>, repository: &State<Arc<dyn PersonRepository>>, geocoder: &State<Box<dyn Geocoder>>, mandate_types: &State<MandateTypes>) -> Result<Canonical<Json<Person>>, Status> {
+    create_person(person_data, admin, db, config, repository, geocoder, mandate_types).await
 }
 
 #[post("/elus/create", data = "<person_data>")]
-fn create_person_create(person_data: Json<Person>, db: &State<DbConn>) -> Result<Json<Person>, Status> {
-    create_person(person_data, db)
+async fn create_person_create(person_data: Validated<Person>, admin: Option<auth::Admin>, db: &State<DbConn>, config: &State<AppConfig>, repository: &State<Arc<dyn PersonRepository>>, geocoder: &State<Box<dyn Geocoder>>, mandate_types: &State<MandateTypes>) -> Result<Canonical<Json<Person>>, Status> {
+    create_person(person_data, admin, db, config, repository, geocoder, mandate_types).await
+}
+
+async fn create_person(person_data: Validated<Person>, admin: Option<auth::Admin>, db: &State<DbConn>, config: &State<AppConfig>, repository: &State<Arc<dyn PersonRepository>>, geocoder: &State<Box<dyn Geocoder>>, mandate_types: &State<MandateTypes>) -> Result<Canonical<Json<Person>>, Status> {
+    let person_data = match admin {
+        Some(_) => person_data.into_inner(),
+        None => person_data.into_inner().without_admin_fields(),
+    };
+    let new_person = to_new_person(person_data, db, geocoder, mandate_types).await?;
+    let transaction = transaction::Transaction::begin(db, &config.busy_retry).await?;
+    let created = repository.create(new_person)?;
+    transaction.commit()?;
+    let uuid = created.uuid.clone();
+
+    Ok(Canonical::new(Json(Person::from(created)), &uuid))
+}
+
+/// A response to `PUT /elus/<key>`, which creates the elu when the key is
+/// an email nobody has.
+#[derive(Responder)]
+enum Upserted {
+    Updated(Canonical<Redacted<Person>>),
+    #[response(status = 201)]
+    Created(Canonical<Redacted<Person>>, Header<'static>),
+}
+
+/// Replaces an elu's data, email included; omitted optional fields are
+/// cleared. An unknown email key creates the elu instead, unless the body
+/// has another email; `If-None-Match: *` refuses to replace an existing
+/// elu, for strict creation.
+#[put("/elus/<key>", data = "<person_data>")]
+#[allow(clippy::too_many_arguments)]
+async fn update_person(key: &str, _admin: auth::Admin, person_data: Validated<Person>, if_none_match: vcard::IfNoneMatch, db: &State<DbConn>, config: &State<AppConfig>, repository: Visible, geocoder: &State<Box<dyn Geocoder>>, mandate_types: &State<MandateTypes>) -> Result<Upserted, Status> {
+    let key = PersonKey::parse(key)?;
+    let new_person = to_new_person(person_data.into_inner(), db, geocoder, mandate_types).await?;
+    let transaction = transaction::Transaction::begin(db, &config.busy_retry).await?;
+    let person = match repository.find(&key) {
+        Err(status) if status == Status::NotFound => None,
+        result => Some(result?),
+    };
+
+    let upserted = match (person, key) {
+        (Some(_), _) if if_none_match.is_wildcard() => return Err(Status::PreconditionFailed),
+        (Some(person), _) => {
+            let updated = repository.update(&person.email, new_person)?;
+            Upserted::Updated(Canonical::new(Redacted(Person::from(updated)), &person.uuid))
+        }
+        (None, PersonKey::Email(email)) => {
+            if new_person.email != email {
+                return Err(Status::UnprocessableEntity);
+            }
+            let created = repository.create(new_person)?;
+            let location = Header::new("Location", format!("/elus/{}", created.uuid));
+            let uuid = created.uuid.clone();
+            Upserted::Created(Canonical::new(Redacted(Person::from(created)), &uuid), location)
+        }
+        (None, _) => return Err(Status::NotFound),
+    };
+    transaction.commit()?;
+
+    Ok(upserted)
+}
+
+/// Deletes an elu along with their documents, relations, memberships,
+/// mandate terms and tags, all of them or none; the files of documents
+/// are removed once that's committed.
+#[delete("/elus/<key>")]
+async fn delete_person(key: &str, _admin: auth::Admin, transaction: transaction::Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>, store: &State<Box<dyn storage::BlobStore>>) -> Result<Status, Status> {
+    let person = repository.find(&PersonKey::parse(key)?)?;
+    let documents = {
+        let mut connection = db::lock(db)?;
+        let documents = documents::delete_all(person.id, &mut connection).map_err(|_| Status::InternalServerError)?;
+        related::delete_all(person.id, &mut connection).map_err(|_| Status::InternalServerError)?;
+        bodies::delete_memberships(person.id, &mut connection).map_err(|_| Status::InternalServerError)?;
+        terms::delete_all(person.id, &mut connection).map_err(|_| Status::InternalServerError)?;
+        tags::delete_all(person.id, &mut connection).map_err(|_| Status::InternalServerError)?;
+        documents
+    };
+    repository.delete(&person.email)?;
+    transaction.commit()?;
+    documents::remove_files(&documents, store.as_ref()).await;
+
+    Ok(Status::NoContent)
+}
+
+/// Validates the submitted person, geocoding the office address when no
+/// coordinates are given.
+async fn to_new_person(person_data: Person, db: &State<DbConn>, geocoder: &State<Box<dyn Geocoder>>, mandate_types: &State<MandateTypes>) -> Result<db::NewPerson, Status> {
+    let mut coordinates = person_data.latitude.zip(person_data.longitude);
+    if coordinates.is_none() {
+        if let Some(address) = &person_data.office_address {
+            coordinates = geocoder.geocode(address)
+                .await
+                .map(|found| (found.latitude, found.longitude));
+        }
+    }
+
+    if let Some(code) = &person_data.commune_code {
+        db::get_commune(code, &mut *db::lock(db)?).map_err(|_| Status::UnprocessableEntity)?;
+    }
+    let visibility = match person_data.visibility {
+        Some(visibility) => visibility,
+        None => settings::default_visibility(person_data.commune_code.as_deref(), &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?,
+    };
+
+    Ok(db::NewPerson {
+        name: person_data.name,
+        emails: db::other_emails(&person_data.email, &person_data.emails),
+        email: person_data.email,
+        mandates: person_data.mandates.iter().map(|mandate| mandate_types.title(mandate)).collect(),
+        commune_code: person_data.commune_code,
+        office_address: person_data.office_address,
+        latitude: coordinates.map(|(lat, _)| lat),
+        longitude: coordinates.map(|(_, lon)| lon),
+        may_contact_by_email: person_data.may_contact_by_email,
+        consent_date: person_data.consent_date,
+        consent_source: person_data.consent_source,
+        visibility,
+        custom: person_data.custom,
+    })
+}
+
+fn routes() -> Vec<rocket::Route> {
+    [
+        routes![index, elus, get_person, lookup_persons, elus_near, create_person_new, create_person_create, update_person, delete_person],
+        communes::routes(),
+        export::routes(),
+        import::routes(),
+        search::routes(),
+        bulk::routes(),
+        jobs::routes(),
+        openapi::routes(),
+        json_schema::routes(),
+        version::routes(),
+        discovery::routes(),
+        embed::routes(),
+        flags::routes(),
+        reload::routes(),
+        scraping::routes(),
+        vcard::routes(),
+        documents::routes(),
+        related::routes(),
+        bodies::routes(),
+        terms::routes(),
+        tags::routes(),
+        custom_fields::routes(),
+        settings::routes(),
+        saved_searches::routes(),
+        elections::routes(),
+        alerts::routes(),
+        notify::routes(),
+        mail_queue::routes(),
+        explain::routes(),
+        maintenance::routes(),
+        scheduled_export::routes(),
+        api_keys::routes(),
+        users::routes(),
+        dashboard::routes(),
+        audit::routes(),
+        password_reset::routes(),
+        two_factor::routes(),
+        sync::routes(),
+        events::routes(),
+        diff::routes(),
+        webhooks::routes(),
+        stats::routes(),
+    ]
+    .concat()
 }
 
-fn create_person(person_data: Json<Person>, db: &State<DbConn>) -> Result<Json<Person>, Status> {
-    let mut connection = db.lock().unwrap();
+fn build_rocket(figment: Figment, mut connection: SqliteConnection) -> Rocket<Build> {
+    let config: AppConfig = figment.extract().unwrap_or_else(|e| panic!("invalid configuration: {}", secrets::explain(e)));
+    if config.redaction.demo && config.redaction.demo_key.is_none() {
+        panic!("invalid configuration: redaction.demo needs a redaction.demo_key");
+    }
+    db::check_schema(&mut connection);
+    match db::index_phonetic(&mut connection) {
+        Ok(0) => {}
+        Ok(indexed) => log::info!("Computed the phonetic keys of {} elus", indexed),
+        Err(e) => panic!("Failed to compute phonetic keys: {}", e),
+    }
+
+    let mandate_types = MandateTypes::load(&mut connection).expect("Failed to load mandate types");
+    let log_queries = config.log_queries.unwrap_or(figment.profile() == rocket::Config::DEBUG_PROFILE);
+    if log_queries {
+        query_log::instrument(&mut connection);
+    }
+    let db: DbConn = Arc::new(Mutex::new(connection));
+    transaction::set_busy_retry(&db, config.busy_retry.clone());
+    let cipher = config.encryption.as_ref().map(|encryption| {
+        let cipher = encryption::Cipher::from_config(encryption).unwrap_or_else(|e| panic!("{}", e));
+        match encryption::encrypt_existing(&cipher, &mut db.lock().unwrap()) {
+            Ok(0) => {}
+            Ok(sealed) => log::info!("Encrypted the emails of {} elus", sealed),
+            Err(e) => panic!("{}", e),
+        }
+        cipher
+    });
+    let sealer = cipher.clone().map(encryption::Sealer::new).unwrap_or_default();
+    let sync_targets = sync::from_config(&config.sync);
+    let outbox = events::Outbox::new(&sync_targets, sealer.clone());
+    let repository: Arc<dyn PersonRepository> = match config.backend {
+        Backend::Sqlite => {
+            let mut repository = db::SqliteRepository::new(db.clone()).with_outbox(outbox).with_retry(config.busy_retry.clone());
+            if let Some(cipher) = cipher {
+                repository = repository.with_cipher(cipher);
+            }
+            match &config.replica {
+                Some(replica) => {
+                    let mut connection = db::establish_replica(&replica.database_url);
+                    if log_queries {
+                        query_log::instrument(&mut connection);
+                    }
+                    let connection = Arc::new(Mutex::new(connection));
+                    Arc::new(repository.with_replica(connection, Duration::from_secs(replica.max_lag)))
+                }
+                None => Arc::new(repository),
+            }
+        }
+        Backend::Memory => Arc::new(events::RecordingRepository::new(Arc::new(repository::MemoryRepository::default()), db.clone(), outbox)),
+    };
+    let tracer = Arc::new(telemetry::Tracer::default());
+    let repository: Arc<dyn PersonRepository> = match &config.tracing {
+        Some(_) => Arc::new(telemetry::TracedRepository::new(repository, tracer.clone())),
+        None => repository,
+    };
+    let repository: Arc<dyn PersonRepository> = match config.public_ids {
+        PublicIds::Sequential => repository,
+        PublicIds::Opaque => Arc::new(repository::OpaqueIdRepository::new(repository)),
+    };
+
+    let mut rocket = rocket::custom(figment)
+        .manage(db)
+        .manage(repository)
+        .manage(geocoding::from_config(&config))
+        .manage(mail::from_config(&config))
+        .manage(storage::from_config(&config))
+        .manage(sync_targets)
+        .manage(sealer)
+        .manage(mandate_types)
+        .manage(search::SearchIndex::default())
+        .manage(shutdown::Workers::default())
+        .manage(flags::Flags::new(&config.flags))
+        .manage(reload::Live::new(config.webhooks.clone()))
+        .manage(scraping::Protections::new(config.scraping.clone()))
+        .manage(tracer)
+        .manage(error_reporting::from_config(&config))
+        .register("/", catchers![problem::catcher, normalize::not_found, validation::unprocessable])
+        .attach(request_id::RequestIds)
+        .attach(transaction::Transactions)
+        .attach(content_headers::ContentHeaders)
+        .attach(error_reporting::ErrorReporting)
+        .attach(csrf::Csrf)
+        .attach(scraping::Scraping)
+        .attach(shutdown::fairing());
+
+    rocket = match &config.open_data {
+        Some(open_data) => rocket.mount("/", api_keys::metered(open_data::read_only(routes()), true)).attach(open_data::Caching(open_data.clone())),
+        None => rocket
+            .mount("/", api_keys::metered(routes(), false))
+            .attach(mail_queue::fairing(config.mail_queue.clone()))
+            .attach(jobs::fairing(config.jobs.clone()))
+            .attach(sync::fairing(config.sync.clone()))
+            .attach(webhooks::fairing())
+            .attach(alerts::fairing(config.alerts.clone())),
+    };
+
+    #[cfg(feature = "nats")]
+    if let (Some(nats), None) = (&config.nats, &config.open_data) {
+        rocket = rocket.attach(nats::fairing(nats.clone()));
+    }
+
+    #[cfg(feature = "testdata")]
+    if config.open_data.is_none() {
+        rocket = rocket.mount("/", testdata::routes());
+    }
+
+    #[cfg(feature = "tantivy")]
+    if let Some(full_text) = &config.full_text {
+        rocket = rocket.attach(full_text::fairing(full_text.clone()));
+    }
+
+    if let Some(tracing) = &config.tracing {
+        rocket = rocket.attach(telemetry::Telemetry).attach(telemetry::fairing(tracing.clone()));
+    }
+
+    if config.redaction.demo {
+        rocket = rocket.attach(redaction::Demo);
+    }
 
-    if db::email_exists(&person_data.email, &mut connection) {
-        return Err(Status::Conflict);
+    if config.envelope {
+        rocket = rocket.attach(envelope::Envelope);
     }
 
-    if db::name_exists(&person_data.name, &mut connection) {
-        return Err(Status::Conflict);
+    // Last, so that the queries of the other fairings' responses, such as
+    // commits, are still tagged.
+    if log_queries {
+        rocket = rocket.attach(query_log::QueryLog);
     }
 
-    let mandates_json = serde_json::to_string(&person_data.mandates).unwrap();
-    db::insert_person(
-        person_data.name.clone(),
-        person_data.email.clone(),
-        mandates_json,
-        &mut connection
-    )?;
+    if let (Some(exports), None) = (&config.exports, &config.open_data) {
+        rocket = rocket.attach(scheduled_export::fairing(exports.clone()));
+    }
 
-    let created = db::get_elu_by_email(&person_data.email, &mut connection)?;
+    if let (Some(seconds), None) = (config.email_check_interval, &config.open_data) {
+        rocket = rocket.attach(deliverability::fairing(Duration::from_secs(seconds)));
+    }
 
-    Ok(Json(Person::from(created)))
+    rocket.manage(config)
 }
 
 #[launch]
 fn rocket() -> _ {
-    let connection = db::establish_connection();
-    rocket::build()
-        .manage(Mutex::new(connection))
-        .mount("/", routes![index, elus, get_person_by_email, create_person_new, create_person_create])
+    if let Some(dir) = argument("--generate-client", std::env::args().skip(1)) {
+        client_gen::write(dir.as_ref(), &routes(), openapi::spec()).unwrap_or_else(|e| panic!("Failed to write the client to {}: {}", dir, e));
+        println!("Client crate written to {}", dir);
+        std::process::exit(0);
+    }
+
+    let mut figment = secrets::merge(rocket::Config::figment()).unwrap_or_else(|e| panic!("{}", e));
+    if let Some(backend) = argument("--backend", std::env::args().skip(1)) {
+        let backend: Backend = backend.parse().unwrap_or_else(|e| panic!("{}", e));
+        figment = figment.merge(("backend", backend));
+    }
+
+    // Without SQLite persons, the remaining tables (communes, documents,
+    // mail) only need to live as long as the process.
+    let connection = match figment.extract_inner("backend") {
+        Ok(Backend::Memory) => db::establish_in_memory(),
+        _ => db::establish_connection(),
+    };
+    build_rocket(figment, connection)
+}
+
+/// The value of `<option> <value>` or `<option>=<value>`, such as
+/// `--backend memory`.
+fn argument(option: &str, mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == option {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(option).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use diesel::prelude::*;
     use rocket::local::blocking::Client;
-    use rocket::http::Status;
+    use rocket::http::{Header, Status};
+
+    pub(crate) fn setup_test_db() -> SqliteConnection {
+        db::establish_in_memory()
+    }
+
+    pub(crate) const ADMIN_TOKEN: &str = "test-admin-token";
 
-    fn setup_test_db() -> SqliteConnection {
-        let mut connection = SqliteConnection::establish(":memory:")
-            .expect("Failed to create in-memory database");
+    /// Client with administrative endpoints enabled, the mail queue and job
+    /// workers disabled, so tests process the queues explicitly, and no
+    /// redaction, so responses deserialize into `Person`.
+    pub(crate) fn client(connection: SqliteConnection) -> Client {
+        build_client(|figment| figment, connection)
+    }
 
-        // Run migrations
-        diesel::sql_query("CREATE TABLE elus (
-            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
-            name TEXT NOT NULL,
-            email TEXT NOT NULL UNIQUE,
-            mandates TEXT NOT NULL
-        )")
-        .execute(&mut connection)
-        .expect("Failed to create table");
+    /// Same as `client`, with test-specific configuration on top.
+    pub(crate) fn build_client(configure: impl FnOnce(Figment) -> Figment, connection: SqliteConnection) -> Client {
+        let figment = rocket::Config::figment()
+            .merge(("admin_token", ADMIN_TOKEN))
+            .merge(("mail_queue.poll_interval", 0))
+            .merge(("jobs.poll_interval", 0))
+            .merge(("sync.poll_interval", 0))
+            .merge(("webhooks.poll_interval", 0))
+            .merge(("alerts.poll_interval", 0))
+            .merge(("redaction.public", Vec::<String>::new()));
+        Client::tracked(build_rocket(configure(figment), connection))
+            .expect("valid rocket instance")
+    }
 
-        connection
+    pub(crate) fn admin() -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN))
     }
 
-    fn insert_test_persons(connection: &mut SqliteConnection) {
-        use self::schema::elus;
+    /// An HTTP server answering one request with `status`, returning the
+    /// request's head and body; for testing HTTP clients.
+    pub(crate) fn serve_once(listener: std::net::TcpListener, status: &'static str) -> std::thread::JoinHandle<(String, String)> {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut writer = stream;
+            write!(writer, "HTTP/1.1 {}\r\ncontent-length: 4\r\nconnection: close\r\n\r\nnope", status).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        })
+    }
 
+    pub(crate) fn insert_test_persons(connection: &mut SqliteConnection) {
         let persons = vec![
             db::NewPerson {
-                name: "Jean Dupont".to_string(),
-                email: "jean.dupont@example.com".to_string(),
-                mandates: serde_json::to_string(&vec!["Maire", "Conseiller régional"]).unwrap(),
+                name: "Jean Dupont".parse().unwrap(),
+                email: "jean.dupont@example.com".parse().unwrap(),
+                mandates: vec!["Maire".to_string(), "Conseiller régional".to_string()],
+                commune_code: Some("75056".to_string()),
+                office_address: Some("Place de l'Hôtel de Ville, 75004 Paris".to_string()),
+                latitude: Some(48.8566),
+                longitude: Some(2.3522),
+                ..Default::default()
             },
             db::NewPerson {
-                name: "Marie Martin".to_string(),
-                email: "marie.martin@example.com".to_string(),
-                mandates: serde_json::to_string(&vec!["Députée"]).unwrap(),
+                name: "Marie Martin".parse().unwrap(),
+                email: "marie.martin@example.com".parse().unwrap(),
+                mandates: vec!["Députée".to_string()],
+                commune_code: Some("75056".to_string()),
+                latitude: Some(48.8620),
+                longitude: Some(2.3186),
+                ..Default::default()
             },
             db::NewPerson {
-                name: "Pierre Durand".to_string(),
-                email: "pierre.durand@example.com".to_string(),
-                mandates: serde_json::to_string(&vec!["Sénateur", "Conseiller municipal"]).unwrap(),
+                name: "Pierre Durand".parse().unwrap(),
+                email: "pierre.durand@example.com".parse().unwrap(),
+                mandates: vec!["Sénateur".to_string(), "Conseiller municipal".to_string()],
+                commune_code: None,
+                office_address: Some("1 place de la Comédie, 69001 Lyon".to_string()),
+                latitude: Some(45.7676),
+                longitude: Some(4.8361),
+                ..Default::default()
             },
         ];
 
-        diesel::insert_into(elus::table)
-            .values(&persons)
-            .execute(connection)
-            .expect("Failed to insert test data");
+        for person in &persons {
+            db::insert_person(person, connection).expect("Failed to insert test data");
+        }
     }
 
     #[test]
     fn test_hello_world() {
         let connection = setup_test_db();
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
         let response = client.get("/").dispatch();
 
         assert_eq!(response.status(), Status::Ok);
@@ -168,11 +851,7 @@ mod tests {
         let mut connection = setup_test_db();
         insert_test_persons(&mut connection);
 
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
         let response = client.get("/elus").dispatch();
 
         assert_eq!(response.status(), Status::Ok);
@@ -187,15 +866,29 @@ mod tests {
     }
 
     #[test]
-    fn test_get_person_by_email() {
+    fn test_get_person_by_uuid_or_id() {
         let mut connection = setup_test_db();
         insert_test_persons(&mut connection);
 
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email]);
+        let client = client(connection);
+        let person: Person = client.get("/elus/marie.martin@example.com").dispatch().into_json().unwrap();
+        assert_eq!(uuid::Uuid::parse_str(&person.uuid).unwrap().get_version_num(), 4);
+
+        let by_uuid: Person = client.get(format!("/elus/{}", person.uuid)).dispatch().into_json().unwrap();
+        assert_eq!(by_uuid.email, "marie.martin@example.com");
+        let by_upper_uuid: Person = client.get(format!("/elus/{}", person.uuid.to_uppercase())).dispatch().into_json().unwrap();
+        assert_eq!(by_upper_uuid.uuid, person.uuid);
+        let by_id: Person = client.get("/elus/2").dispatch().into_json().unwrap();
+        assert_eq!(by_id.uuid, person.uuid);
+        assert_eq!(client.get(format!("/elus/{}", uuid::Uuid::new_v4())).dispatch().status(), Status::NotFound);
+    }
 
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+    #[test]
+    fn test_get_person_by_email() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+
+        let client = client(connection);
 
         // Test finding an existing person
         let response = client.get("/elus/marie.martin@example.com").dispatch();
@@ -212,19 +905,82 @@ mod tests {
         assert_eq!(response.status(), Status::NotFound);
     }
 
+    #[test]
+    fn test_emails_are_normalized() {
+        let client = client(setup_test_db());
+        let person = rocket::serde::json::json!({ "name": "Alice Wonderland", "email": " Alice@Example.COM", "mandates": [] });
+
+        let created: Person = client.post("/elus/create").header(admin()).json(&person).dispatch().into_json().unwrap();
+        assert_eq!(created.email, "Alice@example.com");
+        let response = client.get("/elus/Alice@EXAMPLE.com").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(client.get("/elus/alice").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_names_are_normalized() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let person = rocket::serde::json::json!({ "name": " Jean  Dupont", "email": "jean.dupont2@example.com", "mandates": [] });
+
+        let response = client.post("/elus/create").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let persons: Vec<Person> = client.get("/elus?name=jean%20%20dupont").dispatch().into_json().unwrap();
+        assert_eq!(persons.len(), 1);
+    }
+
+    #[test]
+    fn test_emails() {
+        let client = client(setup_test_db());
+        let person = rocket::serde::json::json!({
+            "name": "Alice Wonderland",
+            "email": "alice@mairie.example",
+            "emails": ["alice@example.com"],
+            "mandates": [],
+        });
+
+        assert_eq!(client.post("/elus/create").json(&person).dispatch().status(), Status::Ok);
+        let found: Person = client.get("/elus/alice@example.com").dispatch().into_json().unwrap();
+        assert_eq!(found.email, "alice@mairie.example");
+        assert_eq!(found.emails, ["alice@mairie.example", "alice@example.com"]);
+        let invalid = rocket::serde::json::json!({ "name": "Bob", "email": "bob@example.com", "emails": ["bob"], "mandates": [] });
+        assert_eq!(client.post("/elus/create").json(&invalid).dispatch().status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        for (name, email, visibility) in [("Alice Interne", "alice@example.com", "internal"), ("Bob Cache", "bob@example.com", "hidden")] {
+            let person = rocket::serde::json::json!({ "name": name, "email": email, "mandates": ["Maire"], "visibility": visibility });
+            assert_eq!(client.post("/elus/create").header(admin()).json(&person).dispatch().status(), Status::Ok);
+        }
+        let names = |request: rocket::local::blocking::LocalRequest| -> Vec<String> {
+            let persons: Vec<rocket::serde::json::Value> = request.dispatch().into_json().unwrap();
+            persons.iter().map(|person| person["name"].as_str().unwrap().to_string()).collect()
+        };
+
+        assert_eq!(names(client.get("/elus?mandate=Maire")), ["Jean Dupont"]);
+        assert_eq!(names(client.get("/elus?mandate=Maire").header(admin())), ["Jean Dupont", "Alice Interne", "Bob Cache"]);
+        assert_eq!(names(client.get("/elus/search?q=bob")), Vec::<String>::new());
+        assert_eq!(client.get("/elus/bob@example.com").dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/elus/bob@example.com").header(admin()).dispatch().status(), Status::Ok);
+        let lookup = rocket::serde::json::json!({ "emails": ["alice@example.com", "jean.dupont@example.com"] });
+        assert_eq!(names(client.post("/elus/lookup").json(&lookup)), ["Jean Dupont"]);
+    }
+
     #[test]
     fn test_create_person_new() {
         let connection = setup_test_db();
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email, create_person_new, create_person_create]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
 
         let new_person = Person {
-            name: "Alice Wonderland".to_string(),
-            email: "alice@example.com".to_string(),
+            name: "Alice Wonderland".parse().unwrap(),
+            email: "alice@example.com".parse().unwrap(),
             mandates: vec!["Conseillère".to_string()],
+            ..Default::default()
         };
 
         let response = client
@@ -241,19 +997,43 @@ mod tests {
         assert_eq!(created.mandates[0], "Conseillère");
     }
 
+    #[test]
+    fn test_consent_is_recorded_by_admins() {
+        let client = client(setup_test_db());
+        let person = |email: &str| Person {
+            name: email.split('@').next().unwrap().parse().unwrap(),
+            email: email.parse().unwrap(),
+            may_contact_by_email: true,
+            consent_source: Some("formulaire".to_string()),
+            ..Default::default()
+        };
+
+        let created: Person = client.post("/elus/create").json(&person("alice@example.com")).dispatch().into_json().unwrap();
+        assert_eq!((created.may_contact_by_email, created.consent_source), (false, None));
+        let created: Person = client.post("/elus/create").header(admin()).json(&person("bob@example.com")).dispatch().into_json().unwrap();
+        assert_eq!((created.may_contact_by_email, created.consent_source.as_deref()), (true, Some("formulaire")));
+    }
+
+    #[test]
+    fn test_visibility_is_chosen_by_admins() {
+        let client = client(setup_test_db());
+        let person = rocket::serde::json::json!({ "name": "Alice Cachee", "email": "alice@example.com", "mandates": [], "visibility": "hidden" });
+
+        let created: Person = client.post("/elus/create").json(&person).dispatch().into_json().unwrap();
+        assert_eq!(created.visibility, Some(visibility::Visibility::Public));
+        assert_eq!(client.get("/elus/alice@example.com").dispatch().status(), Status::Ok);
+    }
+
     #[test]
     fn test_create_person_create_alias() {
         let connection = setup_test_db();
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email, create_person_new, create_person_create]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
 
         let new_person = Person {
-            name: "Bob Builder".to_string(),
-            email: "bob@example.com".to_string(),
+            name: "Bob Builder".parse().unwrap(),
+            email: "bob@example.com".parse().unwrap(),
             mandates: vec!["Architecte".to_string(), "Ingénieur".to_string()],
+            ..Default::default()
         };
 
         let response = client
@@ -274,16 +1054,13 @@ mod tests {
         let mut connection = setup_test_db();
         insert_test_persons(&mut connection);
 
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email, create_person_new, create_person_create]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
 
         let duplicate_email_person = Person {
-            name: "Different Name".to_string(),
-            email: "jean.dupont@example.com".to_string(),
+            name: "Different Name".parse().unwrap(),
+            email: "jean.dupont@example.com".parse().unwrap(),
             mandates: vec!["Some mandate".to_string()],
+            ..Default::default()
         };
 
         let response = client
@@ -299,16 +1076,13 @@ mod tests {
         let mut connection = setup_test_db();
         insert_test_persons(&mut connection);
 
-        let rocket = rocket::build()
-            .manage(Mutex::new(connection))
-            .mount("/", routes![index, elus, get_person_by_email, create_person_new, create_person_create]);
-
-        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let client = client(connection);
 
         let duplicate_name_person = Person {
-            name: "Jean Dupont".to_string(),
-            email: "different.email@example.com".to_string(),
+            name: "Jean Dupont".parse().unwrap(),
+            email: "different.email@example.com".parse().unwrap(),
             mandates: vec!["Some mandate".to_string()],
+            ..Default::default()
         };
 
         let response = client
@@ -318,4 +1092,419 @@ mod tests {
 
         assert_eq!(response.status(), Status::Conflict);
     }
+
+    #[test]
+    fn test_create_person_unknown_commune() {
+        let connection = setup_test_db();
+        let client = client(connection);
+
+        let person = Person {
+            name: "Claire Lune".parse().unwrap(),
+            email: "claire@example.com".parse().unwrap(),
+            mandates: vec!["Maire".to_string()],
+            commune_code: Some("99999".to_string()),
+            ..Default::default()
+        };
+
+        let response = client
+            .post("/elus/new")
+            .json(&person)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn test_elus_near() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/elus/near?lat=48.8534&lon=2.3488&radius_km=5").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        let names: Vec<&str> = persons.iter().map(|person| person.name.as_str()).collect();
+        assert_eq!(names, vec!["Jean Dupont", "Marie Martin"]);
+
+        let response = client.get("/elus/near?lat=48.8534&lon=2.3488&radius_km=500").dispatch();
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 3);
+        assert_eq!(persons[2].name, "Pierre Durand");
+
+        let persons: Vec<Person> = client.get("/elus/near?lat=48.8534&lon=2.3488&radius_km=500&mandate=maire").dispatch().into_json().unwrap();
+        assert_eq!(persons.iter().map(|person| person.name.as_str()).collect::<Vec<_>>(), ["Jean Dupont"]);
+        let page: rocket::serde::json::Value = client.get("/elus/near?lat=48.8534&lon=2.3488&radius_km=500&page=2&per_page=1").dispatch().into_json().unwrap();
+        assert_eq!(page["elus"][0]["name"], "Marie Martin");
+
+        let response = client.get("/elus/near?lat=120&lon=2.3488&radius_km=5").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    struct FixedGeocoder;
+
+    #[rocket::async_trait]
+    impl Geocoder for FixedGeocoder {
+        async fn geocode(&self, _address: &str) -> Option<geocoding::Coordinates> {
+            Some(geocoding::Coordinates { latitude: 43.6045, longitude: 1.4440 })
+        }
+    }
+
+    #[test]
+    fn test_create_person_geocodes_office_address() {
+        let geocoder: Box<dyn Geocoder> = Box::new(FixedGeocoder);
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        let repository: Arc<dyn PersonRepository> = Arc::new(db::SqliteRepository::new(db.clone()));
+        let rocket = rocket::build()
+            .manage(db)
+            .manage(repository)
+            .manage(geocoder)
+            .manage(MandateTypes::default())
+            .manage(AppConfig::default())
+            .mount("/", routes![create_person_new]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+
+        let person = Person {
+            name: "Claire Lune".parse().unwrap(),
+            email: "claire@example.com".parse().unwrap(),
+            office_address: Some("Place du Capitole, 31000 Toulouse".to_string()),
+            ..Default::default()
+        };
+        let response = client.post("/elus/new").json(&person).dispatch();
+        let created: Person = response.into_json().expect("valid JSON");
+        assert_eq!((created.latitude, created.longitude), (Some(43.6045), Some(1.4440)));
+
+        let person = Person {
+            name: "Luc Soleil".parse().unwrap(),
+            email: "luc@example.com".parse().unwrap(),
+            office_address: Some("Place du Capitole, 31000 Toulouse".to_string()),
+            latitude: Some(43.6),
+            longitude: Some(1.43),
+            ..Default::default()
+        };
+        let response = client.post("/elus/new").json(&person).dispatch();
+        let created: Person = response.into_json().expect("valid JSON");
+        assert_eq!((created.latitude, created.longitude), (Some(43.6), Some(1.43)));
+    }
+
+    #[test]
+    fn test_elus_filters() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/elus?mandate=Maire&commune=75056").dispatch();
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 1);
+        assert_eq!(persons[0].name, "Jean Dupont");
+
+        let response = client.get("/elus?name=mar").dispatch();
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 1);
+        assert_eq!(persons[0].name, "Marie Martin");
+    }
+
+    #[test]
+    fn test_elus_filter_expression() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let names = |filter: &str| -> Vec<String> {
+            let uri = format!("/elus?filter={}", rocket::http::RawStr::new(filter).percent_encode());
+            let persons: Vec<Person> = client.get(uri).dispatch().into_json().expect("valid JSON");
+            persons.into_iter().map(|person| person.name.to_string()).collect()
+        };
+
+        assert_eq!(names(r#"mandate eq "Maire" and commune eq "75056""#), ["Jean Dupont"]);
+        assert_eq!(names(r#"mandate eq "Députée" or name contains "durand""#), ["Marie Martin", "Pierre Durand"]);
+        assert_eq!(names(r#"not (commune eq "75056") and email_status eq "unchecked""#), ["Pierre Durand"]);
+        assert_eq!(names(r#"commune ne "75056""#), ["Pierre Durand"]);
+        // The other criteria narrow the expression down.
+        let persons: Vec<Person> = client.get("/elus?name=marie&filter=commune%20eq%20%2275056%22").dispatch().into_json().unwrap();
+        assert_eq!(persons.len(), 1);
+
+        assert_eq!(client.get("/elus?filter=email%20eq%20%22jean%22").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/elus?filter=mandate%20eq").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_elus_sort() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let names = |uri: &str| match client.get(uri).dispatch().into_json::<Listing>() {
+            Some(Listing::All(elus) | Listing::Page { elus, .. }) => elus.into_iter().map(|person| person.name.to_string()).collect::<Vec<_>>(),
+            None => panic!("expected a listing"),
+        };
+
+        assert_eq!(names("/elus?sort=-name"), ["Pierre Durand", "Marie Martin", "Jean Dupont"]);
+        assert_eq!(names("/elus?sort=-name&per_page=2&page=2"), ["Jean Dupont"]);
+        assert_eq!(names("/elus?commune=75056&sort=name"), ["Jean Dupont", "Marie Martin"]);
+
+        let problem = |uri: &str| {
+            let response = client.get(uri).dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+            response.into_json::<rocket::serde::json::Value>().unwrap()["detail"].as_str().unwrap().to_string()
+        };
+        assert_eq!(problem("/elus?sort=email"), "sort must be one of name, updated_at, optionally prefixed by - for descending order.");
+        assert!(problem("/elus?sort=-name&after=MTpKZWFu").starts_with("after only follows the order by name"));
+        assert_eq!(problem("/elus?per_page=ten"), "page, per_page and limit must be numbers.");
+        assert!(problem("/elus?filter=mandate%20eq").starts_with("Invalid filter: expected a quoted value"));
+    }
+
+    #[test]
+    fn test_elus_pagination() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let page = |uri: &str| match client.get(uri).dispatch().into_json::<Listing>() {
+            Some(Listing::Page { elus, next_cursor }) => (elus.into_iter().map(|person| person.name.to_string()).collect::<Vec<_>>(), next_cursor),
+            other => panic!("expected a page, got {:?}", other),
+        };
+
+        let (names, cursor) = page("/elus?limit=2");
+        assert_eq!(names, vec!["Jean Dupont", "Marie Martin"]);
+        let cursor = cursor.expect("a next page");
+        let (names, cursor) = page(&format!("/elus?after={}&limit=2", cursor));
+        assert_eq!((names, cursor), (vec!["Pierre Durand".to_string()], None));
+
+        assert_eq!(page("/elus?page=2&per_page=2").0, vec!["Pierre Durand"]);
+        assert_eq!(page("/elus?mandate=Maire&per_page=2"), (vec!["Jean Dupont".to_string()], None));
+
+        assert_eq!(client.get("/elus?after=bogus").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/elus?page=0").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_configured_page_sizes() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("pagination.default_per_page", 2)).merge(("pagination.max_per_page", 2)), connection);
+
+        let listing: rocket::serde::json::Value = client.get("/elus?page=1").dispatch().into_json().unwrap();
+        assert_eq!(listing["elus"].as_array().map(Vec::len), Some(2));
+
+        let response = client.get("/elus?per_page=3").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+        let problem: rocket::serde::json::Value = response.into_json().unwrap();
+        assert_eq!(problem["instance"], "/elus");
+        assert!(problem["detail"].as_str().unwrap().contains("between 1 and 2"));
+    }
+
+    #[test]
+    fn test_lookup_persons() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let emails = ["pierre.durand@example.com", "nobody@example.com", "jean.dupont@example.com"];
+        let response = client.post("/elus/lookup").json(&rocket::serde::json::json!({ "emails": emails })).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let persons: Vec<Person> = response.into_json().expect("valid JSON");
+        let names: Vec<&str> = persons.iter().map(|person| person.name.as_str()).collect();
+        assert_eq!(names, vec!["Pierre Durand", "Jean Dupont"]);
+        assert_eq!(persons[1].mandates, vec!["Maire", "Conseiller régional"]);
+
+        let emails = vec!["jean.dupont@example.com"; MAX_LOOKUP_EMAILS + 1];
+        let response = client.post("/elus/lookup").json(&rocket::serde::json::json!({ "emails": emails })).dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+
+    #[test]
+    fn test_redaction() {
+        use rocket::serde::json::Value;
+
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        let client = build_client(|figment| figment.merge(("redaction.public", ["email", "office_address"])), connection);
+
+        let response = client.get("/elus/jean.dupont@example.com").dispatch();
+        let person: Value = response.into_json().expect("valid JSON");
+        assert_eq!(person["name"], "Jean Dupont");
+        assert!(person.get("email").is_none() && person.get("office_address").is_none());
+
+        let response = client.get("/elus/jean.dupont@example.com").header(admin()).dispatch();
+        let person: Person = response.into_json().expect("valid JSON");
+        assert_eq!(person.email, "jean.dupont@example.com");
+
+        for uri in ["/elus", "/elus?limit=1", "/elus/near?lat=48.85&lon=2.35&radius_km=10", "/communes/75056/elus"] {
+            let listing: Value = client.get(uri).dispatch().into_json().expect("valid JSON");
+            let persons = listing.get("elus").unwrap_or(&listing).as_array().expect("persons");
+            assert!(!persons.is_empty(), "{}", uri);
+            assert!(persons.iter().all(|person| person.get("email").is_none()), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_update_person() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let person = Person {
+            name: "Marie Martin".parse().unwrap(),
+            email: "marie.martin@example.org".parse().unwrap(),
+            mandates: vec!["Députée".to_string(), "Conseillère municipale".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(client.put("/elus/marie.martin@example.com").json(&person).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.put("/elus/claire@example.com").json(&person).dispatch().status(), Status::Unauthorized);
+        let response = client.put("/elus/marie.martin@example.com").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let updated: Person = response.into_json().expect("valid JSON");
+        assert_eq!(updated.mandates.len(), 2);
+        assert_eq!((updated.latitude, updated.commune_code), (None, None));
+
+        let response = client.get("/elus/marie.martin@example.com").dispatch();
+        assert_eq!(response.status(), Status::PermanentRedirect);
+
+        let person = Person { name: "Jean Dupont".parse().unwrap(), ..person };
+        let response = client.put("/elus/marie.martin@example.org").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client.put("/elus/999").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_upsert_person() {
+        let client = client(setup_test_db());
+        let person = Person {
+            name: "Claire Lune".parse().unwrap(),
+            email: "claire@example.com".parse().unwrap(),
+            mandates: vec!["Maire".to_string()],
+            ..Default::default()
+        };
+
+        let response = client.put("/elus/claire@example.com").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let location = response.headers().get_one("Location").unwrap().to_string();
+        let created: Person = response.into_json().unwrap();
+        assert_eq!(location, format!("/elus/{}", created.uuid));
+
+        let response = client.put("/elus/claire@example.com").header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.put("/elus/claire@example.com").header(admin()).header(Header::new("If-None-Match", "*")).json(&person).dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+
+        let other = Person { name: "Luc Soleil".parse().unwrap(), email: "luc@example.com".parse().unwrap(), ..person };
+        let response = client.put("/elus/soleil@example.com").header(admin()).json(&other).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client.put("/elus/luc@example.com").header(admin()).header(Header::new("If-None-Match", "*")).json(&other).dispatch();
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    #[test]
+    fn test_uuid_path_survives_email_change() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/elus/marie.martin@example.com").dispatch();
+        let location = response.headers().get_one("Content-Location").unwrap().to_string();
+        let marie: Person = response.into_json().unwrap();
+        assert_eq!(location, format!("/elus/{}", marie.uuid));
+
+        let person = Person {
+            name: marie.name,
+            email: "marie.martin@example.org".parse().unwrap(),
+            mandates: marie.mandates,
+            ..Default::default()
+        };
+        let response = client.put(location.clone()).header(admin()).json(&person).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Location"), Some(location.as_str()));
+
+        let renamed: Person = client.get(location.clone()).dispatch().into_json().unwrap();
+        assert_eq!(renamed.email, "marie.martin@example.org");
+        assert_eq!(client.delete(location.clone()).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.get(location).dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_former_email_redirects() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let person = Person {
+            name: "Marie Martin".parse().unwrap(),
+            email: "marie.martin@example.org".parse().unwrap(),
+            mandates: vec!["Députée".to_string()],
+            ..Default::default()
+        };
+        let marie: Person = client.put("/elus/marie.martin@example.com").header(admin()).json(&person).dispatch().into_json().unwrap();
+
+        let response = client.get("/elus/marie.martin@example.com").dispatch();
+        assert_eq!(response.status(), Status::PermanentRedirect);
+        assert_eq!(response.headers().get_one("Location"), Some(format!("/elus/{}", marie.uuid).as_str()));
+        assert_eq!(client.delete("/elus/marie.martin@example.com").header(admin()).dispatch().status(), Status::NotFound);
+
+        // Taking the address back ends the redirect.
+        let person = Person { email: "marie.martin@example.com".parse().unwrap(), ..person };
+        assert_eq!(client.put("/elus/marie.martin@example.org").header(admin()).json(&person).dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/elus/marie.martin@example.com").dispatch().status(), Status::Ok);
+        let response = client.get("/elus/marie.martin@example.org").dispatch();
+        assert_eq!(response.status(), Status::PermanentRedirect);
+    }
+
+    #[test]
+    fn test_delete_person() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.delete("/elus/pierre.durand@example.com").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.delete("/elus/pierre.durand@example.com").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.get("/elus/pierre.durand@example.com").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.delete("/elus/pierre.durand@example.com").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Nothing is deleted when a step fails.
+        assert_eq!(client.put("/elus/jean.dupont@example.com/tags/bureau").header(admin()).dispatch().status(), Status::NoContent);
+        let db = client.rocket().state::<DbConn>().unwrap();
+        diesel::connection::SimpleConnection::batch_execute(&mut *db.lock().unwrap(), "DROP TABLE email_aliases").unwrap();
+        let response = client.delete("/elus/jean.dupont@example.com").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+        let tagged: Vec<serde_json::Value> = client.get("/elus?tag=bureau").dispatch().into_json().unwrap();
+        assert_eq!(tagged.len(), 1);
+    }
+
+    #[test]
+    fn test_argument() {
+        let args = |args: &[&str]| argument("--backend", args.iter().map(|arg| arg.to_string()));
+
+        assert_eq!(args(&["--backend", "memory"]), Some("memory".to_string()));
+        assert_eq!(args(&["-v", "--backend=sqlite"]), Some("sqlite".to_string()));
+        assert_eq!(args(&["--backends=sqlite"]), None);
+        assert_eq!(args(&[]), None);
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let client = build_client(|figment| figment.merge(("backend", "memory")), db::establish_in_memory());
+
+        let person = Person {
+            name: "Alice Wonderland".parse().unwrap(),
+            email: "alice@example.com".parse().unwrap(),
+            mandates: vec!["Conseillère".to_string()],
+            ..Default::default()
+        };
+        let response = client.post("/elus/new").json(&person).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let persons: Vec<Person> = client.get("/elus?mandate=Conseill%C3%A8re").dispatch().into_json().expect("valid JSON");
+        assert_eq!(persons.len(), 1);
+
+        // Nothing reached the SQLite tables.
+        let db = client.rocket().state::<DbConn>().unwrap();
+        assert_eq!(schema::elus::table.count().get_result::<i64>(&mut *db.lock().unwrap()), Ok(0));
+    }
 }
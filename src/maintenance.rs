@@ -0,0 +1,206 @@
+//! Upkeep of the SQLite database, which operators run during their
+//! maintenance window: `POST /admin/db/maintenance` checkpoints the WAL
+//! into the database, vacuums it and refreshes the statistics of the
+//! query planner, and `GET /admin/db/stats` tells how big it is and what
+//! takes the room. Vacuuming rewrites the whole file and holds the
+//! connection meanwhile, so requests wait for it.
+
+use std::time::Instant;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+use rocket::http::Status;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::{db, DbConn};
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    value: i64,
+}
+
+fn pragma(name: &str, connection: &mut SqliteConnection) -> QueryResult<i64> {
+    diesel::sql_query(format!("SELECT {0} AS value FROM pragma_{0}", name)).get_result::<Count>(connection).map(|count| count.value)
+}
+
+#[derive(QueryableByName)]
+struct File {
+    #[diesel(sql_type = Text)]
+    file: String,
+}
+
+/// Path of the main database file; `None` for in-memory databases.
+fn database_file(connection: &mut SqliteConnection) -> QueryResult<Option<String>> {
+    let main = diesel::sql_query("SELECT file FROM pragma_database_list WHERE name = 'main'").get_result::<File>(connection)?;
+    Ok(Some(main.file).filter(|file| !file.is_empty()))
+}
+
+fn file_size(path: &str) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+#[derive(Debug, Serialize, Deserialize, QueryableByName)]
+#[serde(crate = "rocket::serde")]
+pub struct ObjectSize {
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    /// `table` or `index`.
+    #[diesel(sql_type = Text)]
+    pub kind: String,
+    #[diesel(sql_type = BigInt)]
+    pub pages: i64,
+    #[diesel(sql_type = BigInt)]
+    pub bytes: i64,
+}
+
+/// The tables and indexes by size, biggest first; `None` when SQLite was
+/// built without the `dbstat` table telling them.
+fn object_sizes(connection: &mut SqliteConnection) -> Option<Vec<ObjectSize>> {
+    diesel::sql_query(
+        "SELECT dbstat.name AS name, sqlite_schema.type AS kind, COUNT(*) AS pages, SUM(dbstat.pgsize) AS bytes \
+         FROM dbstat JOIN sqlite_schema ON sqlite_schema.name = dbstat.name \
+         GROUP BY dbstat.name ORDER BY bytes DESC, dbstat.name",
+    )
+    .load(connection)
+    .ok()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Stats {
+    /// Size of the database file, and of its WAL; null in memory.
+    pub file_bytes: Option<u64>,
+    pub wal_bytes: Option<u64>,
+    pub page_size: i64,
+    pub page_count: i64,
+    /// Unused pages, which vacuuming gives back.
+    pub freelist_count: i64,
+    pub objects: Option<Vec<ObjectSize>>,
+}
+
+pub fn stats(connection: &mut SqliteConnection) -> QueryResult<Stats> {
+    let file = database_file(connection)?;
+    Ok(Stats {
+        file_bytes: file.as_deref().and_then(file_size),
+        wal_bytes: file.as_deref().and_then(|file| file_size(&format!("{}-wal", file))),
+        page_size: pragma("page_size", connection)?,
+        page_count: pragma("page_count", connection)?,
+        freelist_count: pragma("freelist_count", connection)?,
+        objects: object_sizes(connection),
+    })
+}
+
+#[derive(QueryableByName)]
+struct Checkpoint {
+    #[diesel(sql_type = BigInt)]
+    busy: i64,
+    #[diesel(sql_type = BigInt)]
+    log: i64,
+    #[diesel(sql_type = BigInt)]
+    checkpointed: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Maintenance {
+    /// Whether readers kept the WAL from being checkpointed in full.
+    pub checkpoint_busy: bool,
+    /// Frames of the WAL, and those moved into the database; -1 when the
+    /// database isn't in WAL mode.
+    pub wal_frames: i64,
+    pub checkpointed_frames: i64,
+    /// Pages before and after vacuuming.
+    pub pages_before: i64,
+    pub pages_after: i64,
+    pub duration_ms: u128,
+}
+
+pub fn run(connection: &mut SqliteConnection) -> QueryResult<Maintenance> {
+    let started = Instant::now();
+    let pages_before = pragma("page_count", connection)?;
+    // Checkpoints first so that the vacuum sees, and compacts, every page;
+    // truncating would report no frames, so the WAL is truncated after.
+    let checkpoint = diesel::sql_query("PRAGMA wal_checkpoint(RESTART)").get_result::<Checkpoint>(connection)?;
+    connection.batch_execute("VACUUM; ANALYZE; PRAGMA wal_checkpoint(TRUNCATE);")?;
+
+    Ok(Maintenance {
+        checkpoint_busy: checkpoint.busy != 0,
+        wal_frames: checkpoint.log,
+        checkpointed_frames: checkpoint.checkpointed,
+        pages_before,
+        pages_after: pragma("page_count", connection)?,
+        duration_ms: started.elapsed().as_millis(),
+    })
+}
+
+#[post("/admin/db/maintenance")]
+fn maintenance(_admin: Admin, db: &State<DbConn>) -> Result<Json<Maintenance>, Status> {
+    let report = run(&mut *db::lock(db)?).map_err(|e| {
+        log::error!("Database maintenance failed: {}", e);
+        Status::InternalServerError
+    })?;
+    log::info!("Database maintenance done in {} ms: {} pages, down from {}", report.duration_ms, report.pages_after, report.pages_before);
+
+    Ok(Json(report))
+}
+
+#[get("/admin/db/stats")]
+fn db_stats(_admin: Admin, db: &State<DbConn>) -> Result<Json<Stats>, Status> {
+    stats(&mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![maintenance, db_stats]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_run_on_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("elus.db");
+        let mut connection = db::establish(path.to_str().unwrap());
+        connection.batch_execute("PRAGMA journal_mode = WAL; PRAGMA wal_autocheckpoint = 0; CREATE TABLE filler (data TEXT); CREATE INDEX filler_data ON filler (data);").unwrap();
+        for _ in 0..200 {
+            connection.batch_execute("INSERT INTO filler VALUES (hex(randomblob(500)))").unwrap();
+        }
+        connection.batch_execute("DELETE FROM filler").unwrap();
+
+        let before = stats(&mut connection).unwrap();
+        assert!(before.file_bytes.is_some() && before.wal_bytes.unwrap() > 0);
+        let report = run(&mut connection).unwrap();
+        assert!(report.wal_frames > 0 && !report.checkpoint_busy);
+        assert!(report.pages_after < report.pages_before);
+        let after = stats(&mut connection).unwrap();
+        assert_eq!((after.freelist_count, after.wal_bytes), (0, Some(0)));
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        assert_eq!(client.get("/admin/db/stats").dispatch().status(), Status::Unauthorized);
+        let stats: Stats = client.get("/admin/db/stats").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(stats.file_bytes, None);
+        assert!(stats.page_count > 0);
+        if let Some(objects) = stats.objects {
+            assert!(objects.iter().any(|object| object.name == "elus" && object.kind == "table"));
+        }
+
+        assert_eq!(client.post("/admin/db/maintenance").dispatch().status(), Status::Unauthorized);
+        let response = client.post("/admin/db/maintenance").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let report: Maintenance = response.into_json().unwrap();
+        assert_eq!(report.wal_frames, -1);
+    }
+}
@@ -0,0 +1,168 @@
+//! Mandate types: codes which can be given on write in place of titles,
+//! and labels of their titles in other languages than French, the
+//! language titles are stored in. Responses show the labels in the
+//! language preferred by the caller's `Accept-Language`, falling back to
+//! English when the type has no label in any of the languages accepted.
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::request::Request;
+use rocket::serde::json::Value;
+
+use crate::schema;
+
+/// Language of the stored titles, and of responses to callers not telling
+/// which languages they accept.
+pub const DEFAULT_LANGUAGE: &str = "fr";
+pub const FALLBACK_LANGUAGE: &str = "en";
+
+#[derive(Debug, Default, Clone)]
+pub struct MandateType {
+    pub code: String,
+    /// Labels by language code, the French one being the stored title.
+    pub labels: HashMap<String, String>,
+}
+
+/// The known mandate types, loaded once at startup.
+#[derive(Debug, Default, Clone)]
+pub struct MandateTypes(Vec<MandateType>);
+
+impl MandateTypes {
+    pub fn load(connection: &mut SqliteConnection) -> QueryResult<MandateTypes> {
+        use self::schema::mandate_labels::dsl::*;
+
+        let rows: Vec<(String, String, String)> = mandate_labels.order((code, language)).select((code, language, label)).load(connection)?;
+        let mut types: Vec<MandateType> = Vec::new();
+        for (type_code, type_language, type_label) in rows {
+            if types.last().is_none_or(|last| last.code != type_code) {
+                types.push(MandateType { code: type_code, ..Default::default() });
+            }
+            types.last_mut().unwrap().labels.insert(type_language, type_label);
+        }
+
+        Ok(MandateTypes(types))
+    }
+
+    /// The title to store for a mandate given by code or by title.
+    pub fn title(&self, mandate: &str) -> String {
+        self.0
+            .iter()
+            .find(|mandate_type| mandate_type.code == mandate)
+            .and_then(|mandate_type| mandate_type.labels.get(DEFAULT_LANGUAGE))
+            .cloned()
+            .unwrap_or_else(|| mandate.to_string())
+    }
+
+    /// The label of a stored title in the first of `languages` the type has
+    /// one in; titles of no known type are left as they are.
+    pub fn label<'a>(&'a self, title: &'a str, languages: &[String]) -> &'a str {
+        let Some(mandate_type) = self.0.iter().find(|mandate_type| mandate_type.labels.get(DEFAULT_LANGUAGE).is_some_and(|label| label == title)) else {
+            return title;
+        };
+        if languages.is_empty() {
+            return title;
+        }
+
+        languages
+            .iter()
+            .map(String::as_str)
+            .chain([FALLBACK_LANGUAGE])
+            .find_map(|language| mandate_type.labels.get(language))
+            .map_or(title, String::as_str)
+    }
+
+    /// The language `label` picks for `languages`, for the types having a
+    /// label in it.
+    pub fn language<'a>(&self, languages: &'a [String]) -> &'a str {
+        if languages.is_empty() {
+            return DEFAULT_LANGUAGE;
+        }
+
+        languages
+            .iter()
+            .map(String::as_str)
+            .find(|language| self.0.iter().any(|mandate_type| mandate_type.labels.contains_key(*language)))
+            .unwrap_or(FALLBACK_LANGUAGE)
+    }
+
+    /// Replaces the titles of a serialized person's mandates with their
+    /// labels in `languages`.
+    pub fn localize(&self, person: &mut Value, languages: &[String]) {
+        if let Some(Value::Array(mandates)) = person.get_mut("mandates") {
+            for mandate in mandates {
+                if let Value::String(title) = mandate {
+                    *title = self.label(title, languages).to_string();
+                }
+            }
+        }
+    }
+}
+
+/// The primary subtags of the languages of an `Accept-Language` header,
+/// most preferred first.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let tag = parts.next()?.split('-').next()?.to_ascii_lowercase();
+            let quality = parts
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// The languages the caller accepts; none when they didn't say.
+pub fn accepted_languages(request: &Request<'_>) -> Vec<String> {
+    request.headers().get_one("Accept-Language").map(parse_accept_language).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, insert_test_persons, setup_test_db};
+    use rocket::http::Header;
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(parse_accept_language("en-GB,en;q=0.9,fr;q=0.8"), vec!["en", "en", "fr"]);
+        assert_eq!(parse_accept_language("de;q=0.5, it, *;q=0.1, es;q=0"), vec!["it", "de"]);
+        assert_eq!(parse_accept_language(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_labels() {
+        let types = MandateTypes::load(&mut setup_test_db()).unwrap();
+        let languages = |header: &str| parse_accept_language(header);
+
+        assert_eq!(types.title("maire"), "Maire");
+        assert_eq!(types.title("Maire adjointe"), "Maire adjointe");
+        assert_eq!(types.label("Maire", &[]), "Maire");
+        assert_eq!(types.label("Maire", &languages("en")), "Mayor");
+        assert_eq!(types.label("Maire", &languages("fr-FR,en;q=0.5")), "Maire");
+        assert_eq!(types.label("Sénateur", &languages("de")), "Senator");
+        assert_eq!(types.label("Maire adjointe", &languages("en")), "Maire adjointe");
+    }
+
+    #[test]
+    fn test_localized_responses() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let person: Value = client.get("/elus/1").header(Header::new("Accept-Language", "en-US")).dispatch().into_json().unwrap();
+        assert_eq!(person["mandates"], rocket::serde::json::json!(["Mayor", "Regional councillor"]));
+        let person: Value = client.get("/elus/1").dispatch().into_json().unwrap();
+        assert_eq!(person["mandates"], rocket::serde::json::json!(["Maire", "Conseiller régional"]));
+
+        let found: Value = client.get("/elus?mandate=conseiller_regional").dispatch().into_json().unwrap();
+        assert_eq!(found[0]["name"], "Jean Dupont");
+    }
+}
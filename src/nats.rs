@@ -0,0 +1,292 @@
+//! Publishing of change events to NATS, as CloudEvents JSON on
+//! `<subject_prefix>.<kind>` subjects (`rckd.elus.created`, ...). The
+//! publisher tails the events table from the last event the server
+//! acknowledged, so events are published at least once, in order, and the
+//! backlog is caught up with after an outage or restart.
+//!
+//! NATS is the only broker events are published to; AMQP brokers such as
+//! RabbitMQ aren't supported. Only the bits of the NATS client protocol
+//! needed to publish are implemented, over plain TCP. Events are only
+//! marked as published once the server answered a PING sent after them,
+//! so those lost with a broken connection are published again over the
+//! next one.
+
+use std::io;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::serde::Deserialize;
+use rocket::tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use rocket::tokio::net::TcpStream;
+
+use crate::cloudevents;
+use crate::config::AppConfig;
+use crate::db::{self, DbError};
+use crate::encryption::Sealer;
+use crate::redaction::RedactionConfig;
+use crate::{events, shutdown, DbConn};
+
+/// Consumer whose cursor in the events table tracks what was published.
+const CONSUMER: &str = "nats";
+/// Events published per batch, before waiting for the server to
+/// acknowledge them.
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NatsConfig {
+    /// `nats://[token@]host[:port]`.
+    pub url: String,
+    #[serde(default = "default_subject_prefix")]
+    pub subject_prefix: String,
+    /// Seconds between two checks for new events.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn default_subject_prefix() -> String {
+    "rckd.elus".to_string()
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+/// Host, port and auth token of a `nats://` URL.
+fn parse_url(url: &str) -> Result<(String, u16, Option<String>), String> {
+    let rest = url.strip_prefix("nats://").ok_or_else(|| format!("unsupported NATS URL {}", url))?;
+    let rest = rest.trim_end_matches('/');
+    let (token, address) = match rest.rsplit_once('@') {
+        Some((token, address)) => (Some(token.to_string()), address),
+        None => (None, rest),
+    };
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port in NATS URL {}", url))?),
+        None => (address, 4222),
+    };
+    Ok((host.to_string(), port, token))
+}
+
+pub struct NatsClient {
+    reader: BufReader<rocket::tokio::net::tcp::OwnedReadHalf>,
+    writer: rocket::tokio::net::tcp::OwnedWriteHalf,
+}
+
+impl NatsClient {
+    pub async fn connect(url: &str) -> io::Result<Self> {
+        let (host, port, token) = parse_url(url).map_err(io::Error::other)?;
+        let (reader, writer) = TcpStream::connect((host.as_str(), port)).await?.into_split();
+        let mut client = NatsClient { reader: BufReader::new(reader), writer };
+
+        let info = client.read_line().await?;
+        if !info.starts_with("INFO ") {
+            return Err(io::Error::other(format!("unexpected NATS greeting {:?}", info)));
+        }
+        let mut options = serde_json::json!({ "verbose": false, "pedantic": false, "name": "rckd", "lang": "rust", "protocol": 1 });
+        if let Some(token) = token {
+            options["auth_token"] = token.into();
+        }
+        client.writer.write_all(format!("CONNECT {}\r\n", options).as_bytes()).await?;
+        client.flush().await?;
+        Ok(client)
+    }
+
+    async fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "NATS server closed the connection"));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    pub async fn publish(&mut self, subject: &str, payload: &[u8]) -> io::Result<()> {
+        self.writer.write_all(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes()).await?;
+        self.writer.write_all(payload).await?;
+        self.writer.write_all(b"\r\n").await
+    }
+
+    /// Waits for the server to have processed everything sent so far.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"PING\r\n").await?;
+        loop {
+            match self.read_line().await?.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => self.writer.write_all(b"PONG\r\n").await?,
+                line if line.starts_with("-ERR") => return Err(io::Error::other(format!("NATS server error: {}", line))),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Publishes the events following the publisher's cursor, moving it past
+/// them once the server has them. Returns the number of events published.
+pub async fn publish_pending(db: &DbConn, client: &mut NatsClient, config: &NatsConfig, sealer: &Sealer, redaction: &RedactionConfig) -> io::Result<usize> {
+    let locked = |e: DbError| io::Error::other(e.to_string());
+    let cursor = events::cursor(CONSUMER, &mut *db::lock(db).map_err(locked)?).map_err(io::Error::other)?;
+    let pending = events::since(cursor, BATCH_SIZE, sealer, &mut *db::lock(db).map_err(locked)?).map_err(io::Error::other)?;
+    let Some(last) = pending.last() else {
+        return Ok(0);
+    };
+
+    for event in &pending {
+        let payload = cloudevents::payload(event, redaction);
+        client.publish(&format!("{}.{}", config.subject_prefix, event.kind), &payload).await?;
+    }
+    client.flush().await?;
+
+    events::advance(CONSUMER, last.seq, &mut *db::lock(db).map_err(locked)?).map_err(io::Error::other)?;
+    Ok(pending.len())
+}
+
+/// Publishes the pending events over `client`, connecting it first if
+/// needed, and drops it if it fails so that the next call reconnects.
+async fn publish_all(db: &DbConn, client: &mut Option<NatsClient>, config: &NatsConfig, sealer: &Sealer, redaction: &RedactionConfig) {
+    let connected = match client {
+        Some(connected) => connected,
+        None => match NatsClient::connect(&config.url).await {
+            Ok(connected) => client.insert(connected),
+            Err(e) => return log::warn!("Could not connect to NATS at {}: {}", config.url, e),
+        },
+    };
+    // Catch up on the backlog a batch at a time.
+    loop {
+        match publish_pending(db, connected, config, sealer, redaction).await {
+            Ok(published) if published as i64 == BATCH_SIZE => continue,
+            Ok(_) => return,
+            Err(e) => {
+                log::warn!("Publishing events to NATS failed: {}", e);
+                *client = None;
+                return;
+            }
+        }
+    }
+}
+
+/// Background worker publishing the events, reconnecting after failures.
+pub fn fairing(config: NatsConfig) -> AdHoc {
+    AdHoc::on_liftoff("NATS publisher", move |rocket| Box::pin(async move {
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let redaction = rocket.state::<AppConfig>().expect("configuration is managed").redaction.clone();
+        let shutdown = rocket.shutdown();
+        // Unpublished events are left to the next start, which resumes
+        // from the cursor.
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval.max(1)));
+            let mut client = None;
+            while shutdown::tick(&mut interval, &shutdown).await {
+                publish_all(&db, &mut client, &config, &sealer, &redaction).await;
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloudevents::CloudEvent;
+    use crate::encryption::Cipher;
+    use crate::tests::setup_test_db;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("nats://localhost"), Ok(("localhost".to_string(), 4222, None)));
+        assert_eq!(parse_url("nats://s3cr3t@nats:4223/"), Ok(("nats".to_string(), 4223, Some("s3cr3t".to_string()))));
+        assert!(parse_url("amqp://rabbitmq").is_err());
+    }
+
+    /// Serves a NATS client until it disconnects, returning the subjects
+    /// and payloads published; if `crash`, the connection is closed on the
+    /// first publication instead.
+    fn session(stream: TcpStream, crash: bool) -> Vec<(String, String)> {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(b"INFO {\"server_id\":\"test\"}\r\n").unwrap();
+
+        let mut published = vec![];
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                return published;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["PING"] => writer.write_all(b"PONG\r\n").unwrap(),
+                ["PUB", ..] if crash => return published,
+                ["PUB", subject, length] => {
+                    let mut payload = vec![0; length.parse::<usize>().unwrap() + 2];
+                    reader.read_exact(&mut payload).unwrap();
+                    payload.truncate(payload.len() - 2);
+                    published.push((subject.to_string(), String::from_utf8(payload).unwrap()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A NATS server accepting one client, returning what it published.
+    fn serve_once(listener: TcpListener) -> thread::JoinHandle<Vec<(String, String)>> {
+        thread::spawn(move || session(listener.accept().unwrap().0, false))
+    }
+
+    /// Two changes of the same elu, recorded as events.
+    fn record_events() -> (DbConn, Sealer) {
+        let mut connection = setup_test_db();
+        let new_person = crate::db::NewPerson { name: "Jean Dupont".parse().unwrap(), email: "jean@mairie.example".parse().unwrap(), ..Default::default() };
+        let person = crate::db::insert_person(&new_person, &mut connection).unwrap();
+        let sealer = Sealer::new(Cipher::new(&[7; 32]));
+        events::record(events::ChangeKind::Created, &person, &sealer, &mut connection).unwrap();
+        events::record(events::ChangeKind::Deleted, &person, &sealer, &mut connection).unwrap();
+        (Arc::new(Mutex::new(connection)), sealer)
+    }
+
+    #[rocket::async_test]
+    async fn test_publish_pending() {
+        let (db, sealer) = record_events();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = NatsConfig { url: format!("nats://{}", listener.local_addr().unwrap()), subject_prefix: default_subject_prefix(), poll_interval: 1 };
+        let server = serve_once(listener);
+
+        let mut client = NatsClient::connect(&config.url).await.unwrap();
+        assert_eq!(publish_pending(&db, &mut client, &config, &sealer, &RedactionConfig::default()).await.unwrap(), 2);
+        assert_eq!(publish_pending(&db, &mut client, &config, &sealer, &RedactionConfig::default()).await.unwrap(), 0);
+        drop(client);
+
+        let published = server.join().unwrap();
+        let subjects: Vec<&str> = published.iter().map(|(subject, _)| subject.as_str()).collect();
+        assert_eq!(subjects, ["rckd.elus.created", "rckd.elus.deleted"]);
+        let event: CloudEvent = serde_json::from_str(&published[1].1).unwrap();
+        assert_eq!((event.event_type.as_str(), event.data["email"].as_str()), ("fr.rckd.elu.deleted", Some("jean@mairie.example")));
+    }
+
+    #[rocket::async_test]
+    async fn test_reconnect() {
+        let (db, sealer) = record_events();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = NatsConfig { url: format!("nats://{}", listener.local_addr().unwrap()), subject_prefix: default_subject_prefix(), poll_interval: 1 };
+        let server = thread::spawn(move || {
+            let lost = session(listener.accept().unwrap().0, true);
+            (lost, session(listener.accept().unwrap().0, false))
+        });
+
+        let mut client = None;
+        publish_all(&db, &mut client, &config, &sealer, &RedactionConfig::default()).await;
+        assert!(client.is_none());
+        publish_all(&db, &mut client, &config, &sealer, &RedactionConfig::default()).await;
+        assert!(client.is_some());
+        drop(client);
+
+        let (lost, published) = server.join().unwrap();
+        assert!(lost.is_empty());
+        let subjects: Vec<&str> = published.iter().map(|(subject, _)| subject.as_str()).collect();
+        assert_eq!(subjects, ["rckd.elus.created", "rckd.elus.deleted"]);
+        assert_eq!(events::cursor(CONSUMER, &mut db.lock().unwrap()).unwrap(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+//! Redirects for paths which don't match a route only because of the case
+//! of the route's fixed segments, as sent by reverse proxies and legacy
+//! clients with other conventions: `/Elus/Jean@Example.com/` is permanently
+//! redirected to `/elus/Jean@Example.com`, dynamic segments keeping their
+//! case. Rocket itself ignores empty segments, so that `/elus/` is already
+//! served as `/elus`.
+
+use rocket::http::{Method, Status};
+use rocket::request::Request;
+use rocket::response::Redirect;
+
+use crate::problem::Problem;
+
+/// The path matching `route` that `segments` would be, if they match it
+/// but for case.
+fn normalize(route: &str, segments: &[&str]) -> Option<String> {
+    let route_segments: Vec<&str> = route.split('/').skip(1).collect();
+    let mut normalized = String::new();
+    for (index, route_segment) in route_segments.iter().enumerate() {
+        if route_segment.starts_with('<') && route_segment.ends_with("..>") {
+            for segment in segments.get(index..).unwrap_or_default() {
+                normalized.push('/');
+                normalized.push_str(segment);
+            }
+            return Some(normalized);
+        }
+
+        let segment = segments.get(index)?;
+        normalized.push('/');
+        if route_segment.starts_with('<') && !segment.is_empty() {
+            normalized.push_str(segment);
+        } else if route_segment.eq_ignore_ascii_case(segment) {
+            normalized.push_str(route_segment);
+        } else {
+            return None;
+        }
+    }
+
+    (segments.len() == route_segments.len()).then_some(normalized)
+}
+
+/// The path of a route the request would match but for case, unless it
+/// already matches it.
+fn normalized_path(request: &Request<'_>) -> Option<String> {
+    let path = request.uri().path().as_str();
+    let trimmed = if path.len() > 1 { path.trim_end_matches('/') } else { path };
+    let segments: Vec<&str> = trimmed.split('/').skip(1).collect();
+    let method = match request.method() {
+        Method::Head => Method::Get,
+        method => method,
+    };
+
+    request
+        .rocket()
+        .routes()
+        .filter(|route| route.method == method)
+        .find_map(|route| normalize(route.uri.origin.path().as_str(), &segments))
+        .filter(|normalized| normalized != trimmed)
+}
+
+/// Redirects unmatched requests to the path they were meant for, if there
+/// is one.
+#[catch(404)]
+pub fn not_found(request: &Request<'_>) -> Result<Redirect, Problem> {
+    match normalized_path(request) {
+        Some(path) => Ok(Redirect::permanent(match request.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        })),
+        None => Err(Problem::of(Status::NotFound, request)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/elus/<key>", &["Elus", "Jean@Example.com"]), Some("/elus/Jean@Example.com".to_string()));
+        assert_eq!(normalize("/elus/<key>", &["elus", ""]), None);
+        assert_eq!(normalize("/elus", &["elus", "export.geojson"]), None);
+        assert_eq!(normalize("/static/<path..>", &["Static", "css", "Site.css"]), Some("/static/css/Site.css".to_string()));
+        assert_eq!(normalize("/", &[""]), Some("/".to_string()));
+    }
+
+    #[test]
+    fn test_redirects() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let location = |uri: &str| {
+            let response = client.get(uri.to_string()).dispatch();
+            assert_eq!(response.status(), Status::PermanentRedirect);
+            response.headers().get_one("Location").unwrap().to_string()
+        };
+
+        assert_eq!(client.get("/elus/").dispatch().status(), Status::Ok);
+        assert_eq!(location("/ELUS?name=mar"), "/elus?name=mar");
+        assert_eq!(location("/Elus/jean@example.com/"), "/elus/jean@example.com");
+
+        let response = client.get("/nothing/").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+        assert_eq!(client.get("/elus/nobody@example.com").dispatch().status(), Status::NotFound);
+    }
+}
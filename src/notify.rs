@@ -0,0 +1,306 @@
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::encryption::Sealer;
+use crate::mail::Message;
+use crate::mail_queue::{self, FAILED, QUEUED, SENT};
+use crate::schema::notifications;
+use crate::transaction::Transaction;
+use crate::repository::{PersonFilter, PersonRepository};
+use crate::{db, timestamp, DbConn, Person};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NotificationRequest {
+    /// Subject and body templates, with `{{name}}`, `{{email}}`,
+    /// `{{mandates}}` and `{{commune}}` placeholders.
+    pub subject: String,
+    pub template: String,
+    #[serde(default)]
+    pub filter: RecipientFilter,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RecipientFilter {
+    pub mandate: Option<String>,
+    /// INSEE commune code.
+    pub commune: Option<String>,
+}
+
+impl From<&RecipientFilter> for PersonFilter {
+    fn from(filter: &RecipientFilter) -> Self {
+        PersonFilter {
+            mandate: filter.mandate.clone(),
+            commune_code: filter.commune.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NotificationReport {
+    pub id: i32,
+    pub subject: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    pub queued: usize,
+    pub sent: usize,
+    pub failed: usize,
+    /// Matching elus left out for not having consented to mailings.
+    pub skipped: usize,
+    pub recipients: Vec<RecipientReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RecipientReport {
+    pub email: String,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = notifications)]
+struct Notification {
+    id: i32,
+    subject: String,
+    created_at: PrimitiveDateTime,
+    skipped: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notifications)]
+struct NewNotification<'a> {
+    subject: &'a str,
+    template: &'a str,
+    skipped: i32,
+}
+
+/// Whether the person may be sent mailings: they agreed to, and when they
+/// did is on record.
+fn consents(person: &Person) -> bool {
+    person.may_contact_by_email && person.consent_date.is_some()
+}
+
+/// Substitutes the `{{placeholder}}`s of a template with the person's data.
+pub fn render(template: &str, person: &Person) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").ok_or("unterminated placeholder")? + start;
+        match rest[start + 2..end].trim() {
+            "name" => rendered.push_str(&person.name),
+            "email" => rendered.push_str(&person.email),
+            "mandates" => rendered.push_str(&person.mandates.join(", ")),
+            "commune" => rendered.push_str(person.commune_code.as_deref().unwrap_or_default()),
+            other => return Err(format!("unknown placeholder {{{{{}}}}}", other)),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+fn report(notification_id: i32, sealer: &Sealer, connection: &mut SqliteConnection) -> Result<NotificationReport, Status> {
+    let notification = notifications::table
+        .find(notification_id)
+        .select(Notification::as_select())
+        .first(connection)
+        .map_err(|_| Status::NotFound)?;
+    let recipients = mail_queue::by_notification(notification_id, sealer, connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let count = |status: &str| recipients.iter().filter(|recipient| recipient.status == status).count();
+    Ok(NotificationReport {
+        id: notification.id,
+        subject: notification.subject,
+        created_at: notification.created_at,
+        queued: count(QUEUED),
+        sent: count(SENT),
+        failed: count(FAILED),
+        skipped: notification.skipped as usize,
+        recipients: recipients
+            .into_iter()
+            .map(|recipient| RecipientReport {
+                email: recipient.recipient,
+                status: recipient.status,
+                error: recipient.last_error,
+            })
+            .collect(),
+    })
+}
+
+/// Renders the messages for the matching elus who consented to mailings
+/// and hands them to the mail queue, which delivers them in the background.
+#[post("/elus/notify", data = "<request>")]
+fn notify(request: Json<NotificationRequest>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>, sealer: &State<Sealer>) -> Result<(Status, Json<NotificationReport>), Status> {
+    let recipients: Vec<Person> = repository.search(&PersonFilter::from(&request.filter))?
+        .into_iter()
+        .map(Person::from)
+        .collect();
+
+    // Rendered for those who didn't consent too, so that templates are
+    // checked whoever matches.
+    let rendered = recipients
+        .iter()
+        .map(|person| Ok((consents(person), Message {
+            to: person.email.to_string(),
+            subject: render(&request.subject, person)?,
+            body: render(&request.template, person)?,
+        })))
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|_| Status::UnprocessableEntity)?;
+    let (messages, skipped): (Vec<_>, Vec<_>) = rendered.into_iter().partition(|(consents, _)| *consents);
+
+    let mut connection = db::lock(db)?;
+    let notification_id = connection
+        .transaction(|connection| {
+            let notification_id = diesel::insert_into(notifications::table)
+                .values(NewNotification { subject: &request.subject, template: &request.template, skipped: skipped.len() as i32 })
+                .returning(notifications::id)
+                .get_result(connection)?;
+            for (_, message) in &messages {
+                mail_queue::enqueue(message, Some(notification_id), sealer, connection)?;
+            }
+            QueryResult::Ok(notification_id)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Accepted, Json(report(notification_id, sealer, &mut connection)?)))
+}
+
+#[get("/elus/notify/<id>", rank = 2)]
+fn notification_report(id: i32, _admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<NotificationReport>, Status> {
+    let mut connection = db::lock(db)?;
+
+    Ok(Json(report(id, sealer, &mut connection)?))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![notify, notification_report]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::tests::RecordingMailer;
+    use crate::mail_queue::MailQueueConfig;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_render() {
+        let person = Person {
+            name: "Jean Dupont".parse().unwrap(),
+            mandates: vec!["Maire".to_string(), "Conseiller régional".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(render("Bonjour {{ name }} ({{mandates}})", &person), Ok("Bonjour Jean Dupont (Maire, Conseiller régional)".to_string()));
+        assert!(render("Bonjour {{prenom}}", &person).is_err());
+        assert!(render("Bonjour {{name", &person).is_err());
+    }
+
+    #[test]
+    fn test_notify_filtered_recipients() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        {
+            use crate::schema::elus::dsl::*;
+            diesel::update(elus.filter(email.eq("jean.dupont@example.com")))
+                .set((may_contact_by_email.eq(true), consent_date.eq(Some(timestamp::now()))))
+                .execute(&mut connection)
+                .unwrap();
+        }
+        let person = crate::db::NewPerson {
+            name: "Paul Rebond".parse().unwrap(),
+            email: "paul@bounce.example".parse().unwrap(),
+            mandates: vec!["Maire".to_string()],
+            commune_code: Some("75056".to_string()),
+            may_contact_by_email: true,
+            consent_date: Some(timestamp::now()),
+            consent_source: Some("formulaire".to_string()),
+            ..Default::default()
+        };
+        crate::db::insert_person(&person, &mut connection).unwrap();
+        // Consent without a date isn't proof of consent.
+        let person = crate::db::NewPerson {
+            name: "Luc Sansdate".parse().unwrap(),
+            email: "luc@example.com".parse().unwrap(),
+            mandates: vec!["Maire".to_string()],
+            commune_code: Some("75056".to_string()),
+            may_contact_by_email: true,
+            ..Default::default()
+        };
+        crate::db::insert_person(&person, &mut connection).unwrap();
+        let client = client(connection);
+
+        let request = NotificationRequest {
+            subject: "Conseil du {{commune}}".to_string(),
+            template: "Bonjour {{name}}".to_string(),
+            filter: RecipientFilter {
+                mandate: Some("Maire".to_string()),
+                commune: Some("75056".to_string()),
+            },
+        };
+        let response = client.post("/elus/notify").json(&request).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/elus/notify").header(admin()).json(&request).dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let queued: NotificationReport = response.into_json().expect("valid JSON");
+        assert_eq!((queued.queued, queued.skipped), (2, 1));
+        assert!(queued.recipients.iter().all(|recipient| recipient.email != "luc@example.com"));
+
+        let mailer = RecordingMailer::default();
+        let config = MailQueueConfig { max_attempts: 1, ..Default::default() };
+        let db = client.rocket().state::<DbConn>().unwrap();
+        mail_queue::process_due(db, &mailer, &config, client.rocket().state::<Sealer>().unwrap(), timestamp::now()).unwrap();
+
+        let report: NotificationReport = client
+            .get(format!("/elus/notify/{}", queued.id))
+            .header(admin())
+            .dispatch()
+            .into_json()
+            .expect("valid JSON");
+        assert_eq!((report.queued, report.sent, report.failed, report.skipped), (0, 1, 1, 1));
+        assert_eq!(report.recipients[1].error.as_deref(), Some("550 mailbox unavailable"));
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent[0], Message {
+            to: "jean.dupont@example.com".to_string(),
+            subject: "Conseil du 75056".to_string(),
+            body: "Bonjour Jean Dupont".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_notify_rejects_unknown_placeholders() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let request = NotificationRequest {
+            subject: "Conseil".to_string(),
+            template: "Bonjour {{prenom}}".to_string(),
+            filter: RecipientFilter::default(),
+        };
+        let response = client.post("/elus/notify").header(admin()).json(&request).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client.get("/elus/notify/1").header(admin()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,81 @@
+//! Open-data mode, for serving the public mirror of the directory from the
+//! same binary: only `GET` routes are mounted, public responses are cached
+//! for long by clients and CDNs, API key quotas are only counted and the
+//! background workers, which write or send, aren't run.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::route::Route;
+use rocket::serde::Deserialize;
+use rocket::{Request, Response};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct OpenDataConfig {
+    /// Seconds public responses may be served from caches.
+    pub max_age: u64,
+    /// Seconds caches may keep serving a response while refreshing it.
+    pub stale_while_revalidate: u64,
+}
+
+impl Default for OpenDataConfig {
+    fn default() -> Self {
+        OpenDataConfig { max_age: 3600, stale_while_revalidate: 86400 }
+    }
+}
+
+/// The routes answering `GET`.
+pub fn read_only(routes: Vec<Route>) -> Vec<Route> {
+    routes.into_iter().filter(|route| route.method == Method::Get).collect()
+}
+
+/// Fairing allowing successful public responses to be cached, unless they
+/// say otherwise.
+pub struct Caching(pub OpenDataConfig);
+
+#[rocket::async_trait]
+impl Fairing for Caching {
+    fn info(&self) -> Info {
+        Info { name: "Open-data caching", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let public = !request.headers().contains("Authorization") && !request.headers().contains("Cookie");
+        if request.method() != Method::Get || response.status() != Status::Ok || !public || response.headers().contains("Cache-Control") {
+            return;
+        }
+
+        let cache_control = format!("public, max-age={}, stale-while-revalidate={}", self.0.max_age, self.0.stale_while_revalidate);
+        response.set_header(Header::new("Cache-Control", cache_control));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api_keys::HEADER;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+    use rocket::http::{Header, Status};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_open_data_mode() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("open_data.max_age", 600)), connection);
+
+        let response = client.get("/elus").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("public, max-age=600, stale-while-revalidate=86400"));
+        let response = client.get("/elus").header(admin()).dispatch();
+        assert_eq!(response.headers().get_one("Cache-Control"), None);
+        let response = client.get("/elus/nobody@example.com").dispatch();
+        assert_eq!((response.status(), response.headers().get_one("Cache-Control")), (Status::NotFound, None));
+
+        let person = json!({ "name": "Lucie Bernard", "email": "lucie.bernard@example.com", "mandates": [] });
+        assert_eq!(client.post("/elus/create").header(admin()).json(&person).dispatch().status(), Status::NotFound);
+        assert_eq!(client.delete("/elus/jean.dupont@example.com").header(admin()).dispatch().status(), Status::NotFound);
+
+        // Unknown keys are served as anonymous callers would be.
+        assert_eq!(client.get("/elus").header(Header::new(HEADER, "guessed")).dispatch().status(), Status::Ok);
+    }
+}
@@ -0,0 +1,739 @@
+//! The OpenAPI description of the elus API, served at `/openapi.json`.
+//! Request bodies of the documented operations are validated against its
+//! schemas (see `validation`), so the document and the checks can't drift
+//! apart. Operation ids are stable: they name the methods of the
+//! generated client (see `client_gen`).
+//!
+//! The examples of requests and responses are those of the handlers, run
+//! on the fixtures of the tests, which fail when `openapi_examples.json`
+//! no longer matches what the handlers answer; they rewrite it when
+//! `UPDATE_OPENAPI_EXAMPLES` is set.
+
+use std::sync::OnceLock;
+
+use rocket::serde::json::{json, Json, Value};
+
+/// Examples of the operations, by operation id: a `request` body, and
+/// `responses` by status.
+const EXAMPLES: &str = include_str!("openapi_examples.json");
+
+/// The OpenAPI 3.1 document, built once.
+pub fn spec() -> &'static Value {
+    static SPEC: OnceLock<Value> = OnceLock::new();
+
+    SPEC.get_or_init(|| {
+        let mut spec = document();
+        // Every operation may fail with a problem.
+        for path in spec["paths"].as_object_mut().into_iter().flat_map(|paths| paths.values_mut()) {
+            for (method, operation) in path.as_object_mut().into_iter().flatten() {
+                if method != "parameters" {
+                    operation["responses"]["default"] = json!({ "$ref": "#/components/responses/Problem" });
+                }
+            }
+        }
+        add_examples(&mut spec, &serde_json::from_str(EXAMPLES).expect("examples are valid JSON"));
+        spec
+    })
+}
+
+/// Sets the JSON examples of the operations, next to their schemas.
+fn add_examples(spec: &mut Value, examples: &Value) {
+    for path in spec["paths"].as_object_mut().into_iter().flat_map(|paths| paths.values_mut()) {
+        for operation in path.as_object_mut().into_iter().flat_map(|path| path.values_mut()) {
+            let Some(example) = operation["operationId"].as_str().and_then(|id| examples.get(id)) else {
+                continue;
+            };
+            if let Some(request) = example.get("request") {
+                operation["requestBody"]["content"]["application/json"]["example"] = request.clone();
+            }
+            for (status, response) in example["responses"].as_object().into_iter().flatten() {
+                operation["responses"][status]["content"]["application/json"]["example"] = response.clone();
+            }
+        }
+    }
+}
+
+/// The document, without the responses all operations share.
+fn document() -> Value {
+    let person = json!({ "$ref": "#/components/schemas/Person" });
+    let key = json!({
+        "name": "key",
+        "in": "path",
+        "required": true,
+        "description": "UUID of the elu (canonical), or their email or id.",
+        "schema": { "type": "string" },
+    });
+    let write = json!({
+        "requestBody": { "required": true, "content": { "application/json": { "schema": person } } },
+        "responses": {
+            "200": { "description": "The elu as saved.", "content": { "application/json": { "schema": person } } },
+            "409": { "description": "The name or email is already taken." },
+            "422": { "$ref": "#/components/responses/Invalid" },
+        },
+    });
+    let mut replace = operation("replaceElu", "Replaces an elu's data, or creates the elu if the key is an unknown email.", &write);
+    replace["parameters"] = json!([{
+        "name": "If-None-Match",
+        "in": "header",
+        "description": "`*` to only create the elu, never replace one.",
+        "schema": { "type": "string" },
+    }]);
+    replace["responses"]["201"] = json!({ "description": "The elu as created.", "content": { "application/json": { "schema": person } } });
+    replace["responses"]["404"] = json!({ "description": "No elu has this id or UUID." });
+    replace["responses"]["412"] = json!({ "description": "`If-None-Match: *` was sent and the elu exists." });
+
+    let relations = json!(["substitute_of", "has_substitute", "deputy_of", "has_deputy"]);
+    let relation_parameters = json!([
+        key,
+        { "name": "relation", "in": "path", "required": true, "schema": { "type": "string", "enum": relations } },
+        { "name": "other", "in": "path", "required": true, "description": "UUID, email or id of the other elu.", "schema": { "type": "string" } },
+    ]);
+
+    let query = |name: &str, kind: &str, description: &str| json!({ "name": name, "in": "query", "description": description, "schema": { "type": kind } });
+    let criteria = [
+        query("name", "string", "Part of the name."),
+        query("mandate", "string", "A mandate, by title or code."),
+        query("commune", "string", "INSEE code of the commune."),
+        query("tag", "string", "A tag."),
+        query("email_status", "string", "Deliverability of the address."),
+        query(
+            "filter",
+            "string",
+            "An expression such as `mandate eq \"Maire\" and (commune eq \"75056\" or not name contains \"dupont\")`, on the fields name, mandate, commune, email_status and visibility.",
+        ),
+    ];
+    let paging = [
+        query("page", "integer", "Page number, from 1, with per_page."),
+        query("per_page", "integer", "Elus per page."),
+        query("limit", "integer", "Elus per page, with after."),
+        query("after", "string", "The next_cursor of the previous page."),
+        query("sort", "string", "name or updated_at, prefixed by - for descending order; cursors only follow the order by name."),
+    ];
+    let listing_parameters: Vec<Value> = criteria.iter().chain(&paging).cloned().collect();
+    let patch_parameters: Vec<Value> = criteria.iter().cloned().chain([query("dry_run", "boolean", "Only report what would be modified.")]).collect();
+    let search_parameters: Vec<Value> = [
+        json!({ "name": "q", "in": "query", "required": true, "schema": { "type": "string" } }),
+        query("limit", "integer", "Results per page, paged as listElus, which also narrows down and sorts them with its criteria; 10 when not given."),
+        query("fuzzy", "boolean", "Also match names which sound like the text."),
+    ]
+    .into_iter()
+    .chain(criteria.iter().cloned())
+    .chain(paging.iter().filter(|parameter| !["limit", "after"].contains(&parameter["name"].as_str().unwrap())).cloned())
+    .collect();
+
+    let mut document = json!({
+        "openapi": "3.1.0",
+        "info": { "title": "Annuaire des élus", "version": env!("CARGO_PKG_VERSION") },
+        "paths": {
+            "/elus": {
+                "get": {
+                    "operationId": "listElus",
+                    "summary": "Lists the elus.",
+                    "parameters": listing_parameters,
+                    "responses": {
+                        "200": { "description": "The elus, or a page of them.", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Listing" } } } },
+                    },
+                },
+                "patch": {
+                    "operationId": "patchElus",
+                    "summary": "Patches the elus matching the criteria of listElus, at least one of them, in one transaction.",
+                    "parameters": patch_parameters,
+                    "requestBody": { "required": true, "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Patch" } } } },
+                    "responses": {
+                        "200": { "description": "What was modified.", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/PatchSummary" } } } },
+                        "400": { "description": "No criteria were given." },
+                        "422": { "$ref": "#/components/responses/Invalid" },
+                    },
+                },
+            },
+            "/elus/create": { "post": operation("createElu", "Creates an elu.", &write) },
+            "/elus/new": { "post": operation("newElu", "Creates an elu (alias of /elus/create).", &write) },
+            "/elus/lookup": {
+                "post": {
+                    "operationId": "lookupElus",
+                    "summary": "Fetches the elus having the given emails.",
+                    "requestBody": { "required": true, "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Lookup" } } } },
+                    "responses": {
+                        "200": { "description": "The elus found.", "content": { "application/json": { "schema": { "type": "array", "items": person } } } },
+                        "422": { "$ref": "#/components/responses/Invalid" },
+                    },
+                },
+            },
+            "/elus/search": {
+                "get": {
+                    "operationId": "searchElus",
+                    "summary": "Searches the elus by email, name or mandate, best matches first.",
+                    "parameters": search_parameters,
+                    "responses": {
+                        "200": {
+                            "description": "The elus found, each with the score of its match.",
+                            "content": { "application/json": { "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Hit" } } } },
+                        },
+                    },
+                },
+            },
+            "/elus/{key}": {
+                "parameters": [key],
+                "get": {
+                    "operationId": "getElu",
+                    "summary": "Fetches an elu.",
+                    "responses": {
+                        "200": {
+                            "description": "The elu, or their profile page to callers preferring HTML.",
+                            "content": { "application/json": { "schema": person }, "text/html": { "schema": { "type": "string" } } },
+                        },
+                        "308": { "description": "The key is a former email of the elu." },
+                        "404": { "description": "No such elu." },
+                    },
+                },
+                "put": replace,
+                "delete": {
+                    "operationId": "deleteElu",
+                    "summary": "Deletes an elu, their documents and relations.",
+                    "responses": { "204": { "description": "Deleted." }, "404": { "description": "No such elu." } },
+                },
+            },
+            "/elus/{key}/related": {
+                "parameters": [key],
+                "get": {
+                    "operationId": "listRelated",
+                    "summary": "Lists the elus related to an elu.",
+                    "responses": { "200": { "description": "The relations, each with the other elu." }, "404": { "description": "No such elu." } },
+                },
+            },
+            "/elus/{key}/related/{relation}/{other}": {
+                "parameters": relation_parameters,
+                "put": {
+                    "operationId": "addRelated",
+                    "summary": "Records that the elu is the relation of the other elu.",
+                    "responses": { "204": { "description": "Recorded." }, "404": { "description": "No such elu or relation." } },
+                },
+                "delete": {
+                    "operationId": "removeRelated",
+                    "summary": "Removes a relation.",
+                    "responses": { "204": { "description": "Removed." }, "404": { "description": "No such relation." } },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Person": {
+                    "type": "object",
+                    "description": "An elu. Responses leave out the fields of redaction.public to callers without the admin token.",
+                    "x-redactable": true,
+                    "required": ["name", "email", "mandates"],
+                    "properties": {
+                        "uuid": { "type": "string", "format": "uuid", "readOnly": true },
+                        "name": { "type": "string", "format": "person-name", "minLength": 1, "maxLength": 200 },
+                        "email": { "type": "string", "format": "email", "maxLength": 254, "description": "Primary address." },
+                        "emails": {
+                            "type": "array",
+                            "maxItems": 10,
+                            "items": { "type": "string", "format": "email", "maxLength": 254 },
+                            "description": "Every address, the primary one first; on input, the others may be listed alone.",
+                        },
+                        "mandates": {
+                            "type": "array",
+                            "maxItems": 20,
+                            "items": { "type": "string", "minLength": 1, "maxLength": 200 },
+                            "description": "Titles, or codes of known mandate types.",
+                        },
+                        "commune_code": { "type": ["string", "null"], "minLength": 5, "maxLength": 5 },
+                        "office_address": { "type": ["string", "null"], "maxLength": 500 },
+                        "latitude": { "type": ["number", "null"], "minimum": -90, "maximum": 90 },
+                        "longitude": { "type": ["number", "null"], "minimum": -180, "maximum": 180 },
+                        "may_contact_by_email": {
+                            "type": "boolean",
+                            "description": "Whether the elu agreed to receive mailings; notifications only go to those who did, on a recorded consent_date. Only taken from admins.",
+                        },
+                        "consent_date": { "type": ["string", "null"], "format": "date-time" },
+                        "consent_source": { "type": ["string", "null"], "maxLength": 200, "description": "How consent was collected." },
+                        "visibility": {
+                            "type": "string",
+                            "enum": ["public", "internal", "hidden"],
+                            "description": "Who may see the elu: everyone, signed-in users, or admins only; new elus get the default_visibility of their commune when not given. Only taken from admins.",
+                        },
+                        "custom": {
+                            "type": "object",
+                            "description": "Values of the custom fields, by name, as GET /custom-fields defines them.",
+                        },
+                        "email_status": {
+                            "type": "string",
+                            "enum": ["unchecked", "deliverable", "invalid_syntax", "no_mail_server"],
+                            "readOnly": true,
+                        },
+                        "related": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Link" },
+                            "description": "Relations with other elus, when fetching a single elu.",
+                            "readOnly": true,
+                        },
+                    },
+                },
+                "Link": {
+                    "type": "object",
+                    "required": ["relation", "uuid", "name"],
+                    "properties": {
+                        "relation": { "type": "string", "enum": relations },
+                        "uuid": { "type": "string", "format": "uuid" },
+                        "name": { "type": "string" },
+                    },
+                },
+                "Lookup": {
+                    "type": "object",
+                    "required": ["emails"],
+                    "properties": { "emails": { "type": "array", "items": { "type": "string", "format": "email" } } },
+                },
+                "Patch": {
+                    "type": "object",
+                    "description": "Changes made to each elu; unset fields are kept.",
+                    "properties": {
+                        "add_mandates": { "type": "array", "maxItems": 20, "items": { "type": "string", "minLength": 1, "maxLength": 200 } },
+                        "remove_mandates": { "type": "array", "maxItems": 20, "items": { "type": "string", "minLength": 1, "maxLength": 200 } },
+                        "commune_code": { "type": "string", "minLength": 5, "maxLength": 5 },
+                    },
+                },
+                "PatchSummary": {
+                    "type": "object",
+                    "required": ["matched", "modified", "dry_run"],
+                    "properties": {
+                        "matched": { "type": "integer" },
+                        "modified": { "type": "array", "items": { "type": "string", "format": "uuid" }, "description": "UUIDs of the elus modified." },
+                        "dry_run": { "type": "boolean" },
+                    },
+                },
+                "Problem": {
+                    "type": "object",
+                    "description": "An RFC 7807 problem; `errors` lists the violations of refused bodies.",
+                    "required": ["type", "title", "status"],
+                    "properties": {
+                        "type": { "type": "string" },
+                        "title": { "type": "string" },
+                        "status": { "type": "integer" },
+                        "detail": { "type": "string" },
+                        "instance": { "type": "string", "description": "Path of the request which failed." },
+                        "errors": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "pointer": { "type": "string", "description": "JSON pointer to the offending value." },
+                                    "detail": { "type": "string" },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "Problem": {
+                    "description": "The request failed.",
+                    "content": { "application/problem+json": { "schema": { "$ref": "#/components/schemas/Problem" } } },
+                },
+                "Invalid": {
+                    "description": "The body doesn't match the schema.",
+                    "content": { "application/problem+json": { "schema": { "$ref": "#/components/schemas/Problem" } } },
+                },
+            },
+        },
+    });
+
+    let (paths, schemas) = resources(&key, &listing_parameters);
+    for (path, item) in paths.as_object().into_iter().flatten() {
+        document["paths"][path] = item.clone();
+    }
+    for (name, schema) in schemas.as_object().into_iter().flatten() {
+        document["components"]["schemas"][name] = schema.clone();
+    }
+    document
+}
+
+/// The operations on the communes, tags, bodies and documents, with their
+/// schemas.
+fn resources(key: &Value, listing_parameters: &[Value]) -> (Value, Value) {
+    let code = json!({ "name": "code", "in": "path", "required": true, "description": "INSEE code of the commune.", "schema": { "type": "string" } });
+    let tag = json!({ "name": "name", "in": "path", "required": true, "description": "Name of the tag.", "schema": { "type": "string" } });
+    let id = |description: &str| json!({ "name": "id", "in": "path", "required": true, "description": description, "schema": { "type": "integer" } });
+    let schema = |name: &str| json!({ "$ref": format!("#/components/schemas/{}", name) });
+    let body = |name: &str| json!({ "required": true, "content": { "application/json": { "schema": schema(name) } } });
+    let returns = |description: &str, schema: Value| json!({ "description": description, "content": { "application/json": { "schema": schema } } });
+    let list = |name: &str| json!({ "type": "array", "items": schema(name) });
+    let no_content = |description: &str| json!({ "description": description });
+    let mut commune_elus_parameters = vec![code.clone()];
+    commune_elus_parameters.extend_from_slice(listing_parameters);
+
+    let paths = json!({
+        "/communes/{code}": {
+            "parameters": [code],
+            "get": {
+                "operationId": "getCommune",
+                "summary": "Fetches a commune.",
+                "responses": { "200": returns("The commune.", schema("Commune")), "404": no_content("No such commune.") },
+            },
+        },
+        "/communes/{code}/elus": {
+            "get": {
+                "operationId": "listCommuneElus",
+                "summary": "Lists the elus of a commune, as listElus does.",
+                "parameters": commune_elus_parameters,
+                "responses": { "200": returns("The elus, or a page of them.", schema("Listing")) },
+            },
+        },
+        "/communes/import": {
+            "post": {
+                "operationId": "importCommunes",
+                "summary": "Adds or updates communes from the CSV of the official geographic code (COG).",
+                "requestBody": { "required": true, "content": { "text/csv": { "schema": { "type": "string" } } } },
+                "responses": { "200": returns("How many communes were imported.", json!({ "type": "integer" })) },
+            },
+        },
+        "/tags": {
+            "get": {
+                "operationId": "listTags",
+                "summary": "Lists the tags, by name.",
+                "responses": { "200": returns("The tags.", list("Tag")) },
+            },
+            "post": {
+                "operationId": "createTag",
+                "summary": "Creates a tag.",
+                "requestBody": body("NewTag"),
+                "responses": { "201": returns("The tag as created.", schema("Tag")), "409": no_content("The tag exists."), "422": no_content("The name is invalid.") },
+            },
+        },
+        "/tags/complete": {
+            "get": {
+                "operationId": "completeTags",
+                "summary": "Suggests the tags starting with the text, most used first.",
+                "parameters": [
+                    { "name": "q", "in": "query", "required": true, "schema": { "type": "string" } },
+                    { "name": "limit", "in": "query", "description": "At most 100; 10 when not given.", "schema": { "type": "integer" } },
+                ],
+                "responses": { "200": returns("The tags suggested.", list("Tag")) },
+            },
+        },
+        "/tags/{name}": {
+            "parameters": [tag],
+            "get": {
+                "operationId": "getTag",
+                "summary": "Fetches a tag.",
+                "responses": { "200": returns("The tag.", schema("Tag")), "404": no_content("No such tag.") },
+            },
+            "put": {
+                "operationId": "updateTag",
+                "summary": "Renames a tag or changes its description.",
+                "requestBody": body("NewTag"),
+                "responses": { "200": returns("The tag as saved.", schema("Tag")), "404": no_content("No such tag."), "409": no_content("The new name is taken.") },
+            },
+            "delete": {
+                "operationId": "deleteTag",
+                "summary": "Deletes a tag, untagging the elus having it.",
+                "responses": { "204": no_content("Deleted."), "404": no_content("No such tag.") },
+            },
+        },
+        "/elus/{key}/tags": {
+            "parameters": [key],
+            "get": {
+                "operationId": "listEluTags",
+                "summary": "Lists the tags of an elu.",
+                "responses": { "200": returns("Names of the tags.", json!({ "type": "array", "items": { "type": "string" } })), "404": no_content("No such elu.") },
+            },
+        },
+        "/elus/{key}/tags/{name}": {
+            "parameters": [key, tag],
+            "put": {
+                "operationId": "tagElu",
+                "summary": "Tags an elu.",
+                "responses": { "204": no_content("Tagged."), "404": no_content("No such elu or tag.") },
+            },
+            "delete": {
+                "operationId": "untagElu",
+                "summary": "Untags an elu.",
+                "responses": { "204": no_content("Untagged."), "404": no_content("No such elu or tag.") },
+            },
+        },
+        "/bodies": {
+            "get": {
+                "operationId": "listBodies",
+                "summary": "Lists the bodies, by name.",
+                "parameters": [{ "name": "commune", "in": "query", "description": "Only the bodies of this commune.", "schema": { "type": "string" } }],
+                "responses": { "200": returns("The bodies.", list("Body")) },
+            },
+            "post": {
+                "operationId": "createBody",
+                "summary": "Creates a body.",
+                "requestBody": body("NewBody"),
+                "responses": { "201": returns("The body as created.", schema("Body")), "422": no_content("The kind or commune is invalid.") },
+            },
+        },
+        "/bodies/{id}": {
+            "parameters": [id("Id of the body.")],
+            "get": {
+                "operationId": "getBody",
+                "summary": "Fetches a body with its members.",
+                "responses": { "200": returns("The body.", schema("BodyMembers")), "404": no_content("No such body.") },
+            },
+            "put": {
+                "operationId": "updateBody",
+                "summary": "Replaces a body's data.",
+                "requestBody": body("NewBody"),
+                "responses": { "200": returns("The body as saved.", schema("Body")), "404": no_content("No such body.") },
+            },
+            "delete": {
+                "operationId": "deleteBody",
+                "summary": "Deletes a body.",
+                "responses": { "204": no_content("Deleted."), "404": no_content("No such body.") },
+            },
+        },
+        "/bodies/{id}/members/{key}": {
+            "parameters": [id("Id of the body."), key],
+            "put": {
+                "operationId": "addMember",
+                "summary": "Seats an elu on a body, or changes their role.",
+                "requestBody": { "content": { "application/json": { "schema": schema("NewMember") } } },
+                "responses": { "204": no_content("Seated."), "404": no_content("No such body or elu.") },
+            },
+            "delete": {
+                "operationId": "removeMember",
+                "summary": "Removes an elu from a body.",
+                "responses": { "204": no_content("Removed."), "404": no_content("The elu doesn't sit on the body.") },
+            },
+        },
+        "/elus/{key}/bodies": {
+            "parameters": [key],
+            "get": {
+                "operationId": "listEluBodies",
+                "summary": "Lists the bodies an elu sits on.",
+                "responses": { "200": returns("The bodies, with the elu's role.", list("Membership")), "404": no_content("No such elu.") },
+            },
+        },
+        "/elus/{key}/documents": {
+            "parameters": [key],
+            "get": {
+                "operationId": "listDocuments",
+                "summary": "Lists the documents of an elu; private ones only to admins.",
+                "responses": { "200": returns("The documents.", list("Document")), "404": no_content("No such elu.") },
+            },
+            "post": {
+                "operationId": "uploadDocument",
+                "summary": "Attaches a document to an elu, stored with the content type of the request.",
+                "parameters": [
+                    { "name": "title", "in": "query", "required": true, "schema": { "type": "string" } },
+                    { "name": "filename", "in": "query", "description": "Name of the uploaded file.", "schema": { "type": "string" } },
+                    { "name": "visibility", "in": "query", "description": "public or private, the default.", "schema": { "type": "string" } },
+                ],
+                "requestBody": { "required": true, "content": { "*/*": { "schema": { "type": "string", "format": "binary" } } } },
+                "responses": { "201": returns("The document as stored.", schema("Document")), "413": no_content("The file is too large.") },
+            },
+        },
+        "/elus/{key}/documents/{id}": {
+            "parameters": [key, id("Id of the document.")],
+            "get": {
+                "operationId": "downloadDocument",
+                "summary": "Downloads a document, or the byte range of the Range header.",
+                "responses": {
+                    "200": { "description": "The document.", "content": { "*/*": { "schema": { "type": "string", "format": "binary" } } } },
+                    "206": no_content("The range of the document."),
+                    "404": no_content("No such document."),
+                },
+            },
+            "patch": {
+                "operationId": "updateDocument",
+                "summary": "Changes the title or visibility of a document.",
+                "requestBody": body("DocumentUpdate"),
+                "responses": { "200": returns("The document as saved.", schema("Document")), "404": no_content("No such document.") },
+            },
+            "delete": {
+                "operationId": "deleteDocument",
+                "summary": "Deletes a document.",
+                "responses": { "204": no_content("Deleted."), "404": no_content("No such document.") },
+            },
+        },
+    });
+
+    let body_properties = json!({
+        "id": { "type": "integer" },
+        "name": { "type": "string" },
+        "kind": { "type": "string", "enum": ["council", "commission", "group"] },
+        "commune_code": { "type": ["string", "null"], "description": "INSEE code of the commune the body belongs to, if any." },
+    });
+    let schemas = json!({
+        "Commune": {
+            "type": "object",
+            "required": ["code", "name", "department"],
+            "properties": { "code": { "type": "string" }, "name": { "type": "string" }, "department": { "type": "string" } },
+        },
+        "Listing": {
+            "description": "All the elus, or a page of them when paging parameters are given.",
+            "oneOf": [{ "title": "All", "type": "array", "items": schema("Person") }, { "title": "Page", "$ref": "#/components/schemas/ListingPage" }],
+        },
+        "ListingPage": {
+            "type": "object",
+            "required": ["elus", "next_cursor"],
+            "properties": {
+                "elus": { "type": "array", "items": schema("Person") },
+                "next_cursor": { "type": ["string", "null"], "description": "`after` parameter fetching the next page; null on the last one." },
+            },
+        },
+        "Hit": {
+            "description": "An elu found by a search.",
+            "allOf": [schema("Person"), { "type": "object", "required": ["score"], "properties": { "score": { "type": "integer", "description": "Quality of the match, best first." } } }],
+        },
+        "Tag": {
+            "type": "object",
+            "required": ["name", "elus"],
+            "properties": {
+                "name": { "type": "string" },
+                "description": { "type": ["string", "null"] },
+                "elus": { "type": "integer", "description": "How many elus have the tag." },
+            },
+        },
+        "NewTag": {
+            "type": "object",
+            "required": ["name"],
+            "properties": { "name": { "type": "string", "maxLength": 50 }, "description": { "type": ["string", "null"] } },
+        },
+        "Body": { "type": "object", "required": ["id", "name", "kind"], "properties": body_properties },
+        "NewBody": {
+            "type": "object",
+            "required": ["name", "kind"],
+            "properties": { "name": body_properties["name"], "kind": body_properties["kind"], "commune_code": body_properties["commune_code"] },
+        },
+        "BodyMembers": {
+            "allOf": [schema("Body"), { "type": "object", "required": ["members"], "properties": { "members": list("Member") } }],
+        },
+        "Member": {
+            "type": "object",
+            "required": ["uuid", "name"],
+            "properties": { "uuid": { "type": "string", "format": "uuid" }, "name": { "type": "string" }, "role": { "type": ["string", "null"] } },
+        },
+        "Membership": {
+            "description": "A body an elu sits on, and their role in it.",
+            "allOf": [schema("Body"), { "type": "object", "properties": { "role": { "type": ["string", "null"] } } }],
+        },
+        "NewMember": { "type": "object", "properties": { "role": { "type": ["string", "null"] } } },
+        "Document": {
+            "type": "object",
+            "required": ["id", "title", "content_type", "size", "visibility", "created_at"],
+            "properties": {
+                "id": { "type": "integer" },
+                "title": { "type": "string" },
+                "filename": { "type": ["string", "null"], "description": "Name of the uploaded file." },
+                "content_type": { "type": "string" },
+                "size": { "type": "integer" },
+                "visibility": { "type": "string", "enum": ["public", "private"] },
+                "created_at": { "type": "string", "format": "date-time" },
+            },
+        },
+        "DocumentUpdate": {
+            "type": "object",
+            "properties": { "title": { "type": "string" }, "visibility": { "type": "string", "enum": ["public", "private"] } },
+        },
+    });
+    (paths, schemas)
+}
+
+/// `template` with an id and a summary.
+fn operation(id: &str, summary: &str, template: &Value) -> Value {
+    let mut operation = template.clone();
+    operation["operationId"] = json!(id);
+    operation["summary"] = json!(summary);
+    operation
+}
+
+/// The schema of a component, by name.
+pub fn schema(name: &str) -> Option<&'static Value> {
+    spec()["components"]["schemas"].get(name)
+}
+
+#[get("/openapi.json")]
+fn openapi() -> Json<&'static Value> {
+    Json(spec())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![openapi]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use crate::{db, validation};
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::LocalResponse;
+    use std::collections::HashMap;
+
+    /// The response, which must be a success, with its UUIDs and timestamps, which differ between
+    /// runs, replaced with fixed values.
+    fn example(response: LocalResponse<'_>, uuids: &mut HashMap<String, String>) -> Value {
+        fn normalize(value: &mut Value, key: Option<&str>, uuids: &mut HashMap<String, String>) {
+            match value {
+                Value::Object(object) => object.iter_mut().for_each(|(key, value)| normalize(value, Some(key), uuids)),
+                Value::Array(values) => values.iter_mut().for_each(|value| normalize(value, key, uuids)),
+                Value::String(string) if key.is_some_and(|key| key.ends_with("_at")) => *string = "2025-01-01T00:00:00Z".to_string(),
+                Value::String(string) if uuid::Uuid::parse_str(string).is_ok() => {
+                    let next = uuids.len() + 1;
+                    *string = uuids.entry(string.clone()).or_insert_with(|| format!("00000000-0000-4000-8000-{:012}", next)).clone();
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(response.status(), Status::Ok);
+        let mut value = response.into_json::<Value>().expect("JSON response");
+        normalize(&mut value, None, uuids);
+        value
+    }
+
+    #[test]
+    fn test_examples() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        let client = client(connection);
+        let mut uuids = HashMap::new();
+
+        let list = example(client.get("/elus?mandate=Maire").dispatch(), &mut uuids);
+        let get = example(client.get("/elus/jean.dupont@example.com").dispatch(), &mut uuids);
+        let search = example(client.get("/elus/search?q=dupont").dispatch(), &mut uuids);
+        let lookup_request = json!({ "emails": ["jean.dupont@example.com", "marie.martin@example.com"] });
+        let lookup = client.post("/elus/lookup").header(admin()).header(ContentType::JSON).body(lookup_request.to_string()).dispatch();
+        let lookup = example(lookup, &mut uuids);
+        let create_request = json!({
+            "name": "Sophie Bernard",
+            "email": "sophie.bernard@example.com",
+            "mandates": ["Adjointe au maire"],
+            "commune_code": "75056",
+            "may_contact_by_email": true,
+            "consent_date": "2025-01-01T00:00:00Z",
+            "consent_source": "Formulaire d'inscription",
+        });
+        let create = client.post("/elus/create").header(admin()).header(ContentType::JSON).body(create_request.to_string()).dispatch();
+        let create = example(create, &mut uuids);
+
+        let examples = json!({
+            "listElus": { "responses": { "200": list } },
+            "getElu": { "responses": { "200": get } },
+            "searchElus": { "responses": { "200": search } },
+            "lookupElus": { "request": lookup_request, "responses": { "200": lookup } },
+            "createElu": { "request": create_request, "responses": { "200": create } },
+        });
+        for (id, name) in [("lookupElus", "Lookup"), ("createElu", "Person")] {
+            let mut violations = Vec::new();
+            validation::validate(&examples[id]["request"], schema(name).unwrap(), String::new(), &mut violations);
+            assert!(violations.is_empty(), "{}: {:?}", id, violations);
+        }
+
+        if std::env::var_os("UPDATE_OPENAPI_EXAMPLES").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/openapi_examples.json");
+            std::fs::write(path, serde_json::to_string_pretty(&examples).unwrap() + "\n").unwrap();
+            return;
+        }
+        assert_eq!(examples, serde_json::from_str::<Value>(EXAMPLES).unwrap(), "run the tests with UPDATE_OPENAPI_EXAMPLES=1 to update the examples");
+
+        let spec = client.get("/openapi.json").dispatch().into_json::<Value>().unwrap();
+        assert_eq!(spec["paths"]["/elus/{key}"]["get"]["responses"]["200"]["content"]["application/json"]["example"], examples["getElu"]["responses"]["200"]);
+        assert_eq!(spec["paths"]["/elus/create"]["post"]["requestBody"]["content"]["application/json"]["example"]["name"], "Sophie Bernard");
+    }
+}
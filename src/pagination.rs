@@ -0,0 +1,225 @@
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::Deserialize;
+
+use crate::config::AppConfig;
+use crate::db::Person;
+use crate::problem::{self, Problem};
+use crate::repository::{Page, PersonFilter, PersonRepository};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PaginationConfig {
+    /// Results per page when the request doesn't say.
+    pub default_per_page: i64,
+    /// Most results per page a request may ask for.
+    pub max_per_page: i64,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        PaginationConfig { default_per_page: 50, max_per_page: 500 }
+    }
+}
+
+fn bad_request(detail: String) -> Problem {
+    Problem { detail: Some(detail), ..Problem::new(Status::BadRequest) }
+}
+
+/// Paging query parameters: `page` (from 1) and `per_page` to page by
+/// number, or `after` and `limit` to page by cursor, `after` being the
+/// `next_cursor` of the previous page. Cursors aren't thrown off by
+/// persons added or removed in the meantime, nor slowed down by deep
+/// pages.
+#[derive(Debug, Default)]
+pub struct PageParams {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl PageParams {
+    /// The requested page; `None` when no paging parameter was given.
+    pub fn page(&self, config: &PaginationConfig) -> Result<Option<Page>, Problem> {
+        if self.page.is_none() && self.per_page.is_none() && self.after.is_none() && self.limit.is_none() {
+            return Ok(None);
+        }
+        if self.page.is_some() && self.after.is_some() {
+            return Err(bad_request("page and after can't be combined; pass the next_cursor of the previous page as after alone.".to_string()));
+        }
+
+        let limit = self.per_page.or(self.limit).unwrap_or(config.default_per_page.min(config.max_per_page));
+        if !(1..=config.max_per_page).contains(&limit) {
+            return Err(bad_request(format!(
+                "per_page and limit must be between 1 and {}; fetch larger result sets a page at a time, following next_cursor.",
+                config.max_per_page
+            )));
+        }
+        let out_of_range = || bad_request("page must be a positive number within range.".to_string());
+        let offset = match self.page {
+            Some(page) if page >= 1 => (page - 1).checked_mul(limit).ok_or_else(out_of_range)?,
+            Some(_) => return Err(out_of_range()),
+            None => 0,
+        };
+        let after = self
+            .after
+            .as_deref()
+            .map(|cursor| decode_cursor(cursor).ok_or_else(|| bad_request("after must be the next_cursor of a previous page.".to_string())))
+            .transpose()?;
+
+        Ok(Some(Page { after, offset, limit, ..Default::default() }))
+    }
+}
+
+/// The page the paging parameters of the query string ask for, validated
+/// against the configuration, for list endpoints to take as a guard;
+/// `None` when the request asks for all results.
+#[derive(Debug, Default)]
+pub struct Pagination(pub Option<Page>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Pagination {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(config) = request.rocket().state::<AppConfig>() else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        let number = |name: &str| request.query_value::<i64>(name).transpose();
+        let (Ok(page), Ok(per_page), Ok(limit)) = (number("page"), number("per_page"), number("limit")) else {
+            return problem::refuse(bad_request("page, per_page and limit must be numbers.".to_string()), request);
+        };
+        let after = request.query_value::<String>("after").and_then(Result::ok);
+        match (PageParams { page, per_page, after, limit }).page(&config.pagination) {
+            Ok(page) => request::Outcome::Success(Pagination(page)),
+            Err(problem) => problem::refuse(problem, request),
+        }
+    }
+}
+
+/// Keys a list endpoint may be sorted by.
+pub trait SortKey: Copy + PartialEq + Send + Sync + 'static {
+    /// The keys, by the name `?sort=` gives them.
+    const KEYS: &'static [(&'static str, Self)];
+}
+
+/// The order of `?sort=`: a key, prefixed by `-` to sort in descending
+/// order, as in `sort=-updated_at`. Endpoints list in their own order when
+/// the request doesn't say, and break ties by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortSpec<K> {
+    pub key: Option<K>,
+    pub descending: bool,
+}
+
+impl<K> Default for SortSpec<K> {
+    fn default() -> Self {
+        SortSpec { key: None, descending: false }
+    }
+}
+
+impl<K: SortKey> SortSpec<K> {
+    pub fn parse(sort: &str) -> Result<Self, Problem> {
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+        match K::KEYS.iter().find(|(key, _)| *key == name) {
+            Some((_, key)) => Ok(SortSpec { key: Some(*key), descending }),
+            None => {
+                let keys: Vec<_> = K::KEYS.iter().map(|(key, _)| *key).collect();
+                Err(bad_request(format!("sort must be one of {}, optionally prefixed by - for descending order.", keys.join(", "))))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, K: SortKey> FromRequest<'r> for SortSpec<K> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.query_value::<&str>("sort") {
+            None => request::Outcome::Success(SortSpec::default()),
+            Some(Ok(sort)) => match SortSpec::parse(sort) {
+                Ok(spec) => request::Outcome::Success(spec),
+                Err(problem) => problem::refuse(problem, request),
+            },
+            Some(Err(_)) => problem::refuse(bad_request("sort must be text.".to_string()), request),
+        }
+    }
+}
+
+pub fn encode_cursor(person: &Person) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(format!("{}:{}", person.id, person.name).as_bytes())
+}
+
+fn decode_cursor(cursor: &str) -> Option<(String, i32)> {
+    let decoded = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (id, name) = decoded.split_once(':')?;
+    Some((name.to_string(), id.parse().ok()?))
+}
+
+/// Fetches a page of results, along with the cursor of the next one if
+/// there are more.
+pub fn fetch(repository: &dyn PersonRepository, filter: &PersonFilter, page: Page) -> Result<(Vec<Person>, Option<String>), Status> {
+    let limit = page.limit as usize;
+    let mut persons = repository.search_page(filter, &Page { limit: page.limit + 1, ..page })?;
+    if persons.len() <= limit {
+        return Ok((persons, None));
+    }
+
+    persons.truncate(limit);
+    let next_cursor = persons.last().map(encode_cursor);
+    Ok((persons, next_cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_params() {
+        let config = PaginationConfig::default();
+        let params = |page, per_page, after: Option<&str>, limit| {
+            let params = PageParams { page, per_page, after: after.map(str::to_string), limit };
+            params.page(&config).map_err(|problem| problem.status)
+        };
+
+        assert_eq!(params(None, None, None, None), Ok(None));
+        assert!(matches!(params(Some(3), Some(20), None, None), Ok(Some(Page { offset: 40, limit: 20, after: None, .. }))));
+        assert!(matches!(params(None, None, None, Some(5)), Ok(Some(Page { offset: 0, limit: 5, .. }))));
+        assert_eq!(params(Some(0), None, None, None).unwrap_err(), 400);
+        assert_eq!(params(None, None, None, Some(config.max_per_page + 1)).unwrap_err(), 400);
+        assert_eq!(params(Some(i64::MAX), None, None, None).unwrap_err(), 400);
+        assert_eq!(params(Some(2), None, Some("MTpKZWFu"), None).unwrap_err(), 400);
+        assert_eq!(params(None, None, Some("not a cursor"), None).unwrap_err(), 400);
+    }
+
+    #[test]
+    fn test_sort_spec() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum Key {
+            Name,
+            Date,
+        }
+        impl SortKey for Key {
+            const KEYS: &'static [(&'static str, Self)] = &[("name", Key::Name), ("date", Key::Date)];
+        }
+
+        assert_eq!(SortSpec::parse("name").unwrap(), SortSpec { key: Some(Key::Name), descending: false });
+        assert_eq!(SortSpec::parse("-date").unwrap(), SortSpec { key: Some(Key::Date), descending: true });
+        let problem = SortSpec::<Key>::parse("-updated_at").unwrap_err();
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.detail.as_deref(), Some("sort must be one of name, date, optionally prefixed by - for descending order."));
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = BASE64_URL_SAFE_NO_PAD.encode("12:Hélène: la Maire".as_bytes());
+        assert_eq!(decode_cursor(&cursor), Some(("Hélène: la Maire".to_string(), 12)));
+        assert_eq!(decode_cursor(&BASE64_URL_SAFE_NO_PAD.encode(b"Jean")), None);
+    }
+}
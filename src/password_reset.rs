@@ -0,0 +1,272 @@
+//! Password recovery for dashboard users, through a link mailed to their
+//! address. Reset tokens aren't stored: they're signed with the configured
+//! secret, expire, and are tied to the password they replace, so that they
+//! can't be used once it has changed.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::content::RawHtml;
+use rocket::serde::Deserialize;
+use rocket::State;
+
+use crate::auth::constant_time_eq;
+use crate::config::AppConfig;
+use crate::dashboard::{escape, page};
+use crate::encryption::Sealer;
+use crate::mail::Message;
+use crate::schema::users;
+use crate::sha256::hmac_sha256;
+use crate::users::{set_password, User, MIN_PASSWORD_LEN};
+use crate::{audit, db, lockout, mail_queue, timestamp, DbConn};
+
+fn default_validity() -> u64 {
+    60 * 60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PasswordResetConfig {
+    /// Key reset tokens are signed with; changing it voids pending ones.
+    pub secret: String,
+    /// Public URL of the service, which reset links point to.
+    pub base_url: String,
+    /// Seconds reset links stay valid for.
+    #[serde(default = "default_validity")]
+    pub validity: u64,
+}
+
+#[derive(FromForm)]
+struct Forgot<'r> {
+    email: &'r str,
+}
+
+#[derive(FromForm)]
+struct Reset<'r> {
+    password: &'r str,
+}
+
+type HtmlError = (Status, RawHtml<String>);
+
+fn signature(user_id: i32, expires: i64, password_hash: &str, secret: &str) -> String {
+    let message = format!("{}.{}.{}", user_id, expires, password_hash);
+    BASE64_URL_SAFE_NO_PAD.encode(hmac_sha256(secret.as_bytes(), message.as_bytes()))
+}
+
+/// `<user id>.<expiry, as a Unix timestamp>.<signature>`.
+pub fn issue(user_id: i32, password_hash: &str, expires: i64, secret: &str) -> String {
+    format!("{}.{}.{}", user_id, expires, signature(user_id, expires, password_hash, secret))
+}
+
+/// The user a valid, unexpired token was issued to.
+pub fn verify(token: &str, now: i64, secret: &str, connection: &mut SqliteConnection) -> QueryResult<Option<User>> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [user_id, expires, provided] = parts[..] else {
+        return Ok(None);
+    };
+    let (Ok(user_id), Ok(expires)) = (user_id.parse::<i32>(), expires.parse::<i64>()) else {
+        return Ok(None);
+    };
+    if expires <= now {
+        return Ok(None);
+    }
+
+    let found: Option<(User, String)> = users::table
+        .find(user_id)
+        .select((User::as_select(), users::password_hash))
+        .first(connection)
+        .optional()?;
+
+    Ok(found.and_then(|(user, password_hash)| {
+        constant_time_eq(signature(user_id, expires, &password_hash, secret).as_bytes(), provided.as_bytes()).then_some(user)
+    }))
+}
+
+fn configured(config: &AppConfig) -> Result<&PasswordResetConfig, HtmlError> {
+    config.password_reset.as_ref().ok_or_else(|| (Status::NotFound, page("Introuvable", "<p>La réinitialisation des mots de passe n'est pas disponible.</p>")))
+}
+
+fn internal_error<E>(_: E) -> HtmlError {
+    (Status::InternalServerError, page("Erreur", "<p>Erreur interne, veuillez réessayer.</p>"))
+}
+
+fn invalid_link() -> HtmlError {
+    (Status::BadRequest, page("Lien invalide", "<p>Ce lien de réinitialisation est invalide ou a expiré.</p>\n<p><a href=\"/auth/forgot\">Demander un nouveau lien</a></p>"))
+}
+
+#[get("/auth/forgot")]
+fn forgot_form(config: &State<AppConfig>) -> Result<RawHtml<String>, HtmlError> {
+    configured(config)?;
+    Ok(page(
+        "Mot de passe oublié",
+        "<h1>Mot de passe oublié</h1>\n<form method=\"post\" action=\"/auth/forgot\">\n\
+         <label>Courriel <input name=\"email\" type=\"email\" autocomplete=\"email\" required></label>\n\
+         <button type=\"submit\">Recevoir un lien</button>\n</form>",
+    ))
+}
+
+/// Mails a reset link to the user with this address. The answer is the
+/// same whether there's one or not, not to reveal who has an account.
+#[post("/auth/forgot", data = "<forgot>")]
+fn forgot(forgot: Form<Forgot<'_>>, ip: Option<IpAddr>, db: &State<DbConn>, config: &State<AppConfig>, sealer: &State<Sealer>) -> Result<RawHtml<String>, HtmlError> {
+    let reset = configured(config)?;
+    let mut connection = db::lock(db).map_err(internal_error)?;
+
+    let found: Option<(User, String)> = users::table
+        .filter(users::email.eq(forgot.email))
+        .select((User::as_select(), users::password_hash))
+        .first(&mut *connection)
+        .optional()
+        .map_err(internal_error)?;
+
+    if let Some((user, password_hash)) = found {
+        let expires = (timestamp::now() + Duration::from_secs(reset.validity)).assume_utc().unix_timestamp();
+        let token = issue(user.id, &password_hash, expires, &reset.secret);
+        let message = Message {
+            to: forgot.email.to_string(),
+            subject: "Réinitialisation de votre mot de passe".to_string(),
+            body: format!(
+                "Bonjour {},\n\nPour choisir un nouveau mot de passe, ouvrez ce lien dans les {} minutes :\n{}/auth/reset/{}\n\n\
+                 Si vous n'êtes pas à l'origine de cette demande, ignorez ce message.\n",
+                user.username,
+                reset.validity / 60,
+                reset.base_url.trim_end_matches('/'),
+                token
+            ),
+        };
+
+        connection
+            .transaction(|connection| {
+                mail_queue::enqueue(&message, None, sealer, connection)?;
+                audit::record(audit::PASSWORD_RESET_REQUESTED, Some(&user.username), ip, connection)
+            })
+            .map_err(internal_error)?;
+    }
+
+    Ok(page("Mot de passe oublié", "<p>Si ce courriel correspond à un compte, un lien de réinitialisation vient d'y être envoyé.</p>"))
+}
+
+fn reset_page(token: &str, error: Option<&str>) -> RawHtml<String> {
+    let error = error.map(|error| format!("<p class=\"error\">{}</p>\n", escape(error))).unwrap_or_default();
+    page(
+        "Nouveau mot de passe",
+        &format!(
+            "<h1>Nouveau mot de passe</h1>\n{}<form method=\"post\" action=\"/auth/reset/{}\">\n\
+             <label>Mot de passe <input name=\"password\" type=\"password\" autocomplete=\"new-password\" minlength=\"{}\" required></label>\n\
+             <button type=\"submit\">Enregistrer</button>\n</form>",
+            error,
+            escape(token),
+            MIN_PASSWORD_LEN
+        ),
+    )
+}
+
+#[get("/auth/reset/<token>")]
+fn reset_form(token: &str, db: &State<DbConn>, config: &State<AppConfig>) -> Result<RawHtml<String>, HtmlError> {
+    let reset = configured(config)?;
+    let now = timestamp::now().assume_utc().unix_timestamp();
+    verify(token, now, &reset.secret, &mut *db::lock(db).map_err(internal_error)?).map_err(internal_error)?.ok_or_else(invalid_link)?;
+
+    Ok(reset_page(token, None))
+}
+
+#[post("/auth/reset/<token>", data = "<new>")]
+fn reset(token: &str, new: Form<Reset<'_>>, ip: Option<IpAddr>, db: &State<DbConn>, config: &State<AppConfig>) -> Result<RawHtml<String>, HtmlError> {
+    let reset = configured(config)?;
+    let now = timestamp::now().assume_utc().unix_timestamp();
+    let mut connection = db::lock(db).map_err(internal_error)?;
+    let user = verify(token, now, &reset.secret, &mut connection).map_err(internal_error)?.ok_or_else(invalid_link)?;
+
+    if new.password.chars().count() < MIN_PASSWORD_LEN {
+        let error = format!("Le mot de passe doit faire au moins {} caractères.", MIN_PASSWORD_LEN);
+        return Err((Status::UnprocessableEntity, reset_page(token, Some(&error))));
+    }
+
+    connection
+        .transaction(|connection| {
+            set_password(user.id, new.password, connection)?;
+            lockout::clear(&lockout::keys(&user.username, None), connection)?;
+            audit::record(audit::PASSWORD_RESET, Some(&user.username), ip, connection)
+        })
+        .map_err(internal_error)?;
+
+    Ok(page("Mot de passe modifié", "<p>Votre mot de passe a été modifié.</p>\n<p><a href=\"/admin/login\">Se connecter</a></p>"))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![forgot_form, forgot, reset_form, reset]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::mail_queue;
+    use crate::tests::{build_client, setup_test_db};
+    use crate::users::NewUser;
+    use rocket::http::ContentType;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn create_user(connection: &mut SqliteConnection) -> User {
+        let new_user = NewUser { username: "secretariat".to_string(), password: PASSWORD.to_string(), email: Some("secretariat@mairie.example".to_string()) };
+        crate::users::create(&new_user, connection).unwrap()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut connection = setup_test_db();
+        let user = create_user(&mut connection);
+        let password_hash: String = users::table.find(user.id).select(users::password_hash).first(&mut connection).unwrap();
+        let token = issue(user.id, &password_hash, 2_000, "secret");
+
+        assert_eq!(verify(&token, 1_000, "secret", &mut connection).unwrap().map(|user| user.id), Some(user.id));
+        assert!(verify(&token, 2_000, "secret", &mut connection).unwrap().is_none());
+        assert!(verify(&token, 1_000, "other secret", &mut connection).unwrap().is_none());
+        assert!(verify(&token.replace(".2000.", ".3000."), 1_000, "secret", &mut connection).unwrap().is_none());
+        assert!(verify("garbage", 1_000, "secret", &mut connection).unwrap().is_none());
+
+        // Tokens can't be used once the password has changed.
+        set_password(user.id, "another long password", &mut connection).unwrap();
+        assert!(verify(&token, 1_000, "secret", &mut connection).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reset_flow() {
+        let mut connection = setup_test_db();
+        create_user(&mut connection);
+        let client = build_client(
+            |figment| figment.merge(("password_reset.secret", "test secret")).merge(("password_reset.base_url", "https://annuaire.example/")),
+            connection,
+        );
+        let form = |uri: &str, body: &str| client.post(uri.to_string()).header(ContentType::Form).body(body).dispatch().status();
+
+        assert_eq!(form("/auth/forgot", "email=nobody%40mairie.example"), Status::Ok);
+        assert_eq!(form("/auth/forgot", "email=secretariat%40mairie.example"), Status::Ok);
+
+        let db = client.rocket().state::<DbConn>().unwrap().clone();
+        let (recipient, body): (String, String) =
+            mail_queue::table.select((mail_queue::recipient, mail_queue::body)).first(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!(recipient, "secretariat@mairie.example");
+        let link = body.lines().find(|line| line.starts_with("https://annuaire.example/auth/reset/")).expect("a reset link");
+        let path = link.trim_start_matches("https://annuaire.example");
+
+        assert_eq!(client.get(path.to_string()).dispatch().status(), Status::Ok);
+        assert_eq!(form(path, "password=short"), Status::UnprocessableEntity);
+        assert_eq!(form(path, "password=a+brand+new+password"), Status::Ok);
+        assert_eq!(form(path, "password=yet+another+password"), Status::BadRequest);
+
+        assert_eq!(form("/admin/login", "username=secretariat&password=a+brand+new+password"), Status::SeeOther);
+        assert_eq!(client.get("/auth/reset/1.1.forged").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_reset_disabled() {
+        let client = crate::tests::client(setup_test_db());
+        assert_eq!(client.get("/auth/forgot").dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,141 @@
+//! Names of elus, normalized wherever they come in so that `Jean  Dupont `
+//! and `Jean Dupont` are the same person: surrounding whitespace is
+//! trimmed, inner runs of whitespace collapsed to a single space and
+//! the name put in Unicode normalization form C, so that accents typed as
+//! combining marks and precomposed letters compare equal. Names read back
+//! from the database are trusted as stored.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteValue};
+use rocket::serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use unicode_normalization::UnicodeNormalization;
+
+/// `name` trimmed, with its whitespace collapsed and its accents composed.
+pub fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").nfc().collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub struct PersonName(String);
+
+/// Why a string isn't a `PersonName`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlankName;
+
+impl fmt::Display for BlankName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("must not be blank")
+    }
+}
+
+impl PersonName {
+    pub fn parse(name: &str) -> Result<PersonName, BlankName> {
+        let name = normalize(name);
+        if name.is_empty() {
+            return Err(BlankName);
+        }
+        Ok(PersonName(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// For test fixtures which don't care about the name.
+#[cfg(test)]
+impl Default for PersonName {
+    fn default() -> Self {
+        PersonName("Jean Personne".to_string())
+    }
+}
+
+impl FromStr for PersonName {
+    type Err = BlankName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        PersonName::parse(name)
+    }
+}
+
+impl Deref for PersonName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PersonName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for PersonName {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for PersonName {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Serialize for PersonName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for PersonName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        PersonName::parse(&name).map_err(de::Error::custom)
+    }
+}
+
+impl ToSql<Text, Sqlite> for PersonName {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for PersonName {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        <String as FromSql<Text, Sqlite>>::from_sql(value).map(PersonName)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(" Jean  Dupont \t"), "Jean Dupont");
+        assert_eq!(normalize("He\u{301}le\u{300}ne Noe\u{308}l-C\u{327}elik"), "Hélène Noël-Çelik");
+        assert_eq!(normalize("\u{301}Ana Ba\u{301}\u{301}"), "\u{301}Ana Bá\u{301}");
+        assert_eq!(normalize("Łukasz Wałęsa"), "Łukasz Wałęsa");
+        assert_eq!(normalize("Nguye\u{302}\u{303}n Va\u{306}n A\u{300}"), "Nguyễn Văn À");
+        assert_eq!(normalize("Wale\u{328}sa"), "Walęsa");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(PersonName::parse("Jean  Dupont "), Ok(PersonName("Jean Dupont".to_string())));
+        assert_eq!(PersonName::parse(" \n "), Err(BlankName));
+        assert_eq!(serde_json::from_str::<PersonName>("\" \"").unwrap_err().to_string(), "must not be blank");
+        assert_eq!(serde_json::to_string(&"Marie\u{a0}Martin".parse::<PersonName>().unwrap()).unwrap(), "\"Marie Martin\"");
+    }
+}
@@ -0,0 +1,94 @@
+//! Phonetic keys of French names, for fuzzy searches: names which sound
+//! alike get the same key, such as "Dupont" and "Dupon", or "Durand" and
+//! "Durant". The rules are a simplified take on French phonetics: letters
+//! written differently but sounding the same are merged, nasal vowels and
+//! "o" sounds are unified, silent final letters dropped and doubled
+//! letters collapsed. The `search_phonetic` column holds the key of each
+//! name.
+
+use crate::repository::normalize_name;
+
+/// Rewrites applied in order, each to the whole word.
+const REWRITES: &[(&str, &str)] = &[
+    ("ph", "f"),
+    ("th", "t"),
+    ("sch", "ch"),
+    ("gn", "n"),
+    ("qu", "k"),
+    ("ck", "k"),
+    ("ch", "%"),
+    ("ce", "se"),
+    ("ci", "si"),
+    ("cy", "si"),
+    ("c", "k"),
+    ("q", "k"),
+    ("%", "c"),
+    ("ge", "je"),
+    ("gi", "ji"),
+    ("gy", "ji"),
+    ("gue", "ge"),
+    ("gui", "gi"),
+    ("h", ""),
+    ("z", "s"),
+    ("w", "v"),
+    ("y", "i"),
+    ("eau", "o"),
+    ("au", "o"),
+    ("ean", "an"),
+    ("ain", "in"),
+    ("ein", "in"),
+    ("un", "in"),
+    ("am", "an"),
+    ("em", "an"),
+    ("en", "an"),
+    ("om", "on"),
+    ("ai", "e"),
+    ("ei", "e"),
+];
+
+/// Final letters French doesn't pronounce.
+const SILENT_ENDINGS: &[char] = &['e', 's', 't', 'd', 'x'];
+
+fn word_key(word: &str) -> String {
+    let mut key: String = word.chars().filter(char::is_ascii_lowercase).collect();
+    for (from, to) in REWRITES {
+        key = key.replace(from, to);
+    }
+    while key.len() > 1 && key.ends_with(SILENT_ENDINGS) {
+        key.pop();
+    }
+
+    let mut collapsed = String::with_capacity(key.len());
+    for c in key.chars() {
+        if !collapsed.ends_with(c) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+/// The key of each word of the name, separated by spaces.
+pub fn key(name: &str) -> String {
+    normalize_name(name)
+        .split([' ', '-', '\''])
+        .map(word_key)
+        .filter(|key| !key.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(key("Dupon"), key("Dupont"));
+        assert_eq!(key("Durant"), key("Durand"));
+        assert_eq!(key("Philippe Lefèvre"), key("Filipe Lefevre"));
+        assert_eq!(key("Marie-Claire Rousseau"), key("Mari Cler Rousso"));
+        assert_eq!(key("Jean Dupont"), "jan dupon");
+        assert_ne!(key("Martin"), key("Durand"));
+        assert_eq!(key(""), "");
+    }
+}
@@ -0,0 +1,129 @@
+//! Just enough PNG to serve generated bitmaps: 1-bit grayscale images with
+//! uncompressed (stored) deflate blocks.
+
+use qrcode::{Color, QrCode};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Pixels per QR code module and width of the quiet zone, in modules.
+const MODULE_PIXELS: u32 = 8;
+const QUIET_ZONE: u32 = 4;
+
+/// Encodes a black and white image; `dark(x, y)` tells whether a pixel is black.
+pub fn encode_monochrome(width: u32, height: u32, dark: impl Fn(u32, u32) -> bool) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 1, grayscale, deflate, adaptive filtering, no interlace.
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    let row_len = width.div_ceil(8) as usize;
+    let mut scanlines = Vec::with_capacity((row_len + 1) * height as usize);
+    for y in 0..height {
+        // Filter type "none".
+        scanlines.push(0);
+        let mut row = vec![0xFFu8; row_len];
+        for x in (0..width).filter(|&x| dark(x, y)) {
+            row[x as usize / 8] &= !(0x80 >> (x % 8));
+        }
+        scanlines.extend(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Renders a QR code, with its quiet zone, at a size phones scan easily.
+pub fn encode_qr_code(qr: &QrCode) -> Vec<u8> {
+    let side = (qr.width() as u32 + 2 * QUIET_ZONE) * MODULE_PIXELS;
+    encode_monochrome(side, side, |x, y| {
+        let (x, y) = (x / MODULE_PIXELS, y / MODULE_PIXELS);
+        let in_code = |coordinate: u32| (QUIET_ZONE..QUIET_ZONE + qr.width() as u32).contains(&coordinate);
+        in_code(x) && in_code(y) && qr[((x - QUIET_ZONE) as usize, (y - QUIET_ZONE) as usize)] == Color::Dark
+    })
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream made of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(u8::from(blocks.peek().is_none()));
+        let len = block.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode_monochrome() {
+        let png = encode_monochrome(10, 2, |x, _| x % 2 == 0);
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 10, 0, 0, 0, 2]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        // Two scanlines of a filter byte plus two bytes of pixels.
+        let idat = &png[33 + 8..];
+        let stored = &idat[2 + 5..2 + 5 + 6];
+        assert_eq!(stored, [0, 0x55, 0x7F, 0, 0x55, 0x7F]);
+    }
+
+    #[test]
+    fn test_encode_qr_code() {
+        // A version 1 code: 21 modules, plus the quiet zone on both sides.
+        let qr = QrCode::with_error_correction_level(b"HELLO WORLD", qrcode::EcLevel::M).unwrap();
+        let png = encode_qr_code(&qr);
+        let side = (21 + 2 * QUIET_ZONE) * MODULE_PIXELS;
+        assert_eq!(png[16..24], [side.to_be_bytes(), side.to_be_bytes()].concat());
+    }
+}
@@ -0,0 +1,120 @@
+//! Error bodies in the `application/problem+json` format of RFC 7807.
+//! Handlers keep failing with a bare `Status`; the catchers turn it into a
+//! problem, so every error response has the same shape.
+
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Serialize;
+
+/// Seconds after which callers may retry requests answered with a 503.
+const RETRY_AFTER: u64 = 1;
+
+/// One reason a request body was refused.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Violation {
+    /// JSON pointer to the offending value; empty for the whole body.
+    pub pointer: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Path of the request which failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<Violation>,
+}
+
+impl Problem {
+    /// A problem which is nothing more than its status.
+    pub fn new(status: Status) -> Self {
+        Problem {
+            problem_type: "about:blank",
+            title: status.reason_lossy(),
+            status: status.code,
+            detail: None,
+            instance: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The problem, for the given request.
+    pub fn of(status: Status, request: &Request<'_>) -> Self {
+        Problem { instance: Some(request.uri().path().to_string()), ..Problem::new(status) }
+    }
+}
+
+/// A bare error status, for handlers returning problems with details
+/// along with the errors of calls failing with a `Status`.
+impl From<Status> for Problem {
+    fn from(status: Status) -> Self {
+        Problem::new(status)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Problem {
+    fn respond_to(mut self, request: &'r Request<'_>) -> response::Result<'static> {
+        self.instance = self.instance.or_else(|| Some(request.uri().path().to_string()));
+        let body = serde_json::to_string(&self).map_err(|_| Status::InternalServerError)?;
+
+        let mut response = Response::build();
+        response.status(Status::new(self.status)).header(ContentType::new("application", "problem+json"));
+        // What the service is short of, such as the database, being held
+        // briefly, callers are told to try again soon.
+        if self.status == Status::ServiceUnavailable.code {
+            response.raw_header("Retry-After", RETRY_AFTER.to_string());
+        }
+        response.sized_body(body.len(), Cursor::new(body)).ok()
+    }
+}
+
+/// Why a request guard refused the request, for the catcher to tell.
+struct Detail(Option<String>);
+
+/// Fails a request guard with the status of the problem, whose detail the
+/// catcher then answers with.
+pub fn refuse<T>(problem: Problem, request: &Request<'_>) -> request::Outcome<T, ()> {
+    request.local_cache(|| Detail(problem.detail));
+    request::Outcome::Error((Status::new(problem.status), ()))
+}
+
+#[catch(default)]
+pub fn catcher(status: Status, request: &Request<'_>) -> Problem {
+    let Detail(detail) = request.local_cache(|| Detail(None));
+    Problem { detail: detail.clone(), ..Problem::of(status, request) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{client, setup_test_db};
+    use rocket::http::Status;
+    use rocket::serde::json::{json, Value};
+
+    #[test]
+    fn test_problem_responses() {
+        let client = client(setup_test_db());
+
+        let response = client.get("/elus/nobody@example.com").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+        assert_eq!(
+            response.into_json::<Value>().unwrap(),
+            json!({ "type": "about:blank", "title": "Not Found", "status": 404, "instance": "/elus/nobody@example.com" })
+        );
+
+        let problem: Value = client.get("/events").dispatch().into_json().unwrap();
+        assert_eq!((problem["status"].as_u64(), problem["title"].as_str()), (Some(401), Some("Unauthorized")));
+    }
+}
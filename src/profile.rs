@@ -0,0 +1,206 @@
+//! HTML profile pages of elus, served from the same `/elus/<key>` URL as
+//! their JSON to callers preferring `text/html`, such as browsers. Pages
+//! are rendered from `templates/person.html` and embed the elu as
+//! schema.org JSON-LD, for search engines. They show what the JSON would
+//! show the caller: redacted fields are left out of both.
+
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::content::RawHtml;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{json, Json, Value};
+
+use crate::dashboard::escape;
+use crate::redaction::redact;
+use crate::settings::{self, Settings};
+use crate::{db, DbConn, Person};
+
+const TEMPLATE: &str = include_str!("../templates/person.html");
+
+/// Replaces the `{{name}}` placeholders of `template` with their values,
+/// which are inserted as they are; unknown placeholders are left empty.
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else { break };
+        rendered.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + end];
+        if let Some((_, value)) = values.iter().find(|(placeholder, _)| *placeholder == name) {
+            rendered.push_str(value);
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// How a relation reads on the page of the elu.
+fn relation_label(relation: &str) -> &str {
+    match relation {
+        "substitute_of" => "Suppléant(e) de",
+        "has_substitute" => "A pour suppléant(e)",
+        "deputy_of" => "Adjoint(e) de",
+        "has_deputy" => "A pour adjoint(e)",
+        other => other,
+    }
+}
+
+/// The person, a redacted `Person`, as schema.org JSON-LD.
+fn json_ld(person: &Value) -> Value {
+    let mut json_ld = json!({
+        "@context": "https://schema.org",
+        "@type": "Person",
+        "@id": format!("/elus/{}", person["uuid"].as_str().unwrap_or_default()),
+        "identifier": person["uuid"],
+        "name": person["name"],
+    });
+    if let Some(email) = person["email"].as_str() {
+        json_ld["email"] = json!(format!("mailto:{}", email));
+    }
+    if let Some(mandates) = person["mandates"].as_array().filter(|mandates| !mandates.is_empty()) {
+        json_ld["jobTitle"] = json!(mandates);
+    }
+    if person["office_address"].is_string() || person["latitude"].is_number() {
+        let mut place = json!({ "@type": "Place" });
+        if let Some(address) = person["office_address"].as_str() {
+            place["address"] = json!(address);
+        }
+        if let (Some(latitude), Some(longitude)) = (person["latitude"].as_f64(), person["longitude"].as_f64()) {
+            place["geo"] = json!({ "@type": "GeoCoordinates", "latitude": latitude, "longitude": longitude });
+        }
+        json_ld["workLocation"] = place;
+    }
+    json_ld
+}
+
+/// The body of the page below its title.
+fn details(person: &Value) -> String {
+    let mut html = String::new();
+    if let Some(mandates) = person["mandates"].as_array().filter(|mandates| !mandates.is_empty()) {
+        let items: Vec<String> = mandates.iter().filter_map(Value::as_str).map(|mandate| format!("<li>{}</li>", escape(mandate))).collect();
+        html.push_str(&format!("<ul class=\"mandates\">{}</ul>\n", items.join("")));
+    }
+
+    let mut fields = vec![];
+    if let Some(email) = person["email"].as_str() {
+        fields.push(("Courriel", format!("<a href=\"mailto:{0}\">{0}</a>", escape(email))));
+    }
+    if let Some(code) = person["commune_code"].as_str() {
+        fields.push(("Commune", format!("<a href=\"/communes/{0}\">{0}</a>", escape(code))));
+    }
+    if let Some(address) = person["office_address"].as_str() {
+        fields.push(("Adresse", escape(address)));
+    }
+    if !fields.is_empty() {
+        let rows: Vec<String> = fields.iter().map(|(label, value)| format!("<dt>{}</dt><dd>{}</dd>", label, value)).collect();
+        html.push_str(&format!("<dl>{}</dl>\n", rows.join("")));
+    }
+
+    if let Some(related) = person["related"].as_array().filter(|related| !related.is_empty()) {
+        let items: Vec<String> = related
+            .iter()
+            .map(|link| {
+                format!(
+                    "<li>{} <a href=\"/elus/{}\">{}</a></li>",
+                    escape(relation_label(link["relation"].as_str().unwrap_or_default())),
+                    escape(link["uuid"].as_str().unwrap_or_default()),
+                    escape(link["name"].as_str().unwrap_or_default())
+                )
+            })
+            .collect();
+        html.push_str(&format!("<ul class=\"related\">{}</ul>\n", items.join("")));
+    }
+    html
+}
+
+/// The profile page of a redacted `Person`, presented as the settings of
+/// their commune say.
+pub fn page(person: &Value, settings: Option<&Settings>) -> String {
+    // Keeps `</script>` in names from closing the JSON-LD early.
+    let json_ld = json_ld(person).to_string().replace("</", "<\\/");
+    render(
+        TEMPLATE,
+        &[
+            ("name", &escape(person["name"].as_str().unwrap_or_default())),
+            ("uuid", &escape(person["uuid"].as_str().unwrap_or_default())),
+            ("json_ld", &json_ld),
+            ("details", &details(person)),
+            ("lang", &escape(settings::lang(settings))),
+            ("footer", &settings.map(settings::footer).unwrap_or_default()),
+        ],
+    )
+}
+
+fn prefers_html(request: &Request<'_>) -> bool {
+    request.accept().is_some_and(|accept| accept.preferred().is_html())
+}
+
+/// An elu as the caller prefers them: JSON, or their profile page.
+pub struct Negotiated(pub Person);
+
+impl<'r> Responder<'r, 'static> for Negotiated {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let person = redact(&self.0, request)?;
+        let mut response = if prefers_html(request) {
+            let db = request.rocket().state::<DbConn>().ok_or(Status::InternalServerError)?;
+            let settings = match &self.0.commune_code {
+                Some(code) => settings::find(code, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?,
+                None => None,
+            };
+            RawHtml(page(&person, settings.as_ref())).respond_to(request)?
+        } else {
+            Json(person).respond_to(request)?
+        };
+        response.set_header(Header::new("Vary", "Accept"));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::http::{Accept, ContentType, Status};
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render("<h1>{{name}}</h1>{{missing}}", &[("name", "{{uuid}}"), ("uuid", "1")]), "<h1>{{uuid}}</h1>");
+        assert_eq!(render("{{name", &[("name", "Jean")]), "{{name");
+    }
+
+    #[test]
+    fn test_page() {
+        let person = json!({ "uuid": "abc", "name": "Jean </script><b>Dupont</b>", "mandates": ["Maire"], "office_address": "Place de l'Hôtel de Ville" });
+        let html = page(&person, None);
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"fr\">"));
+        assert!(!html.contains("<footer>"));
+        assert!(html.contains("<h1>Jean &lt;/script&gt;&lt;b&gt;Dupont&lt;/b&gt;</h1>"));
+        assert!(html.contains("\"name\":\"Jean <\\/script><b>Dupont<\\/b>\""));
+        assert!(html.contains("<ul class=\"mandates\"><li>Maire</li></ul>"));
+        assert!(html.contains("<dt>Adresse</dt><dd>Place de l&#39;Hôtel de Ville</dd>"));
+        assert!(!html.contains("Courriel"));
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/elus/jean.dupont@example.com").header(Accept::HTML).dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept"));
+        let html = response.into_string().unwrap();
+        assert!(html.contains("<h1>Jean Dupont</h1>"));
+        assert!(html.contains("<script type=\"application/ld+json\">{\"@context\":\"https://schema.org\""));
+
+        let response = client.get("/elus/jean.dupont@example.com").header(admin()).dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept"));
+        let browser = Header::new("Accept", "text/html,application/xhtml+xml,*/*;q=0.8");
+        let response = client.get("/elus/jean.dupont@example.com").header(browser).dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        assert_eq!(client.get("/elus/nobody@example.com").header(Accept::HTML).dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,113 @@
+//! Logging of the SQL queries, tagged with the ID of the request making
+//! them and timed, for finding what makes an endpoint slow without a
+//! debugger. On in the debug profile and wherever `log_queries` is set;
+//! queries are logged at the debug level, with this module as the target,
+//! and those of the workers are tagged `-`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+use diesel::connection::{Instrumentation, InstrumentationEvent};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::task;
+use rocket::{Data, Request, Response};
+
+use crate::request_id;
+
+/// The ID of the request each task is serving.
+fn requests() -> &'static Mutex<HashMap<task::Id, String>> {
+    static REQUESTS: OnceLock<Mutex<HashMap<task::Id, String>>> = OnceLock::new();
+    REQUESTS.get_or_init(Default::default)
+}
+
+fn current_request() -> Option<String> {
+    let id = task::try_id()?;
+    requests().lock().unwrap_or_else(PoisonError::into_inner).get(&id).cloned()
+}
+
+/// The line logged for a query.
+fn describe(request_id: &str, query: &dyn Display, elapsed: Duration, error: Option<&diesel::result::Error>) -> String {
+    match error {
+        Some(error) => format!("[{}] {} ({:.3?}, failed: {})", request_id, query, elapsed, error),
+        None => format!("[{}] {} ({:.3?})", request_id, query, elapsed),
+    }
+}
+
+/// Instrumentation of a connection, logging its queries.
+#[derive(Default)]
+struct QueryLogger {
+    started: Option<Instant>,
+}
+
+impl Instrumentation for QueryLogger {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        if !log::log_enabled!(log::Level::Debug) {
+            return;
+        }
+        match event {
+            InstrumentationEvent::StartQuery { .. } => self.started = Some(Instant::now()),
+            InstrumentationEvent::FinishQuery { query, error, .. } => {
+                let elapsed = self.started.take().map(|started| started.elapsed()).unwrap_or_default();
+                let request_id = current_request().unwrap_or_else(|| "-".to_string());
+                log::debug!("{}", describe(&request_id, &query, elapsed, error));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Logs the queries of the connection.
+pub fn instrument(connection: &mut SqliteConnection) {
+    connection.set_instrumentation(QueryLogger::default());
+}
+
+/// Fairing telling the queries which request they're made for.
+pub struct QueryLog;
+
+#[rocket::async_trait]
+impl Fairing for QueryLog {
+    fn info(&self) -> Info {
+        Info { name: "Query log", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if let Some(id) = task::try_id() {
+            requests().lock().unwrap_or_else(PoisonError::into_inner).insert(id, request_id::of(request).to_string());
+        }
+    }
+
+    async fn on_response<'r>(&self, _: &'r Request<'_>, _: &mut Response<'r>) {
+        if let Some(id) = task::try_id() {
+            requests().lock().unwrap_or_else(PoisonError::into_inner).remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_client, setup_test_db};
+    use rocket::http::Status;
+
+    #[test]
+    fn test_describe() {
+        let query = "SELECT 1";
+        assert_eq!(describe("proxy-42", &query, Duration::from_micros(1500), None), "[proxy-42] SELECT 1 (1.500ms)");
+        let error = diesel::result::Error::NotFound;
+        assert_eq!(describe("-", &query, Duration::ZERO, Some(&error)), "[-] SELECT 1 (0.000ns, failed: Record not found)");
+    }
+
+    #[test]
+    fn test_query_log() {
+        let client = build_client(|figment| figment.merge(("log_queries", true)), setup_test_db());
+        assert_eq!(client.get("/elus").dispatch().status(), Status::Ok);
+        let rows = diesel::select(diesel::dsl::sql::<diesel::sql_types::Integer>("1"));
+        let mut connection = setup_test_db();
+        instrument(&mut connection);
+        assert_eq!(rows.get_result::<i32>(&mut connection).unwrap(), 1);
+    }
+}
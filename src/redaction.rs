@@ -0,0 +1,245 @@
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::config::AppConfig;
+use crate::email::Email;
+use crate::mandate_types::{self, MandateTypes};
+use crate::sha256;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RedactionConfig {
+    /// Fields of persons left out of responses to callers without the
+    /// admin token.
+    pub public: Vec<String>,
+    /// Whether everyone, admins included, sees made-up emails instead of
+    /// the real ones, for demo instances serving a copy of production:
+    /// in responses, webhooks, NATS messages and exports alike. Keys and
+    /// lookups still take the real addresses.
+    pub demo: bool,
+    /// Key the made-up emails derive from, without which anyone may check
+    /// them against guessed addresses. Required with `demo`.
+    pub demo_key: Option<String>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig { public: vec!["email".to_string()], demo: false, demo_key: None }
+    }
+}
+
+impl RedactionConfig {
+    /// The address shown for `email` on demo instances: the same for the
+    /// same address, and distinct for distinct ones.
+    pub fn fake_email(&self, email: &str) -> String {
+        let key = self.demo_key.as_deref().unwrap_or_default();
+        let digest = sha256::hmac_sha256(key.as_bytes(), email.to_lowercase().as_bytes());
+        format!("elu-{}@example.invalid", &sha256::hex(&digest)[..20])
+    }
+
+    /// `text` as it leaves the instance: with its email addresses made up
+    /// on demo instances, wherever they are in it.
+    pub fn shown(&self, text: String) -> String {
+        if !self.demo {
+            return text;
+        }
+
+        let local = |byte: &u8| byte.is_ascii_alphanumeric() || b"._%+-".contains(byte);
+        let domain = |byte: &u8| byte.is_ascii_alphanumeric() || b".-".contains(byte);
+        let mut shown = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(at) = rest.find('@') {
+            let bytes = rest.as_bytes();
+            let start = bytes[..at].iter().rposition(|byte| !local(byte)).map_or(0, |i| i + 1);
+            let mut end = bytes[at + 1..].iter().position(|byte| !domain(byte)).map_or(bytes.len(), |i| at + 1 + i);
+            while end > at + 1 && bytes[end - 1] == b'.' {
+                end -= 1;
+            }
+            let address = &rest[start..end];
+            if start < at && Email::parse(address).is_ok() {
+                shown.push_str(&rest[..start]);
+                shown.push_str(&self.fake_email(address));
+                rest = &rest[end..];
+            } else {
+                shown.push_str(&rest[..=at]);
+                rest = &rest[at + 1..];
+            }
+        }
+        shown.push_str(rest);
+        shown
+    }
+}
+
+/// Response types holding persons, which know where the persons are in
+/// their JSON form.
+pub trait Redactable: Serialize {
+    /// The persons within `value`, the serialization of a `Self`.
+    fn persons(value: &mut Value) -> Vec<&mut Value>;
+}
+
+impl<T: Redactable> Redactable for Vec<T> {
+    fn persons(value: &mut Value) -> Vec<&mut Value> {
+        match value {
+            Value::Array(items) => items.iter_mut().flat_map(T::persons).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Removes the fields from a serialized person; leaving out `email` leaves
+/// out the other addresses of `emails` too.
+pub fn remove_fields(value: &mut Value, fields: &[String]) {
+    if let Value::Object(object) = value {
+        for field in fields {
+            object.remove(field);
+            if field == "email" {
+                object.remove("emails");
+            }
+        }
+    }
+}
+
+/// Request guard telling whether the caller sees the elus' emails, which
+/// searches then match on too.
+pub struct EmailsShown(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for EmailsShown {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let hidden = request.rocket().state::<AppConfig>().is_none_or(|config| config.redaction.public.iter().any(|field| field == "email"));
+        request::Outcome::Success(EmailsShown(auth::is_admin(request) || !hidden))
+    }
+}
+
+/// Language the mandates of a response are labelled in.
+pub struct ContentLanguage(pub String);
+
+/// JSON response shaped for the caller: administrators see everything,
+/// other callers don't see the fields configured in `redaction.public`.
+/// Mandates are labelled in the caller's language.
+pub struct Redacted<T>(pub T);
+
+/// The JSON form of `value` shaped for the caller, as `Redacted` responds
+/// with it.
+pub fn redact<T: Redactable>(value: &T, request: &Request<'_>) -> Result<Value, Status> {
+    let mut value = serde_json::to_value(value).map_err(|_| Status::InternalServerError)?;
+    let config = request.rocket().state::<AppConfig>().ok_or(Status::InternalServerError)?;
+    let mandate_types = request.rocket().state::<MandateTypes>().ok_or(Status::InternalServerError)?;
+    let redacted: &[String] = if auth::is_admin(request) { &[] } else { &config.redaction.public };
+    let languages = mandate_types::accepted_languages(request);
+    for person in T::persons(&mut value) {
+        remove_fields(person, redacted);
+        mandate_types.localize(person, &languages);
+    }
+    let language = mandate_types.language(&languages).to_string();
+    request.local_cache(|| ContentLanguage(language));
+
+    Ok(value)
+}
+
+impl<'r, T: Redactable> Responder<'r, 'static> for Redacted<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Json(redact(&self.0, request)?).respond_to(request)
+    }
+}
+
+/// Fairing making up the emails of textual responses, attached on demo
+/// instances. Streamed bodies, such as server-sent events, are left as
+/// they are.
+pub struct Demo;
+
+#[rocket::async_trait]
+impl Fairing for Demo {
+    fn info(&self) -> Info {
+        Info { name: "Demo emails", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(content_type) = response.content_type() else {
+            return;
+        };
+        let json = content_type.is_json() || content_type.sub().as_str().ends_with("+json");
+        if !json && content_type.top() != "text" || response.body().preset_size().is_none() {
+            return;
+        }
+        let Some(config) = request.rocket().state::<AppConfig>() else {
+            return;
+        };
+
+        let Ok(body) = response.body_mut().to_string().await else {
+            return;
+        };
+        let body = config.redaction.shown(body);
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+
+    fn demo() -> RedactionConfig {
+        RedactionConfig { demo: true, demo_key: Some("demo".to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn test_fake_email() {
+        let config = demo();
+        let fake = config.fake_email("jean.dupont@example.com");
+        assert!(fake.starts_with("elu-") && fake.ends_with("@example.invalid"));
+        assert_eq!(config.fake_email("Jean.Dupont@example.com"), fake);
+        assert_ne!(config.fake_email("marie.martin@example.com"), fake);
+        let rekeyed = RedactionConfig { demo_key: Some("other".to_string()), ..config };
+        assert_ne!(rekeyed.fake_email("jean.dupont@example.com"), fake);
+    }
+
+    #[test]
+    fn test_shown() {
+        let config = demo();
+        let fake = config.fake_email("jean.dupont@example.com");
+        assert_eq!(
+            config.shown("{\"email\":\"jean.dupont@example.com\"},jean.dupont@example.com.".to_string()),
+            format!("{{\"email\":\"{}\"}},{}.", fake, fake)
+        );
+        assert_eq!(config.shown("Été @ 10h, a@b".to_string()), "Été @ 10h, a@b");
+        let real = RedactionConfig { demo: false, ..config };
+        assert_eq!(real.shown("jean.dupont@example.com".to_string()), "jean.dupont@example.com");
+    }
+
+    #[test]
+    fn test_demo_responses() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("redaction.demo", true)).merge(("redaction.demo_key", "demo")), connection);
+        let fake = demo().fake_email("jean.dupont@example.com");
+
+        let person: Value = client.get("/elus/jean.dupont@example.com").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!((&person["email"], &person["emails"][0]), (&Value::from(fake.clone()), &Value::from(fake.clone())));
+        let persons: Vec<Value> = client.get("/elus").dispatch().into_json().unwrap();
+        assert!(persons.iter().all(|person| person["email"].as_str().unwrap().ends_with("@example.invalid")));
+
+        let update = serde_json::json!({ "name": "Jean Dupont", "email": "jean.dupont@example.com", "mandates": ["Maire"] });
+        let updated: Value = client.put("/elus/jean.dupont@example.com").header(admin()).json(&update).dispatch().into_json().unwrap();
+        assert_eq!(updated["email"], fake);
+        let events = client.get("/events").header(admin()).dispatch().into_string().unwrap();
+        assert!(events.contains(&fake) && !events.contains("jean.dupont@example.com"));
+        let csv = client.get("/elus/export.csv").header(admin()).dispatch().into_string().unwrap();
+        assert!(csv.contains(&fake) && !csv.contains("@example.com"));
+    }
+
+    #[test]
+    #[should_panic(expected = "redaction.demo_key")]
+    fn test_demo_needs_key() {
+        build_client(|figment| figment.merge(("redaction.demo", true)), setup_test_db());
+    }
+}
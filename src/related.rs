@@ -0,0 +1,192 @@
+//! Relations between elus, from which the organization chart of a council
+//! can be drawn: who is the substitute ("suppléant") of whom, who are the
+//! deputies ("adjoints") of a mayor. A relation is stored once, from the
+//! elu holding the role, and listed from both ends; from the other end it
+//! reads as its inverse, `has_substitute` for `substitute_of`.
+
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::db;
+use crate::person_name::PersonName;
+use crate::redaction::{Redactable, Redacted};
+use crate::repository::{PersonKey, PersonRepository};
+use crate::schema::relations;
+use crate::scraping::Probe;
+use crate::visibility::Visible;
+use crate::{DbConn, Person};
+
+/// Kinds of relations, and how they read from the related elu.
+const KINDS: &[(&str, &str)] = &[("substitute_of", "has_substitute"), ("deputy_of", "has_deputy")];
+
+/// The stored kind of `relation`, and whether it reads from the related
+/// elu, so that the two elus are to be swapped.
+fn resolve(relation: &str) -> Option<(&'static str, bool)> {
+    KINDS.iter().find_map(|(kind, inverse)| {
+        if relation == *kind {
+            Some((*kind, false))
+        } else if relation == *inverse {
+            Some((*kind, true))
+        } else {
+            None
+        }
+    })
+}
+
+fn inverse(kind: &str) -> &'static str {
+    KINDS.iter().find(|(known, _)| *known == kind).map(|(_, inverse)| *inverse).unwrap_or("related_to")
+}
+
+/// A relation as listed in the payload of an elu.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Link {
+    pub relation: String,
+    pub uuid: String,
+    pub name: PersonName,
+}
+
+/// The relations of the elu, with the other elus of each.
+pub fn relations_of(elu_id: i32, db: &DbConn, repository: &dyn PersonRepository) -> Result<Vec<(String, db::Person)>, Status> {
+    let rows: Vec<(i32, String, i32)> = relations::table
+        .filter(relations::elu_id.eq(elu_id).or(relations::related_id.eq(elu_id)))
+        .order((relations::kind, relations::elu_id, relations::related_id))
+        .select((relations::elu_id, relations::kind, relations::related_id))
+        .load(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut found = Vec::with_capacity(rows.len());
+    for (holder, kind, related) in rows {
+        let (relation, other) = if holder == elu_id { (kind, related) } else { (inverse(&kind).to_string(), holder) };
+        match repository.get(other) {
+            Ok(person) => found.push((relation, person)),
+            Err(status) if status == Status::NotFound => {}
+            Err(status) => return Err(status),
+        }
+    }
+    Ok(found)
+}
+
+/// The relations of the elu, as listed in their payload.
+pub fn links(elu_id: i32, db: &DbConn, repository: &dyn PersonRepository) -> Result<Vec<Link>, Status> {
+    let relations = relations_of(elu_id, db, repository)?;
+    Ok(relations.into_iter().map(|(relation, person)| Link { relation, uuid: person.uuid, name: person.name }).collect())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Related {
+    relation: String,
+    elu: Person,
+}
+
+impl Redactable for Related {
+    fn persons(value: &mut Value) -> Vec<&mut Value> {
+        vec![&mut value["elu"]]
+    }
+}
+
+/// Lists the elus related to the elu, as `GET /elus/<key>` would show them.
+#[get("/elus/<key>/related")]
+fn list_related(key: &str, _probe: Probe, db: &State<DbConn>, repository: Visible) -> Result<Redacted<Vec<Related>>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let relations = relations_of(elu.id, db, repository.as_ref())?;
+
+    Ok(Redacted(relations.into_iter().map(|(relation, person)| Related { relation, elu: Person::from(person) }).collect()))
+}
+
+/// The stored form of "`key` is the `relation` of `other`".
+fn edge(key: &str, relation: &str, other: &str, repository: &dyn PersonRepository) -> Result<(i32, &'static str, i32), Status> {
+    let (kind, swapped) = resolve(relation).ok_or(Status::NotFound)?;
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let other = repository.find(&PersonKey::parse(other)?)?;
+    if elu.id == other.id {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    Ok(if swapped { (other.id, kind, elu.id) } else { (elu.id, kind, other.id) })
+}
+
+/// Records that the elu is the `relation` of the other elu, for instance
+/// `PUT /elus/<deputy>/related/deputy_of/<mayor>`.
+#[put("/elus/<key>/related/<relation>/<other>")]
+fn add_related(key: &str, relation: &str, other: &str, _admin: Admin, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let (elu_id, kind, related_id) = edge(key, relation, other, repository.as_ref())?;
+    diesel::insert_or_ignore_into(relations::table)
+        .values((relations::elu_id.eq(elu_id), relations::kind.eq(kind), relations::related_id.eq(related_id)))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+#[delete("/elus/<key>/related/<relation>/<other>")]
+fn remove_related(key: &str, relation: &str, other: &str, _admin: Admin, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let (elu_id, kind, related_id) = edge(key, relation, other, repository.as_ref())?;
+    let deleted = diesel::delete(relations::table.find((elu_id, kind, related_id)))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+    Ok(Status::NoContent)
+}
+
+/// Deletes every relation of an elu, before the elu itself is deleted.
+pub fn delete_all(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(relations::table.filter(relations::elu_id.eq(elu_id).or(relations::related_id.eq(elu_id)))).execute(connection)?;
+    Ok(())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_related, add_related, remove_related]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("deputy_of"), Some(("deputy_of", false)));
+        assert_eq!(resolve("has_substitute"), Some(("substitute_of", true)));
+        assert_eq!(resolve("boss_of"), None);
+    }
+
+    #[test]
+    fn test_related() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let uri = "/elus/marie.martin@example.com/related/deputy_of/jean.dupont@example.com";
+        assert_eq!(client.put(uri).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.put(uri).header(admin()).dispatch().status(), Status::NoContent);
+        let uri = "/elus/pierre.durand@example.com/related/has_substitute/jean.dupont@example.com";
+        assert_eq!(client.put(uri).header(admin()).dispatch().status(), Status::NoContent);
+        let uri = "/elus/jean.dupont@example.com/related/deputy_of/jean.dupont@example.com";
+        assert_eq!(client.put(uri).header(admin()).dispatch().status(), Status::UnprocessableEntity);
+
+        let related: Vec<Related> = client.get("/elus/jean.dupont@example.com/related").dispatch().into_json().unwrap();
+        let relations: Vec<(&str, &str)> = related.iter().map(|related| (related.relation.as_str(), related.elu.name.as_str())).collect();
+        assert_eq!(relations, [("has_deputy", "Marie Martin"), ("substitute_of", "Pierre Durand")]);
+        let person: Person = client.get("/elus/pierre.durand@example.com").dispatch().into_json().unwrap();
+        assert_eq!((person.related[0].relation.as_str(), person.related[0].name.as_str()), ("has_substitute", "Jean Dupont"));
+
+        let uri = "/elus/jean.dupont@example.com/related/has_deputy/marie.martin@example.com";
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NotFound);
+        assert_eq!(client.delete("/elus/pierre.durand@example.com").header(admin()).dispatch().status(), Status::NoContent);
+        let related: Vec<Related> = client.get("/elus/jean.dupont@example.com/related").dispatch().into_json().unwrap();
+        assert!(related.is_empty());
+    }
+}
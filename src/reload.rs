@@ -0,0 +1,103 @@
+//! Configuration changes applied without a restart: `POST /admin/reload`
+//! reads `Rocket.toml` and the environment again, then resets the feature
+//! flags to their configured values and applies the webhook retry policy
+//! and rotation grace. Everything else, the database and backend first,
+//! keeps its value until the next restart, as do polling intervals of the
+//! background workers.
+
+use std::sync::{Arc, RwLock};
+
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::config::AppConfig;
+use crate::flags::Flags;
+use crate::problem::Problem;
+use crate::secrets;
+use crate::webhooks::WebhookConfig;
+
+/// Settings which may change while running, shared with the workers using
+/// them.
+#[derive(Debug)]
+pub struct Live<T>(Arc<RwLock<T>>);
+
+impl<T> Clone for Live<T> {
+    fn clone(&self) -> Self {
+        Live(self.0.clone())
+    }
+}
+
+impl<T: Clone> Live<T> {
+    pub fn new(value: T) -> Self {
+        Live(Arc::new(RwLock::new(value)))
+    }
+
+    pub fn get(&self) -> T {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, value: T) {
+        *self.0.write().unwrap() = value;
+    }
+}
+
+/// Where the configuration is read from on reload.
+fn figment() -> Result<Figment, String> {
+    secrets::merge(rocket::Config::figment())
+}
+
+fn apply(config: AppConfig, flags: &Flags, webhooks: &Live<WebhookConfig>) {
+    flags.reset(&config.flags);
+    webhooks.set(config.webhooks);
+}
+
+#[post("/admin/reload")]
+fn reload(_admin: Admin, flags: &State<Flags>, webhooks: &State<Live<WebhookConfig>>) -> Result<Status, Problem> {
+    let config: AppConfig = figment()
+        .and_then(|figment| figment.extract().map_err(secrets::explain))
+        .map_err(|e| Problem { detail: Some(e), ..Problem::new(Status::UnprocessableEntity) })?;
+    apply(config, flags, webhooks);
+    log::info!("Configuration reloaded");
+
+    Ok(Status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![reload]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, setup_test_db};
+    use rocket::serde::json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_apply() {
+        let flags = Flags::new(&BTreeMap::new());
+        let webhooks = Live::new(WebhookConfig::default());
+        let config: AppConfig = Figment::new()
+            .merge(("flags.import", false))
+            .merge(("webhooks.max_attempts", 2))
+            .extract()
+            .unwrap();
+
+        apply(config, &flags, &webhooks.clone());
+        assert!(!flags.is_enabled("import"));
+        assert_eq!(webhooks.get().max_attempts, 2);
+    }
+
+    #[test]
+    fn test_reload() {
+        let client = client(setup_test_db());
+        client.put("/admin/flags").header(admin()).json(&json!({ "import": false })).dispatch();
+
+        assert_eq!(client.post("/admin/reload").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/admin/reload").header(admin()).dispatch().status(), Status::NoContent);
+        // Back to the configured value.
+        assert_ne!(client.post("/communes/import").header(admin()).body("").dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rocket::http::Status;
+use rocket::request::FromParam;
+use rocket::serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{self, NewPerson, Person};
+use crate::deliverability::EmailStatus;
+use crate::email::Email;
+use crate::filter;
+use crate::pagination::{SortKey, SortSpec};
+use crate::person_name;
+use crate::phonetic;
+use crate::timestamp;
+use crate::visibility::Visibility;
+
+/// Folds case and French diacritics of the name normalized as names are
+/// stored, so that searching for "helene  " finds "Hélène". The
+/// `search_name` column holds the result for each person.
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in person_name::normalize(name).chars().flat_map(char::to_lowercase) {
+        match c {
+            'à' | 'â' | 'ä' => normalized.push('a'),
+            'ç' => normalized.push('c'),
+            'é' | 'è' | 'ê' | 'ë' => normalized.push('e'),
+            'î' | 'ï' => normalized.push('i'),
+            'ô' | 'ö' => normalized.push('o'),
+            'ù' | 'û' | 'ü' => normalized.push('u'),
+            'ÿ' => normalized.push('y'),
+            'œ' => normalized.push_str("oe"),
+            'æ' => normalized.push_str("ae"),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Criteria for `PersonRepository::search`; unset criteria match everyone.
+#[derive(Debug, Default, Clone)]
+pub struct PersonFilter {
+    /// Substring of the name, ignoring case and accents.
+    pub name: Option<String>,
+    /// Phonetic key, as `phonetic::key` gives, the name's key must contain.
+    pub phonetic: Option<String>,
+    pub mandate: Option<String>,
+    pub commune_code: Option<String>,
+    pub email_status: Option<EmailStatus>,
+    /// Restricts to the persons with these ids, such as those having a tag.
+    pub ids: Option<Vec<i32>>,
+    /// Restricts to persons located within the radius, nearest first.
+    pub near: Option<Near>,
+    /// Restricts to persons at most this visibility, which callers seeing
+    /// it may see.
+    pub audience: Option<Visibility>,
+    /// Restricts to persons matching the expression of `?filter=`.
+    pub expression: Option<filter::Expr>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Near {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl Near {
+    /// Distance to the person's office, if it's located within the radius.
+    pub fn distance_km(&self, person: &Person) -> Option<f64> {
+        let distance = db::haversine(person.latitude?, person.longitude?, self.latitude, self.longitude);
+        (distance <= self.radius_km).then_some(distance)
+    }
+}
+
+impl PersonFilter {
+    pub fn matches(&self, person: &Person) -> bool {
+        self.name.as_ref().is_none_or(|name| normalize_name(&person.name).contains(&normalize_name(name)))
+            && self.phonetic.as_ref().is_none_or(|key| phonetic::key(&person.name).contains(key.as_str()))
+            && self.mandate.as_ref().is_none_or(|mandate| person.mandates.contains(mandate))
+            && self.commune_code.as_ref().is_none_or(|code| person.commune_code.as_ref() == Some(code))
+            && self.email_status.is_none_or(|status| person.email_status == status.as_str())
+            && self.ids.as_ref().is_none_or(|ids| ids.contains(&person.id))
+            && self.near.is_none_or(|near| near.distance_km(person).is_some())
+            && self.audience.is_none_or(|audience| person.visibility <= audience)
+            && self.expression.as_ref().is_none_or(|expression| expression.matches(person))
+    }
+}
+
+/// A slice of search results, which are then ordered by `sort`, by name
+/// unless it says otherwise, and id so that it's stable across requests.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    /// Name and id of the last person of the previous page, when paging
+    /// by cursor, which only follows the order by name.
+    pub after: Option<(String, i32)>,
+    pub offset: i64,
+    pub limit: i64,
+    pub sort: SortSpec<PersonSort>,
+}
+
+/// What `GET /elus?sort=` may sort persons by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersonSort {
+    Name,
+    UpdatedAt,
+}
+
+impl SortKey for PersonSort {
+    const KEYS: &'static [(&'static str, Self)] = &[("name", PersonSort::Name), ("updated_at", PersonSort::UpdatedAt)];
+}
+
+impl SortSpec<PersonSort> {
+    /// Whether this is the order by name, the one cursors follow.
+    pub fn is_by_name(&self) -> bool {
+        self.key.is_none_or(|key| key == PersonSort::Name) && !self.descending
+    }
+
+    pub fn compare(&self, a: &Person, b: &Person) -> std::cmp::Ordering {
+        let ordering = match self.key.unwrap_or(PersonSort::Name) {
+            PersonSort::Name => (&a.name, a.id).cmp(&(&b.name, b.id)),
+            PersonSort::UpdatedAt => (a.updated_at, a.id).cmp(&(b.updated_at, b.id)),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl Page {
+    fn follows(&self, person: &Person) -> bool {
+        self.after.as_ref().is_none_or(|(name, id)| (person.name.as_str(), person.id) > (name.as_str(), *id))
+    }
+}
+
+/// Ways of designating a person.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PersonKey {
+    Id(i32),
+    Uuid(String),
+    Email(Email),
+}
+
+impl PersonKey {
+    /// Tells the kinds of keys apart: numeric ids and UUIDs can't be email
+    /// addresses. Keys of none of the kinds designate no one.
+    pub fn parse(key: &str) -> Result<PersonKey, Status> {
+        if let Ok(id) = key.parse() {
+            Ok(PersonKey::Id(id))
+        } else if let Ok(uuid) = Uuid::parse_str(key) {
+            Ok(PersonKey::Uuid(uuid.to_string()))
+        } else {
+            Email::from_param(key).map(PersonKey::Email).map_err(|_| Status::NotFound)
+        }
+    }
+
+    fn matches(&self, person: &Person) -> bool {
+        match self {
+            PersonKey::Id(id) => person.id == *id,
+            PersonKey::Uuid(uuid) => person.uuid == *uuid,
+            PersonKey::Email(email) => person.has_email(email),
+        }
+    }
+}
+
+/// Storage of the elus, so handlers don't depend on a particular database.
+/// Errors are reported as the HTTP status the handlers answer with:
+/// `NotFound` for unknown emails, and `Conflict` when a name or email is
+/// already taken by someone else.
+pub trait PersonRepository: Send + Sync {
+    fn list(&self) -> Result<Vec<Person>, Status>;
+    fn find(&self, key: &PersonKey) -> Result<Person, Status>;
+    /// The person with the database id, for ids read from other tables
+    /// rather than given by callers.
+    fn get(&self, id: i32) -> Result<Person, Status> {
+        self.find(&PersonKey::Id(id))
+    }
+    /// The person who used to have the email address.
+    fn find_alias(&self, email: &Email) -> Result<Person, Status>;
+    /// The persons having one of the emails, in no particular order;
+    /// unknown emails are skipped.
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status>;
+    fn create(&self, person: NewPerson) -> Result<Person, Status>;
+    /// Replaces the person's data; changing the email address resets its
+    /// deliverability status.
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status>;
+    /// Replaces the data of several persons, all of them or none.
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status>;
+    fn delete(&self, email: &Email) -> Result<Person, Status>;
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status>;
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status>;
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status>;
+    /// The visibility of the most restricted persons the repository hands
+    /// out; persons from elsewhere, such as the full-text index, are
+    /// checked against it.
+    fn audience(&self) -> Visibility {
+        Visibility::Hidden
+    }
+}
+
+/// Where persons are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Sqlite,
+    /// Kept in memory and lost on shutdown, for demos and tests.
+    Memory,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("unknown backend {:?}, expected sqlite or memory", s)),
+        }
+    }
+}
+
+/// Keys callers may designate persons with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum PublicIds {
+    /// Database ids as well as UUIDs and emails.
+    #[default]
+    Sequential,
+    /// UUIDs and emails only, so that nobody can walk the directory by
+    /// counting ids.
+    Opaque,
+}
+
+/// Repository answering lookups by database id with `NotFound`, for
+/// `PublicIds::Opaque`; ids read from other tables still go through `get`.
+pub struct OpaqueIdRepository {
+    inner: Arc<dyn PersonRepository>,
+}
+
+impl OpaqueIdRepository {
+    pub fn new(inner: Arc<dyn PersonRepository>) -> Self {
+        OpaqueIdRepository { inner }
+    }
+}
+
+impl PersonRepository for OpaqueIdRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        self.inner.list()
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        match key {
+            PersonKey::Id(_) => Err(Status::NotFound),
+            key => self.inner.find(key),
+        }
+    }
+
+    fn get(&self, id: i32) -> Result<Person, Status> {
+        self.inner.get(id)
+    }
+
+    fn find_alias(&self, email: &Email) -> Result<Person, Status> {
+        self.inner.find_alias(email)
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        self.inner.get_many(emails)
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        self.inner.create(person)
+    }
+
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status> {
+        self.inner.update(email, person)
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        self.inner.update_many(updates)
+    }
+
+    fn delete(&self, email: &Email) -> Result<Person, Status> {
+        self.inner.delete(email)
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        self.inner.search(filter)
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        self.inner.search_page(filter, page)
+    }
+
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status> {
+        self.inner.set_email_status(id, status)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRepository {
+    persons: Mutex<Vec<Person>>,
+    /// Former emails, by the id of the person who had them.
+    aliases: Mutex<HashMap<Email, i32>>,
+    last_id: AtomicI32,
+}
+
+impl MemoryRepository {
+    /// A repository holding the given persons, such as a past state of
+    /// another one.
+    pub fn with_persons(persons: Vec<Person>) -> Self {
+        let last_id = persons.iter().map(|person| person.id).max().unwrap_or(0);
+        MemoryRepository { persons: Mutex::new(persons), aliases: Mutex::default(), last_id: AtomicI32::new(last_id) }
+    }
+}
+
+fn is_taken(persons: &[Person], person: &NewPerson, except: Option<i32>) -> bool {
+    persons
+        .iter()
+        .filter(|other| Some(other.id) != except)
+        .any(|other| person.addresses().any(|address| other.has_email(address)) || other.name == person.name)
+}
+
+impl PersonRepository for MemoryRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        Ok(self.persons.lock().unwrap().clone())
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        let persons = self.persons.lock().unwrap();
+
+        persons.iter().find(|person| key.matches(person)).cloned().ok_or(Status::NotFound)
+    }
+
+    fn find_alias(&self, email: &Email) -> Result<Person, Status> {
+        let persons = self.persons.lock().unwrap();
+        let id = *self.aliases.lock().unwrap().get(email).ok_or(Status::NotFound)?;
+
+        persons.iter().find(|person| person.id == id).cloned().ok_or(Status::NotFound)
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        let persons = self.persons.lock().unwrap();
+
+        Ok(persons.iter().filter(|person| emails.iter().any(|email| person.has_email(email))).cloned().collect())
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        let mut persons = self.persons.lock().unwrap();
+        if is_taken(&persons, &person, None) {
+            return Err(Status::Conflict);
+        }
+
+        let created = Person {
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            uuid: Uuid::new_v4().to_string(),
+            name: person.name,
+            emails: db::other_emails(&person.email, &person.emails),
+            email: person.email,
+            mandates: person.mandates,
+            commune_code: person.commune_code,
+            office_address: person.office_address,
+            latitude: person.latitude,
+            longitude: person.longitude,
+            may_contact_by_email: person.may_contact_by_email,
+            consent_date: person.consent_date,
+            consent_source: person.consent_source,
+            visibility: person.visibility,
+            custom: person.custom,
+            email_status: EmailStatus::Unchecked.as_str().to_string(),
+            updated_at: timestamp::now(),
+        };
+        let mut aliases = self.aliases.lock().unwrap();
+        for address in std::iter::once(&created.email).chain(&created.emails) {
+            aliases.remove(address);
+        }
+        persons.push(created.clone());
+
+        Ok(created)
+    }
+
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status> {
+        let mut persons = self.persons.lock().unwrap();
+        let index = persons.iter().position(|current| current.has_email(email)).ok_or(Status::NotFound)?;
+        let current = &persons[index];
+        if is_taken(&persons, &person, Some(current.id)) {
+            return Err(Status::Conflict);
+        }
+
+        let email_status = if person.email == current.email { current.email_status.clone() } else { EmailStatus::Unchecked.as_str().to_string() };
+        let updated = Person {
+            id: current.id,
+            uuid: current.uuid.clone(),
+            name: person.name,
+            emails: db::other_emails(&person.email, &person.emails),
+            email: person.email,
+            mandates: person.mandates,
+            commune_code: person.commune_code,
+            office_address: person.office_address,
+            latitude: person.latitude,
+            longitude: person.longitude,
+            may_contact_by_email: person.may_contact_by_email,
+            consent_date: person.consent_date,
+            consent_source: person.consent_source,
+            visibility: person.visibility,
+            custom: person.custom,
+            email_status,
+            updated_at: timestamp::now(),
+        };
+        let mut aliases = self.aliases.lock().unwrap();
+        for address in std::iter::once(&updated.email).chain(&updated.emails) {
+            aliases.remove(address);
+        }
+        if !updated.has_email(&persons[index].email) {
+            aliases.insert(persons[index].email.clone(), updated.id);
+        }
+        persons[index] = updated.clone();
+
+        Ok(updated)
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        let persons = self.persons.lock().unwrap().clone();
+        let aliases = self.aliases.lock().unwrap().clone();
+        let updated: Result<Vec<_>, _> = updates.into_iter().map(|(email, person)| self.update(&email, person)).collect();
+        if updated.is_err() {
+            *self.persons.lock().unwrap() = persons;
+            *self.aliases.lock().unwrap() = aliases;
+        }
+        updated
+    }
+
+    fn delete(&self, email: &Email) -> Result<Person, Status> {
+        let mut persons = self.persons.lock().unwrap();
+        let index = persons.iter().position(|person| person.has_email(email)).ok_or(Status::NotFound)?;
+        let deleted = persons.remove(index);
+        self.aliases.lock().unwrap().retain(|_, id| *id != deleted.id);
+
+        Ok(deleted)
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        let persons = self.persons.lock().unwrap();
+        let mut found: Vec<Person> = persons.iter().filter(|person| filter.matches(person)).cloned().collect();
+        if let Some(near) = filter.near {
+            found.sort_by(|a, b| near.distance_km(a).partial_cmp(&near.distance_km(b)).unwrap());
+        }
+
+        Ok(found)
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        let persons = self.persons.lock().unwrap();
+        let mut found: Vec<&Person> = persons.iter().filter(|person| filter.matches(person) && page.follows(person)).collect();
+        found.sort_by(|a, b| page.sort.compare(a, b));
+
+        Ok(found.into_iter().skip(page.offset as usize).take(page.limit as usize).cloned().collect())
+    }
+
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status> {
+        let mut persons = self.persons.lock().unwrap();
+        if let Some(person) = persons.iter_mut().find(|person| person.id == id) {
+            person.email_status = status.as_str().to_string();
+            person.updated_at = timestamp::now();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("Hélène LŒUVRE"), "helene loeuvre");
+        assert_eq!(normalize_name("ÉLODIE Noël-Çelik"), "elodie noel-celik");
+        assert_eq!(normalize_name(" He\u{301}le\u{300}ne  Lœuvre"), "helene loeuvre");
+    }
+
+    #[test]
+    fn test_memory_repository() {
+        let repository = MemoryRepository::default();
+        let new_person = |name: &str, email: &str, mandates: &[&str], coordinates: Option<(f64, f64)>| NewPerson {
+            name: name.parse().unwrap(),
+            email: email.parse().unwrap(),
+            mandates: mandates.iter().map(|mandate| mandate.to_string()).collect(),
+            latitude: coordinates.map(|(lat, _)| lat),
+            longitude: coordinates.map(|(_, lon)| lon),
+            ..Default::default()
+        };
+
+        let jean = repository.create(new_person("Jean Dupont", "jean@example.com", &["Maire"], Some((48.8566, 2.3522)))).unwrap();
+        repository.create(new_person("Pierre Durand", "pierre@example.com", &["Sénateur"], Some((45.7676, 4.8361)))).unwrap();
+        assert_eq!(repository.create(new_person("Jean Dupont", "other@example.com", &[], None)).unwrap_err(), Status::Conflict);
+
+        let near = PersonFilter {
+            near: Some(Near { latitude: 45.76, longitude: 4.83, radius_km: 500.0 }),
+            ..Default::default()
+        };
+        let found: Vec<String> = repository.search(&near).unwrap().into_iter().map(|person| person.name.to_string()).collect();
+        assert_eq!(found, vec!["Pierre Durand", "Jean Dupont"]);
+        let accented = PersonFilter { name: Some("PIERRE".to_string()), ..Default::default() };
+        assert_eq!(repository.search(&accented).unwrap().len(), 1);
+        let mayors = PersonFilter { mandate: Some("Maire".to_string()), ..Default::default() };
+        assert_eq!(repository.search(&mayors).unwrap().len(), 1);
+
+        let first = repository.search_page(&PersonFilter::default(), &Page { limit: 1, ..Default::default() }).unwrap();
+        assert_eq!(first[0].name, "Jean Dupont");
+        let after = Page { after: Some((first[0].name.to_string(), first[0].id)), limit: 1, ..Default::default() };
+        assert_eq!(repository.search_page(&PersonFilter::default(), &after).unwrap()[0].name, "Pierre Durand");
+
+        repository.set_email_status(jean.id, EmailStatus::Deliverable).unwrap();
+        let moved = repository.update(&"jean@example.com".parse().unwrap(), new_person("Jean Dupont", "jean@mairie.example", &["Maire"], None)).unwrap();
+        assert_eq!((moved.id, moved.email_status.as_str()), (jean.id, "unchecked"));
+        assert_eq!(repository.find_alias(&"jean@example.com".parse().unwrap()).unwrap().id, jean.id);
+        // Someone else taking the former address ends the alias.
+        repository.create(new_person("Jeanne Dupont", "jean@example.com", &[], None)).unwrap();
+        assert_eq!(repository.find_alias(&"jean@example.com".parse().unwrap()).unwrap_err(), Status::NotFound);
+
+        assert_eq!(repository.delete(&"jean@mairie.example".parse().unwrap()).unwrap().id, jean.id);
+        assert_eq!(repository.find(&PersonKey::Email("jean@mairie.example".parse().unwrap())).unwrap_err(), Status::NotFound);
+        assert_eq!(repository.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_opaque_ids() {
+        let inner = Arc::new(MemoryRepository::default());
+        let new_person = NewPerson { name: "Jean Dupont".parse().unwrap(), email: "jean@example.com".parse().unwrap(), ..Default::default() };
+        let jean = inner.create(new_person).unwrap();
+        let repository = OpaqueIdRepository::new(inner);
+
+        assert_eq!(repository.find(&PersonKey::Id(jean.id)).unwrap_err(), Status::NotFound);
+        assert_eq!(repository.find(&PersonKey::Uuid(jean.uuid.clone())).unwrap().id, jean.id);
+        assert_eq!(repository.get(jean.id).unwrap().uuid, jean.uuid);
+    }
+}
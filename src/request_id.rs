@@ -0,0 +1,62 @@
+//! Request IDs, to match what a client got with the logs and error
+//! reports. The ID is the `X-Request-Id` header set by a reverse proxy, if
+//! any, or made up, and is sent back in the response's `X-Request-Id`.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::sha256::hex;
+
+pub const HEADER: &str = "X-Request-Id";
+
+struct RequestId(String);
+
+/// Whether a client-provided ID can be passed on as is.
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// The ID of the request.
+pub fn of<'r>(request: &'r Request<'_>) -> &'r str {
+    let RequestId(id) = request.local_cache(|| {
+        let id = request.headers().get_one(HEADER).filter(|id| is_acceptable(id));
+        RequestId(id.map(str::to_string).unwrap_or_else(|| hex(&rand::random::<[u8; 16]>())))
+    });
+    id
+}
+
+/// Fairing sending the request ID back.
+pub struct RequestIds;
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info { name: "Request IDs", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new(HEADER, of(request).to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, setup_test_db};
+
+    #[test]
+    fn test_request_ids() {
+        let client = client(setup_test_db());
+
+        let response = client.get("/version").dispatch();
+        let id = response.headers().get_one(HEADER).unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        assert_ne!(client.get("/version").dispatch().headers().get_one(HEADER), Some(id.as_str()));
+
+        let response = client.get("/version").header(Header::new(HEADER, "proxy-42")).dispatch();
+        assert_eq!(response.headers().get_one(HEADER), Some("proxy-42"));
+        let response = client.get("/version").header(Header::new(HEADER, "not acceptable")).dispatch();
+        assert_eq!(response.headers().get_one(HEADER).map(str::len), Some(32));
+    }
+}
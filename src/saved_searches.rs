@@ -0,0 +1,221 @@
+//! Saved searches: dashboard users keep the criteria of `GET /elus` under
+//! a name, as its query string such as `mandate=maire&tag=budget`, and get
+//! their live results from `GET /searches/<id>/results`, so that dashboard
+//! widgets don't have to rebuild the filter themselves. Each user only
+//! sees their own searches.
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::criteria::Criteria;
+use crate::mandate_types::MandateTypes;
+use crate::pagination::{Pagination, SortSpec};
+use crate::problem::Problem;
+use crate::redaction::Redacted;
+use crate::repository::PersonSort;
+use crate::schema::saved_searches;
+use crate::sessions::Session;
+use crate::visibility::Visible;
+use crate::{db, timestamp, DbConn, Listing};
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = saved_searches)]
+#[serde(crate = "rocket::serde")]
+pub struct SavedSearch {
+    pub id: i32,
+    pub name: String,
+    /// The query string of `GET /elus` selecting the elus.
+    pub query: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub updated_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewSearch {
+    pub name: String,
+    pub query: String,
+}
+
+impl NewSearch {
+    /// The trimmed name and the query without its leading `?`, once the
+    /// criteria it holds are known to be valid.
+    fn validate(&self) -> Result<(&str, &str), Status> {
+        let name = self.name.trim();
+        let query = self.query.strip_prefix('?').unwrap_or(&self.query);
+        if name.is_empty() {
+            return Err(Status::UnprocessableEntity);
+        }
+        Criteria::parse(query)?;
+        Ok((name, query))
+    }
+}
+
+fn conflict_or_internal(error: diesel::result::Error) -> Status {
+    match error {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+        _ => Status::InternalServerError,
+    }
+}
+
+/// The user's search of that id; someone else's is not found either.
+fn find(id: i32, session: &Session, connection: &mut SqliteConnection) -> Result<SavedSearch, Status> {
+    saved_searches::table
+        .filter(saved_searches::id.eq(id).and(saved_searches::user_id.eq(session.user.id)))
+        .select(SavedSearch::as_select())
+        .first(connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)
+}
+
+#[get("/searches")]
+fn list_searches(session: Session, db: &State<DbConn>) -> Result<Json<Vec<SavedSearch>>, Status> {
+    saved_searches::table
+        .filter(saved_searches::user_id.eq(session.user.id))
+        .order(saved_searches::name)
+        .select(SavedSearch::as_select())
+        .load(&mut *db::lock(db)?)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Saves a search; its name has to be new to the user.
+#[post("/searches", data = "<new_search>")]
+fn create_search(new_search: Json<NewSearch>, session: Session, db: &State<DbConn>) -> Result<Created<Json<SavedSearch>>, Status> {
+    let (name, query) = new_search.validate()?;
+    let now = timestamp::now();
+    let search = diesel::insert_into(saved_searches::table)
+        .values((
+            saved_searches::user_id.eq(session.user.id),
+            saved_searches::name.eq(name),
+            saved_searches::query.eq(query),
+            saved_searches::created_at.eq(now),
+            saved_searches::updated_at.eq(now),
+        ))
+        .returning(SavedSearch::as_returning())
+        .get_result(&mut *db::lock(db)?)
+        .map_err(conflict_or_internal)?;
+
+    Ok(Created::new(format!("/searches/{}", search.id)).body(Json(search)))
+}
+
+#[get("/searches/<id>")]
+fn get_search(id: i32, session: Session, db: &State<DbConn>) -> Result<Json<SavedSearch>, Status> {
+    find(id, &session, &mut *db::lock(db)?).map(Json)
+}
+
+/// Renames a search or changes its criteria.
+#[put("/searches/<id>", data = "<new_search>")]
+fn update_search(id: i32, new_search: Json<NewSearch>, session: Session, db: &State<DbConn>) -> Result<Json<SavedSearch>, Status> {
+    let (name, query) = new_search.validate()?;
+    let mut connection = db::lock(db)?;
+    find(id, &session, &mut connection)?;
+    diesel::update(saved_searches::table.find(id))
+        .set((saved_searches::name.eq(name), saved_searches::query.eq(query), saved_searches::updated_at.eq(timestamp::now())))
+        .returning(SavedSearch::as_returning())
+        .get_result(&mut *connection)
+        .map(Json)
+        .map_err(conflict_or_internal)
+}
+
+#[delete("/searches/<id>")]
+fn delete_search(id: i32, session: Session, db: &State<DbConn>) -> Result<Status, Status> {
+    let mut connection = db::lock(db)?;
+    find(id, &session, &mut connection)?;
+    diesel::delete(saved_searches::table.find(id)).execute(&mut *connection).map_err(|_| Status::InternalServerError)?;
+    Ok(Status::NoContent)
+}
+
+/// The elus matching the search now, as `GET /elus` lists them, paging
+/// and sorting included.
+#[get("/searches/<id>/results")]
+fn search_results(
+    id: i32,
+    pagination: Pagination,
+    sort: SortSpec<PersonSort>,
+    session: Session,
+    db: &State<DbConn>,
+    repository: Visible,
+    mandate_types: &State<MandateTypes>,
+) -> Result<Redacted<Listing>, Problem> {
+    let search = find(id, &session, &mut *db::lock(db)?)?;
+    let filter = Criteria::parse(&search.query)?.filter(db, mandate_types)?;
+    Ok(Redacted(crate::listing(&*repository, &filter, pagination, sort)?))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_searches, create_search, get_search, update_search, delete_search, search_results]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csrf;
+    use crate::tests::{client, insert_test_persons, setup_test_db};
+    use crate::users::{self, NewUser};
+    use rocket::http::{ContentType, Header};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::{json, Value};
+
+    /// Signs in as the user, returning the CSRF token of the session.
+    fn login(client: &Client, username: &str) -> Header<'static> {
+        let body = format!("username={}&password=correct+horse+battery+staple", username);
+        assert_eq!(client.post("/admin/login").header(ContentType::Form).body(body).dispatch().status(), Status::SeeOther);
+        let html = client.get("/admin").dispatch().into_string().unwrap();
+        let token = html.split("name=\"csrf_token\" value=\"").nth(1).and_then(|rest| rest.split('"').next()).unwrap();
+        Header::new(csrf::HEADER, token.to_string())
+    }
+
+    #[test]
+    fn test_saved_searches() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        for username in ["secretariat", "cabinet"] {
+            let new_user = NewUser { username: username.to_string(), password: "correct horse battery staple".to_string(), email: None };
+            users::create(&new_user, &mut connection).unwrap();
+        }
+        let client = client(connection);
+        assert_eq!(client.get("/searches").dispatch().status(), Status::Unauthorized);
+
+        let token = login(&client, "secretariat");
+        let create = |name: &str, query: &str| client.post("/searches").header(token.clone()).json(&json!({ "name": name, "query": query })).dispatch();
+        assert_eq!(client.post("/searches").json(&json!({ "name": "Maires", "query": "mandate=maire" })).dispatch().status(), Status::Forbidden);
+        let response = create("Paris", "?commune=75056");
+        assert_eq!(response.status(), Status::Created);
+        let search: SavedSearch = response.into_json().unwrap();
+        assert_eq!(search.query, "commune=75056");
+        assert_eq!(create("Paris", "mandate=maire").status(), Status::Conflict);
+        assert_eq!(create("Typo", "mandat=maire").status(), Status::UnprocessableEntity);
+        assert_eq!(create(" ", "mandate=maire").status(), Status::UnprocessableEntity);
+
+        let results = |id: i32| -> Vec<String> {
+            let persons: Vec<Value> = client.get(format!("/searches/{}/results", id)).dispatch().into_json().unwrap();
+            persons.iter().map(|person| person["name"].as_str().unwrap().to_string()).collect()
+        };
+        assert_eq!(results(search.id), ["Jean Dupont", "Marie Martin"]);
+        let page: Value = client.get(format!("/searches/{}/results?limit=1", search.id)).dispatch().into_json().unwrap();
+        assert_eq!(page["elus"].as_array().unwrap().len(), 1);
+
+        let response = client.put(format!("/searches/{}", search.id)).header(token.clone()).json(&json!({ "name": "Maire de Paris", "query": "commune=75056&mandate=maire" })).dispatch();
+        assert_eq!(response.into_json::<SavedSearch>().unwrap().name, "Maire de Paris");
+        assert_eq!(results(search.id), ["Jean Dupont"]);
+
+        let token = login(&client, "cabinet");
+        assert!(client.get("/searches").dispatch().into_json::<Vec<SavedSearch>>().unwrap().is_empty());
+        assert_eq!(client.get(format!("/searches/{}/results", search.id)).dispatch().status(), Status::NotFound);
+        assert_eq!(client.delete(format!("/searches/{}", search.id)).header(token).dispatch().status(), Status::NotFound);
+
+        let token = login(&client, "secretariat");
+        assert_eq!(client.delete(format!("/searches/{}", search.id)).header(token).dispatch().status(), Status::NoContent);
+        assert_eq!(client.get(format!("/searches/{}", search.id)).dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,373 @@
+//! Nightly exports of the whole directory, as CSV the importer takes back
+//! and as NDJSON, one person per line, written to a local directory or an
+//! S3 bucket under `exports/<date>-<id>/`. The last `keep` successful
+//! exports are kept, older ones removed; runs are recorded in the
+//! `exports` table, which `GET /admin/exports` lists.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::fs;
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::config::AppConfig;
+use crate::import::{csv_field, csv_value, REPORT_COLUMNS};
+use crate::redaction::RedactionConfig;
+use crate::repository::PersonRepository;
+use crate::schema::exports;
+use crate::storage::{BlobStore, LocalStore, S3Config, S3Store};
+use crate::{db, settings, shutdown, timeouts, timestamp, DbConn, Person};
+
+/// Exports `GET /admin/exports` lists.
+const LISTED: i64 = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", tag = "backend", rename_all = "lowercase")]
+pub enum ExportDestination {
+    Local { dir: PathBuf },
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScheduledExportConfig {
+    pub destination: ExportDestination,
+    /// Hour, UTC, from which the day's export is made.
+    #[serde(default = "default_hour")]
+    pub hour: u8,
+    /// Successful exports kept.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// Seconds between two checks for whether an export is due.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn default_hour() -> u8 {
+    2
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+fn default_poll_interval() -> u64 {
+    600
+}
+
+pub fn store(destination: &ExportDestination) -> Box<dyn BlobStore> {
+    match destination {
+        ExportDestination::Local { dir } => Box::new(LocalStore::new(dir)),
+        ExportDestination::S3(s3) => Box::new(S3Store::new(s3.clone())),
+    }
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = exports)]
+struct ExportRow {
+    id: i32,
+    status: String,
+    files: String,
+    persons: i32,
+    error: Option<String>,
+    removed: bool,
+    started_at: PrimitiveDateTime,
+    finished_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Export {
+    pub id: i32,
+    /// `running`, `succeeded` or `failed`.
+    pub status: String,
+    /// Keys of the files written.
+    pub files: Vec<String>,
+    pub persons: i32,
+    pub error: Option<String>,
+    /// Whether the files were removed to keep only the latest exports.
+    pub removed: bool,
+    #[serde(with = "timestamp::rfc3339")]
+    pub started_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub finished_at: Option<PrimitiveDateTime>,
+}
+
+impl From<ExportRow> for Export {
+    fn from(row: ExportRow) -> Self {
+        Export {
+            id: row.id,
+            status: row.status,
+            files: serde_json::from_str(&row.files).unwrap_or_default(),
+            persons: row.persons,
+            error: row.error,
+            removed: row.removed,
+            started_at: row.started_at,
+            finished_at: row.finished_at,
+        }
+    }
+}
+
+pub fn list(limit: i64, connection: &mut SqliteConnection) -> QueryResult<Vec<Export>> {
+    exports::table
+        .order(exports::id.desc())
+        .limit(limit)
+        .select(ExportRow::as_select())
+        .load(connection)
+        .map(|rows| rows.into_iter().map(Export::from).collect())
+}
+
+/// Whether the day's export is due at `now`, the last one having started
+/// at `last`.
+fn due(now: PrimitiveDateTime, hour: u8, last: Option<PrimitiveDateTime>) -> bool {
+    now.hour() >= hour && last.is_none_or(|last| last.date() < now.date())
+}
+
+/// Columns of the CSV export: those the CSV importer takes, and those it
+/// sets, then a `custom.<name>` one for each custom field the persons have
+/// values of.
+fn csv_columns(persons: &[Value]) -> Vec<String> {
+    let mut columns = vec!["uuid".to_string()];
+    columns.extend(REPORT_COLUMNS.iter().map(|column| column.to_string()));
+    columns.extend(["organization".to_string(), "email_status".to_string(), "updated_at".to_string()]);
+    let custom: BTreeSet<&String> = persons.iter().filter_map(|person| person["custom"].as_object()).flat_map(|custom| custom.keys()).collect();
+    columns.extend(custom.into_iter().map(|field| format!("custom.{}", field)));
+    columns
+}
+
+fn column<'a>(person: &'a Value, column: &str) -> &'a Value {
+    match column.strip_prefix("custom.") {
+        Some(field) => &person["custom"][field],
+        None => &person[column],
+    }
+}
+
+pub fn csv(persons: &[Value]) -> String {
+    let columns = csv_columns(persons);
+    let mut csv = format!("{}\n", columns.join(","));
+    for person in persons {
+        let fields: Vec<String> = columns.iter().map(|name| csv_field(&csv_value(column(person, name)))).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn ndjson(persons: &[Value]) -> String {
+    persons.iter().map(|person| format!("{}\n", person)).collect()
+}
+
+/// Names the commune of each person as it goes by in its settings.
+fn add_organizations(persons: &mut [Value], db: &DbConn) -> Result<(), String> {
+    let codes = persons.iter().filter_map(|person| person["commune_code"].as_str());
+    let unreadable = |e: &dyn std::fmt::Display| format!("could not read the settings of the communes: {}", e);
+    let settings = settings::find_all(codes, &mut *db::lock(db).map_err(|e| unreadable(&e))?).map_err(|e| unreadable(&e))?;
+    settings::add_organizations(persons, &settings);
+    Ok(())
+}
+
+/// Writes the files of the export under `prefix`, returning their keys.
+async fn write(prefix: &str, persons: &[Value], store: &dyn BlobStore, redaction: &RedactionConfig) -> Result<Vec<String>, String> {
+    let mut keys = vec![];
+    for (name, content) in [("elus.csv", csv(persons)), ("elus.ndjson", ndjson(persons))] {
+        let staged = std::env::temp_dir().join(format!(".export-{:016x}", rand::random::<u64>()));
+        fs::write(&staged, redaction.shown(content)).await.map_err(|e| format!("could not stage {}: {}", name, e))?;
+        let key = format!("{}/{}", prefix, name);
+        if let Err(e) = store.put(&key, &staged).await {
+            let _ = fs::remove_file(&staged).await;
+            return Err(format!("could not write {}: {}", key, e));
+        }
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Removes the files of the successful exports beyond the `keep` latest.
+async fn prune(keep: usize, db: &DbConn, store: &dyn BlobStore) -> QueryResult<()> {
+    let outdated: Vec<Export> = exports::table
+        .filter(exports::status.eq("succeeded").and(exports::removed.eq(false)))
+        .order(exports::id.desc())
+        .offset(keep as i64)
+        .select(ExportRow::as_select())
+        .load(&mut *db::lock(db)?)?
+        .into_iter()
+        .map(Export::from)
+        .collect();
+
+    for export in outdated {
+        for key in &export.files {
+            if let Err(e) = store.delete(key).await {
+                log::warn!("Could not remove {} of export {}: {}", key, export.id, e);
+            }
+        }
+        diesel::update(exports::table.find(export.id)).set(exports::removed.eq(true)).execute(&mut *db::lock(db)?)?;
+    }
+    Ok(())
+}
+
+/// Exports the directory, recording the run whether it succeeds or not.
+pub async fn run(db: &DbConn, repository: Arc<dyn PersonRepository>, store: &dyn BlobStore, config: &ScheduledExportConfig, redaction: &RedactionConfig) -> Result<Export, Status> {
+    let internal = |_| Status::InternalServerError;
+    let (id, started_at): (i32, PrimitiveDateTime) = diesel::insert_into(exports::table)
+        .default_values()
+        .returning((exports::id, exports::started_at))
+        .get_result(&mut *db::lock(db)?)
+        .map_err(internal)?;
+
+    let written = match rocket::tokio::task::spawn_blocking(move || repository.list()).await {
+        Ok(Ok(persons)) => {
+            let mut persons: Vec<Value> = persons.into_iter().map(|person| serde_json::to_value(Person::from(person)).expect("persons serialize to JSON")).collect();
+            let prefix = format!("exports/{}-{}", started_at.date(), id);
+            match add_organizations(&mut persons, db) {
+                Ok(()) => write(&prefix, &persons, store, redaction).await.map(|keys| (keys, persons.len())),
+                Err(error) => Err(error),
+            }
+        }
+        Ok(Err(status)) => Err(format!("could not list the elus: {}", status)),
+        Err(e) => Err(format!("listing the elus panicked: {}", e)),
+    };
+
+    let update = diesel::update(exports::table.find(id));
+    let finished_at = exports::finished_at.eq(timestamp::now());
+    match &written {
+        Ok((keys, persons)) => {
+            let files = serde_json::to_string(keys).expect("keys serialize to JSON");
+            update.set((exports::status.eq("succeeded"), exports::files.eq(files), exports::persons.eq(*persons as i32), finished_at)).execute(&mut *db::lock(db)?)
+        }
+        Err(error) => update.set((exports::status.eq("failed"), exports::error.eq(error), finished_at)).execute(&mut *db::lock(db)?),
+    }
+    .map_err(internal)?;
+
+    if written.is_ok() {
+        prune(config.keep, db, store).await.map_err(internal)?;
+    }
+    exports::table.find(id).select(ExportRow::as_select()).first(&mut *db::lock(db)?).map(Export::from).map_err(internal)
+}
+
+pub fn fairing(config: ScheduledExportConfig) -> AdHoc {
+    AdHoc::on_liftoff("Nightly export", move |rocket| Box::pin(async move {
+        if config.poll_interval == 0 {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let repository = rocket.state::<Arc<dyn PersonRepository>>().expect("repository is managed").clone();
+        let redaction = rocket.state::<AppConfig>().expect("configuration is managed").redaction.clone();
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let store = store(&config.destination);
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval));
+            while shutdown::tick(&mut interval, &shutdown).await {
+                let last = db::lock(&db).map_err(Into::into).and_then(|mut connection| exports::table.select(diesel::dsl::max(exports::started_at)).first::<Option<PrimitiveDateTime>>(&mut *connection));
+                match last {
+                    Ok(last) if due(timestamp::now(), config.hour, last) => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::error!("Could not check for the nightly export: {}", e);
+                        continue;
+                    }
+                }
+
+                match rocket::tokio::time::timeout(job, run(&db, repository.clone(), store.as_ref(), &config, &redaction)).await {
+                    Ok(Ok(export)) if export.status == "succeeded" => log::info!("Exported {} elus to {}", export.persons, export.files.join(", ")),
+                    Ok(Ok(export)) => log::error!("Nightly export failed: {}", export.error.unwrap_or_default()),
+                    Ok(Err(status)) => log::error!("Nightly export failed: {}", status),
+                    Err(_) => log::warn!("Nightly export interrupted after {} seconds", job.as_secs()),
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+/// The latest exports, newest first.
+#[get("/admin/exports")]
+fn list_exports(_admin: Admin, db: &State<DbConn>) -> Result<Json<Vec<Export>>, Status> {
+    list(LISTED, &mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+/// Exports the directory now, as the nightly export would.
+#[post("/admin/exports")]
+async fn export_now(_admin: Admin, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>, config: &State<AppConfig>) -> Result<Json<Export>, Status> {
+    let exports = config.exports.as_ref().ok_or(Status::NotFound)?;
+    let store = store(&exports.destination);
+    timeouts::within(config.timeouts.job(), run(db, repository.inner().clone(), store.as_ref(), exports, &config.redaction)).await.map(Json)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_exports, export_now]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_due() {
+        let now = datetime!(2026-01-19 03:00:00);
+        assert!(due(now, 2, None));
+        assert!(due(now, 2, Some(datetime!(2026-01-18 02:00:00))));
+        assert!(!due(now, 2, Some(datetime!(2026-01-19 02:00:00))));
+        assert!(!due(now, 4, None));
+    }
+
+    #[test]
+    fn test_csv() {
+        let persons = [
+            json!({ "uuid": "abc", "name": "Dupont, Jean", "email": "jean@example.com", "mandates": ["Maire", "Conseiller régional"], "latitude": 48.85, "custom": { "siege": 12 } }),
+            json!({ "uuid": "def", "name": "Marie Martin", "email": "marie@example.com", "mandates": [], "custom": { "delegation": "sport" } }),
+        ];
+        assert_eq!(
+            csv(&persons),
+            "uuid,name,email,mandates,commune_code,office_address,latitude,longitude,organization,email_status,updated_at,custom.delegation,custom.siege\n\
+             abc,\"Dupont, Jean\",jean@example.com,Maire|Conseiller régional,,,48.85,,,,,,12\n\
+             def,Marie Martin,marie@example.com,,,,,,,,,sport,\n"
+        );
+    }
+
+    #[test]
+    fn test_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let configure = |figment: rocket::figment::Figment| {
+            figment
+                .merge(("exports.destination.backend", "local"))
+                .merge(("exports.destination.dir", dir.path().to_str().unwrap()))
+                .merge(("exports.keep", 2))
+                .merge(("exports.poll_interval", 0))
+                .merge(("redaction.demo", true))
+                .merge(("redaction.demo_key", "demo"))
+        };
+        let client = build_client(configure, connection);
+
+        assert_eq!(client.post("/admin/exports").dispatch().status(), Status::Unauthorized);
+        let first: Export = client.post("/admin/exports").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!((first.status.as_str(), first.persons), ("succeeded", 3));
+        let ndjson = std::fs::read_to_string(dir.path().join(&first.files[1])).unwrap();
+        assert_eq!(ndjson.lines().count(), 3);
+        assert!(serde_json::from_str::<Person>(ndjson.lines().next().unwrap()).is_ok());
+        let csv = std::fs::read_to_string(dir.path().join(&first.files[0])).unwrap();
+        assert!(!ndjson.contains("@example.com") && !csv.contains("@example.com"));
+
+        client.post("/admin/exports").header(admin()).dispatch();
+        client.post("/admin/exports").header(admin()).dispatch();
+        let exports: Vec<Export> = client.get("/admin/exports").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(exports.iter().map(|export| export.removed).collect::<Vec<_>>(), [false, false, true]);
+        assert!(!dir.path().join(&first.files[0]).exists());
+    }
+}
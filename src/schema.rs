@@ -1,10 +1,415 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Integer,
+        name -> Text,
+        key_hash -> Text,
+        monthly_quota -> Nullable<BigInt>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    api_usage (api_key_id, month) {
+        api_key_id -> Integer,
+        month -> Text,
+        requests -> BigInt,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        event -> Text,
+        username -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    backup_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code_hash -> Text,
+    }
+}
+
+diesel::table! {
+    bodies (id) {
+        id -> Integer,
+        name -> Text,
+        kind -> Text,
+        commune_code -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    body_members (body_id, elu_id) {
+        body_id -> Integer,
+        elu_id -> Integer,
+        role -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    collectivite_settings (code) {
+        code -> Text,
+        display_name -> Nullable<Text>,
+        contact_email -> Nullable<Text>,
+        default_visibility -> Text,
+        locale -> Text,
+    }
+}
+
+diesel::table! {
+    communes (code) {
+        code -> Text,
+        name -> Text,
+        department -> Text,
+    }
+}
+
+diesel::table! {
+    custom_fields (name) {
+        name -> Text,
+        kind -> Text,
+        description -> Nullable<Text>,
+        required -> Bool,
+        options -> Nullable<Text>,
+        minimum -> Nullable<Double>,
+        maximum -> Nullable<Double>,
+        max_length -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    documents (id) {
+        id -> Integer,
+        elu_id -> Integer,
+        title -> Text,
+        content_type -> Text,
+        size -> BigInt,
+        visibility -> Text,
+        created_at -> Timestamp,
+        filename -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    elections (id) {
+        id -> Integer,
+        date -> Date,
+        scope -> Text,
+        kind -> Text,
+        status -> Text,
+        transitioned_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     elus (id) {
         id -> Integer,
         name -> Text,
         email -> Text,
-        mandates -> Text,
+        commune_code -> Nullable<Text>,
+        office_address -> Nullable<Text>,
+        latitude -> Nullable<Double>,
+        longitude -> Nullable<Double>,
+        email_status -> Text,
+        updated_at -> Timestamp,
+        search_name -> Text,
+        search_phonetic -> Text,
+        uuid -> Text,
+        may_contact_by_email -> Bool,
+        consent_date -> Nullable<Timestamp>,
+        consent_source -> Nullable<Text>,
+        visibility -> Text,
+        custom -> Text,
+    }
+}
+
+diesel::table! {
+    elu_emails (email) {
+        email -> Text,
+        elu_id -> Integer,
+        is_primary -> Bool,
+    }
+}
+
+diesel::table! {
+    elu_tags (elu_id, tag_id) {
+        elu_id -> Integer,
+        tag_id -> Integer,
+    }
+}
+
+diesel::table! {
+    email_aliases (email) {
+        email -> Text,
+        elu_id -> Integer,
+    }
+}
+
+diesel::table! {
+    event_cursors (consumer) {
+        consumer -> Text,
+        seq -> BigInt,
+    }
+}
+
+diesel::table! {
+    events (seq) {
+        seq -> BigInt,
+        kind -> Text,
+        elu_id -> Integer,
+        payload -> Text,
+        at -> Timestamp,
     }
 }
+
+diesel::table! {
+    exports (id) {
+        id -> Integer,
+        status -> Text,
+        files -> Text,
+        persons -> Integer,
+        error -> Nullable<Text>,
+        removed -> Bool,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        kind -> Text,
+        format -> Text,
+        input -> Text,
+        status -> Text,
+        progress -> Nullable<Text>,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+        rejection_report -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    login_failures (key) {
+        key -> Text,
+        failures -> Integer,
+        last_failure_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mail_queue (id) {
+        id -> Integer,
+        notification_id -> Nullable<Integer>,
+        recipient -> Text,
+        subject -> Text,
+        body -> Text,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        created_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mandate_labels (code, language) {
+        code -> Text,
+        language -> Text,
+        label -> Text,
+    }
+}
+
+diesel::table! {
+    mandate_terms (elu_id, title) {
+        elu_id -> Integer,
+        title -> Text,
+        started_on -> Nullable<Date>,
+        ends_on -> Nullable<Date>,
+        alerted_on -> Nullable<Date>,
+    }
+}
+
+diesel::table! {
+    mandate_types (code) {
+        code -> Text,
+    }
+}
+
+diesel::table! {
+    mandates (id) {
+        id -> Integer,
+        elu_id -> Integer,
+        position -> Integer,
+        title -> Text,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Integer,
+        subject -> Text,
+        template -> Text,
+        created_at -> Timestamp,
+        skipped -> Integer,
+    }
+}
+
+diesel::table! {
+    relations (elu_id, kind, related_id) {
+        elu_id -> Integer,
+        kind -> Text,
+        related_id -> Integer,
+    }
+}
+
+diesel::table! {
+    saved_searches (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        query -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Text,
+        user_id -> Integer,
+        csrf_token -> Text,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sync_queue (id) {
+        id -> Integer,
+        target -> Text,
+        kind -> Text,
+        elu_id -> Integer,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        created_at -> Timestamp,
+        synced_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Integer,
+        name -> Text,
+        description -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+        created_at -> Timestamp,
+        email -> Nullable<Text>,
+        totp_secret -> Nullable<Text>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        webhook_id -> Integer,
+        event_seq -> BigInt,
+        status -> Text,
+        attempts -> Integer,
+        response_code -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Integer,
+        url -> Text,
+        created_at -> Timestamp,
+        secret -> Text,
+        previous_secret -> Nullable<Text>,
+        previous_secret_expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(api_usage -> api_keys (api_key_id));
+diesel::joinable!(backup_codes -> users (user_id));
+diesel::joinable!(body_members -> bodies (body_id));
+diesel::joinable!(body_members -> elus (elu_id));
+diesel::joinable!(collectivite_settings -> communes (code));
+diesel::joinable!(documents -> elus (elu_id));
+diesel::joinable!(elu_emails -> elus (elu_id));
+diesel::joinable!(elu_tags -> elus (elu_id));
+diesel::joinable!(elu_tags -> tags (tag_id));
+diesel::joinable!(email_aliases -> elus (elu_id));
+diesel::joinable!(mail_queue -> notifications (notification_id));
+diesel::joinable!(mandate_labels -> mandate_types (code));
+diesel::joinable!(mandate_terms -> elus (elu_id));
+diesel::joinable!(mandates -> elus (elu_id));
+diesel::joinable!(saved_searches -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(webhook_deliveries -> events (event_seq));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    api_usage,
+    audit_log,
+    backup_codes,
+    bodies,
+    body_members,
+    collectivite_settings,
+    communes,
+    custom_fields,
+    documents,
+    elections,
+    elus,
+    elu_emails,
+    elu_tags,
+    email_aliases,
+    event_cursors,
+    events,
+    exports,
+    jobs,
+    login_failures,
+    mail_queue,
+    mandate_labels,
+    mandate_terms,
+    mandate_types,
+    mandates,
+    notifications,
+    relations,
+    saved_searches,
+    sessions,
+    sync_queue,
+    tags,
+    users,
+    webhook_deliveries,
+    webhooks,
+);
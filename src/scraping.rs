@@ -0,0 +1,263 @@
+//! Protections against scraping of the public endpoints: lookups of elus
+//! (`GET /elus/<key>`, its QR code, relations, bodies and tags,
+//! `POST /elus/lookup` and `GET /elus/search`), which probing for addresses
+//! goes through, are limited per client IP over a sliding window, and
+//! public responses can be delayed by a random jitter so timings tell
+//! nothing. Clients are told apart by their remote address, or by the
+//! `X-Real-IP` header when `trusted_proxy` says a reverse proxy sets it.
+//! The latest requests are kept in an in-memory access log, from which
+//! `GET /admin/scrapers` reports the busiest clients. Callers with the
+//! admin token are neither limited nor delayed.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Response, State};
+use time::PrimitiveDateTime;
+
+use crate::auth::{self, Admin};
+use crate::timestamp;
+
+/// Clients `GET /admin/scrapers` reports when no limit is given.
+const DEFAULT_REPORTED: usize = 20;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ScrapingConfig {
+    /// Lookups of single elus a client may make within `window`; unlimited
+    /// when unset.
+    pub probe_limit: Option<usize>,
+    /// Seconds of the sliding window of `probe_limit`.
+    pub window: u64,
+    /// Upper bound, in milliseconds, of the random delay added to public
+    /// responses; 0 adds none.
+    pub jitter_ms: u64,
+    /// Requests kept in the access log.
+    pub log_size: usize,
+    /// Whether the server is behind a reverse proxy setting `X-Real-IP`;
+    /// otherwise anyone could send the header to get a fresh limit.
+    pub trusted_proxy: bool,
+}
+
+impl Default for ScrapingConfig {
+    fn default() -> Self {
+        ScrapingConfig { probe_limit: None, window: 60, jitter_ms: 0, log_size: 10_000, trusted_proxy: false }
+    }
+}
+
+struct Entry {
+    ip: Option<IpAddr>,
+    path: String,
+    status: u16,
+    probe: bool,
+    at: PrimitiveDateTime,
+}
+
+pub struct Protections {
+    config: ScrapingConfig,
+    /// When each client made its lookups within the window, oldest first.
+    probes: Mutex<HashMap<Option<IpAddr>, VecDeque<Instant>>>,
+    log: Mutex<VecDeque<Entry>>,
+}
+
+impl Protections {
+    pub fn new(config: ScrapingConfig) -> Self {
+        Protections { config, probes: Mutex::default(), log: Mutex::default() }
+    }
+
+    /// The IP the request is counted against.
+    fn client(&self, request: &Request<'_>) -> Option<IpAddr> {
+        if self.config.trusted_proxy {
+            request.client_ip()
+        } else {
+            request.remote().map(|remote| remote.ip())
+        }
+    }
+
+    /// Counts a lookup by `ip` at `now`, telling whether it's within the
+    /// limit; refused lookups count too, so probing harder doesn't help.
+    fn allow(&self, ip: Option<IpAddr>, now: Instant) -> bool {
+        let Some(limit) = self.config.probe_limit else {
+            return true;
+        };
+
+        let window = Duration::from_secs(self.config.window);
+        let mut probes = self.probes.lock().unwrap_or_else(PoisonError::into_inner);
+        probes.retain(|_, times| times.back().is_some_and(|last| now.duration_since(*last) < window));
+        let times = probes.entry(ip).or_default();
+        while times.front().is_some_and(|first| now.duration_since(*first) >= window) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        times.len() <= limit
+    }
+
+    fn record(&self, entry: Entry) {
+        let mut log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        if log.len() >= self.config.log_size {
+            log.pop_front();
+        }
+        if self.config.log_size > 0 {
+            log.push_back(entry);
+        }
+    }
+
+    /// The clients of the access log by number of requests, most first.
+    fn report(&self, limit: usize) -> Vec<Scraper> {
+        let mut by_ip: BTreeMap<Option<IpAddr>, (Scraper, HashSet<&str>)> = BTreeMap::new();
+        let log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        for entry in log.iter() {
+            let (scraper, paths) = by_ip.entry(entry.ip).or_insert_with(|| (Scraper {
+                ip: entry.ip.map(|ip| ip.to_string()),
+                requests: 0,
+                probes: 0,
+                not_found: 0,
+                refused: 0,
+                distinct_paths: 0,
+                first_seen: entry.at,
+                last_seen: entry.at,
+            }, HashSet::new()));
+            scraper.requests += 1;
+            scraper.probes += entry.probe as usize;
+            scraper.not_found += (entry.status == 404) as usize;
+            scraper.refused += (entry.status == 429) as usize;
+            scraper.last_seen = entry.at;
+            paths.insert(&entry.path);
+        }
+
+        let mut scrapers: Vec<Scraper> = by_ip.into_values().map(|(scraper, paths)| Scraper { distinct_paths: paths.len(), ..scraper }).collect();
+        scrapers.sort_by(|a, b| b.requests.cmp(&a.requests).then(b.probes.cmp(&a.probes)));
+        scrapers.truncate(limit);
+        scrapers
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Scraper {
+    /// Client IP; null for requests whose client wasn't known.
+    pub ip: Option<String>,
+    pub requests: usize,
+    /// Lookups of single elus.
+    pub probes: usize,
+    pub not_found: usize,
+    /// Requests refused for going over the lookup limit.
+    pub refused: usize,
+    pub distinct_paths: usize,
+    #[serde(with = "timestamp::rfc3339")]
+    pub first_seen: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub last_seen: PrimitiveDateTime,
+}
+
+/// Request guard of the lookups of elus, failing with 429 Too Many
+/// Requests beyond the client's limit.
+pub struct Probe;
+
+/// Marks requests the `Probe` guard ran on, for the access log.
+struct Probed;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Probe {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request.local_cache(|| Some(Probed));
+        match request.rocket().state::<Protections>() {
+            Some(protections) if !auth::is_admin(request) && !protections.allow(protections.client(request), Instant::now()) => {
+                request::Outcome::Error((Status::TooManyRequests, ()))
+            }
+            _ => request::Outcome::Success(Probe),
+        }
+    }
+}
+
+/// Fairing keeping the access log and delaying public responses.
+pub struct Scraping;
+
+#[rocket::async_trait]
+impl Fairing for Scraping {
+    fn info(&self) -> Info {
+        Info { name: "Scraping protections", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(protections) = request.rocket().state::<Protections>() else {
+            return;
+        };
+
+        protections.record(Entry {
+            ip: protections.client(request),
+            path: request.uri().path().to_string(),
+            status: response.status().code,
+            probe: request.local_cache(|| None::<Probed>).is_some(),
+            at: timestamp::now(),
+        });
+        if protections.config.jitter_ms > 0 && !auth::is_admin(request) {
+            let jitter = rand::random::<u64>() % (protections.config.jitter_ms + 1);
+            rocket::tokio::time::sleep(Duration::from_millis(jitter)).await;
+        }
+    }
+}
+
+/// The clients making the most requests among the latest ones.
+#[get("/admin/scrapers?<limit>")]
+fn list_scrapers(limit: Option<usize>, _admin: Admin, protections: &State<Protections>) -> Json<Vec<Scraper>> {
+    Json(protections.report(limit.unwrap_or(DEFAULT_REPORTED)))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_scrapers]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, insert_test_persons, setup_test_db};
+    use rocket::http::Header;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_sliding_window() {
+        let protections = Protections::new(ScrapingConfig { probe_limit: Some(2), window: 10, ..Default::default() });
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let start = Instant::now();
+
+        assert!(protections.allow(ip, start));
+        assert!(protections.allow(ip, start + Duration::from_secs(4)));
+        assert!(!protections.allow(ip, start + Duration::from_secs(8)));
+        assert!(protections.allow(Some("192.0.2.2".parse().unwrap()), start + Duration::from_secs(8)));
+        // The first lookup left the window, the refused one didn't.
+        assert!(!protections.allow(ip, start + Duration::from_secs(11)));
+        assert!(protections.allow(ip, start + Duration::from_secs(19)));
+    }
+
+    #[test]
+    fn test_probing() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("scraping.probe_limit", 2)), connection);
+        let scraper: SocketAddr = "192.0.2.1:4242".parse().unwrap();
+        let probe = |email: &str| client.get(format!("/elus/{}", email)).remote(scraper).dispatch().status();
+
+        assert_eq!(probe("jean.dupont@example.com"), Status::Ok);
+        assert_eq!(probe("jean@example.com"), Status::NotFound);
+        assert_eq!(probe("marie.martin@example.com"), Status::TooManyRequests);
+        let forged = Header::new("X-Real-IP", "198.51.100.7");
+        assert_eq!(client.get("/elus/marie.martin@example.com/tags").remote(scraper).header(forged).dispatch().status(), Status::TooManyRequests);
+        assert_eq!(client.get("/elus/marie.martin@example.com").remote(scraper).header(admin()).dispatch().status(), Status::Ok);
+        client.get("/elus").remote("192.0.2.2:4242".parse().unwrap()).dispatch();
+
+        let scrapers: Vec<Scraper> = client.get("/admin/scrapers").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(scrapers[0].ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!((scrapers[0].requests, scrapers[0].probes, scrapers[0].not_found, scrapers[0].refused), (5, 5, 1, 2));
+        assert_eq!(scrapers[1].ip.as_deref(), Some("192.0.2.2"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Typeahead search, `GET /elus/search?q=<text>`: the elus whose email is
+//! the text, for callers who see emails, whose name contains it or who
+//! hold a mandate of that title or code, best matches first. Each result
+//! comes with its `score`. With `fuzzy=true`, names which sound like the
+//! text match too, so "Dupond" finds "Dupont". The criteria, paging and
+//! sorting of `GET /elus` narrow down and order the matches. Candidates
+//! come from the repository's queries, narrowed down by the criteria and
+//! at most `MAX_CANDIDATES` each, or from the full-text index when there's
+//! one, so only the best matches of very common texts are ranked.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use rocket::http::Status;
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::criteria::Filters;
+use crate::db;
+use crate::email::Email;
+use crate::mandate_types::MandateTypes;
+use crate::pagination::{Pagination, SortSpec};
+use crate::phonetic;
+use crate::problem::Problem;
+use crate::redaction::{EmailsShown, Redactable, Redacted};
+use crate::repository::{normalize_name, Page, PersonFilter, PersonKey, PersonRepository, PersonSort};
+use crate::scraping::Probe;
+use crate::visibility::Visible;
+use crate::Person;
+
+/// Results returned when no paging is given.
+const DEFAULT_LIMIT: i64 = 10;
+/// Candidates taken from each of the repository's queries, first by name.
+const MAX_CANDIDATES: i64 = 1000;
+
+const EXACT_EMAIL: u32 = 100;
+const NAME_PREFIX: u32 = 80;
+const WORD_PREFIX: u32 = 60;
+const NAME_SUBSTRING: u32 = 40;
+const PHONETIC: u32 = 30;
+const MANDATE: u32 = 20;
+
+/// How well `person` matches `query`, 0 if not at all; names only match
+/// phonetically if `fuzzy`, and emails only `by_email`.
+pub fn score(person: &db::Person, query: &str, mandate: &str, fuzzy: bool, by_email: bool) -> u32 {
+    let query = query.trim();
+    let normalized = normalize_name(query);
+    let name = normalize_name(&person.name);
+
+    if by_email && std::iter::once(&person.email).chain(&person.emails).any(|email| email.eq_ignore_ascii_case(query)) {
+        EXACT_EMAIL
+    } else if normalized.is_empty() {
+        0
+    } else if name.starts_with(&normalized) {
+        NAME_PREFIX
+    } else if name.split([' ', '-', '\'']).any(|word| word.starts_with(&normalized)) {
+        WORD_PREFIX
+    } else if name.contains(&normalized) {
+        NAME_SUBSTRING
+    } else if fuzzy && sounds_like(&person.name, query) {
+        PHONETIC
+    } else if person.mandates.iter().any(|title| title == mandate || normalize_name(title) == normalized) {
+        MANDATE
+    } else {
+        0
+    }
+}
+
+fn sounds_like(name: &str, query: &str) -> bool {
+    let key = phonetic::key(query);
+    !key.is_empty() && phonetic::key(name).contains(&key)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Hit {
+    #[serde(flatten)]
+    pub elu: Person,
+    pub score: u32,
+}
+
+impl Redactable for Hit {
+    fn persons(value: &mut Value) -> Vec<&mut Value> {
+        vec![value]
+    }
+}
+
+/// A faster source of candidates than the repository's queries.
+pub trait Index: Send + Sync {
+    /// The persons which may match `query`, or hold `mandate`; a superset
+    /// of the matches is fine, as candidates are then scored. Emails are
+    /// only looked up `by_email`.
+    fn candidates(&self, query: &str, mandate: &str, fuzzy: bool, by_email: bool) -> Result<Vec<db::Person>, Status>;
+}
+
+/// The index searches use, if any, which is set once opened.
+#[derive(Default)]
+pub struct SearchIndex(OnceLock<Arc<dyn Index>>);
+
+impl SearchIndex {
+    #[cfg_attr(not(feature = "tantivy"), allow(dead_code))]
+    pub fn set(&self, index: Arc<dyn Index>) {
+        let _ = self.0.set(index);
+    }
+}
+
+/// The candidates for `query` meeting `criteria` from the queries of the
+/// repository each kind of match needs.
+fn candidates(query: &str, mandate: &str, fuzzy: bool, by_email: bool, criteria: &PersonFilter, repository: &dyn PersonRepository) -> Result<Vec<db::Person>, Status> {
+    let mut candidates: HashMap<i32, db::Person> = HashMap::new();
+    if let Some(email) = query.parse::<Email>().ok().filter(|_| by_email) {
+        match repository.find(&PersonKey::Email(email)) {
+            Ok(person) => {
+                candidates.insert(person.id, person);
+            }
+            Err(status) if status == Status::NotFound => {}
+            Err(status) => return Err(status),
+        }
+    }
+    let page = Page { limit: MAX_CANDIDATES, ..Default::default() };
+    let by_name = PersonFilter { name: Some(query.trim().to_string()), ..criteria.clone() };
+    let by_mandate = PersonFilter { mandate: Some(mandate.to_string()), ..criteria.clone() };
+    for person in repository.search_page(&by_name, &page)?.into_iter().chain(repository.search_page(&by_mandate, &page)?) {
+        candidates.insert(person.id, person);
+    }
+    let key = phonetic::key(query);
+    if fuzzy && !key.is_empty() {
+        for person in repository.search_page(&PersonFilter { phonetic: Some(key), ..criteria.clone() }, &page)? {
+            candidates.insert(person.id, person);
+        }
+    }
+    Ok(candidates.into_values().collect())
+}
+
+/// The matches of `query` meeting `criteria`, from `index` if given or
+/// else the repository, and their scores, best first; only persons the
+/// repository would hand out are returned, and only matched on their
+/// emails `by_email`.
+pub fn search(query: &str, fuzzy: bool, by_email: bool, criteria: &PersonFilter, repository: &dyn PersonRepository, index: Option<&dyn Index>, mandate_types: &MandateTypes) -> Result<Vec<(db::Person, u32)>, Status> {
+    let mandate = mandate_types.title(query.trim());
+    let candidates: Vec<db::Person> = match index {
+        Some(index) => index.candidates(query, &mandate, fuzzy, by_email)?.into_iter().filter(|person| person.visibility <= repository.audience()).collect(),
+        None => candidates(query, &mandate, fuzzy, by_email, criteria, repository)?,
+    };
+
+    let mut hits: Vec<(db::Person, u32)> = candidates
+        .into_iter()
+        // Criteria the queries replaced with their own, such as a name, still apply.
+        .filter(|person| criteria.matches(person))
+        .map(|person| {
+            let score = score(&person, query, &mandate, fuzzy, by_email);
+            (person, score)
+        })
+        .filter(|(_, score)| *score > 0)
+        .collect();
+    hits.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then_with(|| (&a.name, a.id).cmp(&(&b.name, b.id))));
+    Ok(hits)
+}
+
+#[get("/elus/search?<q>&<fuzzy>")]
+#[allow(clippy::too_many_arguments)]
+fn search_elus(
+    q: &str,
+    fuzzy: Option<bool>,
+    filters: Filters,
+    pagination: Pagination,
+    sort: SortSpec<PersonSort>,
+    _probe: Probe,
+    emails: EmailsShown,
+    repository: Visible,
+    index: &State<SearchIndex>,
+    mandate_types: &State<MandateTypes>,
+) -> Result<Redacted<Vec<Hit>>, Problem> {
+    if q.trim().is_empty() {
+        return Err(Status::BadRequest.into());
+    }
+    let page = match pagination.0 {
+        Some(page) if page.after.is_some() => {
+            return Err(Problem { detail: Some("after only follows listings by name; page search results by number.".to_string()), ..Problem::new(Status::BadRequest) });
+        }
+        Some(page) => page,
+        None => Page { limit: DEFAULT_LIMIT, ..Default::default() },
+    };
+
+    let mut hits = search(q, fuzzy.unwrap_or(false), emails.0, &filters.0, repository.as_ref(), index.0.get().map(|index| index.as_ref()), mandate_types)?;
+    if sort.key.is_some() {
+        hits.sort_by(|(a, _), (b, _)| sort.compare(a, b));
+    }
+    let hits = hits.into_iter().skip(page.offset as usize).take(page.limit as usize);
+    Ok(Redacted(hits.map(|(person, score)| Hit { elu: Person::from(person), score }).collect()))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![search_elus]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MemoryRepository;
+    use crate::tests::{admin, build_client, client, insert_test_persons, setup_test_db};
+
+    #[test]
+    fn test_ranking() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let search = |q: &str| -> Vec<(String, u32)> {
+            let hits: Vec<Hit> = client.get(format!("/elus/search?q={}", q)).dispatch().into_json().unwrap();
+            hits.into_iter().map(|hit| (hit.elu.name.to_string(), hit.score)).collect()
+        };
+
+        assert_eq!(search("pierre.durand@example.com"), [("Pierre Durand".to_string(), EXACT_EMAIL)]);
+        assert_eq!(search("mar"), [("Marie Martin".to_string(), NAME_PREFIX)]);
+        assert_eq!(search("dur"), [("Pierre Durand".to_string(), WORD_PREFIX)]);
+        assert_eq!(search("upon"), [("Jean Dupont".to_string(), NAME_SUBSTRING)]);
+        assert_eq!(search("maire"), [("Jean Dupont".to_string(), MANDATE)]);
+        assert!(search("dupond").is_empty());
+        assert_eq!(search("dupond&fuzzy=true"), [("Jean Dupont".to_string(), PHONETIC)]);
+        assert_eq!(search("durant&fuzzy=true"), [("Pierre Durand".to_string(), PHONETIC)]);
+        assert_eq!(client.get("/elus/search?q=%20").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_criteria() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let names = |query: &str| -> Vec<String> {
+            let hits: Vec<Hit> = client.get(format!("/elus/search?q=d&{}", query)).dispatch().into_json().unwrap();
+            hits.into_iter().map(|hit| hit.elu.name.to_string()).collect()
+        };
+
+        assert_eq!(names(""), ["Jean Dupont", "Pierre Durand"]);
+        assert_eq!(names("commune=75056"), ["Jean Dupont"]);
+        assert_eq!(names("limit=1"), ["Jean Dupont"]);
+        assert_eq!(names("page=2&per_page=1"), ["Pierre Durand"]);
+        assert_eq!(names("sort=-name"), ["Pierre Durand", "Jean Dupont"]);
+        assert_eq!(client.get("/elus/search?q=d&limit=0").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/elus/search?q=d&after=MTpKZWFu").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_candidates_are_capped() {
+        let repository = MemoryRepository::default();
+        for index in 0..MAX_CANDIDATES + 5 {
+            let commune_code = (index == MAX_CANDIDATES + 4).then(|| "75056".to_string());
+            let person = db::NewPerson { name: format!("Elu {:04}", index).parse().unwrap(), email: format!("elu{}@example.com", index).parse().unwrap(), commune_code, ..Default::default() };
+            repository.create(person).unwrap();
+        }
+        let search = |criteria: &PersonFilter| candidates("elu", "elu", true, false, criteria, &repository).unwrap();
+
+        assert_eq!(search(&PersonFilter::default()).len(), MAX_CANDIDATES as usize);
+        let in_paris = search(&PersonFilter { commune_code: Some("75056".to_string()), ..Default::default() });
+        assert_eq!(in_paris.iter().map(|person| person.name.to_string()).collect::<Vec<_>>(), [format!("Elu {:04}", MAX_CANDIDATES + 4)]);
+    }
+
+    #[test]
+    fn test_hidden_emails_dont_match() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = build_client(|figment| figment.merge(("redaction.public", ["email"])), connection);
+        let uri = "/elus/search?q=pierre.durand@example.com";
+
+        let hits: Vec<Value> = client.get(uri).dispatch().into_json().unwrap();
+        assert!(hits.is_empty());
+        let hits: Vec<Value> = client.get(uri).header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(hits[0]["score"], EXACT_EMAIL);
+    }
+}
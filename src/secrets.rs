@@ -0,0 +1,110 @@
+//! Secrets given through the environment, as Docker and Kubernetes provide
+//! them: a secret `NAME` is read from the file `NAME_FILE` points at if
+//! set, or else from the `NAME` variable itself. Those of the
+//! configuration take precedence over `Rocket.toml` and `ROCKET_*`
+//! variables.
+
+use std::fs;
+
+use rocket::figment::error::Kind;
+use rocket::figment::{self, Figment};
+
+/// Secrets of the configuration: their environment variable, and the key
+/// they set.
+const SECRETS: &[(&str, &str)] = &[
+    ("ADMIN_TOKEN", "admin_token"),
+    ("SMTP_PASSWORD", "smtp.password"),
+    ("S3_SECRET_KEY", "storage.secret_key"),
+    ("CRM_TOKEN", "sync.crm.token"),
+    ("PASSWORD_RESET_SECRET", "password_reset.secret"),
+    ("ENCRYPTION_KEY", "encryption.key"),
+    ("REPLICA_DATABASE_URL", "replica.database_url"),
+];
+
+fn resolve(name: &str, env: impl Fn(&str) -> Option<String>) -> Result<Option<String>, String> {
+    let file_variable = format!("{}_FILE", name);
+    match env(&file_variable) {
+        Some(path) => fs::read_to_string(&path)
+            .map(|secret| Some(secret.trim_end_matches(['\n', '\r']).to_string()))
+            .map_err(|e| format!("{}: could not read {}: {}", file_variable, path, e)),
+        None => Ok(env(name)),
+    }
+}
+
+/// The secret `name`, from `<name>_FILE` or `<name>`.
+pub fn var(name: &str) -> Result<Option<String>, String> {
+    resolve(name, |variable| std::env::var(variable).ok())
+}
+
+/// Like `var`, for secrets which must be set.
+pub fn required(name: &str) -> Result<String, String> {
+    var(name)?.ok_or_else(|| format!("{} (or {}_FILE) must be set", name, name))
+}
+
+fn merge_with(mut figment: Figment, env: impl Fn(&str) -> Option<String>) -> Result<Figment, String> {
+    for (name, key) in SECRETS {
+        if let Some(secret) = resolve(name, &env)? {
+            figment = figment.merge((*key, secret));
+        }
+    }
+    Ok(figment)
+}
+
+/// The configuration with the secrets set in the environment.
+pub fn merge(figment: Figment) -> Result<Figment, String> {
+    merge_with(figment, |variable| std::env::var(variable).ok())
+}
+
+/// Describes a configuration error, telling which variables provide a
+/// missing secret.
+pub fn explain(error: figment::Error) -> String {
+    let hint = error.clone().into_iter().find_map(|error| match &error.kind {
+        Kind::MissingField(field) => {
+            let key: Vec<&str> = error.path.iter().map(String::as_str).chain([field.as_ref()]).collect();
+            SECRETS.iter().find(|(_, secret)| *secret == key.join(".")).map(|(name, secret)| format!("{} is missing: set it, {} or {}_FILE", secret, name, name))
+        }
+        _ => None,
+    });
+    match hint {
+        Some(hint) => format!("{} ({})", error, hint),
+        None => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("smtp");
+        fs::write(&path, "from-file\n").unwrap();
+        let env: HashMap<String, String> = [
+            ("SMTP_PASSWORD_FILE".to_string(), path.to_str().unwrap().to_string()),
+            ("SMTP_PASSWORD".to_string(), "from-env".to_string()),
+            ("ADMIN_TOKEN".to_string(), "token".to_string()),
+            ("CRM_TOKEN_FILE".to_string(), dir.path().join("missing").to_str().unwrap().to_string()),
+        ]
+        .into();
+        let env = |variable: &str| env.get(variable).cloned();
+
+        assert_eq!(resolve("SMTP_PASSWORD", env), Ok(Some("from-file".to_string())));
+        assert_eq!(resolve("ADMIN_TOKEN", env), Ok(Some("token".to_string())));
+        assert_eq!(resolve("ENCRYPTION_KEY", env), Ok(None));
+        assert!(resolve("CRM_TOKEN", env).unwrap_err().starts_with("CRM_TOKEN_FILE: could not read"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let env = |variable: &str| (variable == "PASSWORD_RESET_SECRET").then(|| "s3cr3t".to_string());
+        let figment = Figment::new().merge(("password_reset.base_url", "https://annuaire.example"));
+
+        let config: AppConfig = merge_with(figment.clone(), env).unwrap().extract().unwrap();
+        assert_eq!(config.password_reset.unwrap().secret, "s3cr3t");
+        let error = figment.extract::<AppConfig>().unwrap_err();
+        assert!(explain(error).contains("password_reset.secret is missing: set it, PASSWORD_RESET_SECRET or PASSWORD_RESET_SECRET_FILE"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Cookie-backed sessions of dashboard users, kept apart from the bearer
+//! token and API keys used by API clients. The cookie only holds a random
+//! token; everything else stays in the `sessions` table, which stores the
+//! token's hash.
+
+use std::time::Duration;
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::Deserialize;
+
+use crate::config::AppConfig;
+use crate::schema::{sessions, users};
+use crate::sha256::{hex, sha256};
+use crate::users::User;
+use crate::{csrf, db, timestamp, DbConn};
+
+pub const COOKIE: &str = "rckd_session";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SessionConfig {
+    /// Seconds of inactivity after which users have to sign in again.
+    pub idle_timeout: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig { idle_timeout: 30 * 60 }
+    }
+}
+
+/// Request guard for a signed-in dashboard user, refusing state-changing
+/// requests without the session's CSRF token.
+#[derive(Debug)]
+pub struct Session {
+    id: String,
+    pub user: User,
+    /// Must be sent back with every state-changing request made during the
+    /// session; see `csrf`.
+    pub csrf_token: String,
+}
+
+fn token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn session_id(token: &str) -> String {
+    hex(&sha256(token.as_bytes()))
+}
+
+/// Opens a session for the user, returning the token to set as cookie.
+pub fn start(user: &User, idle_timeout: Duration, connection: &mut SqliteConnection) -> QueryResult<String> {
+    let token = token();
+    let now = timestamp::now();
+    connection.transaction(|connection| {
+        diesel::delete(sessions::table.filter(sessions::last_seen_at.lt(now - idle_timeout))).execute(connection)?;
+        diesel::insert_into(sessions::table)
+            .values((
+                sessions::id.eq(session_id(&token)),
+                sessions::user_id.eq(user.id),
+                sessions::csrf_token.eq(self::token()),
+                sessions::created_at.eq(now),
+                sessions::last_seen_at.eq(now),
+            ))
+            .execute(connection)
+    })?;
+    Ok(token)
+}
+
+/// The live session the token belongs to, whose idle timer is reset.
+pub fn resume(token: &str, idle_timeout: Duration, connection: &mut SqliteConnection) -> QueryResult<Option<Session>> {
+    let id = session_id(token);
+    let now = timestamp::now();
+    connection.transaction(|connection| {
+        let found = sessions::table
+            .inner_join(users::table)
+            .filter(sessions::id.eq(&id))
+            .select((sessions::csrf_token, sessions::last_seen_at, User::as_select()))
+            .first::<(String, time::PrimitiveDateTime, User)>(connection)
+            .optional()?;
+
+        match found {
+            Some((csrf_token, last_seen_at, user)) if last_seen_at >= now - idle_timeout => {
+                diesel::update(sessions::table.find(&id)).set(sessions::last_seen_at.eq(now)).execute(connection)?;
+                Ok(Some(Session { id, user, csrf_token }))
+            }
+            Some(_) => {
+                diesel::delete(sessions::table.find(&id)).execute(connection)?;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    })
+}
+
+pub fn end(session: &Session, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(sessions::table.find(&session.id)).execute(connection).map(|_| ())
+}
+
+pub fn cookie(token: String) -> Cookie<'static> {
+    Cookie::build((COOKIE, token)).path("/").http_only(true).secure(true).same_site(SameSite::Strict).build()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let cookies: &CookieJar<'_> = request.cookies();
+        let (Some(token), Some(db), Some(config)) =
+            (cookies.get(COOKIE), request.rocket().state::<DbConn>(), request.rocket().state::<AppConfig>())
+        else {
+            return request::Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        let Ok(mut connection) = db::lock(db) else {
+            return request::Outcome::Error((Status::ServiceUnavailable, ()));
+        };
+        let idle_timeout = Duration::from_secs(config.sessions.idle_timeout);
+        match resume(token.value(), idle_timeout, &mut connection) {
+            Ok(Some(session)) if csrf::is_unsafe(request.method()) && !csrf::verify(request, &session.csrf_token) => {
+                request::Outcome::Error((Status::Forbidden, ()))
+            }
+            Ok(Some(session)) => request::Outcome::Success(session),
+            Ok(None) => request::Outcome::Error((Status::Unauthorized, ())),
+            Err(_) => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup_test_db;
+    use crate::users::{self, NewUser};
+
+    #[test]
+    fn test_session_lifecycle() {
+        let mut connection = setup_test_db();
+        let new_user = NewUser { username: "secretariat".to_string(), password: "correct horse battery staple".to_string(), email: None };
+        let user = users::create(&new_user, &mut connection).unwrap();
+        let timeout = Duration::from_secs(60);
+
+        let token = start(&user, timeout, &mut connection).unwrap();
+        let session = resume(&token, timeout, &mut connection).unwrap().expect("a live session");
+        assert_eq!(session.user.username, "secretariat");
+        assert!(resume("forged", timeout, &mut connection).unwrap().is_none());
+
+        // Idle sessions are over, and removed.
+        diesel::update(sessions::table)
+            .set(sessions::last_seen_at.eq(timestamp::now() - Duration::from_secs(61)))
+            .execute(&mut connection)
+            .unwrap();
+        assert!(resume(&token, timeout, &mut connection).unwrap().is_none());
+        assert_eq!(sessions::table.count().get_result::<i64>(&mut connection), Ok(0));
+
+        let token = start(&user, timeout, &mut connection).unwrap();
+        let session = resume(&token, timeout, &mut connection).unwrap().unwrap();
+        end(&session, &mut connection).unwrap();
+        assert!(resume(&token, timeout, &mut connection).unwrap().is_none());
+    }
+}
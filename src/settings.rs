@@ -0,0 +1,242 @@
+//! Settings of each collectivity, that is each commune, at
+//! `/collectivites/<code>/settings`: the name it goes by, an address to
+//! contact it at, the visibility its new elus get when none is given and
+//! the locale of its pages. Profile pages and the embedded widget are
+//! rendered in its locale with its name and contact in the footer, and
+//! vCards and exports carry its name as organization. Communes nobody
+//! configured get the defaults: their own name, no contact, public elus,
+//! French.
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::dashboard::escape;
+use crate::email::Email;
+use crate::mandate_types::DEFAULT_LANGUAGE;
+use crate::schema::{collectivite_settings, communes};
+use crate::visibility::Visibility;
+use crate::{db, DbConn};
+
+/// Longest display name.
+const MAX_NAME_LEN: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Settings {
+    /// INSEE code of the commune.
+    pub code: String,
+    pub display_name: String,
+    pub contact_email: Option<Email>,
+    pub default_visibility: Visibility,
+    /// Language tag, such as `fr` or `fr-FR`.
+    pub locale: String,
+}
+
+/// The settings an admin sets; unset ones get their default.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewSettings {
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub contact_email: Option<Email>,
+    #[serde(default)]
+    pub default_visibility: Visibility,
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = collectivite_settings, treat_none_as_null = true)]
+struct SettingsRow {
+    code: String,
+    display_name: Option<String>,
+    contact_email: Option<Email>,
+    default_visibility: Visibility,
+    locale: String,
+}
+
+/// Whether `locale` looks like a language tag: a language, optionally
+/// followed by a region, as in `fr-FR`.
+fn is_locale(locale: &str) -> bool {
+    let (language, region) = match locale.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (locale, None),
+    };
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && region.is_none_or(|region| region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()))
+}
+
+/// The settings of the commune, `None` if there's no such commune.
+pub fn find(code: &str, connection: &mut SqliteConnection) -> QueryResult<Option<Settings>> {
+    let Some(name) = communes::table.find(code).select(communes::name).first::<String>(connection).optional()? else {
+        return Ok(None);
+    };
+    let row = collectivite_settings::table.find(code).select(SettingsRow::as_select()).first(connection).optional()?;
+
+    Ok(Some(match row {
+        Some(row) => Settings {
+            code: row.code,
+            display_name: row.display_name.unwrap_or(name),
+            contact_email: row.contact_email,
+            default_visibility: row.default_visibility,
+            locale: row.locale,
+        },
+        None => Settings {
+            code: code.to_string(),
+            display_name: name,
+            contact_email: None,
+            default_visibility: Visibility::default(),
+            locale: DEFAULT_LANGUAGE.to_string(),
+        },
+    }))
+}
+
+/// The settings of the communes among `codes`, by code, leaving out the
+/// unknown ones.
+pub fn find_all<'a>(codes: impl IntoIterator<Item = &'a str>, connection: &mut SqliteConnection) -> QueryResult<HashMap<String, Settings>> {
+    let mut found = HashMap::new();
+    for code in codes {
+        if !found.contains_key(code) {
+            if let Some(settings) = find(code, connection)? {
+                found.insert(code.to_string(), settings);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Adds the display name of their commune to serialized persons, as
+/// `organization`.
+pub fn add_organizations(persons: &mut [Value], settings: &HashMap<String, Settings>) {
+    for person in persons {
+        let organization = person["commune_code"].as_str().and_then(|code| settings.get(code)).map(|settings| settings.display_name.clone());
+        if let (Some(organization), Value::Object(object)) = (organization, person) {
+            object.insert("organization".to_string(), Value::from(organization));
+        }
+    }
+}
+
+/// The visibility of new elus of the commune, if any, which don't say.
+pub fn default_visibility(commune_code: Option<&str>, connection: &mut SqliteConnection) -> QueryResult<Visibility> {
+    let Some(code) = commune_code else {
+        return Ok(Visibility::default());
+    };
+    Ok(find(code, connection)?.map(|settings| settings.default_visibility).unwrap_or_default())
+}
+
+/// The `lang` of pages about the commune, if any.
+pub fn lang(settings: Option<&Settings>) -> &str {
+    settings.map_or(DEFAULT_LANGUAGE, |settings| settings.locale.as_str())
+}
+
+/// The footer of pages about the commune: its name and contact.
+pub fn footer(settings: &Settings) -> String {
+    let contact = settings
+        .contact_email
+        .as_ref()
+        .map(|email| format!(" · <a href=\"mailto:{0}\">{0}</a>", escape(email)))
+        .unwrap_or_default();
+    format!("<footer>{}{}</footer>\n", escape(&settings.display_name), contact)
+}
+
+#[get("/collectivites/<code>/settings")]
+fn get_settings(code: &str, db: &State<DbConn>) -> Result<Json<Settings>, Status> {
+    find(code, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?.map(Json).ok_or(Status::NotFound)
+}
+
+#[put("/collectivites/<code>/settings", data = "<new_settings>")]
+fn update_settings(code: &str, new_settings: Json<NewSettings>, _admin: Admin, db: &State<DbConn>) -> Result<Json<Settings>, Status> {
+    let new_settings = new_settings.into_inner();
+    let display_name = new_settings.display_name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty());
+    let locale = new_settings.locale.unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+    if display_name.as_ref().is_some_and(|name| name.chars().count() > MAX_NAME_LEN) || !is_locale(&locale) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut connection = db::lock(db)?;
+    if find(code, &mut connection).map_err(|_| Status::InternalServerError)?.is_none() {
+        return Err(Status::NotFound);
+    }
+    let row = SettingsRow { code: code.to_string(), display_name, contact_email: new_settings.contact_email, default_visibility: new_settings.default_visibility, locale };
+    diesel::insert_into(collectivite_settings::table)
+        .values(&row)
+        .on_conflict(collectivite_settings::code)
+        .do_update()
+        .set(&row)
+        .execute(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    find(code, &mut connection).map_err(|_| Status::InternalServerError)?.map(Json).ok_or(Status::NotFound)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_settings, update_settings]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::tests::{admin, client, setup_test_db};
+    use rocket::http::Accept;
+    use rocket::serde::json::{json, Value};
+
+    #[test]
+    fn test_is_locale() {
+        assert!(is_locale("fr"));
+        assert!(is_locale("fr-FR"));
+        assert!(is_locale("oci"));
+        assert!(!is_locale("FR"));
+        assert!(!is_locale("fr_FR"));
+        assert!(!is_locale("fr-"));
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut connection = setup_test_db();
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        let client = client(connection);
+
+        let settings: Settings = client.get("/collectivites/75056/settings").dispatch().into_json().unwrap();
+        assert_eq!((settings.display_name.as_str(), settings.locale.as_str(), settings.default_visibility), ("Paris", "fr", Visibility::Public));
+        assert_eq!(client.get("/collectivites/99999/settings").dispatch().status(), Status::NotFound);
+
+        let update = json!({ "display_name": "Ville de Paris", "contact_email": "elus@paris.example", "default_visibility": "internal", "locale": "fr-FR" });
+        assert_eq!(client.put("/collectivites/75056/settings").json(&update).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.put("/collectivites/75056/settings").header(admin()).json(&json!({ "locale": "français" })).dispatch().status(), Status::UnprocessableEntity);
+        assert_eq!(client.put("/collectivites/99999/settings").header(admin()).json(&update).dispatch().status(), Status::NotFound);
+        let settings: Settings = client.put("/collectivites/75056/settings").header(admin()).json(&update).dispatch().into_json().unwrap();
+        assert_eq!((settings.display_name.as_str(), settings.locale.as_str()), ("Ville de Paris", "fr-FR"));
+
+        // New elus of the commune are internal unless they say otherwise.
+        for (email, visibility) in [("luc@example.com", None), ("anne@example.com", Some("public"))] {
+            let mut person = json!({ "name": "Luc Bernard", "email": email, "mandates": ["Maire"], "commune_code": "75056" });
+            if let Some(visibility) = visibility {
+                person["visibility"] = json!(visibility);
+                person["name"] = json!("Anne Petit");
+            }
+            assert_eq!(client.post("/elus/create").header(admin()).json(&person).dispatch().status(), Status::Ok);
+        }
+        let elu: Value = client.get("/elus/luc@example.com").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(elu["visibility"], "internal");
+        let elu: Value = client.get("/elus/anne@example.com").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(elu["visibility"], "public");
+
+        let html = client.get("/elus/anne@example.com").header(Accept::HTML).dispatch().into_string().unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"fr-FR\">"));
+        assert!(html.contains("<footer>Ville de Paris · <a href=\"mailto:elus@paris.example\">elus@paris.example</a></footer>"));
+        let widget = client.get("/embed/elus?commune=75056").dispatch().into_string().unwrap();
+        assert!(widget.contains("<h2>Ville de Paris</h2>"));
+        assert!(widget.contains("<html lang=\"fr-FR\">"));
+    }
+}
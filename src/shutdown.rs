@@ -0,0 +1,84 @@
+//! Orderly shutdown. On SIGTERM or Ctrl-C, Rocket stops accepting
+//! connections and gives in-flight requests `shutdown.grace` seconds to
+//! complete (see `Rocket.toml`) while the shutdown fairing runs: the
+//! background workers stop between two runs, those working off a queue
+//! after a last run so that due mail, webhook deliveries and sync changes go
+//! out, and the SQLite write-ahead log is then checkpointed into the
+//! database file.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use rocket::fairing::AdHoc;
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::Interval;
+use rocket::{Orbit, Rocket, Shutdown};
+
+use crate::{db, DbConn};
+
+/// How long workers get to finish their last run before they are cancelled
+/// and the database is checkpointed regardless.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The background worker tasks, waited for on shutdown.
+#[derive(Default)]
+pub struct Workers(Mutex<Vec<JoinHandle<()>>>);
+
+/// Keeps track of a worker spawned at liftoff.
+pub fn track(rocket: &Rocket<Orbit>, worker: JoinHandle<()>) {
+    rocket.state::<Workers>().expect("workers are managed").0.lock().unwrap().push(worker);
+}
+
+/// Waits for the next tick of `interval`; false once shutdown started,
+/// for the worker to stop.
+pub async fn tick(interval: &mut Interval, shutdown: &Shutdown) -> bool {
+    rocket::tokio::select! {
+        _ = interval.tick() => true,
+        _ = shutdown.clone() => false,
+    }
+}
+
+/// Checkpoints the write-ahead log, if the database uses one, and empties it.
+fn checkpoint(db: &DbConn) {
+    if let Err(e) = db::lock(db).map_err(Into::into).and_then(|mut connection| connection.batch_execute("PRAGMA wal_checkpoint(TRUNCATE)")) {
+        log::error!("Could not checkpoint the database: {}", e);
+    }
+}
+
+pub fn fairing() -> AdHoc {
+    AdHoc::on_shutdown("Orderly shutdown", |rocket| Box::pin(async move {
+        let workers = std::mem::take(&mut *rocket.state::<Workers>().expect("workers are managed").0.lock().unwrap());
+        for mut worker in workers {
+            if rocket::tokio::time::timeout(WORKER_TIMEOUT, &mut worker).await.is_err() {
+                log::warn!("A background worker didn't stop within {} seconds, cancelling it", WORKER_TIMEOUT.as_secs());
+                worker.abort();
+            }
+        }
+
+        checkpoint(rocket.state::<DbConn>().expect("database connection is managed"));
+        log::info!("Background work flushed, shutting down");
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mail::Message;
+    use crate::mail_queue::{self, SENT};
+    use crate::schema::mail_queue as queue;
+    use crate::tests::{build_client, setup_test_db};
+    use crate::DbConn;
+    use diesel::prelude::*;
+
+    #[test]
+    fn test_flushes_queue_on_shutdown() {
+        let client = build_client(|figment| figment.merge(("mail_queue.poll_interval", 3600)), setup_test_db());
+        let db = client.rocket().state::<DbConn>().unwrap().clone();
+        let message = Message { to: "jean@example.com".to_string(), subject: "Bonjour".to_string(), body: "...".to_string() };
+        mail_queue::enqueue(&message, None, &Default::default(), &mut db.lock().unwrap()).unwrap();
+
+        client.terminate();
+        let status: String = queue::table.select(queue::status).first(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!(status, SENT);
+    }
+}
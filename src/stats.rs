@@ -0,0 +1,132 @@
+//! Statistics for the observatory reports: `GET /stats/communes` counts
+//! the elus of each commune by mandate, as JSON or, with `format=csv`, as
+//! the CSV the reports are made from. Counts are grouped in SQL, over the
+//! elus the caller may see.
+
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use rocket::http::{Header, Status};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+
+use crate::import::csv_field;
+use crate::mandate_types::DEFAULT_LANGUAGE;
+use crate::visibility::{Visibility, Visible};
+use crate::{db, DbConn};
+
+#[derive(Debug, Serialize, QueryableByName)]
+#[serde(crate = "rocket::serde")]
+pub struct CommuneStats {
+    /// Unset for the elus of no commune.
+    #[diesel(sql_type = Nullable<Text>)]
+    pub commune_code: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub commune_name: Option<String>,
+    /// The stored title, and the code of its type when it's of a known one.
+    #[diesel(sql_type = Text)]
+    pub mandate: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub mandate_type: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub elus: i64,
+}
+
+/// The elus holding each mandate in each commune, among those of the
+/// visibilities `audience` may see, by commune and mandate.
+pub fn by_commune(audience: Visibility, connection: &mut SqliteConnection) -> QueryResult<Vec<CommuneStats>> {
+    let seen: Vec<Visibility> = audience.seen().collect();
+    let mut query = diesel::sql_query(format!(
+        "SELECT elus.commune_code, communes.name AS commune_name, mandates.title AS mandate, mandate_labels.code AS mandate_type, \
+         COUNT(DISTINCT elus.id) AS elus \
+         FROM mandates JOIN elus ON elus.id = mandates.elu_id \
+         LEFT JOIN communes ON communes.code = elus.commune_code \
+         LEFT JOIN mandate_labels ON mandate_labels.label = mandates.title AND mandate_labels.language = ? \
+         WHERE elus.visibility IN ({}) \
+         GROUP BY elus.commune_code, communes.name, mandates.title, mandate_labels.code \
+         ORDER BY elus.commune_code, mandates.title",
+        vec!["?"; seen.len()].join(", ")
+    ))
+    .into_boxed::<Sqlite>()
+    .bind::<Text, _>(DEFAULT_LANGUAGE);
+    for visibility in seen {
+        query = query.bind::<Text, _>(visibility.as_str());
+    }
+    query.load(connection)
+}
+
+pub fn csv(stats: &[CommuneStats]) -> String {
+    let mut csv = "commune_code,commune_name,mandate,mandate_type,elus\n".to_string();
+    for row in stats {
+        let fields = [row.commune_code.as_deref(), row.commune_name.as_deref(), Some(row.mandate.as_str()), row.mandate_type.as_deref()];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field.unwrap_or_default())).collect();
+        csv.push_str(&format!("{},{}\n", fields.join(","), row.elus));
+    }
+    csv
+}
+
+#[derive(Responder)]
+enum Stats {
+    Json(Json<Vec<CommuneStats>>),
+    #[response(content_type = "text/csv")]
+    Csv(String, Header<'static>),
+}
+
+/// Counts of elus by commune and mandate; `format` is `json`, the default,
+/// or `csv`.
+#[get("/stats/communes?<format>")]
+fn commune_stats(format: Option<&str>, db: &State<DbConn>, repository: Visible) -> Result<Stats, Status> {
+    let stats = by_commune(repository.audience(), &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+    match format.unwrap_or("json") {
+        "json" => Ok(Stats::Json(Json(stats))),
+        "csv" => Ok(Stats::Csv(csv(&stats), Header::new("Content-Disposition", "attachment; filename=\"communes.csv\""))),
+        _ => Err(Status::BadRequest),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![commune_stats]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::elus;
+    use rocket::http::ContentType;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::{json, Value};
+
+    #[test]
+    fn test_commune_stats() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        diesel::update(elus::table.filter(elus::email.eq("marie.martin@example.com")))
+            .set(elus::visibility.eq(Visibility::Hidden))
+            .execute(&mut connection)
+            .unwrap();
+        let client = client(connection);
+
+        let stats: Vec<Value> = client.get("/stats/communes").dispatch().into_json().unwrap();
+        let rows: Vec<(&Value, &Value, &Value)> = stats.iter().map(|row| (&row["commune_code"], &row["mandate"], &row["elus"])).collect();
+        assert_eq!(
+            rows,
+            [
+                (&Value::Null, &json!("Conseiller municipal"), &json!(1)),
+                (&Value::Null, &json!("Sénateur"), &json!(1)),
+                (&json!("75056"), &json!("Conseiller régional"), &json!(1)),
+                (&json!("75056"), &json!("Maire"), &json!(1)),
+            ]
+        );
+        assert_eq!(stats[3]["commune_name"], "Paris");
+
+        let response = client.get("/stats/communes?format=csv").header(admin()).dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        let csv = response.into_string().unwrap();
+        assert_eq!(csv.lines().next(), Some("commune_code,commune_name,mandate,mandate_type,elus"));
+        assert!(csv.lines().any(|line| line == "75056,Paris,Députée,,1"));
+        assert_eq!(client.get("/stats/communes?format=xlsx").dispatch().status(), Status::BadRequest);
+    }
+}
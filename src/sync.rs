@@ -0,0 +1,352 @@
+//! Outbound synchronization of the directory with external systems. The
+//! creations and updates recorded as change events (see `events`) are
+//! queued for each configured sync target, and a background worker pushes
+//! the queue in order, retrying failures with an exponential backoff. Changes still failing after `max_attempts`
+//! are set aside as dead letters for an administrator to look into and
+//! retry; later changes to the same person carry on without them.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+
+use crate::auth::Admin;
+use crate::crm::{CrmConfig, CrmTarget};
+use crate::db::{self, Person};
+use crate::encryption::Sealer;
+use crate::events::ChangeKind;
+use crate::schema::sync_queue;
+use crate::{shutdown, timeouts, timestamp, DbConn};
+
+pub const QUEUED: &str = "queued";
+pub const SYNCED: &str = "synced";
+pub const DEAD: &str = "dead";
+
+const BATCH_SIZE: i64 = 100;
+
+/// An external system kept up to date with the directory. Pushes may be
+/// repeated after a failure, so they should be idempotent.
+#[rocket::async_trait]
+pub trait SyncTarget: Send + Sync {
+    /// Identifies the target's queue; changing it orphans pending changes.
+    fn name(&self) -> &str;
+    async fn push(&self, kind: ChangeKind, person: &Person) -> Result<(), String>;
+}
+
+pub type SyncTargets = Vec<Arc<dyn SyncTarget>>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SyncConfig {
+    /// CRM whose REST API gets created and updated elus.
+    pub crm: Option<CrmConfig>,
+    /// Seconds between two runs of the sync worker; 0 disables the worker.
+    pub poll_interval: u64,
+    /// Attempts after which a change becomes a dead letter.
+    pub max_attempts: i32,
+    /// Delay, in seconds, before the first retry; doubled after each failure.
+    pub retry_delay: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            crm: None,
+            poll_interval: 30,
+            max_attempts: 8,
+            retry_delay: 60,
+        }
+    }
+}
+
+impl SyncConfig {
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = (attempts - 1).clamp(0, 16) as u32;
+        Duration::from_secs(self.retry_delay.saturating_mul(1 << exponent))
+    }
+}
+
+pub fn from_config(config: &SyncConfig) -> SyncTargets {
+    let mut targets: SyncTargets = Vec::new();
+    if let Some(crm) = &config.crm {
+        targets.push(Arc::new(CrmTarget::new(crm.clone())));
+    }
+    targets
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = sync_queue)]
+#[serde(crate = "rocket::serde")]
+pub struct QueuedChange {
+    pub id: i32,
+    pub target: String,
+    pub kind: String,
+    pub elu_id: i32,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub next_attempt_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Queues a change for each of the targets; meant to be called within the
+/// transaction recording its event.
+pub fn enqueue(kind: ChangeKind, person: &Person, targets: &[String], sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<usize> {
+    let payload = sealer.seal_person(person);
+    let rows: Vec<_> = targets
+        .iter()
+        .map(|target| {
+            (
+                sync_queue::target.eq(target),
+                sync_queue::kind.eq(kind.as_str()),
+                sync_queue::elu_id.eq(person.id),
+                sync_queue::payload.eq(&payload),
+                sync_queue::next_attempt_at.eq(timestamp::now()),
+            )
+        })
+        .collect();
+    diesel::insert_into(sync_queue::table).values(rows).execute(connection)
+}
+
+/// Pushes the changes due at `now`, in order: a change waiting for a retry
+/// holds back the later changes to the same person for the same target.
+/// Returns the number of changes attempted.
+pub async fn process_due(db: &DbConn, targets: &[Arc<dyn SyncTarget>], config: &SyncConfig, sealer: &Sealer, now: PrimitiveDateTime) -> QueryResult<usize> {
+    let queued = sync_queue::table
+        .filter(sync_queue::status.eq(QUEUED))
+        .order(sync_queue::id)
+        .limit(BATCH_SIZE)
+        .select(QueuedChange::as_select())
+        .load(&mut *db::lock(db)?)?;
+
+    let mut held_back = HashSet::new();
+    let mut attempted = 0;
+    for change in queued {
+        let key = (change.target.clone(), change.elu_id);
+        if held_back.contains(&key) {
+            continue;
+        }
+        if change.next_attempt_at > now {
+            held_back.insert(key);
+            continue;
+        }
+        // Changes for a target that's no longer configured stay queued.
+        let Some(target) = targets.iter().find(|target| target.name() == change.target) else {
+            continue;
+        };
+
+        let person = sealer.open_person(&change.payload).map_err(|_| "undecryptable payload".to_string());
+        let result = match (change.kind.parse::<ChangeKind>(), person.and_then(|person| serde_json::from_value::<Person>(person).map_err(|e| format!("invalid payload: {}", e)))) {
+            (Ok(kind), Ok(person)) => target.push(kind, &person).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        attempted += 1;
+
+        let attempts = change.attempts + 1;
+        let row = sync_queue::table.find(change.id);
+        let mut connection = db::lock(db)?;
+        match result {
+            Ok(()) => diesel::update(row)
+                .set((sync_queue::status.eq(SYNCED), sync_queue::attempts.eq(attempts), sync_queue::synced_at.eq(now)))
+                .execute(&mut *connection)?,
+            Err(e) if attempts >= config.max_attempts => {
+                log::warn!("Giving up syncing change {} to {}: {}", change.id, change.target, e);
+                diesel::update(row)
+                    .set((sync_queue::status.eq(DEAD), sync_queue::attempts.eq(attempts), sync_queue::last_error.eq(e)))
+                    .execute(&mut *connection)?
+            }
+            Err(e) => {
+                held_back.insert(key);
+                diesel::update(row)
+                    .set((
+                        sync_queue::attempts.eq(attempts),
+                        sync_queue::last_error.eq(e),
+                        sync_queue::next_attempt_at.eq(now + config.backoff(attempts)),
+                    ))
+                    .execute(&mut *connection)?
+            }
+        };
+    }
+
+    Ok(attempted)
+}
+
+/// Background worker pushing the queue.
+pub fn fairing(config: SyncConfig) -> AdHoc {
+    AdHoc::on_liftoff("Sync worker", move |rocket| Box::pin(async move {
+        let targets = rocket.state::<SyncTargets>().expect("sync targets are managed").clone();
+        if config.poll_interval == 0 || targets.is_empty() {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(config.poll_interval));
+            loop {
+                // Pushes what's due one last time when shutting down.
+                let running = shutdown::tick(&mut interval, &shutdown).await;
+                match rocket::tokio::time::timeout(job, process_due(&db, &targets, &config, &sealer, timestamp::now())).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => log::error!("Sync run failed: {}", e),
+                    Err(_) => log::warn!("Sync run cancelled after {} seconds", job.as_secs()),
+                }
+                if !running {
+                    break;
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+/// Changes that couldn't be pushed after all their attempts.
+#[get("/admin/sync/dead-letters")]
+fn dead_letters(_admin: Admin, db: &State<DbConn>, sealer: &State<Sealer>) -> Result<Json<Vec<QueuedChange>>, Status> {
+    let dead: Vec<QueuedChange> = sync_queue::table
+        .filter(sync_queue::status.eq(DEAD))
+        .order(sync_queue::id)
+        .select(QueuedChange::as_select())
+        .load(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+    dead.into_iter()
+        .map(|change| Ok(QueuedChange { payload: sealer.open_person(&change.payload).map_err(|_| Status::InternalServerError)?.to_string(), ..change }))
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
+
+/// Puts a dead letter back in the queue, with a fresh set of attempts.
+#[post("/admin/sync/dead-letters/<id>/retry")]
+fn retry_dead_letter(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let requeued = diesel::update(sync_queue::table.find(id).filter(sync_queue::status.eq(DEAD)))
+        .set((sync_queue::status.eq(QUEUED), sync_queue::attempts.eq(0), sync_queue::next_attempt_at.eq(timestamp::now())))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if requeued == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![dead_letters, retry_dead_letter]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::Cipher;
+    use crate::tests::{admin, build_client, setup_test_db};
+    use std::sync::Mutex;
+    use time::macros::datetime;
+
+    /// Records pushes; persons whose email ends in `@down.example` fail.
+    #[derive(Default)]
+    struct RecordingTarget {
+        pushed: Mutex<Vec<(ChangeKind, String)>>,
+    }
+
+    #[rocket::async_trait]
+    impl SyncTarget for RecordingTarget {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn push(&self, kind: ChangeKind, person: &Person) -> Result<(), String> {
+            if person.email.ends_with("@down.example") {
+                return Err("503 Service Unavailable".to_string());
+            }
+            self.pushed.lock().unwrap().push((kind, person.name.to_string()));
+            Ok(())
+        }
+    }
+
+    fn person(id: i32, name: &str, email: &str) -> Person {
+        Person {
+            id,
+            uuid: format!("00000000-0000-4000-8000-{:012}", id),
+            name: name.parse().unwrap(),
+            email: email.parse().unwrap(),
+            emails: vec![],
+            mandates: vec![],
+            commune_code: None,
+            office_address: None,
+            latitude: None,
+            longitude: None,
+            may_contact_by_email: false,
+            consent_date: None,
+            consent_source: None,
+            visibility: Default::default(),
+            custom: Default::default(),
+            email_status: "unknown".to_string(),
+            updated_at: datetime!(2030-01-01 12:00:00),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_process_due() {
+        let mut connection = setup_test_db();
+        let targets = ["recording".to_string()];
+        let sealer = Sealer::new(Cipher::new(&[7; 32]));
+        enqueue(ChangeKind::Created, &person(1, "Jean Dupont", "jean@down.example"), &targets, &sealer, &mut connection).unwrap();
+        enqueue(ChangeKind::Updated, &person(1, "Jean Dupont", "jean@mairie.example"), &targets, &sealer, &mut connection).unwrap();
+        enqueue(ChangeKind::Created, &person(2, "Marie Martin", "marie@mairie.example"), &targets, &sealer, &mut connection).unwrap();
+        let payloads: Vec<String> = sync_queue::table.select(sync_queue::payload).load(&mut connection).unwrap();
+        assert!(payloads.iter().all(|payload| payload.contains("Jean Dupont") || payload.contains("Marie Martin")));
+        assert!(!payloads.iter().any(|payload| payload.contains("example")));
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        let recording = Arc::new(RecordingTarget::default());
+        let targets: SyncTargets = vec![recording.clone()];
+        let config = SyncConfig { max_attempts: 2, ..Default::default() };
+
+        // Jean's update waits for his creation to go through.
+        let now = timestamp::now();
+        assert_eq!(process_due(&db, &targets, &config, &sealer, now).await, Ok(2));
+        assert_eq!(*recording.pushed.lock().unwrap(), [(ChangeKind::Created, "Marie Martin".to_string())]);
+        assert_eq!(process_due(&db, &targets, &config, &sealer, now).await, Ok(0));
+
+        // Once dead, it no longer holds back the update.
+        let later = now + config.backoff(1);
+        assert_eq!(process_due(&db, &targets, &config, &sealer, later).await, Ok(2));
+        assert_eq!(recording.pushed.lock().unwrap().last(), Some(&(ChangeKind::Updated, "Jean Dupont".to_string())));
+
+        let dead: Vec<QueuedChange> = sync_queue::table.filter(sync_queue::status.eq(DEAD)).select(QueuedChange::as_select()).load(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!((dead.len(), dead[0].attempts, dead[0].last_error.as_deref()), (1, 2, Some("503 Service Unavailable")));
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let client = build_client(|figment| figment.merge(("sync.crm.base_url", "http://crm.invalid")).merge(("sync.crm.token", "secret")), setup_test_db());
+        let response = client
+            .post("/elus/create")
+            .json(&rocket::serde::json::json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": [] }))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let db = client.rocket().state::<DbConn>().unwrap().clone();
+        let queued: QueuedChange = sync_queue::table.select(QueuedChange::as_select()).first(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!((queued.target.as_str(), queued.kind.as_str()), ("crm", "created"));
+        diesel::update(sync_queue::table).set(sync_queue::status.eq(DEAD)).execute(&mut *db.lock().unwrap()).unwrap();
+
+        assert_eq!(client.get("/admin/sync/dead-letters").dispatch().status(), Status::Unauthorized);
+        let response = client.get("/admin/sync/dead-letters").header(admin()).dispatch();
+        assert_eq!(response.into_json::<Vec<QueuedChange>>().map(|dead| dead.len()), Some(1));
+
+        let uri = format!("/admin/sync/dead-letters/{}/retry", queued.id);
+        assert_eq!(client.post(uri.clone()).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.post(uri).header(admin()).dispatch().status(), Status::NotFound);
+        let status: String = sync_queue::table.select(sync_queue::status).first(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!(status, QUEUED);
+    }
+}
@@ -0,0 +1,313 @@
+//! Free-form tags staff put on elus to organize the directory beyond their
+//! mandates, such as "bureau-municipal". Tags are created on first use, or
+//! beforehand with a description, and `GET /elus?tag=<name>` lists the
+//! elus having one. `GET /tags/complete?q=<text>` suggests the most used
+//! tags for what's being typed. Anyone can read tags; only administrators
+//! can change them.
+
+use std::sync::Arc;
+
+use diesel::dsl::count;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::db::{self, escape_like};
+use crate::repository::{PersonKey, PersonRepository};
+use crate::schema::{elu_tags, tags};
+use crate::scraping::Probe;
+use crate::transaction::Transaction;
+use crate::visibility::Visible;
+use crate::DbConn;
+
+/// Longest tag name, in characters.
+const MAX_LENGTH: usize = 50;
+
+/// Suggestions returned when no limit is given, and at most.
+const DEFAULT_SUGGESTIONS: i64 = 10;
+const MAX_SUGGESTIONS: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+pub struct Tag {
+    pub name: String,
+    pub description: Option<String>,
+    /// How many elus have the tag.
+    pub elus: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewTag {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// The tag as stored: lowercased, with hyphens between words. Tags are made
+/// of letters, digits, `-` and `_`, so they can be used in paths as they
+/// are.
+pub fn normalize(name: &str) -> Option<String> {
+    let normalized = name.split_whitespace().collect::<Vec<_>>().join("-").to_lowercase();
+    let valid = !normalized.is_empty()
+        && normalized.chars().count() <= MAX_LENGTH
+        && normalized.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(normalized)
+}
+
+/// The tags matching `pattern`, a `LIKE` pattern, with how many elus have
+/// each; the most used first if `by_use`, else by name.
+fn load(pattern: Option<&str>, by_use: bool, limit: Option<i64>, connection: &mut SqliteConnection) -> QueryResult<Vec<Tag>> {
+    let mut query = tags::table
+        .left_join(elu_tags::table)
+        .group_by((tags::id, tags::name, tags::description))
+        .select((tags::name, tags::description, count(elu_tags::elu_id.nullable())))
+        .into_boxed();
+    if let Some(pattern) = pattern {
+        query = query.filter(tags::name.like(pattern).escape('\\'));
+    }
+    query = if by_use {
+        query.order((count(elu_tags::elu_id.nullable()).desc(), tags::name))
+    } else {
+        query.order(tags::name)
+    };
+    if let Some(limit) = limit {
+        query = query.limit(limit);
+    }
+    query.load(connection)
+}
+
+fn find(name: &str, connection: &mut SqliteConnection) -> Result<Tag, Status> {
+    let name = normalize(name).ok_or(Status::NotFound)?;
+    load(Some(&escape_like(&name)), false, None, connection)
+        .map_err(|_| Status::InternalServerError)?
+        .pop()
+        .ok_or(Status::NotFound)
+}
+
+fn id_of(name: &str, connection: &mut SqliteConnection) -> QueryResult<Option<i32>> {
+    tags::table.filter(tags::name.eq(name)).select(tags::id).first(connection).optional()
+}
+
+fn conflict_or_internal(error: diesel::result::Error) -> Status {
+    match error {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+        _ => Status::InternalServerError,
+    }
+}
+
+/// Ids of the elus having the tag; none for unknown tags.
+pub fn tagged(name: &str, connection: &mut SqliteConnection) -> QueryResult<Vec<i32>> {
+    let Some(name) = normalize(name) else {
+        return Ok(Vec::new());
+    };
+    elu_tags::table
+        .inner_join(tags::table)
+        .filter(tags::name.eq(name))
+        .select(elu_tags::elu_id)
+        .load(connection)
+}
+
+/// Removes every tag of an elu, before the elu itself is deleted.
+pub fn delete_all(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(elu_tags::table.filter(elu_tags::elu_id.eq(elu_id))).execute(connection)?;
+    Ok(())
+}
+
+#[get("/tags")]
+fn list_tags(db: &State<DbConn>) -> Result<Json<Vec<Tag>>, Status> {
+    load(None, false, None, &mut *db::lock(db)?).map(Json).map_err(|_| Status::InternalServerError)
+}
+
+/// The tags having a word starting with `q`, the most used first.
+#[get("/tags/complete?<q>&<limit>")]
+fn complete_tags(q: &str, limit: Option<i64>, db: &State<DbConn>) -> Result<Json<Vec<Tag>>, Status> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGESTIONS);
+    let prefix = normalize(q).ok_or(Status::BadRequest)?;
+    if !(1..=MAX_SUGGESTIONS).contains(&limit) {
+        return Err(Status::BadRequest);
+    }
+
+    let mut connection = db::lock(db)?;
+    let mut suggestions = load(Some(&format!("{}%", escape_like(&prefix))), true, Some(limit), &mut connection)
+        .map_err(|_| Status::InternalServerError)?;
+    if (suggestions.len() as i64) < limit {
+        let within = load(Some(&format!("%-{}%", escape_like(&prefix))), true, Some(limit), &mut connection)
+            .map_err(|_| Status::InternalServerError)?;
+        for tag in within {
+            if suggestions.len() as i64 == limit {
+                break;
+            }
+            if !suggestions.contains(&tag) {
+                suggestions.push(tag);
+            }
+        }
+    }
+
+    Ok(Json(suggestions))
+}
+
+#[get("/tags/<name>")]
+fn get_tag(name: &str, db: &State<DbConn>) -> Result<Json<Tag>, Status> {
+    find(name, &mut *db::lock(db)?).map(Json)
+}
+
+#[post("/tags", data = "<new_tag>")]
+fn create_tag(new_tag: Json<NewTag>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Created<Json<Tag>>, Status> {
+    let name = normalize(&new_tag.name).ok_or(Status::UnprocessableEntity)?;
+    let mut connection = db::lock(db)?;
+    diesel::insert_into(tags::table)
+        .values((tags::name.eq(&name), tags::description.eq(&new_tag.description)))
+        .execute(&mut *connection)
+        .map_err(conflict_or_internal)?;
+
+    let tag = Tag { name, description: new_tag.into_inner().description, elus: 0 };
+    Ok(Created::new(format!("/tags/{}", tag.name)).body(Json(tag)))
+}
+
+/// Renames a tag or changes its description; elus keep it.
+#[put("/tags/<name>", data = "<new_tag>")]
+fn update_tag(name: &str, new_tag: Json<NewTag>, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Json<Tag>, Status> {
+    let new_name = normalize(&new_tag.name).ok_or(Status::UnprocessableEntity)?;
+    let mut connection = db::lock(db)?;
+    let current = find(name, &mut connection)?;
+    diesel::update(tags::table.filter(tags::name.eq(&current.name)))
+        .set((tags::name.eq(&new_name), tags::description.eq(&new_tag.description)))
+        .execute(&mut *connection)
+        .map_err(conflict_or_internal)?;
+
+    Ok(Json(Tag { name: new_name, description: new_tag.into_inner().description, elus: current.elus }))
+}
+
+/// Deletes a tag, taking it off every elu having it.
+#[delete("/tags/<name>")]
+fn delete_tag(name: &str, _admin: Admin, _transaction: Transaction, db: &State<DbConn>) -> Result<Status, Status> {
+    let name = normalize(name).ok_or(Status::NotFound)?;
+    let deleted = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            let Some(id) = id_of(&name, connection)? else {
+                return Ok(0);
+            };
+            diesel::delete(elu_tags::table.filter(elu_tags::tag_id.eq(id))).execute(connection)?;
+            diesel::delete(tags::table.find(id)).execute(connection)
+        })
+        .map_err(|_: diesel::result::Error| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// The tags of the elu, by name.
+#[get("/elus/<key>/tags")]
+fn list_elu_tags(key: &str, _probe: Probe, db: &State<DbConn>, repository: Visible) -> Result<Json<Vec<String>>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    elu_tags::table
+        .inner_join(tags::table)
+        .filter(elu_tags::elu_id.eq(elu.id))
+        .order(tags::name)
+        .select(tags::name)
+        .load(&mut *db::lock(db)?)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Puts the tag on the elu, creating the tag if it's new.
+#[put("/elus/<key>/tags/<name>")]
+fn tag_elu(key: &str, name: &str, _admin: Admin, _transaction: Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let name = normalize(name).ok_or(Status::UnprocessableEntity)?;
+    db::lock(db)?
+        .transaction(|connection| {
+            let id = match id_of(&name, connection)? {
+                Some(id) => id,
+                None => diesel::insert_into(tags::table).values(tags::name.eq(&name)).returning(tags::id).get_result(connection)?,
+            };
+            diesel::insert_or_ignore_into(elu_tags::table)
+                .values((elu_tags::elu_id.eq(elu.id), elu_tags::tag_id.eq(id)))
+                .execute(connection)
+        })
+        .map_err(|_: diesel::result::Error| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+#[delete("/elus/<key>/tags/<name>")]
+fn untag_elu(key: &str, name: &str, _admin: Admin, _transaction: Transaction, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let name = normalize(name).ok_or(Status::NotFound)?;
+    let mut connection = db::lock(db)?;
+    let id = id_of(&name, &mut connection).map_err(|_| Status::InternalServerError)?.ok_or(Status::NotFound)?;
+    let deleted = diesel::delete(elu_tags::table.find((elu.id, id)))
+        .execute(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_tags, complete_tags, get_tag, create_tag, update_tag, delete_tag, list_elu_tags, tag_elu, untag_elu]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(" Bureau  Municipal "), Some("bureau-municipal".to_string()));
+        assert_eq!(normalize("élu_référent"), Some("élu_référent".to_string()));
+        assert_eq!(normalize("a/b"), None);
+        assert_eq!(normalize("  "), None);
+        assert_eq!(normalize(&"x".repeat(51)), None);
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+        let names = |path: &str| -> Vec<String> {
+            let persons: Vec<rocket::serde::json::Value> = client.get(path).dispatch().into_json().unwrap();
+            persons.iter().map(|person| person["name"].as_str().unwrap().to_string()).collect()
+        };
+
+        assert_eq!(client.put("/elus/jean.dupont@example.com/tags/bureau-municipal").dispatch().status(), Status::Unauthorized);
+        for (elu, tag) in [("jean.dupont@example.com", "Bureau Municipal"), ("marie.martin@example.com", "bureau-municipal"), ("marie.martin@example.com", "budget")] {
+            let response = client.put(format!("/elus/{}/tags/{}", elu, tag.replace(' ', "%20"))).header(admin()).dispatch();
+            assert_eq!(response.status(), Status::NoContent);
+        }
+        let response = client.post("/tags").header(admin()).json(&json!({ "name": "Commission Finances", "description": "Membres de la commission" })).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Location"), Some("/tags/commission-finances"));
+        assert_eq!(client.post("/tags").header(admin()).json(&json!({ "name": "budget" })).dispatch().status(), Status::Conflict);
+
+        assert_eq!(names("/elus?tag=bureau-municipal"), ["Jean Dupont", "Marie Martin"]);
+        assert_eq!(names("/elus?tag=budget&name=marie"), ["Marie Martin"]);
+        assert!(names("/elus?tag=inconnu").is_empty());
+        let tags: Vec<String> = client.get("/elus/marie.martin@example.com/tags").dispatch().into_json().unwrap();
+        assert_eq!(tags, ["budget", "bureau-municipal"]);
+
+        let suggestions: Vec<Tag> = client.get("/tags/complete?q=bu").dispatch().into_json().unwrap();
+        let suggested: Vec<(&str, i64)> = suggestions.iter().map(|tag| (tag.name.as_str(), tag.elus)).collect();
+        assert_eq!(suggested, [("bureau-municipal", 2), ("budget", 1)]);
+        let suggestions: Vec<Tag> = client.get("/tags/complete?q=fin").dispatch().into_json().unwrap();
+        assert_eq!(suggestions[0].name, "commission-finances");
+
+        let renamed: Tag = client.put("/tags/budget").header(admin()).json(&json!({ "name": "finances" })).dispatch().into_json().unwrap();
+        assert_eq!((renamed.name.as_str(), renamed.elus), ("finances", 1));
+        assert_eq!(names("/elus?tag=finances"), ["Marie Martin"]);
+        assert_eq!(client.delete("/elus/marie.martin@example.com/tags/finances").header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete("/tags/bureau-municipal").header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.get("/tags/bureau-municipal").dispatch().status(), Status::NotFound);
+        let tags: Vec<Tag> = client.get("/tags").dispatch().into_json().unwrap();
+        assert_eq!(tags.iter().map(|tag| (tag.name.as_str(), tag.elus)).collect::<Vec<_>>(), [("commission-finances", 0), ("finances", 0)]);
+    }
+}
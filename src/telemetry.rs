@@ -0,0 +1,376 @@
+//! Request tracing with `tracing` spans, exported to an OpenTelemetry
+//! collector such as Jaeger over OTLP/HTTP, JSON-encoded, by the
+//! `tracing-opentelemetry` layer of the subscriber installed at liftoff.
+//! Every request gets a server span, continuing the trace of its W3C
+//! `traceparent` header if it has one; the repository calls made while
+//! handling it get child spans, and webhook deliveries get spans of their
+//! own, passing their trace on to the receiver. Finished spans are batched
+//! and exported every `export_interval` seconds.
+//!
+//! Repository calls are attributed to the request handled by the task
+//! making them, so calls made off that task, such as blocking exports, are
+//! not traced.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor, Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{HeaderMap, Status};
+use rocket::serde::Deserialize;
+use rocket::tokio::task;
+use rocket::{Data, Request, Response};
+use tracing::field::Empty;
+use tracing::{info_span, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::db::{NewPerson, Person};
+use crate::deliverability::EmailStatus;
+use crate::email::Email;
+use crate::repository::{Page, PersonFilter, PersonKey, PersonRepository};
+use crate::shutdown;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TracingConfig {
+    /// Base URL of the OTLP/HTTP collector, such as Jaeger's
+    /// `http://jaeger:4318`; spans are POSTed to `/v1/traces` below it.
+    pub endpoint: String,
+    /// Share of the traces started here which are recorded, from 0 to 1.
+    /// Requests with a `traceparent` header follow its sampling decision.
+    pub sampling: f64,
+    /// `service.name` the spans are reported under.
+    pub service_name: String,
+    /// Seconds between two exports; 0 disables the exporter.
+    pub export_interval: u64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            endpoint: "http://localhost:4318".to_string(),
+            sampling: 1.0,
+            service_name: "rckd".to_string(),
+            export_interval: 5,
+        }
+    }
+}
+
+/// The provider batching the spans, sampled as configured, and exporting
+/// them to the collector.
+pub fn provider(config: &TracingConfig) -> Result<SdkTracerProvider, String> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(format!("{}/v1/traces", config.endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|e| format!("invalid tracing configuration: {}", e))?;
+    let batches = BatchConfigBuilder::default().with_scheduled_delay(Duration::from_secs(config.export_interval)).build();
+
+    Ok(SdkTracerProvider::builder()
+        .with_span_processor(BatchSpanProcessor::builder(exporter).with_batch_config(batches).build())
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling))))
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build())
+}
+
+/// The subscriber handing the `tracing` spans over to `provider`.
+pub fn subscriber(provider: &SdkTracerProvider) -> impl Subscriber + Send + Sync {
+    tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer("rckd")))
+}
+
+/// The `traceparent` header passing the trace of `span` on.
+pub fn traceparent(span: &Span) -> Option<String> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut headers);
+    headers.remove("traceparent")
+}
+
+struct Headers<'a>(&'a HeaderMap<'a>);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_one(key)
+    }
+
+    /// The headers of the trace context, the only ones looked up.
+    fn keys(&self) -> Vec<&str> {
+        ["traceparent", "tracestate"].into_iter().filter(|name| self.0.contains(name)).collect()
+    }
+}
+
+/// Keeps track of the span of the request each task is handling.
+#[derive(Default)]
+pub struct Tracer {
+    current: Mutex<HashMap<task::Id, Span>>,
+}
+
+impl Tracer {
+    fn enter(&self, span: Span) {
+        if let Some(id) = task::try_id() {
+            self.current.lock().unwrap().insert(id, span);
+        }
+    }
+
+    fn exit(&self) {
+        if let Some(id) = task::try_id() {
+            self.current.lock().unwrap().remove(&id);
+        }
+    }
+
+    fn current(&self) -> Option<Span> {
+        self.current.lock().unwrap().get(&task::try_id()?).cloned()
+    }
+
+    /// Runs `operation` in a child span of the current request's, if there
+    /// is one.
+    fn in_span<T>(&self, name: &'static str, operation: impl FnOnce() -> Result<T, Status>) -> Result<T, Status> {
+        let Some(parent) = self.current() else {
+            return operation();
+        };
+
+        let span = info_span!(parent: &parent, "repository", otel.name = name, error.type = Empty, otel.status_code = Empty);
+        let result = span.in_scope(operation);
+        if let Err(status) = &result {
+            span.record("error.type", status.code.to_string().as_str());
+            if status.code >= 500 {
+                span.record("otel.status_code", "error");
+            }
+        }
+        result
+    }
+}
+
+/// The span of a request, until it's answered.
+struct RequestSpan(Mutex<Option<Span>>);
+
+/// Fairing giving every request a server span.
+pub struct Telemetry;
+
+#[rocket::async_trait]
+impl Fairing for Telemetry {
+    fn info(&self) -> Info {
+        Info { name: "Request tracing", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let Some(tracer) = request.rocket().state::<Arc<Tracer>>() else {
+            return;
+        };
+
+        let method = request.method().as_str();
+        let span = info_span!(
+            "request",
+            otel.name = method,
+            otel.kind = "server",
+            otel.status_code = Empty,
+            http.request.method = method,
+            http.route = Empty,
+            http.response.status_code = Empty,
+            url.path = request.uri().path().as_str(),
+        );
+        // Without a valid header, the span starts a trace of its own.
+        let _ = span.set_parent(TraceContextPropagator::new().extract(&Headers(request.headers())));
+        tracer.enter(span.clone());
+        request.local_cache(|| RequestSpan(Mutex::new(Some(span))));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(tracer) = request.rocket().state::<Arc<Tracer>>() else {
+            return;
+        };
+        let Some(span) = request.local_cache(|| RequestSpan(Mutex::new(None))).0.lock().unwrap().take() else {
+            return;
+        };
+
+        tracer.exit();
+        if let Some(route) = request.route() {
+            span.record("otel.name", format!("{} {}", request.method(), route.uri.origin.path()).as_str());
+            span.record("http.route", route.uri.origin.path().as_str());
+        }
+        span.record("http.response.status_code", i64::from(response.status().code));
+        if response.status().code >= 500 {
+            span.record("otel.status_code", "error");
+        }
+    }
+}
+
+/// Repository giving each call a span of the request making it.
+pub struct TracedRepository {
+    inner: Arc<dyn PersonRepository>,
+    tracer: Arc<Tracer>,
+}
+
+impl TracedRepository {
+    pub fn new(inner: Arc<dyn PersonRepository>, tracer: Arc<Tracer>) -> Self {
+        TracedRepository { inner, tracer }
+    }
+}
+
+impl PersonRepository for TracedRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        self.tracer.in_span("PersonRepository::list", || self.inner.list())
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::find", || self.inner.find(key))
+    }
+
+    fn get(&self, id: i32) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::get", || self.inner.get(id))
+    }
+
+    fn find_alias(&self, email: &Email) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::find_alias", || self.inner.find_alias(email))
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        self.tracer.in_span("PersonRepository::get_many", || self.inner.get_many(emails))
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::create", || self.inner.create(person))
+    }
+
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::update", || self.inner.update(email, person))
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        self.tracer.in_span("PersonRepository::update_many", || self.inner.update_many(updates))
+    }
+
+    fn delete(&self, email: &Email) -> Result<Person, Status> {
+        self.tracer.in_span("PersonRepository::delete", || self.inner.delete(email))
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        self.tracer.in_span("PersonRepository::search", || self.inner.search(filter))
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        self.tracer.in_span("PersonRepository::search_page", || self.inner.search_page(filter, page))
+    }
+
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status> {
+        self.tracer.in_span("PersonRepository::set_email_status", || self.inner.set_email_status(id, status))
+    }
+}
+
+/// Installs the subscriber exporting the spans, and flushes them on
+/// shutdown.
+pub fn fairing(config: TracingConfig) -> AdHoc {
+    AdHoc::on_liftoff("Span exporter", move |rocket| Box::pin(async move {
+        if config.export_interval == 0 {
+            return;
+        }
+
+        let provider = match provider(&config) {
+            Ok(provider) => provider,
+            Err(e) => return log::error!("Not exporting spans: {}", e),
+        };
+        if tracing::subscriber::set_global_default(subscriber(&provider)).is_err() {
+            return log::warn!("Not exporting spans: another tracing subscriber is installed");
+        }
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            shutdown.await;
+            // The exporter's HTTP client blocks.
+            let flushed = task::spawn_blocking(move || provider.shutdown()).await;
+            if let Ok(Err(e)) = flushed {
+                log::warn!("Could not export the last spans: {}", e);
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{build_client, insert_test_persons, setup_test_db};
+    use opentelemetry::trace::{SpanKind, TraceContextExt, TraceId};
+    use opentelemetry::{KeyValue, Value};
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use rocket::http::Header;
+    use tracing::instrument::WithSubscriber;
+
+    /// A subscriber, for the current thread, keeping the spans in memory.
+    fn record() -> (InMemorySpanExporter, SdkTracerProvider, tracing::subscriber::DefaultGuard) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let guard = tracing::subscriber::set_default(subscriber(&provider));
+        (exporter, provider, guard)
+    }
+
+    #[rocket::async_test]
+    async fn test_repository_spans() {
+        let (exporter, _provider, _guard) = record();
+        let tracer = Arc::new(Tracer::default());
+        let db = Arc::new(Mutex::new(setup_test_db()));
+        insert_test_persons(&mut db.lock().unwrap());
+        let repository = TracedRepository::new(Arc::new(crate::db::SqliteRepository::new(db)), tracer.clone());
+
+        repository.list().unwrap();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        let request = info_span!("request", otel.kind = "server");
+        let traced = tracer.clone();
+        let entered = request.clone();
+        rocket::tokio::spawn(
+            async move {
+                traced.enter(entered);
+                assert_eq!(repository.find(&PersonKey::Email("nobody@example.com".parse().unwrap())).err(), Some(Status::NotFound));
+                traced.exit();
+            }
+            .with_current_subscriber(),
+        )
+        .await
+        .unwrap();
+        drop(request);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "PersonRepository::find");
+        assert_eq!(spans[0].span_context.trace_id(), spans[1].span_context.trace_id());
+        assert_eq!(spans[0].parent_span_id, spans[1].span_context.span_id());
+        assert!(spans[0].attributes.contains(&KeyValue::new("error.type", "404")));
+        assert_eq!(spans[1].span_kind, SpanKind::Server);
+    }
+
+    #[test]
+    fn test_request_spans() {
+        let client = build_client(|figment| figment.merge(("tracing.export_interval", 0)), setup_test_db());
+        let (exporter, _provider, _guard) = record();
+
+        client.get("/elus/nobody@example.com").header(Header::new("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")).dispatch();
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = spans.iter().find(|span| span.span_kind == SpanKind::Server).unwrap();
+        assert_eq!(span.name, "GET /elus/<key>");
+        assert_eq!(span.span_context.trace_id(), TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap());
+        assert_eq!(span.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert!(span.attributes.contains(&KeyValue::new("http.response.status_code", Value::I64(404))));
+
+        // A trace the caller didn't sample isn't recorded.
+        exporter.reset();
+        client.get("/elus/nobody@example.com").header(Header::new("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")).dispatch();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let (_exporter, _provider, _guard) = record();
+        let span = info_span!("webhook");
+        let header = traceparent(&span).unwrap();
+        let trace_id = span.context().span().span_context().trace_id();
+        assert!(header.starts_with(&format!("00-{}-", trace_id)) && header.ends_with("-01"));
+        assert_eq!(traceparent(&Span::none()), None);
+    }
+}
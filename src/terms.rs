@@ -0,0 +1,170 @@
+//! Dates of mandates: when each of an elu's mandates started and when it
+//! ends, set by administrators at `/elus/<key>/terms/<mandate>`, the
+//! mandate being given by title or code. Terms of mandates the elu no
+//! longer holds are kept, but not listed.
+
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::Date;
+
+use crate::auth::Admin;
+use crate::mandate_types::MandateTypes;
+use crate::repository::{PersonKey, PersonRepository};
+use crate::schema::mandate_terms;
+use crate::visibility::Visible;
+use crate::{db, timestamp, DbConn};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = mandate_terms)]
+#[serde(crate = "rocket::serde")]
+pub struct Term {
+    pub title: String,
+    #[serde(with = "timestamp::date::option")]
+    pub started_on: Option<Date>,
+    #[serde(with = "timestamp::date::option")]
+    pub ends_on: Option<Date>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewTerm {
+    #[serde(default, with = "timestamp::date::option")]
+    started_on: Option<Date>,
+    #[serde(default, with = "timestamp::date::option")]
+    ends_on: Option<Date>,
+}
+
+/// The terms of the elu's mandates.
+pub fn terms_of(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<Vec<Term>> {
+    mandate_terms::table
+        .filter(mandate_terms::elu_id.eq(elu_id))
+        .order(mandate_terms::title)
+        .select(Term::as_select())
+        .load(connection)
+}
+
+#[get("/elus/<key>/terms")]
+fn list_terms(key: &str, db: &State<DbConn>, repository: Visible) -> Result<Json<Vec<Term>>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let terms = terms_of(elu.id, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(terms.into_iter().filter(|term| elu.mandates.contains(&term.title)).collect()))
+}
+
+/// Sets the dates of one of the elu's mandates. Changing the end date
+/// allows the coming end to be announced again.
+#[put("/elus/<key>/terms/<mandate>", data = "<term>")]
+fn set_term(
+    key: &str,
+    mandate: &str,
+    term: Json<NewTerm>,
+    _admin: Admin,
+    db: &State<DbConn>,
+    repository: &State<Arc<dyn PersonRepository>>,
+    mandate_types: &State<MandateTypes>,
+) -> Result<Json<Term>, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let title = mandate_types.title(mandate);
+    if !elu.mandates.contains(&title) {
+        return Err(Status::NotFound);
+    }
+    if term.started_on.zip(term.ends_on).is_some_and(|(started_on, ends_on)| started_on > ends_on) {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut connection = db::lock(db)?;
+    let current = mandate_terms::table
+        .find((elu.id, &title))
+        .select(mandate_terms::ends_on)
+        .first::<Option<Date>>(&mut *connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?;
+    let values = (
+        mandate_terms::elu_id.eq(elu.id),
+        mandate_terms::title.eq(&title),
+        mandate_terms::started_on.eq(term.started_on),
+        mandate_terms::ends_on.eq(term.ends_on),
+    );
+    let saved = match current {
+        Some(ends_on) if ends_on == term.ends_on => diesel::update(mandate_terms::table.find((elu.id, &title))).set(values).returning(Term::as_returning()).get_result(&mut *connection),
+        _ => diesel::replace_into(mandate_terms::table).values(values).returning(Term::as_returning()).get_result(&mut *connection),
+    };
+
+    saved.map(Json).map_err(|_| Status::InternalServerError)
+}
+
+#[delete("/elus/<key>/terms/<mandate>")]
+fn delete_term(key: &str, mandate: &str, _admin: Admin, db: &State<DbConn>, repository: &State<Arc<dyn PersonRepository>>, mandate_types: &State<MandateTypes>) -> Result<Status, Status> {
+    let elu = repository.find(&PersonKey::parse(key)?)?;
+    let deleted = diesel::delete(mandate_terms::table.find((elu.id, mandate_types.title(mandate))))
+        .execute(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// Starts a new term of the mandate on `started_on`, replacing the last one.
+pub fn open(elu_id: i32, title: &str, started_on: Date, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::replace_into(mandate_terms::table)
+        .values((mandate_terms::elu_id.eq(elu_id), mandate_terms::title.eq(title), mandate_terms::started_on.eq(started_on)))
+        .execute(connection)?;
+    Ok(())
+}
+
+/// Ends the current term of the mandate on `ends_on`.
+pub fn close(elu_id: i32, title: &str, ends_on: Date, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::insert_into(mandate_terms::table)
+        .values((mandate_terms::elu_id.eq(elu_id), mandate_terms::title.eq(title), mandate_terms::ends_on.eq(ends_on)))
+        .on_conflict((mandate_terms::elu_id, mandate_terms::title))
+        .do_update()
+        .set(mandate_terms::ends_on.eq(ends_on))
+        .execute(connection)?;
+    Ok(())
+}
+
+/// Deletes the terms of an elu, before the elu itself is deleted.
+pub fn delete_all(elu_id: i32, connection: &mut SqliteConnection) -> QueryResult<()> {
+    diesel::delete(mandate_terms::table.filter(mandate_terms::elu_id.eq(elu_id))).execute(connection)?;
+    Ok(())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_terms, set_term, delete_term]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, client, insert_test_persons, setup_test_db};
+    use rocket::serde::json::json;
+    use time::macros::date;
+
+    #[test]
+    fn test_terms() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let uri = "/elus/jean.dupont@example.com/terms/Maire";
+        let term = json!({ "started_on": "2020-07-03", "ends_on": "2026-03-22" });
+        assert_eq!(client.put(uri).json(&term).dispatch().status(), Status::Unauthorized);
+        let saved: Term = client.put(uri).header(admin()).json(&term).dispatch().into_json().unwrap();
+        assert_eq!(saved, Term { title: "Maire".to_string(), started_on: Some(date!(2020 - 07 - 03)), ends_on: Some(date!(2026 - 03 - 22)) });
+
+        let backwards = json!({ "started_on": "2026-03-22", "ends_on": "2020-07-03" });
+        assert_eq!(client.put(uri).header(admin()).json(&backwards).dispatch().status(), Status::UnprocessableEntity);
+        let response = client.put("/elus/jean.dupont@example.com/terms/Adjoint").header(admin()).json(&term).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let terms: Vec<Term> = client.get("/elus/jean.dupont@example.com/terms").dispatch().into_json().unwrap();
+        assert_eq!(terms, [saved]);
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,130 @@
+//! Synthetic elus for load tests and pagination benchmarks, which need
+//! realistic volumes: `POST /admin/testdata?count=100000` inserts that many
+//! made-up persons, a batch per transaction so that the writer isn't held
+//! for the whole run. Only built with the `testdata` feature, which
+//! production builds leave off.
+//!
+//! Persons go straight to the database: no change events are recorded for
+//! them, and their emails, all at `example.invalid`, are stored in the
+//! clear until the next startup seals them when encryption is on.
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::custom_fields::CustomValues;
+use crate::db::{self, DbError, NewPerson};
+use crate::schema::communes;
+use crate::visibility::Visibility;
+use crate::DbConn;
+
+/// Most persons one request may create.
+const MAX_COUNT: usize = 1_000_000;
+
+/// Persons inserted per transaction.
+const BATCH_SIZE: usize = 1_000;
+
+const FIRST_NAMES: &[&str] = &[
+    "Jean", "Marie", "Pierre", "Anne", "Michel", "Isabelle", "Philippe", "Catherine", "Alain", "Sylvie", "Nicolas", "Nathalie", "François",
+    "Hélène", "Éric", "Christine", "Laurent", "Valérie", "Stéphane", "Sophie",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Martin", "Bernard", "Dubois", "Thomas", "Robert", "Richard", "Petit", "Durand", "Leroy", "Moreau", "Simon", "Laurent", "Lefèvre",
+    "Michel", "Garcia", "David", "Bertrand", "Roux", "Vincent", "Fournier",
+];
+
+const MANDATES: &[&str] = &["Maire", "Adjoint au maire", "Conseiller municipal", "Conseiller départemental", "Conseiller régional", "Député", "Sénateur"];
+
+/// A made-up person, with the `index`th email of the run.
+fn person(run: &str, index: usize, communes: &[String], rng: &mut impl Rng) -> NewPerson {
+    let name = format!("{} {}", FIRST_NAMES.choose(rng).unwrap(), LAST_NAMES.choose(rng).unwrap());
+    let held = rng.gen_range(1..=2);
+    let mandates = MANDATES.choose_multiple(rng, held).map(|mandate| mandate.to_string()).collect();
+    let located = rng.gen_bool(0.8);
+
+    NewPerson {
+        name: name.parse().expect("valid name"),
+        email: format!("testdata-{}-{}@example.invalid", run, index).parse().expect("valid email"),
+        emails: Vec::new(),
+        mandates,
+        commune_code: communes.choose(rng).cloned(),
+        office_address: None,
+        // Within metropolitan France.
+        latitude: located.then(|| rng.gen_range(42.5..51.0)),
+        longitude: located.then(|| rng.gen_range(-4.5..8.0)),
+        may_contact_by_email: rng.gen_bool(0.5),
+        consent_date: None,
+        consent_source: None,
+        visibility: Visibility::Public,
+        custom: CustomValues::default(),
+    }
+}
+
+/// Inserts `count` made-up persons, spread over the known communes; the
+/// connection is released between batches for other requests to go on.
+pub fn generate(count: usize, db: &DbConn) -> Result<usize, DbError> {
+    let communes: Vec<String> = communes::table.select(communes::code).load(&mut *db::lock(db)?)?;
+    let run = format!("{:08x}", rand::random::<u32>());
+    let mut rng = rand::thread_rng();
+
+    let mut created = 0;
+    while created < count {
+        let batch = BATCH_SIZE.min(count - created);
+        db::lock(db)?.transaction(|connection: &mut SqliteConnection| {
+            for index in created..created + batch {
+                db::insert_person(&person(&run, index, &communes, &mut rng), connection)?;
+            }
+            QueryResult::Ok(())
+        })?;
+        created += batch;
+    }
+    Ok(created)
+}
+
+#[post("/admin/testdata?<count>")]
+fn create_testdata(count: usize, _admin: Admin, db: &State<DbConn>) -> Result<Json<Value>, Status> {
+    if !(1..=MAX_COUNT).contains(&count) {
+        return Err(Status::UnprocessableEntity);
+    }
+    let created = generate(count, db)?;
+    Ok(Json(json!({ "created": created })))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![create_testdata]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::elus;
+    use crate::tests::{admin, client, setup_test_db};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_testdata() {
+        let mut connection = setup_test_db();
+        let paris = db::Commune { code: "75056".to_string(), name: "Paris".to_string(), department: "75".to_string() };
+        db::upsert_communes(&[paris], &mut connection).unwrap();
+        let db: DbConn = Arc::new(Mutex::new(connection));
+        assert_eq!(generate(BATCH_SIZE + 5, &db).unwrap(), BATCH_SIZE + 5);
+        let codes: Vec<Option<String>> = elus::table.select(elus::commune_code).load(&mut *db.lock().unwrap()).unwrap();
+        assert_eq!(codes.len(), BATCH_SIZE + 5);
+        assert!(codes.iter().all(|code| code.as_deref() == Some("75056")));
+
+        let connection = Arc::into_inner(db).unwrap().into_inner().unwrap();
+        let client = client(connection);
+        assert_eq!(client.post("/admin/testdata?count=10").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/admin/testdata?count=0").header(admin()).dispatch().status(), Status::UnprocessableEntity);
+        let response = client.post("/admin/testdata?count=10").header(admin()).dispatch();
+        assert_eq!(response.into_json::<Value>().unwrap()["created"], 10);
+        let persons: Vec<Value> = client.get("/elus").dispatch().into_json().unwrap();
+        assert_eq!(persons.len(), BATCH_SIZE + 15);
+    }
+}
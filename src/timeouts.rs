@@ -0,0 +1,98 @@
+//! Time limits on work which could otherwise hang a worker indefinitely.
+//! Exports, imports and document downloads answer 504 once they exceed the
+//! request timeout, and a background job run exceeding the job timeout is
+//! cancelled, its work being picked up again by the next run.
+
+use std::future::Future;
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::serde::Deserialize;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::timeout;
+use rocket::{Orbit, Rocket};
+
+use crate::config::AppConfig;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TimeoutConfig {
+    /// Seconds exports, imports and document downloads may take.
+    pub request: u64,
+    /// Seconds a run of a background worker may take.
+    pub job: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig { request: 30, job: 300 }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn request(&self) -> Duration {
+        Duration::from_secs(self.request)
+    }
+
+    pub fn job(&self) -> Duration {
+        Duration::from_secs(self.job)
+    }
+}
+
+/// The job timeout of the launched application.
+pub fn job(rocket: &Rocket<Orbit>) -> Duration {
+    rocket.state::<AppConfig>().map(|config| config.timeouts.job()).unwrap_or_else(|| TimeoutConfig::default().job())
+}
+
+/// Runs blocking work, such as repository calls, off the async workers,
+/// giving up on it after `limit`. Blocking calls can't be interrupted: the
+/// work then runs to completion unobserved.
+pub async fn blocking<T: Send + 'static>(limit: Duration, work: impl FnOnce() -> Result<T, Status> + Send + 'static) -> Result<T, Status> {
+    match timeout(limit, spawn_blocking(work)).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            log::error!("Blocking work panicked: {}", e);
+            Err(Status::InternalServerError)
+        }
+        Err(_) => Err(Status::GatewayTimeout),
+    }
+}
+
+/// Awaits `work`, cancelling it after `limit`.
+pub async fn within<T>(limit: Duration, work: impl Future<Output = Result<T, Status>>) -> Result<T, Status> {
+    timeout(limit, work).await.unwrap_or(Err(Status::GatewayTimeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, setup_test_db};
+    use crate::DbConn;
+
+    #[rocket::async_test]
+    async fn test_gives_up_after_limit() {
+        let slow = blocking(Duration::from_millis(10), || {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(())
+        });
+        assert_eq!(slow.await, Err(Status::GatewayTimeout));
+        assert_eq!(blocking(Duration::from_secs(1), || Ok(3)).await, Ok(3));
+
+        let pending = within(Duration::from_millis(10), std::future::pending::<Result<(), Status>>());
+        assert_eq!(pending.await, Err(Status::GatewayTimeout));
+    }
+
+    #[test]
+    fn test_timed_out_import() {
+        let client = build_client(|figment| figment.merge(("timeouts.request", 0)), setup_test_db());
+        let db = client.rocket().state::<DbConn>().unwrap().clone();
+
+        // The import waits for the connection for as long as it's held.
+        let connection = db.lock().unwrap();
+        let csv = "TYPECOM,COM,DEP,LIBELLE\nCOM,01001,01,L'Abergement-Clémenciat\n";
+        let response = client.post("/communes/import").header(admin()).body(csv).dispatch();
+        drop(connection);
+        assert_eq!(response.status(), Status::GatewayTimeout);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Timestamps are stored in SQLite as UTC `PrimitiveDateTime`s (what
+//! `CURRENT_TIMESTAMP` produces) and exposed in the API as RFC 3339.
+
+use rocket::serde::{Deserialize, Deserializer, Serializer};
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+pub fn now() -> PrimitiveDateTime {
+    let now = OffsetDateTime::now_utc();
+    PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// A point in time given in a query: an RFC 3339 timestamp, or a date
+/// standing for its midnight UTC.
+pub fn parse_point(value: &str) -> Option<PrimitiveDateTime> {
+    if let Ok(at) = OffsetDateTime::parse(value, &Rfc3339) {
+        let utc = at.to_offset(time::UtcOffset::UTC);
+        return Some(PrimitiveDateTime::new(utc.date(), utc.time()));
+    }
+    let date = time::Date::parse(value, time::macros::format_description!("[year]-[month]-[day]")).ok()?;
+    Some(PrimitiveDateTime::new(date, time::Time::MIDNIGHT))
+}
+
+/// `#[serde(with = "timestamp::rfc3339")]` for `PrimitiveDateTime` fields.
+pub mod rfc3339 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(timestamp: &PrimitiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let formatted = timestamp.assume_utc().format(&Rfc3339).map_err(rocket::serde::ser::Error::custom)?;
+        serializer.serialize_str(&formatted)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PrimitiveDateTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let parsed = OffsetDateTime::parse(&value, &Rfc3339).map_err(rocket::serde::de::Error::custom)?;
+        let utc = parsed.to_offset(time::UtcOffset::UTC);
+        Ok(PrimitiveDateTime::new(utc.date(), utc.time()))
+    }
+
+    /// The same, for `Option<PrimitiveDateTime>` fields.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(timestamp: &Option<PrimitiveDateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+            match timestamp {
+                Some(timestamp) => super::serialize(timestamp, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PrimitiveDateTime>, D::Error> {
+            #[derive(Deserialize)]
+            #[serde(crate = "rocket::serde")]
+            struct Wrapper(#[serde(with = "super")] PrimitiveDateTime);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(timestamp)| timestamp))
+        }
+    }
+}
+
+// `#[serde(with = "timestamp::date")]` for `Date` fields, as `YYYY-MM-DD`;
+// `timestamp::date::option` for `Option<Date>` ones.
+time::serde::format_description!(pub date, Date, "[year]-[month]-[day]");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::Serialize;
+    use time::macros::datetime;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Stamped {
+        #[serde(with = "rfc3339")]
+        at: PrimitiveDateTime,
+        #[serde(with = "rfc3339::option")]
+        until: Option<PrimitiveDateTime>,
+    }
+
+    #[test]
+    fn test_rfc3339_round_trip() {
+        let stamped = Stamped { at: datetime!(2025-11-10 09:30:00), until: None };
+        let json = serde_json::to_string(&stamped).unwrap();
+
+        assert_eq!(json, r#"{"at":"2025-11-10T09:30:00Z","until":null}"#);
+        assert_eq!(serde_json::from_str::<Stamped>(&json).unwrap(), stamped);
+
+        let parsed: Stamped = serde_json::from_str(r#"{"at":"2025-11-10T10:30:00+01:00","until":"2025-11-11T00:00:00Z"}"#).unwrap();
+        assert_eq!(parsed.at, datetime!(2025-11-10 09:30:00));
+        assert_eq!(parsed.until, Some(datetime!(2025-11-11 00:00:00)));
+    }
+
+    #[test]
+    fn test_parse_point() {
+        assert_eq!(parse_point("2026-01-15"), Some(datetime!(2026-01-15 00:00:00)));
+        assert_eq!(parse_point("2026-01-15T10:30:00+01:00"), Some(datetime!(2026-01-15 09:30:00)));
+        assert_eq!(parse_point("yesterday"), None);
+    }
+}
@@ -0,0 +1,304 @@
+//! Transactions spanning a whole request, for handlers making several
+//! changes through the repository and the other modules, which each lock
+//! the connection on their own: with a `Transaction` guard, the changes
+//! are committed if the response is a success and rolled back otherwise,
+//! whichever step failed. Handlers which must wait on something else
+//! first, such as the geocoder, open one with `Transaction::begin` once
+//! they're done waiting and commit it themselves, and work handed to a
+//! thread of its own, such as that of `timeouts::blocking`, opens one
+//! there with `Transaction::begin_blocking`. A transaction nobody ended,
+//! as when the client goes away mid-request, is rolled back.
+//!
+//! The transaction is open on the one connection, so until it ends, other
+//! requests and the workers wait in `db::lock`, backing off as
+//! `busy_retry` says, and find the database busy past its attempts, as
+//! they would with SQLite's own locks; scoped handlers shouldn't wait on
+//! anything but the database meanwhile, and commit early to do so. The
+//! transaction belongs to the task, or thread, which opened it: work handed
+//! to other tasks or threads waits for it too.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread::{self, ThreadId};
+
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{Responder, Response};
+use rocket::tokio::task;
+use rocket::tokio::time::sleep;
+
+use crate::config::AppConfig;
+use crate::db::{BusyRetryConfig, DbError};
+use crate::problem::Problem;
+use crate::DbConn;
+
+/// Who a transaction is open for: the async task, or else the thread,
+/// which opened it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    Task(task::Id),
+    Thread(ThreadId),
+}
+
+impl Owner {
+    fn current() -> Owner {
+        task::try_id().map_or_else(|| Owner::Thread(thread::current().id()), Owner::Task)
+    }
+}
+
+/// Who each connection is in a transaction for, by the address of the
+/// connection.
+fn owners() -> &'static Mutex<HashMap<usize, Owner>> {
+    static OWNERS: OnceLock<Mutex<HashMap<usize, Owner>>> = OnceLock::new();
+    OWNERS.get_or_init(Default::default)
+}
+
+/// How `db::lock` waits for the transactions on each connection to end,
+/// by the address of the connection.
+fn retries() -> &'static Mutex<HashMap<usize, BusyRetryConfig>> {
+    static RETRIES: OnceLock<Mutex<HashMap<usize, BusyRetryConfig>>> = OnceLock::new();
+    RETRIES.get_or_init(Default::default)
+}
+
+fn key(db: &DbConn) -> usize {
+    std::sync::Arc::as_ptr(db) as usize
+}
+
+/// Sets how callers of `db::lock` wait for the transactions on the
+/// connection to end; they back off as `BusyRetryConfig::default` says
+/// otherwise.
+pub fn set_busy_retry(db: &DbConn, retry: BusyRetryConfig) {
+    retries().lock().unwrap_or_else(PoisonError::into_inner).insert(key(db), retry);
+}
+
+pub fn busy_retry(db: &DbConn) -> BusyRetryConfig {
+    retries().lock().unwrap_or_else(PoisonError::into_inner).get(&key(db)).cloned().unwrap_or_default()
+}
+
+/// Whether the current task or thread may use the connection, which it
+/// may unless another has a transaction open on it; to be checked with the
+/// connection locked, as transactions are opened and closed.
+pub fn is_free(db: &DbConn) -> bool {
+    owners().lock().unwrap_or_else(PoisonError::into_inner).get(&key(db)).is_none_or(|owner| *owner == Owner::current())
+}
+
+/// Opens a transaction on the connection for the current task or thread,
+/// finding the connection busy while another has one open.
+fn begin(db: &DbConn) -> Result<(), DbError> {
+    let mut connection = db.lock().map_err(|_| DbError::Pool)?;
+    let mut owners = owners().lock().unwrap_or_else(PoisonError::into_inner);
+    if owners.contains_key(&key(db)) {
+        return Err(DbError::Busy);
+    }
+    AnsiTransactionManager::begin_transaction(&mut *connection)?;
+    owners.insert(key(db), Owner::current());
+    Ok(())
+}
+
+/// Commits the transaction of the current task, which is rolled back if
+/// that fails, or rolls it back, and gives the connection back to everyone.
+fn finish(db: &DbConn, commit: bool) -> Result<(), DbError> {
+    // The transaction is ours to end, from whichever task drops it.
+    let mut connection = db.lock().unwrap_or_else(PoisonError::into_inner);
+    let finished = if commit {
+        AnsiTransactionManager::commit_transaction(&mut *connection)
+    } else {
+        AnsiTransactionManager::rollback_transaction(&mut *connection)
+    };
+    owners().lock().unwrap_or_else(PoisonError::into_inner).remove(&key(db));
+    Ok(finished?)
+}
+
+/// A transaction open on the connection, until ended once.
+struct Open {
+    db: DbConn,
+    ended: AtomicBool,
+}
+
+impl Open {
+    fn end(&self, commit: bool) -> Result<(), DbError> {
+        if self.ended.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        finish(&self.db, commit)
+    }
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        if let Err(e) = self.end(false) {
+            log::error!("Could not roll back an abandoned transaction: {}", e);
+        }
+    }
+}
+
+/// The transaction of the request, which the fairing ends.
+struct Scope(Mutex<Option<Arc<Open>>>);
+
+/// A transaction open for the current task. As a request guard, it lasts
+/// until the response; `begin` opens one lasting until `commit`. Either
+/// way, it's rolled back if dropped without being ended.
+pub struct Transaction(Arc<Open>);
+
+impl Transaction {
+    fn open(db: &DbConn) -> Transaction {
+        Transaction(Arc::new(Open { db: db.clone(), ended: AtomicBool::new(false) }))
+    }
+
+    /// Opens a transaction, waiting as `retry` says while another task has
+    /// one open.
+    pub async fn begin(db: &DbConn, retry: &BusyRetryConfig) -> Result<Transaction, DbError> {
+        let mut attempts = 1;
+        loop {
+            match begin(db) {
+                Ok(()) => return Ok(Transaction::open(db)),
+                Err(DbError::Busy) if attempts < retry.max_attempts => {
+                    sleep(retry.backoff(attempts)).await;
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Opens a transaction for the current thread, outside of the async
+    /// workers, waiting as `retry` says while another has one open.
+    pub fn begin_blocking(db: &DbConn, retry: &BusyRetryConfig) -> Result<Transaction, DbError> {
+        let mut attempts = 1;
+        loop {
+            match begin(db) {
+                Ok(()) => return Ok(Transaction::open(db)),
+                Err(DbError::Busy) if attempts < retry.max_attempts => {
+                    thread::sleep(retry.backoff(attempts));
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Commits the changes now rather than with the response, for work
+    /// which mustn't hold the database to follow.
+    pub fn commit(self) -> Result<(), DbError> {
+        self.0.end(true)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Transaction {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let (Some(db), Some(config)) = (request.rocket().state::<DbConn>(), request.rocket().state::<AppConfig>()) else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        match Transaction::begin(db, &config.busy_retry).await {
+            Ok(transaction) => {
+                request.local_cache(|| Scope(Mutex::new(Some(transaction.0.clone()))));
+                request::Outcome::Success(transaction)
+            }
+            Err(e) => request::Outcome::Error((e.into(), ())),
+        }
+    }
+}
+
+/// Fairing ending the transactions of the requests, once answered; a
+/// transaction failing to end turns the response into an error.
+pub struct Transactions;
+
+#[rocket::async_trait]
+impl Fairing for Transactions {
+    fn info(&self) -> Info {
+        Info { name: "Request transactions", kind: Kind::Response }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(open) = request.local_cache(|| Scope(Mutex::new(None))).0.lock().unwrap_or_else(PoisonError::into_inner).take() else {
+            return;
+        };
+
+        let commit = response.status().code < 400;
+        if let Err(e) = open.end(commit) {
+            log::error!("Could not end the transaction of {} {}: {}", request.method(), request.uri(), e);
+            if let Ok(failed) = Problem::from(e).respond_to(request) {
+                *response = failed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::tests::setup_test_db;
+
+    #[test]
+    fn test_scope() {
+        use crate::schema::tags;
+        use diesel::prelude::*;
+
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+        let count = |db: &DbConn| tags::table.count().get_result::<i64>(&mut *db::lock(db).unwrap()).unwrap();
+
+        let scoped = db.clone();
+        runtime
+            .block_on(runtime.spawn(async move {
+                begin(&scoped).unwrap();
+                let insert = diesel::insert_into(tags::table).values(tags::name.eq("bureau"));
+                insert.execute(&mut *db::lock(&scoped).unwrap()).unwrap();
+
+                // Other tasks, and threads, find the connection busy.
+                let other = scoped.clone();
+                assert!(matches!(task::spawn(async move { db::lock(&other).map(drop) }).await.unwrap(), Err(DbError::Busy)));
+                let other = scoped.clone();
+                assert!(matches!(std::thread::spawn(move || db::lock(&other).map(drop)).join().unwrap(), Err(DbError::Busy)));
+                let other = scoped.clone();
+                assert!(matches!(task::spawn(async move { begin(&other) }).await.unwrap(), Err(DbError::Busy)));
+                finish(&scoped, false).unwrap();
+            }))
+            .unwrap();
+        assert!(is_free(&db));
+        assert_eq!(count(&db), 0);
+
+        begin(&db).unwrap();
+        diesel::insert_into(tags::table).values(tags::name.eq("bureau")).execute(&mut *db::lock(&db).unwrap()).unwrap();
+        finish(&db, true).unwrap();
+        assert_eq!(count(&db), 1);
+        assert!(is_free(&db));
+
+        // A request going away mid-transaction has it rolled back.
+        let scoped = db.clone();
+        let (inserted, insertion) = std::sync::mpsc::channel();
+        let abandoned = runtime.spawn(async move {
+            let _transaction = Transaction::begin(&scoped, &BusyRetryConfig::default()).await.unwrap();
+            diesel::insert_into(tags::table).values(tags::name.eq("budget")).execute(&mut *db::lock(&scoped).unwrap()).unwrap();
+            inserted.send(()).unwrap();
+            std::future::pending::<()>().await;
+        });
+        insertion.recv().unwrap();
+        assert!(!is_free(&db));
+        abandoned.abort();
+        assert!(runtime.block_on(abandoned).unwrap_err().is_cancelled());
+        assert!(is_free(&db));
+        assert_eq!(count(&db), 1);
+    }
+
+    #[test]
+    fn test_lock_waits_for_the_transaction() {
+        let db: DbConn = Arc::new(Mutex::new(setup_test_db()));
+        set_busy_retry(&db, BusyRetryConfig { max_attempts: 10, retry_delay: 5 });
+
+        begin(&db).unwrap();
+        let other = db.clone();
+        let waiting = std::thread::spawn(move || db::lock(&other).map(drop));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        finish(&db, true).unwrap();
+        assert!(waiting.join().unwrap().is_ok());
+    }
+}
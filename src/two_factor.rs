@@ -0,0 +1,321 @@
+//! TOTP second factor for dashboard users. Users enroll from their
+//! session: a secret is generated and shown as a QR code, and only
+//! required to sign in once a first code has confirmed the authenticator
+//! app holds it. Backup codes, each usable once, stand in for lost
+//! authenticators; administrators can reset a user's second factor with
+//! the admin token.
+
+use std::net::IpAddr;
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::form::Form;
+use rocket::http::{Header, Status};
+use rocket::response::content::RawHtml;
+use qrcode::{EcLevel, QrCode};
+use rocket::State;
+
+use crate::auth::Admin;
+use crate::dashboard::{escape, page};
+use crate::schema::{backup_codes, users};
+use crate::sessions::Session;
+use crate::sha256::{hex, sha256};
+use crate::users::User;
+use crate::{audit, base32, csrf, db, png, timestamp, totp, DbConn};
+
+const ISSUER: &str = "rckd";
+const BACKUP_CODES: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum Verified {
+    /// The user hasn't enabled the second factor.
+    NotRequired,
+    Totp,
+    BackupCode,
+}
+
+#[derive(FromForm)]
+struct Confirm<'r> {
+    code: &'r str,
+}
+
+#[derive(Responder)]
+#[response(content_type = "image/png")]
+struct QrCodePng(Vec<u8>, Header<'static>);
+
+type HtmlError = (Status, RawHtml<String>);
+
+fn now() -> i64 {
+    timestamp::now().assume_utc().unix_timestamp()
+}
+
+/// Backup codes are compared as typed, without dashes, spaces or case.
+fn hash_backup_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect();
+    hex(&sha256(normalized.as_bytes()))
+}
+
+fn backup_code() -> String {
+    let code = base32::encode(&rand::random::<[u8; 7]>());
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+/// Checks the code given at sign in, either a TOTP code or one of the
+/// user's backup codes, which is then used up.
+pub fn check(user: &User, code: &str, unix_time: i64, connection: &mut SqliteConnection) -> QueryResult<Option<Verified>> {
+    if !user.totp_enabled {
+        return Ok(Some(Verified::NotRequired));
+    }
+
+    connection.transaction(|connection| {
+        let (secret, last_step): (Option<String>, Option<i64>) =
+            users::table.find(user.id).select((users::totp_secret, users::totp_last_step)).first(connection)?;
+        let secret = secret.and_then(|secret| base32::decode(&secret)).unwrap_or_default();
+        if let Some(step) = totp::verify(&secret, code.trim(), unix_time, last_step) {
+            diesel::update(users::table.find(user.id)).set(users::totp_last_step.eq(step)).execute(connection)?;
+            return Ok(Some(Verified::Totp));
+        }
+
+        let used = diesel::delete(backup_codes::table.filter(backup_codes::user_id.eq(user.id)).filter(backup_codes::code_hash.eq(hash_backup_code(code))))
+            .execute(connection)?;
+        Ok((used > 0).then_some(Verified::BackupCode))
+    })
+}
+
+/// Generates a new secret for the user, pending confirmation.
+pub fn enroll(user_id: i32, connection: &mut SqliteConnection) -> QueryResult<Vec<u8>> {
+    let secret: [u8; 20] = rand::random();
+    diesel::update(users::table.find(user_id))
+        .set((users::totp_secret.eq(base32::encode(&secret)), users::totp_enabled.eq(false), users::totp_last_step.eq(None::<i64>)))
+        .execute(connection)?;
+    Ok(secret.to_vec())
+}
+
+/// Enables the pending secret if `code` matches it, returning the new
+/// backup codes.
+pub fn confirm(user_id: i32, code: &str, unix_time: i64, connection: &mut SqliteConnection) -> QueryResult<Option<Vec<String>>> {
+    connection.transaction(|connection| {
+        let secret: Option<String> = users::table
+            .find(user_id)
+            .filter(users::totp_enabled.eq(false))
+            .select(users::totp_secret)
+            .first::<Option<String>>(connection)
+            .optional()?
+            .flatten();
+        let Some(step) = secret.and_then(|secret| base32::decode(&secret)).and_then(|secret| totp::verify(&secret, code.trim(), unix_time, None)) else {
+            return Ok(None);
+        };
+
+        diesel::update(users::table.find(user_id)).set((users::totp_enabled.eq(true), users::totp_last_step.eq(step))).execute(connection)?;
+        diesel::delete(backup_codes::table.filter(backup_codes::user_id.eq(user_id))).execute(connection)?;
+        let codes: Vec<String> = (0..BACKUP_CODES).map(|_| backup_code()).collect();
+        let rows: Vec<_> = codes.iter().map(|code| (backup_codes::user_id.eq(user_id), backup_codes::code_hash.eq(hash_backup_code(code)))).collect();
+        diesel::insert_into(backup_codes::table).values(rows).execute(connection)?;
+        Ok(Some(codes))
+    })
+}
+
+pub fn disable(user_id: i32, connection: &mut SqliteConnection) -> QueryResult<usize> {
+    connection.transaction(|connection| {
+        diesel::delete(backup_codes::table.filter(backup_codes::user_id.eq(user_id))).execute(connection)?;
+        diesel::update(users::table.find(user_id))
+            .set((users::totp_secret.eq(None::<String>), users::totp_enabled.eq(false), users::totp_last_step.eq(None::<i64>)))
+            .execute(connection)
+    })
+}
+
+fn internal_error<E>(_: E) -> HtmlError {
+    (Status::InternalServerError, page("Erreur", "<p>Erreur interne, veuillez réessayer.</p>"))
+}
+
+fn confirm_page(session: &Session, secret: &str, error: Option<&str>) -> RawHtml<String> {
+    let error = error.map(|error| format!("<p class=\"error\">{}</p>\n", escape(error))).unwrap_or_default();
+    page(
+        "Double authentification",
+        &format!(
+            "<h1>Double authentification</h1>\n{}<p>Scannez ce code avec votre application d'authentification, \
+             ou saisissez-y la clé <code>{}</code>.</p>\n<img src=\"/admin/totp/qrcode.png\" alt=\"Code QR\">\n\
+             <form method=\"post\" action=\"/admin/totp/confirm\">\n{}\n\
+             <label>Code affiché <input name=\"code\" inputmode=\"numeric\" autocomplete=\"one-time-code\" required></label>\n\
+             <button type=\"submit\">Activer</button>\n</form>",
+            error,
+            escape(secret),
+            csrf::field(&session.csrf_token)
+        ),
+    )
+}
+
+#[get("/admin/totp")]
+fn totp_status(session: Session, db: &State<DbConn>) -> Result<RawHtml<String>, HtmlError> {
+    if session.user.totp_enabled {
+        let remaining: i64 = backup_codes::table
+            .filter(backup_codes::user_id.eq(session.user.id))
+            .count()
+            .get_result(&mut *db::lock(db).map_err(internal_error)?)
+            .map_err(internal_error)?;
+        let body = format!("<h1>Double authentification</h1>\n<p>Activée ; il vous reste {} codes de secours.</p>", remaining);
+        return Ok(page("Double authentification", &body));
+    }
+
+    Ok(page(
+        "Double authentification",
+        &format!(
+            "<h1>Double authentification</h1>\n<p>Désactivée.</p>\n<form method=\"post\" action=\"/admin/totp\">\n{}\n\
+             <button type=\"submit\">Configurer</button>\n</form>",
+            csrf::field(&session.csrf_token)
+        ),
+    ))
+}
+
+/// Starts enrollment; refused while a second factor is enabled, which
+/// must not be replaced without the old one.
+#[post("/admin/totp")]
+fn start_enrollment(session: Session, db: &State<DbConn>) -> Result<RawHtml<String>, HtmlError> {
+    if session.user.totp_enabled {
+        return Err((Status::Conflict, page("Double authentification", "<p>La double authentification est déjà activée.</p>")));
+    }
+
+    let secret = enroll(session.user.id, &mut *db::lock(db).map_err(internal_error)?).map_err(internal_error)?;
+    Ok(confirm_page(&session, &base32::encode(&secret), None))
+}
+
+/// The pending secret, as a QR code for authenticator apps.
+#[get("/admin/totp/qrcode.png")]
+fn provisioning_qr_code(session: Session, db: &State<DbConn>) -> Result<QrCodePng, Status> {
+    let secret: Option<String> = users::table
+        .find(session.user.id)
+        .filter(users::totp_enabled.eq(false))
+        .select(users::totp_secret)
+        .first(&mut *db::lock(db)?)
+        .map_err(|_| Status::InternalServerError)?;
+    let secret = secret.and_then(|secret| base32::decode(&secret)).ok_or(Status::NotFound)?;
+
+    let uri = totp::provisioning_uri(ISSUER, &session.user.username, &secret);
+    let qr = QrCode::with_error_correction_level(uri.as_bytes(), EcLevel::M).map_err(|_| Status::InternalServerError)?;
+    Ok(QrCodePng(png::encode_qr_code(&qr), Header::new("Cache-Control", "no-store")))
+}
+
+#[post("/admin/totp/confirm", data = "<form>")]
+fn confirm_enrollment(form: Form<Confirm<'_>>, session: Session, ip: Option<IpAddr>, db: &State<DbConn>) -> Result<RawHtml<String>, HtmlError> {
+    let mut connection = db::lock(db).map_err(internal_error)?;
+    let Some(codes) = confirm(session.user.id, form.code, now(), &mut connection).map_err(internal_error)? else {
+        let secret: Option<String> = users::table.find(session.user.id).select(users::totp_secret).first(&mut *connection).map_err(internal_error)?;
+        let page = confirm_page(&session, &secret.unwrap_or_default(), Some("Code incorrect, veuillez réessayer."));
+        return Err((Status::UnprocessableEntity, page));
+    };
+    audit::record(audit::TWO_FACTOR_ENABLED, Some(&session.user.username), ip, &mut connection).map_err(internal_error)?;
+
+    let codes: String = codes.iter().map(|code| format!("<li><code>{}</code></li>\n", code)).collect();
+    Ok(page(
+        "Double authentification",
+        &format!(
+            "<h1>Double authentification activée</h1>\n<p>Conservez ces codes de secours en lieu sûr : chacun permet \
+             une connexion sans votre application, et ils ne seront plus affichés.</p>\n<ul>\n{}</ul>\n<p><a href=\"/admin\">Retour</a></p>",
+            codes
+        ),
+    ))
+}
+
+/// Turns a user's second factor off, for users who lost both their
+/// authenticator and their backup codes.
+#[delete("/admin/users/<id>/totp")]
+fn reset_two_factor(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let mut connection = db::lock(db)?;
+    let username: String = users::table
+        .find(id)
+        .select(users::username)
+        .first(&mut *connection)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    disable(id, &mut connection).map_err(|_| Status::InternalServerError)?;
+    audit::record(audit::TWO_FACTOR_DISABLED, Some(&username), None, &mut connection).map_err(|_| Status::InternalServerError)?;
+    Ok(Status::NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![totp_status, start_enrollment, provisioning_qr_code, confirm_enrollment, reset_two_factor]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup_test_db;
+    use crate::users::{self, NewUser};
+    use rocket::http::ContentType;
+
+    fn create_user(connection: &mut SqliteConnection) -> User {
+        let new_user = NewUser { username: "secretariat".to_string(), password: "correct horse battery staple".to_string(), email: None };
+        users::create(&new_user, connection).unwrap()
+    }
+
+    #[test]
+    fn test_enrollment() {
+        let mut connection = setup_test_db();
+        let user = create_user(&mut connection);
+        assert_eq!(check(&user, "", 0, &mut connection), Ok(Some(Verified::NotRequired)));
+
+        let secret = enroll(user.id, &mut connection).unwrap();
+        let now = 1_700_000_000;
+        let code = totp::code(&secret, totp::step(now));
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        assert_eq!(confirm(user.id, wrong, now, &mut connection), Ok(None));
+        let codes = confirm(user.id, &code, now, &mut connection).unwrap().expect("backup codes");
+        assert_eq!(codes.len(), BACKUP_CODES);
+        // Confirming again doesn't hand out new codes.
+        assert_eq!(confirm(user.id, &totp::code(&secret, totp::step(now) + 1), now + 30, &mut connection), Ok(None));
+
+        let user = users::authenticate("secretariat", "correct horse battery staple", &mut connection).unwrap().unwrap();
+        assert!(user.totp_enabled);
+        // The code used to confirm can't be used again.
+        assert_eq!(check(&user, &code, now, &mut connection), Ok(None));
+        assert_eq!(check(&user, &totp::code(&secret, totp::step(now) + 1), now + 30, &mut connection), Ok(Some(Verified::Totp)));
+        assert_eq!(check(&user, &codes[0].to_lowercase().replace('-', " "), now, &mut connection), Ok(Some(Verified::BackupCode)));
+        assert_eq!(check(&user, &codes[0], now, &mut connection), Ok(None));
+        assert_eq!(check(&user, "", now, &mut connection), Ok(None));
+
+        disable(user.id, &mut connection).unwrap();
+        let user = users::authenticate("secretariat", "correct horse battery staple", &mut connection).unwrap().unwrap();
+        assert_eq!(check(&user, "", now, &mut connection), Ok(Some(Verified::NotRequired)));
+        assert_eq!(backup_codes::table.count().get_result::<i64>(&mut connection), Ok(0));
+    }
+
+    #[test]
+    fn test_two_factor_login() {
+        let mut connection = setup_test_db();
+        create_user(&mut connection);
+        let client = crate::tests::client(connection);
+        let login = |code: &str| {
+            let body = format!("username=secretariat&password=correct+horse+battery+staple&code={}", code);
+            client.post("/admin/login").header(ContentType::Form).body(body).dispatch().status()
+        };
+        assert_eq!(login(""), Status::SeeOther);
+
+        let csrf_token = |html: &str| html.split("name=\"csrf_token\" value=\"").nth(1).and_then(|rest| rest.split('"').next()).unwrap().to_string();
+        let html = client.get("/admin").dispatch().into_string().unwrap();
+        let token = Header::new(csrf::HEADER, csrf_token(&html));
+        assert_eq!(client.get("/admin/totp/qrcode.png").dispatch().status(), Status::NotFound);
+
+        let html = client.post("/admin/totp").header(token.clone()).dispatch().into_string().unwrap();
+        let secret = html.split("<code>").nth(1).and_then(|rest| rest.split('<').next()).map(base32::decode).unwrap().unwrap();
+        let response = client.get("/admin/totp/qrcode.png").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+
+        let body = format!("code={}", totp::code(&secret, totp::step(now())));
+        let response = client.post("/admin/totp/confirm").header(token.clone()).header(ContentType::Form).body(body).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let html = response.into_string().unwrap();
+        let backup_code = html.split("<li><code>").nth(1).and_then(|rest| rest.split('<').next()).unwrap().to_string();
+        assert_eq!(client.post("/admin/totp").header(token.clone()).dispatch().status(), Status::Conflict);
+        assert_eq!(client.post("/admin/logout").header(token).dispatch().status(), Status::SeeOther);
+
+        assert_eq!(login(""), Status::Unauthorized);
+        assert_eq!(login("123456"), Status::Unauthorized);
+        assert_eq!(login(&backup_code), Status::SeeOther);
+
+        let response = client.delete("/admin/users/1/totp").header(crate::tests::admin()).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(login(""), Status::SeeOther);
+    }
+}
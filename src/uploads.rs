@@ -0,0 +1,207 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use rocket::http::{ContentType, Status};
+use rocket::serde::Deserialize;
+
+/// A file format that can be uploaded, recognised by its leading bytes.
+#[derive(Debug, PartialEq)]
+pub struct FileType {
+    pub mime: &'static str,
+    pub extensions: &'static [&'static str],
+    magic: &'static [&'static [u8]],
+}
+
+pub const PDF: FileType = FileType { mime: "application/pdf", extensions: &["pdf"], magic: &[b"%PDF-"] };
+pub const PNG: FileType = FileType { mime: "image/png", extensions: &["png"], magic: &[b"\x89PNG\r\n\x1a\n"] };
+pub const JPEG: FileType = FileType { mime: "image/jpeg", extensions: &["jpg", "jpeg"], magic: &[b"\xff\xd8\xff"] };
+pub const GIF: FileType = FileType { mime: "image/gif", extensions: &["gif"], magic: &[b"GIF87a", b"GIF89a"] };
+
+const KNOWN_TYPES: &[FileType] = &[PDF, PNG, JPEG, GIF];
+
+/// Bytes read from the start of a file to identify its format.
+const SNIFF_LEN: usize = 16;
+const CLAMD_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UploadConfig {
+    /// Largest accepted document, in bytes.
+    pub max_document_size: u64,
+    /// clamd socket uploads are scanned through before being stored; no
+    /// scan happens when unset.
+    pub clamd_socket: Option<PathBuf>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_document_size: 20 * 1024 * 1024,
+            clamd_socket: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    UnsupportedType,
+    BadFilename,
+    Infected(String),
+    ScanFailed,
+}
+
+impl From<Rejection> for Status {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::UnsupportedType => Status::UnsupportedMediaType,
+            Rejection::BadFilename | Rejection::Infected(_) => Status::UnprocessableEntity,
+            Rejection::ScanFailed => Status::ServiceUnavailable,
+        }
+    }
+}
+
+pub fn sniff(head: &[u8]) -> Option<&'static FileType> {
+    KNOWN_TYPES.iter().find(|file_type| file_type.magic.iter().any(|magic| head.starts_with(magic)))
+}
+
+/// Checks an uploaded file against the allowed formats: its content, the
+/// declared content type and the extension of the original file name (if
+/// given) must all agree. Returns the detected format.
+pub fn check_type(head: &[u8], declared: &ContentType, filename: Option<&str>, allowed: &[FileType]) -> Result<&'static FileType, Rejection> {
+    let sniffed = sniff(head).filter(|file_type| allowed.contains(file_type)).ok_or(Rejection::UnsupportedType)?;
+    if ContentType::parse_flexible(sniffed.mime).as_ref() != Some(declared) {
+        return Err(Rejection::UnsupportedType);
+    }
+
+    if let Some(filename) = filename {
+        if filename.is_empty() || filename.contains(['/', '\\']) || filename.chars().any(char::is_control) {
+            return Err(Rejection::BadFilename);
+        }
+        let extension = filename.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+        if !extension.is_some_and(|extension| sniffed.extensions.contains(&extension.as_str())) {
+            return Err(Rejection::UnsupportedType);
+        }
+    }
+
+    Ok(sniffed)
+}
+
+/// Validates a file written to `path` before it's persisted, scanning it
+/// with clamd when configured.
+pub fn validate(path: &Path, declared: &ContentType, filename: Option<&str>, allowed: &[FileType], config: &UploadConfig) -> Result<&'static FileType, Rejection> {
+    let file = std::fs::File::open(path).map_err(|_| Rejection::ScanFailed)?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).map_err(|_| Rejection::ScanFailed)?;
+    let file_type = check_type(&head, declared, filename, allowed)?;
+
+    if let Some(socket) = &config.clamd_socket {
+        let file = std::fs::File::open(path).map_err(|_| Rejection::ScanFailed)?;
+        match clamd_scan(socket, file) {
+            Ok(None) => {}
+            Ok(Some(signature)) => return Err(Rejection::Infected(signature)),
+            Err(e) => {
+                log::error!("clamd scan through {} failed: {}", socket.display(), e);
+                return Err(Rejection::ScanFailed);
+            }
+        }
+    }
+
+    Ok(file_type)
+}
+
+/// Streams `content` to clamd with `INSTREAM`; returns the name of the
+/// signature found, if any.
+pub fn clamd_scan(socket: &Path, mut content: impl Read) -> io::Result<Option<String>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(b"zINSTREAM\0")?;
+
+    let mut chunk = vec![0; CLAMD_CHUNK];
+    loop {
+        let read = content.read(&mut chunk)?;
+        stream.write_all(&(read as u32).to_be_bytes())?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&chunk[..read])?;
+    }
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    parse_clamd_reply(reply.trim_end_matches(['\0', '\n']))
+}
+
+fn parse_clamd_reply(reply: &str) -> io::Result<Option<String>> {
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(None)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Some(signature.to_string()))
+    } else {
+        Err(io::Error::other(format!("unexpected clamd reply: {}", reply)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn test_check_type() {
+        let pdf = b"%PDF-1.7\n";
+
+        assert_eq!(check_type(pdf, &ContentType::PDF, None, &[PDF]), Ok(&PDF));
+        assert_eq!(check_type(pdf, &ContentType::PDF, Some("Déclaration.PDF"), &[PDF]), Ok(&PDF));
+        assert_eq!(check_type(pdf, &ContentType::PDF, Some("declaration.exe"), &[PDF]), Err(Rejection::UnsupportedType));
+        assert_eq!(check_type(pdf, &ContentType::PDF, Some("../declaration.pdf"), &[PDF]), Err(Rejection::BadFilename));
+        assert_eq!(check_type(pdf, &ContentType::PNG, None, &[PDF, PNG]), Err(Rejection::UnsupportedType));
+        assert_eq!(check_type(b"\x89PNG\r\n\x1a\n", &ContentType::PNG, None, &[PDF]), Err(Rejection::UnsupportedType));
+        assert_eq!(check_type(b"MZ\x90\x00", &ContentType::PDF, None, &[PDF]), Err(Rejection::UnsupportedType));
+    }
+
+    #[test]
+    fn test_parse_clamd_reply() {
+        assert_eq!(parse_clamd_reply("stream: OK").unwrap(), None);
+        assert_eq!(parse_clamd_reply("stream: Eicar-Test-Signature FOUND").unwrap(), Some("Eicar-Test-Signature".to_string()));
+        assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR").is_err());
+    }
+
+    #[test]
+    fn test_clamd_scan() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket = directory.path().join("clamd.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 10];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut content = Vec::new();
+            loop {
+                let mut len = [0; 4];
+                stream.read_exact(&mut len).unwrap();
+                let len = u32::from_be_bytes(len) as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; len];
+                stream.read_exact(&mut chunk).unwrap();
+                content.extend(chunk);
+            }
+            let reply: &[u8] = if content.windows(5).any(|window| window == b"EICAR") {
+                b"stream: Eicar-Test-Signature FOUND\0"
+            } else {
+                b"stream: OK\0"
+            };
+            stream.write_all(reply).unwrap();
+        });
+
+        let found = clamd_scan(&socket, &b"%PDF-1.4 EICAR"[..]).unwrap();
+        assert_eq!(found, Some("Eicar-Test-Signature".to_string()));
+        server.join().unwrap();
+    }
+}
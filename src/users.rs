@@ -0,0 +1,151 @@
+//! Accounts of the people signing in to the admin dashboard.
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::{json::Json, Deserialize, Serialize};
+use rocket::State;
+
+use crate::auth::{constant_time_eq, Admin};
+use crate::schema::{sessions, users};
+use crate::sha256::pbkdf2_hmac_sha256;
+use crate::{db, DbConn};
+
+/// PBKDF2 rounds for new passwords; stored hashes record their own count,
+/// so it can be raised without invalidating them.
+const PASSWORD_ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 310_000 };
+pub const MIN_PASSWORD_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = users)]
+#[serde(crate = "rocket::serde")]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    /// Where password reset links are sent; passwords can't be reset
+    /// without one.
+    pub email: Option<String>,
+    /// Whether signing in takes a TOTP code too; see `two_factor`.
+    pub totp_enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// `pbkdf2-sha256$<iterations>$<salt>$<hash>`, salt and hash in unpadded
+/// base64url.
+pub fn hash_password(password: &str) -> String {
+    let salt: [u8; 16] = rand::random();
+    let hash = pbkdf2_hmac_sha256(password.as_bytes(), &salt, PASSWORD_ITERATIONS);
+    format!("pbkdf2-sha256${}${}${}", PASSWORD_ITERATIONS, BASE64_URL_SAFE_NO_PAD.encode(salt), BASE64_URL_SAFE_NO_PAD.encode(hash))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let ["pbkdf2-sha256", iterations, salt, hash] = parts[..] else {
+        return false;
+    };
+    let (Ok(iterations), Ok(salt), Ok(hash)) = (iterations.parse(), BASE64_URL_SAFE_NO_PAD.decode(salt), BASE64_URL_SAFE_NO_PAD.decode(hash)) else {
+        return false;
+    };
+
+    constant_time_eq(&pbkdf2_hmac_sha256(password.as_bytes(), &salt, iterations), &hash)
+}
+
+pub fn create(new_user: &NewUser, connection: &mut SqliteConnection) -> QueryResult<User> {
+    diesel::insert_into(users::table)
+        .values((
+            users::username.eq(&new_user.username),
+            users::password_hash.eq(hash_password(&new_user.password)),
+            users::email.eq(&new_user.email),
+        ))
+        .returning(User::as_returning())
+        .get_result(connection)
+}
+
+/// The user with these credentials, if any.
+pub fn authenticate(username: &str, password: &str, connection: &mut SqliteConnection) -> QueryResult<Option<User>> {
+    let found: Option<(User, String)> = users::table
+        .filter(users::username.eq(username))
+        .select((User::as_select(), users::password_hash))
+        .first(connection)
+        .optional()?;
+
+    Ok(match found {
+        Some((user, stored)) if verify_password(password, &stored) => Some(user),
+        Some(_) => None,
+        None => {
+            // Take as long as for a wrong password, not to reveal which
+            // usernames exist.
+            hash_password(password);
+            None
+        }
+    })
+}
+
+/// Replaces the user's password, ending their sessions.
+pub fn set_password(user_id: i32, password: &str, connection: &mut SqliteConnection) -> QueryResult<()> {
+    connection.transaction(|connection| {
+        diesel::update(users::table.find(user_id)).set(users::password_hash.eq(hash_password(password))).execute(connection)?;
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(connection)?;
+        Ok(())
+    })
+}
+
+#[post("/admin/users", data = "<new_user>")]
+fn create_user(new_user: Json<NewUser>, _admin: Admin, db: &State<DbConn>) -> Result<Created<Json<User>>, Status> {
+    if new_user.username.trim().is_empty()
+        || new_user.password.chars().count() < MIN_PASSWORD_LEN
+        || new_user.email.as_deref().is_some_and(|email| !email.contains('@'))
+    {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let user = create(&new_user, &mut *db::lock(db)?).map_err(|e| match e {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+        _ => Status::InternalServerError,
+    })?;
+
+    let location = format!("/admin/users/{}", user.id);
+    Ok(Created::new(location).body(Json(user)))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![create_user]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup_test_db;
+
+    #[test]
+    fn test_password_hashing() {
+        let stored = hash_password("correct horse battery staple");
+        assert!(stored.starts_with("pbkdf2-sha256$1000$"));
+        assert!(verify_password("correct horse battery staple", &stored));
+        assert!(!verify_password("correct horse battery stapler", &stored));
+        assert_ne!(hash_password("correct horse battery staple"), stored);
+        assert!(!verify_password("anything", "plain-text"));
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let mut connection = setup_test_db();
+        let new_user = NewUser { username: "secretariat".to_string(), password: "correct horse battery staple".to_string(), email: None };
+        let user = create(&new_user, &mut connection).unwrap();
+
+        let found = authenticate("secretariat", "correct horse battery staple", &mut connection).unwrap();
+        assert_eq!(found.map(|found| found.id), Some(user.id));
+        assert!(authenticate("secretariat", "wrong", &mut connection).unwrap().is_none());
+        assert!(authenticate("nobody", "correct horse battery staple", &mut connection).unwrap().is_none());
+    }
+}
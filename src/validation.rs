@@ -0,0 +1,257 @@
+//! Validation of request bodies against the schemas of the OpenAPI
+//! document, beyond what deserializing them checks: formats, enums and
+//! length or range limits. Refused bodies get a 422 problem listing every
+//! violation with a JSON pointer to the offending value.
+//!
+//! Only the subset of JSON Schema the document uses is supported: `$ref`
+//! to components, `type`, `enum`, `required`, `properties`, `items`,
+//! `minLength`/`maxLength`, `maxItems`, `minimum`/`maximum` and the
+//! `email`, `uuid`, `date`, `date-time` and `person-name` formats.
+//! `readOnly` properties are ignored on input. Schemas can add checks the
+//! document can't state, like those of the custom fields admins define.
+
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::DeserializeOwned;
+
+use crate::email::Email;
+use crate::openapi;
+use crate::person_name;
+use crate::problem::{Problem, Violation};
+
+/// Request body types described by a schema of the OpenAPI document.
+pub trait Schema {
+    /// Name of the schema among the document's components.
+    const NAME: &'static str;
+
+    /// Violations of `value` beyond those of the schema.
+    fn check(_value: &Value, _request: &Request<'_>) -> Result<Vec<Violation>, Status> {
+        Ok(Vec::new())
+    }
+}
+
+/// Violations found by the `Validated` guard, for the catcher to report.
+struct Invalid(Vec<Violation>);
+
+/// A JSON body which matched its schema.
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Validated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Schema> FromData<'r> for Validated<T> {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let value = match Json::<Value>::from_data(request, data).await {
+            data::Outcome::Success(Json(value)) => value,
+            data::Outcome::Error((status, _)) => return data::Outcome::Error((status, ())),
+            data::Outcome::Forward(forward) => return data::Outcome::Forward(forward),
+        };
+        let schema = openapi::schema(T::NAME).expect("schema is documented");
+
+        let mut violations = Vec::new();
+        validate(&value, schema, String::new(), &mut violations);
+        match T::check(&value, request) {
+            Ok(more) => violations.extend(more),
+            Err(status) => return data::Outcome::Error((status, ())),
+        }
+        if violations.is_empty() {
+            match serde_json::from_value(value) {
+                Ok(body) => return data::Outcome::Success(Validated(body)),
+                Err(e) => violations.push(Violation { pointer: String::new(), detail: e.to_string() }),
+            }
+        }
+
+        request.local_cache(|| Invalid(violations));
+        data::Outcome::Error((Status::UnprocessableEntity, ()))
+    }
+}
+
+/// Reports the violations of the refused body, if that's why the request
+/// is unprocessable.
+#[catch(422)]
+pub fn unprocessable(request: &Request<'_>) -> Problem {
+    let Invalid(violations) = request.local_cache(|| Invalid(Vec::new()));
+
+    let mut problem = Problem::of(Status::UnprocessableEntity, request);
+    if !violations.is_empty() {
+        problem.detail = Some("The request body doesn't match its schema.".to_string());
+        problem.errors = violations.clone();
+    }
+    problem
+}
+
+fn type_matches(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+/// Escapes a property name for use in a JSON pointer.
+pub fn escape(property: &str) -> String {
+    property.replace('~', "~0").replace('/', "~1")
+}
+
+/// Adds the ways `value`, found at `pointer`, violates `schema` to
+/// `violations`.
+pub fn validate(value: &Value, schema: &Value, pointer: String, violations: &mut Vec<Violation>) {
+    let mut violate = |detail: String| violations.push(Violation { pointer: pointer.clone(), detail });
+
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/components/schemas/");
+        match openapi::schema(name) {
+            Some(schema) => validate(value, schema, pointer, violations),
+            None => violate(format!("unknown schema {}", reference)),
+        }
+        return;
+    }
+
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(expected) => vec![expected.as_str()],
+        Value::Array(expected) => expected.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|expected| type_matches(value, expected)) {
+        violate(format!("must be of type {}", types.join(" or ")));
+        return;
+    }
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            violate(format!("must be one of {}", allowed.join(", ")));
+        }
+    }
+
+    match value {
+        Value::String(string) => {
+            // Names are limited in length once normalized, as they're stored.
+            let length = match schema["format"].as_str() {
+                Some("person-name") => person_name::normalize(string).chars().count() as u64,
+                _ => string.chars().count() as u64,
+            };
+            if let Some(min) = schema["minLength"].as_u64().filter(|min| length < *min) {
+                violate(format!("must be at least {} characters long", min));
+            }
+            if let Some(max) = schema["maxLength"].as_u64().filter(|max| length > *max) {
+                violate(format!("must be at most {} characters long", max));
+            }
+            match schema["format"].as_str() {
+                Some("email") if let Err(e) = Email::parse(string) => violate(e.to_string()),
+                Some("uuid") if uuid::Uuid::parse_str(string).is_err() => violate("must be a UUID".to_string()),
+                Some("date") if time::Date::parse(string, time::macros::format_description!("[year]-[month]-[day]")).is_err() => {
+                    violate("must be a date".to_string())
+                }
+                Some("date-time") if time::OffsetDateTime::parse(string, &time::format_description::well_known::Rfc3339).is_err() => {
+                    violate("must be an RFC 3339 date-time".to_string())
+                }
+                _ => {}
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema["minimum"].as_f64().filter(|min| number < *min) {
+                violate(format!("must be at least {}", min));
+            }
+            if let Some(max) = schema["maximum"].as_f64().filter(|max| number > *max) {
+                violate(format!("must be at most {}", max));
+            }
+        }
+        Value::Array(items) => {
+            if let Some(max) = schema["maxItems"].as_u64().filter(|max| items.len() as u64 > *max) {
+                violate(format!("must have at most {} items", max));
+            }
+            for (index, item) in items.iter().enumerate() {
+                validate(item, &schema["items"], format!("{}/{}", pointer, index), violations);
+            }
+        }
+        Value::Object(object) => {
+            for required in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(required) {
+                    violations.push(Violation { pointer: format!("{}/{}", pointer, escape(required)), detail: "is required".to_string() });
+                }
+            }
+            for (property, property_value) in object {
+                let property_schema = &schema["properties"][property];
+                if property_schema["readOnly"] != Value::Bool(true) {
+                    validate(property_value, property_schema, format!("{}/{}", pointer, escape(property)), violations);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{client, setup_test_db};
+    use rocket::serde::json::json;
+
+    fn violations(value: Value) -> Vec<(String, String)> {
+        let mut violations = Vec::new();
+        validate(&value, openapi::schema("Person").unwrap(), String::new(), &mut violations);
+        violations.into_iter().map(|violation| (violation.pointer, violation.detail)).collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(violations(json!({ "name": "Jean Dupont", "email": "jean@example.com", "mandates": ["maire"], "uuid": "" })), vec![]);
+        assert_eq!(
+            violations(json!({ "name": "", "email": "jean", "mandates": ["Maire", 3], "latitude": 91.5, "commune_code": null })),
+            vec![
+                ("/email".to_string(), "must be an email address".to_string()),
+                ("/latitude".to_string(), "must be at most 90".to_string()),
+                ("/mandates/1".to_string(), "must be of type string".to_string()),
+                ("/name".to_string(), "must be at least 1 characters long".to_string()),
+            ]
+        );
+        assert_eq!(violations(json!({ "name": " \t ", "email": "jean@example.com", "mandates": [] })), vec![("/name".to_string(), "must be at least 1 characters long".to_string())]);
+        assert_eq!(
+            violations(json!({ "name": "Jean Dupont", "email": "jean@example.com", "mandates": [], "consent_date": "2026-01-25" })),
+            vec![("/consent_date".to_string(), "must be an RFC 3339 date-time".to_string())]
+        );
+        assert_eq!(violations(json!({ "name": "Jean Dupont", "email": "jean@example.com", "mandates": [], "consent_date": "2026-01-25T10:00:00Z" })), vec![]);
+        assert_eq!(violations(json!([])), vec![(String::new(), "must be of type object".to_string())]);
+        assert_eq!(
+            violations(json!({ "name": "Jean Dupont" })),
+            vec![("/email".to_string(), "is required".to_string()), ("/mandates".to_string(), "is required".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_problem_response() {
+        let client = client(setup_test_db());
+
+        let response = client.post("/elus/create").json(&json!({ "name": "Jean Dupont", "email": "jean", "mandates": [] })).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/problem+json; charset=utf-8"));
+        let problem: Value = response.into_json().unwrap();
+        assert_eq!(problem["status"], 422);
+        assert_eq!(problem["errors"], json!([{ "pointer": "/email", "detail": "must be an email address" }]));
+
+        let response = client.get("/openapi.json").dispatch();
+        assert_eq!(response.into_json::<Value>().unwrap()["openapi"], "3.1.0");
+    }
+}
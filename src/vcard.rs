@@ -0,0 +1,266 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use qrcode::{EcLevel, QrCode};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Value;
+use rocket::State;
+
+use crate::config::AppConfig;
+use crate::png;
+use crate::redaction::redact;
+use crate::repository::PersonKey;
+use crate::scraping::Probe;
+use crate::settings;
+use crate::visibility::{Visibility, Visible};
+use crate::{db, DbConn, Person};
+
+/// Renders a person, a redacted `Person`, as a vCard 3.0, the version most
+/// phone contact apps import from a scanned QR code, of the organization
+/// they're an elu of. Redacted fields are left out of the card.
+pub fn to_vcard(person: &Value, organization: Option<&str>) -> String {
+    let name = person["name"].as_str().unwrap_or_default();
+    let (given, family) = match name.trim().rsplit_once(' ') {
+        Some((given, family)) => (given, family),
+        None => ("", name.trim()),
+    };
+
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        "VERSION:3.0".to_string(),
+        format!("N:{};{};;;", escape(family), escape(given)),
+        format!("FN:{}", escape(name)),
+    ];
+    let email = person["email"].as_str();
+    if let Some(email) = email {
+        lines.push(format!("EMAIL;TYPE=INTERNET,WORK:{}", escape(email)));
+    }
+    for other in person["emails"].as_array().into_iter().flatten().filter_map(Value::as_str).filter(|other| Some(*other) != email) {
+        lines.push(format!("EMAIL;TYPE=INTERNET:{}", escape(other)));
+    }
+    let mandates: Vec<&str> = person["mandates"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+    if !mandates.is_empty() {
+        lines.push(format!("TITLE:{}", escape(&mandates.join(", "))));
+    }
+    if let Some(organization) = organization {
+        lines.push(format!("ORG:{}", escape(organization)));
+    }
+    if let Some(address) = person["office_address"].as_str() {
+        lines.push(format!("ADR;TYPE=WORK:;;{};;;;", escape(address)));
+    }
+    if let (Some(latitude), Some(longitude)) = (person["latitude"].as_f64(), person["longitude"].as_f64()) {
+        lines.push(format!("GEO:{};{}", latitude, longitude));
+    }
+    lines.push("END:VCARD".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+}
+
+/// Folds a content line at 75 octets, without splitting UTF-8 sequences,
+/// and terminates it with CRLF.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        self.0.as_deref().is_some_and(|header| {
+            header
+                .split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        })
+    }
+
+    /// Whether the header is `*`, matching any current representation.
+    pub fn is_wildcard(&self) -> bool {
+        self.0.as_deref().is_some_and(|header| header.trim() == "*")
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
+
+#[derive(Responder)]
+enum QrCodePng {
+    #[response(content_type = "image/png")]
+    Image(Vec<u8>, Header<'static>, Header<'static>),
+    #[response(status = 304)]
+    NotModified((), Header<'static>, Header<'static>),
+}
+
+/// The vCard of an elu as a QR code, holding what their JSON would show
+/// the caller.
+pub struct VcardQrCode {
+    person: Person,
+    organization: Option<String>,
+    if_none_match: IfNoneMatch,
+    /// Whether the caller sees what anonymous callers do, so that shared
+    /// caches may keep the image.
+    shared: bool,
+}
+
+impl VcardQrCode {
+    /// The vCard in the QR code, as the caller of `request` sees it.
+    fn vcard(&self, request: &Request<'_>) -> Result<String, Status> {
+        let config = request.rocket().state::<AppConfig>().ok_or(Status::InternalServerError)?;
+        let person = redact(&self.person, request)?;
+        Ok(config.redaction.shown(to_vcard(&person, self.organization.as_deref())))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for VcardQrCode {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let vcard = self.vcard(request)?;
+        let mut hasher = DefaultHasher::new();
+        vcard.hash(&mut hasher);
+        let etag = format!("\"{:016x}\"", hasher.finish());
+
+        // Printed directories keep pointing at the same URL: let caches reuse
+        // the image for a while, and revalidate cheaply afterwards. Images
+        // served to signed-in users and admins stay in their own cache.
+        let cache_control = Header::new("Cache-Control", if self.shared { "public, max-age=3600" } else { "private, max-age=3600" });
+        if self.if_none_match.matches(&etag) {
+            return QrCodePng::NotModified((), cache_control, Header::new("ETag", etag)).respond_to(request);
+        }
+
+        let qr = QrCode::with_error_correction_level(vcard.as_bytes(), EcLevel::M).map_err(|_| Status::InternalServerError)?;
+        let image = png::encode_qr_code(&qr);
+
+        QrCodePng::Image(image, cache_control, Header::new("ETag", etag)).respond_to(request)
+    }
+}
+
+/// The elu's vCard as a QR code, with the display name of their commune as
+/// organization.
+#[get("/elus/<key>/qrcode.png")]
+fn qrcode_png(key: &str, _probe: Probe, if_none_match: IfNoneMatch, db: &State<DbConn>, repository: Visible) -> Result<VcardQrCode, Status> {
+    let person = Person::from(repository.find(&PersonKey::parse(key)?)?);
+    let organization = match &person.commune_code {
+        Some(code) => settings::find(code, &mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?.map(|settings| settings.display_name),
+        None => None,
+    };
+
+    Ok(VcardQrCode { person, organization, if_none_match, shared: repository.audience() == Visibility::Public })
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![qrcode_png]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{admin, build_client, client, insert_test_persons, setup_test_db};
+    use rocket::http::ContentType;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn test_to_vcard() {
+        let person = json!({
+            "name": "Jean Dupont",
+            "email": "jean.dupont@example.com",
+            "emails": ["jean.dupont@example.com"],
+            "mandates": ["Maire", "Conseiller régional"],
+            "office_address": "Place de l'Hôtel de Ville; 75004 Paris",
+            "latitude": 48.8566,
+            "longitude": 2.3522,
+        });
+
+        assert_eq!(
+            to_vcard(&person, Some("Ville de Paris")),
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Dupont;Jean;;;\r\nFN:Jean Dupont\r\n\
+             EMAIL;TYPE=INTERNET,WORK:jean.dupont@example.com\r\n\
+             TITLE:Maire\\, Conseiller régional\r\n\
+             ORG:Ville de Paris\r\n\
+             ADR;TYPE=WORK:;;Place de l'Hôtel de Ville\\; 75004 Paris;;;;\r\n\
+             GEO:48.8566;2.3522\r\nEND:VCARD\r\n"
+        );
+        assert_eq!(to_vcard(&json!({ "name": "Dupont", "mandates": [] }), None), "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Dupont;;;;\r\nFN:Dupont\r\nEND:VCARD\r\n");
+    }
+
+    #[test]
+    fn test_fold_long_lines() {
+        let folded = fold(&format!("TITLE:{}", "é".repeat(40)));
+        let lines: Vec<&str> = folded.split("\r\n").collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+    }
+
+    #[test]
+    fn test_qrcode_png_endpoint() {
+        let mut connection = setup_test_db();
+        insert_test_persons(&mut connection);
+        let client = client(connection);
+
+        let response = client.get("/elus/jean.dupont@example.com/qrcode.png").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("public, max-age=3600"));
+        let etag = response.headers().get_one("ETag").expect("ETag header").to_string();
+        let image = response.into_bytes().expect("body");
+        assert!(image.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let response = client
+            .get("/elus/jean.dupont@example.com/qrcode.png")
+            .header(Header::new("If-None-Match", etag))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+
+        let response = client.get("/elus/jean.dupont@example.com/qrcode.png").header(admin()).dispatch();
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("private, max-age=3600"));
+
+        let response = client.get("/elus/nonexistent@example.com/qrcode.png").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_qrcode_payload_is_redacted() {
+        let client = build_client(|figment| figment.merge(("redaction.public", ["email"])), setup_test_db());
+        let person = Person {
+            name: "Jean Dupont".parse().unwrap(),
+            email: "jean.dupont@example.com".parse().unwrap(),
+            emails: vec!["jean.dupont@example.com".parse().unwrap(), "j.dupont@example.org".parse().unwrap()],
+            mandates: vec!["Maire".to_string()],
+            ..Default::default()
+        };
+        let qr_code = VcardQrCode { person, organization: None, if_none_match: IfNoneMatch(None), shared: true };
+
+        let anonymous = qr_code.vcard(client.get("/elus/jean.dupont@example.com/qrcode.png").inner()).unwrap();
+        assert!(anonymous.contains("FN:Jean Dupont\r\n") && !anonymous.contains("EMAIL"));
+        let admin = qr_code.vcard(client.get("/elus/jean.dupont@example.com/qrcode.png").header(admin()).inner()).unwrap();
+        assert!(admin.contains("EMAIL;TYPE=INTERNET,WORK:jean.dupont@example.com\r\n"));
+        assert!(admin.contains("EMAIL;TYPE=INTERNET:j.dupont@example.org\r\n"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Which build is serving, and against which database schema.
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use crate::{db, timestamp, DbConn};
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Version {
+    pub version: &'static str,
+    /// Commit the build was made from; `unknown` outside of a git checkout.
+    pub git_sha: &'static str,
+    /// When the commit was first built here, best-effort: rebuilds of
+    /// edited sources on the same commit keep it.
+    #[serde(with = "timestamp::rfc3339")]
+    pub built_at: PrimitiveDateTime,
+    /// Latest migration applied to the database.
+    pub schema_version: Option<String>,
+    /// Latest migration this build knows of.
+    pub expected_schema_version: String,
+}
+
+fn built_at() -> PrimitiveDateTime {
+    let seconds = env!("BUILT_AT").parse().unwrap_or_default();
+    let built_at = OffsetDateTime::from_unix_timestamp(seconds).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    PrimitiveDateTime::new(built_at.date(), built_at.time())
+}
+
+#[get("/version")]
+fn version(db: &State<DbConn>) -> Result<Json<Version>, Status> {
+    let schema_version = db::schema_version(&mut *db::lock(db)?).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        built_at: built_at(),
+        schema_version,
+        expected_schema_version: db::embedded_schema_version(),
+    }))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![version]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{client, setup_test_db};
+    use rocket::http::Status;
+    use rocket::serde::json::Value;
+
+    #[test]
+    fn test_version() {
+        let client = client(setup_test_db());
+
+        let response = client.get("/version").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let version: Value = response.into_json().unwrap();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["schema_version"], version["expected_schema_version"]);
+        assert_eq!(version["schema_version"].as_str().map(str::len), Some(18));
+    }
+}
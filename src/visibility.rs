@@ -0,0 +1,234 @@
+//! Who may see each elu: `public` records are listed to everyone,
+//! `internal` ones only to signed-in dashboard users and admins, and
+//! `hidden` ones only to admins. Public handlers get their repository
+//! through the `Visible` guard, which leaves out the records the caller
+//! may not see from every read, so that unauthenticated lists, searches
+//! and exports can't leak them.
+
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteValue};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::db::{NewPerson, Person};
+use crate::deliverability::EmailStatus;
+use crate::email::Email;
+use crate::repository::{Page, PersonFilter, PersonKey, PersonRepository};
+use crate::sessions::Session;
+
+/// Ordered from the widest audience to the narrowest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+#[diesel(sql_type = Text)]
+pub enum Visibility {
+    #[default]
+    Public,
+    Internal,
+    Hidden,
+}
+
+const ALL: [Visibility; 3] = [Visibility::Public, Visibility::Internal, Visibility::Hidden];
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Internal => "internal",
+            Visibility::Hidden => "hidden",
+        }
+    }
+
+    /// The visibilities of the records a caller seeing `self` may see.
+    pub fn seen(self) -> impl Iterator<Item = Visibility> {
+        ALL.into_iter().filter(move |visibility| *visibility <= self)
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = ();
+
+    fn from_str(visibility: &str) -> Result<Self, ()> {
+        ALL.into_iter().find(|candidate| candidate.as_str() == visibility).ok_or(())
+    }
+}
+
+impl ToSql<Text, Sqlite> for Visibility {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Visibility {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let visibility = <String as FromSql<Text, Sqlite>>::from_sql(value)?;
+        visibility.parse().map_err(|_| format!("unknown visibility {:?}", visibility).into())
+    }
+}
+
+/// The records the caller of `request` may see: all of them with the admin
+/// token, internal ones too within a dashboard session.
+pub async fn audience(request: &Request<'_>) -> Visibility {
+    if auth::is_admin(request) {
+        Visibility::Hidden
+    } else if request.guard::<Session>().await.is_success() {
+        Visibility::Internal
+    } else {
+        Visibility::Public
+    }
+}
+
+/// Repository leaving out the persons its audience may not see; writes go
+/// through unchanged, as they're reserved to admins anyway.
+pub struct VisibleRepository {
+    inner: Arc<dyn PersonRepository>,
+    audience: Visibility,
+}
+
+impl VisibleRepository {
+    pub fn new(inner: Arc<dyn PersonRepository>, audience: Visibility) -> Self {
+        VisibleRepository { inner, audience }
+    }
+
+    fn seen(&self, person: Person) -> Result<Person, Status> {
+        if person.visibility <= self.audience {
+            Ok(person)
+        } else {
+            Err(Status::NotFound)
+        }
+    }
+
+    fn filter(&self, filter: &PersonFilter) -> PersonFilter {
+        let audience = filter.audience.map_or(self.audience, |audience| audience.min(self.audience));
+        PersonFilter { audience: Some(audience), ..filter.clone() }
+    }
+}
+
+impl PersonRepository for VisibleRepository {
+    fn list(&self) -> Result<Vec<Person>, Status> {
+        self.inner.search(&self.filter(&PersonFilter::default()))
+    }
+
+    fn find(&self, key: &PersonKey) -> Result<Person, Status> {
+        self.seen(self.inner.find(key)?)
+    }
+
+    fn get(&self, id: i32) -> Result<Person, Status> {
+        self.seen(self.inner.get(id)?)
+    }
+
+    fn find_alias(&self, email: &Email) -> Result<Person, Status> {
+        self.seen(self.inner.find_alias(email)?)
+    }
+
+    fn get_many(&self, emails: &[Email]) -> Result<Vec<Person>, Status> {
+        Ok(self.inner.get_many(emails)?.into_iter().filter(|person| person.visibility <= self.audience).collect())
+    }
+
+    fn create(&self, person: NewPerson) -> Result<Person, Status> {
+        self.inner.create(person)
+    }
+
+    fn update(&self, email: &Email, person: NewPerson) -> Result<Person, Status> {
+        self.inner.update(email, person)
+    }
+
+    fn update_many(&self, updates: Vec<(Email, NewPerson)>) -> Result<Vec<Person>, Status> {
+        self.inner.update_many(updates)
+    }
+
+    fn delete(&self, email: &Email) -> Result<Person, Status> {
+        self.inner.delete(email)
+    }
+
+    fn search(&self, filter: &PersonFilter) -> Result<Vec<Person>, Status> {
+        self.inner.search(&self.filter(filter))
+    }
+
+    fn search_page(&self, filter: &PersonFilter, page: &Page) -> Result<Vec<Person>, Status> {
+        self.inner.search_page(&self.filter(filter), page)
+    }
+
+    fn set_email_status(&self, id: i32, status: EmailStatus) -> Result<(), Status> {
+        self.inner.set_email_status(id, status)
+    }
+
+    fn audience(&self) -> Visibility {
+        self.audience
+    }
+}
+
+/// Request guard handing out the repository as the caller may see it.
+pub struct Visible(Arc<dyn PersonRepository>);
+
+impl Visible {
+    pub fn into_inner(self) -> Arc<dyn PersonRepository> {
+        self.0
+    }
+}
+
+impl AsRef<dyn PersonRepository> for Visible {
+    fn as_ref(&self) -> &(dyn PersonRepository + 'static) {
+        self.0.as_ref()
+    }
+}
+
+impl Deref for Visible {
+    type Target = dyn PersonRepository;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Visible {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(repository) = request.rocket().state::<Arc<dyn PersonRepository>>() else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        let repository = match audience(request).await {
+            Visibility::Hidden => repository.clone(),
+            audience => Arc::new(VisibleRepository::new(repository.clone(), audience)),
+        };
+        request::Outcome::Success(Visible(repository))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MemoryRepository;
+
+    #[test]
+    fn test_visible_repository() {
+        let memory: Arc<dyn PersonRepository> = Arc::new(MemoryRepository::default());
+        for (name, email, visibility) in [
+            ("Jean Dupont", "jean@example.com", Visibility::Public),
+            ("Marie Martin", "marie@example.com", Visibility::Internal),
+            ("Pierre Durand", "pierre@example.com", Visibility::Hidden),
+        ] {
+            memory.create(NewPerson { name: name.parse().unwrap(), email: email.parse().unwrap(), visibility, ..Default::default() }).unwrap();
+        }
+        let public = VisibleRepository::new(memory.clone(), Visibility::Public);
+        let internal = VisibleRepository::new(memory.clone(), Visibility::Internal);
+        let names = |persons: Vec<Person>| persons.into_iter().map(|person| person.name.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names(public.list().unwrap()), ["Jean Dupont"]);
+        assert_eq!(names(internal.search(&PersonFilter::default()).unwrap()), ["Jean Dupont", "Marie Martin"]);
+        assert_eq!(public.find(&PersonKey::Email("marie@example.com".parse().unwrap())).unwrap_err(), Status::NotFound);
+        assert_eq!(internal.get_many(&["marie@example.com".parse().unwrap(), "pierre@example.com".parse().unwrap()]).unwrap().len(), 1);
+        assert_eq!("hidden".parse(), Ok(Visibility::Hidden));
+    }
+}
@@ -0,0 +1,560 @@
+//! Webhooks: endpoints registered by integrators, which get every change
+//! event POSTed to them as a CloudEvent, in order. Each webhook starts with
+//! the events following its registration: the dispatcher turns the events
+//! past the webhook's cursor in the events table into deliveries, which
+//! are retried with an exponential backoff, holding back the later ones,
+//! until `max_attempts` fail. Failed deliveries can then be replayed. Only
+//! plain HTTP endpoints are supported.
+//!
+//! Deliveries are signed with the webhook's secret, in the `X-Signature`
+//! header: `t=<unix time>,v1=<hex HMAC-SHA256 of "<unix time>.<body>">`.
+//! Receivers should check the signature and reject old timestamps, to
+//! prevent replays. After the secret is rotated, deliveries carry a second
+//! `v1` signature, made with the previous secret, until it expires, so
+//! receivers can switch secrets without missing deliveries.
+//!
+//! With tracing on, each delivery is a span whose `traceparent` header the
+//! receiver can continue the trace from.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use time::PrimitiveDateTime;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::auth::Admin;
+use crate::cloudevents;
+use crate::config::AppConfig;
+use crate::encryption::Sealer;
+use crate::events;
+use crate::redaction::RedactionConfig;
+use crate::reload::Live;
+use crate::sha256::{hex, hmac_sha256};
+use crate::schema::{event_cursors, webhook_deliveries, webhooks};
+use crate::{db, shutdown, telemetry, timeouts, timestamp, DbConn};
+
+pub const PENDING: &str = "pending";
+pub const DELIVERED: &str = "delivered";
+pub const FAILED: &str = "failed";
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Deliveries created, and attempted, per webhook and run.
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct WebhookConfig {
+    /// Seconds between two runs of the dispatcher; 0 disables it.
+    pub poll_interval: u64,
+    /// Attempts after which a delivery is marked as failed.
+    pub max_attempts: i32,
+    /// Delay, in seconds, before the first retry; doubled after each failure.
+    pub retry_delay: u64,
+    /// Seconds deliveries are still signed with the previous secret after
+    /// a rotation.
+    pub rotation_grace: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            poll_interval: 5,
+            max_attempts: 5,
+            retry_delay: 60,
+            rotation_grace: 24 * 60 * 60,
+        }
+    }
+}
+
+impl WebhookConfig {
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = (attempts - 1).clamp(0, 16) as u32;
+        Duration::from_secs(self.retry_delay.saturating_mul(1 << exponent))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = webhooks)]
+#[serde(crate = "rocket::serde")]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(skip)]
+    pub secret: String,
+    #[serde(skip)]
+    pub previous_secret: Option<String>,
+    /// When deliveries stop being signed with the previous secret, while a
+    /// rotation is under way.
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub previous_secret_expires_at: Option<PrimitiveDateTime>,
+}
+
+impl Webhook {
+    /// The secrets deliveries are signed with at `now`, current one first.
+    fn signing_secrets(&self, now: PrimitiveDateTime) -> Vec<&str> {
+        let mut secrets = vec![self.secret.as_str()];
+        if self.previous_secret_expires_at.is_some_and(|expires_at| expires_at > now) {
+            secrets.extend(self.previous_secret.as_deref());
+        }
+        secrets
+    }
+}
+
+/// A webhook with its new secret: only shown when the webhook is created,
+/// and when its secret is rotated.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WebhookSecret {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewWebhook {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = webhook_deliveries)]
+#[serde(crate = "rocket::serde")]
+pub struct Delivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event_seq: i64,
+    pub status: String,
+    pub attempts: i32,
+    /// HTTP status of the last attempt's response, if it got one.
+    pub response_code: Option<i32>,
+    pub last_error: Option<String>,
+    #[serde(with = "timestamp::rfc3339")]
+    pub next_attempt_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(with = "timestamp::rfc3339::option")]
+    pub delivered_at: Option<PrimitiveDateTime>,
+}
+
+fn consumer(webhook_id: i32) -> String {
+    format!("webhook:{}", webhook_id)
+}
+
+fn new_secret() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// The `X-Signature` header of a delivery of `body` at `unix_time`.
+pub fn signature(secrets: &[&str], unix_time: i64, body: &[u8]) -> String {
+    let mut signed = format!("{}.", unix_time).into_bytes();
+    signed.extend_from_slice(body);
+
+    let mut header = format!("t={}", unix_time);
+    for secret in secrets {
+        header.push_str(&format!(",v1={}", hex(&hmac_sha256(secret.as_bytes(), &signed))));
+    }
+    header
+}
+
+/// POSTs the event, signed at `now`, in a span of its own, failing unless the endpoint answers
+/// with a 2xx status. Returns the response's status, if there was a
+/// response.
+async fn post(client: &Client<HttpConnector, Body>, hook: &Webhook, body: Vec<u8>, now: PrimitiveDateTime) -> (Option<i32>, Result<(), String>) {
+    let span = tracing::info_span!(
+        "webhook",
+        otel.name = "POST webhook",
+        otel.kind = "client",
+        otel.status_code = Empty,
+        http.request.method = "POST",
+        http.response.status_code = Empty,
+        url.full = hook.url.as_str(),
+    );
+    let (response_code, result) = send(client, telemetry::traceparent(&span), hook, body, now).instrument(span.clone()).await;
+    if let Some(code) = response_code {
+        span.record("http.response.status_code", code);
+    }
+    if result.is_err() {
+        span.record("otel.status_code", "error");
+    }
+
+    (response_code, result)
+}
+
+async fn send(client: &Client<HttpConnector, Body>, traceparent: Option<String>, hook: &Webhook, body: Vec<u8>, now: PrimitiveDateTime) -> (Option<i32>, Result<(), String>) {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(&hook.url)
+        .header("content-type", cloudevents::CONTENT_TYPE)
+        .header(SIGNATURE_HEADER, signature(&hook.signing_secrets(now), now.assume_utc().unix_timestamp(), &body));
+    if let Some(traceparent) = traceparent {
+        request = request.header("traceparent", traceparent);
+    }
+    let request = request.body(Body::from(body));
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return (None, Err(format!("invalid webhook URL: {}", e))),
+    };
+
+    match client.request(request).await {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16().into()), Ok(())),
+        Ok(response) => (Some(response.status().as_u16().into()), Err(format!("webhook returned {}", response.status()))),
+        Err(e) => (None, Err(e.to_string())),
+    }
+}
+
+/// Creates the deliveries of the events past each webhook's cursor, and
+/// moves the cursors past them.
+fn fan_out(sealer: &Sealer, connection: &mut SqliteConnection) -> QueryResult<usize> {
+    connection.transaction(|connection| {
+        let hook_ids = webhooks::table.select(webhooks::id).load::<i32>(connection)?;
+        let mut created = 0;
+        for hook_id in hook_ids {
+            let consumer = consumer(hook_id);
+            let pending = events::since(events::cursor(&consumer, connection)?, BATCH_SIZE, sealer, connection)?;
+            let Some(last) = pending.last() else {
+                continue;
+            };
+            let rows: Vec<_> = pending
+                .iter()
+                .map(|event| (webhook_deliveries::webhook_id.eq(hook_id), webhook_deliveries::event_seq.eq(event.seq)))
+                .collect();
+            created += diesel::insert_into(webhook_deliveries::table).values(rows).execute(connection)?;
+            events::advance(&consumer, last.seq, connection)?;
+        }
+        Ok(created)
+    })
+}
+
+/// Attempts the deliveries due at `now`, in order: a delivery waiting for
+/// a retry holds back the later deliveries to the same webhook. Returns
+/// the number of deliveries attempted.
+pub async fn deliver_pending(db: &DbConn, client: &Client<HttpConnector, Body>, config: &WebhookConfig, sealer: &Sealer, redaction: &RedactionConfig, now: PrimitiveDateTime) -> QueryResult<usize> {
+    fan_out(sealer, &mut *db::lock(db)?)?;
+    let pending = webhook_deliveries::table
+        .inner_join(webhooks::table)
+        .filter(webhook_deliveries::status.eq(PENDING))
+        .order(webhook_deliveries::id)
+        .limit(BATCH_SIZE)
+        .select((Delivery::as_select(), Webhook::as_select()))
+        .load::<(Delivery, Webhook)>(&mut *db::lock(db)?)?;
+
+    let mut held_back = HashSet::new();
+    let mut attempted = 0;
+    for (delivery, hook) in pending {
+        if held_back.contains(&delivery.webhook_id) {
+            continue;
+        }
+        if delivery.next_attempt_at > now {
+            held_back.insert(delivery.webhook_id);
+            continue;
+        }
+
+        let event = events::get(delivery.event_seq, sealer, &mut *db::lock(db)?)?;
+        let (response_code, result) = post(client, &hook, cloudevents::payload(&event, redaction), now).await;
+        attempted += 1;
+
+        let attempts = delivery.attempts + 1;
+        let row = webhook_deliveries::table.find(delivery.id);
+        let attempt = (webhook_deliveries::attempts.eq(attempts), webhook_deliveries::response_code.eq(response_code));
+        let mut connection = db::lock(db)?;
+        match result {
+            Ok(()) => diesel::update(row)
+                .set((attempt, webhook_deliveries::status.eq(DELIVERED), webhook_deliveries::last_error.eq(None::<String>), webhook_deliveries::delivered_at.eq(now)))
+                .execute(&mut *connection)?,
+            Err(e) if attempts >= config.max_attempts => {
+                log::warn!("Giving up delivering event {} to webhook {}: {}", event.seq, delivery.webhook_id, e);
+                diesel::update(row)
+                    .set((attempt, webhook_deliveries::status.eq(FAILED), webhook_deliveries::last_error.eq(e)))
+                    .execute(&mut *connection)?
+            }
+            Err(e) => {
+                held_back.insert(delivery.webhook_id);
+                diesel::update(row)
+                    .set((attempt, webhook_deliveries::last_error.eq(e), webhook_deliveries::next_attempt_at.eq(now + config.backoff(attempts))))
+                    .execute(&mut *connection)?
+            }
+        };
+    }
+
+    Ok(attempted)
+}
+
+/// Background worker delivering events to the webhooks, with the retry
+/// policy of the configuration as last reloaded.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Webhook dispatcher", move |rocket| Box::pin(async move {
+        let live = rocket.state::<Live<WebhookConfig>>().expect("webhook settings are managed").clone();
+        let poll_interval = live.get().poll_interval;
+        if poll_interval == 0 {
+            return;
+        }
+
+        let db = rocket.state::<DbConn>().expect("database connection is managed").clone();
+        let sealer = rocket.state::<Sealer>().expect("sealer is managed").clone();
+        let redaction = rocket.state::<AppConfig>().expect("configuration is managed").redaction.clone();
+        let job = timeouts::job(rocket);
+        let shutdown = rocket.shutdown();
+        let worker = rocket::tokio::spawn(async move {
+            let client = Client::new();
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(poll_interval));
+            loop {
+                // Delivers what's due one last time when shutting down.
+                let running = shutdown::tick(&mut interval, &shutdown).await;
+                let config = live.get();
+                match rocket::tokio::time::timeout(job, deliver_pending(&db, &client, &config, &sealer, &redaction, timestamp::now())).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => log::error!("Webhook run failed: {}", e),
+                    Err(_) => log::warn!("Webhook run cancelled after {} seconds", job.as_secs()),
+                }
+                if !running {
+                    break;
+                }
+            }
+        });
+        shutdown::track(rocket, worker);
+    }))
+}
+
+#[get("/webhooks")]
+fn list_webhooks(_admin: Admin, db: &State<DbConn>) -> Result<Json<Vec<Webhook>>, Status> {
+    webhooks::table
+        .order(webhooks::id)
+        .select(Webhook::as_select())
+        .load(&mut *db::lock(db)?)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Registers a webhook, which gets the events following its registration.
+#[post("/webhooks", data = "<new_webhook>")]
+fn create_webhook(new_webhook: Json<NewWebhook>, _admin: Admin, db: &State<DbConn>) -> Result<Created<Json<WebhookSecret>>, Status> {
+    let valid = new_webhook.url.starts_with("http://") && new_webhook.url.parse::<Uri>().is_ok_and(|uri| uri.host().is_some());
+    if !valid {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let secret = new_secret();
+    let webhook = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            let webhook = diesel::insert_into(webhooks::table)
+                .values((webhooks::url.eq(&new_webhook.url), webhooks::secret.eq(&secret)))
+                .returning(Webhook::as_returning())
+                .get_result(connection)?;
+            events::advance(&consumer(webhook.id), events::latest(connection)?, connection)?;
+            QueryResult::Ok(webhook)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    let location = format!("/webhooks/{}", webhook.id);
+    Ok(Created::new(location).body(Json(WebhookSecret { webhook, secret })))
+}
+
+/// Gives the webhook a new secret. Deliveries are signed with both the new
+/// and the previous secret for `rotation_grace`; rotating again within it
+/// retires the previous secret right away.
+#[post("/webhooks/<id>/secret")]
+fn rotate_secret(id: i32, _admin: Admin, db: &State<DbConn>, config: &State<Live<WebhookConfig>>) -> Result<Json<WebhookSecret>, Status> {
+    let secret = new_secret();
+    let expires_at = timestamp::now() + Duration::from_secs(config.get().rotation_grace);
+    let webhook = diesel::update(webhooks::table.find(id))
+        .set((
+            webhooks::previous_secret.eq(webhooks::secret.nullable()),
+            webhooks::previous_secret_expires_at.eq(expires_at),
+            webhooks::secret.eq(&secret),
+        ))
+        .returning(Webhook::as_returning())
+        .get_result(&mut *db::lock(db)?)
+        .optional()
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(WebhookSecret { webhook, secret }))
+}
+
+#[delete("/webhooks/<id>")]
+fn delete_webhook(id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let deleted = db
+        .lock()
+        .unwrap()
+        .transaction(|connection| {
+            diesel::delete(webhook_deliveries::table.filter(webhook_deliveries::webhook_id.eq(id))).execute(connection)?;
+            diesel::delete(event_cursors::table.find(consumer(id))).execute(connection)?;
+            diesel::delete(webhooks::table.find(id)).execute(connection)
+        })
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 { Err(Status::NotFound) } else { Ok(Status::NoContent) }
+}
+
+/// The webhook's deliveries, newest first, optionally only those with the
+/// given status.
+#[get("/webhooks/<id>/deliveries?<status>")]
+fn list_deliveries(id: i32, status: Option<&str>, _admin: Admin, db: &State<DbConn>) -> Result<Json<Vec<Delivery>>, Status> {
+    let statuses = match status {
+        Some(status @ (PENDING | DELIVERED | FAILED)) => vec![status],
+        Some(_) => return Err(Status::BadRequest),
+        None => vec![PENDING, DELIVERED, FAILED],
+    };
+
+    let mut connection = db::lock(db)?;
+    webhooks::table.find(id).select(webhooks::id).first::<i32>(&mut *connection).map_err(|_| Status::NotFound)?;
+    webhook_deliveries::table
+        .filter(webhook_deliveries::webhook_id.eq(id))
+        .filter(webhook_deliveries::status.eq_any(statuses))
+        .order(webhook_deliveries::id.desc())
+        .limit(BATCH_SIZE)
+        .select(Delivery::as_select())
+        .load(&mut *connection)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Puts a failed delivery back in line for the dispatcher, with a fresh
+/// set of attempts.
+#[post("/webhooks/<id>/deliveries/<delivery_id>/retry")]
+fn retry_delivery(id: i32, delivery_id: i32, _admin: Admin, db: &State<DbConn>) -> Result<Status, Status> {
+    let mut connection = db::lock(db)?;
+    let status: String = webhook_deliveries::table
+        .find(delivery_id)
+        .filter(webhook_deliveries::webhook_id.eq(id))
+        .select(webhook_deliveries::status)
+        .first(&mut *connection)
+        .map_err(|_| Status::NotFound)?;
+    if status != FAILED {
+        return Err(Status::Conflict);
+    }
+
+    diesel::update(webhook_deliveries::table.find(delivery_id))
+        .set((webhook_deliveries::status.eq(PENDING), webhook_deliveries::attempts.eq(0), webhook_deliveries::next_attempt_at.eq(timestamp::now())))
+        .execute(&mut *connection)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::Accepted)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list_webhooks, create_webhook, rotate_secret, delete_webhook, list_deliveries, retry_delivery]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloudevents::CloudEvent;
+    use crate::tests::{admin, client, serve_once, setup_test_db};
+    use rocket::serde::json::json;
+    use std::net::TcpListener;
+    use time::macros::datetime;
+
+    #[rocket::async_test]
+    async fn test_deliver_pending() {
+        let client = rocket::local::asynchronous::Client::tracked(crate::build_rocket(
+            rocket::Config::figment().merge(("admin_token", crate::tests::ADMIN_TOKEN)).merge(("webhooks.poll_interval", 0)),
+            setup_test_db(),
+        ))
+        .await
+        .unwrap();
+        // Events from before the registration aren't delivered.
+        let person = json!({ "name": "Jean Dupont", "email": "jean@mairie.example", "mandates": [] });
+        client.post("/elus/create").json(&person).dispatch().await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/rckd", listener.local_addr().unwrap());
+        let created: WebhookSecret = client.post("/webhooks").header(admin()).json(&json!({ "url": url })).dispatch().await.into_json().await.unwrap();
+        let webhook = created.webhook;
+        client.delete("/elus/jean@mairie.example").header(admin()).dispatch().await;
+
+        let db = client.rocket().state::<DbConn>().unwrap().clone();
+        let http = Client::new();
+        let sealer = client.rocket().state::<Sealer>().unwrap();
+        let redaction = RedactionConfig::default();
+        let config = WebhookConfig { max_attempts: 2, ..Default::default() };
+        let now = timestamp::now();
+        let server = serve_once(listener.try_clone().unwrap(), "503 Service Unavailable");
+        assert_eq!(deliver_pending(&db, &http, &config, sealer, &redaction, now).await, Ok(1));
+        server.join().unwrap();
+        assert_eq!(deliver_pending(&db, &http, &config, sealer, &redaction, now).await, Ok(0));
+
+        let server = serve_once(listener.try_clone().unwrap(), "500 Internal Server Error");
+        assert_eq!(deliver_pending(&db, &http, &config, sealer, &redaction, now + config.backoff(1)).await, Ok(1));
+        server.join().unwrap();
+
+        let uri = format!("/webhooks/{}/deliveries?status=failed", webhook.id);
+        let failed: Vec<Delivery> = client.get(uri).header(admin()).dispatch().await.into_json().await.unwrap();
+        assert_eq!((failed.len(), failed[0].attempts, failed[0].response_code), (1, 2, Some(500)));
+
+        let retry = format!("/webhooks/{}/deliveries/{}/retry", webhook.id, failed[0].id);
+        assert_eq!(client.post(retry.clone()).header(admin()).dispatch().await.status(), Status::Accepted);
+        assert_eq!(client.post(retry).header(admin()).dispatch().await.status(), Status::Conflict);
+        let server = serve_once(listener, "202 Accepted");
+        assert_eq!(deliver_pending(&db, &http, &config, sealer, &redaction, timestamp::now()).await, Ok(1));
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /hooks/rckd HTTP/1.1\r\n"));
+        let signature_line = head.lines().find_map(|line| line.strip_prefix("x-signature: ")).unwrap();
+        let unix_time = signature_line.strip_prefix("t=").and_then(|rest| rest.split(',').next()).unwrap().parse().unwrap();
+        assert_eq!(signature_line, signature(&[&created.secret], unix_time, body.as_bytes()));
+        assert!(head.contains("content-type: application/cloudevents+json; charset=utf-8\r\n"));
+        let event: CloudEvent = serde_json::from_str(&body).unwrap();
+        assert_eq!((event.event_type.as_str(), event.schemaversion.as_str()), ("fr.rckd.elu.deleted", "1"));
+
+        let uri = format!("/webhooks/{}/deliveries", webhook.id);
+        let deliveries: Vec<Delivery> = client.get(uri).header(admin()).dispatch().await.into_json().await.unwrap();
+        assert_eq!((deliveries[0].status.as_str(), deliveries[0].response_code), (DELIVERED, Some(202)));
+    }
+
+    #[test]
+    fn test_signature() {
+        let hook = Webhook {
+            id: 1,
+            url: "http://crm.example/hook".to_string(),
+            created_at: datetime!(2030-01-01 12:00:00),
+            secret: "new".to_string(),
+            previous_secret: Some("old".to_string()),
+            previous_secret_expires_at: Some(datetime!(2030-01-02 12:00:00)),
+        };
+        assert_eq!(hook.signing_secrets(datetime!(2030-01-02 11:59:59)), ["new", "old"]);
+        assert_eq!(hook.signing_secrets(datetime!(2030-01-02 12:00:00)), ["new"]);
+
+        let expected = hex(&hmac_sha256(b"new", b"1700000000.{}"));
+        assert_eq!(signature(&["new"], 1700000000, b"{}"), format!("t=1700000000,v1={}", expected));
+        assert_eq!(signature(&["new", "old"], 1700000000, b"{}").matches(",v1=").count(), 2);
+    }
+
+    #[test]
+    fn test_manage_webhooks() {
+        let client = client(setup_test_db());
+        assert_eq!(client.post("/webhooks").json(&json!({ "url": "http://crm.example/hook" })).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/webhooks").header(admin()).json(&json!({ "url": "ftp://crm.example" })).dispatch().status(), Status::UnprocessableEntity);
+
+        let created: WebhookSecret = client.post("/webhooks").header(admin()).json(&json!({ "url": "http://crm.example/hook" })).dispatch().into_json().unwrap();
+        let webhook = created.webhook;
+        let listed: Vec<Webhook> = client.get("/webhooks").header(admin()).dispatch().into_json().unwrap();
+        assert_eq!(listed.iter().map(|hook| hook.id).collect::<Vec<_>>(), [webhook.id]);
+
+        let rotated: WebhookSecret = client.post(format!("/webhooks/{}/secret", webhook.id)).header(admin()).dispatch().into_json().unwrap();
+        assert_ne!(rotated.secret, created.secret);
+        assert!(rotated.webhook.previous_secret_expires_at.is_some());
+        assert_eq!(client.post("/webhooks/999/secret").header(admin()).dispatch().status(), Status::NotFound);
+
+        let uri = format!("/webhooks/{}", webhook.id);
+        assert_eq!(client.delete(uri.clone()).header(admin()).dispatch().status(), Status::NoContent);
+        assert_eq!(client.delete(uri).header(admin()).dispatch().status(), Status::NotFound);
+    }
+}